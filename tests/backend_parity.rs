@@ -0,0 +1,257 @@
+// Differential testing of the two backends of wayland-client
+//
+// Each scenario below runs some client logic through a sniffing proxy, once with each backend:
+// the rust one, and the native one with the `client_native` feature, selected at runtime with
+// `ConnectOptions::backend()`. Their traffic is compared with each other, through a reference
+// trace stored in `tests/parity_traces/`:
+//
+//     cargo test --test backend_parity --features client_native
+//
+// reports any divergence in the requests they send or the way they handle the events. Without
+// the feature, only the rust backend is checked against the reference. Setting
+// `WAYLAND_RS_BLESS_TRACES=1` rewrites the reference traces with the traffic of the rust
+// backend, to be reviewed before committing.
+
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+extern crate wayland_commons as wc;
+
+use wc::capture::{CapturedArgument, CapturedMessage, Direction};
+use wc::sniffer::{forward, LogFormat, Sniffer};
+use wc::socket::Socket;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+use ways::protocol::{wl_compositor, wl_output, wl_region, wl_surface};
+
+use wayc::protocol::wl_compositor::WlCompositor;
+use wayc::protocol::wl_output::WlOutput;
+
+#[cfg(feature = "client_native")]
+const BACKENDS: &[wayc::Backend] = &[wayc::Backend::Rust, wayc::Backend::Native];
+#[cfg(not(feature = "client_native"))]
+const BACKENDS: &[wayc::Backend] = &[wayc::Backend::Rust];
+
+// Number the objects in the order of their creation
+//
+// The backends allocate the protocol ids differently, libwayland reusing the last freed id
+// first and the rust implementation the lowest one, which is not a divergence.
+fn normalize_ids(msgs: &mut [CapturedMessage]) {
+    let mut labels = HashMap::new();
+    labels.insert(1, 1);
+    let mut next = 2;
+    let label = |labels: &HashMap<u32, u32>, id: u32| labels.get(&id).cloned().unwrap_or(0);
+    for msg in msgs {
+        msg.sender_id = label(&labels, msg.sender_id);
+        let delete_id = msg.interface == "wl_display" && msg.name == "delete_id";
+        for arg in &mut msg.args {
+            match *arg {
+                CapturedArgument::Object(ref mut id) if *id != 0 => *id = label(&labels, *id),
+                CapturedArgument::Uint(ref mut id) if delete_id => *id = label(&labels, *id),
+                CapturedArgument::NewId(ref mut id) => {
+                    labels.insert(*id, next);
+                    *id = next;
+                    next += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Render the traffic of a connection, without the timestamps
+//
+// The requests and events are listed separately: the order of each direction is
+// deterministic, but how they interleave depends on the scheduling of the proxy.
+fn render(mut msgs: Vec<CapturedMessage>) -> String {
+    normalize_ids(&mut msgs);
+    let mut trace = String::new();
+    for &(direction, title) in &[(Direction::Sent, "requests"), (Direction::Received, "events")] {
+        trace.push_str(title);
+        trace.push_str(":\n");
+        for msg in msgs.iter().filter(|msg| msg.direction == direction) {
+            let mut line = Vec::new();
+            LogFormat::Text.write(msg, &mut line).unwrap();
+            let line = String::from_utf8(line).unwrap();
+            // strip the `[seconds.micros] ` prefix
+            trace.push_str("  ");
+            trace.push_str(&line[line.find("] ").unwrap() + 2..]);
+        }
+    }
+    trace
+}
+
+// Run a client scenario through a sniffing proxy, returning its traffic
+fn sniff<F>(server: &mut TestServer, backend: wayc::Backend, run: &F) -> String
+where
+    F: Fn(&mut TestClient, &mut TestServer),
+{
+    let mut path: PathBuf = env::var_os("XDG_RUNTIME_DIR").unwrap().into();
+    path.push(&server.socket_name);
+    let upstream = UnixStream::connect(path).unwrap();
+    let (client_socket, proxy_socket) = UnixStream::pair().unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let proxy = std::thread::spawn(move || {
+        let client = unsafe { Socket::from_raw_fd(proxy_socket.into_raw_fd()) };
+        let server = unsafe { Socket::from_raw_fd(upstream.into_raw_fd()) };
+        let mut sniffer = Sniffer::new::<wayc::protocol::wl_display::WlDisplay>();
+        sniffer.register::<WlCompositor>();
+        sniffer.register::<WlOutput>();
+        forward(&client, &server, &mut sniffer, |msg| sender.send(msg).unwrap()).unwrap();
+    });
+
+    let options = wayc::ConnectOptions::new().backend(backend);
+    let display =
+        unsafe { wayc::Display::from_fd_with_options(client_socket.into_raw_fd(), &options) };
+    let mut client = TestClient::from_display(display.unwrap());
+    assert_eq!(client.display.backend(), backend);
+    run(&mut client, server);
+    // disconnecting the client stops the proxy
+    drop(client);
+    proxy.join().unwrap();
+    render(receiver.iter().collect())
+}
+
+// Run a client scenario with each backend and compare their traffic with the reference
+fn check_parity<F>(scenario: &str, mut server: TestServer, run: F)
+where
+    F: Fn(&mut TestClient, &mut TestServer),
+{
+    let mut reference_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    reference_path.push("tests/parity_traces");
+    reference_path.push(format!("{}.trace", scenario));
+    let bless = env::var_os("WAYLAND_RS_BLESS_TRACES").is_some();
+
+    for &backend in BACKENDS {
+        let trace = sniff(&mut server, backend, &run);
+        if bless && backend == wayc::Backend::Rust {
+            fs::write(&reference_path, &trace).unwrap();
+        }
+        let reference = fs::read_to_string(&reference_path).unwrap();
+        if trace != reference {
+            panic!(
+                "The traffic of scenario `{}` with the {:?} backend diverges from {:?}.\n\
+                 expected:\n{}\nfound:\n{}",
+                scenario, backend, reference_path, reference, trace
+            );
+        }
+    }
+}
+
+fn server_with_globals() -> TestServer {
+    let mut server = TestServer::new();
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(|(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            if output.as_ref().version() >= 2 {
+                output.scale(2);
+                output.done();
+            }
+        }),
+    );
+
+    ways::request_enum!(Reqs |
+        Compositor => wl_compositor::WlCompositor,
+        Surface => wl_surface::WlSurface,
+        Region => wl_region::WlRegion
+    );
+
+    let filter = ways::Filter::new(|req, filter, _| match req {
+        Reqs::Compositor { request: wl_compositor::Request::CreateSurface { id }, .. } => {
+            id.assign(filter.clone());
+        }
+        Reqs::Compositor { request: wl_compositor::Request::CreateRegion { id }, .. } => {
+            id.assign(filter.clone());
+        }
+        _ => {}
+    });
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        4,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                compositor.assign(filter.clone());
+            },
+        ),
+    );
+    server
+}
+
+#[test]
+fn backend_selection() {
+    let server = TestServer::new();
+    // no other test of this file uses the variable
+    env::set_var("WAYLAND_DISPLAY", &server.socket_name);
+    for &backend in BACKENDS {
+        let display = wayc::Display::connect_with_backend(backend).unwrap();
+        assert_eq!(display.backend(), backend);
+    }
+    #[cfg(not(feature = "client_native"))]
+    {
+        match wayc::Display::connect_with_backend(wayc::Backend::Native) {
+            Err(wayc::ConnectError::NoWaylandLib) => {}
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("The native backend is not available."),
+        }
+    }
+}
+
+#[test]
+fn parity_globals() {
+    check_parity("globals", server_with_globals(), |client, server| {
+        let manager = wayc::GlobalManager::new(&client.display_proxy);
+        roundtrip(client, server).unwrap();
+        let output = manager.instantiate_exact::<WlOutput>(3).unwrap();
+        let old_output = manager.instantiate_exact::<WlOutput>(1).unwrap();
+        manager.instantiate_range::<WlCompositor>(1, 3).unwrap();
+        roundtrip(client, server).unwrap();
+        output.release();
+        // the version is too old for `release()`
+        assert!(!old_output.as_ref().supports_request("release"));
+        roundtrip(client, server).unwrap();
+    });
+}
+
+#[test]
+fn parity_dead_objects() {
+    check_parity("dead_objects", server_with_globals(), |client, server| {
+        let manager = wayc::GlobalManager::new(&client.display_proxy);
+        roundtrip(client, server).unwrap();
+        let compositor = manager.instantiate_exact::<WlCompositor>(4).unwrap();
+        let surface = compositor.create_surface();
+        let region = compositor.create_region();
+        roundtrip(client, server).unwrap();
+        region.destroy();
+        // a destroyed object given as an argument
+        surface.set_opaque_region(Some(&region));
+        surface.destroy();
+        // requests to a destroyed object
+        surface.commit();
+        roundtrip(client, server).unwrap();
+    });
+}
+
+#[test]
+fn parity_zombie_events() {
+    check_parity("zombie_events", server_with_globals(), |client, server| {
+        let manager = wayc::GlobalManager::new(&client.display_proxy);
+        roundtrip(client, server).unwrap();
+        let output = manager.instantiate_exact::<WlOutput>(3).unwrap();
+        output.quick_assign(|_, _, _| {});
+        // released before the server sends its initial events
+        output.release();
+        roundtrip(client, server).unwrap();
+        // the protocol id is free again
+        manager.instantiate_exact::<WlOutput>(3).unwrap();
+        roundtrip(client, server).unwrap();
+    });
+}
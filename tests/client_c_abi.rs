@@ -0,0 +1,489 @@
+// Tests of the C API exported by the `c_abi` feature, used the way libEGL's platform-wayland
+// uses the one of libwayland-client.
+
+mod helpers;
+
+use helpers::{wayc, ways};
+
+use ways::protocol::wl_compositor::{Request as CompositorReq, WlCompositor as ServerCompositor};
+use ways::protocol::wl_output::{Mode, Subpixel, Transform, WlOutput as ServerOutput};
+use ways::protocol::wl_surface::Request as SurfaceReq;
+
+use wayc::protocol::{wl_callback, wl_compositor, wl_output, wl_registry, wl_surface};
+use wayc::sys::client::{wl_display, wl_event_queue, wl_proxy};
+use wayc::sys::common::wl_interface;
+use wayc::Interface;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, OsStr};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const WL_MARSHAL_FLAG_DESTROY: u32 = 1;
+
+extern "C" {
+    #[cfg(not(feature = "client_native"))]
+    static wl_display_interface: wl_interface;
+
+    fn wl_display_create_queue(display: *mut wl_display) -> *mut wl_event_queue;
+    fn wl_display_roundtrip_queue(display: *mut wl_display, queue: *mut wl_event_queue) -> c_int;
+    fn wl_display_dispatch_queue_pending(
+        display: *mut wl_display,
+        queue: *mut wl_event_queue,
+    ) -> c_int;
+    fn wl_display_prepare_read_queue(display: *mut wl_display, queue: *mut wl_event_queue)
+        -> c_int;
+    fn wl_display_read_events(display: *mut wl_display) -> c_int;
+    fn wl_display_flush(display: *mut wl_display) -> c_int;
+    fn wl_display_get_fd(display: *mut wl_display) -> c_int;
+    fn wl_display_get_error(display: *mut wl_display) -> c_int;
+    fn wl_event_queue_destroy(queue: *mut wl_event_queue);
+
+    fn wl_proxy_create_wrapper(proxy: *mut wl_proxy) -> *mut wl_proxy;
+    fn wl_proxy_wrapper_destroy(proxy: *mut wl_proxy);
+    fn wl_proxy_set_queue(proxy: *mut wl_proxy, queue: *mut wl_event_queue);
+    fn wl_proxy_marshal_constructor(
+        proxy: *mut wl_proxy,
+        opcode: u32,
+        interface: *const wl_interface,
+        ...
+    ) -> *mut wl_proxy;
+    fn wl_proxy_marshal_flags(
+        proxy: *mut wl_proxy,
+        opcode: u32,
+        interface: *const wl_interface,
+        version: u32,
+        flags: u32,
+        ...
+    ) -> *mut wl_proxy;
+    fn wl_proxy_add_listener(
+        proxy: *mut wl_proxy,
+        listener: *mut extern "C" fn(),
+        data: *mut c_void,
+    ) -> c_int;
+    fn wl_proxy_get_listener(proxy: *mut wl_proxy) -> *const c_void;
+    fn wl_proxy_set_user_data(proxy: *mut wl_proxy, data: *mut c_void);
+    fn wl_proxy_get_user_data(proxy: *mut wl_proxy) -> *mut c_void;
+    fn wl_proxy_get_version(proxy: *mut wl_proxy) -> u32;
+    fn wl_proxy_get_id(proxy: *mut wl_proxy) -> u32;
+    fn wl_proxy_get_class(proxy: *mut wl_proxy) -> *const c_char;
+    fn wl_proxy_destroy(proxy: *mut wl_proxy);
+}
+
+// Run a server whose surfaces enter all the bound outputs and have their frame callbacks done
+// on commit
+fn start_server(socket_name: &'static str) -> (Arc<AtomicBool>, thread::JoinHandle<()>) {
+    let done = Arc::new(AtomicBool::new(false));
+    let started = Arc::new((Mutex::new(false), Condvar::new()));
+    let (server_done, server_started) = (done.clone(), started.clone());
+    let server_thread = thread::spawn(move || {
+        let mut display = ways::Display::new();
+        display.add_socket(Some(socket_name)).unwrap();
+        let outputs = Rc::new(RefCell::new(Vec::<ServerOutput>::new()));
+        let bound = outputs.clone();
+        display.create_global::<ServerOutput, _>(
+            3,
+            ways::Filter::new(move |(output, _): (ways::Main<ServerOutput>, u32), _, _| {
+                output.quick_assign(|_, _, _| {});
+                output.geometry(
+                    0,
+                    0,
+                    520,
+                    290,
+                    Subpixel::Unknown,
+                    "make".into(),
+                    "model".into(),
+                    Transform::Normal,
+                );
+                output.mode(Mode::Current | Mode::Preferred, 1920, 1080, 60_000);
+                output.done();
+                bound.borrow_mut().push((*output).clone());
+            }),
+        );
+        display.create_global::<ServerCompositor, _>(
+            4,
+            ways::Filter::new(move |(compositor, _): (ways::Main<ServerCompositor>, u32), _, _| {
+                let outputs = outputs.clone();
+                compositor.quick_assign(move |_, request, _| {
+                    if let CompositorReq::CreateSurface { id } = request {
+                        let outputs = outputs.clone();
+                        let mut callbacks = Vec::new();
+                        id.quick_assign(move |surface, request, _| match request {
+                            SurfaceReq::Frame { callback } => callbacks.push(callback),
+                            SurfaceReq::Commit => {
+                                for output in outputs.borrow().iter() {
+                                    if output.as_ref().is_alive() {
+                                        surface.enter(output);
+                                    }
+                                }
+                                for callback in callbacks.drain(..) {
+                                    callback.done(42);
+                                }
+                            }
+                            _ => {}
+                        });
+                    }
+                });
+            }),
+        );
+        {
+            let (lock, cvar) = &*server_started;
+            *lock.lock().unwrap() = true;
+            cvar.notify_one();
+        }
+
+        while !server_done.load(Ordering::SeqCst) {
+            display.dispatch(Duration::from_millis(10), &mut ()).unwrap();
+            display.flush_clients(&mut ());
+        }
+    });
+
+    let (lock, cvar) = &*started;
+    let mut started = lock.lock().unwrap();
+    while !*started {
+        started = cvar.wait(started).unwrap();
+    }
+    (done, server_thread)
+}
+
+/*
+ * The listeners, as a C program would write them
+ */
+
+#[derive(Default)]
+struct State {
+    globals: Vec<(u32, String, u32)>,
+    mode: Option<(i32, i32, i32)>,
+    output_done: bool,
+    entered: Vec<*mut wl_proxy>,
+    frame_done: Option<u32>,
+}
+
+unsafe fn state<'a>(data: *mut c_void) -> &'a mut State {
+    &mut *(data as *mut State)
+}
+
+#[repr(C)]
+struct RegistryListener {
+    global: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *const c_char, u32),
+    global_remove: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+unsafe extern "C" fn registry_global(
+    data: *mut c_void,
+    _: *mut wl_proxy,
+    name: u32,
+    interface: *const c_char,
+    version: u32,
+) {
+    let interface = CStr::from_ptr(interface).to_str().unwrap().to_owned();
+    state(data).globals.push((name, interface, version));
+}
+
+unsafe extern "C" fn registry_global_remove(_: *mut c_void, _: *mut wl_proxy, _: u32) {}
+
+static REGISTRY_LISTENER: RegistryListener =
+    RegistryListener { global: registry_global, global_remove: registry_global_remove };
+
+#[repr(C)]
+struct OutputListener {
+    geometry: unsafe extern "C" fn(
+        *mut c_void,
+        *mut wl_proxy,
+        i32,
+        i32,
+        i32,
+        i32,
+        i32,
+        *const c_char,
+        *const c_char,
+        i32,
+    ),
+    mode: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, i32, i32, i32),
+    done: unsafe extern "C" fn(*mut c_void, *mut wl_proxy),
+    scale: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, i32),
+}
+
+unsafe extern "C" fn output_geometry(
+    _: *mut c_void,
+    _: *mut wl_proxy,
+    _: i32,
+    _: i32,
+    width: i32,
+    height: i32,
+    _: i32,
+    make: *const c_char,
+    model: *const c_char,
+    _: i32,
+) {
+    assert_eq!((width, height), (520, 290));
+    assert_eq!(CStr::from_ptr(make).to_str(), Ok("make"));
+    assert_eq!(CStr::from_ptr(model).to_str(), Ok("model"));
+}
+
+unsafe extern "C" fn output_mode(
+    data: *mut c_void,
+    _: *mut wl_proxy,
+    _: u32,
+    width: i32,
+    height: i32,
+    refresh: i32,
+) {
+    state(data).mode = Some((width, height, refresh));
+}
+
+unsafe extern "C" fn output_done(data: *mut c_void, _: *mut wl_proxy) {
+    state(data).output_done = true;
+}
+
+unsafe extern "C" fn output_scale(_: *mut c_void, _: *mut wl_proxy, _: i32) {}
+
+static OUTPUT_LISTENER: OutputListener = OutputListener {
+    geometry: output_geometry,
+    mode: output_mode,
+    done: output_done,
+    scale: output_scale,
+};
+
+#[repr(C)]
+struct SurfaceListener {
+    enter: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, *mut wl_proxy),
+    leave: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, *mut wl_proxy),
+}
+
+unsafe extern "C" fn surface_enter(data: *mut c_void, _: *mut wl_proxy, output: *mut wl_proxy) {
+    state(data).entered.push(output);
+}
+
+unsafe extern "C" fn surface_leave(_: *mut c_void, _: *mut wl_proxy, _: *mut wl_proxy) {}
+
+static SURFACE_LISTENER: SurfaceListener =
+    SurfaceListener { enter: surface_enter, leave: surface_leave };
+
+#[repr(C)]
+struct CallbackListener {
+    done: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+unsafe extern "C" fn callback_done(data: *mut c_void, callback: *mut wl_proxy, serial: u32) {
+    state(data).frame_done = Some(serial);
+    wl_proxy_destroy(callback);
+}
+
+static CALLBACK_LISTENER: CallbackListener = CallbackListener { done: callback_done };
+
+unsafe fn add_listener<L>(proxy: *mut wl_proxy, listener: &'static L, state: &mut State) {
+    let listener = listener as *const L as *mut extern "C" fn();
+    assert_eq!(wl_proxy_add_listener(proxy, listener, state as *mut State as *mut c_void), 0);
+    assert_eq!(wl_proxy_get_listener(proxy), listener as *const c_void);
+}
+
+// Get the registry on a queue of its own, through a wrapper of the display
+unsafe fn get_registry(
+    display: *mut wl_display,
+    queue: *mut wl_event_queue,
+    state: &mut State,
+) -> *mut wl_proxy {
+    let wrapper = wl_proxy_create_wrapper(display as *mut wl_proxy);
+    wl_proxy_set_queue(wrapper, queue);
+    let registry = wl_proxy_marshal_constructor(
+        wrapper,
+        1,
+        wl_registry::WlRegistry::c_interface(),
+        ptr::null_mut::<wl_proxy>(),
+    );
+    wl_proxy_wrapper_destroy(wrapper);
+    assert!(!registry.is_null());
+    add_listener(registry, &REGISTRY_LISTENER, state);
+    assert!(wl_display_roundtrip_queue(display, queue) >= 0);
+    registry
+}
+
+unsafe fn bind(
+    registry: *mut wl_proxy,
+    state: &State,
+    interface: *const wl_interface,
+) -> *mut wl_proxy {
+    let bind_name = CStr::from_ptr((*interface).name);
+    let &(name, _, version) =
+        state.globals.iter().find(|&(_, i, _)| i.as_bytes() == bind_name.to_bytes()).unwrap();
+    let proxy = wl_proxy_marshal_flags(
+        registry,
+        0,
+        interface,
+        version,
+        0,
+        name,
+        (*interface).name,
+        version,
+        ptr::null_mut::<wl_proxy>(),
+    );
+    assert!(!proxy.is_null());
+    proxy
+}
+
+// Dispatch a queue until a condition is met, reading the socket like libEGL does
+unsafe fn dispatch_until(
+    display: *mut wl_display,
+    queue: *mut wl_event_queue,
+    state: &mut State,
+    cond: fn(&State) -> bool,
+) {
+    while !cond(state) {
+        while wl_display_prepare_read_queue(display, queue) != 0 {
+            assert!(wl_display_dispatch_queue_pending(display, queue) >= 0);
+        }
+        assert!(wl_display_flush(display) >= 0);
+        let mut fds =
+            [nix::poll::PollFd::new(wl_display_get_fd(display), nix::poll::PollFlags::POLLIN)];
+        nix::poll::poll(&mut fds, 1000).unwrap();
+        assert_eq!(wl_display_read_events(display), 0);
+        assert!(wl_display_dispatch_queue_pending(display, queue) >= 0);
+    }
+}
+
+#[test]
+fn c_objects() {
+    let (done, server_thread) = start_server("wayland-client-c-abi-objects");
+    let display = wayc::Display::connect_to_name("wayland-client-c-abi-objects").unwrap();
+    let c_display = display.get_display_ptr();
+    let mut state = State::default();
+
+    unsafe {
+        #[cfg(not(feature = "client_native"))]
+        assert_eq!(*(c_display as *const *const wl_interface), &wl_display_interface as *const _);
+
+        let queue = wl_display_create_queue(c_display);
+        let registry = get_registry(c_display, queue, &mut state);
+        assert_eq!(CStr::from_ptr(wl_proxy_get_class(registry)).to_str(), Ok("wl_registry"));
+        assert_eq!(state.globals.len(), 2);
+
+        // the created objects use the queue of their parent
+        let output = bind(registry, &state, wl_output::WlOutput::c_interface());
+        add_listener(output, &OUTPUT_LISTENER, &mut state);
+        let compositor = bind(registry, &state, wl_compositor::WlCompositor::c_interface());
+        assert_eq!(wl_proxy_get_version(output), 3);
+        assert_eq!(wl_proxy_get_version(compositor), 4);
+        assert_eq!(CStr::from_ptr(wl_proxy_get_class(output)).to_str(), Ok("wl_output"));
+        assert!(wl_proxy_get_id(compositor) > wl_proxy_get_id(output));
+        wl_proxy_set_user_data(compositor, 0xDEAD_BEEF as *mut c_void);
+        assert_eq!(wl_proxy_get_user_data(compositor), 0xDEAD_BEEF as *mut c_void);
+        assert_eq!(wl_proxy_get_user_data(output), &mut state as *mut State as *mut c_void);
+
+        dispatch_until(c_display, queue, &mut state, |state| state.output_done);
+        assert_eq!(state.mode, Some((1920, 1080, 60_000)));
+
+        // the arguments of the events are the proxies given to C
+        let surface = wl_proxy_marshal_flags(
+            compositor,
+            0,
+            wl_surface::WlSurface::c_interface(),
+            wl_proxy_get_version(compositor),
+            0,
+            ptr::null_mut::<wl_proxy>(),
+        );
+        add_listener(surface, &SURFACE_LISTENER, &mut state);
+        let callback = wl_proxy_marshal_flags(
+            surface,
+            3,
+            wl_callback::WlCallback::c_interface(),
+            wl_proxy_get_version(surface),
+            0,
+            ptr::null_mut::<wl_proxy>(),
+        );
+        add_listener(callback, &CALLBACK_LISTENER, &mut state);
+        wl_proxy_marshal_flags(surface, 6, ptr::null(), wl_proxy_get_version(surface), 0);
+        dispatch_until(c_display, queue, &mut state, |state| state.frame_done.is_some());
+        assert_eq!(state.frame_done, Some(42));
+        assert_eq!(state.entered, vec![output]);
+
+        wl_proxy_marshal_flags(
+            surface,
+            0,
+            ptr::null(),
+            wl_proxy_get_version(surface),
+            WL_MARSHAL_FLAG_DESTROY,
+        );
+        wl_proxy_marshal_flags(
+            output,
+            0,
+            ptr::null(),
+            wl_proxy_get_version(output),
+            WL_MARSHAL_FLAG_DESTROY,
+        );
+        wl_proxy_destroy(compositor);
+        wl_proxy_destroy(registry);
+        assert!(wl_display_roundtrip_queue(c_display, queue) >= 0);
+        assert_eq!(wl_display_get_error(c_display), 0);
+        wl_event_queue_destroy(queue);
+    }
+
+    done.store(true, Ordering::SeqCst);
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn rust_objects() {
+    let (done, server_thread) = start_server("wayland-client-c-abi-rust");
+    let display = wayc::Display::connect_to_name(OsStr::new("wayland-client-c-abi-rust")).unwrap();
+    let mut event_queue = display.create_event_queue();
+    let attached = (*display).clone().attach(event_queue.token());
+    let manager = wayc::GlobalManager::new(&attached);
+    event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(3).unwrap();
+    output.quick_assign(|_, _, _| {});
+    let compositor = manager.instantiate_exact::<wl_compositor::WlCompositor>(4).unwrap();
+    let surface = compositor.create_surface();
+    let entered = Rc::new(RefCell::new(Vec::new()));
+    surface.quick_assign({
+        let entered = entered.clone();
+        move |_, event, _| {
+            if let wl_surface::Event::Enter { output } = event {
+                entered.borrow_mut().push(output);
+            }
+        }
+    });
+
+    let c_display = display.get_display_ptr();
+    let c_surface = surface.as_ref().c_ptr();
+    let mut state = State::default();
+    unsafe {
+        assert_eq!(wl_proxy_get_id(c_surface), surface.as_ref().id());
+        assert_eq!(wl_proxy_get_version(c_surface), 4);
+        assert_eq!(CStr::from_ptr(wl_proxy_get_class(c_surface)).to_str(), Ok("wl_surface"));
+        // the handle of an object is the same every time
+        assert_eq!(surface.as_ref().c_ptr(), c_surface);
+
+        // a frame callback on a queue of libEGL, for a surface of the application
+        let queue = wl_display_create_queue(c_display);
+        let wrapper = wl_proxy_create_wrapper(c_surface);
+        wl_proxy_set_queue(wrapper, queue);
+        let callback = wl_proxy_marshal_flags(
+            wrapper,
+            3,
+            wl_callback::WlCallback::c_interface(),
+            wl_proxy_get_version(wrapper),
+            0,
+            ptr::null_mut::<wl_proxy>(),
+        );
+        wl_proxy_wrapper_destroy(wrapper);
+        add_listener(callback, &CALLBACK_LISTENER, &mut state);
+        wl_proxy_marshal_flags(c_surface, 6, ptr::null(), wl_proxy_get_version(c_surface), 0);
+        assert!(wl_display_roundtrip_queue(c_display, queue) >= 0);
+        assert_eq!(state.frame_done, Some(42));
+        wl_event_queue_destroy(queue);
+    }
+
+    // the events of the surface are still dispatched to its rust handler
+    event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+    assert_eq!(&*entered.borrow(), &[(**output).clone()]);
+
+    done.store(true, Ordering::SeqCst);
+    server_thread.join().unwrap();
+}
@@ -0,0 +1,86 @@
+#[cfg(not(feature = "client_native"))]
+mod helpers;
+
+#[cfg(not(feature = "client_native"))]
+mod tests {
+    use super::helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+    use wayland_commons::capture::{Capture, Direction, Replayer};
+    use wayland_commons::socket::Socket;
+
+    use std::cell::Cell;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::rc::Rc;
+
+    use ways::protocol::wl_compositor::WlCompositor as ServerCompositor;
+    use ways::protocol::wl_output::WlOutput as ServerOutput;
+
+    fn record_globals() -> Capture {
+        let mut server = TestServer::new();
+        server
+            .display
+            .create_global::<ServerCompositor, _>(4, ways::Filter::new(|_: (_, _), _, _| {}));
+        server.display.create_global::<ServerOutput, _>(2, ways::Filter::new(|_: (_, _), _, _| {}));
+
+        let mut client = TestClient::new(&server.socket_name);
+        client.display.start_capture();
+        let manager = wayc::GlobalManager::new(&client.display_proxy);
+        roundtrip(&mut client, &mut server).unwrap();
+        assert_eq!(manager.list().len(), 2);
+
+        client.display.stop_capture().unwrap()
+    }
+
+    #[test]
+    fn capture_globals() {
+        let capture = record_globals();
+
+        let sent = capture.messages.iter().filter(|m| m.direction == Direction::Sent);
+        let names = sent.map(|m| (&m.interface[..], &m.name[..])).collect::<Vec<_>>();
+        assert_eq!(names, vec![("wl_display", "get_registry"), ("wl_display", "sync")]);
+
+        let globals = capture
+            .messages
+            .iter()
+            .filter(|m| m.direction == Direction::Received && m.interface == "wl_registry")
+            .count();
+        assert_eq!(globals, 2);
+
+        // the capture survives serialization
+        let mut buffer = Vec::new();
+        capture.write_to(&mut buffer).unwrap();
+        assert_eq!(Capture::read_from(&buffer[..]).unwrap().messages.len(), capture.messages.len());
+    }
+
+    #[test]
+    fn replay_globals() {
+        let capture = record_globals();
+
+        let (server_end, client_end) = UnixStream::pair().unwrap();
+        let mut replayer =
+            Replayer::new(capture, unsafe { Socket::from_raw_fd(server_end.into_raw_fd()) })
+                .unwrap();
+        let mut client = unsafe { TestClient::from_fd(client_end.into_raw_fd()) };
+
+        // the client must send the same requests as during the recording, for the
+        // replayed events to target the same objects
+        let manager = wayc::GlobalManager::new(&client.display_proxy);
+        let done = Rc::new(Cell::new(false));
+        let done2 = done.clone();
+        client.display_proxy.sync().quick_assign(move |_, _, _| done2.set(true));
+        client.display.flush().unwrap();
+
+        while replayer.feed().unwrap() > 0 {
+            client.event_queue.prepare_read().unwrap().read_events().unwrap();
+            client.event_queue.dispatch_pending(&mut (), |_, _, _| {}).unwrap();
+        }
+        assert!(replayer.is_done());
+        assert!(done.get());
+
+        let globals = manager.list();
+        assert_eq!(globals.len(), 2);
+        assert_eq!(globals[0], (1, "wl_compositor".into(), 4));
+        assert_eq!(globals[1], (2, "wl_output".into(), 2));
+    }
+}
@@ -4,7 +4,7 @@ use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
 
 use ways::protocol::wl_output::WlOutput as ServerOutput;
 
-use std::os::unix::io::IntoRawFd;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
 
 fn main() {
     let mut server = TestServer::new();
@@ -29,4 +29,51 @@ fn main() {
     my_client.kill();
 
     assert!(roundtrip(&mut client, &mut server).is_err());
+
+    // the variable is consumed by the connection
+    assert!(::std::env::var_os("WAYLAND_SOCKET").is_none());
+    assert!(wayc::Display::connect_to_wayland_socket(&wayc::ConnectOptions::new()).is_err());
+
+    // a connection not owning its fd
+    let (s1, s2) = ::std::os::unix::net::UnixStream::pair().unwrap();
+    unsafe { server.display.create_client(s1.into_raw_fd(), &mut ()) };
+    let options = wayc::ConnectOptions::new().owned(false).cloexec(false);
+    let display = unsafe { wayc::Display::from_fd_with_options(s2.as_raw_fd(), &options) }.unwrap();
+    let mut client = TestClient::from_display(display);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(manager.list().len(), 1);
+    ::std::mem::drop((manager, client));
+    // the fd is still open
+    assert!(::nix::fcntl::fcntl(s2.as_raw_fd(), ::nix::fcntl::FcntlArg::F_GETFD).is_ok());
+
+    connect_to_abstract(&mut server);
+}
+
+fn connect_to_abstract(server: &mut TestServer) {
+    use nix::sys::socket;
+
+    let name = format!("wayland-rs-test-{}", ::std::process::id());
+    let addr = socket::UnixAddr::new_abstract(name.as_bytes()).unwrap();
+    let listener = socket::socket(
+        socket::AddressFamily::Unix,
+        socket::SockType::Stream,
+        socket::SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .unwrap();
+    socket::bind(listener, &socket::SockAddr::Unix(addr)).unwrap();
+    socket::listen(listener, 1).unwrap();
+
+    let display = wayc::Display::connect_to_abstract(&name).unwrap();
+    let fd = socket::accept(listener).unwrap();
+    unsafe { server.display.create_client(fd, &mut ()) };
+
+    let mut client = TestClient::from_display(display);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, server).unwrap();
+    assert_eq!(manager.list().len(), 1);
+
+    let _ = ::nix::unistd::close(listener);
+    assert!(wayc::Display::connect_to_abstract(&name).is_err());
 }
@@ -1,11 +1,15 @@
 mod helpers;
 
-use helpers::{ways, TestClient};
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
 
 use std::cell::Cell;
 use std::ffi::OsStr;
+use std::future::Future;
+use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 use std::time::Duration;
 
 #[test]
@@ -109,3 +113,317 @@ fn client_dispatch() {
 
     server_thread.join().unwrap();
 }
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn client_dispatch_stats() {
+    use wayc::protocol::wl_output;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(
+            |(output, _): (ways::Main<ways::protocol::wl_output::WlOutput>, u32), _, _| {
+                output.scale(1);
+                output.done();
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert!(client.event_queue.stats().is_none());
+    client.event_queue.start_stats();
+    let slow = Rc::new(std::cell::RefCell::new(Vec::new()));
+    client.event_queue.set_slow_dispatch_hook(Duration::from_millis(20), {
+        let slow = slow.clone();
+        move |info| slow.borrow_mut().push(info)
+    });
+
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    output.quick_assign(|_, event, _| {
+        if let wl_output::Event::Done = event {
+            std::thread::sleep(Duration::from_millis(30));
+        }
+    });
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let stats = client.event_queue.stop_stats().unwrap();
+    assert_eq!(stats.dispatched.get("wl_output"), Some(&2));
+    assert_eq!(stats.dispatched.get("wl_callback"), Some(&1));
+    // the two output events and the sync callback arrived together
+    assert!(stats.max_queue_depth >= 3);
+    assert!(stats.filter_time >= Duration::from_millis(30));
+    assert!(client.event_queue.stats().is_none());
+
+    let slow = slow.borrow();
+    assert_eq!(slow.len(), 1);
+    assert_eq!((slow[0].interface, slow[0].event), ("wl_output", "done"));
+    assert_eq!(slow[0].id, output.as_ref().id());
+    assert!(slow[0].duration >= Duration::from_millis(30));
+}
+
+#[test]
+fn client_dispatch_timeout() {
+    use std::io::ErrorKind;
+    use std::time::Instant;
+
+    let mut server = TestServer::new();
+    let mut client = TestClient::new(&server.socket_name);
+
+    // the server does not answer, no events arrive
+    let start = Instant::now();
+    let ret =
+        client.event_queue.dispatch_timeout(&mut (), Duration::from_millis(100), |_, _, _| {});
+    assert_eq!(ret.unwrap(), 0);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    let start = Instant::now();
+    let err = client
+        .event_queue
+        .sync_roundtrip_timeout(&mut (), Duration::from_millis(100), |_, _, _| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // once the server answers, the timed out roundtrip completes
+    server.answer();
+    let ret = client.event_queue.dispatch_timeout(&mut (), Duration::from_secs(5), |_, _, _| {});
+    assert!(ret.unwrap() > 0);
+}
+
+// a waker counting how many times it was woken
+fn counting_waker(count: &Arc<AtomicUsize>) -> Waker {
+    fn clone(data: *const ()) -> RawWaker {
+        let count = unsafe { Arc::from_raw(data as *const AtomicUsize) };
+        std::mem::forget(count.clone());
+        RawWaker::new(Arc::into_raw(count) as *const (), &VTABLE)
+    }
+    fn wake(data: *const ()) {
+        wake_by_ref(data);
+        drop_waker(data);
+    }
+    fn wake_by_ref(data: *const ()) {
+        unsafe { &*(data as *const AtomicUsize) }.fetch_add(1, Ordering::SeqCst);
+    }
+    fn drop_waker(data: *const ()) {
+        drop(unsafe { Arc::from_raw(data as *const AtomicUsize) });
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop_waker);
+    let data = Arc::into_raw(count.clone()) as *const ();
+    unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) }
+}
+
+#[test]
+fn client_dispatch_response_future() {
+    let mut server = TestServer::new();
+    let mut client = TestClient::new(&server.socket_name);
+
+    let wakes = Arc::new(AtomicUsize::new(0));
+    let waker = counting_waker(&wakes);
+    let mut cx = Context::from_waker(&waker);
+
+    let mut future = client.display_proxy.sync_async();
+    assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert_eq!(wakes.load(Ordering::SeqCst), 1);
+    match Pin::new(&mut future).poll(&mut cx) {
+        Poll::Ready(wayc::protocol::wl_callback::Event::Done { .. }) => {}
+        _ => panic!("the future did not resolve with the done event"),
+    }
+}
+
+#[test]
+fn client_dispatch_unhandled_event_sink() {
+    use wayc::protocol::wl_output;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(
+            |(output, _): (ways::Main<ways::protocol::wl_output::WlOutput>, u32), _, _| {
+                output.scale(2);
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let unhandled = Arc::new(Mutex::new(Vec::new()));
+    let sink_unhandled = unhandled.clone();
+    client.display.set_unhandled_event_sink(move |event| {
+        sink_unhandled.lock().unwrap().push((
+            event.interface,
+            event.opcode,
+            event.name,
+            event.id,
+            format!("{:?}", event.args),
+        ));
+    });
+
+    // the output has no filter, its event goes to the sink
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert_eq!(
+        &*unhandled.lock().unwrap(),
+        &[("wl_output", 3, "scale", output.as_ref().id(), "[Int(2)]".to_owned())]
+    );
+
+    client.display.clear_unhandled_event_sink();
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn client_dispatch_some() {
+    use wayc::protocol::wl_output;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(
+            |(output, _): (ways::Main<ways::protocol::wl_output::WlOutput>, u32), _, _| {
+                output.scale(1);
+                output.scale(2);
+                output.scale(3);
+                output.done();
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let received = Rc::new(Cell::new(0));
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    let output_received = received.clone();
+    output.quick_assign(move |_, _, _| output_received.set(output_received.get() + 1));
+
+    // receive the events of the output without dispatching them
+    client.display.flush().unwrap();
+    server.answer();
+    std::thread::sleep(Duration::from_millis(100));
+    client.event_queue.prepare_read().unwrap().read_events().unwrap();
+
+    let ret = client.event_queue.dispatch_some(&mut (), 3, |_, _, _| unreachable!()).unwrap();
+    assert_eq!(ret, (3, true));
+    assert_eq!(received.get(), 3);
+
+    let ret = client.event_queue.dispatch_some(&mut (), 3, |_, _, _| unreachable!()).unwrap();
+    assert_eq!(ret, (1, false));
+    assert_eq!(received.get(), 4);
+
+    let ret = client.event_queue.dispatch_some(&mut (), 3, |_, _, _| unreachable!()).unwrap();
+    assert_eq!(ret, (0, false));
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn client_dispatch_high_priority() {
+    use wayc::protocol::wl_output;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(
+            |(output, _): (ways::Main<ways::protocol::wl_output::WlOutput>, u32), _, _| {
+                output.scale(1);
+                output.scale(2);
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let received = Rc::new(std::cell::RefCell::new(Vec::new()));
+    let assign = |output: &wayc::Main<wl_output::WlOutput>, name: &'static str| {
+        let received = received.clone();
+        output.quick_assign(move |_, event, _| {
+            if let wl_output::Event::Scale { factor } = event {
+                received.borrow_mut().push((name, factor));
+            }
+        });
+    };
+    let bulk = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    assign(&bulk, "bulk");
+    let urgent = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    assign(&urgent, "urgent");
+    urgent.as_ref().set_high_priority(true);
+    assert!(urgent.as_ref().is_high_priority());
+    assert!(!bulk.as_ref().is_high_priority());
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the events of the urgent output are dispatched first, each object keeps its order
+    assert_eq!(&*received.borrow(), &[("urgent", 1), ("urgent", 2), ("bulk", 1), ("bulk", 2)]);
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn client_dispatch_high_priority_pending() {
+    use wayc::protocol::wl_output;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(
+            |(output, _): (ways::Main<ways::protocol::wl_output::WlOutput>, u32), _, _| {
+                output.scale(1);
+                output.scale(2);
+                output.done();
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let received = Rc::new(std::cell::RefCell::new(Vec::new()));
+    let assign = |output: &wayc::Main<wl_output::WlOutput>, name: &'static str| {
+        let received = received.clone();
+        output.quick_assign(move |_, event, _| match event {
+            wl_output::Event::Scale { factor } => received.borrow_mut().push((name, factor)),
+            wl_output::Event::Done => received.borrow_mut().push((name, 0)),
+            _ => {}
+        });
+    };
+    let bulk = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    assign(&bulk, "bulk");
+    let urgent = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    assign(&urgent, "urgent");
+
+    // receive the events of both outputs without dispatching them
+    client.display.flush().unwrap();
+    server.answer();
+    ::std::thread::sleep(Duration::from_millis(100));
+    client.event_queue.prepare_read().unwrap().read_events().unwrap();
+
+    // the first event of the bulk output is dispatched, then the urgent one is prioritized
+    let ret = client.event_queue.dispatch_some(&mut (), 1, |_, _, _| unreachable!()).unwrap();
+    assert_eq!(ret, (1, true));
+    urgent.as_ref().set_high_priority(true);
+    client.event_queue.dispatch_pending(&mut (), |_, _, _| unreachable!()).unwrap();
+
+    // the waiting events of the urgent output are dispatched first, in order
+    assert_eq!(
+        &*received.borrow(),
+        &[("bulk", 1), ("urgent", 1), ("urgent", 2), ("urgent", 0), ("bulk", 2), ("bulk", 0)]
+    );
+}
@@ -0,0 +1,169 @@
+// Dispatch throughput benchmarks, run them with:
+//
+//     cargo test --release --test client_dispatch_bench -- --ignored --nocapture
+
+mod helpers;
+
+use helpers::{wayc, ways, TestClient};
+
+use wayc::protocol::wl_output;
+
+use std::ffi::OsStr;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// number of events received by each output
+const EVENTS: usize = 200_000;
+// maximum number of bytes waiting to be sent to the client
+const BACKLOG: usize = 16 * 1024;
+
+// Run a server sending `EVENTS` mode events to each of the outputs of the client
+fn start_server(
+    socket_name: &'static str,
+    outputs: usize,
+) -> (Arc<AtomicBool>, thread::JoinHandle<()>) {
+    let done = Arc::new(AtomicBool::new(false));
+    let started = Arc::new((Mutex::new(false), Condvar::new()));
+    let (server_done, server_started) = (done.clone(), started.clone());
+    let server_thread = thread::spawn(move || {
+        let mut display = ways::Display::new();
+        display.add_socket(Some(socket_name)).unwrap();
+        let bound = Arc::new(Mutex::new(Vec::new()));
+        let server_bound = bound.clone();
+        display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+            2,
+            ways::Filter::new(
+                move |(output, _): (ways::Main<ways::protocol::wl_output::WlOutput>, u32), _, _| {
+                    server_bound.lock().unwrap().push((output, 0));
+                },
+            ),
+        );
+        {
+            let (lock, cvar) = &*server_started;
+            *lock.lock().unwrap() = true;
+            cvar.notify_one();
+        }
+
+        while !server_done.load(Ordering::SeqCst) {
+            let mut bound = bound.lock().unwrap();
+            if bound.len() == outputs {
+                let client = bound[0].0.as_ref().client().unwrap();
+                if client.pending_bytes() < BACKLOG {
+                    for &mut (ref output, ref mut sent) in bound.iter_mut() {
+                        for _ in 0..(EVENTS - *sent).min(100) {
+                            output.mode(
+                                ways::protocol::wl_output::Mode::Current,
+                                1920,
+                                1080,
+                                60_000,
+                            );
+                            *sent += 1;
+                        }
+                    }
+                }
+            }
+            ::std::mem::drop(bound);
+            display.flush_clients(&mut ());
+            display.dispatch(Duration::from_millis(0), &mut ()).unwrap();
+        }
+    });
+
+    let (lock, cvar) = &*started;
+    let mut started = lock.lock().unwrap();
+    while !*started {
+        started = cvar.wait(started).unwrap();
+    }
+    (done, server_thread)
+}
+
+// Bind an output on a new event queue, and dispatch it until it received all the events
+fn dispatch_output(display: &wayc::Display, ready: &(Mutex<usize>, Condvar), outputs: usize) {
+    let mut evq = display.create_event_queue();
+    let attached = (**display).clone().attach(evq.token());
+    let manager = wayc::GlobalManager::new(&attached);
+    evq.sync_roundtrip(&mut (), |_, _, _| unreachable!()).unwrap();
+    let received = Arc::new(AtomicUsize::new(0));
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    output.quick_assign({
+        let received = received.clone();
+        move |_, _, _| {
+            received.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    evq.sync_roundtrip(&mut (), |_, _, _| unreachable!()).unwrap();
+
+    // all the outputs are dispatched at the same time
+    let (lock, cvar) = ready;
+    let mut ready = lock.lock().unwrap();
+    *ready += 1;
+    cvar.notify_all();
+    while *ready < outputs {
+        ready = cvar.wait(ready).unwrap();
+    }
+    ::std::mem::drop(ready);
+
+    // another queue may read the last events of this one, don't block on the socket
+    while received.load(Ordering::Relaxed) < EVENTS {
+        evq.dispatch_pending(&mut (), |_, _, _| unreachable!()).unwrap();
+        if let Some(guard) = evq.prepare_read() {
+            match guard.read_events() {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+                Err(e) => panic!("Failed to read the events: {}", e),
+            }
+        }
+    }
+}
+
+fn bench_dispatch(socket_name: &'static str, queues: usize) {
+    let (done, server_thread) = start_server(socket_name, queues);
+    let client = TestClient::new(OsStr::new(socket_name));
+
+    let ready = Arc::new((Mutex::new(0), Condvar::new()));
+    let threads = (0..queues)
+        .map(|_| {
+            let display = client.display.clone();
+            let ready = ready.clone();
+            thread::spawn(move || dispatch_output(&display, &ready, queues))
+        })
+        .collect::<Vec<_>>();
+
+    // the timer starts once all the outputs are bound
+    {
+        let (lock, cvar) = &*ready;
+        let mut ready = lock.lock().unwrap();
+        while *ready < queues {
+            ready = cvar.wait(ready).unwrap();
+        }
+    }
+    let begin = Instant::now();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let elapsed = begin.elapsed();
+
+    done.store(true, Ordering::SeqCst);
+    server_thread.join().unwrap();
+    println!(
+        "{} queue(s): {} events in {:?}, {:.0} events/s",
+        queues,
+        queues * EVENTS,
+        elapsed,
+        (queues * EVENTS) as f64 / elapsed.as_secs_f64()
+    );
+}
+
+#[test]
+#[ignore]
+fn dispatch_one_queue() {
+    bench_dispatch("wayland-client-dispatch-bench-1", 1);
+}
+
+#[test]
+#[ignore]
+fn dispatch_four_queues() {
+    bench_dispatch("wayland-client-dispatch-bench-4", 4);
+}
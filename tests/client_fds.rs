@@ -0,0 +1,200 @@
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+fn insert_seat(
+    server: &mut TestServer,
+) -> Arc<Mutex<Option<ways::Main<ways::protocol::wl_keyboard::WlKeyboard>>>> {
+    use ways::protocol::wl_seat;
+
+    let keyboard = Arc::new(Mutex::new(None));
+    let keyboard2 = keyboard.clone();
+
+    server.display.create_global::<wl_seat::WlSeat, _>(
+        1,
+        ways::Filter::new(move |(seat, _): (ways::Main<wl_seat::WlSeat>, u32), _, _| {
+            let keyboard = keyboard.clone();
+            seat.quick_assign(move |_, request, _| {
+                if let wl_seat::Request::GetKeyboard { id } = request {
+                    id.quick_assign(|_, _, _| {});
+                    *keyboard.lock().unwrap() = Some(id);
+                }
+            });
+        }),
+    );
+
+    keyboard2
+}
+
+fn send_keymaps(
+    server: &mut TestServer,
+    keyboard: &ways::protocol::wl_keyboard::WlKeyboard,
+    n: usize,
+) {
+    let file = tempfile::tempfile().unwrap();
+    for _ in 0..n {
+        keyboard.keymap(ways::protocol::wl_keyboard::KeymapFormat::NoKeymap, file.as_raw_fd(), 0);
+    }
+    server.display.flush_clients(&mut ());
+}
+
+#[test]
+fn received_fds_are_cloexec() {
+    let mut server = TestServer::new();
+    let server_keyboard = insert_seat(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let seat = manager.instantiate_exact::<wayc::protocol::wl_seat::WlSeat>(1).unwrap();
+    let keyboard = seat.get_keyboard();
+    let cloexec = Arc::new(Mutex::new(Vec::new()));
+    keyboard.quick_assign({
+        let cloexec = cloexec.clone();
+        move |_, event, _| {
+            if let wayc::protocol::wl_keyboard::Event::Keymap { fd, .. } = event {
+                let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD).unwrap());
+                cloexec.lock().unwrap().push(flags.contains(FdFlag::FD_CLOEXEC));
+                let _ = nix::unistd::close(fd);
+            }
+        }
+    });
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let server_keyboard = server_keyboard.lock().unwrap().take().unwrap();
+    send_keymaps(&mut server, &server_keyboard, 1);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert_eq!(*cloexec.lock().unwrap(), vec![true]);
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn fd_budget() {
+    use std::io::ErrorKind;
+
+    let mut server = TestServer::new();
+    let server_keyboard = insert_seat(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the keyboard events go to a queue that we don't dispatch
+    let mut keyboard_queue = client.display.create_event_queue();
+    let seat = manager.instantiate_exact::<wayc::protocol::wl_seat::WlSeat>(1).unwrap();
+    let keyboard = seat.as_ref().clone().attach(keyboard_queue.token()).get_keyboard();
+    let received = Arc::new(Mutex::new(0));
+    keyboard.quick_assign({
+        let received = received.clone();
+        move |_, event, _| {
+            if let wayc::protocol::wl_keyboard::Event::Keymap { fd, .. } = event {
+                *received.lock().unwrap() += 1;
+                let _ = nix::unistd::close(fd);
+            }
+        }
+    });
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let pressure = Arc::new(Mutex::new(Vec::new()));
+    client.display.set_fd_budget(2, {
+        let pressure = pressure.clone();
+        move |held| pressure.lock().unwrap().push(held)
+    });
+
+    let server_keyboard = server_keyboard.lock().unwrap().take().unwrap();
+    send_keymaps(&mut server, &server_keyboard, 2);
+
+    // read the events without dispatching them
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    client.event_queue.prepare_read().unwrap().read_events().unwrap();
+    assert_eq!(client.display.pending_fds(), 2);
+
+    // the budget is reached, the next messages stay in the socket
+    send_keymaps(&mut server, &server_keyboard, 1);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let err = client.event_queue.prepare_read().unwrap().read_events().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    assert_eq!(*pressure.lock().unwrap(), vec![2]);
+    assert_eq!(client.display.pending_fds(), 2);
+
+    // dispatching releases the fds and reading resumes
+    keyboard_queue.dispatch_pending(&mut (), |_, _, _| {}).unwrap();
+    assert_eq!(client.display.pending_fds(), 0);
+    assert_eq!(*received.lock().unwrap(), 2);
+
+    roundtrip(&mut client, &mut server).unwrap();
+    keyboard_queue.dispatch_pending(&mut (), |_, _, _| {}).unwrap();
+    assert_eq!(*received.lock().unwrap(), 3);
+    assert_eq!(client.display.pending_fds(), 0);
+    assert_eq!(*pressure.lock().unwrap(), vec![2]);
+}
+
+#[test]
+fn keymap_wrapper() {
+    use std::io::Write;
+    use wayc::keymap::Keymap;
+    use ways::protocol::wl_keyboard::KeymapFormat;
+
+    let mut server = TestServer::new();
+    let server_keyboard = insert_seat(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let seat = manager.instantiate_exact::<wayc::protocol::wl_seat::WlSeat>(1).unwrap();
+    let keyboard = seat.get_keyboard();
+    let keymaps = Arc::new(Mutex::new(Vec::new()));
+    let others = Arc::new(Mutex::new(Vec::new()));
+    keyboard.quick_assign({
+        let keymaps = keymaps.clone();
+        let others = others.clone();
+        move |keyboard, event, _| match Keymap::from_event(&keyboard, event) {
+            Ok(keymap) => {
+                let keymap = keymap.unwrap();
+                let contents = keymap.as_str().unwrap().to_owned();
+                keymaps.lock().unwrap().push((keymap.format(), contents, keymap.as_bytes().len()));
+            }
+            Err(event) => others.lock().unwrap().push(event),
+        }
+    });
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let server_keyboard = server_keyboard.lock().unwrap().take().unwrap();
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(b"xkb_keymap { };\0").unwrap();
+    server_keyboard.keymap(KeymapFormat::XkbV1, file.as_raw_fd(), 16);
+    server_keyboard.modifiers(1, 2, 0, 0, 0);
+    server_keyboard.keymap(KeymapFormat::NoKeymap, file.as_raw_fd(), 0);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    use wayc::protocol::wl_keyboard::KeymapFormat as ClientFormat;
+    assert_eq!(
+        *keymaps.lock().unwrap(),
+        vec![
+            (ClientFormat::XkbV1, "xkb_keymap { };".to_owned(), 16),
+            (ClientFormat::NoKeymap, String::new(), 0)
+        ]
+    );
+    // the other events are given back
+    let others = others.lock().unwrap();
+    match &others[..] {
+        [wayc::protocol::wl_keyboard::Event::Modifiers { serial: 1, mods_depressed: 2, .. }] => {}
+        others => panic!("Unexpected events: {:?}", others),
+    }
+}
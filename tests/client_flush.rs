@@ -0,0 +1,110 @@
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use std::io::ErrorKind;
+
+use ways::protocol::wl_compositor::{
+    Request as CompositorRequest, WlCompositor as ServerCompositor,
+};
+
+#[test]
+fn flush_partial_progress() {
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerCompositor, _>(
+        1,
+        ways::Filter::new(|(compositor, _): (ways::Main<ServerCompositor>, u32), _, _| {
+            compositor.quick_assign(|_, request, _| {
+                if let CompositorRequest::CreateRegion { id } = request {
+                    id.quick_assign(|_, _, _| {});
+                }
+            })
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor =
+        manager.instantiate_exact::<wayc::protocol::wl_compositor::WlCompositor>(1).unwrap();
+    let region = compositor.create_region();
+
+    // send requests without the server reading them, until the socket is full
+    let mut written = 0;
+    loop {
+        for _ in 0..100 {
+            region.add(0, 0, 1, 1);
+        }
+        match client.display.flush() {
+            Ok(progress) => {
+                written += progress.written;
+                if !progress.is_complete() {
+                    break;
+                }
+            }
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::WouldBlock);
+                break;
+            }
+        }
+    }
+    assert!(written > 0);
+
+    // once the server reads them, the remaining requests can be written
+    server.answer();
+    roundtrip(&mut client, &mut server).unwrap();
+    let progress = client.display.flush().unwrap();
+    assert!(progress.is_complete());
+    assert_eq!(progress.written, 0);
+}
+
+// libwayland kills the connection when its buffer is full
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn requests_queued_on_full_socket() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let received = Rc::new(Cell::new(0));
+    let received2 = received.clone();
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerCompositor, _>(
+        1,
+        ways::Filter::new(move |(compositor, _): (ways::Main<ServerCompositor>, u32), _, _| {
+            let received = received2.clone();
+            compositor.quick_assign(move |_, request, _| {
+                if let CompositorRequest::CreateRegion { id } = request {
+                    let received = received.clone();
+                    id.quick_assign(move |_, _, _| received.set(received.get() + 1));
+                }
+            })
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor =
+        manager.instantiate_exact::<wayc::protocol::wl_compositor::WlCompositor>(1).unwrap();
+    let region = compositor.create_region();
+
+    // far more requests than the socket can hold, without the server reading them
+    let sent = 50_000;
+    for _ in 0..sent {
+        region.add(0, 0, 1, 1);
+    }
+    assert_eq!(client.display.flush().unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert!(region.as_ref().is_alive());
+
+    // none of them was lost
+    while received.get() < sent {
+        server.answer();
+        let _ = client.display.flush();
+    }
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(received.get(), sent);
+}
@@ -1,12 +1,13 @@
 mod helpers;
 
-use helpers::{wayc, ways, TestClient};
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
 
 use ways::protocol::wl_seat::WlSeat as ServerSeat;
 
 use wayc::protocol::wl_seat;
 
 use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -136,3 +137,325 @@ fn display_from_external_on_new_thread() {
 
     server_thread.join().unwrap();
 }
+
+#[test]
+fn assign_threadsafe_from_other_thread() {
+    let socket_name = "wayland-client-assign-threadsafe";
+
+    let kill_switch = Arc::new(Mutex::new(false));
+    let server_kill_switch = kill_switch.clone();
+
+    let server_startup_info = Arc::new((Mutex::new(false), Condvar::new()));
+    let server_startup_info_clone = server_startup_info.clone();
+
+    let server_thread = thread::spawn(move || {
+        let mut display = ways::Display::new();
+        display.add_socket(Some(socket_name)).unwrap();
+
+        // Make sure to release the lock.
+        {
+            let (lock, cvar) = &*server_startup_info_clone;
+            let mut started = lock.lock().unwrap();
+            *started = true;
+            // Notify the client that we're ready.
+            cvar.notify_one();
+        }
+
+        loop {
+            display.dispatch(Duration::from_millis(10), &mut ()).unwrap();
+            display.flush_clients(&mut ());
+            if *(server_kill_switch.lock().unwrap()) {
+                break;
+            }
+        }
+    });
+
+    // Wait for the server to start up.
+    let (lock, cvar) = &*server_startup_info;
+    let mut started = lock.lock().unwrap();
+    while !*started {
+        started = cvar.wait(started).unwrap();
+    }
+
+    let client = TestClient::new(OsStr::new(socket_name));
+
+    let display_clone = client.display.clone();
+    let done = Arc::new(AtomicBool::new(false));
+    let worker_done = done.clone();
+
+    // the worker owns its event queue, the closure shares its state with this thread
+    let worker = thread::spawn(move || {
+        let mut evq = display_clone.create_event_queue();
+        let attached = (**display_clone).clone().attach(evq.token());
+        let callback_done = worker_done.clone();
+        attached
+            .sync()
+            .assign_threadsafe(move |_, _, _| callback_done.store(true, Ordering::SeqCst));
+        while !worker_done.load(Ordering::SeqCst) {
+            evq.dispatch(&mut (), |_, _, _| unreachable!()).unwrap();
+        }
+    });
+
+    worker.join().unwrap();
+    assert!(done.load(Ordering::SeqCst));
+
+    *kill_switch.lock().unwrap() = true;
+
+    server_thread.join().unwrap();
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn event_queue_handoff() {
+    use wayc::HandoffError;
+
+    let socket_name = "wayland-client-event-queue-handoff";
+
+    let kill_switch = Arc::new(Mutex::new(false));
+    let server_kill_switch = kill_switch.clone();
+
+    let server_startup_info = Arc::new((Mutex::new(false), Condvar::new()));
+    let server_startup_info_clone = server_startup_info.clone();
+
+    let server_thread = thread::spawn(move || {
+        let mut display = ways::Display::new();
+        display.add_socket(Some(socket_name)).unwrap();
+
+        // Make sure to release the lock.
+        {
+            let (lock, cvar) = &*server_startup_info_clone;
+            let mut started = lock.lock().unwrap();
+            *started = true;
+            // Notify the client that we're ready.
+            cvar.notify_one();
+        }
+
+        loop {
+            display.dispatch(Duration::from_millis(10), &mut ()).unwrap();
+            display.flush_clients(&mut ());
+            if *(server_kill_switch.lock().unwrap()) {
+                break;
+            }
+        }
+    });
+
+    // Wait for the server to start up.
+    let (lock, cvar) = &*server_startup_info;
+    let mut started = lock.lock().unwrap();
+    while !*started {
+        started = cvar.wait(started).unwrap();
+    }
+
+    let client = TestClient::new(OsStr::new(socket_name));
+
+    // set up an object on this thread
+    let evq = client.display.create_event_queue();
+    let attached = (**client.display).clone().attach(evq.token());
+    let callback = attached.sync();
+    callback.quick_assign(|_, _, _| {});
+
+    let token = evq.token();
+    let (evq, error) = evq.handoff().err().unwrap();
+    assert_eq!(error, HandoffError::TokensAlive);
+    drop(token);
+
+    let (evq, error) = evq.handoff().err().unwrap();
+    assert_eq!(
+        error,
+        HandoffError::ThreadBoundFilter { interface: "wl_callback", id: callback.as_ref().id() }
+    );
+
+    let done = Arc::new(AtomicBool::new(false));
+    let callback_done = done.clone();
+    callback.assign_threadsafe(move |_, _, _| callback_done.store(true, Ordering::SeqCst));
+    let handoff = evq.handoff().ok().unwrap();
+
+    // the worker dispatches the queue created on this thread
+    let worker_done = done.clone();
+    let worker = thread::spawn(move || {
+        let mut evq = handoff.into_queue();
+        while !worker_done.load(Ordering::SeqCst) {
+            evq.dispatch(&mut (), |_, _, _| unreachable!()).unwrap();
+        }
+    });
+
+    worker.join().unwrap();
+    assert!(done.load(Ordering::SeqCst));
+
+    *kill_switch.lock().unwrap() = true;
+
+    server_thread.join().unwrap();
+}
+
+#[cfg(feature = "client_native")]
+#[test]
+fn event_queue_handoff_unsupported() {
+    let mut server = TestServer::new();
+    let mut client = TestClient::new(&server.socket_name);
+
+    let evq = client.display.create_event_queue();
+    let (_, error) = evq.handoff().err().unwrap();
+    assert_eq!(error, wayc::HandoffError::Unsupported);
+
+    // the settings of the rust implementation are accepted, and have no effect
+    client.display.set_request_staging(true);
+    client.display.set_strictness(wayc::Strictness::Strict);
+    client.display.start_capture();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(client.display.stop_capture().is_none());
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn request_staging() {
+    use std::collections::HashMap;
+    use ways::protocol::{wl_compositor, wl_surface};
+
+    let mut server = TestServer::new();
+    let damages = Arc::new(Mutex::new(HashMap::<u32, Vec<i32>>::new()));
+    let server_damages = damages.clone();
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        1,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                let damages = server_damages.clone();
+                compositor.quick_assign(move |_, request, _| {
+                    if let wl_compositor::Request::CreateSurface { id } = request {
+                        let damages = damages.clone();
+                        id.quick_assign(move |surface, request, _| match request {
+                            wl_surface::Request::Damage { x, .. } => damages
+                                .lock()
+                                .unwrap()
+                                .entry(surface.as_ref().id())
+                                .or_default()
+                                .push(x),
+                            wl_surface::Request::Commit => damages
+                                .lock()
+                                .unwrap()
+                                .entry(surface.as_ref().id())
+                                .or_default()
+                                .push(-1),
+                            _ => {}
+                        });
+                    }
+                });
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    client.display.set_request_staging(true);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    let compositor =
+        manager.instantiate_exact::<wayc::protocol::wl_compositor::WlCompositor>(1).unwrap();
+
+    // more requests than a staging buffer holds, from several threads at once
+    let threads = (0..4)
+        .map(|_| {
+            let surface = compositor.create_surface().detach();
+            thread::spawn(move || {
+                for x in 0..300 {
+                    surface.damage(x, 0, 1, 1);
+                }
+                surface.commit();
+                surface.as_ref().id()
+            })
+        })
+        .collect::<Vec<_>>();
+    let ids = threads.into_iter().map(|thread| thread.join().unwrap()).collect::<Vec<_>>();
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let mut expected = (0..300).collect::<Vec<_>>();
+    expected.push(-1);
+    let damages = damages.lock().unwrap();
+    assert_eq!(damages.len(), 4);
+    for id in ids {
+        assert_eq!(damages[&id], expected);
+    }
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn request_staging_order() {
+    use ways::protocol::{wl_compositor, wl_surface};
+
+    let mut server = TestServer::new();
+    let damages = Arc::new(Mutex::new(Vec::new()));
+    let server_damages = damages.clone();
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        1,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                let damages = server_damages.clone();
+                compositor.quick_assign(move |_, request, _| {
+                    if let wl_compositor::Request::CreateSurface { id } = request {
+                        let damages = damages.clone();
+                        id.quick_assign(move |_, request, _| {
+                            if let wl_surface::Request::Damage { x, .. } = request {
+                                damages.lock().unwrap().push(x);
+                            }
+                        });
+                    }
+                });
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    client.display.set_request_staging(true);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    let compositor =
+        manager.instantiate_exact::<wayc::protocol::wl_compositor::WlCompositor>(1).unwrap();
+    let surface = compositor.create_surface().detach();
+
+    // the threads take turns sending the requests, while others keep writing them
+    let turn = Arc::new((Mutex::new(0), Condvar::new()));
+    let done = Arc::new(AtomicBool::new(false));
+    let flushers = (0..2)
+        .map(|_| {
+            let display = client.display.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    let _ = display.flush();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    let threads = (0..4)
+        .map(|i| {
+            let surface = surface.clone();
+            let turn = turn.clone();
+            thread::spawn(move || {
+                let (ref counter, ref cvar) = *turn;
+                let mut counter = counter.lock().unwrap();
+                loop {
+                    while *counter < 2000 && *counter % 4 != i {
+                        counter = cvar.wait(counter).unwrap();
+                    }
+                    if *counter == 2000 {
+                        return;
+                    }
+                    surface.damage(*counter, 0, 1, 1);
+                    *counter += 1;
+                    cvar.notify_all();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    done.store(true, Ordering::SeqCst);
+    for flusher in flushers {
+        flusher.join().unwrap();
+    }
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the requests are received in the order they were sent
+    assert_eq!(*damages.lock().unwrap(), (0..2000).collect::<Vec<_>>());
+}
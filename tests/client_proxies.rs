@@ -1,3 +1,7 @@
+#[cfg(feature = "client_native")]
+#[macro_use]
+extern crate wayland_sys;
+
 mod helpers;
 
 use helpers::{roundtrip, roundtrip_with_ddata, wayc, ways, TestClient, TestServer};
@@ -179,6 +183,53 @@ fn dead_proxies() {
     assert!(!output2.as_ref().is_alive());
 }
 
+#[test]
+fn proxy_object_ids() {
+    use std::collections::HashSet;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerOutput, _>(
+        3,
+        ways::Filter::new(|(output, _): (ways::Main<ServerOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(3).unwrap();
+    let output_id = output.as_ref().object_id();
+    assert_eq!(output_id.interface(), "wl_output");
+    assert_eq!(output_id.protocol_id(), output.as_ref().id());
+
+    // the proxies of the same object hash the same
+    let mut outputs = HashSet::new();
+    outputs.insert((**output).clone());
+    assert!(outputs.contains(&**output));
+    let ids: HashSet<_> = vec![output_id].into_iter().collect();
+
+    let weak = output.as_ref().downgrade();
+    assert_eq!(weak.object_id(), output_id);
+    assert!(weak.upgrade().unwrap() == **output);
+
+    output.release();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the id stays valid once the object is destroyed
+    assert!(weak.upgrade().is_none());
+    assert_eq!(output.as_ref().object_id(), output_id);
+    assert!(ids.contains(&output.as_ref().object_id()));
+
+    // a new object gets a different id, even if it reuses the protocol id
+    let output2 = manager.instantiate_exact::<wl_output::WlOutput>(3).unwrap();
+    assert_ne!(output2.as_ref().object_id(), output_id);
+    assert!(!ids.contains(&output2.as_ref().object_id()));
+    assert!(weak.upgrade().is_none());
+}
+
 #[test]
 fn dead_connection() {
     fn get_output() -> wl_output::WlOutput {
@@ -253,3 +304,253 @@ fn dead_object_argument() {
 
     assert!(client_entered);
 }
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn display_objects() {
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerOutput, _>(
+        3,
+        ways::Filter::new(|(output, _): (ways::Main<ServerOutput>, _), _, _| {
+            output.quick_assign(|_, _, _| {});
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(3).unwrap();
+    let output_id = output.as_ref().id();
+
+    let objects = client.display.enumerate_objects();
+    assert_eq!(objects[0].interface, "wl_display");
+    assert_eq!(objects[1].interface, "wl_registry");
+    let info = objects.iter().find(|o| o.id == output_id).unwrap();
+    assert_eq!((info.interface, info.version, info.alive), ("wl_output", 3, true));
+
+    // a destroyed object stays listed until the server releases its id
+    output.release();
+    let info = client.display.enumerate_objects().into_iter().find(|o| o.id == output_id).unwrap();
+    assert!(!info.alive);
+
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(client
+        .display
+        .enumerate_objects()
+        .iter()
+        .all(|o| o.id != output_id || o.interface != "wl_output"));
+}
+
+#[cfg(not(feature = "client_native"))]
+fn zombie_output_event(policy: Option<wayc::ZombiePolicy>) -> (TestClient, u32) {
+    use std::sync::{Arc, Mutex};
+
+    let mut server = TestServer::new();
+    let server_outputs = Arc::new(Mutex::new(Vec::new()));
+    server.display.create_global::<ServerOutput, _>(
+        3,
+        ways::Filter::new({
+            let server_outputs = server_outputs.clone();
+            move |(output, _): (ways::Main<ServerOutput>, _), _, _| {
+                output.quick_assign(|_, _, _| {});
+                server_outputs.lock().unwrap().push((*output).clone());
+            }
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    if let Some(policy) = policy {
+        client.display.set_zombie_policy(policy);
+    }
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(3).unwrap();
+    output.quick_assign(|_, _, _| panic!("Unexpected event."));
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the server sends an event while the client destroys the output
+    let output_id = output.as_ref().id();
+    output.release();
+    let server_outputs = server_outputs.lock().unwrap().clone();
+    server_outputs[0].done();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    (client, output_id)
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn zombie_events_discarded() {
+    let (client, _) = zombie_output_event(None);
+    assert_eq!(client.display.zombie_events(), 1);
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn zombie_events_delivered() {
+    use std::sync::{Arc, Mutex};
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let (client, output_id) = zombie_output_event(Some(wayc::ZombiePolicy::Deliver(Box::new({
+        let received = received.clone();
+        move |id, event| received.lock().unwrap().push((id, event.interface, event.name))
+    }))));
+    assert_eq!(client.display.zombie_events(), 1);
+    assert_eq!(*received.lock().unwrap(), vec![(output_id, "wl_output", "done")]);
+}
+
+#[test]
+fn proxy_reassign() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerOutput, _>(
+        2,
+        ways::Filter::new(|(output, _): (ways::Main<ServerOutput>, u32), _, _| {
+            output.scale(1);
+            output.scale(2);
+            output.scale(3);
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(2).unwrap();
+    let first_received = received.clone();
+    output.quick_assign(move |output, event, _| {
+        if let wl_output::Event::Scale { factor } = event {
+            first_received.borrow_mut().push(("first", factor));
+            // switch to an other filter from within the callback
+            let second_received = first_received.clone();
+            output.reassign(wayc::Filter::new(
+                move |(output, event): (wayc::Main<wl_output::WlOutput>, _), _, _| {
+                    if let wl_output::Event::Scale { factor } = event {
+                        second_received.borrow_mut().push(("second", factor));
+                        // the next events go to the fallback
+                        output.unassign();
+                    }
+                },
+            ));
+        }
+    });
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert_eq!(&*received.borrow(), &[("first", 1), ("second", 2)]);
+}
+
+#[cfg(feature = "client_native")]
+#[test]
+fn foreign_proxies_and_queue() {
+    use std::os::raw::c_void;
+
+    use wayc::protocol::{wl_callback::WlCallback, wl_registry::WlRegistry};
+    use wayc::sys::client::*;
+    use wayc::{ForeignProxyError, Interface, Main};
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerOutput, _>(1, ways::Filter::new(|_: (_, _), _, _| {}));
+
+    let client = TestClient::new(&server.socket_name);
+    let display_ptr = client.display.get_display_ptr();
+
+    // a queue created by another library
+    let queue_ptr =
+        unsafe { ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_display_create_queue, display_ptr) };
+    let event_queue = unsafe { client.display.create_event_queue_from_external(queue_ptr) };
+    let display_proxy = (**client.display).clone().attach(event_queue.token());
+    let mut foreign_client =
+        TestClient { display: client.display.clone(), display_proxy, event_queue };
+    let manager = wayc::GlobalManager::new(&foreign_client.display_proxy);
+    roundtrip(&mut foreign_client, &mut server).unwrap();
+    assert_eq!(manager.list().len(), 1);
+    ::std::mem::drop((manager, foreign_client));
+    // the queue is still ours to destroy
+    unsafe { ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_event_queue_destroy, queue_ptr) };
+
+    let sync = || unsafe {
+        ffi_dispatch!(
+            WAYLAND_CLIENT_HANDLE,
+            wl_proxy_marshal_constructor,
+            display_ptr as *mut wl_proxy,
+            0,
+            WlCallback::c_interface(),
+            ::std::ptr::null_mut::<c_void>()
+        )
+    };
+
+    // a proxy nobody manages yet
+    let callback_ptr = sync();
+    unsafe {
+        assert_eq!(
+            Main::<WlRegistry>::try_from_c_ptr(callback_ptr).err(),
+            Some(ForeignProxyError::WrongInterface)
+        );
+        let callback = Main::<WlCallback>::try_from_c_ptr(callback_ptr).unwrap();
+        assert!(!callback.as_ref().is_external());
+        // taking it again gives the same object
+        let again = Main::<WlCallback>::try_from_c_ptr(callback_ptr).unwrap();
+        assert!(again.as_ref().equals(callback.as_ref()));
+    }
+
+    // a proxy managed by another library
+    extern "C" fn done(_: *mut c_void, _: *mut wl_proxy, _: u32) {}
+    static LISTENER: [extern "C" fn(*mut c_void, *mut wl_proxy, u32); 1] = [done];
+    let callback_ptr = sync();
+    unsafe {
+        ffi_dispatch!(
+            WAYLAND_CLIENT_HANDLE,
+            wl_proxy_add_listener,
+            callback_ptr,
+            &LISTENER as *const _ as *mut _,
+            ::std::ptr::null_mut()
+        );
+        assert_eq!(
+            Main::<WlCallback>::try_from_c_ptr(callback_ptr).err(),
+            Some(ForeignProxyError::ExternallyManaged)
+        );
+        assert!(Proxy::<WlCallback>::from_c_ptr(callback_ptr).is_external());
+    }
+}
+
+#[cfg(feature = "client_native")]
+#[test]
+fn proxy_tags() {
+    use std::os::raw::c_char;
+
+    use wayc::sys::client::*;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerOutput, _>(1, ways::Filter::new(|_: (_, _), _, _| {}));
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    if !has_proxy_tags() {
+        return;
+    }
+
+    let output = manager.instantiate_exact::<wl_output::WlOutput>(1).unwrap();
+    let ptr = output.as_ref().c_ptr();
+    let tag: &'static *const c_char =
+        Box::leak(Box::new(b"wayland-rs\0".as_ptr() as *const c_char));
+    unsafe {
+        assert!(ffi_dispatch!(WAYLAND_CLIENT_TAGS_HANDLE, wl_proxy_get_tag, ptr).is_null());
+        ffi_dispatch!(WAYLAND_CLIENT_TAGS_HANDLE, wl_proxy_set_tag, ptr, tag);
+        assert_eq!(
+            ffi_dispatch!(WAYLAND_CLIENT_TAGS_HANDLE, wl_proxy_get_tag, ptr),
+            tag as *const _
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use wayc::protocol::wl_shm::{Format, WlShm};
+use wayc::shm::ShmPool;
+
+use ways::protocol::wl_buffer::WlBuffer as ServerBuffer;
+
+#[derive(Default)]
+struct ShmState {
+    fd: Option<RawFd>,
+    size: i32,
+    buffers: Vec<(ways::Main<ServerBuffer>, i32)>,
+    destroyed: usize,
+}
+
+fn insert_shm(server: &mut TestServer) -> Arc<Mutex<ShmState>> {
+    use ways::protocol::{wl_shm, wl_shm_pool};
+
+    let state = Arc::new(Mutex::new(ShmState::default()));
+    let state2 = state.clone();
+
+    ways::request_enum!(Reqs |
+        Shm => wl_shm::WlShm,
+        Pool => wl_shm_pool::WlShmPool
+    );
+
+    let filter = ways::Filter::new(move |req, filter, _| match req {
+        Reqs::Shm { request: wl_shm::Request::CreatePool { id, fd, size }, .. } => {
+            let mut state = state.lock().unwrap();
+            assert!(state.fd.is_none());
+            state.fd = Some(fd);
+            state.size = size;
+            id.assign(filter.clone());
+        }
+        Reqs::Pool { request: wl_shm_pool::Request::CreateBuffer { id, offset, .. }, .. } => {
+            let buffer_state = state.clone();
+            // wl_buffer.destroy is the only request of wl_buffer
+            id.quick_assign(move |_, _, _| buffer_state.lock().unwrap().destroyed += 1);
+            state.lock().unwrap().buffers.push((id, offset));
+        }
+        Reqs::Pool { request: wl_shm_pool::Request::Resize { size }, .. } => {
+            let mut state = state.lock().unwrap();
+            assert!(size > state.size);
+            state.size = size;
+        }
+        Reqs::Pool { request: wl_shm_pool::Request::Destroy, .. } => {}
+        _ => panic!("Unexpected request"),
+    });
+
+    server.display.create_global::<wl_shm::WlShm, _>(
+        1,
+        ways::Filter::new(move |(shm, _): (ways::Main<wl_shm::WlShm>, u32), _, _| {
+            shm.assign(filter.clone());
+        }),
+    );
+
+    state2
+}
+
+fn read_pool(state: &ShmState, offset: i32, len: usize) -> Vec<u8> {
+    let fd = nix::unistd::dup(state.fd.unwrap()).unwrap();
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.seek(SeekFrom::Start(offset as u64)).unwrap();
+    let mut contents = vec![0; len];
+    file.read_exact(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn shm_pool_buffers() {
+    let mut server = TestServer::new();
+    let state = insert_shm(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let shm = manager.instantiate_exact::<WlShm>(1).unwrap();
+    let mut pool = ShmPool::new(&shm).unwrap();
+    let mut first = pool.create_buffer(4, 4, 16, Format::Argb8888).unwrap();
+    let mut second = pool.create_buffer(4, 4, 16, Format::Argb8888).unwrap();
+    first.with_canvas(|canvas| canvas.copy_from_slice(&[1; 64])).unwrap();
+    second.with_canvas(|canvas| canvas.copy_from_slice(&[2; 64])).unwrap();
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    {
+        let state = state.lock().unwrap();
+        assert_eq!(state.size as usize, pool.len());
+        assert_eq!(state.buffers.len(), 2);
+        let (_, first_offset) = state.buffers[0];
+        let (_, second_offset) = state.buffers[1];
+        assert_eq!(read_pool(&state, first_offset, 64), vec![1; 64]);
+        assert_eq!(read_pool(&state, second_offset, 64), vec![2; 64]);
+    }
+
+    // a busy buffer can not be accessed, and is only destroyed once released
+    second.mark_busy();
+    assert!(second.with_canvas(|_| ()).is_none());
+    drop(second);
+    drop(first);
+
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(state.lock().unwrap().destroyed, 1);
+
+    state.lock().unwrap().buffers[1].0.release();
+
+    // one roundtrip to receive the release, one for the server to receive the destroy
+    roundtrip(&mut client, &mut server).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(state.lock().unwrap().destroyed, 2);
+}
+
+#[test]
+fn shm_pool_grow() {
+    let mut server = TestServer::new();
+    let state = insert_shm(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let shm = manager.instantiate_exact::<WlShm>(1).unwrap();
+    let mut pool = ShmPool::new(&shm).unwrap();
+    let initial_len = pool.len();
+
+    let mut small = pool.create_buffer(4, 4, 16, Format::Argb8888).unwrap();
+    small.with_canvas(|canvas| canvas.copy_from_slice(&[3; 64])).unwrap();
+    let mut large = pool.create_buffer(64, 64, 256, Format::Argb8888).unwrap();
+    large.with_canvas(|canvas| canvas.iter_mut().for_each(|b| *b = 4)).unwrap();
+    assert!(pool.len() > initial_len);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.size as usize, pool.len());
+    // the contents of existing buffers are preserved
+    let (_, small_offset) = state.buffers[0];
+    let (_, large_offset) = state.buffers[1];
+    assert_eq!(read_pool(&state, small_offset, 64), vec![3; 64]);
+    assert_eq!(read_pool(&state, large_offset, 64 * 256), vec![4; 64 * 256]);
+}
@@ -0,0 +1,189 @@
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ways::protocol::wl_callback::WlCallback as ServerCallback;
+
+use wayc::protocol::wl_compositor::WlCompositor;
+use wayc::protocol::wl_subcompositor::WlSubcompositor;
+
+struct ServerState {
+    // the destructor requests received, in order
+    destroyed: Vec<&'static str>,
+    callbacks: Vec<ways::Main<ServerCallback>>,
+}
+
+fn insert_globals(server: &mut TestServer) -> Rc<RefCell<ServerState>> {
+    use ways::protocol::{wl_compositor, wl_region, wl_subcompositor, wl_subsurface, wl_surface};
+
+    let state = Rc::new(RefCell::new(ServerState { destroyed: Vec::new(), callbacks: Vec::new() }));
+    let state2 = state.clone();
+
+    ways::request_enum!(Reqs |
+        Compositor => wl_compositor::WlCompositor,
+        Subcompositor => wl_subcompositor::WlSubcompositor,
+        Surface => wl_surface::WlSurface,
+        Subsurface => wl_subsurface::WlSubsurface,
+        Region => wl_region::WlRegion
+    );
+
+    let filter = ways::Filter::new(move |req, filter, _| match req {
+        Reqs::Compositor { request: wl_compositor::Request::CreateSurface { id }, .. } => {
+            id.assign(filter.clone());
+        }
+        Reqs::Compositor { request: wl_compositor::Request::CreateRegion { id }, .. } => {
+            id.assign(filter.clone());
+        }
+        Reqs::Subcompositor {
+            request: wl_subcompositor::Request::GetSubsurface { id, .. },
+            ..
+        } => {
+            id.assign(filter.clone());
+        }
+        Reqs::Surface { request: wl_surface::Request::Frame { callback }, .. } => {
+            state.borrow_mut().callbacks.push(callback);
+        }
+        Reqs::Surface { request: wl_surface::Request::Destroy, .. } => {
+            state.borrow_mut().destroyed.push("wl_surface");
+        }
+        Reqs::Subsurface { request: wl_subsurface::Request::Destroy, .. } => {
+            state.borrow_mut().destroyed.push("wl_subsurface");
+        }
+        Reqs::Region { request: wl_region::Request::Destroy, .. } => {
+            state.borrow_mut().destroyed.push("wl_region");
+        }
+        _ => panic!("Unexpected request."),
+    });
+
+    let filter2 = filter.clone();
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        1,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                compositor.assign(filter.clone());
+            },
+        ),
+    );
+    server.display.create_global::<wl_subcompositor::WlSubcompositor, _>(
+        1,
+        ways::Filter::new(
+            move |(subcompositor, _): (ways::Main<wl_subcompositor::WlSubcompositor>, u32),
+                  _,
+                  _| {
+                subcompositor.assign(filter2.clone());
+            },
+        ),
+    );
+
+    state2
+}
+
+#[test]
+fn destruction_scope_order() {
+    let mut server = TestServer::new();
+    let state = insert_globals(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor = manager.instantiate_exact::<WlCompositor>(1).unwrap();
+    let subcompositor = manager.instantiate_exact::<WlSubcompositor>(1).unwrap();
+    let parent = compositor.create_surface();
+
+    let mut scope = wayc::DestructionScope::new();
+    let surface = compositor.create_surface();
+    scope.add(&surface.detach(), |surface| surface.destroy());
+    let subsurface = subcompositor.get_subsurface(&surface, &parent);
+    scope.add(&subsurface.detach(), |subsurface| subsurface.destroy());
+    let region = compositor.create_region();
+    scope.add(&region.detach(), |region| region.destroy());
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(scope.len(), 3);
+
+    // a single call destroys everything, in the reverse order of addition
+    scope.destroy(&client.display).unwrap();
+    assert!(!surface.as_ref().is_alive());
+    assert!(!subsurface.as_ref().is_alive());
+    assert!(!region.as_ref().is_alive());
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(state.borrow().destroyed, vec!["wl_region", "wl_subsurface", "wl_surface"]);
+    assert!(parent.as_ref().is_alive());
+}
+
+#[test]
+fn destruction_scope_parents() {
+    let mut server = TestServer::new();
+    let state = insert_globals(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor = manager.instantiate_exact::<WlCompositor>(1).unwrap();
+    let subcompositor = manager.instantiate_exact::<WlSubcompositor>(1).unwrap();
+
+    // the subsurface is added before the surfaces it was created from
+    let mut scope = wayc::DestructionScope::new();
+    let parent = compositor.create_surface();
+    scope.add(&parent.detach(), |surface| surface.destroy());
+    let surface = compositor.create_surface();
+    let subsurface = subcompositor.get_subsurface(&surface, &parent);
+    scope.add(&subsurface.detach(), |subsurface| subsurface.destroy());
+    scope.add(&surface.detach(), |surface| surface.destroy());
+    let region = compositor.create_region();
+    scope.add(&region.detach(), |region| region.destroy());
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the subsurface is still destroyed before both surfaces
+    assert_eq!(scope.destroy_unflushed(), 4);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(
+        state.borrow().destroyed,
+        vec!["wl_region", "wl_subsurface", "wl_surface", "wl_surface"]
+    );
+}
+
+#[test]
+fn destruction_scope_dead_members() {
+    let mut server = TestServer::new();
+    let state = insert_globals(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor = manager.instantiate_exact::<WlCompositor>(1).unwrap();
+
+    let mut scope = wayc::DestructionScope::new();
+    let surface = compositor.create_surface();
+    scope.add(&surface.detach(), |surface| surface.destroy());
+    let region = compositor.create_region();
+    scope.add(&region.detach(), |region| region.destroy());
+    let callback = surface.frame();
+    callback.quick_assign(|_, _, _| {});
+    scope.add_server_destroyed(&callback.detach());
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(scope.len(), 3);
+
+    // the server destroys the callback
+    state.borrow_mut().callbacks.pop().unwrap().done(0);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(!callback.as_ref().is_alive());
+    assert_eq!(scope.len(), 2);
+
+    // the region is destroyed outside of the scope
+    region.destroy();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(scope.len(), 1);
+    scope.prune();
+    assert_eq!(scope.len(), 1);
+
+    // only the surface is left to destroy
+    assert_eq!(scope.destroy_unflushed(), 1);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(state.borrow().destroyed, vec!["wl_region", "wl_surface"]);
+}
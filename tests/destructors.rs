@@ -107,3 +107,129 @@ fn client_destructor_cleanup() {
 
     assert!(*destructor_called.lock().unwrap());
 }
+
+// Destruction ordering tests, using a raw socket as server to precisely control
+// the ordering of the events the client receives.
+#[cfg(not(feature = "client_native"))]
+mod destruction_ordering {
+    use super::helpers::{wayc, TestClient};
+    use super::WlOutput;
+
+    use std::cell::Cell;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::rc::Rc;
+
+    use wayland_commons::socket::{BufferedSocket, Socket};
+    use wayland_commons::wire::{Argument, Message};
+
+    fn raw_connection() -> (BufferedSocket, TestClient) {
+        let (server_end, client_end) = UnixStream::pair().unwrap();
+        let server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server_end.into_raw_fd()) });
+        let client = unsafe { TestClient::from_fd(client_end.into_raw_fd()) };
+        (server, client)
+    }
+
+    fn send_raw(server: &mut BufferedSocket, client: &mut TestClient, msgs: &[Message]) {
+        for msg in msgs {
+            server.write_message(msg).unwrap();
+        }
+        server.flush().unwrap();
+        client.event_queue.prepare_read().unwrap().read_events().unwrap();
+        client.event_queue.dispatch_pending(&mut (), |_, _, _| {}).unwrap();
+    }
+
+    fn callback_done(id: u32) -> Message {
+        Message { sender_id: id, opcode: 0, args: wayland_commons::smallvec![Argument::Uint(0)] }
+    }
+
+    fn delete_id(id: u32) -> Message {
+        Message { sender_id: 1, opcode: 1, args: wayland_commons::smallvec![Argument::Uint(id)] }
+    }
+
+    #[test]
+    fn client_no_event_after_destructor_event() {
+        let (mut server, mut client) = raw_connection();
+
+        let called = Rc::new(Cell::new(0));
+        let called2 = called.clone();
+        let callback = client.display_proxy.sync();
+        callback.quick_assign(move |_, _, _| called2.set(called2.get() + 1));
+        let id = callback.as_ref().id();
+        client.display.flush().unwrap();
+
+        // the destructor event is (wrongly) sent twice, the second must not reach the callback
+        send_raw(&mut server, &mut client, &[callback_done(id), callback_done(id)]);
+
+        assert_eq!(called.get(), 1);
+        assert!(!callback.as_ref().is_alive());
+    }
+
+    #[test]
+    fn client_destructor_event_then_delete_id() {
+        let (mut server, mut client) = raw_connection();
+
+        let callback = client.display_proxy.sync();
+        callback.quick_assign(|_, _, _| {});
+        let id = callback.as_ref().id();
+        client.display.flush().unwrap();
+
+        send_raw(&mut server, &mut client, &[callback_done(id)]);
+        assert!(!callback.as_ref().is_alive());
+
+        // the id is not released until the server acknowledges the destruction
+        let other = client.display_proxy.sync();
+        assert_ne!(other.as_ref().id(), id);
+
+        send_raw(&mut server, &mut client, &[delete_id(id)]);
+        let reused = client.display_proxy.sync();
+        assert_eq!(reused.as_ref().id(), id);
+    }
+
+    #[test]
+    fn client_delete_id_then_destructor_event() {
+        let (mut server, mut client) = raw_connection();
+
+        let called = Rc::new(Cell::new(0));
+        let called2 = called.clone();
+        let callback = client.display_proxy.sync();
+        callback.quick_assign(move |_, _, _| called2.set(called2.get() + 1));
+        let id = callback.as_ref().id();
+        client.display.flush().unwrap();
+
+        // the display events are dispatched first, so the id is marked as deleted
+        // by the server before the destructor event reaches the callback
+        send_raw(&mut server, &mut client, &[callback_done(id), delete_id(id)]);
+        assert_eq!(called.get(), 1);
+
+        let reused = client.display_proxy.sync();
+        assert_eq!(reused.as_ref().id(), id);
+    }
+
+    #[test]
+    fn client_destroyed_object_never_dispatched() {
+        use wayc::protocol::wl_output::Event as OutputEvent;
+
+        let (mut server, mut client) = raw_connection();
+
+        let called = Rc::new(Cell::new(0));
+        let called2 = called.clone();
+        let registry = client.display_proxy.get_registry();
+        let output = registry.bind::<WlOutput>(3, 1);
+        output.quick_assign(move |output, event, _| {
+            if let OutputEvent::Done = event {
+                // destroy the output while the second event is still pending in the queue
+                output.release();
+                called2.set(called2.get() + 1);
+            }
+        });
+        let id = output.as_ref().id();
+        client.display.flush().unwrap();
+
+        let done = Message { sender_id: id, opcode: 2, args: wayland_commons::smallvec![] };
+        send_raw(&mut server, &mut client, &[done.clone(), done]);
+
+        assert_eq!(called.get(), 1);
+        assert!(!output.as_ref().is_alive());
+    }
+}
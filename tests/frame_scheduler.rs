@@ -0,0 +1,157 @@
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use ways::protocol::wl_callback::WlCallback as ServerCallback;
+
+// stores the frame callbacks requested on the surfaces
+fn insert_compositor(server: &mut TestServer) -> Rc<RefCell<Vec<ways::Main<ServerCallback>>>> {
+    use ways::protocol::{wl_compositor, wl_surface};
+
+    let callbacks = Rc::new(RefCell::new(Vec::new()));
+    let callbacks2 = callbacks.clone();
+
+    ways::request_enum!(Reqs |
+        Compositor => wl_compositor::WlCompositor,
+        Surface => wl_surface::WlSurface
+    );
+
+    let filter = ways::Filter::new(move |req, filter, _| match req {
+        Reqs::Compositor {
+            request: wl_compositor::Request::CreateSurface { id: surface }, ..
+        } => {
+            surface.assign(filter.clone());
+        }
+        Reqs::Surface { request: wl_surface::Request::Frame { callback }, .. } => {
+            callbacks.borrow_mut().push(callback);
+        }
+        Reqs::Surface { request: wl_surface::Request::Commit, .. } => {}
+        _ => panic!("Unexpected request."),
+    });
+
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        1,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                compositor.assign(filter.clone());
+            },
+        ),
+    );
+
+    callbacks2
+}
+
+fn counting_waker(count: &Arc<AtomicUsize>) -> Waker {
+    unsafe fn clone(data: *const ()) -> RawWaker {
+        let count = Arc::from_raw(data as *const AtomicUsize);
+        let cloned = count.clone();
+        std::mem::forget(count);
+        RawWaker::new(Arc::into_raw(cloned) as *const (), &VTABLE)
+    }
+    unsafe fn wake(data: *const ()) {
+        let count = Arc::from_raw(data as *const AtomicUsize);
+        count.fetch_add(1, Ordering::SeqCst);
+    }
+    unsafe fn wake_by_ref(data: *const ()) {
+        (*(data as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+    }
+    unsafe fn drop_waker(data: *const ()) {
+        drop(Arc::from_raw(data as *const AtomicUsize));
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop_waker);
+    let data = Arc::into_raw(count.clone()) as *const ();
+    unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) }
+}
+
+fn setup(
+) -> (TestServer, TestClient, Rc<RefCell<Vec<ways::Main<ServerCallback>>>>, wayc::FrameScheduler) {
+    use wayc::protocol::wl_compositor::WlCompositor;
+
+    let mut server = TestServer::new();
+    let callbacks = insert_compositor(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor = manager.instantiate_exact::<WlCompositor>(1).unwrap();
+    let surface = compositor.create_surface();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    (server, client, callbacks, wayc::FrameScheduler::new(&surface))
+}
+
+#[test]
+fn frame_scheduler_throttles() {
+    let (mut server, mut client, callbacks, scheduler) = setup();
+
+    // nothing to draw
+    assert!(!scheduler.begin_frame());
+
+    // the requests are coalesced until the next frame
+    scheduler.request_redraw();
+    scheduler.request_redraw();
+    assert!(scheduler.begin_frame());
+    scheduler.surface().commit();
+    scheduler.request_redraw();
+    assert!(!scheduler.begin_frame());
+    assert!(scheduler.is_throttled());
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(callbacks.borrow().len(), 1);
+
+    let wakes = Arc::new(AtomicUsize::new(0));
+    let waker = counting_waker(&wakes);
+    let mut cx = Context::from_waker(&waker);
+    let mut future = scheduler.await_frame_async();
+    assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+    callbacks.borrow_mut().remove(0).done(42);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(wakes.load(Ordering::SeqCst), 1);
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Some(42)));
+    assert!(!scheduler.is_throttled());
+    assert_eq!(scheduler.last_frame_time(), Some(42));
+
+    // the redraw requested while throttled can now happen
+    assert!(scheduler.begin_frame());
+    scheduler.surface().commit();
+    client.display.flush().unwrap();
+    server.answer();
+    callbacks.borrow_mut().remove(0).done(43);
+    server.display.flush_clients(&mut ());
+    assert_eq!(scheduler.await_frame(&mut client.event_queue, &mut ()).unwrap(), Some(43));
+
+    // nothing in flight
+    assert_eq!(scheduler.await_frame(&mut client.event_queue, &mut ()).unwrap(), None);
+    assert_eq!(Pin::new(&mut scheduler.await_frame_async()).poll(&mut cx), Poll::Ready(None));
+}
+
+#[test]
+fn frame_scheduler_tracks_leaks() {
+    let (mut server, mut client, callbacks, scheduler) = setup();
+
+    // callbacks requested without waiting for the previous ones
+    scheduler.track(scheduler.surface().frame());
+    scheduler.track(scheduler.surface().frame());
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(scheduler.outstanding_callbacks(), 2);
+    assert!(scheduler.oldest_callback_age().is_some());
+
+    callbacks.borrow_mut().remove(0).done(1);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(scheduler.outstanding_callbacks(), 1);
+    assert!(scheduler.is_throttled());
+
+    callbacks.borrow_mut().remove(0).done(2);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(scheduler.outstanding_callbacks(), 0);
+    assert_eq!(scheduler.oldest_callback_age(), None);
+}
@@ -6,6 +6,7 @@ use ways::protocol::wl_compositor::WlCompositor as ServerCompositor;
 use ways::protocol::wl_output::WlOutput as ServerOutput;
 use ways::protocol::wl_shell::WlShell as ServerShell;
 
+use std::cell::RefCell;
 use std::sync::{Arc, Mutex};
 
 #[test]
@@ -136,6 +137,41 @@ fn range_instantiate() {
     assert!(manager.instantiate_range::<WlOutput>(1, 3) == Err(GlobalError::Missing));
 }
 
+#[test]
+fn version_negotiation() {
+    use wayc::protocol::wl_seat::WlSeat;
+    use wayc::GlobalError;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_seat::WlSeat, _>(
+        4,
+        ways::Filter::new(|_: (_, _), _, _| {}),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let (id, _, advertised) = manager.list()[0].clone();
+    let registry = client.display_proxy.get_registry();
+    assert!(
+        registry.bind_range::<WlSeat>(id, advertised, 5..=7) == Err(GlobalError::VersionTooLow(4))
+    );
+    let seat = registry.bind_range::<WlSeat>(id, advertised, 1..=7).unwrap();
+    assert!(seat.as_ref().version() == 4);
+
+    assert!(seat.as_ref().supports_event("name"));
+    assert!(seat.as_ref().supports_request("get_touch"));
+    assert!(!seat.as_ref().supports_request("release"));
+    assert!(!seat.as_ref().supports_event("frobnicate"));
+
+    let old_seat = manager.instantiate_range::<WlSeat>(1, 1).unwrap();
+    assert!(!old_seat.as_ref().supports_event("name"));
+
+    roundtrip(&mut client, &mut server).unwrap();
+}
+
 #[test]
 #[should_panic]
 fn wrong_version_create_global() {
@@ -238,3 +274,156 @@ fn two_step_binding() {
 
     roundtrip(&mut client, &mut server).unwrap();
 }
+
+#[test]
+fn capability_probe() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use ways::protocol::wl_output::Mode;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerCompositor, _>(4, ways::Filter::new(|_: (_, _), _, _| {}));
+    server.display.create_global::<ServerOutput, _>(
+        3,
+        ways::Filter::new(|(output, _): (ways::Main<ServerOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            output.mode(Mode::Current, 1920, 1080, 60_000);
+        }),
+    );
+
+    let socket_name = server.socket_name.clone();
+    let done = Arc::new(AtomicBool::new(false));
+    let client_done = done.clone();
+    let client_thread = std::thread::spawn(move || {
+        let client = TestClient::new(&socket_name);
+        let reports = wayc::CapabilityProbe::new().run(&client.display).unwrap();
+        client_done.store(true, Ordering::Release);
+        reports
+            .into_iter()
+            .map(|r| (r.interface, r.version, r.bound, r.events.iter().map(|e| e.name).collect()))
+            .collect::<Vec<(String, u32, bool, Vec<&str>)>>()
+    });
+
+    while !done.load(Ordering::Acquire) {
+        server.answer();
+    }
+
+    let reports = client_thread.join().unwrap();
+    assert_eq!(
+        reports,
+        vec![
+            ("wl_compositor".into(), 4, true, vec![]),
+            ("wl_output".into(), 3, true, vec!["mode"]),
+        ]
+    );
+}
+
+#[test]
+fn registry_snapshot() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use wayc::protocol::{wl_compositor, wl_output, wl_seat, wl_shm};
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerCompositor, _>(4, ways::Filter::new(|_: (_, _), _, _| {}));
+    server.display.create_global::<ServerOutput, _>(3, ways::Filter::new(|_: (_, _), _, _| {}));
+    let bound = Arc::new(Mutex::new(Vec::new()));
+    let server_bound = bound.clone();
+    server.display.create_global::<ways::protocol::wl_seat::WlSeat, _>(
+        2,
+        ways::Filter::new(
+            move |(_, version): (ways::Main<ways::protocol::wl_seat::WlSeat>, u32), _, _| {
+                server_bound.lock().unwrap().push(version);
+            },
+        ),
+    );
+
+    let socket_name = server.socket_name.clone();
+    let done = Arc::new(AtomicBool::new(false));
+    let client_done = done.clone();
+    let client_thread = std::thread::spawn(move || {
+        let mut client = TestClient::new(&socket_name);
+        let snapshot = client.display.get_registry_snapshot(client.event_queue.token()).unwrap();
+        let globals = snapshot
+            .globals()
+            .iter()
+            .map(|global| (global.interface.clone(), global.version))
+            .collect::<Vec<_>>();
+
+        let compositor = snapshot.bind::<wl_compositor::WlCompositor>(1..=3).unwrap();
+        assert_eq!(compositor.as_ref().version(), 3);
+        let seat = snapshot.bind::<wl_seat::WlSeat>(1..=5).unwrap();
+        assert_eq!(seat.as_ref().version(), 2);
+        assert_eq!(
+            snapshot.bind::<wl_output::WlOutput>(4..=5).err(),
+            Some(wayc::GlobalError::VersionTooLow(3))
+        );
+        assert_eq!(snapshot.bind::<wl_shm::WlShm>(1..=1).err(), Some(wayc::GlobalError::Missing));
+
+        client.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+        client_done.store(true, Ordering::Release);
+        globals
+    });
+
+    while !done.load(Ordering::Acquire) {
+        server.answer();
+    }
+
+    let globals = client_thread.join().unwrap();
+    assert_eq!(
+        globals,
+        vec![("wl_compositor".into(), 4), ("wl_output".into(), 3), ("wl_seat".into(), 2)]
+    );
+    assert_eq!(*bound.lock().unwrap(), vec![2]);
+}
+
+#[test]
+fn global_with_data() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use wayc::protocol::wl_output;
+
+    let mut server = TestServer::new();
+    let bound = Rc::new(RefCell::new(Vec::new()));
+    let server_bound = bound.clone();
+    let filter = ways::Filter::new(
+        move |(output, version, name): (ways::Main<ServerOutput>, u32, Rc<&'static str>), _, _| {
+            server_bound.borrow_mut().push((*name, version, output));
+        },
+    );
+    // the version is clamped to the one of the protocol files
+    let left =
+        server.display.create_global_with_data::<ServerOutput, _, _>(10, filter.clone(), "left");
+    server.display.create_global_with_data::<ServerOutput, _, _>(10, filter, "right");
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    let registry = client.display_proxy.get_registry();
+    roundtrip(&mut client, &mut server).unwrap();
+    let globals = manager.list();
+    assert_eq!(globals, vec![(1, "wl_output".into(), 3), (2, "wl_output".into(), 3)]);
+
+    let scales = Rc::new(Cell::new(0));
+    for &(id, _, version) in &globals {
+        let client_scales = scales.clone();
+        registry.bind::<wl_output::WlOutput>(version, id).quick_assign(move |_, event, _| {
+            if let wl_output::Event::Scale { .. } = event {
+                client_scales.set(client_scales.get() + 1);
+            }
+        });
+    }
+    roundtrip(&mut client, &mut server).unwrap();
+    let names =
+        bound.borrow().iter().map(|&(name, version, _)| (name, version)).collect::<Vec<_>>();
+    assert_eq!(names, vec![("left", 3), ("right", 3)]);
+
+    // a disabled global is no longer advertised, but its resources keep working
+    left.disable();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(manager.list(), vec![(2, "wl_output".into(), 3)]);
+    bound.borrow()[0].2.scale(2);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(scales.get(), 1);
+
+    left.destroy();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(manager.list(), vec![(2, "wl_output".into(), 3)]);
+}
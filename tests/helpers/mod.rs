@@ -62,6 +62,12 @@ impl TestClient {
         TestClient { display: Arc::new(display), display_proxy: attached, event_queue }
     }
 
+    pub fn from_display(display: self::wayc::Display) -> TestClient {
+        let event_queue = display.create_event_queue();
+        let attached = (*display).clone().attach(event_queue.token());
+        TestClient { display: Arc::new(display), display_proxy: attached, event_queue }
+    }
+
     pub unsafe fn from_fd(fd: RawFd) -> TestClient {
         let display = self::wayc::Display::from_fd(fd).unwrap();
         let event_queue = display.create_event_queue();
@@ -89,7 +95,7 @@ pub fn roundtrip_with_ddata<CD: 'static, SD: 'static>(
             Ok(_) => {}
             Err(e) => {
                 if e.kind() != ::std::io::ErrorKind::BrokenPipe {
-                    return Err(e);
+                    return Err(e.into());
                 }
             }
         }
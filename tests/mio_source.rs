@@ -0,0 +1,81 @@
+mod helpers;
+
+use helpers::{wayc, ways, TestClient, TestServer};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use mio::{Events, Interest, Poll, Token};
+
+const SERVER: Token = Token(0);
+const CLIENT: Token = Token(1);
+
+// wait for the source of a token to be readable
+fn wait_readable(poll: &mut Poll, token: Token) {
+    let mut events = Events::with_capacity(4);
+    loop {
+        poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+        assert!(!events.is_empty(), "Timed out waiting for readiness");
+        if events.iter().any(|event| event.token() == token && event.is_readable()) {
+            return;
+        }
+    }
+}
+
+#[test]
+fn mio_registration() {
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(|_: (_, _), _, _| {}),
+    );
+    let mut client = TestClient::new(&server.socket_name);
+
+    let mut server_poll = Poll::new().unwrap();
+    server_poll.registry().register(&mut server.display, SERVER, Interest::READABLE).unwrap();
+    let mut client_poll = Poll::new().unwrap();
+    client_poll.registry().register(&mut client.event_queue, CLIENT, Interest::READABLE).unwrap();
+
+    let globals = Rc::new(RefCell::new(Vec::new()));
+    let globals2 = globals.clone();
+    let registry = client.display_proxy.get_registry();
+    registry.quick_assign(move |_, event, _| {
+        if let wayc::protocol::wl_registry::Event::Global { interface, .. } = event {
+            globals2.borrow_mut().push(interface);
+        }
+    });
+    client.display.flush().unwrap();
+
+    wait_readable(&mut server_poll, SERVER);
+    server.display.dispatch_ready(&mut ()).unwrap();
+    server.display.flush_clients(&mut ());
+
+    wait_readable(&mut client_poll, CLIENT);
+    assert_eq!(client.event_queue.dispatch_ready(&mut (), |_, _, _| {}).unwrap(), 1);
+    assert_eq!(*globals.borrow(), ["wl_output"]);
+
+    // spurious wakeups are not an error
+    assert_eq!(client.event_queue.dispatch_ready(&mut (), |_, _, _| {}).unwrap(), 0);
+    server.display.dispatch_ready(&mut ()).unwrap();
+
+    client_poll.registry().deregister(&mut client.event_queue).unwrap();
+    server_poll.registry().deregister(&mut server.display).unwrap();
+}
+
+#[test]
+fn mio_client_disconnect() {
+    let mut server = TestServer::new();
+    let mut client = TestClient::new(&server.socket_name);
+    let mut client_poll = Poll::new().unwrap();
+    // registering the display is equivalent to registering its queues
+    let mut display = (*client.display).clone();
+    client_poll.registry().register(&mut display, CLIENT, Interest::READABLE).unwrap();
+
+    // the client is accepted, then the server goes away
+    server.answer();
+    drop(server);
+
+    wait_readable(&mut client_poll, CLIENT);
+    assert!(client.event_queue.dispatch_ready(&mut (), |_, _, _| {}).is_err());
+}
@@ -7,10 +7,11 @@ extern crate wayland_commons as wc;
 
 use wc::smallvec;
 use wc::socket::{BufferedSocket, Socket};
-use wc::wire::{Argument, Message};
+use wc::wire::{Argument, ArgumentType, Message};
 
 use std::cell::RefCell;
 use std::env;
+use std::ffi::CString;
 use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
@@ -90,6 +91,177 @@ fn client_wrong_sender() {
     assert_eq!(socket.flush(), Err(nix::Error::Sys(nix::errno::Errno::EPIPE)));
 }
 
+// bind a global with an interface name that is not valid UTF-8, returning the error of the server
+#[cfg(not(feature = "server_native"))]
+fn bind_invalid_utf8(strictness: ways::Strictness) -> String {
+    let mut server = TestServer::new();
+    server.display.set_strictness(strictness);
+
+    let mut socket: PathBuf = env::var_os("XDG_RUNTIME_DIR").unwrap().into();
+    socket.push(&server.socket_name);
+    let socket = UnixStream::connect(socket).unwrap();
+
+    let mut socket = BufferedSocket::new(unsafe { Socket::from_raw_fd(socket.into_raw_fd()) });
+    socket
+        .write_message(&Message {
+            sender_id: 1, // wl_display
+            opcode: 1,    // wl_registry
+            args: smallvec![Argument::NewId(2)],
+        })
+        .unwrap();
+    socket
+        .write_message(&Message {
+            sender_id: 2, // wl_registry
+            opcode: 0,    // bind
+            args: smallvec![
+                Argument::Uint(1),
+                Argument::Str(Box::new(CString::new(&b"wl_\xFFoutput"[..]).unwrap())),
+                Argument::Uint(1),
+                Argument::NewId(3),
+            ],
+        })
+        .unwrap();
+    socket.flush().unwrap();
+
+    server.answer();
+
+    let mut error = None;
+    // the server closes the connection after the error, which ends the reading with EPIPE
+    let _ = socket.read_messages(
+        |id, opcode| match (id, opcode) {
+            // wl_display.error
+            (1, 0) => Some(&[ArgumentType::Object, ArgumentType::Uint, ArgumentType::Str][..]),
+            _ => None,
+        },
+        |msg| {
+            if let Argument::Str(ref s) = msg.args[2] {
+                error = Some(s.to_string_lossy().into_owned());
+            }
+            true
+        },
+    );
+    error.unwrap()
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn client_invalid_utf8() {
+    // the server rejects the string when it is strict
+    let error = bind_invalid_utf8(ways::Strictness::Strict);
+    assert_eq!(error, "invalid arguments: argument 1 is not valid UTF-8");
+    // and otherwise converts it lossily
+    let error = bind_invalid_utf8(ways::Strictness::Lenient);
+    assert!(!error.contains("UTF-8"));
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn server_invalid_utf8() {
+    let dispatch = |strictness| {
+        let (client_socket, server_socket) = UnixStream::pair().unwrap();
+        let mut client = unsafe { TestClient::from_fd(client_socket.into_raw_fd()) };
+        client.display.set_strictness(strictness);
+        let _registry = client.display_proxy.get_registry();
+        client.display.flush().unwrap();
+
+        let mut socket =
+            BufferedSocket::new(unsafe { Socket::from_raw_fd(server_socket.into_raw_fd()) });
+        socket
+            .write_message(&Message {
+                sender_id: 2, // wl_registry
+                opcode: 0,    // global
+                args: smallvec![
+                    Argument::Uint(1),
+                    Argument::Str(Box::new(CString::new(&b"wl_\xFFoutput"[..]).unwrap())),
+                    Argument::Uint(1),
+                ],
+            })
+            .unwrap();
+        socket.flush().unwrap();
+
+        client.event_queue.dispatch(&mut (), |_, _, _| {})
+    };
+    assert!(dispatch(wayc::Strictness::Strict).is_err());
+    assert!(dispatch(wayc::Strictness::Lenient).is_ok());
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn client_message_too_large() {
+    let mut server = TestServer::new();
+
+    let mut socket: PathBuf = env::var_os("XDG_RUNTIME_DIR").unwrap().into();
+    socket.push(&server.socket_name);
+    let socket = UnixStream::connect(socket).unwrap();
+
+    let mut socket = BufferedSocket::new(unsafe { Socket::from_raw_fd(socket.into_raw_fd()) });
+    socket.set_max_message_size(32_000);
+    socket
+        .write_message(&Message {
+            sender_id: 1, // wl_display
+            opcode: 1,    // wl_registry
+            args: smallvec![Argument::NewId(2), Argument::Array(Box::new(vec![0; 20_000]))],
+        })
+        .unwrap();
+    socket.flush().unwrap();
+
+    server.answer();
+
+    let mut error = None;
+    // the server closes the connection after the error, which ends the reading with EPIPE
+    let _ = socket.read_messages(
+        |id, opcode| match (id, opcode) {
+            // wl_display.error
+            (1, 0) => Some(&[ArgumentType::Object, ArgumentType::Uint, ArgumentType::Str][..]),
+            _ => None,
+        },
+        |msg| {
+            if let Argument::Str(ref s) = msg.args[2] {
+                error = Some(s.to_string_lossy().into_owned());
+            }
+            true
+        },
+    );
+    assert_eq!(error.unwrap(), "message too large (20016 bytes)");
+}
+
+#[cfg(not(any(feature = "client_native", feature = "server_native")))]
+#[test]
+fn large_messages() {
+    let long_name = "a".repeat(20_000);
+
+    let mut server = TestServer::new();
+    server.display.set_max_message_size(32_000);
+    let server_name = long_name.clone();
+    server.display.create_global::<ways::protocol::wl_seat::WlSeat, _>(
+        2,
+        ways::Filter::new(
+            move |(seat, _): (ways::Main<ways::protocol::wl_seat::WlSeat>, u32), _, _| {
+                seat.name(server_name.clone());
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    client.display.set_max_message_size(32_000);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let received = Rc::new(RefCell::new(None));
+    let my_received = received.clone();
+    let seat = manager.instantiate_exact::<wayc::protocol::wl_seat::WlSeat>(2).unwrap();
+    seat.quick_assign(move |_, event, _| {
+        if let wayc::protocol::wl_seat::Event::Name { name } = event {
+            *my_received.borrow_mut() = Some(name);
+        }
+    });
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert_eq!(received.borrow().as_ref(), Some(&long_name));
+}
+
 #[test]
 fn client_receive_error() {
     let mut server = TestServer::new();
@@ -126,3 +298,206 @@ fn client_receive_error() {
         assert_eq!(error.message, "I don't like you!");
     }
 }
+
+#[test]
+fn client_receive_typed_error() {
+    use ways::protocol::wl_shm;
+
+    let mut server = TestServer::new();
+    let server_shm = Rc::new(RefCell::new(None));
+    let my_server_shm = server_shm.clone();
+    server.display.create_global::<wl_shm::WlShm, _>(
+        1,
+        ways::Filter::new(move |(shm, _), _, _| *my_server_shm.borrow_mut() = Some(shm)),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    manager.instantiate_exact::<wayc::protocol::wl_shm::WlShm>(1).unwrap();
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let shm: ways::Main<wl_shm::WlShm> = server_shm.borrow_mut().take().unwrap();
+    shm.post_error(wl_shm::Error::InvalidFd, "Not a valid fd".into());
+
+    assert!(roundtrip(&mut client, &mut server).is_err());
+    let error = client.display.protocol_error().unwrap();
+    assert_eq!(error.code, wl_shm::Error::InvalidFd.to_raw());
+    assert_eq!(error.object_interface, "wl_shm");
+}
+
+#[test]
+fn client_receive_implementation_error() {
+    let mut server = TestServer::new();
+    let server_output = Rc::new(RefCell::new(None));
+    let my_server_output = server_output.clone();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(output, _), _, _| *my_server_output.borrow_mut() = Some(output)),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    manager.instantiate_exact::<wayc::protocol::wl_output::WlOutput>(3).unwrap();
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let client_handle = server_output.borrow().as_ref().unwrap().as_ref().client().unwrap();
+    client_handle.post_implementation_error("Out of buffers".into());
+
+    assert!(roundtrip(&mut client, &mut server).is_err());
+    let error = client.display.protocol_error().unwrap();
+    assert_eq!(error.code, 3);
+    assert_eq!(error.object_id, 1);
+    assert_eq!(error.object_interface, "wl_display");
+    #[cfg(not(feature = "client_native"))]
+    {
+        assert_eq!(error.message, "Out of buffers");
+    }
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn client_connection_state() {
+    use std::sync::{Arc, Mutex};
+
+    let mut server = TestServer::new();
+    let server_output = Rc::new(RefCell::new(None));
+    let my_server_output = server_output.clone();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(output, _), _, _| *my_server_output.borrow_mut() = Some(output)),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let states = Arc::new(Mutex::new(Vec::new()));
+    client.display.set_state_listener({
+        let states = states.clone();
+        move |state| states.lock().unwrap().push(format!("{:?}", state))
+    });
+    assert!(match client.display.state() {
+        wayc::ConnectionState::Connecting => true,
+        _ => false,
+    });
+
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(match client.display.state() {
+        wayc::ConnectionState::Ready => true,
+        _ => false,
+    });
+
+    manager.instantiate_exact::<wayc::protocol::wl_output::WlOutput>(3).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    server_output.borrow().as_ref().unwrap().as_ref().post_error(42, "I don't like you!".into());
+    let err = roundtrip(&mut client, &mut server).unwrap_err();
+
+    let state = client.display.state();
+    match state {
+        wayc::ConnectionState::ErrorDeferred(ref e) => assert_eq!(e.code, 42),
+        ref other => panic!("Unexpected state: {:?}", other),
+    }
+    // all methods report the same error
+    let protocol_error = |e: wayc::DispatchError| match e {
+        wayc::DispatchError::Protocol(e) => Some(e.code),
+        _ => None,
+    };
+    assert_eq!(protocol_error(err.into()), Some(42));
+    assert_eq!(protocol_error(client.display.last_error().unwrap()), Some(42));
+    assert_eq!(protocol_error(client.display.flush().unwrap_err()), Some(42));
+    assert_eq!(
+        protocol_error(client.event_queue.dispatch_pending(&mut (), |_, _, _| {}).unwrap_err()),
+        Some(42)
+    );
+    assert_eq!(
+        protocol_error(client.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap_err()),
+        Some(42)
+    );
+    // requests are discarded
+    client.display_proxy.sync();
+
+    assert_eq!(states.lock().unwrap().len(), 2);
+    assert_eq!(states.lock().unwrap()[0], "Ready");
+    assert!(states.lock().unwrap()[1].starts_with("ErrorDeferred"));
+}
+
+#[test]
+fn client_connection_lost() {
+    let mut server = TestServer::new();
+    let mut client = TestClient::new(&server.socket_name);
+
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(!client.display.state().is_failed());
+
+    ::std::mem::drop(server);
+
+    let err = client.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap_err();
+    assert!(match err {
+        wayc::DispatchError::Backend(_) => true,
+        _ => false,
+    });
+    match client.display.state() {
+        wayc::ConnectionState::Dead(kind) => assert_eq!(kind, err.kind()),
+        other => panic!("Unexpected state: {:?}", other),
+    }
+    assert_eq!(client.display.flush().unwrap_err().kind(), err.kind());
+}
+
+#[cfg(not(feature = "client_native"))]
+#[test]
+fn client_reconnect() {
+    use wayc::protocol::wl_output::WlOutput as ClientOutput;
+    use ways::protocol::wl_output::WlOutput as ServerOutput;
+
+    let mut server = TestServer::new();
+    server.display.create_global::<ServerOutput, _>(3, ways::Filter::new(|_: (_, _), _, _| {}));
+    let socket_name = server.socket_name.clone();
+
+    let mut client = TestClient::new(&socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    let output = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // bind the globals of the new compositor on reconnection
+    let managers = Rc::new(RefCell::new(Vec::new()));
+    client.event_queue.add_reconnect_handler({
+        let managers = managers.clone();
+        move |display, _| managers.borrow_mut().push(wayc::GlobalManager::new(&display))
+    });
+
+    // the compositor restarts
+    ::std::mem::drop(server);
+    assert!(client.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).is_err());
+    assert!(client.display.state().is_failed());
+
+    let mut display = ways::Display::new();
+    display.add_socket(Some(&socket_name)).unwrap();
+    display.create_global::<ServerOutput, _>(2, ways::Filter::new(|_: (_, _), _, _| {}));
+    let mut server = TestServer { display, socket_name };
+
+    client.display.reconnect().unwrap();
+    assert!(match client.display.state() {
+        wayc::ConnectionState::Connecting => true,
+        _ => false,
+    });
+    // the objects of the previous connection are dead
+    assert!(!output.as_ref().is_alive());
+
+    // the handler runs on the first dispatch, its requests are answered by the next roundtrip
+    roundtrip(&mut client, &mut server).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(!client.display.state().is_failed());
+    let managers = managers.borrow();
+    assert_eq!(managers.len(), 1);
+    assert_eq!(managers[0].list(), vec![(1, "wl_output".into(), 2)]);
+    managers[0].instantiate_exact::<ClientOutput>(2).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+}
@@ -127,3 +127,305 @@ fn server_code_generation() {
     );
     run_codegen_test(tempfile.path(), SERVER_CODE_TARGET);
 }
+
+#[test]
+fn code_generation_is_deterministic() {
+    for &side in &[Side::Client, Side::Server] {
+        let generate = || {
+            let mut code = Vec::new();
+            wayland_scanner::generate_code_streams(
+                Cursor::new(PROTOCOL.as_bytes()),
+                &mut code,
+                side,
+            );
+            code
+        };
+        assert!(generate() == generate());
+    }
+}
+
+#[test]
+fn serde_code_generation() {
+    for &side in &[Side::Client, Side::Server] {
+        let generate = |options: &wayland_scanner::Options| {
+            let mut code = Vec::new();
+            wayland_scanner::generate_code_streams_with_options(
+                Cursor::new(PROTOCOL.as_bytes()),
+                &mut code,
+                side,
+                options,
+            );
+            String::from_utf8(code).unwrap()
+        };
+        let plain = generate(&wayland_scanner::Options::new());
+        let with_serde = generate(&wayland_scanner::Options::new().serde(true));
+        assert!(!plain.contains("serde"));
+        assert!(with_serde.contains("Serialize"));
+        assert!(with_serde.contains("serde_helpers"));
+    }
+}
+
+#[test]
+fn async_helpers_code_generation() {
+    let generate = |side, options: &wayland_scanner::Options| {
+        let mut code = Vec::new();
+        wayland_scanner::generate_code_streams_with_options(
+            Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+            &mut code,
+            side,
+            options,
+        );
+        String::from_utf8(code).unwrap()
+    };
+    let options = wayland_scanner::Options::new()
+        .destructor_events(&[("wl_callback", "done")])
+        .async_helpers(true);
+    let client = generate(Side::Client, &options);
+    assert!(client.contains("sync_async"));
+    assert!(client.contains("frame_async"));
+    // only requests answered by a single destructor event get a helper
+    assert!(!client.contains("get_registry_async"));
+    assert!(!generate(Side::Server, &options).contains("_async"));
+    assert!(!generate(Side::Client, &wayland_scanner::Options::new()).contains("_async"));
+}
+
+#[test]
+fn post_error_code_generation() {
+    let mut code = Vec::new();
+    wayland_scanner::generate_code_streams(
+        Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+        &mut code,
+        Side::Server,
+    );
+    let code = String::from_utf8(code).unwrap();
+    // wl_shm has an error enum, wl_output does not
+    let shm =
+        &code[code.find("pub mod wl_shm {").unwrap()..code.find("pub mod wl_buffer {").unwrap()];
+    assert!(shm.contains("pub fn post_error"));
+    let output =
+        &code[code.find("pub mod wl_output {").unwrap()..code.find("pub mod wl_region {").unwrap()];
+    assert!(!output.contains("pub fn post_error"));
+}
+
+#[test]
+fn checked_events_code_generation() {
+    let generate = |checked_events| {
+        let mut code = Vec::new();
+        wayland_scanner::generate_code_streams_with_options(
+            Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+            &mut code,
+            Side::Server,
+            &wayland_scanner::Options::new().checked_events(checked_events),
+        );
+        String::from_utf8(code).unwrap()
+    };
+    let code = generate(true);
+    let output =
+        &code[code.find("pub mod wl_output {").unwrap()..code.find("pub mod wl_region {").unwrap()];
+    // wl_output.scale and wl_output.done are available since version 2, wl_output.mode is not
+    assert!(output.contains("pub fn scale_checked"));
+    assert!(output.contains("pub fn done_checked"));
+    assert!(!output.contains("pub fn mode_checked"));
+    assert!(!generate(false).contains("_checked"));
+}
+
+#[test]
+fn argument_metadata_code_generation() {
+    let generate = |argument_metadata| {
+        let mut code = Vec::new();
+        wayland_scanner::generate_code_streams_with_options(
+            Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+            &mut code,
+            Side::Client,
+            &wayland_scanner::Options::new().argument_metadata(argument_metadata),
+        );
+        String::from_utf8(code).unwrap()
+    };
+    let code = generate(true);
+    let surface: String = code
+        [code.find("pub mod wl_surface {").unwrap()..code.find("pub mod wl_seat {").unwrap()]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    assert!(surface.contains("constARGUMENTS"));
+    // wl_surface.attach accepts a null buffer, wl_surface.set_buffer_transform takes an enum
+    assert!(surface.contains("ArgumentDesc{allow_null:true,enum_check:None}"));
+    assert!(surface.contains("super::wl_output::Transform::from_raw(v)"));
+    assert!(!generate(false).contains("ArgumentDesc"));
+}
+
+#[test]
+fn no_std_code_generation() {
+    let mut code = Vec::new();
+    wayland_scanner::generate_code_streams_with_options(
+        Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+        &mut code,
+        Side::Client,
+        &wayland_scanner::Options::new().no_std(true).argument_metadata(true),
+    );
+    let code = String::from_utf8(code).unwrap();
+    // nothing depends on std or on the objects of the client or the server
+    assert!(!code.contains("std::"));
+    assert!(!code.contains("Proxy"));
+    assert!(!code.contains("MessageGroup"));
+    let surface: String = code
+        [code.find("pub mod wl_surface {").unwrap()..code.find("pub mod wl_seat {").unwrap()]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    assert!(surface.contains("pubconstNAME:&str=\"wl_surface\";"));
+    assert!(surface.contains("pubconstREQUESTS:&[super::MessageDesc]"));
+    assert!(surface.contains("pubconstEVENT_ARGUMENTS:&[&[super::ArgumentDesc]]"));
+    assert!(surface.contains("name:\"attach\""));
+    assert!(surface.contains("pubenumError"));
+    assert!(surface.contains("pubconstREQ_DAMAGE_BUFFER_SINCE:u32=4"));
+}
+
+#[test]
+fn borrowed_events_code_generation() {
+    let generate = |borrowed_events| {
+        let mut code = Vec::new();
+        wayland_scanner::generate_code_streams_with_options(
+            Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+            &mut code,
+            Side::Client,
+            &wayland_scanner::Options::new().borrowed_events(borrowed_events),
+        );
+        String::from_utf8(code).unwrap()
+    };
+    let code = generate(true);
+    let registry = &code
+        [code.find("pub mod wl_registry {").unwrap()..code.find("pub mod wl_callback {").unwrap()];
+    assert!(registry.contains("pub enum EventRef"));
+    // wl_registry.global carries a string, wl_callback.done does not
+    let callback = &code[code.find("pub mod wl_callback {").unwrap()
+        ..code.find("pub mod wl_compositor {").unwrap()];
+    assert!(!callback.contains("EventRef"));
+    assert!(!generate(false).contains("EventRef"));
+}
+
+#[test]
+fn borrowed_parsing_code_generation() {
+    let generate = |borrowed_parsing| {
+        let mut code = Vec::new();
+        wayland_scanner::generate_code_streams_with_options(
+            Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+            &mut code,
+            Side::Client,
+            &wayland_scanner::Options::new().borrowed_parsing(borrowed_parsing),
+        );
+        String::from_utf8(code).unwrap().chars().filter(|c| !c.is_whitespace()).collect::<String>()
+    };
+    let code = generate(true);
+    assert!(code.contains("fnfrom_raw_ref(msg:MessageRef"));
+    assert!(code.contains("usesuper::{ArgumentRef,MessageRef};"));
+    // by default, the generated code only uses the names it always needed
+    let code = generate(false);
+    assert!(!code.contains("MessageRef"));
+    assert!(!code.contains("ArgumentRef"));
+}
+
+#[test]
+fn borrowed_events_decoding() {
+    use wayland_client::protocol::{wl_keyboard, wl_registry};
+    use wayland_commons::wire::{Argument, Message, MessageRef};
+    use wayland_commons::{smallvec, MessageGroup};
+
+    let encode = |msg: Message, signature| {
+        let mut words = [0u32; 16];
+        let (len, _) = msg.write_to_buffers(&mut words, &mut []).unwrap();
+        (words, len, signature)
+    };
+
+    let (words, len, signature) = encode(
+        Message {
+            sender_id: 2,
+            opcode: 0,
+            args: smallvec![
+                Argument::Uint(7),
+                Argument::Str(Box::new(std::ffi::CString::new("wl_seat").unwrap())),
+                Argument::Uint(5),
+            ],
+        },
+        wl_registry::Event::MESSAGES[0].signature,
+    );
+    let (msg, _, _) = MessageRef::from_raw(&words[..len], signature, &[]).unwrap();
+    assert_eq!(
+        wl_registry::EventRef::from_raw_ref(msg),
+        Ok(wl_registry::EventRef::Global { name: 7, interface: "wl_seat", version: 5 })
+    );
+
+    let (words, len, signature) = encode(
+        Message {
+            sender_id: 3,
+            opcode: 1,
+            args: smallvec![
+                Argument::Uint(12),
+                Argument::Object(4),
+                Argument::Array(Box::new(vec![1, 0, 0, 0, 2, 0, 0, 0])),
+            ],
+        },
+        wl_keyboard::Event::MESSAGES[1].signature,
+    );
+    let (msg, _, _) = MessageRef::from_raw(&words[..len], signature, &[]).unwrap();
+    assert_eq!(
+        wl_keyboard::EventRef::from_raw_ref(msg),
+        Ok(wl_keyboard::EventRef::Enter {
+            serial: 12,
+            surface: 4,
+            keys: &[1, 0, 0, 0, 2, 0, 0, 0]
+        })
+    );
+}
+
+#[test]
+fn interface_description_code_generation() {
+    let generate = |interface_description| {
+        let mut code = Vec::new();
+        wayland_scanner::generate_code_streams_with_options(
+            Cursor::new(&include_bytes!("../wayland-client/wayland.xml")[..]),
+            &mut code,
+            Side::Client,
+            &wayland_scanner::Options::new().interface_description(interface_description),
+        );
+        String::from_utf8(code).unwrap()
+    };
+    assert!(generate(true).contains("const DESCRIPTION"));
+    assert!(!generate(false).contains("const DESCRIPTION"));
+}
+
+#[test]
+fn interface_description() {
+    use wayland_client::protocol::{wl_compositor, wl_output, wl_registry, wl_surface};
+    use wayland_commons::wire::ArgumentType;
+    use wayland_commons::Interface;
+
+    let compositor = wl_compositor::WlCompositor::DESCRIPTION;
+    assert_eq!(compositor.name, "wl_compositor");
+    assert_eq!(compositor.request_child(0), Some("wl_surface"));
+    assert_eq!(compositor.request_child(1), Some("wl_region"));
+    assert_eq!(compositor.destructor_requests().count(), 0);
+
+    let surface = wl_surface::WlSurface::DESCRIPTION;
+    assert_eq!(surface.requests[3].name, "frame");
+    assert_eq!(surface.request_child(3), Some("wl_callback"));
+    assert_eq!(surface.request_child(1), None);
+    assert_eq!(surface.destructor_requests().collect::<Vec<_>>(), [0]);
+    let attach = surface.request_args[1];
+    assert_eq!(attach[0].name, "buffer");
+    assert_eq!(attach[0].typ, ArgumentType::Object);
+    assert_eq!(attach[0].interface, Some("wl_buffer"));
+    assert!(attach[0].allow_null);
+    assert_eq!(surface.request_args[7][0].enum_name, Some("wl_output.transform"));
+
+    // the interface of the object created by `wl_registry.bind` is only known at runtime
+    assert_eq!(wl_registry::WlRegistry::DESCRIPTION.request_child(0), None);
+
+    let transform = wl_output::WlOutput::DESCRIPTION.find_enum("transform").unwrap();
+    assert!(!transform.bitfield);
+    assert_eq!(transform.entry_name(1), Some("90"));
+    let mode = wl_output::WlOutput::DESCRIPTION.find_enum("mode").unwrap();
+    assert!(mode.bitfield);
+    assert_eq!(mode.entry_name(2), Some("preferred"));
+    assert!(wl_output::WlOutput::DESCRIPTION.find_enum("foo").is_none());
+}
@@ -257,7 +257,7 @@ pub mod wl_foo {
             panic!("Event::as_raw_c_in can not be used Client-side.")
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlFoo(Proxy<WlFoo>);
     impl AsRef<Proxy<WlFoo>> for WlFoo {
         #[inline]
@@ -694,7 +694,7 @@ pub mod wl_bar {
             panic!("Event::as_raw_c_in can not be used Client-side.")
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlBar(Proxy<WlBar>);
     impl AsRef<Proxy<WlBar>> for WlBar {
         #[inline]
@@ -919,7 +919,7 @@ pub mod wl_display {
             panic!("Event::as_raw_c_in can not be used Client-side.")
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlDisplay(Proxy<WlDisplay>);
     impl AsRef<Proxy<WlDisplay>> for WlDisplay {
         #[inline]
@@ -1102,7 +1102,7 @@ pub mod wl_registry {
             panic!("Event::as_raw_c_in can not be used Client-side.")
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlRegistry(Proxy<WlRegistry>);
     impl AsRef<Proxy<WlRegistry>> for WlRegistry {
         #[inline]
@@ -1296,7 +1296,7 @@ pub mod wl_callback {
             panic!("Event::as_raw_c_in can not be used Client-side.")
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlCallback(Proxy<WlCallback>);
     impl AsRef<Proxy<WlCallback>> for WlCallback {
         #[inline]
@@ -285,7 +285,7 @@ pub mod wl_foo {
             }
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlFoo(Resource<WlFoo>);
     impl AsRef<Resource<WlFoo>> for WlFoo {
         #[inline]
@@ -737,7 +737,7 @@ pub mod wl_bar {
             }
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlBar(Resource<WlBar>);
     impl AsRef<Resource<WlBar>> for WlBar {
         #[inline]
@@ -972,7 +972,7 @@ pub mod wl_callback {
             }
         }
     }
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct WlCallback(Resource<WlCallback>);
     impl AsRef<Resource<WlCallback>> for WlCallback {
         #[inline]
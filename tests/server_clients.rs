@@ -71,3 +71,255 @@ fn client_user_data() {
     assert!(clients[1].data_map().get::<HasCompositor>().is_some());
     assert!(clients[1].data_map().get::<HasOutput>().is_some());
 }
+
+#[test]
+fn client_destructor_user_data() {
+    use std::cell::Cell;
+
+    let mut server = TestServer::new();
+    let disconnected = Arc::new(Mutex::new(Vec::new()));
+
+    struct Serials(Cell<u32>);
+
+    server.display.create_global::<wl_output::WlOutput, _>(1, {
+        let disconnected = disconnected.clone();
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            let client = output.as_ref().client().unwrap();
+            if client.data_map().insert_if_missing(|| Serials(Cell::new(0))) {
+                let disconnected = disconnected.clone();
+                client.add_destructor(ways::Filter::new(
+                    move |data_map: Arc<ways::UserDataMap>, _, _| {
+                        let serials = data_map.get::<Serials>().unwrap();
+                        disconnected.lock().unwrap().push(serials.0.get());
+                    },
+                ));
+            }
+            let serials = client.data_map().get::<Serials>().unwrap();
+            serials.0.set(serials.0.get() + 1);
+        })
+    });
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    manager.instantiate_exact::<ClientOutput>(1).unwrap();
+    manager.instantiate_exact::<ClientOutput>(1).unwrap();
+
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(disconnected.lock().unwrap().is_empty());
+
+    ::std::mem::drop(manager);
+    ::std::mem::drop(client);
+
+    server.answer();
+
+    // the destructor was only added once, and sees the state of the client
+    assert_eq!(&*disconnected.lock().unwrap(), &[2]);
+}
+
+#[test]
+fn display_shutdown() {
+    use std::path::PathBuf;
+
+    let mut server = TestServer::new();
+    let destructor_data = Arc::new(Mutex::new(None));
+
+    server.display.create_global::<wl_output::WlOutput, _>(1, {
+        let destructor_data = destructor_data.clone();
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            let destructor_data = destructor_data.clone();
+            output.as_ref().client().unwrap().add_destructor(ways::Filter::new(
+                move |_, _, mut data: ways::DispatchData| {
+                    *destructor_data.lock().unwrap() = data.get::<u32>().cloned();
+                },
+            ));
+            // this event is still sent by the shutdown
+            output.mode(wl_output::Mode::Current, 1920, 1080, 60);
+        })
+    });
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let modes = Arc::new(Mutex::new(Vec::new()));
+    let output = manager.instantiate_exact::<ClientOutput>(1).unwrap();
+    output.quick_assign({
+        let modes = modes.clone();
+        move |_, event, _| {
+            if let wayc::protocol::wl_output::Event::Mode { width, height, .. } = event {
+                modes.lock().unwrap().push((width, height));
+            }
+        }
+    });
+    client.display.flush().unwrap();
+    ::std::thread::sleep(::std::time::Duration::from_millis(100));
+    server.display.dispatch(::std::time::Duration::from_millis(10), &mut 42u32).unwrap();
+
+    server.display.shutdown(&mut 42u32);
+    assert_eq!(*destructor_data.lock().unwrap(), Some(42));
+
+    assert!(client.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).is_err());
+    assert_eq!(&*modes.lock().unwrap(), &[(1920, 1080)]);
+
+    // new clients can no longer connect
+    assert!(wayc::Display::connect_to_name(&server.socket_name).is_err());
+
+    if cfg!(not(feature = "server_native")) {
+        let mut socket_path = PathBuf::from(::std::env::var_os("XDG_RUNTIME_DIR").unwrap());
+        socket_path.push(&server.socket_name);
+        let mut lock_path = socket_path.clone().into_os_string();
+        lock_path.push(".lock");
+        assert!(!socket_path.exists());
+        assert!(!PathBuf::from(lock_path).exists());
+    }
+}
+
+#[test]
+fn display_replaces_stale_socket() {
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+
+    let mut socket_path = PathBuf::from(::std::env::var_os("XDG_RUNTIME_DIR").unwrap());
+    socket_path.push("wayland-rs-stale-socket");
+    let _ = ::std::fs::remove_file(&socket_path);
+    // a socket left behind by a server that did not remove it
+    ::std::mem::drop(UnixListener::bind(&socket_path).unwrap());
+    assert!(socket_path.exists());
+
+    let mut display = ways::Display::new();
+    display.add_socket(Some("wayland-rs-stale-socket")).unwrap();
+
+    // the socket is locked while in use
+    let mut other_display = ways::Display::new();
+    assert!(other_display.add_socket(Some("wayland-rs-stale-socket")).is_err());
+
+    ::std::mem::drop(display);
+    assert!(!socket_path.exists());
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn flush_scheduling() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut server = TestServer::new();
+    let notified = Arc::new(AtomicUsize::new(0));
+    let server_notified = notified.clone();
+    server.display.set_flush_notifier(move || {
+        server_notified.fetch_add(1, Ordering::SeqCst);
+    });
+
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    let server_outputs = outputs.clone();
+    server.display.create_global::<wl_output::WlOutput, _>(
+        1,
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            server_outputs.lock().unwrap().push(output);
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    manager.instantiate_exact::<ClientOutput>(1).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // everything was flushed by the roundtrips
+    assert!(!server.display.needs_flush());
+    let before = notified.load(Ordering::SeqCst);
+    assert!(before > 0);
+
+    let output = outputs.lock().unwrap()[0].clone();
+    let client = output.as_ref().client().unwrap();
+    assert_eq!(client.pending_bytes(), 0);
+    output.mode(wl_output::Mode::Current, 1920, 1080, 60_000);
+    output.mode(wl_output::Mode::Preferred, 1280, 720, 60_000);
+
+    // notified once for both events
+    assert!(server.display.needs_flush());
+    assert_eq!(notified.load(Ordering::SeqCst), before + 1);
+    assert_eq!(client.pending_bytes(), 2 * 24);
+
+    server.display.flush_clients(&mut ());
+    assert!(!server.display.needs_flush());
+    assert_eq!(client.pending_bytes(), 0);
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn flush_notifier_on_destructor_request() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let mut server = TestServer::new();
+    let notified = Arc::new(AtomicUsize::new(0));
+    let server_notified = notified.clone();
+    server.display.set_flush_notifier(move || {
+        server_notified.fetch_add(1, Ordering::SeqCst);
+    });
+
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(|(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    let output = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(!server.display.needs_flush());
+    let before = notified.load(Ordering::SeqCst);
+
+    output.release();
+    client.display.flush().unwrap();
+    ::std::thread::sleep(Duration::from_millis(100));
+    server.display.dispatch(Duration::from_millis(10), &mut ()).unwrap();
+
+    // the delete_id event waits for a flush
+    assert!(server.display.needs_flush());
+    assert_eq!(notified.load(Ordering::SeqCst), before + 1);
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn flush_notifier_uses_clients() {
+    let mut server = TestServer::new();
+    let clients = Arc::new(Mutex::new(Vec::<ways::Client>::new()));
+    let pending = Arc::new(Mutex::new(Vec::new()));
+    let (notifier_clients, notifier_pending) = (clients.clone(), pending.clone());
+    // the connections of the clients are unlocked when the notifier is invoked
+    server.display.set_flush_notifier(move || {
+        for client in notifier_clients.lock().unwrap().iter() {
+            notifier_pending.lock().unwrap().push(client.pending_bytes());
+        }
+    });
+
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    let server_outputs = outputs.clone();
+    server.display.create_global::<wl_output::WlOutput, _>(
+        1,
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            server_outputs.lock().unwrap().push(output);
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    manager.instantiate_exact::<ClientOutput>(1).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let output = outputs.lock().unwrap()[0].clone();
+    clients.lock().unwrap().push(output.as_ref().client().unwrap());
+    pending.lock().unwrap().clear();
+    output.mode(wl_output::Mode::Current, 1920, 1080, 60_000);
+    assert_eq!(&*pending.lock().unwrap(), &[24]);
+}
@@ -0,0 +1,136 @@
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use ways::protocol::{wl_compositor, wl_output};
+
+use wayc::protocol::wl_output::WlOutput as ClientOutput;
+
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+#[cfg(not(feature = "server_native"))]
+fn pool_client(pool: &mut ways::DispatchPool, thread: Option<usize>) -> TestClient {
+    let (server_cx, client_cx) = UnixStream::pair().unwrap();
+    unsafe {
+        match thread {
+            Some(thread) => pool.create_client_on(thread, server_cx.into_raw_fd()),
+            None => pool.create_client(server_cx.into_raw_fd()),
+        };
+        TestClient::from_fd(client_cx.into_raw_fd())
+    }
+}
+
+#[test]
+#[cfg(not(feature = "server_native"))]
+fn pool_clients_on_several_threads() {
+    let mut display = ways::Display::new();
+    let binds = Arc::new(Mutex::new(Vec::new()));
+    let binds2 = binds.clone();
+    display.create_shared_global::<wl_output::WlOutput, _>(1, move |output, _| {
+        output.quick_assign(|_, _, _| {});
+        binds2.lock().unwrap().push(thread::current().id());
+    });
+
+    let mut pool = display.create_dispatch_pool(2).unwrap();
+    assert_eq!(pool.threads(), 2);
+
+    // the pool dispatches the clients by itself, the display is never dispatched
+    let clients = (0..2)
+        .map(|_| {
+            let mut client = pool_client(&mut pool, None);
+            let manager = wayc::GlobalManager::new(&client.display_proxy);
+            client.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+            manager.instantiate_exact::<ClientOutput>(1).unwrap();
+            client.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+            (client, manager)
+        })
+        .collect::<Vec<_>>();
+
+    let binds = binds.lock().unwrap();
+    assert_eq!(binds.len(), 2);
+    assert!(binds[0] != binds[1]);
+    assert!(binds.iter().all(|&id| id != thread::current().id()));
+    ::std::mem::drop(clients);
+}
+
+#[test]
+#[cfg(not(feature = "server_native"))]
+fn pool_global_namespace() {
+    let mut server = TestServer::new();
+    // only bound to the thread of the display
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        1,
+        ways::Filter::new(|_: (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {}),
+    );
+    let output = server.display.create_shared_global::<wl_output::WlOutput, _>(1, |output, _| {
+        output.quick_assign(|_, _, _| {});
+    });
+    let mut pool = server.display.create_dispatch_pool(1).unwrap();
+
+    let mut client = TestClient::new(&server.socket_name);
+    let client_manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(
+        client_manager.list(),
+        vec![(1, "wl_compositor".into(), 1), (2, "wl_output".into(), 1)]
+    );
+
+    let mut pool_cx = pool_client(&mut pool, None);
+    let pool_manager = wayc::GlobalManager::new(&pool_cx.display_proxy);
+    pool_cx.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+    assert_eq!(pool_manager.list(), vec![(2, "wl_output".into(), 1)]);
+
+    // the removal of the global is sent by the thread of the display
+    output.disable();
+    roundtrip(&mut client, &mut server).unwrap();
+    pool_cx.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+    assert_eq!(client_manager.list(), vec![(1, "wl_compositor".into(), 1)]);
+    assert!(pool_manager.list().is_empty());
+}
+
+#[test]
+#[cfg(not(feature = "server_native"))]
+fn pool_isolates_clients() {
+    let mut display = ways::Display::new();
+    let (started_sender, started) = mpsc::channel::<()>();
+    let (release, release_receiver) = mpsc::channel::<()>();
+    let blocking = Mutex::new(Some((started_sender, release_receiver)));
+    display.create_shared_global::<wl_output::WlOutput, _>(1, move |output, _| {
+        output.quick_assign(|_, _, _| {});
+        // the first bind blocks its thread until it is released
+        let blocking = blocking.lock().unwrap().take();
+        if let Some((started, release)) = blocking {
+            started.send(()).unwrap();
+            release.recv().unwrap();
+        }
+    });
+    let mut pool = display.create_dispatch_pool(2).unwrap();
+
+    let mut slow = pool_client(&mut pool, Some(0));
+    let slow_manager = wayc::GlobalManager::new(&slow.display_proxy);
+    slow.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+    slow_manager.instantiate_exact::<ClientOutput>(1).unwrap();
+    slow.display.flush().unwrap();
+    started.recv().unwrap();
+
+    // the other thread still serves its clients
+    let mut fast = pool_client(&mut pool, Some(1));
+    let fast_manager = wayc::GlobalManager::new(&fast.display_proxy);
+    fast.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+    let fast_output = fast_manager.instantiate_exact::<ClientOutput>(1).unwrap();
+    fast.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+    assert!(fast_output.as_ref().is_alive());
+
+    release.send(()).unwrap();
+    slow.event_queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+}
+
+#[test]
+#[cfg(feature = "server_native")]
+fn pool_unavailable() {
+    let mut display = ways::Display::new();
+    assert!(display.create_dispatch_pool(2).is_err());
+}
@@ -121,6 +121,67 @@ fn dead_resources() {
     }
 }
 
+#[test]
+fn resource_object_ids() {
+    use std::collections::HashMap;
+
+    let mut server = TestServer::new();
+
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    let outputs2 = outputs.clone();
+
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(newo, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            newo.quick_assign(|_, _, _| {});
+            outputs2.lock().unwrap().push((*newo).clone());
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    let mut client2 = TestClient::new(&server.socket_name);
+    let manager2 = wayc::GlobalManager::new(&client2.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+    roundtrip(&mut client2, &mut server).unwrap();
+
+    let client_output = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+    manager2.instantiate_exact::<ClientOutput>(3).unwrap();
+    roundtrip(&mut client2, &mut server).unwrap();
+
+    let (output, weak, names) = {
+        let outputs = outputs.lock().unwrap();
+        // the same protocol id in two clients
+        assert_eq!(outputs[0].as_ref().id(), outputs[1].as_ref().id());
+        assert_ne!(outputs[0].as_ref().object_id(), outputs[1].as_ref().object_id());
+
+        let mut names = HashMap::new();
+        names.insert(outputs[0].as_ref().object_id(), "first");
+        names.insert(outputs[1].as_ref().object_id(), "second");
+        (outputs[0].clone(), outputs[0].as_ref().downgrade(), names)
+    };
+    assert!(weak.upgrade().unwrap() == output);
+
+    client_output.release();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the id stays valid once the object is destroyed
+    assert!(weak.upgrade().is_none());
+    assert_eq!(names.get(&output.as_ref().object_id()), Some(&"first"));
+
+    // a new object reusing the protocol id gets a different id
+    manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+    let outputs = outputs.lock().unwrap();
+    // libwayland-client does not reuse a freed id right away
+    #[cfg(not(feature = "client_native"))]
+    assert_eq!(outputs[2].as_ref().id(), weak.object_id().protocol_id());
+    assert!(names.get(&outputs[2].as_ref().object_id()).is_none());
+    assert!(weak.upgrade().is_none());
+}
+
 #[test]
 fn get_resource() {
     let mut server = TestServer::new();
@@ -155,3 +216,344 @@ fn get_resource() {
     // but this suceeds
     assert!(clients[0].get_resource::<wl_output::WlOutput>(3).is_some());
 }
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn send_hooks() {
+    use wayc::protocol::wl_output::Event as ClientEvent;
+    use ways::protocol::wl_output::Event as ServerEvent;
+
+    let mut server = TestServer::new();
+
+    // double the scale of all outputs
+    server.display.set_send_hook::<wl_output::WlOutput, _>(|_, event| match event {
+        ServerEvent::Scale { factor } => Some(ServerEvent::Scale { factor: factor * 2 }),
+        other => Some(other),
+    });
+
+    server.display.create_global::<wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(|(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            // and drop the done events of this one
+            output.as_ref().set_send_hook(|_, event| match event {
+                ServerEvent::Done => None,
+                other => Some(other),
+            });
+            output.scale(1);
+            output.done();
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let output = manager.instantiate_exact::<ClientOutput>(2).unwrap();
+    output.quick_assign({
+        let events = events.clone();
+        move |_, event, _| events.lock().unwrap().push(event)
+    });
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 1);
+    match events[0] {
+        ClientEvent::Scale { factor } => assert_eq!(factor, 2),
+        _ => panic!("Unexpected event"),
+    }
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn client_resources() {
+    let mut server = TestServer::new();
+
+    let clients = Arc::new(Mutex::new(Vec::new()));
+    let clients2 = clients.clone();
+
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            clients2.lock().unwrap().push(output.as_ref().client().unwrap());
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let output = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    let output_id = output.as_ref().id();
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let server_client = clients.lock().unwrap()[0].clone();
+    let resources = server_client.enumerate_resources();
+    assert_eq!(resources[0].interface, "wl_display");
+    assert_eq!(resources[1].interface, "wl_registry");
+    let info = resources.iter().find(|r| r.id == output_id).unwrap();
+    assert_eq!((info.interface, info.version, info.alive), ("wl_output", 3, true));
+
+    // destroyed resources are removed from the list
+    output.release();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(server_client.enumerate_resources().iter().all(|r| r.id != output_id));
+
+    server_client.kill();
+    assert!(server_client.enumerate_resources().is_empty());
+}
+
+#[test]
+fn send_checked_events() {
+    let mut server = TestServer::new();
+
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    let outputs2 = outputs.clone();
+
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            outputs2.lock().unwrap().push(output);
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    for version in 1..3 {
+        let received = received.clone();
+        let output = manager.instantiate_exact::<ClientOutput>(version).unwrap();
+        output.quick_assign(move |_, event, _| {
+            if let wayc::protocol::wl_output::Event::Scale { factor } = event {
+                received.lock().unwrap().push((version, factor));
+            }
+        });
+    }
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let outputs = outputs.lock().unwrap();
+    let (old, new) = (&outputs[0], &outputs[1]);
+    assert_eq!(old.as_ref().version(), 1);
+
+    // wl_output.scale is only available since version 2
+    assert_eq!(old.scale_checked(ways::VersionCheck::Skip, 2), Ok(()));
+    assert_eq!(
+        old.scale_checked(ways::VersionCheck::Error, 2),
+        Err(ways::VersionTooLow { interface: "wl_output", event: "scale", since: 2, version: 1 })
+    );
+    assert_eq!(new.scale_checked(ways::VersionCheck::Error, 2), Ok(()));
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert_eq!(&*received.lock().unwrap(), &[(2, 2)]);
+}
+
+#[test]
+#[should_panic]
+fn send_checked_events_panic() {
+    let mut server = TestServer::new();
+
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    let outputs2 = outputs.clone();
+
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            outputs2.lock().unwrap().push(output);
+        }),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+    manager.instantiate_exact::<ClientOutput>(1).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let _ = outputs.lock().unwrap()[0].scale_checked(ways::VersionCheck::Panic, 2);
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn client_resources_of() {
+    let mut server = TestServer::new();
+
+    let clients = Arc::new(Mutex::new(Vec::new()));
+    let clients2 = clients.clone();
+
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            clients2.lock().unwrap().push(output.as_ref().client().unwrap());
+        }),
+    );
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        4,
+        ways::Filter::new(
+            |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                compositor.quick_assign(|_, _, _| {});
+            },
+        ),
+    );
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let output1 = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    manager.instantiate_exact::<wayc::protocol::wl_compositor::WlCompositor>(4).unwrap();
+    let output2 = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let server_client = clients.lock().unwrap()[0].clone();
+    let outputs = server_client.resources_of::<wl_output::WlOutput>();
+    let ids = outputs.iter().map(|o| o.as_ref().id()).collect::<Vec<_>>();
+    assert_eq!(ids, vec![output1.as_ref().id(), output2.as_ref().id()]);
+    assert_eq!(server_client.resources_of::<wl_compositor::WlCompositor>().len(), 1);
+
+    // destroyed resources are not listed
+    output1.release();
+    roundtrip(&mut client, &mut server).unwrap();
+    let outputs = server_client.resources_of::<wl_output::WlOutput>();
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].as_ref().id(), output2.as_ref().id());
+
+    server_client.kill();
+    assert!(server_client.resources_of::<wl_output::WlOutput>().is_empty());
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn request_gates_defer() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use ways::protocol::wl_surface;
+
+    let mut server = TestServer::new();
+
+    let commits = Arc::new(Mutex::new(0));
+    let commits2 = commits.clone();
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        4,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                let commits = commits2.clone();
+                compositor.quick_assign(move |_, request, _| {
+                    if let wl_compositor::Request::CreateSurface { id } = request {
+                        let commits = commits.clone();
+                        id.quick_assign(move |_, request, _| {
+                            if let wl_surface::Request::Commit = request {
+                                *commits.lock().unwrap() += 1;
+                            }
+                        });
+                    }
+                });
+            },
+        ),
+    );
+
+    // throttle the commits
+    let throttled = Arc::new(AtomicBool::new(false));
+    let throttled2 = throttled.clone();
+    server.display.set_request_gate::<wl_surface::WlSurface, _>(move |_, _, opcode| {
+        if opcode == 6 && throttled2.load(Ordering::SeqCst) {
+            ways::GateDecision::Defer
+        } else {
+            ways::GateDecision::Allow
+        }
+    });
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor =
+        manager.instantiate_exact::<wayc::protocol::wl_compositor::WlCompositor>(4).unwrap();
+    let surface = compositor.create_surface();
+    surface.commit();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(*commits.lock().unwrap(), 1);
+
+    throttled.store(true, Ordering::SeqCst);
+    surface.commit();
+    surface.commit();
+    surface.damage(0, 0, 10, 10);
+    client.display.flush().unwrap();
+    for _ in 0..3 {
+        server.answer();
+    }
+    assert_eq!(*commits.lock().unwrap(), 1);
+
+    // the deferred requests are dispatched in order once allowed
+    throttled.store(false, Ordering::SeqCst);
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(*commits.lock().unwrap(), 3);
+}
+
+#[cfg(not(feature = "server_native"))]
+#[test]
+fn request_gates_kill() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut server = TestServer::new();
+
+    let clients = Arc::new(Mutex::new(Vec::new()));
+    let clients2 = clients.clone();
+    server.display.create_global::<wl_output::WlOutput, _>(
+        3,
+        ways::Filter::new(move |(output, _): (ways::Main<wl_output::WlOutput>, u32), _, _| {
+            output.quick_assign(|_, _, _| {});
+            // releasing the first output kills the client
+            if clients2.lock().unwrap().is_empty() {
+                output.as_ref().set_request_gate(|_, _, opcode| {
+                    if opcode == 0 {
+                        ways::GateDecision::Kill
+                    } else {
+                        ways::GateDecision::Allow
+                    }
+                });
+            }
+            clients2.lock().unwrap().push(output.as_ref().client().unwrap());
+        }),
+    );
+    // the gate of the interface is only evaluated if the gate of the resource allows the request
+    let evaluated = Arc::new(AtomicUsize::new(0));
+    let evaluated2 = evaluated.clone();
+    server.display.set_request_gate::<wl_output::WlOutput, _>(move |_, output, _| {
+        assert!(output.is_alive());
+        evaluated2.fetch_add(1, Ordering::SeqCst);
+        ways::GateDecision::Allow
+    });
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let output1 = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    let output2 = manager.instantiate_exact::<ClientOutput>(3).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+    let server_client = clients.lock().unwrap()[0].clone();
+
+    output2.release();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(server_client.alive());
+    assert_eq!(evaluated.load(Ordering::SeqCst), 1);
+
+    output1.release();
+    assert!(roundtrip(&mut client, &mut server).is_err());
+    assert!(!server_client.alive());
+    assert_eq!(evaluated.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,146 @@
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use wayc::protocol::wl_shm::Format;
+
+use ways::protocol::wl_buffer::WlBuffer as ServerBuffer;
+use ways::shm::{init_shm_global, BufferAccessError, BufferData, ShmBuffer};
+
+fn insert_compositor(server: &mut TestServer) -> Arc<Mutex<Option<ServerBuffer>>> {
+    use ways::protocol::{wl_compositor, wl_surface};
+
+    let buffer_found = Arc::new(Mutex::new(None));
+    let buffer_found2 = buffer_found.clone();
+
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        1,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                let buffer_found = buffer_found.clone();
+                compositor.quick_assign(move |_, request, _| {
+                    if let wl_compositor::Request::CreateSurface { id } = request {
+                        let buffer_found = buffer_found.clone();
+                        id.quick_assign(move |_, request, _| {
+                            if let wl_surface::Request::Attach { buffer, .. } = request {
+                                *buffer_found.lock().unwrap() = buffer;
+                            }
+                        });
+                    }
+                });
+            },
+        ),
+    );
+
+    buffer_found2
+}
+
+fn attach_buffer(
+    client: &mut TestClient,
+    manager: &wayc::GlobalManager,
+    file: &std::fs::File,
+    pool_size: i32,
+    (offset, width, height, stride): (i32, i32, i32, i32),
+) {
+    let shm = manager.instantiate_exact::<wayc::protocol::wl_shm::WlShm>(1).unwrap();
+    let pool = shm.create_pool(file.as_raw_fd(), pool_size);
+    let buffer = pool.create_buffer(offset, width, height, stride, Format::Argb8888);
+    let compositor =
+        manager.instantiate_exact::<wayc::protocol::wl_compositor::WlCompositor>(1).unwrap();
+    let surface = compositor.create_surface();
+    surface.attach(Some(&buffer), 0, 0);
+    client.display.flush().unwrap();
+}
+
+#[test]
+fn shm_buffer_contents() {
+    let mut server = TestServer::new();
+    init_shm_global(&mut server.display, Vec::new());
+    let buffer_found = insert_compositor(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&[0; 16]).unwrap();
+    file.write_all(&[42; 32]).unwrap();
+    file.flush().unwrap();
+    attach_buffer(&mut client, &manager, &file, 48, (16, 2, 4, 8));
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let buffer = buffer_found.lock().unwrap().take().unwrap();
+    let shm_buffer = ShmBuffer::get(&buffer).unwrap();
+    let expected = BufferData {
+        offset: 16,
+        width: 2,
+        height: 4,
+        stride: 8,
+        format: ways::protocol::wl_shm::Format::Argb8888,
+    };
+    assert_eq!(shm_buffer.data(), expected);
+    let contents = shm_buffer
+        .with_contents(|contents, data| {
+            contents[data.offset as usize..(data.offset + data.stride * data.height) as usize]
+                .to_vec()
+        })
+        .unwrap();
+    assert_eq!(contents, vec![42; 32]);
+}
+
+#[test]
+fn shm_buffer_truncated_pool() {
+    let mut server = TestServer::new();
+    init_shm_global(&mut server.display, Vec::new());
+    let buffer_found = insert_compositor(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let file = tempfile::tempfile().unwrap();
+    file.set_len(4096 * 4).unwrap();
+    attach_buffer(&mut client, &manager, &file, 4096 * 4, (0, 64, 64, 256));
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the client truncates the file under our feet
+    file.set_len(0).unwrap();
+
+    let buffer = buffer_found.lock().unwrap().take().unwrap();
+    let shm_buffer = ShmBuffer::get(&buffer).unwrap();
+    let ret =
+        shm_buffer.with_contents(|contents, _| contents.iter().map(|&b| b as u32).sum::<u32>());
+    assert_eq!(ret, Err(BufferAccessError::BadMap));
+    // the pool is unusable from now on
+    assert_eq!(shm_buffer.with_contents(|_, _| ()), Err(BufferAccessError::BadMap));
+}
+
+#[test]
+fn shm_invalid_buffer() {
+    let mut server = TestServer::new();
+    init_shm_global(&mut server.display, Vec::new());
+    insert_compositor(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let file = tempfile::tempfile().unwrap();
+    file.set_len(64).unwrap();
+    // this buffer does not fit in the pool
+    attach_buffer(&mut client, &manager, &file, 64, (32, 4, 4, 16));
+
+    assert!(roundtrip(&mut client, &mut server).is_err());
+    let error = client.display.protocol_error().unwrap();
+    assert_eq!(error.code, ways::protocol::wl_shm::Error::InvalidStride as u32);
+    assert_eq!(error.object_interface, "wl_shm_pool");
+}
@@ -0,0 +1,191 @@
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+extern crate wayland_commons as wc;
+
+use wc::capture::{CapturedArgument, CapturedMessage, Direction};
+use wc::smallvec;
+use wc::sniffer::{forward, LogFormat, Sniffer};
+use wc::socket::Socket;
+use wc::wire::{write_message, Argument, Message};
+
+use std::env;
+use std::ffi::CString;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn feed(sniffer: &mut Sniffer, direction: Direction, msgs: &[Message]) -> Vec<CapturedMessage> {
+    let mut bytes = Vec::new();
+    let mut fds = Vec::new();
+    for msg in msgs {
+        write_message(msg, &mut bytes, &mut fds).unwrap();
+    }
+    let mut decoded = Vec::new();
+    // feed the data in two parts, splitting a message
+    let (first, second) = bytes.split_at(bytes.len() / 2 + 1);
+    sniffer.feed(direction, first, &fds, |msg| decoded.push(msg)).unwrap();
+    sniffer.feed(direction, second, &[], |msg| decoded.push(msg)).unwrap();
+    decoded
+}
+
+fn names(msgs: &[CapturedMessage]) -> Vec<String> {
+    msgs.iter().map(|msg| format!("{}@{}.{}", msg.interface, msg.sender_id, msg.name)).collect()
+}
+
+#[test]
+fn sniffer_tracks_objects() {
+    use wayc::protocol::{wl_display, wl_output};
+    let mut sniffer = Sniffer::new::<wl_display::WlDisplay>();
+    sniffer.register::<wl_output::WlOutput>();
+
+    let requests = feed(
+        &mut sniffer,
+        Direction::Sent,
+        &[Message { sender_id: 1, opcode: 1, args: smallvec![Argument::NewId(2)] }],
+    );
+    assert_eq!(names(&requests), ["wl_display@1.get_registry"]);
+
+    let events = feed(
+        &mut sniffer,
+        Direction::Received,
+        &[Message {
+            sender_id: 2,
+            opcode: 0,
+            args: smallvec![
+                Argument::Uint(1),
+                Argument::Str(Box::new(CString::new("wl_output").unwrap())),
+                Argument::Uint(3),
+            ],
+        }],
+    );
+    assert_eq!(names(&events), ["wl_registry@2.global"]);
+
+    let requests = feed(
+        &mut sniffer,
+        Direction::Sent,
+        &[
+            Message {
+                sender_id: 2,
+                opcode: 0,
+                args: smallvec![
+                    Argument::Uint(1),
+                    Argument::Str(Box::new(CString::new("wl_output").unwrap())),
+                    Argument::Uint(3),
+                    Argument::NewId(3),
+                ],
+            },
+            Message { sender_id: 3, opcode: 0, args: smallvec![] },
+        ],
+    );
+    assert_eq!(names(&requests), ["wl_registry@2.bind", "wl_output@3.release"]);
+
+    let events = feed(
+        &mut sniffer,
+        Direction::Received,
+        &[
+            Message {
+                sender_id: 3,
+                opcode: 3,
+                args: smallvec![Argument::Int(2)], // wl_output.scale
+            },
+            Message { sender_id: 1, opcode: 1, args: smallvec![Argument::Uint(3)] },
+            Message { sender_id: 3, opcode: 3, args: smallvec![Argument::Int(2)] },
+        ],
+    );
+    // the object is only forgotten once its id is deleted
+    assert_eq!(names(&events), ["wl_output@3.scale", "wl_display@1.delete_id", "@3."]);
+    assert_eq!(events[0].args, [CapturedArgument::Int(2)]);
+    assert!(events[2].args.is_empty());
+}
+
+#[test]
+fn sniffer_log_formats() {
+    let msg = CapturedMessage {
+        timestamp: Duration::from_micros(1_000_042),
+        direction: Direction::Sent,
+        interface: "wl_surface".into(),
+        name: "attach".into(),
+        sender_id: 3,
+        opcode: 1,
+        args: vec![
+            CapturedArgument::Object(0),
+            CapturedArgument::Int(-2),
+            CapturedArgument::Fixed(384),
+            CapturedArgument::Str(b"a \"b\"\n".to_vec()),
+            CapturedArgument::NewId(4),
+            CapturedArgument::Array(vec![1, 255]),
+            CapturedArgument::Fd,
+        ],
+    };
+
+    let mut text = Vec::new();
+    LogFormat::Text.write(&msg, &mut text).unwrap();
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "[1.000042] -> wl_surface@3.attach(nil, -2, 1.5, \"a \\\"b\\\"\\n\", new id @4, array[2], fd)\n"
+    );
+
+    let mut json = Vec::new();
+    LogFormat::Json.write(&msg, &mut json).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        concat!(
+            "{\"timestamp\":1.000042,\"direction\":\"request\",\"interface\":\"wl_surface\",",
+            "\"id\":3,\"opcode\":1,\"name\":\"attach\",\"args\":[",
+            "{\"type\":\"object\",\"value\":0},{\"type\":\"int\",\"value\":-2},",
+            "{\"type\":\"fixed\",\"value\":1.5},{\"type\":\"string\",\"value\":\"a \\\"b\\\"\\n\"},",
+            "{\"type\":\"new_id\",\"value\":4},{\"type\":\"array\",\"value\":\"01ff\"},",
+            "{\"type\":\"fd\"}]}\n"
+        )
+    );
+}
+
+#[test]
+fn sniffer_proxy() {
+    let mut server = TestServer::new();
+    server.display.create_global::<ways::protocol::wl_output::WlOutput, _>(
+        2,
+        ways::Filter::new(
+            |(output, _): (ways::Main<ways::protocol::wl_output::WlOutput>, u32), _, _| {
+                output.scale(2);
+            },
+        ),
+    );
+
+    let mut path: PathBuf = env::var_os("XDG_RUNTIME_DIR").unwrap().into();
+    path.push(&server.socket_name);
+    let upstream = UnixStream::connect(path).unwrap();
+    let (client_socket, proxy_socket) = UnixStream::pair().unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let proxy = std::thread::spawn(move || {
+        let client = unsafe { Socket::from_raw_fd(proxy_socket.into_raw_fd()) };
+        let server = unsafe { Socket::from_raw_fd(upstream.into_raw_fd()) };
+        let mut sniffer = Sniffer::new::<wayc::protocol::wl_display::WlDisplay>();
+        sniffer.register::<wayc::protocol::wl_output::WlOutput>();
+        forward(&client, &server, &mut sniffer, |msg| sender.send(msg).unwrap()).unwrap();
+    });
+
+    let mut client = unsafe { TestClient::from_fd(client_socket.into_raw_fd()) };
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+    manager.instantiate_exact::<wayc::protocol::wl_output::WlOutput>(2).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // disconnecting the client stops the proxy
+    drop(manager);
+    drop(client);
+    proxy.join().unwrap();
+
+    let msgs = receiver.iter().collect::<Vec<_>>();
+    let names = names(&msgs);
+    assert!(names.contains(&"wl_registry@2.global".to_owned()));
+    assert!(names.contains(&"wl_registry@2.bind".to_owned()));
+    assert!(names.contains(&"wl_output@3.scale".to_owned()));
+    let bind = msgs.iter().find(|msg| msg.name == "bind").unwrap();
+    assert_eq!(bind.direction, Direction::Sent);
+}
@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+
+mod helpers;
+
+use helpers::{roundtrip, wayc, ways, TestClient, TestServer};
+
+use wayc::protocol::wl_output::Transform;
+use wayc::protocol::wl_shm::{Format, WlShm};
+use wayc::shm::ShmPool;
+use wayc::surface::{Rect, SurfaceState};
+
+// records the requests sent to the surfaces
+fn insert_compositor(server: &mut TestServer, version: u32) -> Arc<Mutex<Vec<String>>> {
+    use ways::protocol::{wl_compositor, wl_surface};
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let requests2 = requests.clone();
+
+    ways::request_enum!(Reqs |
+        Compositor => wl_compositor::WlCompositor,
+        Surface => wl_surface::WlSurface
+    );
+
+    let filter = ways::Filter::new(move |req, filter, _| match req {
+        Reqs::Compositor {
+            request: wl_compositor::Request::CreateSurface { id: surface }, ..
+        } => {
+            surface.assign(filter.clone());
+        }
+        Reqs::Surface { request, .. } => {
+            let request = match request {
+                wl_surface::Request::Attach { buffer, .. } => {
+                    format!("attach {}", if buffer.is_some() { "buffer" } else { "null" })
+                }
+                wl_surface::Request::Damage { x, y, width, height } => {
+                    format!("damage {} {} {} {}", x, y, width, height)
+                }
+                wl_surface::Request::DamageBuffer { x, y, width, height } => {
+                    format!("damage_buffer {} {} {} {}", x, y, width, height)
+                }
+                wl_surface::Request::SetInputRegion { region } => {
+                    format!("set_input_region {}", if region.is_some() { "region" } else { "null" })
+                }
+                wl_surface::Request::SetBufferScale { scale } => {
+                    format!("set_buffer_scale {}", scale)
+                }
+                wl_surface::Request::SetBufferTransform { transform } => {
+                    format!("set_buffer_transform {}", transform.to_raw())
+                }
+                wl_surface::Request::Commit => "commit".into(),
+                _ => panic!("Unexpected request."),
+            };
+            requests.lock().unwrap().push(request);
+        }
+        _ => panic!("Unexpected request."),
+    });
+
+    server.display.create_global::<wl_compositor::WlCompositor, _>(
+        version,
+        ways::Filter::new(
+            move |(compositor, _): (ways::Main<wl_compositor::WlCompositor>, u32), _, _| {
+                compositor.assign(filter.clone());
+            },
+        ),
+    );
+
+    requests2
+}
+
+fn insert_shm(server: &mut TestServer) {
+    use ways::protocol::{wl_shm, wl_shm_pool};
+
+    ways::request_enum!(Reqs |
+        Shm => wl_shm::WlShm,
+        Pool => wl_shm_pool::WlShmPool
+    );
+
+    let filter = ways::Filter::new(move |req, filter, _| match req {
+        Reqs::Shm { request: wl_shm::Request::CreatePool { id, .. }, .. } => {
+            id.assign(filter.clone());
+        }
+        Reqs::Pool { request: wl_shm_pool::Request::CreateBuffer { id, .. }, .. } => {
+            id.quick_assign(|_, _, _| {});
+        }
+        _ => {}
+    });
+
+    server.display.create_global::<wl_shm::WlShm, _>(
+        1,
+        ways::Filter::new(move |(shm, _): (ways::Main<wl_shm::WlShm>, u32), _, _| {
+            shm.assign(filter.clone());
+        }),
+    );
+}
+
+fn setup(version: u32) -> (TestServer, TestClient, Arc<Mutex<Vec<String>>>, SurfaceState, ShmPool) {
+    use wayc::protocol::wl_compositor::WlCompositor;
+
+    let mut server = TestServer::new();
+    let requests = insert_compositor(&mut server, version);
+    insert_shm(&mut server);
+
+    let mut client = TestClient::new(&server.socket_name);
+    let manager = wayc::GlobalManager::new(&client.display_proxy);
+    roundtrip(&mut client, &mut server).unwrap();
+
+    let compositor = manager.instantiate_exact::<WlCompositor>(version).unwrap();
+    let shm = manager.instantiate_exact::<WlShm>(1).unwrap();
+    let surface = compositor.create_surface();
+    let pool = ShmPool::new(&shm).unwrap();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    (server, client, requests, SurfaceState::new(&surface), pool)
+}
+
+#[test]
+fn surface_state_commit() {
+    let (mut server, mut client, requests, mut state, mut pool) = setup(4);
+    let buffer = pool.create_buffer(64, 32, 64 * 4, Format::Argb8888).unwrap();
+
+    state.set_buffer_scale(2);
+    state.set_buffer_transform(Transform::_90);
+    state.set_input_region(None);
+    state.attach_shm(&buffer);
+    state.damage_buffer(Rect::new(0, 0, 16, 16));
+    state.damage_buffer(Rect::new(8, 8, 16, 16));
+    state.damage(Rect::new(100, 100, 0, 5));
+    state.damage(Rect::new(1, 1, 2, 2));
+    assert_eq!(state.pending_buffer_damage(), [Rect::new(0, 0, 24, 24)]);
+    // nothing is sent before the commit
+    assert_eq!(state.buffer_scale(), 1);
+    assert_eq!(state.surface_size(), None);
+    state.commit();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert!(buffer.is_busy());
+    assert_eq!(state.buffer_scale(), 2);
+    assert_eq!(state.buffer_transform(), Transform::_90);
+    assert_eq!(state.surface_size(), Some((16, 32)));
+
+    // unchanged state is not sent again
+    state.set_buffer_scale(2);
+    state.commit();
+    state.detach();
+    state.commit();
+    roundtrip(&mut client, &mut server).unwrap();
+    assert_eq!(state.buffer_size(), None);
+
+    assert_eq!(
+        *requests.lock().unwrap(),
+        [
+            "attach buffer",
+            "set_input_region null",
+            "set_buffer_transform 1",
+            "set_buffer_scale 2",
+            "damage_buffer 0 0 24 24",
+            "damage 1 1 2 2",
+            "commit",
+            "commit",
+            "attach null",
+            "commit",
+        ]
+    );
+}
+
+#[test]
+fn surface_state_converts_buffer_damage() {
+    let (mut server, mut client, requests, mut state, mut pool) = setup(3);
+    let buffer = pool.create_buffer(64, 32, 64 * 4, Format::Argb8888).unwrap();
+
+    // the surface is 32x64 once rotated
+    state.set_buffer_transform(Transform::_90);
+    state.attach_shm(&buffer);
+    state.damage_buffer(Rect::new(0, 0, 10, 4));
+    state.commit();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    // the converted damage is merged with the surface damage, rounding outwards
+    state.set_buffer_transform(Transform::Normal);
+    state.set_buffer_scale(2);
+    state.damage_buffer(Rect::new(11, 11, 18, 18));
+    state.damage(Rect::new(0, 0, 6, 6));
+    state.commit();
+    roundtrip(&mut client, &mut server).unwrap();
+
+    assert_eq!(
+        *requests.lock().unwrap(),
+        [
+            "attach buffer",
+            "set_buffer_transform 1",
+            "damage 28 0 4 10",
+            "commit",
+            "set_buffer_transform 0",
+            "set_buffer_scale 2",
+            "damage 0 0 15 15",
+            "commit",
+        ]
+    );
+}
+
+#[test]
+fn surface_state_coalesces_damage() {
+    let (_server, _client, _requests, mut state, _pool) = setup(4);
+
+    // disjoint rectangles are kept separate
+    state.damage(Rect::new(0, 0, 10, 10));
+    state.damage(Rect::new(20, 0, 10, 10));
+    assert_eq!(state.pending_damage().len(), 2);
+    // a rectangle overlapping both merges them
+    state.damage(Rect::new(5, 5, 20, 2));
+    assert_eq!(state.pending_damage(), [Rect::new(0, 0, 30, 10)]);
+
+    // too many rectangles are replaced by their bounding box
+    for i in 0..32 {
+        state.damage(Rect::new(100 + 20 * i, 100, 10, 10));
+    }
+    assert_eq!(state.pending_damage(), [Rect::new(0, 0, 730, 110)]);
+}
@@ -0,0 +1,144 @@
+mod helpers;
+
+use helpers::{wayc, TestClient, TestServer};
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use wayc::{Watchdog, WatchdogEvent};
+
+fn spawn_watchdog(client: &TestClient) -> (Watchdog, mpsc::Receiver<WatchdogEvent>) {
+    let (sender, receiver) = mpsc::channel();
+    let watchdog = Watchdog::spawn(&client.display, Duration::from_millis(50), move |event| {
+        let _ = sender.send(event);
+    })
+    .unwrap();
+    (watchdog, receiver)
+}
+
+// read and dispatch the events of the client from an other thread, like its event loop would
+fn spawn_dispatcher(client: &TestClient) {
+    let display = client.display.clone();
+    ::std::thread::spawn(move || {
+        let mut queue = display.create_event_queue();
+        while queue.dispatch(&mut (), |_, _, _| {}).is_ok() {}
+    });
+}
+
+// dispatch the server until the watchdog reports an event
+fn answer_until_event(
+    server: &mut TestServer,
+    receiver: &mpsc::Receiver<WatchdogEvent>,
+) -> Option<WatchdogEvent> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        server.answer();
+        if let Ok(event) = receiver.try_recv() {
+            return Some(event);
+        }
+    }
+    None
+}
+
+#[test]
+fn watchdog_responsive() {
+    let mut server = TestServer::new();
+    let client = TestClient::new(&server.socket_name);
+    spawn_dispatcher(&client);
+    let (watchdog, receiver) = spawn_watchdog(&client);
+
+    // several pings are answered without any event
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(300) {
+        server.answer();
+    }
+    assert!(receiver.try_recv().is_err());
+    assert!(watchdog.is_running());
+    watchdog.stop();
+}
+
+#[test]
+fn watchdog_unresponsive() {
+    let mut server = TestServer::new();
+    let client = TestClient::new(&server.socket_name);
+    spawn_dispatcher(&client);
+    let (watchdog, receiver) = spawn_watchdog(&client);
+
+    // the server is not dispatched, so the ping is not answered
+    match receiver.recv_timeout(Duration::from_secs(5)) {
+        Ok(WatchdogEvent::CompositorUnresponsive { waiting }) => {
+            assert!(waiting >= Duration::from_millis(50))
+        }
+        other => panic!("Unexpected watchdog event: {:?}", other),
+    }
+    // it is reported only once
+    ::std::thread::sleep(Duration::from_millis(200));
+    assert!(receiver.try_recv().is_err());
+
+    match answer_until_event(&mut server, &receiver) {
+        Some(WatchdogEvent::CompositorResponsive { latency }) => {
+            assert!(latency >= Duration::from_millis(250))
+        }
+        other => panic!("Unexpected watchdog event: {:?}", other),
+    }
+    assert!(watchdog.is_running());
+}
+
+#[test]
+fn watchdog_connection_lost() {
+    let server = TestServer::new();
+    let client = TestClient::new(&server.socket_name);
+    spawn_dispatcher(&client);
+    let (watchdog, receiver) = spawn_watchdog(&client);
+
+    drop(server);
+    match receiver.recv_timeout(Duration::from_secs(5)) {
+        Ok(WatchdogEvent::ConnectionLost(_)) => {}
+        other => panic!("Unexpected watchdog event: {:?}", other),
+    }
+    // the thread exits right after reporting the event
+    ::std::thread::sleep(Duration::from_millis(100));
+    assert!(!watchdog.is_running());
+}
+
+#[test]
+fn watchdog_concurrent_dispatcher() {
+    let mut server = TestServer::new();
+    let client = TestClient::new(&server.socket_name);
+    let (watchdog, receiver) = spawn_watchdog(&client);
+
+    // an other thread waiting for its events in a blocking dispatch is always woken up
+    let display = client.display.clone();
+    let (done, done_receiver) = mpsc::channel();
+    ::std::thread::spawn(move || {
+        let mut queue = display.create_event_queue();
+        for _ in 0..100 {
+            queue.sync_roundtrip(&mut (), |_, _, _| {}).unwrap();
+        }
+        done.send(()).unwrap();
+    });
+
+    let start = Instant::now();
+    while done_receiver.try_recv().is_err() {
+        assert!(start.elapsed() < Duration::from_secs(5), "The dispatching thread is stuck.");
+        server.answer();
+    }
+    // the pings were answered meanwhile
+    assert!(receiver.try_recv().is_err());
+    assert!(watchdog.is_running());
+    watchdog.stop();
+}
+
+#[test]
+fn watchdog_not_reading() {
+    let mut server = TestServer::new();
+    let client = TestClient::new(&server.socket_name);
+    let (watchdog, receiver) = spawn_watchdog(&client);
+
+    // nobody reads the events of the client, the answers to the pings are never seen
+    match answer_until_event(&mut server, &receiver) {
+        Some(WatchdogEvent::CompositorUnresponsive { .. }) => {}
+        other => panic!("Unexpected watchdog event: {:?}", other),
+    }
+    watchdog.stop();
+}
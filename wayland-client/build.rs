@@ -1,7 +1,8 @@
 extern crate wayland_scanner;
 
-use std::env::var;
+use std::env::{var, var_os};
 use std::path::Path;
+use std::process::Command;
 use wayland_scanner::*;
 
 fn main() {
@@ -11,10 +12,48 @@ fn main() {
     let out_dir = Path::new(&out_dir_str);
 
     println!("cargo:rerun-if-changed={}", protocol_file);
-    generate_code_with_destructor_events(
+    generate_code_with_options(
         protocol_file,
         out_dir.join("wayland_api.rs"),
         Side::Client,
-        &[("wl_callback", "done")],
+        &Options::new()
+            .destructor_events(&[("wl_callback", "done")])
+            .async_helpers(true)
+            .argument_metadata(true)
+            .borrowed_events(true)
+            .borrowed_parsing(true)
+            .interface_description(true),
     );
+
+    if var_os("CARGO_FEATURE_C_ABI").is_some() {
+        build_c_abi(out_dir);
+    }
+}
+
+// Compile the variadic entry points of the C API, with the compiler and archiver of the target
+fn build_c_abi(out_dir: &Path) {
+    let source = "src/rust_imp/c_abi.c";
+    let object = out_dir.join("c_abi.o");
+    let compiler = var("CC").unwrap_or_else(|_| "cc".into());
+    let archiver = var("AR").unwrap_or_else(|_| "ar".into());
+
+    println!("cargo:rerun-if-changed={}", source);
+    println!("cargo:rerun-if-env-changed=CC");
+    println!("cargo:rerun-if-env-changed=AR");
+    let status = Command::new(&compiler)
+        .args(&["-c", "-fPIC", "-O2", source, "-o"])
+        .arg(&object)
+        .status()
+        .unwrap_or_else(|e| panic!("Failed to run the C compiler `{}`: {}", compiler, e));
+    assert!(status.success(), "Failed to compile {}", source);
+    let status = Command::new(&archiver)
+        .arg("crs")
+        .arg(out_dir.join("libwayland_client_c_abi.a"))
+        .arg(&object)
+        .status()
+        .unwrap_or_else(|e| panic!("Failed to run the archiver `{}`: {}", archiver, e));
+    assert!(status.success(), "Failed to archive {}", source);
+
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+    println!("cargo:rustc-link-lib=static=wayland_client_c_abi");
 }
@@ -0,0 +1,83 @@
+extern crate wayland_client;
+extern crate wayland_commons;
+extern crate wayland_protocols;
+
+use std::env;
+use std::io::Write;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+
+use wayland_client::protocol::*;
+use wayland_commons::sniffer::{forward, LogFormat, Sniffer};
+use wayland_commons::socket::Socket;
+use wayland_protocols::xdg_shell::client::xdg_wm_base;
+
+// A proxy logging the traffic between the clients and the compositor
+//
+// It listens on the socket given as first argument (`wayland-sniffer` by default) and
+// forwards the connections to the compositor of `WAYLAND_DISPLAY`. Run a client with
+// `WAYLAND_DISPLAY=wayland-sniffer` to see its messages, pass `--json` to log them
+// as JSON instead.
+
+fn main() {
+    let mut format = LogFormat::Text;
+    let mut name = "wayland-sniffer".to_owned();
+    for arg in env::args().skip(1) {
+        if arg == "--json" {
+            format = LogFormat::Json;
+        } else {
+            name = arg;
+        }
+    }
+
+    let runtime_dir: PathBuf = env::var_os("XDG_RUNTIME_DIR").unwrap().into();
+    let server_path =
+        runtime_dir.join(env::var_os("WAYLAND_DISPLAY").unwrap_or_else(|| "wayland-0".into()));
+    let listen_path = runtime_dir.join(&name);
+
+    // Remove the socket left over by a previous run
+    let _ = std::fs::remove_file(&listen_path);
+    let listener = UnixListener::bind(&listen_path).unwrap();
+    eprintln!("Listening on {}", listen_path.display());
+
+    for (client_number, client) in listener.incoming().enumerate() {
+        let client = client.unwrap();
+        let server = match UnixStream::connect(&server_path) {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Failed to connect to the compositor: {}", e);
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            let client = unsafe { Socket::from_raw_fd(client.into_raw_fd()) };
+            let server = unsafe { Socket::from_raw_fd(server.into_raw_fd()) };
+
+            // The sniffer needs the descriptions of the interfaces to decode their messages,
+            // the globals are created by `wl_registry.bind` and must be registered
+            let mut sniffer = Sniffer::new::<wl_display::WlDisplay>();
+            sniffer.register::<wl_compositor::WlCompositor>();
+            sniffer.register::<wl_subcompositor::WlSubcompositor>();
+            sniffer.register::<wl_data_device_manager::WlDataDeviceManager>();
+            sniffer.register::<wl_shm::WlShm>();
+            sniffer.register::<wl_seat::WlSeat>();
+            sniffer.register::<wl_output::WlOutput>();
+            sniffer.register::<wl_shell::WlShell>();
+            sniffer.register::<xdg_wm_base::XdgWmBase>();
+
+            let ret = forward(&client, &server, &mut sniffer, |msg| {
+                let stdout = std::io::stdout();
+                let mut stdout = stdout.lock();
+                if format == LogFormat::Text {
+                    let _ = write!(stdout, "client {}: ", client_number);
+                }
+                let _ = format.write(&msg, &mut stdout);
+            });
+            if let Err(e) = ret {
+                eprintln!("client {}: connection error: {}", client_number, e);
+            }
+        });
+    }
+}
@@ -5,22 +5,24 @@ use std::ops::Deref;
 use std::os::unix::io::{IntoRawFd, RawFd};
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use nix::fcntl;
 
-use crate::{EventQueue, Proxy};
+use crate::{
+    AnonymousObject, Argument, DispatchData, EventQueue, Main, Proxy, QueueToken, RawEvent,
+};
 
 use crate::imp::DisplayInner;
 
-#[cfg(feature = "use_system_lib")]
-use wayland_sys::client::wl_display;
+use wayland_sys::client::{wl_display, wl_event_queue};
 
 /// Enum representing the possible reasons why connecting to the wayland server failed
 #[derive(Debug)]
 pub enum ConnectError {
     /// The library was compiled with the `dlopen` feature, and the `libwayland-client.so`
-    /// library could not be found at runtime
+    /// library could not be found at runtime, or `Backend::Native` was requested without the
+    /// `use_system_lib` feature
     NoWaylandLib,
     /// The `XDG_RUNTIME_DIR` variable is not set while it should be
     XdgRuntimeDirNotSet,
@@ -30,7 +32,7 @@ pub enum ConnectError {
     NoCompositorListening,
     /// The provided socket name is invalid
     InvalidName,
-    /// The FD provided in `WAYLAND_SOCKET` was invalid
+    /// The FD provided in `WAYLAND_SOCKET`, or to `Display::from_fd_with_options()`, was invalid
     InvalidFd,
 }
 
@@ -50,6 +52,86 @@ impl ::std::fmt::Display for ConnectError {
     }
 }
 
+/// The implementation of the protocol used by a connection
+///
+/// The rust implementation is always available, the system library only with the
+/// `use_system_lib` cargo feature. See `Display::connect_with_backend()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The rust implementation of the protocol
+    Rust,
+    /// The `libwayland-client.so` system library
+    Native,
+}
+
+impl Default for Backend {
+    /// The system library with the `use_system_lib` cargo feature, the rust implementation
+    /// otherwise
+    fn default() -> Backend {
+        #[cfg(feature = "use_system_lib")]
+        {
+            Backend::Native
+        }
+        #[cfg(not(feature = "use_system_lib"))]
+        {
+            Backend::Rust
+        }
+    }
+}
+
+/// Options for starting a wayland connection from a file descriptor
+///
+/// They are given to `Display::from_fd_with_options()` and
+/// `Display::connect_to_wayland_socket()`.
+#[derive(Clone, Debug)]
+pub struct ConnectOptions {
+    cloexec: bool,
+    owned: bool,
+    backend: Backend,
+}
+
+impl ConnectOptions {
+    /// Default options
+    ///
+    /// The close-on-exec flag is set on the file descriptor, the `Display` takes its
+    /// ownership and uses the default `Backend`.
+    pub fn new() -> ConnectOptions {
+        ConnectOptions { cloexec: true, owned: true, backend: Backend::default() }
+    }
+
+    /// Whether to set or clear the close-on-exec flag of the file descriptor
+    ///
+    /// Clearing it lets child processes inherit the connection.
+    pub fn cloexec(mut self, cloexec: bool) -> ConnectOptions {
+        self.cloexec = cloexec;
+        self
+    }
+
+    /// Whether the `Display` takes ownership of the file descriptor
+    ///
+    /// If not, the connection uses a duplicate of the file descriptor, which stays open and
+    /// owned by the caller once the `Display` is dropped or if the connection fails.
+    pub fn owned(mut self, owned: bool) -> ConnectOptions {
+        self.owned = owned;
+        self
+    }
+
+    /// The implementation used by the connection
+    ///
+    /// Connecting with `Backend::Native` fails with `ConnectError::NoWaylandLib` if the
+    /// `use_system_lib` cargo feature is not activated.
+    pub fn backend(mut self, backend: Backend) -> ConnectOptions {
+        self.backend = backend;
+        self
+    }
+}
+
+impl Default for ConnectOptions {
+    fn default() -> ConnectOptions {
+        ConnectOptions::new()
+    }
+}
+
 /// A protocol error
 ///
 /// This kind of error is generated by the server if your client didn't respect
@@ -88,6 +170,185 @@ impl ::std::fmt::Display for ProtocolError {
     }
 }
 
+/// The error of a failed dispatching or flushing of a connection
+///
+/// The two first variants are fatal errors of the connection, the one reported first is
+/// also given by `Display::last_error()`.
+#[derive(Debug)]
+pub enum DispatchError {
+    /// The server sent a protocol error and closed the connection
+    ///
+    /// This is most likely a bug of the app, which should be reported as such.
+    Protocol(ProtocolError),
+    /// The connection itself failed, for example because the server died
+    ///
+    /// Reconnecting may help. This also covers the non-fatal `WouldBlock` errors of
+    /// flushing a full socket.
+    Backend(io::Error),
+    /// A message could not be dispatched, as it does not match the known state of the objects
+    ///
+    /// This is a bug of either the server or this library.
+    InvalidState(String),
+}
+
+impl DispatchError {
+    /// The kind of the `io::Error` this error converts into
+    ///
+    /// This is `ErrorKind::Other` for protocol errors and invalid states.
+    pub fn kind(&self) -> io::ErrorKind {
+        match *self {
+            DispatchError::Backend(ref e) => e.kind(),
+            DispatchError::Protocol(_) | DispatchError::InvalidState(_) => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl ::std::error::Error for DispatchError {
+    fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+        match *self {
+            DispatchError::Protocol(ref e) => Some(e),
+            DispatchError::Backend(ref e) => Some(e),
+            DispatchError::InvalidState(_) => None,
+        }
+    }
+}
+
+impl ::std::fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match *self {
+            DispatchError::Protocol(ref e) => write!(f, "{}", e),
+            DispatchError::Backend(ref e) => write!(f, "Wayland connection error: {}", e),
+            DispatchError::InvalidState(ref msg) => write!(f, "Invalid state: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for DispatchError {
+    /// Errors wrapping a `ProtocolError` are converted back into `Protocol`
+    fn from(e: io::Error) -> DispatchError {
+        if e.get_ref().map(|inner| inner.is::<ProtocolError>()).unwrap_or(false) {
+            let inner = e.into_inner().unwrap();
+            DispatchError::Protocol(*inner.downcast::<ProtocolError>().unwrap())
+        } else {
+            DispatchError::Backend(e)
+        }
+    }
+}
+
+impl From<DispatchError> for io::Error {
+    /// A `ProtocolError` is wrapped into an `io::Error` of kind `Other`
+    fn from(e: DispatchError) -> io::Error {
+        match e {
+            DispatchError::Protocol(e) => io::Error::new(io::ErrorKind::Other, e),
+            DispatchError::Backend(e) => e,
+            DispatchError::InvalidState(msg) => io::Error::new(io::ErrorKind::Other, msg),
+        }
+    }
+}
+
+/// The state of a connection to a wayland server
+///
+/// As returned by `Display::state()`. A connection goes through these states in order, and
+/// never comes back to a previous state, unless `Display::reconnect()` is used. Once it is
+/// failed (`ErrorDeferred` or `Dead`), all the methods flushing, reading or dispatching events
+/// report the error given by `ConnectionState::error()`, and the requests sent are discarded.
+#[derive(Clone, Debug)]
+pub enum ConnectionState {
+    /// No message has been received from the server yet
+    Connecting,
+    /// The connection is up and running
+    Ready,
+    /// The server sent a protocol error and closed the connection
+    ///
+    /// The events received before the error can still be dispatched, the error is reported
+    /// by the dispatching methods once there are none left.
+    ErrorDeferred(ProtocolError),
+    /// The connection was lost, for the given reason
+    ///
+    /// If the compositor died or closed the connection, this is `BrokenPipe` or
+    /// `ConnectionReset`.
+    Dead(io::ErrorKind),
+}
+
+impl ConnectionState {
+    /// Whether the connection failed
+    pub fn is_failed(&self) -> bool {
+        match *self {
+            ConnectionState::Connecting | ConnectionState::Ready => false,
+            ConnectionState::ErrorDeferred(_) | ConnectionState::Dead(_) => true,
+        }
+    }
+
+    /// The error reported by the methods of a failed connection
+    pub fn error(&self) -> Option<DispatchError> {
+        match *self {
+            ConnectionState::Connecting | ConnectionState::Ready => None,
+            ConnectionState::ErrorDeferred(ref e) => Some(DispatchError::Protocol(e.clone())),
+            ConnectionState::Dead(kind) => Some(DispatchError::Backend(io::Error::new(
+                kind,
+                "The wayland connection was lost.",
+            ))),
+        }
+    }
+}
+
+/// The progress of a flush of the outgoing buffer
+///
+/// As returned by `Display::flush()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FlushProgress {
+    /// The number of bytes written to the socket by this flush
+    pub written: usize,
+    /// The number of bytes still waiting in the outgoing buffer
+    ///
+    /// If it is not zero, the socket was full and you should flush again once
+    /// it is writable.
+    pub remaining: usize,
+}
+
+impl FlushProgress {
+    /// Whether all the pending requests were written to the socket
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// How the events received for destroyed proxies are handled
+///
+/// When a proxy is destroyed while the server is sending events to it, the events already in
+/// flight still arrive, and find a destroyed proxy. The completion of the destruction is only
+/// known once the server acknowledges it. Set with `Display::set_zombie_policy()`.
+pub enum ZombiePolicy {
+    /// Discard the events, closing the file descriptors they carry
+    ///
+    /// This is the default.
+    Discard,
+    /// Print the events to stderr, then discard them
+    Log,
+    /// Give the events to a callback, along with the id of their destroyed object
+    ///
+    /// The callback takes ownership of the file descriptors of the events. It is invoked
+    /// from the dispatching of the event queue the proxy was attached to, the objects
+    /// created by these events are destroyed as well.
+    Deliver(Box<dyn FnMut(u32, RawEvent) + Send>),
+}
+
+/// Description of an object of the connection
+///
+/// As returned by `Display::objects()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectInfo {
+    /// The protocol id of the object
+    pub id: u32,
+    /// The interface of the object
+    pub interface: &'static str,
+    /// The version of the object
+    pub version: u32,
+    /// Whether the object is still alive, or has been destroyed and is just
+    /// waiting for its id to be released
+    pub alive: bool,
+}
+
 /// A connection to a wayland server
 ///
 /// This object both represent the connection to the server and contains the
@@ -102,6 +363,26 @@ impl ::std::fmt::Display for ProtocolError {
 #[derive(Clone)]
 pub struct Display {
     pub(crate) inner: Arc<DisplayInner>,
+    unhandled_sink: Arc<Mutex<Option<UnhandledSink>>>,
+}
+
+type UnhandledSink = Box<dyn FnMut(&UnhandledEvent) + Send>;
+
+/// An event received by an object that has no filter assigned
+///
+/// See `Display::set_unhandled_event_sink()`.
+#[derive(Debug)]
+pub struct UnhandledEvent<'a> {
+    /// Interface of the object
+    pub interface: &'static str,
+    /// Opcode of the event
+    pub opcode: u16,
+    /// Name of the event
+    pub name: &'static str,
+    /// Arguments of the event
+    pub args: &'a [Argument],
+    /// Protocol id of the object
+    pub id: u32,
 }
 
 impl Display {
@@ -115,38 +396,45 @@ impl Display {
     ///
     /// This requires the `XDG_RUNTIME_DIR` variable to be properly set.
     pub fn connect_to_env() -> Result<Display, ConnectError> {
-        if let Ok(txt) = env::var("WAYLAND_SOCKET") {
+        Display::connect_with_backend(Backend::default())
+    }
+
+    /// Attempt to connect to a wayland server from the environment, with given implementation
+    ///
+    /// This behaves like `connect_to_env()`, the connection using `backend`. With the
+    /// `use_system_lib` cargo feature, this allows running the same code with both
+    /// implementations, to compare them.
+    ///
+    /// Returns `ConnectError::NoWaylandLib` for `Backend::Native` if the `use_system_lib`
+    /// feature is not activated.
+    pub fn connect_with_backend(backend: Backend) -> Result<Display, ConnectError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("connect_to_env", ?backend).entered();
+        if env::var_os("WAYLAND_SOCKET").is_some() {
             // We should connect to the provided WAYLAND_SOCKET
-            let fd = txt.parse::<i32>().map_err(|_| ConnectError::InvalidFd)?;
-            // remove the variable so any child processes don't see it
-            env::remove_var("WAYLAND_SOCKET");
-            // set the CLOEXEC flag on this FD
-            let flags = fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFD);
-            let result = flags
-                .map(|f| fcntl::FdFlag::from_bits(f).unwrap() | fcntl::FdFlag::FD_CLOEXEC)
-                .and_then(|f| fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFD(f)));
-            match result {
-                Ok(_) => {
-                    // setting the O_CLOEXEC worked
-                    unsafe { Display::from_fd(fd) }
-                }
-                Err(_) => {
-                    // something went wrong in F_GETFD or F_SETFD
-                    let _ = ::nix::unistd::close(fd);
-                    Err(ConnectError::InvalidFd)
-                }
-            }
+            Display::connect_to_wayland_socket(&ConnectOptions::new().backend(backend))
         } else {
-            let mut socket_path = env::var_os("XDG_RUNTIME_DIR")
-                .map(Into::<PathBuf>::into)
-                .ok_or(ConnectError::XdgRuntimeDirNotSet)?;
-            socket_path
-                .push(env::var_os("WAYLAND_DISPLAY").ok_or(ConnectError::NoCompositorListening)?);
-
-            let socket = UnixStream::connect(socket_path)
-                .map_err(|_| ConnectError::NoCompositorListening)?;
-            unsafe { Display::from_fd(socket.into_raw_fd()) }
+            Display::connect_to_path(socket_path(None)?, backend)
+        }
+    }
+
+    /// Attempt to connect to a wayland server using the FD number in `WAYLAND_SOCKET`
+    ///
+    /// This is how a compositor spawning a client hands it its connection. The variable is
+    /// removed from the environment, so that child processes don't see it, and the FD is
+    /// then used according to `options`, as by `from_fd_with_options()`.
+    ///
+    /// Returns `ConnectError::InvalidFd` if the variable is not set or invalid.
+    pub fn connect_to_wayland_socket(options: &ConnectOptions) -> Result<Display, ConnectError> {
+        let txt = env::var("WAYLAND_SOCKET").map_err(|_| ConnectError::InvalidFd)?;
+        let fd = txt.parse::<i32>().map_err(|_| ConnectError::InvalidFd)?;
+        // remove the variable so any child processes don't see it
+        env::remove_var("WAYLAND_SOCKET");
+        // the FD was given to this process, but is only valid if it is actually open
+        if fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFD).is_err() {
+            return Err(ConnectError::InvalidFd);
         }
+        unsafe { Display::from_fd_with_options(fd, options) }
     }
 
     /// Attempt to connect to a wayland server socket with given name
@@ -156,14 +444,18 @@ impl Display {
     ///
     /// This requires the `XDG_RUNTIME_DIR` variable to be properly set.
     pub fn connect_to_name<S: Into<OsString>>(name: S) -> Result<Display, ConnectError> {
-        let mut socket_path = env::var_os("XDG_RUNTIME_DIR")
-            .map(Into::<PathBuf>::into)
-            .ok_or(ConnectError::XdgRuntimeDirNotSet)?;
-        socket_path.push(name.into());
+        let name = name.into();
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("connect_to_name", name = ?name).entered();
+        Display::connect_to_path(socket_path(Some(name))?, Backend::default())
+    }
 
+    fn connect_to_path(socket_path: PathBuf, backend: Backend) -> Result<Display, ConnectError> {
         let socket =
-            UnixStream::connect(socket_path).map_err(|_| ConnectError::NoCompositorListening)?;
-        unsafe { Display::from_fd(socket.into_raw_fd()) }
+            UnixStream::connect(&socket_path).map_err(|_| ConnectError::NoCompositorListening)?;
+        let display = unsafe { Display::from_fd_with_backend(socket.into_raw_fd(), backend)? };
+        display.inner.set_socket_path(socket_path);
+        Ok(display)
     }
 
     /// Attempt to use an already connected unix socket on given FD to start a wayland connection
@@ -176,7 +468,126 @@ impl Display {
     ///
     /// The file descriptor must be associated to a connected unix socket.
     pub unsafe fn from_fd(fd: RawFd) -> Result<Display, ConnectError> {
-        Ok(Display { inner: DisplayInner::from_fd(fd)? })
+        Display::from_fd_with_backend(fd, Backend::default())
+    }
+
+    unsafe fn from_fd_with_backend(fd: RawFd, backend: Backend) -> Result<Display, ConnectError> {
+        let ret = connect_inner(fd, backend);
+        #[cfg(feature = "tracing")]
+        match ret {
+            Ok(_) => tracing::debug!(fd, "connected to the wayland server"),
+            Err(ref e) => {
+                tracing::debug!(fd, error = %e, "failed to connect to the wayland server")
+            }
+        }
+        Ok(Display { inner: ret?, unhandled_sink: Arc::new(Mutex::new(None)) })
+    }
+
+    /// Attempt to use an already connected unix socket on given FD, with options
+    ///
+    /// This behaves like `from_fd()`, with the close-on-exec flag and the ownership of the FD
+    /// controlled by `options`. If the Display takes ownership of the FD, it is closed if the
+    /// connection fails.
+    ///
+    /// # Safety
+    ///
+    /// The file descriptor must be associated to a connected unix socket.
+    pub unsafe fn from_fd_with_options(
+        fd: RawFd,
+        options: &ConnectOptions,
+    ) -> Result<Display, ConnectError> {
+        let fd = if options.owned {
+            fd
+        } else {
+            let arg = if options.cloexec {
+                fcntl::FcntlArg::F_DUPFD_CLOEXEC(0)
+            } else {
+                fcntl::FcntlArg::F_DUPFD(0)
+            };
+            fcntl::fcntl(fd, arg).map_err(|_| ConnectError::InvalidFd)?
+        };
+        let mut flags = match fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFD) {
+            Ok(f) => fcntl::FdFlag::from_bits_truncate(f),
+            Err(_) => {
+                let _ = ::nix::unistd::close(fd);
+                return Err(ConnectError::InvalidFd);
+            }
+        };
+        flags.set(fcntl::FdFlag::FD_CLOEXEC, options.cloexec);
+        if fcntl::fcntl(fd, fcntl::FcntlArg::F_SETFD(flags)).is_err() {
+            let _ = ::nix::unistd::close(fd);
+            return Err(ConnectError::InvalidFd);
+        }
+        Display::from_fd_with_backend(fd, options.backend)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    /// Attempt to connect to a wayland server listening on an abstract unix socket
+    ///
+    /// Abstract sockets live outside of the filesystem, and are notably used by nested
+    /// compositors and sandboxes without access to `XDG_RUNTIME_DIR`. The name is given
+    /// without its leading null byte.
+    ///
+    /// This is only available on Linux and Android.
+    pub fn connect_to_abstract<S: AsRef<[u8]>>(name: S) -> Result<Display, ConnectError> {
+        use nix::sys::socket;
+
+        let addr =
+            socket::UnixAddr::new_abstract(name.as_ref()).map_err(|_| ConnectError::InvalidName)?;
+        let fd = socket::socket(
+            socket::AddressFamily::Unix,
+            socket::SockType::Stream,
+            socket::SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .map_err(|_| ConnectError::NoCompositorListening)?;
+        if socket::connect(fd, &socket::SockAddr::Unix(addr)).is_err() {
+            let _ = ::nix::unistd::close(fd);
+            return Err(ConnectError::NoCompositorListening);
+        }
+        unsafe { Display::from_fd(fd) }
+    }
+
+    /// Connect again to the server, typically after the compositor restarted
+    ///
+    /// This connects to the socket this `Display` was initially connected to, or to the one
+    /// designated by `WAYLAND_DISPLAY` if it was created from a file descriptor. See
+    /// `reconnect_from_fd()` for what happens to the state of the connection.
+    ///
+    /// On failure, the state of the current connection is left unchanged.
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Native`.
+    pub fn reconnect(&self) -> Result<(), ConnectError> {
+        let socket_path = match self.inner.socket_path() {
+            Some(path) => path,
+            None => socket_path(None)?,
+        };
+        let socket =
+            UnixStream::connect(&socket_path).map_err(|_| ConnectError::NoCompositorListening)?;
+        unsafe { self.reconnect_from_fd(socket.into_raw_fd()) };
+        self.inner.set_socket_path(socket_path);
+        Ok(())
+    }
+
+    /// Replace the connection of this `Display` with an already connected unix socket
+    ///
+    /// The `Display`, its clones and the existing `EventQueue`s keep working and now use the
+    /// new connection, whose state is back to `Connecting`. All the other objects belong to the
+    /// old connection: they are now dead, and their undispatched events are discarded. The
+    /// globals must thus be bound again, this can be done from the callbacks registered with
+    /// `EventQueue::add_reconnect_handler()`.
+    ///
+    /// Will take ownership of the FD.
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Native`.
+    ///
+    /// # Safety
+    ///
+    /// The file descriptor must be associated to a connected unix socket.
+    pub unsafe fn reconnect_from_fd(&self, fd: RawFd) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("reconnect", fd).entered();
+        self.inner.reconnect(fd);
     }
 
     /// Non-blocking write to the server
@@ -184,20 +595,64 @@ impl Display {
     /// Outgoing messages to the server are buffered by the library for efficiency. This method
     /// flushes the internal buffer to the server socket.
     ///
-    /// Will write as many pending requests as possible to the server socket. Never blocks: if not all
-    /// requests could be written, will return an io error `WouldBlock`.
+    /// Will write as many pending requests as possible to the server socket, and return how many
+    /// bytes were written. Never blocks: if the socket is full before all requests could be written,
+    /// the returned `FlushProgress` has some bytes `remaining`, or a `Backend` error of kind
+    /// `WouldBlock` is returned if nothing could be written at all.
+    ///
+    /// With the system library, a partial write is reported as a `WouldBlock` error, as
+    /// `libwayland-client` does not tell how much was written.
     ///
     /// This function is identical to `EventQueue::flush`
-    pub fn flush(&self) -> io::Result<()> {
+    pub fn flush(&self) -> Result<FlushProgress, DispatchError> {
         self.inner.flush()
     }
 
+    /// The implementation used by this connection
+    pub fn backend(&self) -> Backend {
+        self.inner.backend()
+    }
+
     /// Create a new event queue associated with this wayland connection
     pub fn create_event_queue(&self) -> EventQueue {
         let evq_inner = DisplayInner::create_event_queue(&self.inner);
         EventQueue::new(evq_inner, self.clone())
     }
 
+    /// Retrieve the list of the globals advertised by the server
+    ///
+    /// This creates a registry and does a roundtrip with the server on a dedicated
+    /// event queue, so the events pending on your own queues are not dispatched. The
+    /// globals bound with the returned snapshot are handled by the event queue of `token`.
+    pub fn get_registry_snapshot(
+        &self,
+        token: QueueToken,
+    ) -> Result<crate::RegistrySnapshot, DispatchError> {
+        crate::RegistrySnapshot::take(self, token)
+    }
+
+    /// Create an EventQueue from an event queue created by another library
+    ///
+    /// This allows dispatching, from this crate, the events of the proxies another library
+    /// created on its own queue, or attaching this crate's proxies to it. The queue remains
+    /// owned by its creator: it is not destroyed when the `EventQueue` is dropped.
+    ///
+    /// # Safety
+    ///
+    /// The provided pointer must point to a valid `wl_event_queue` of this connection, which
+    /// must not be destroyed as long as the `EventQueue`, its tokens or the proxies attached
+    /// to it are in use.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// not activated, or on a connection using `Backend::Rust`.
+    pub unsafe fn create_event_queue_from_external(
+        &self,
+        queue: *mut wl_event_queue,
+    ) -> EventQueue {
+        let evq_inner = DisplayInner::create_event_queue_from_external(&self.inner, queue);
+        EventQueue::new(evq_inner, self.clone())
+    }
+
     /// Retrieve the last protocol error if any occured
     ///
     /// If your client does not respect some part of a protocol it is using, the server
@@ -212,6 +667,85 @@ impl Display {
         self.inner.protocol_error()
     }
 
+    /// Retrieve the fatal error of the connection, if any
+    ///
+    /// This is the first error that made the connection fail, and that all the dispatching
+    /// methods report since, like `wl_display_get_error()` does.
+    pub fn last_error(&self) -> Option<DispatchError> {
+        self.state().error()
+    }
+
+    /// Retrieve the current state of the connection
+    ///
+    /// With the system library, the connection is considered `Ready` from the start.
+    pub fn state(&self) -> ConnectionState {
+        self.inner.state()
+    }
+
+    /// Set a callback invoked each time the state of the connection changes
+    ///
+    /// The callback is invoked while the connection is locked, and must not use it.
+    /// This replaces any previously set callback.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn set_state_listener<F>(&self, listener: F)
+    where
+        F: FnMut(&ConnectionState) + Send + 'static,
+    {
+        self.inner.set_state_listener(Some(Box::new(listener)))
+    }
+
+    /// Remove the callback set with `set_state_listener()`
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn clear_state_listener(&self) {
+        self.inner.set_state_listener(None)
+    }
+
+    /// Set a callback receiving the events that no filter handled
+    ///
+    /// The events received by objects that have no filter assigned are given to this callback,
+    /// right before the fallback closure of the event queue dispatching them, which makes it
+    /// possible to log or assert on the events an application forgot to handle, whatever the
+    /// event queue. This replaces any previously set callback.
+    ///
+    /// The callback is shared by all the clones of this `Display`, and is invoked while it is
+    /// locked: it must not set or clear the callback.
+    pub fn set_unhandled_event_sink<F>(&self, sink: F)
+    where
+        F: FnMut(&UnhandledEvent) + Send + 'static,
+    {
+        *self.unhandled_sink.lock().unwrap() = Some(Box::new(sink));
+    }
+
+    /// Remove the callback set with `set_unhandled_event_sink()`
+    pub fn clear_unhandled_event_sink(&self) {
+        *self.unhandled_sink.lock().unwrap() = None;
+    }
+
+    // Give the events reaching the fallback of an event queue to the unhandled event sink first
+    pub(crate) fn with_unhandled_sink<F>(
+        &self,
+        mut fallback: F,
+    ) -> impl FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>)
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        let sink = self.unhandled_sink.clone();
+        move |event, object, data| {
+            if let Some(ref mut sink) = *sink.lock().unwrap() {
+                sink(&UnhandledEvent {
+                    interface: event.interface,
+                    opcode: event.opcode,
+                    name: event.name,
+                    args: &event.args,
+                    id: object.as_ref().id(),
+                });
+            }
+            fallback(event, object, data)
+        }
+    }
+
     /// Retrieve the file descriptor associated with the wayland socket
     ///
     /// This FD should only be used to integrate into a polling mechanism, and should
@@ -220,6 +754,141 @@ impl Display {
         self.inner.get_connection_fd()
     }
 
+    /// Get a snapshot of the objects currently known to this connection
+    ///
+    /// This lists all the ids in use, ordered by id, with their interface, version and
+    /// liveness. Objects that have been destroyed but whose id has not yet been released by
+    /// the server are included and marked as not alive. This can be used to find the objects
+    /// that are never destroyed, like forgotten `wl_callback`s.
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Native`.
+    pub fn enumerate_objects(&self) -> Vec<ObjectInfo> {
+        self.inner.objects()
+    }
+
+    /// Alias of `enumerate_objects()`
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Native`.
+    pub fn objects(&self) -> Vec<ObjectInfo> {
+        self.enumerate_objects()
+    }
+
+    /// Start recording all messages exchanged on this connection
+    ///
+    /// The messages are recorded as they are sent and as they are read from the socket,
+    /// see `wayland_commons::capture` for details. Does nothing if a capture is
+    /// already running.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn start_capture(&self) {
+        self.inner.start_capture()
+    }
+
+    /// Stop the running capture and retrieve its contents
+    ///
+    /// Returns `None` if no capture was running.
+    ///
+    /// NOTE: This method always returns `None` on a connection using `Backend::Native`.
+    pub fn stop_capture(&self) -> Option<wayland_commons::capture::Capture> {
+        self.inner.stop_capture()
+    }
+
+    /// Limit the number of file descriptors held in undispatched events
+    ///
+    /// Events carrying file descriptors keep them open until they are dispatched. If an event
+    /// queue stops being dispatched, this can exhaust the fds available to the process. Once
+    /// `limit` fds are held by the events waiting in the queues of this connection, it stops
+    /// reading from the socket: the pending messages (and their fds) stay in the socket and
+    /// reading events fails with a `WouldBlock` error until enough events are dispatched.
+    ///
+    /// `on_pressure` is invoked with the number of held fds each time the limit is reached.
+    /// It is invoked while the connection is locked, and must not use it.
+    ///
+    /// This replaces any previously set budget. There is no limit by default.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn set_fd_budget<F>(&self, limit: usize, on_pressure: F)
+    where
+        F: FnMut(usize) + Send + 'static,
+    {
+        self.inner.set_fd_budget(Some(crate::imp::FdBudget::new(limit, Box::new(on_pressure))))
+    }
+
+    /// Remove the limit set with `set_fd_budget()`
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn clear_fd_budget(&self) {
+        self.inner.set_fd_budget(None)
+    }
+
+    /// Number of file descriptors held in the events waiting to be dispatched
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Native`.
+    pub fn pending_fds(&self) -> usize {
+        self.inner.pending_fds()
+    }
+
+    /// Set how the events received for destroyed proxies are handled
+    ///
+    /// See `ZombiePolicy` for details. The events are discarded by default.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`, the events
+    /// are then discarded.
+    pub fn set_zombie_policy(&self, policy: ZombiePolicy) {
+        self.inner.set_zombie_policy(policy)
+    }
+
+    /// Number of events received for destroyed proxies so far
+    ///
+    /// This counts the events handled by the `ZombiePolicy`, whether they were discarded,
+    /// logged or delivered.
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Native`.
+    pub fn zombie_events(&self) -> usize {
+        self.inner.zombie_events()
+    }
+
+    /// Set how strictly the events of the server are validated
+    ///
+    /// With `Strictness::Strict`, events containing invalid UTF-8 strings, null arguments
+    /// where the protocol does not allow them or invalid enum values are rejected as a
+    /// protocol error, which is fatal to the connection. The default is `Strictness::Lenient`,
+    /// which tolerates such events.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn set_strictness(&self, strictness: crate::Strictness) {
+        self.inner.set_strictness(strictness)
+    }
+
+    /// Set the maximum size of the messages exchanged with the server
+    ///
+    /// By default, messages are limited to 4096 bytes like in libwayland. Raising this limit
+    /// allows sending and receiving larger messages, for example big arrays, but the server
+    /// must accept them as well. The size is in bytes, header included, and is capped at
+    /// `wayland_commons::wire::MAX_MESSAGE_SIZE`. Requests larger than the limit fail with
+    /// `E2BIG`, and events larger than the limit are a fatal protocol error.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn set_max_message_size(&self, size: usize) {
+        self.inner.set_max_message_size(size)
+    }
+
+    /// Let the threads send their requests without contending on the connection
+    ///
+    /// By default, sending a request locks the connection to write it to the socket buffer.
+    /// Once enabled, the requests that neither create nor destroy objects and carry no file
+    /// descriptor are instead staged in a buffer specific to the sending thread. The staged
+    /// requests are written to the socket, in the order they were sent, on the next flush or
+    /// when a request goes through the connection lock, so the order of the requests is the
+    /// same as without staging.
+    ///
+    /// Disabling it writes the requests staged so far to the socket buffer.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn set_request_staging(&self, enabled: bool) {
+        self.inner.set_request_staging(enabled)
+    }
+
     #[cfg(feature = "use_system_lib")]
     /// Create a Display and from an external display
     ///
@@ -233,22 +902,59 @@ impl Display {
     ///
     /// The provided pointer must point to a valid `wl_display` from `libwayland-client`
     pub unsafe fn from_external_display(display_ptr: *mut wl_display) -> Display {
-        Display { inner: DisplayInner::from_external(display_ptr) }
+        Display {
+            inner: DisplayInner::from_external(display_ptr),
+            unhandled_sink: Arc::new(Mutex::new(None)),
+        }
     }
 
-    #[cfg(feature = "use_system_lib")]
     /// Retrieve the `wl_display` pointer
     ///
     /// If this `Display` was created from an external `wl_display`, its `c_ptr()` method will
     /// return a wrapper to the actual display. While this is perfectly good as a `wl_proxy`
     /// pointer, to send requests, this is not the actual `wl_display` and cannot be used as such.
     ///
-    /// This method will give you the `wl_display`.
+    /// This method will give you the `wl_display`. On a connection using `Backend::Rust`, this
+    /// is the `wl_display` of the compatibility layer of the `c_abi` cargo feature.
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Rust` while the
+    /// `c_abi` feature is not activated.
     pub fn get_display_ptr(&self) -> *mut wl_display {
         self.inner.ptr()
     }
 }
 
+// Start the connection with the requested implementation, taking ownership of the FD
+unsafe fn connect_inner(fd: RawFd, backend: Backend) -> Result<Arc<DisplayInner>, ConnectError> {
+    #[cfg(feature = "use_system_lib")]
+    {
+        DisplayInner::from_fd(fd, backend)
+    }
+    #[cfg(not(feature = "use_system_lib"))]
+    {
+        match backend {
+            Backend::Rust => DisplayInner::from_fd(fd),
+            Backend::Native => {
+                let _ = ::nix::unistd::close(fd);
+                Err(ConnectError::NoWaylandLib)
+            }
+        }
+    }
+}
+
+// The path of the socket with given name in `XDG_RUNTIME_DIR`, defaulting to `WAYLAND_DISPLAY`
+fn socket_path(name: Option<OsString>) -> Result<PathBuf, ConnectError> {
+    let mut socket_path = env::var_os("XDG_RUNTIME_DIR")
+        .map(Into::<PathBuf>::into)
+        .ok_or(ConnectError::XdgRuntimeDirNotSet)?;
+    let name = match name {
+        Some(name) => name,
+        None => env::var_os("WAYLAND_DISPLAY").ok_or(ConnectError::NoCompositorListening)?,
+    };
+    socket_path.push(name);
+    Ok(socket_path)
+}
+
 impl Deref for Display {
     type Target = Proxy<crate::protocol::wl_display::WlDisplay>;
     fn deref(&self) -> &Proxy<crate::protocol::wl_display::WlDisplay> {
@@ -0,0 +1,592 @@
+//! Runtime selection between the rust implementation and the system library
+//!
+//! Each handle wraps the handle of the implementation its connection was opened with, and
+//! forwards to it. The objects of a connection are all of the same implementation.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use wayland_commons::capture::Capture;
+use wayland_commons::filter::Filter;
+use wayland_commons::user_data::UserData;
+use wayland_commons::wire::Strictness;
+use wayland_commons::MessageGroup;
+use wayland_sys::client::{wl_display, wl_event_queue, wl_proxy};
+use wayland_sys::common::wl_interface;
+
+use crate::native_lib;
+use crate::protocol::wl_display::WlDisplay;
+use crate::rust_imp;
+use crate::{
+    AnonymousObject, Backend, ConnectError, ConnectionState, DispatchData, DispatchError,
+    DispatchStats, FlushProgress, Interface, Main, ObjectInfo, ProtocolError, Proxy, RawEvent,
+    SlowDispatch, ZombiePolicy,
+};
+
+pub(crate) use crate::rust_imp::FdBudget;
+pub use crate::rust_imp::ProxyMap;
+
+type StateListener = Box<dyn FnMut(&ConnectionState) + Send>;
+type SlowDispatchHook = (Duration, Box<dyn FnMut(SlowDispatch)>);
+
+fn mismatched() -> ! {
+    panic!("[wayland-client] Objects of the rust and native backends cannot be mixed.")
+}
+
+pub(crate) enum DisplayInner {
+    Rust(Arc<rust_imp::DisplayInner>),
+    Native(Arc<native_lib::DisplayInner>),
+}
+
+impl DisplayInner {
+    pub unsafe fn from_fd(fd: RawFd, backend: Backend) -> Result<Arc<DisplayInner>, ConnectError> {
+        Ok(Arc::new(match backend {
+            Backend::Rust => DisplayInner::Rust(rust_imp::DisplayInner::from_fd(fd)?),
+            Backend::Native => DisplayInner::Native(native_lib::DisplayInner::from_fd(fd)?),
+        }))
+    }
+
+    pub(crate) unsafe fn from_external(display_ptr: *mut wl_display) -> Arc<DisplayInner> {
+        Arc::new(DisplayInner::Native(native_lib::DisplayInner::from_external(display_ptr)))
+    }
+
+    pub(crate) fn backend(&self) -> Backend {
+        match *self {
+            DisplayInner::Rust(ref d) => d.backend(),
+            DisplayInner::Native(_) => Backend::Native,
+        }
+    }
+
+    pub(crate) fn ptr(&self) -> *mut wl_display {
+        match *self {
+            DisplayInner::Rust(ref d) => d.ptr(),
+            DisplayInner::Native(ref d) => d.ptr(),
+        }
+    }
+
+    pub(crate) fn flush(&self) -> Result<FlushProgress, DispatchError> {
+        match *self {
+            DisplayInner::Rust(ref d) => d.flush(),
+            DisplayInner::Native(ref d) => d.flush(),
+        }
+    }
+
+    pub(crate) fn create_event_queue(me: &Arc<DisplayInner>) -> EventQueueInner {
+        match **me {
+            DisplayInner::Rust(ref d) => {
+                EventQueueInner::Rust(rust_imp::DisplayInner::create_event_queue(d))
+            }
+            DisplayInner::Native(ref d) => {
+                EventQueueInner::Native(native_lib::DisplayInner::create_event_queue(d))
+            }
+        }
+    }
+
+    pub(crate) unsafe fn create_event_queue_from_external(
+        me: &Arc<DisplayInner>,
+        queue: *mut wl_event_queue,
+    ) -> EventQueueInner {
+        match **me {
+            DisplayInner::Rust(ref d) => EventQueueInner::Rust(
+                rust_imp::DisplayInner::create_event_queue_from_external(d, queue),
+            ),
+            DisplayInner::Native(ref d) => EventQueueInner::Native(
+                native_lib::DisplayInner::create_event_queue_from_external(d, queue),
+            ),
+        }
+    }
+
+    pub(crate) fn get_proxy(&self) -> &Proxy<WlDisplay> {
+        match *self {
+            DisplayInner::Rust(ref d) => d.get_proxy(),
+            DisplayInner::Native(ref d) => d.get_proxy(),
+        }
+    }
+
+    pub(crate) fn protocol_error(&self) -> Option<ProtocolError> {
+        match *self {
+            DisplayInner::Rust(ref d) => d.protocol_error(),
+            DisplayInner::Native(ref d) => d.protocol_error(),
+        }
+    }
+
+    pub(crate) fn state(&self) -> ConnectionState {
+        match *self {
+            DisplayInner::Rust(ref d) => d.state(),
+            DisplayInner::Native(ref d) => d.state(),
+        }
+    }
+
+    pub(crate) fn set_state_listener(&self, listener: Option<StateListener>) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.set_state_listener(listener),
+            DisplayInner::Native(ref d) => d.set_state_listener(listener),
+        }
+    }
+
+    pub(crate) fn socket_path(&self) -> Option<PathBuf> {
+        match *self {
+            DisplayInner::Rust(ref d) => d.socket_path(),
+            DisplayInner::Native(ref d) => d.socket_path(),
+        }
+    }
+
+    pub(crate) fn set_socket_path(&self, path: PathBuf) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.set_socket_path(path),
+            DisplayInner::Native(ref d) => d.set_socket_path(path),
+        }
+    }
+
+    pub(crate) unsafe fn reconnect(&self, fd: RawFd) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.reconnect(fd),
+            DisplayInner::Native(ref d) => d.reconnect(fd),
+        }
+    }
+
+    pub(crate) fn get_connection_fd(&self) -> RawFd {
+        match *self {
+            DisplayInner::Rust(ref d) => d.get_connection_fd(),
+            DisplayInner::Native(ref d) => d.get_connection_fd(),
+        }
+    }
+
+    pub(crate) fn objects(&self) -> Vec<ObjectInfo> {
+        match *self {
+            DisplayInner::Rust(ref d) => d.objects(),
+            DisplayInner::Native(ref d) => d.objects(),
+        }
+    }
+
+    pub(crate) fn start_capture(&self) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.start_capture(),
+            DisplayInner::Native(ref d) => d.start_capture(),
+        }
+    }
+
+    pub(crate) fn stop_capture(&self) -> Option<Capture> {
+        match *self {
+            DisplayInner::Rust(ref d) => d.stop_capture(),
+            DisplayInner::Native(ref d) => d.stop_capture(),
+        }
+    }
+
+    pub(crate) fn set_fd_budget(&self, budget: Option<FdBudget>) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.set_fd_budget(budget),
+            // the system library gives no control over the fds it holds
+            DisplayInner::Native(_) => {}
+        }
+    }
+
+    pub(crate) fn pending_fds(&self) -> usize {
+        match *self {
+            DisplayInner::Rust(ref d) => d.pending_fds(),
+            DisplayInner::Native(ref d) => d.pending_fds(),
+        }
+    }
+
+    pub(crate) fn set_zombie_policy(&self, policy: ZombiePolicy) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.set_zombie_policy(policy),
+            DisplayInner::Native(ref d) => d.set_zombie_policy(policy),
+        }
+    }
+
+    pub(crate) fn zombie_events(&self) -> usize {
+        match *self {
+            DisplayInner::Rust(ref d) => d.zombie_events(),
+            DisplayInner::Native(ref d) => d.zombie_events(),
+        }
+    }
+
+    pub(crate) fn set_strictness(&self, strictness: Strictness) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.set_strictness(strictness),
+            DisplayInner::Native(ref d) => d.set_strictness(strictness),
+        }
+    }
+
+    pub(crate) fn set_max_message_size(&self, size: usize) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.set_max_message_size(size),
+            DisplayInner::Native(ref d) => d.set_max_message_size(size),
+        }
+    }
+
+    pub(crate) fn set_request_staging(&self, enabled: bool) {
+        match *self {
+            DisplayInner::Rust(ref d) => d.set_request_staging(enabled),
+            DisplayInner::Native(ref d) => d.set_request_staging(enabled),
+        }
+    }
+}
+
+pub(crate) enum EventQueueInner {
+    Rust(rust_imp::EventQueueInner),
+    Native(native_lib::EventQueueInner),
+}
+
+impl EventQueueInner {
+    pub(crate) fn dispatch<F>(&self, data: DispatchData, fallback: F) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.dispatch(data, fallback),
+            EventQueueInner::Native(ref q) => q.dispatch(data, fallback),
+        }
+    }
+
+    pub(crate) fn dispatch_pending<F>(
+        &self,
+        data: DispatchData,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.dispatch_pending(data, fallback),
+            EventQueueInner::Native(ref q) => q.dispatch_pending(data, fallback),
+        }
+    }
+
+    pub(crate) fn dispatch_some<F>(
+        &self,
+        data: DispatchData,
+        max: u32,
+        fallback: F,
+    ) -> Result<(u32, bool), DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.dispatch_some(data, max, fallback),
+            EventQueueInner::Native(ref q) => q.dispatch_some(data, max, fallback),
+        }
+    }
+
+    pub(crate) fn sync_roundtrip<F>(
+        &self,
+        data: DispatchData,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.sync_roundtrip(data, fallback),
+            EventQueueInner::Native(ref q) => q.sync_roundtrip(data, fallback),
+        }
+    }
+
+    pub(crate) fn prepare_read(&self) -> Result<(), ()> {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.prepare_read(),
+            EventQueueInner::Native(ref q) => q.prepare_read(),
+        }
+    }
+
+    pub(crate) fn read_events(&self) -> io::Result<()> {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.read_events(),
+            EventQueueInner::Native(ref q) => q.read_events(),
+        }
+    }
+
+    pub(crate) fn cancel_read(&self) {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.cancel_read(),
+            EventQueueInner::Native(ref q) => q.cancel_read(),
+        }
+    }
+
+    pub(crate) fn take_reconnection(&self) -> bool {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.take_reconnection(),
+            EventQueueInner::Native(ref q) => q.take_reconnection(),
+        }
+    }
+
+    pub(crate) fn start_stats(&self) {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.start_stats(),
+            EventQueueInner::Native(ref q) => q.start_stats(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Option<DispatchStats> {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.stats(),
+            EventQueueInner::Native(ref q) => q.stats(),
+        }
+    }
+
+    pub(crate) fn stop_stats(&self) -> Option<DispatchStats> {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.stop_stats(),
+            EventQueueInner::Native(ref q) => q.stop_stats(),
+        }
+    }
+
+    pub(crate) fn set_slow_dispatch_hook(&self, hook: Option<SlowDispatchHook>) {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.set_slow_dispatch_hook(hook),
+            EventQueueInner::Native(ref q) => q.set_slow_dispatch_hook(hook),
+        }
+    }
+
+    // the queues of the system library are bound to the thread that created them
+    pub(crate) fn supports_handoff(&self) -> bool {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.supports_handoff(),
+            EventQueueInner::Native(_) => false,
+        }
+    }
+
+    pub(crate) fn has_slow_dispatch_hook(&self) -> bool {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.has_slow_dispatch_hook(),
+            EventQueueInner::Native(_) => false,
+        }
+    }
+
+    pub(crate) fn thread_bound_object(&self) -> Option<(&'static str, u32)> {
+        match *self {
+            EventQueueInner::Rust(ref q) => q.thread_bound_object(),
+            EventQueueInner::Native(_) => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum ProxyInner {
+    Rust(rust_imp::ProxyInner),
+    Native(native_lib::ProxyInner),
+}
+
+impl From<rust_imp::ProxyInner> for ProxyInner {
+    fn from(inner: rust_imp::ProxyInner) -> ProxyInner {
+        ProxyInner::Rust(inner)
+    }
+}
+
+impl From<native_lib::ProxyInner> for ProxyInner {
+    fn from(inner: native_lib::ProxyInner) -> ProxyInner {
+        ProxyInner::Native(inner)
+    }
+}
+
+impl ProxyInner {
+    /// The proxy of the rust implementation, for the objects it creates itself
+    pub(crate) fn as_rust(&self) -> &rust_imp::ProxyInner {
+        match *self {
+            ProxyInner::Rust(ref p) => p,
+            ProxyInner::Native(_) => mismatched(),
+        }
+    }
+
+    pub(crate) fn is_alive(&self) -> bool {
+        match *self {
+            ProxyInner::Rust(ref p) => p.is_alive(),
+            ProxyInner::Native(ref p) => p.is_alive(),
+        }
+    }
+
+    pub(crate) fn is_external(&self) -> bool {
+        match *self {
+            // the rust implementation only has objects of its own
+            ProxyInner::Rust(_) => false,
+            ProxyInner::Native(ref p) => p.is_external(),
+        }
+    }
+
+    pub(crate) fn version(&self) -> u32 {
+        match *self {
+            ProxyInner::Rust(ref p) => p.version(),
+            ProxyInner::Native(ref p) => p.version(),
+        }
+    }
+
+    pub(crate) fn is_interface<I: Interface>(&self) -> bool {
+        match *self {
+            ProxyInner::Rust(ref p) => p.is_interface::<I>(),
+            ProxyInner::Native(ref p) => p.is_interface::<I>(),
+        }
+    }
+
+    pub(crate) fn id(&self) -> u32 {
+        match *self {
+            ProxyInner::Rust(ref p) => p.id(),
+            ProxyInner::Native(ref p) => p.id(),
+        }
+    }
+
+    pub(crate) fn is_high_priority(&self) -> bool {
+        match *self {
+            ProxyInner::Rust(ref p) => p.is_high_priority(),
+            ProxyInner::Native(ref p) => p.is_high_priority(),
+        }
+    }
+
+    pub(crate) fn set_high_priority(&self, high_priority: bool) {
+        match *self {
+            ProxyInner::Rust(ref p) => p.set_high_priority(high_priority),
+            ProxyInner::Native(ref p) => p.set_high_priority(high_priority),
+        }
+    }
+
+    pub(crate) fn user_data(&self) -> &UserData {
+        match *self {
+            ProxyInner::Rust(ref p) => p.user_data(),
+            ProxyInner::Native(ref p) => p.user_data(),
+        }
+    }
+
+    pub(crate) fn send<I, J>(&self, msg: I::Request, version: Option<u32>) -> Option<ProxyInner>
+    where
+        I: Interface,
+        J: Interface + AsRef<Proxy<J>> + From<Proxy<J>>,
+    {
+        match *self {
+            ProxyInner::Rust(ref p) => p.send::<I, J>(msg, version).map(ProxyInner::Rust),
+            ProxyInner::Native(ref p) => p.send::<I, J>(msg, version).map(ProxyInner::Native),
+        }
+    }
+
+    pub(crate) fn serial(&self) -> u64 {
+        match *self {
+            ProxyInner::Rust(ref p) => p.serial(),
+            ProxyInner::Native(ref p) => p.serial(),
+        }
+    }
+
+    pub(crate) fn parents(&self) -> Vec<u64> {
+        match *self {
+            ProxyInner::Rust(ref p) => p.parents(),
+            ProxyInner::Native(ref p) => p.parents(),
+        }
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakProxyInner {
+        match *self {
+            ProxyInner::Rust(ref p) => WeakProxyInner::Rust(p.downgrade()),
+            ProxyInner::Native(ref p) => WeakProxyInner::Native(p.downgrade()),
+        }
+    }
+
+    pub(crate) fn equals(&self, other: &ProxyInner) -> bool {
+        match (self, other) {
+            (ProxyInner::Rust(p), ProxyInner::Rust(other)) => p.equals(other),
+            (ProxyInner::Native(p), ProxyInner::Native(other)) => p.equals(other),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn detach(&mut self) {
+        match *self {
+            ProxyInner::Rust(ref mut p) => p.detach(),
+            ProxyInner::Native(ref mut p) => p.detach(),
+        }
+    }
+
+    pub(crate) fn attach(&mut self, queue: &EventQueueInner) {
+        match (self, queue) {
+            (ProxyInner::Rust(p), EventQueueInner::Rust(q)) => p.attach(q),
+            (ProxyInner::Native(p), EventQueueInner::Native(q)) => p.attach(q),
+            _ => mismatched(),
+        }
+    }
+
+    pub(crate) fn c_ptr(&self, interface: *const wl_interface) -> *mut wl_proxy {
+        match *self {
+            ProxyInner::Rust(ref p) => p.c_ptr(interface),
+            ProxyInner::Native(ref p) => p.c_ptr(),
+        }
+    }
+
+    pub fn assign<I, E>(&self, filter: Filter<E>)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        E: From<(Main<I>, I::Event)> + 'static,
+        I::Event: MessageGroup<Map = ProxyMap>,
+    {
+        match *self {
+            ProxyInner::Rust(ref p) => p.assign::<I, E>(filter),
+            ProxyInner::Native(ref p) => p.assign::<I, E>(filter),
+        }
+    }
+
+    pub fn reassign<I, E>(&self, filter: Filter<E>)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        E: From<(Main<I>, I::Event)> + 'static,
+        I::Event: MessageGroup<Map = ProxyMap>,
+    {
+        match *self {
+            ProxyInner::Rust(ref p) => p.reassign::<I, E>(filter),
+            ProxyInner::Native(ref p) => p.reassign::<I, E>(filter),
+        }
+    }
+
+    pub fn unassign<I>(&self)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+    {
+        match *self {
+            ProxyInner::Rust(ref p) => p.unassign(),
+            ProxyInner::Native(ref p) => p.unassign::<I>(),
+        }
+    }
+
+    pub fn assign_threadsafe<I, F>(&self, f: F)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        F: FnMut(Main<I>, I::Event, DispatchData) + Send + 'static,
+        I::Event: MessageGroup<Map = ProxyMap>,
+    {
+        match *self {
+            ProxyInner::Rust(ref p) => p.assign_threadsafe::<I, F>(f),
+            ProxyInner::Native(ref p) => p.assign_threadsafe::<I, F>(f),
+        }
+    }
+
+    pub(crate) unsafe fn init_from_c_ptr<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>>(
+        ptr: *mut wl_proxy,
+    ) -> Self {
+        ProxyInner::Native(native_lib::ProxyInner::init_from_c_ptr::<I>(ptr))
+    }
+
+    pub(crate) unsafe fn try_init_from_c_ptr<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>>(
+        ptr: *mut wl_proxy,
+    ) -> Result<Self, crate::ForeignProxyError> {
+        native_lib::ProxyInner::try_init_from_c_ptr::<I>(ptr).map(ProxyInner::Native)
+    }
+
+    pub(crate) unsafe fn from_c_ptr<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>>(
+        ptr: *mut wl_proxy,
+    ) -> Self {
+        ProxyInner::Native(native_lib::ProxyInner::from_c_ptr::<I>(ptr))
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum WeakProxyInner {
+    Rust(rust_imp::WeakProxyInner),
+    Native(native_lib::WeakProxyInner),
+}
+
+impl WeakProxyInner {
+    pub(crate) fn upgrade(&self) -> Option<ProxyInner> {
+        match *self {
+            WeakProxyInner::Rust(ref w) => w.upgrade().map(ProxyInner::Rust),
+            WeakProxyInner::Native(ref w) => w.upgrade().map(ProxyInner::Native),
+        }
+    }
+}
@@ -1,7 +1,104 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use std::{io, rc::Rc};
 
+use nix::poll::{poll, PollFd, PollFlags};
+
 use crate::imp::EventQueueInner;
-use crate::{AnonymousObject, DispatchData, Display, Main, RawEvent};
+use crate::{AnonymousObject, DispatchData, DispatchError, Display, Main, RawEvent};
+
+type ReconnectHandler =
+    Box<dyn FnMut(crate::Attached<crate::protocol::wl_display::WlDisplay>, DispatchData<'_>)>;
+
+/// Statistics about the dispatching of an event queue
+///
+/// As returned by `EventQueue::stats()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DispatchStats {
+    /// Number of events dispatched, per interface
+    pub dispatched: HashMap<&'static str, u64>,
+    /// Total time spent in the filters and the fallback closure
+    pub filter_time: Duration,
+    /// Highest number of events found waiting in the queue while dispatching it
+    pub max_queue_depth: usize,
+}
+
+/// An event whose dispatching took longer than expected
+///
+/// Given to the hook set with `EventQueue::set_slow_dispatch_hook()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowDispatch {
+    /// The interface of the object that received the event
+    pub interface: &'static str,
+    /// The protocol id of the object that received the event
+    pub id: u32,
+    /// The name of the event
+    pub event: &'static str,
+    /// The time spent in the filter (or the fallback closure) handling the event
+    pub duration: Duration,
+}
+
+/// The reason why an event queue cannot be moved to another thread
+///
+/// As returned by `EventQueue::handoff()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HandoffError {
+    /// Some `QueueToken`s of the queue are still alive
+    TokensAlive,
+    /// An object of the queue is assigned to a filter that can only be invoked from the
+    /// current thread
+    ThreadBoundFilter {
+        /// The interface of the object
+        interface: &'static str,
+        /// The protocol id of the object
+        id: u32,
+    },
+    /// A reconnection callback or a slow dispatch hook is set on the queue
+    ThreadBoundHook,
+    /// The queues of the system library cannot be moved to another thread
+    Unsupported,
+}
+
+impl std::error::Error for HandoffError {}
+
+impl std::fmt::Display for HandoffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            HandoffError::TokensAlive => f.write_str("Some tokens of the event queue are alive."),
+            HandoffError::ThreadBoundFilter { interface, id } => write!(
+                f,
+                "Object {}@{} is assigned to a filter bound to the current thread.",
+                interface, id
+            ),
+            HandoffError::ThreadBoundHook => {
+                f.write_str("A callback bound to the current thread is set on the event queue.")
+            }
+            HandoffError::Unsupported => {
+                f.write_str("Event queues cannot be moved with the system library.")
+            }
+        }
+    }
+}
+
+/// An event queue on its way to another thread
+///
+/// Created by `EventQueue::handoff()`, it can be sent to another thread, where
+/// `into_queue()` gives the event queue back.
+pub struct QueueHandoff {
+    queue: EventQueue,
+}
+
+// `EventQueue::handoff()` checked that the queue is the only owner of its inner state,
+// and that none of the callbacks it can invoke are bound to the current thread
+unsafe impl Send for QueueHandoff {}
+
+impl QueueHandoff {
+    /// Retrieve the event queue, on the thread that will dispatch it
+    pub fn into_queue(self) -> EventQueue {
+        self.queue
+    }
+}
 
 /// An event queue for protocol messages
 ///
@@ -101,6 +198,7 @@ pub struct EventQueue {
     // EventQueue is *not* Send
     pub(crate) inner: Rc<EventQueueInner>,
     display: Display,
+    reconnect_handlers: Vec<ReconnectHandler>,
 }
 
 /// A token representing this event queue
@@ -116,7 +214,7 @@ pub struct QueueToken {
 
 impl EventQueue {
     pub(crate) fn new(inner: EventQueueInner, display: Display) -> EventQueue {
-        EventQueue { inner: Rc::new(inner), display }
+        EventQueue { inner: Rc::new(inner), display, reconnect_handlers: Vec::new() }
     }
     /// Dispatches events from the internal buffer.
     ///
@@ -131,13 +229,45 @@ impl EventQueue {
     /// can just provide a `&mut ()` there.
     ///
     /// If an error is returned, your connection with the wayland compositor is probably lost.
-    /// You may want to check `Display::protocol_error()` to see if it was caused by a protocol error.
-    pub fn dispatch<T: std::any::Any, F>(&mut self, data: &mut T, fallback: F) -> io::Result<u32>
+    /// See `DispatchError` for the possible causes.
+    pub fn dispatch<T: std::any::Any, F>(
+        &mut self,
+        data: &mut T,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        let mut data = DispatchData::wrap(data);
+        self.handle_reconnection(data.reborrow());
+        self.inner.dispatch(data.reborrow(), self.display.with_unhandled_sink(fallback))
+    }
+
+    /// Dispatches events from the internal buffer, waiting at most `timeout` for some to arrive
+    ///
+    /// Behaves like `dispatch()`, except that it gives up if no event is dispatched by this
+    /// queue before the timeout elapses, in which case `Ok(0)` is returned. This protects your
+    /// app from being stuck forever if the compositor hangs.
+    ///
+    /// The provided `data` will be mutably accessible from all the callbacks, via the
+    /// [`DispatchData`](struct.DispatchData.html) mechanism. If you don't need global data, you
+    /// can just provide a `&mut ()` there.
+    ///
+    /// If an error is returned, your connection with the wayland compositor is probably lost.
+    /// See `DispatchError` for the possible causes.
+    pub fn dispatch_timeout<T: std::any::Any, F>(
+        &mut self,
+        data: &mut T,
+        timeout: Duration,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
         let mut data = DispatchData::wrap(data);
-        self.inner.dispatch(data.reborrow(), fallback)
+        self.handle_reconnection(data.reborrow());
+        let fallback = self.display.with_unhandled_sink(fallback);
+        self.dispatch_until(data.reborrow(), Instant::now() + timeout, fallback)
     }
 
     /// Dispatches pending events from the internal buffer.
@@ -151,17 +281,105 @@ impl EventQueue {
     /// can just provide a `&mut ()` there.
     ///
     /// If an error is returned, your connection with the wayland compositor is probably lost.
-    /// You may want to check `Display::protocol_error()` to see if it was caused by a protocol error.
+    /// See `DispatchError` for the possible causes.
     pub fn dispatch_pending<T: std::any::Any, F>(
         &mut self,
         data: &mut T,
         fallback: F,
-    ) -> io::Result<u32>
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        let mut data = DispatchData::wrap(data);
+        self.handle_reconnection(data.reborrow());
+        self.inner.dispatch_pending(data.reborrow(), self.display.with_unhandled_sink(fallback))
+    }
+
+    /// Dispatches the events available once the connection was reported readable
+    ///
+    /// This is meant for event loops notifying readiness, like `mio` with the `mio` cargo feature:
+    /// once the file descriptor of the connection is readable, prefer this method to calling
+    /// `prepare_read()` yourself. It reads the socket until no more data is available, which the
+    /// edge-triggered notifications require, and dispatches the events of this queue. Spurious
+    /// wakeups, or the data having been read by an other queue, are not an error: `Ok(0)` is
+    /// returned. Never blocks.
+    ///
+    /// The events read for other queues are only queued, these need to be dispatched as well.
+    ///
+    /// The provided `data` will be mutably accessible from all the callbacks, via the
+    /// [`DispatchData`](struct.DispatchData.html) mechanism. If you don't need global data, you
+    /// can just provide a `&mut ()` there.
+    ///
+    /// If an error is returned, your connection with the wayland compositor is probably lost.
+    /// See `DispatchError` for the possible causes.
+    pub fn dispatch_ready<T: std::any::Any, F>(
+        &mut self,
+        data: &mut T,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        let mut data = DispatchData::wrap(data);
+        self.handle_reconnection(data.reborrow());
+        let mut fallback = self.display.with_unhandled_sink(fallback);
+        let fd = self.display.get_connection_fd();
+        let mut dispatched = 0;
+        loop {
+            dispatched += self.inner.dispatch_pending(data.reborrow(), &mut fallback)?;
+            let guard = match self.prepare_read() {
+                Some(guard) => guard,
+                None => continue,
+            };
+            // don't read a drained socket, which the C library considers a fatal error
+            let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+            match poll(&mut fds, 0) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(::nix::Error::Sys(::nix::errno::Errno::EINTR)) => continue,
+                Err(::nix::Error::Sys(e)) => return Err(DispatchError::Backend(e.into())),
+                Err(_) => unreachable!(),
+            }
+            match guard.read_events() {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    self.inner.dispatch_pending(data.reborrow(), &mut fallback)?;
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(dispatched)
+    }
+
+    /// Dispatches at most `max_events` pending events from the internal buffer
+    ///
+    /// Behaves like `dispatch_pending()`, except that it stops once `max_events` events have
+    /// been dispatched. This allows bounding the time spent handling events, for example once
+    /// per frame of a render loop. Returns the number of dispatched events, and whether some
+    /// events are still pending.
+    ///
+    /// The provided `data` will be mutably accessible from all the callbacks, via the
+    /// [`DispatchData`](struct.DispatchData.html) mechanism. If you don't need global data, you
+    /// can just provide a `&mut ()` there.
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Native`.
+    pub fn dispatch_some<T: std::any::Any, F>(
+        &mut self,
+        data: &mut T,
+        max_events: u32,
+        fallback: F,
+    ) -> Result<(u32, bool), DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
         let mut data = DispatchData::wrap(data);
-        self.inner.dispatch_pending(data.reborrow(), fallback)
+        self.handle_reconnection(data.reborrow());
+        self.inner.dispatch_some(
+            data.reborrow(),
+            max_events,
+            self.display.with_unhandled_sink(fallback),
+        )
     }
 
     /// Synchronous roundtrip
@@ -178,17 +396,159 @@ impl EventQueue {
     ///
     /// On success returns the number of dispatched events.
     /// If an error is returned, your connection with the wayland compositor is probably lost.
-    /// You may want to check `Display::protocol_error()` to see if it was caused by a protocol error.
+    /// See `DispatchError` for the possible causes.
     pub fn sync_roundtrip<T: std::any::Any, F>(
         &mut self,
         data: &mut T,
         fallback: F,
-    ) -> io::Result<u32>
+    ) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("sync_roundtrip").entered();
         let mut data = DispatchData::wrap(data);
-        self.inner.sync_roundtrip(data.reborrow(), fallback)
+        self.handle_reconnection(data.reborrow());
+        self.inner.sync_roundtrip(data.reborrow(), self.display.with_unhandled_sink(fallback))
+    }
+
+    /// Synchronous roundtrip, giving up after `timeout`
+    ///
+    /// Behaves like `sync_roundtrip()`, except that a `Backend` error of kind `TimedOut` is returned if the
+    /// server did not process the pending requests before the timeout elapsed. The events
+    /// dispatched until then are not accounted for in this case.
+    ///
+    /// The provided `data` will be mutably accessible from all the callbacks, via the
+    /// [`DispatchData`](struct.DispatchData.html) mechanism. If you don't need global data, you
+    /// can just provide a `&mut ()` there.
+    ///
+    /// On success returns the number of dispatched events.
+    pub fn sync_roundtrip_timeout<T: std::any::Any, F>(
+        &mut self,
+        data: &mut T,
+        timeout: Duration,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("sync_roundtrip_timeout").entered();
+        let deadline = Instant::now() + timeout;
+        let mut fallback = self.display.with_unhandled_sink(fallback);
+        let mut data = DispatchData::wrap(data);
+        self.handle_reconnection(data.reborrow());
+
+        let done = Rc::new(Cell::new(false));
+        let done2 = done.clone();
+        self.display.attach(self.token()).sync().quick_assign(move |_, _, _| done2.set(true));
+
+        let mut dispatched = 0;
+        while !done.get() {
+            let count = self.dispatch_until(data.reborrow(), deadline, &mut fallback)?;
+            if count == 0 {
+                return Err(DispatchError::Backend(io::ErrorKind::TimedOut.into()));
+            }
+            dispatched += count;
+        }
+        Ok(dispatched)
+    }
+
+    // Dispatch events, reading them from the socket until at least one was dispatched or
+    // the deadline was reached
+    fn dispatch_until<F>(
+        &self,
+        mut data: DispatchData,
+        deadline: Instant,
+        mut fallback: F,
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        let fd = self.display.get_connection_fd();
+        loop {
+            let dispatched = self.inner.dispatch_pending(data.reborrow(), &mut fallback)?;
+            if dispatched > 0 {
+                return Ok(dispatched);
+            }
+            // if the socket is full, also wait for it to be writable to flush again
+            let flags = match self.display.flush() {
+                Ok(ref progress) if !progress.is_complete() => {
+                    PollFlags::POLLIN | PollFlags::POLLOUT
+                }
+                Ok(_) => PollFlags::POLLIN,
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock => PollFlags::POLLIN | PollFlags::POLLOUT,
+                    // don't abort on EPIPE, so we can continue reading to get the protocol error
+                    io::ErrorKind::BrokenPipe => PollFlags::POLLIN,
+                    _ => return Err(e),
+                },
+            };
+            let guard = match self.prepare_read() {
+                Some(guard) => guard,
+                // some events were queued in the meantime
+                None => continue,
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(0);
+            }
+            let remaining = deadline - now;
+            // round up, to not wake up just before the deadline
+            let millis = remaining.as_secs() * 1000
+                + u64::from((remaining.subsec_nanos() + 999_999) / 1_000_000);
+            let millis = ::std::cmp::min(millis, ::std::i32::MAX as u64) as i32;
+            let mut fds = [PollFd::new(fd, flags)];
+            match poll(&mut fds, millis) {
+                Ok(0) => continue,
+                Ok(_) => {}
+                Err(::nix::Error::Sys(::nix::errno::Errno::EINTR)) => continue,
+                Err(::nix::Error::Sys(e)) => return Err(DispatchError::Backend(e.into())),
+                Err(_) => unreachable!(),
+            }
+            if fds[0].revents() == Some(PollFlags::POLLOUT) {
+                // only writable, flush again
+                continue;
+            }
+            match guard.read_events() {
+                Ok(()) => {}
+                // an other thread read the events under our nose
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    // the events read before the error still need to be dispatched
+                    self.inner.dispatch_pending(data.reborrow(), &mut fallback)?;
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    /// Register a callback invoked after the `Display` of this queue was reconnected
+    ///
+    /// When the connection was replaced using `Display::reconnect()`, all the objects of the
+    /// previous connection are dead. The callbacks of a queue are invoked, in the order they were
+    /// registered, the next time this queue is dispatched, and are meant to recreate the objects
+    /// of your app (globals, surfaces...). They are given the `WlDisplay` attached to this queue,
+    /// and the `DispatchData` of the dispatching method.
+    ///
+    /// NOTE: As the connection cannot be replaced on a connection using `Backend::Native`, the
+    /// callbacks are then never invoked.
+    pub fn add_reconnect_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(crate::Attached<crate::protocol::wl_display::WlDisplay>, DispatchData<'_>)
+            + 'static,
+    {
+        self.reconnect_handlers.push(Box::new(handler));
+    }
+
+    // Invoke the reconnect handlers if the display was reconnected since the last dispatch
+    fn handle_reconnection(&mut self, mut data: DispatchData) {
+        if self.inner.take_reconnection() {
+            let display = (*self.display).clone().attach(self.token());
+            for handler in &mut self.reconnect_handlers {
+                handler(display.clone(), data.reborrow());
+            }
+        }
     }
 
     /// Create a new token associated with this event queue
@@ -222,10 +582,89 @@ impl EventQueue {
         }
     }
 
+    /// Start collecting dispatch statistics for this event queue
+    ///
+    /// Does nothing if the statistics are already being collected.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn start_stats(&mut self) {
+        self.inner.start_stats()
+    }
+
+    /// Retrieve the statistics collected since `start_stats()` was called
+    ///
+    /// Returns `None` if no statistics are being collected.
+    ///
+    /// NOTE: This method always returns `None` on a connection using `Backend::Native`.
+    pub fn stats(&self) -> Option<DispatchStats> {
+        self.inner.stats()
+    }
+
+    /// Stop collecting dispatch statistics and retrieve them
+    ///
+    /// Returns `None` if no statistics were being collected.
+    ///
+    /// NOTE: This method always returns `None` on a connection using `Backend::Native`.
+    pub fn stop_stats(&mut self) -> Option<DispatchStats> {
+        self.inner.stop_stats()
+    }
+
+    /// Set a hook invoked when handling a single event takes at least `threshold`
+    ///
+    /// The hook is invoked right after the slow filter (or fallback closure) returned.
+    ///
+    /// This replaces any previously set hook.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn set_slow_dispatch_hook<F>(&mut self, threshold: Duration, hook: F)
+    where
+        F: FnMut(SlowDispatch) + 'static,
+    {
+        self.inner.set_slow_dispatch_hook(Some((threshold, Box::new(hook))))
+    }
+
+    /// Remove the hook set with `set_slow_dispatch_hook()`
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn clear_slow_dispatch_hook(&mut self) {
+        self.inner.set_slow_dispatch_hook(None)
+    }
+
     /// Access the `Display` of the connection
     pub fn display(&self) -> &Display {
         &self.display
     }
+
+    /// Prepare moving this event queue to another thread
+    ///
+    /// This allows setting up the objects of a queue on one thread, and dispatching it from
+    /// another one, for example a render thread. The returned `QueueHandoff` can be sent to
+    /// the other thread, the objects of the queue keep their state and their pending events.
+    ///
+    /// The filters given to `assign(..)` and `quick_assign(..)` can only be invoked from the
+    /// thread they were assigned on, so the move is rejected if an object of the queue is
+    /// assigned to one of them, or if a reconnection callback or a slow dispatch hook is set.
+    /// Use `assign_threadsafe(..)`, or unassign the objects and assign them again once the
+    /// queue reached its new thread. All the `QueueToken`s of the queue must also have been
+    /// dropped. On failure, the queue is given back along with the reason.
+    ///
+    /// NOTE: This method always fails with `HandoffError::Unsupported` on a connection using
+    /// `Backend::Native`.
+    pub fn handoff(self) -> Result<QueueHandoff, (EventQueue, HandoffError)> {
+        if !self.inner.supports_handoff() {
+            return Err((self, HandoffError::Unsupported));
+        }
+        if Rc::strong_count(&self.inner) > 1 {
+            return Err((self, HandoffError::TokensAlive));
+        }
+        if !self.reconnect_handlers.is_empty() || self.inner.has_slow_dispatch_hook() {
+            return Err((self, HandoffError::ThreadBoundHook));
+        }
+        if let Some((interface, id)) = self.inner.thread_bound_object() {
+            return Err((self, HandoffError::ThreadBoundFilter { interface, id }));
+        }
+        Ok(QueueHandoff { queue: self })
+    }
 }
 
 /// A guard over a read intention.
@@ -0,0 +1,220 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::protocol::wl_callback::{self, WlCallback};
+use crate::protocol::wl_surface::WlSurface;
+use crate::{Attached, DispatchError, EventQueue, Main};
+
+#[derive(Default)]
+struct FrameState {
+    redraw_requested: bool,
+    // the callbacks waiting for their `done` event, with the time they were requested
+    in_flight: Vec<(u32, Instant)>,
+    last_frame: Option<u32>,
+    wakers: Vec<Waker>,
+}
+
+/// A helper throttling the redraws of a surface with frame callbacks
+///
+/// Drawing faster than the compositor displays the frames is wasted work, and clients
+/// should wait for the `wl_surface.frame` callback of their previous commit before drawing
+/// again. This scheduler tracks these callbacks:
+///
+/// - `request_redraw()` marks the surface as needing a redraw, several requests made before
+///   the next frame are coalesced into a single redraw;
+/// - `begin_frame()` tells you whether you should draw now, and requests the frame callback
+///   of the commit that you then have to make;
+/// - `await_frame()` and `await_frame_async()` wait for the in-flight callback.
+///
+/// ```no_run
+/// # use wayland_client::{Attached, EventQueue, FrameScheduler, protocol::wl_surface::WlSurface};
+/// # fn draw(_: &WlSurface) {}
+/// # fn run(surface: &Attached<WlSurface>, queue: &mut EventQueue) {
+/// let scheduler = FrameScheduler::new(surface);
+/// loop {
+///     scheduler.request_redraw();
+///     if scheduler.begin_frame() {
+///         draw(surface);
+///         surface.commit();
+///     }
+///     scheduler.await_frame(queue, &mut ()).unwrap();
+/// }
+/// # }
+/// ```
+///
+/// Compositors usually stop sending frame callbacks for the surfaces that are not visible, so
+/// a callback can stay in flight for a long time. `oldest_callback_age()` and
+/// `outstanding_callbacks()` allow you to detect this, as well as the callbacks leaked by
+/// code requesting them without waiting for the previous ones.
+pub struct FrameScheduler {
+    surface: Attached<WlSurface>,
+    state: Rc<RefCell<FrameState>>,
+}
+
+impl FrameScheduler {
+    /// Create a scheduler for a surface
+    pub fn new(surface: &Attached<WlSurface>) -> FrameScheduler {
+        FrameScheduler {
+            surface: surface.clone(),
+            state: Rc::new(RefCell::new(FrameState::default())),
+        }
+    }
+
+    /// The surface managed by this scheduler
+    pub fn surface(&self) -> &Attached<WlSurface> {
+        &self.surface
+    }
+
+    /// Mark the surface as needing a redraw
+    pub fn request_redraw(&self) {
+        self.state.borrow_mut().redraw_requested = true;
+    }
+
+    /// Whether a redraw has been requested since the last frame
+    pub fn redraw_requested(&self) -> bool {
+        self.state.borrow().redraw_requested
+    }
+
+    /// Whether a frame callback is in flight
+    ///
+    /// You should not draw while this is the case.
+    pub fn is_throttled(&self) -> bool {
+        !self.state.borrow().in_flight.is_empty()
+    }
+
+    /// Start drawing a frame if needed
+    ///
+    /// If a redraw has been requested and no frame callback is in flight, this requests the
+    /// frame callback of the next commit and returns `true`: you should then draw the surface
+    /// and commit it. Otherwise, this returns `false` and does nothing.
+    pub fn begin_frame(&self) -> bool {
+        {
+            let mut state = self.state.borrow_mut();
+            if !state.redraw_requested || !state.in_flight.is_empty() {
+                return false;
+            }
+            state.redraw_requested = false;
+        }
+        self.track(self.surface.frame());
+        true
+    }
+
+    /// Track a frame callback requested by some other code
+    ///
+    /// This assigns a filter to the callback, replacing any previously assigned one, and
+    /// throttles the redraws until it is done.
+    pub fn track(&self, callback: Main<WlCallback>) {
+        // the callback is already dead when its `done` event is dispatched, so keep its id
+        let id = callback.as_ref().id();
+        self.state.borrow_mut().in_flight.push((id, Instant::now()));
+        // don't keep the state alive if the compositor never sends the event
+        let state = Rc::downgrade(&self.state);
+        callback.quick_assign(move |_, event, _| {
+            let wl_callback::Event::Done { callback_data } = event;
+            frame_done(&state, id, callback_data);
+        });
+    }
+
+    /// The timestamp of the last frame, in milliseconds
+    ///
+    /// This is the value of the last `wl_callback.done` event received, `None` if there
+    /// was none yet.
+    pub fn last_frame_time(&self) -> Option<u32> {
+        self.state.borrow().last_frame
+    }
+
+    /// The number of frame callbacks in flight
+    ///
+    /// More than one means that frame callbacks were requested without waiting for the
+    /// previous ones, which is a leak if it keeps growing.
+    pub fn outstanding_callbacks(&self) -> usize {
+        self.state.borrow().in_flight.len()
+    }
+
+    /// How long ago the oldest frame callback still in flight was requested
+    pub fn oldest_callback_age(&self) -> Option<Duration> {
+        self.state.borrow().in_flight.iter().map(|&(_, time)| time.elapsed()).max()
+    }
+
+    /// Wait for the frame callbacks in flight to be done
+    ///
+    /// This dispatches the event queue until then, it must be the queue the surface is
+    /// attached to. Returns the timestamp of the frame, or `None` right away if no callback
+    /// was in flight.
+    ///
+    /// The provided `data` will be mutably accessible from all the callbacks dispatched, via the
+    /// [`DispatchData`](struct.DispatchData.html) mechanism.
+    pub fn await_frame<T: std::any::Any>(
+        &self,
+        queue: &mut EventQueue,
+        data: &mut T,
+    ) -> Result<Option<u32>, DispatchError> {
+        if !self.is_throttled() {
+            return Ok(None);
+        }
+        while self.is_throttled() {
+            queue.dispatch(data, |_, _, _| {})?;
+        }
+        Ok(self.last_frame_time())
+    }
+
+    /// Get a future resolving once the frame callbacks in flight are done
+    ///
+    /// It resolves with the timestamp of the frame, or with `None` right away if no callback
+    /// was in flight. Like `ResponseFuture`, it does not read the socket by itself, so the
+    /// event queue of the surface still needs to be dispatched.
+    pub fn await_frame_async(&self) -> FrameFuture {
+        FrameFuture { state: self.state.clone(), throttled: self.is_throttled() }
+    }
+}
+
+fn frame_done(state: &Weak<RefCell<FrameState>>, id: u32, time: u32) {
+    let state = match state.upgrade() {
+        Some(state) => state,
+        None => return,
+    };
+    let wakers = {
+        let mut state = state.borrow_mut();
+        state.in_flight.retain(|&(callback, _)| callback != id);
+        state.last_frame = Some(time);
+        if state.in_flight.is_empty() {
+            ::std::mem::take(&mut state.wakers)
+        } else {
+            Vec::new()
+        }
+    };
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+/// A future resolving once the frame callbacks of a `FrameScheduler` are done
+///
+/// See `FrameScheduler::await_frame_async()`.
+pub struct FrameFuture {
+    state: Rc<RefCell<FrameState>>,
+    throttled: bool,
+}
+
+impl Future for FrameFuture {
+    type Output = Option<u32>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u32>> {
+        if !self.throttled {
+            return Poll::Ready(None);
+        }
+        let mut state = self.state.borrow_mut();
+        if state.in_flight.is_empty() {
+            Poll::Ready(state.last_frame)
+        } else {
+            if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                state.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+}
@@ -1,8 +1,13 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, Mutex};
 
 use crate::protocol::wl_display;
 use crate::protocol::wl_registry;
-use crate::{Attached, DispatchData, Interface, Main, Proxy};
+use crate::{
+    AnonymousObject, Argument, Attached, DispatchData, DispatchError, Display, Interface, Main,
+    Proxy, QueueToken, RawEvent,
+};
 
 struct Inner {
     list: Vec<(u32, String, u32)>,
@@ -203,12 +208,7 @@ impl GlobalManager {
         let inner = self.inner.lock().unwrap();
         for &(id, ref interface, version) in &inner.list {
             if interface == I::NAME {
-                if version >= min_version {
-                    let version = ::std::cmp::min(version, max_version);
-                    return Ok(self.registry.bind::<I>(version, id));
-                } else {
-                    return Err(GlobalError::VersionTooLow(version));
-                }
+                return self.registry.bind_range::<I>(id, version, min_version..=max_version);
             }
         }
         Err(GlobalError::Missing)
@@ -220,6 +220,218 @@ impl GlobalManager {
     }
 }
 
+impl wl_registry::WlRegistry {
+    /// Bind a global, negotiating its version
+    ///
+    /// `advertised` is the version of the global advertised by the server. This binds the
+    /// highest version within `versions` supported by both the server and the protocol files
+    /// this crate was built with, so that the server never receives an invalid version. An
+    /// error is returned if none of the versions within `versions` is supported.
+    pub fn bind_range<T>(
+        &self,
+        name: u32,
+        advertised: u32,
+        versions: RangeInclusive<u32>,
+    ) -> Result<Main<T>, GlobalError>
+    where
+        T: Interface + AsRef<Proxy<T>> + From<Proxy<T>>,
+    {
+        let supported = ::std::cmp::min(advertised, T::VERSION);
+        if supported < *versions.start() {
+            return Err(GlobalError::VersionTooLow(supported));
+        }
+        Ok(self.bind::<T>(::std::cmp::min(supported, *versions.end()), name))
+    }
+}
+
+/// A global advertised by the server, as listed in a `RegistrySnapshot`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalInfo {
+    /// Name of the global, to be used with `wl_registry.bind`
+    pub name: u32,
+    /// Interface of the global
+    pub interface: String,
+    /// Maximum version supported by the server
+    pub version: u32,
+}
+
+/// The list of the globals advertised by the server
+///
+/// It is created by `Display::get_registry_snapshot()`, and reflects the globals
+/// at the time it was taken: it is not updated when the server creates or removes
+/// globals afterwards, use a `GlobalManager` if you need to track them.
+pub struct RegistrySnapshot {
+    globals: Vec<GlobalInfo>,
+    registry: Attached<wl_registry::WlRegistry>,
+}
+
+impl RegistrySnapshot {
+    pub(crate) fn take(display: &Display, token: QueueToken) -> Result<Self, DispatchError> {
+        // the registry is created on its own queue, so that the roundtrip does not
+        // dispatch the pending events of the queues of the user
+        let mut queue = display.create_event_queue();
+        let attached = (**display).clone().attach(queue.token());
+        let manager = GlobalManager::new(&attached);
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        let globals = manager
+            .list()
+            .into_iter()
+            .map(|(name, interface, version)| GlobalInfo { name, interface, version })
+            .collect();
+        let registry = manager.registry.as_ref().attach(token);
+        Ok(RegistrySnapshot { globals, registry })
+    }
+
+    /// Retrieve the list of the globals, in the order they were advertised
+    pub fn globals(&self) -> &[GlobalInfo] {
+        &self.globals
+    }
+
+    /// Bind the global implementing interface `I`
+    ///
+    /// This binds the highest version supported by both the server and your code within
+    /// `versions`, and returns an error if the global is missing or if the server only
+    /// supports versions lower than the start of the range. Like for
+    /// `GlobalManager::instantiate_range`, you should not use `I::VERSION` as the end
+    /// of the range.
+    ///
+    /// The new object is handled by the event queue given to `Display::get_registry_snapshot()`.
+    /// If several globals implement `I`, the first one advertised is bound.
+    pub fn bind<I>(&self, versions: RangeInclusive<u32>) -> Result<Main<I>, GlobalError>
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+    {
+        match self.globals.iter().find(|global| global.interface == I::NAME) {
+            Some(global) => self.registry.bind_range::<I>(global.name, global.version, versions),
+            None => Err(GlobalError::Missing),
+        }
+    }
+}
+
+/// The capabilities of a global, as reported by `CapabilityProbe`
+#[derive(Debug)]
+pub struct GlobalReport {
+    /// Id of the global
+    pub id: u32,
+    /// Interface of the global
+    pub interface: String,
+    /// Maximum supported version of the global
+    pub version: u32,
+    /// Whether the global was bound by the probe
+    ///
+    /// Only the globals whose interface is known to the probe can be bound.
+    pub bound: bool,
+    /// The events received during the first roundtrip after binding the global
+    ///
+    /// This includes the events sent to the objects created by these events.
+    pub events: Vec<RawEvent>,
+}
+
+type Binder = fn(&Main<wl_registry::WlRegistry>, u32) -> Main<AnonymousObject>;
+
+fn bind_anonymous<I>(registry: &Main<wl_registry::WlRegistry>, id: u32) -> Main<AnonymousObject>
+where
+    I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+{
+    Main::wrap(registry.bind::<I>(1, id).as_ref().clone().anonymize().inner)
+}
+
+/// An utility to probe the capabilities of a compositor
+///
+/// This binds every advertised global at version 1 on a dedicated event queue, and
+/// collects the events the compositor sends in response during one roundtrip, in
+/// the manner of `wayland-info`.
+///
+/// The globals of the core protocol are known by default, the interfaces of other
+/// protocols need to be registered using `with_interface()`, as the probe needs their
+/// definition to parse their events. Other globals are listed but not bound.
+///
+/// The bound objects are never destroyed, as for most of them the protocol does not
+/// provide any mean to destroy them, a probe should thus be run on a short-lived connection.
+pub struct CapabilityProbe {
+    binders: Vec<(&'static str, Binder)>,
+}
+
+impl CapabilityProbe {
+    /// Create a probe knowing the globals of the core protocol
+    pub fn new() -> CapabilityProbe {
+        use crate::protocol::*;
+        CapabilityProbe { binders: Vec::new() }
+            .with_interface::<wl_compositor::WlCompositor>()
+            .with_interface::<wl_data_device_manager::WlDataDeviceManager>()
+            .with_interface::<wl_output::WlOutput>()
+            .with_interface::<wl_seat::WlSeat>()
+            .with_interface::<wl_shell::WlShell>()
+            .with_interface::<wl_shm::WlShm>()
+            .with_interface::<wl_subcompositor::WlSubcompositor>()
+    }
+
+    /// Register an additional global interface to bind
+    pub fn with_interface<I>(mut self) -> CapabilityProbe
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+    {
+        self.binders.retain(|&(name, _)| name != I::NAME);
+        self.binders.push((I::NAME, bind_anonymous::<I>));
+        self
+    }
+
+    /// Run the probe on a connection
+    ///
+    /// This does two roundtrips with the server, and returns the report of all the
+    /// globals it advertised, sorted by id.
+    pub fn run(&self, display: &Display) -> Result<Vec<GlobalReport>, DispatchError> {
+        let mut queue = display.create_event_queue();
+        let attached = (**display).clone().attach(queue.token());
+        let manager = GlobalManager::new(&attached);
+
+        // retrieve the list of globals
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        let mut reports = Vec::new();
+        // maps the id of the objects to the index of the global they are related to
+        let mut owners = HashMap::new();
+        for (id, interface, version) in manager.list() {
+            let binder = self.binders.iter().find(|&&(name, _)| name == interface);
+            if let Some(&(_, binder)) = binder {
+                let object = binder(&manager.registry, id);
+                owners.insert(object.as_ref().id(), reports.len());
+            }
+            reports.push(GlobalReport {
+                id,
+                interface,
+                version,
+                bound: binder.is_some(),
+                events: Vec::new(),
+            });
+        }
+
+        // collect the initial events
+        queue.sync_roundtrip(&mut (), |event, object, _| {
+            let owner = match owners.get(&object.as_ref().id()) {
+                Some(&owner) => owner,
+                None => return,
+            };
+            for arg in &event.args {
+                if let Argument::NewId(Some(ref child)) = *arg {
+                    owners.insert(child.as_ref().id(), owner);
+                }
+            }
+            reports[owner].events.push(event);
+        })?;
+
+        reports.sort_by_key(|report| report.id);
+        Ok(reports)
+    }
+}
+
+impl Default for CapabilityProbe {
+    fn default() -> CapabilityProbe {
+        CapabilityProbe::new()
+    }
+}
+
 /// A trait for implementation of the global advertisement
 ///
 /// It is automatically implemented for `FnMut(Main<I>, DispatchData)` closures,
@@ -289,6 +501,8 @@ where
 /// to a filter in this callback if you plan to do so.. The error case happens if the server
 /// advertised a lower version of the global than the one you requested, in which case you
 /// are given the version it advertised in the error method, if you want to handle it graciously.
+/// Otherwise the global is bound with the highest version supported by both the server and
+/// the protocol files of this crate, so the version given to the macro is a minimum.
 ///
 /// You can also provide closures for the various callbacks, in this case the errors will
 /// be ignored. However, due to a lack of capability of rustc's inference, you'll likely need
@@ -315,11 +529,9 @@ macro_rules! global_filter {
                 callbacks.push((
                     <$interface as Interface>::NAME,
                     Box::new(move |id, version, registry: Attached<wl_registry::WlRegistry>, ddata: DispatchData| {
-                        if version < $version {
-                            GlobalImplementor::<$interface>::error(&mut cb, version, ddata);
-                        } else {
-                            let proxy = registry.bind::<$interface>(version, id);
-                            GlobalImplementor::<$interface>::new_global(&mut cb, proxy, ddata);
+                        match registry.bind_range::<$interface>(id, version, $version..=::std::u32::MAX) {
+                            Ok(proxy) => GlobalImplementor::<$interface>::new_global(&mut cb, proxy, ddata),
+                            Err(_) => GlobalImplementor::<$interface>::error(&mut cb, version, ddata),
                         }
                     }) as Box<_>
                 ));
@@ -0,0 +1,141 @@
+//! Keyboard keymap handling
+//!
+//! The compositor sends the keymap of a keyboard with the `wl_keyboard.keymap` event, as a
+//! file descriptor to map in memory. This module provides `Keymap`, which takes ownership of
+//! this file descriptor, maps it and unmaps it once dropped.
+//!
+//! ```no_run
+//! # use wayland_client::{Main, protocol::wl_keyboard};
+//! # fn setup(keyboard: Main<wl_keyboard::WlKeyboard>) {
+//! use wayland_client::keymap::Keymap;
+//!
+//! keyboard.quick_assign(|keyboard, event, _| match Keymap::from_event(&keyboard, event) {
+//!     Ok(keymap) => {
+//!         let keymap = keymap.expect("Failed to map the keymap");
+//!         // give it to libxkbcommon
+//!         println!("{}", keymap.as_str().unwrap());
+//!     }
+//!     Err(wl_keyboard::Event::Key { key, state, .. }) => {
+//!         println!("Key {} is now {:?}", key, state);
+//!     }
+//!     Err(_) => {}
+//! });
+//! # }
+//! ```
+//!
+//! If the keyboard has been destroyed when its keymap arrives, the event is handled by the
+//! `ZombiePolicy` of the display, which closes the file descriptor by default.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::{ptr, slice, str};
+
+use nix::sys::mman;
+use nix::unistd;
+
+use crate::protocol::wl_keyboard::{Event, KeymapFormat, WlKeyboard};
+use crate::shm::nix_to_io;
+
+/// A keymap sent by the compositor, mapped in memory
+pub struct Keymap {
+    format: KeymapFormat,
+    map: *mut u8,
+    len: usize,
+}
+
+// the mapping is read-only and owned by the keymap
+unsafe impl Send for Keymap {}
+unsafe impl Sync for Keymap {}
+
+impl Keymap {
+    /// Map the keymap of a `wl_keyboard.keymap` event
+    ///
+    /// Gives the event back if it is not a keymap event, so that it can still be handled.
+    /// Otherwise returns the keymap, or an error if it could not be mapped: the file
+    /// descriptor of the event is closed in both cases.
+    pub fn from_event(keyboard: &WlKeyboard, event: Event) -> Result<io::Result<Keymap>, Event> {
+        match event {
+            Event::Keymap { format, fd, size } => {
+                Ok(unsafe { Keymap::from_raw_fd(format, fd, size, keyboard.as_ref().version()) })
+            }
+            event => Err(event),
+        }
+    }
+
+    /// Map a keymap from its file descriptor
+    ///
+    /// Since version 7 of `wl_keyboard`, the file descriptor must be mapped privately, the
+    /// compositor being allowed to send a read-only one shared with its other clients. Older
+    /// compositors may expect a shared mapping, which is tried first for these versions.
+    ///
+    /// The file descriptor is closed, whether the mapping succeeds or not.
+    ///
+    /// # Safety
+    ///
+    /// This takes ownership of the file descriptor, which must be valid and not be used
+    /// anymore once this function returns.
+    pub unsafe fn from_raw_fd(
+        format: KeymapFormat,
+        fd: RawFd,
+        size: u32,
+        version: u32,
+    ) -> io::Result<Keymap> {
+        let len = size as usize;
+        let ret = if format == KeymapFormat::NoKeymap || len == 0 {
+            // the fd may be `/dev/null`, which can't be mapped
+            Ok(Keymap { format, map: ptr::null_mut(), len: 0 })
+        } else {
+            let shared =
+                if version < 7 { map(fd, len, mman::MapFlags::MAP_SHARED).ok() } else { None };
+            match shared {
+                Some(map) => Ok(map),
+                None => map(fd, len, mman::MapFlags::MAP_PRIVATE),
+            }
+            .map(|map| Keymap { format, map, len })
+        };
+        // the mapping stays valid once the fd is closed
+        let _ = unistd::close(fd);
+        ret
+    }
+
+    /// The format of the keymap
+    pub fn format(&self) -> KeymapFormat {
+        self.format
+    }
+
+    /// The contents of the keymap
+    ///
+    /// This is empty for the `NoKeymap` format.
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.map.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.map, self.len) }
+        }
+    }
+
+    /// The contents of the keymap as a string
+    ///
+    /// The keymaps of the `XkbV1` format are null-terminated strings, the contents are
+    /// truncated at the first null byte.
+    pub fn as_str(&self) -> Result<&str, str::Utf8Error> {
+        let bytes = self.as_bytes();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        str::from_utf8(&bytes[..end])
+    }
+}
+
+impl Drop for Keymap {
+    fn drop(&mut self) {
+        if !self.map.is_null() {
+            unsafe {
+                let _ = mman::munmap(self.map as *mut _, self.len);
+            }
+        }
+    }
+}
+
+fn map(fd: RawFd, len: usize, flags: mman::MapFlags) -> io::Result<*mut u8> {
+    let map = unsafe { mman::mmap(ptr::null_mut(), len, mman::ProtFlags::PROT_READ, flags, fd, 0) };
+    map.map(|ptr| ptr as *mut u8).map_err(nix_to_io)
+}
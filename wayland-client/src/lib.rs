@@ -86,12 +86,57 @@
 //! When this is done, the library will be loaded a runtime rather than directly linked. And trying
 //! to create a `Display` on a system that does not have this library will return a `NoWaylandLib`
 //! error.
+//!
+//! ## Selecting the implementation at runtime
+//!
+//! With the `use_system_lib` cargo feature, the rust implementation is still available, and
+//! each `Display` uses the one given to `Display::connect_with_backend()` or
+//! `ConnectOptions::backend()`: `Backend::Native` by default, or `Backend::Rust`. The objects
+//! of a connection always use its implementation, and some methods are documented as
+//! unsupported on the connections using one of them. This allows checking that both
+//! implementations behave the same.
+//!
+//! ## EGL and Vulkan
+//!
+//! The EGL and Vulkan drivers share the wayland connection of the application by calling into
+//! `libwayland-client.so` on the `wl_display` and `wl_surface` pointers they are given: they
+//! send requests and dispatch events on their own queues through this library. These pointers
+//! are provided by the `use_system_lib` cargo feature.
+//!
+//! The `c_abi` cargo feature provides them for the rust implementation as well: it exports the
+//! `wl_display_*`, `wl_event_queue_*` and `wl_proxy_*` functions libEGL uses, implemented on
+//! top of the rust objects, and `Display::get_display_ptr()` and `Proxy::c_ptr()` return
+//! handles these functions accept. A driver loaded at runtime only finds these functions if the
+//! executable exports them, which needs it to be linked with `-Wl,--export-dynamic`. With the
+//! `use_system_lib` feature as well, the functions forward the pointers of the native
+//! implementation to `libwayland-client.so`, which is then always loaded at runtime.
+//!
+//! ## `raw-window-handle` support
+//!
+//! The `raw-window-handle` cargo feature implements the traits of the `raw-window-handle`
+//! crate for `Display` and `WlSurface`, as well as for `WindowHandle` which pairs them, to use
+//! them with graphics libraries such as `wgpu`, `glutin` or `softbuffer`. As these handles are
+//! pointers to `libwayland-client.so` objects, this feature enables `use_system_lib`.
+//!
+//! ## `mio` support
+//!
+//! The `mio` cargo feature implements the `Source` trait of `mio` for `Display` and `EventQueue`,
+//! to register the connection in a `mio::Poll`. Once it is readable, read and dispatch its events
+//! with `EventQueue::dispatch_ready()`, which reads the socket until it is drained as `mio`
+//! requires, and handles the spurious wakeups.
+//!
+//! ## `tracing` support
+//!
+//! The `tracing` cargo feature instruments the library with the `tracing` crate: the connection
+//! setup and the roundtrips are covered by `debug` spans, and each invocation of a filter (or of
+//! the fallback closure) by a `trace` span whose fields are the interface, opcode and id of the
+//! object receiving the event. With the rust implementation, a `trace` event is also emitted for
+//! each message decoded from the socket.
 
 #![warn(missing_docs)]
 
 #[macro_use]
 extern crate bitflags;
-#[cfg(not(feature = "use_system_lib"))]
 #[macro_use]
 extern crate downcast_rs as downcast;
 
@@ -100,29 +145,61 @@ extern crate wayland_sys;
 
 mod display;
 mod event_queue;
+mod frame;
 mod globals;
+pub mod keymap;
+#[cfg(feature = "mio")]
+mod mio_source;
 mod proxy;
+mod response;
+mod scope;
+pub mod shm;
+pub mod surface;
+mod watchdog;
+#[cfg(feature = "raw-window-handle")]
+mod window_handle;
 
 pub use anonymous_object::AnonymousObject;
-pub use display::{ConnectError, Display, ProtocolError};
-pub use event_queue::{EventQueue, QueueToken, ReadEventsGuard};
-pub use globals::{GlobalError, GlobalEvent, GlobalImplementor, GlobalManager};
+pub use display::{
+    Backend, ConnectError, ConnectOptions, ConnectionState, DispatchError, Display, FlushProgress,
+    ObjectInfo, ProtocolError, UnhandledEvent, ZombiePolicy,
+};
+pub use event_queue::{
+    DispatchStats, EventQueue, HandoffError, QueueHandoff, QueueToken, ReadEventsGuard,
+    SlowDispatch,
+};
+pub use frame::{FrameFuture, FrameScheduler};
+pub use globals::{
+    CapabilityProbe, GlobalError, GlobalEvent, GlobalImplementor, GlobalInfo, GlobalManager,
+    GlobalReport, RegistrySnapshot,
+};
 pub use imp::ProxyMap;
-pub use proxy::{Attached, Main, Proxy};
+pub use proxy::{Attached, ForeignProxyError, Main, ObjectId, Proxy, Weak};
+pub use response::ResponseFuture;
+pub use scope::DestructionScope;
+pub use watchdog::{Watchdog, WatchdogEvent};
 pub use wayland_commons::{
     filter::{DispatchData, Filter},
+    set_thread_guard_policy,
     user_data::UserData,
-    Interface, MessageGroup, NoMessage,
+    wire::Strictness,
+    Interface, MessageGroup, NoMessage, ThreadGuardPolicy, ThreadGuardViolation,
 };
+#[cfg(feature = "raw-window-handle")]
+pub use window_handle::WindowHandle;
 
 // rust implementation
 #[cfg(not(feature = "use_system_lib"))]
 #[path = "rust_imp/mod.rs"]
 mod imp;
-// C-lib based implementation
+// both implementations, selected when connecting
 #[cfg(feature = "use_system_lib")]
-#[path = "native_lib/mod.rs"]
+#[path = "dual_imp/mod.rs"]
 mod imp;
+#[cfg(feature = "use_system_lib")]
+mod native_lib;
+#[cfg(feature = "use_system_lib")]
+mod rust_imp;
 
 /// C-associated types
 ///
@@ -137,10 +214,13 @@ pub mod protocol {
     #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
     #![allow(missing_docs, clippy::all)]
 
-    pub(crate) use crate::{AnonymousObject, Attached, Main, Proxy, ProxyMap};
+    pub(crate) use crate::{AnonymousObject, Attached, Main, Proxy, ProxyMap, ResponseFuture};
     pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
     pub(crate) use wayland_commons::smallvec;
-    pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+    pub(crate) use wayland_commons::wire::{
+        Argument, ArgumentDesc, ArgumentInfo, ArgumentRef, ArgumentType, EnumDesc, EnumEntry,
+        InterfaceDesc, Message, MessageDesc, MessageRef,
+    };
     pub(crate) use wayland_commons::{Interface, MessageGroup};
     pub(crate) use wayland_sys as sys;
     include!(concat!(env!("OUT_DIR"), "/wayland_api.rs"));
@@ -154,7 +234,7 @@ mod anonymous_object {
     ///
     /// A special Interface implementation representing an
     /// handle to an object for which the interface is not known.
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct AnonymousObject(pub(crate) Proxy<AnonymousObject>);
 
     impl Interface for AnonymousObject {
@@ -0,0 +1,61 @@
+//! Implementations of the `mio` `Source` trait
+//!
+//! Both `Display` and `EventQueue` register the file descriptor of the connection, so only one
+//! of them can be registered in a given `mio::Poll`. Once it is reported readable, the events
+//! are read and dispatched with `EventQueue::dispatch_ready()`.
+
+use std::io;
+
+use mio::event::Source;
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+
+use crate::{Display, EventQueue};
+
+impl Source for Display {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.get_connection_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.get_connection_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.get_connection_fd()).deregister(registry)
+    }
+}
+
+impl Source for EventQueue {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.display().get_connection_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.display().get_connection_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.display().get_connection_fd()).deregister(registry)
+    }
+}
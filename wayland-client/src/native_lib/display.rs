@@ -1,11 +1,17 @@
 use std::io;
 use std::os::unix::io::RawFd;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::protocol::wl_display::WlDisplay;
 use wayland_sys::client::*;
 
-use crate::{ConnectError, Proxy};
+use wayland_commons::capture::Capture;
+use wayland_commons::wire::Strictness;
+
+use crate::{
+    ConnectError, ConnectionState, DispatchError, FlushProgress, ObjectInfo, Proxy, ZombiePolicy,
+};
 
 use super::{EventQueueInner, ProxyInner};
 
@@ -19,6 +25,8 @@ pub(crate) struct DisplayGuard {
     external: bool,
 }
 
+pub(crate) type StateListener = Box<dyn FnMut(&ConnectionState) + Send>;
+
 unsafe impl Send for DisplayInner {}
 unsafe impl Sync for DisplayInner {}
 
@@ -27,14 +35,11 @@ unsafe fn make_display(ptr: *mut wl_display) -> Result<Arc<DisplayInner>, Connec
         return Err(ConnectError::NoCompositorListening);
     }
 
-    let mut inner = DisplayInner {
-        proxy: Proxy::from_c_ptr(ptr as *mut _),
-        display: Arc::new(DisplayGuard { ptr, external: false }),
-    };
-
-    inner.proxy.inner.display = Some(Arc::downgrade(&inner.display));
+    let display = Arc::new(DisplayGuard { ptr, external: false });
+    let mut proxy = ProxyInner::from_c_ptr::<WlDisplay>(ptr as *mut _);
+    proxy.display = Some(Arc::downgrade(&display));
 
-    Ok(Arc::new(inner))
+    Ok(Arc::new(DisplayInner { proxy: Proxy::wrap(proxy), display }))
 }
 
 impl DisplayInner {
@@ -56,15 +61,26 @@ impl DisplayInner {
         self.display.ptr
     }
 
-    pub(crate) fn flush(&self) -> io::Result<()> {
+    pub(crate) fn flush(&self) -> Result<FlushProgress, DispatchError> {
         let ret = unsafe { ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_display_flush, self.ptr()) };
         if ret >= 0 {
-            Ok(())
+            Ok(FlushProgress { written: ret as usize, remaining: 0 })
         } else {
-            Err(io::Error::last_os_error())
+            Err(self.last_error())
         }
     }
 
+    // The error of the last failed call to the library, which reports protocol errors as EPROTO
+    pub(crate) fn last_error(&self) -> DispatchError {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(::nix::errno::Errno::EPROTO as i32) {
+            if let Some(e) = self.protocol_error() {
+                return DispatchError::Protocol(e);
+            }
+        }
+        DispatchError::Backend(err)
+    }
+
     pub(crate) fn create_event_queue(me: &Arc<DisplayInner>) -> EventQueueInner {
         unsafe {
             let ptr = ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_display_create_queue, me.ptr());
@@ -72,6 +88,13 @@ impl DisplayInner {
         }
     }
 
+    pub(crate) unsafe fn create_event_queue_from_external(
+        me: &Arc<DisplayInner>,
+        ptr: *mut wl_event_queue,
+    ) -> EventQueueInner {
+        EventQueueInner::from_external(me.clone(), ptr)
+    }
+
     pub(crate) fn get_proxy(&self) -> &Proxy<WlDisplay> {
         &self.proxy
     }
@@ -102,6 +125,59 @@ impl DisplayInner {
         }
     }
 
+    pub(crate) fn state(&self) -> crate::ConnectionState {
+        let ret = unsafe { ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_display_get_error, self.ptr()) };
+        if ret == 0 {
+            crate::ConnectionState::Ready
+        } else if let Some(e) = self.protocol_error() {
+            crate::ConnectionState::ErrorDeferred(e)
+        } else {
+            crate::ConnectionState::Dead(io::Error::from_raw_os_error(ret).kind())
+        }
+    }
+
+    // The connection is never reset, so its state only changes when it fails
+    pub(crate) fn set_state_listener(&self, _listener: Option<StateListener>) {}
+
+    pub(crate) fn socket_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    pub(crate) fn set_socket_path(&self, _path: PathBuf) {}
+
+    pub(crate) unsafe fn reconnect(&self, _fd: RawFd) {
+        panic!("[wayland-client] Reconnecting is only available with the rust implementation.")
+    }
+
+    pub(crate) fn objects(&self) -> Vec<ObjectInfo> {
+        panic!(
+            "[wayland-client] Listing the objects is only available with the rust implementation."
+        )
+    }
+
+    pub(crate) fn start_capture(&self) {}
+
+    pub(crate) fn stop_capture(&self) -> Option<Capture> {
+        None
+    }
+
+    pub(crate) fn pending_fds(&self) -> usize {
+        panic!("[wayland-client] Counting the held fds is only available with the rust implementation.")
+    }
+
+    // libwayland discards the events of destroyed proxies
+    pub(crate) fn set_zombie_policy(&self, _policy: ZombiePolicy) {}
+
+    pub(crate) fn zombie_events(&self) -> usize {
+        panic!("[wayland-client] Counting the zombie events is only available with the rust implementation.")
+    }
+
+    pub(crate) fn set_strictness(&self, _strictness: Strictness) {}
+
+    pub(crate) fn set_max_message_size(&self, _size: usize) {}
+
+    pub(crate) fn set_request_staging(&self, _enabled: bool) {}
+
     pub(crate) unsafe fn from_external(display_ptr: *mut wl_display) -> Arc<DisplayInner> {
         Arc::new(DisplayInner {
             proxy: Proxy::wrap(ProxyInner::from_external_display(display_ptr as *mut _)),
@@ -1,8 +1,11 @@
 use std::cell::RefCell;
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{AnonymousObject, DispatchData, Main, RawEvent};
+use crate::{
+    AnonymousObject, DispatchData, DispatchError, DispatchStats, Main, RawEvent, SlowDispatch,
+};
 use wayland_sys::client::*;
 
 use super::DisplayInner;
@@ -24,17 +27,28 @@ where
     DISPATCH_METADATA.set(&RefCell::new((fb, data)), || f())
 }
 
+pub(crate) type SlowDispatchHook = (Duration, Box<dyn FnMut(SlowDispatch)>);
+
 pub(crate) struct EventQueueInner {
     wlevq: *mut wl_event_queue,
     inner: Arc<super::DisplayInner>,
+    // whether the queue was created by this crate and should be destroyed with it
+    owned: bool,
 }
 
 impl EventQueueInner {
     pub(crate) fn new(inner: Arc<DisplayInner>, wlevq: *mut wl_event_queue) -> EventQueueInner {
-        EventQueueInner { inner, wlevq }
+        EventQueueInner { inner, wlevq, owned: true }
+    }
+
+    pub(crate) unsafe fn from_external(
+        inner: Arc<DisplayInner>,
+        wlevq: *mut wl_event_queue,
+    ) -> EventQueueInner {
+        EventQueueInner { inner, wlevq, owned: false }
     }
 
-    pub(crate) fn dispatch<F>(&self, data: DispatchData, fallback: F) -> io::Result<u32>
+    pub(crate) fn dispatch<F>(&self, data: DispatchData, fallback: F) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
@@ -50,12 +64,16 @@ impl EventQueueInner {
             if ret >= 0 {
                 Ok(ret as u32)
             } else {
-                Err(io::Error::last_os_error())
+                Err(self.inner.last_error())
             }
         })
     }
 
-    pub(crate) fn dispatch_pending<F>(&self, data: DispatchData, fallback: F) -> io::Result<u32>
+    pub(crate) fn dispatch_pending<F>(
+        &self,
+        data: DispatchData,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
@@ -71,12 +89,16 @@ impl EventQueueInner {
             if ret >= 0 {
                 Ok(ret as u32)
             } else {
-                Err(io::Error::last_os_error())
+                Err(self.inner.last_error())
             }
         })
     }
 
-    pub(crate) fn sync_roundtrip<F>(&self, data: DispatchData, fallback: F) -> io::Result<u32>
+    pub(crate) fn sync_roundtrip<F>(
+        &self,
+        data: DispatchData,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
@@ -92,11 +114,43 @@ impl EventQueueInner {
             if ret >= 0 {
                 Ok(ret as u32)
             } else {
-                Err(io::Error::last_os_error())
+                Err(self.inner.last_error())
             }
         })
     }
 
+    pub(crate) fn dispatch_some<F>(
+        &self,
+        _data: DispatchData,
+        _max_events: u32,
+        _fallback: F,
+    ) -> Result<(u32, bool), DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        panic!(
+            "[wayland-client] Dispatching a limited number of events is only available with the rust implementation."
+        )
+    }
+
+    // The connection of the system library is never reset
+    pub(crate) fn take_reconnection(&self) -> bool {
+        false
+    }
+
+    // The dispatching is done by the system library, which does not time the events
+    pub(crate) fn start_stats(&self) {}
+
+    pub(crate) fn stats(&self) -> Option<DispatchStats> {
+        None
+    }
+
+    pub(crate) fn stop_stats(&self) -> Option<DispatchStats> {
+        None
+    }
+
+    pub(crate) fn set_slow_dispatch_hook(&self, _hook: Option<SlowDispatchHook>) {}
+
     pub(crate) fn prepare_read(&self) -> Result<(), ()> {
         let ret = unsafe {
             ffi_dispatch!(
@@ -135,8 +189,10 @@ impl EventQueueInner {
 
 impl Drop for EventQueueInner {
     fn drop(&mut self) {
-        unsafe {
-            ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_event_queue_destroy, self.wlevq);
+        if self.owned {
+            unsafe {
+                ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_event_queue_destroy, self.wlevq);
+            }
         }
     }
 }
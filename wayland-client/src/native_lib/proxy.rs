@@ -1,9 +1,10 @@
 use std::cell::RefCell;
+use std::ffi::CStr;
 use std::os::raw::{c_int, c_void};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Weak};
 
-use crate::{Interface, Main, Proxy, RawEvent};
+use crate::{AnonymousObject, Interface, Main, Proxy, RawEvent};
 use wayland_commons::filter::Filter;
 use wayland_commons::user_data::UserData;
 use wayland_commons::wire::ArgumentType;
@@ -17,14 +18,25 @@ use wayland_sys::common::*;
 pub struct ProxyInternal {
     alive: AtomicBool,
     user_data: UserData,
+    serial: u64,
+    // the serials of the objects involved in the creation of this one
+    parents: Vec<u64>,
 }
 
 impl ProxyInternal {
-    pub fn new(user_data: UserData) -> ProxyInternal {
-        ProxyInternal { alive: AtomicBool::new(true), user_data }
+    pub fn new(user_data: UserData, parents: Vec<u64>) -> ProxyInternal {
+        ProxyInternal {
+            alive: AtomicBool::new(true),
+            user_data,
+            serial: crate::proxy::next_object_serial(),
+            parents,
+        }
     }
 }
 
+// the serials of the external proxies are derived from their address, the others are counted
+const EXTERNAL_SERIAL: u64 = 1 << 63;
+
 pub(crate) struct ProxyInner {
     internal: Option<Arc<ProxyInternal>>,
     ptr: *mut wl_proxy,
@@ -84,6 +96,13 @@ impl ProxyInner {
         unsafe { ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_proxy_get_id, self.ptr) }
     }
 
+    // libwayland dispatches the events of a queue in order
+    pub(crate) fn is_high_priority(&self) -> bool {
+        false
+    }
+
+    pub(crate) fn set_high_priority(&self, _high_priority: bool) {}
+
     pub(crate) fn user_data(&self) -> &UserData {
         static INVALID_USERDATA: UserData = UserData::new();
         if let Some(ref inner) = self.internal {
@@ -128,11 +147,21 @@ impl ProxyInner {
                     panic!("Attemping to create an object from a non-attached proxy.");
                 }
                 unsafe {
+                    let mut parents = vec![self.serial()];
                     let ptr = msg.as_raw_c_in(|opcode, args| {
                         assert!(
                             args[nid_idx].o.is_null(),
                             "Trying to use 'send_constructor' with a non-placeholder object."
                         );
+                        let signature = I::Request::MESSAGES[opcode as usize].signature;
+                        for (arg, &kind) in args.iter().zip(signature) {
+                            if kind == ArgumentType::Object && !arg.o.is_null() {
+                                let arg = ProxyInner::from_c_ptr::<AnonymousObject>(
+                                    arg.o as *mut wl_proxy,
+                                );
+                                parents.push(arg.serial());
+                            }
+                        }
                         ffi_dispatch!(
                             WAYLAND_CLIENT_HANDLE,
                             wl_proxy_marshal_array_constructor_versioned,
@@ -143,7 +172,7 @@ impl ProxyInner {
                             version
                         )
                     });
-                    let mut new_proxy = ProxyInner::init_from_c_ptr::<J>(ptr);
+                    let mut new_proxy = ProxyInner::init_child_from_c_ptr::<J>(ptr, parents);
                     new_proxy.display = self.display.clone();
                     Some(new_proxy)
                 }
@@ -180,6 +209,25 @@ impl ProxyInner {
         ret
     }
 
+    pub(crate) fn serial(&self) -> u64 {
+        match self.internal {
+            Some(ref internal) => internal.serial,
+            None => self.ptr as usize as u64 | EXTERNAL_SERIAL,
+        }
+    }
+
+    pub(crate) fn parents(&self) -> Vec<u64> {
+        self.internal.as_ref().map(|internal| internal.parents.clone()).unwrap_or_default()
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakProxyInner {
+        WeakProxyInner {
+            internal: self.internal.as_ref().map(Arc::downgrade),
+            ptr: self.ptr,
+            display: self.display.clone(),
+        }
+    }
+
     pub(crate) fn equals(&self, other: &ProxyInner) -> bool {
         if !self.is_alive() {
             return false;
@@ -221,6 +269,10 @@ impl ProxyInner {
     }
 
     pub(crate) fn c_ptr(&self) -> *mut wl_proxy {
+        if !self.is_alive() {
+            // the proxy has been freed by the C library
+            return std::ptr::null_mut();
+        }
         self.wrapping.unwrap_or(self.ptr)
     }
 
@@ -228,7 +280,7 @@ impl ProxyInner {
     where
         I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
         E: From<(Main<I>, I::Event)> + 'static,
-        I::Event: MessageGroup<Map = super::ProxyMap>,
+        I::Event: MessageGroup<Map = crate::ProxyMap>,
     {
         if self.is_external() {
             panic!("Cannot assign an external proxy to a filter.");
@@ -250,10 +302,73 @@ impl ProxyInner {
         }
     }
 
+    pub fn reassign<I, E>(&self, filter: Filter<E>)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        E: From<(Main<I>, I::Event)> + 'static,
+        I::Event: MessageGroup<Map = crate::ProxyMap>,
+    {
+        self.set_implem::<I>(Some(Box::new(move |evt, obj, data| {
+            filter.send((obj, evt).into(), data)
+        })));
+    }
+
+    pub fn unassign<I>(&self)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+    {
+        self.set_implem::<I>(None);
+    }
+
+    fn set_implem<I>(&self, implem: Option<BoxedCallback<I>>)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+    {
+        if self.is_external() {
+            panic!("Cannot assign an external proxy to a filter.");
+        }
+
+        if !self.is_alive() {
+            return;
+        }
+
+        unsafe {
+            let user_data =
+                &*(ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_proxy_get_user_data, self.ptr)
+                    as *mut ProxyUserData<I>);
+            match user_data.implem.try_borrow_mut() {
+                Ok(mut guard) => *guard = implem,
+                // we are in the callback of this object, replace it once it returns
+                Err(_) => *user_data.pending_implem.borrow_mut() = Some(implem),
+            }
+        }
+    }
+
+    pub fn assign_threadsafe<I, F>(&self, f: F)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        F: FnMut(Main<I>, I::Event, crate::DispatchData) + Send + 'static,
+        I::Event: MessageGroup<Map = crate::ProxyMap>,
+    {
+        // the C library dispatches the events on the thread reading the queue, the filters
+        // are not guarded against other threads, so this is the same as a regular assignment
+        let f = RefCell::new(f);
+        self.assign::<I, _>(Filter::new(move |(proxy, event), _, data| {
+            (*f.borrow_mut())(proxy, event, data)
+        }));
+    }
+
     pub(crate) unsafe fn init_from_c_ptr<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>>(
         ptr: *mut wl_proxy,
     ) -> Self {
-        let new_user_data = Box::new(ProxyUserData::<I>::new(UserData::new()));
+        Self::init_child_from_c_ptr::<I>(ptr, Vec::new())
+    }
+
+    unsafe fn init_child_from_c_ptr<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>>(
+        ptr: *mut wl_proxy,
+        parents: Vec<u64>,
+    ) -> Self {
+        let new_user_data = Box::new(ProxyUserData::<I>::new(UserData::new(), parents));
         let internal = new_user_data.internal.clone();
 
         ffi_dispatch!(
@@ -269,11 +384,56 @@ impl ProxyInner {
         ProxyInner { internal: Some(internal), ptr, wrapping: Some(ptr), display: None }
     }
 
+    pub(crate) unsafe fn try_init_from_c_ptr<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>>(
+        ptr: *mut wl_proxy,
+    ) -> Result<Self, crate::ForeignProxyError> {
+        if ptr.is_null() {
+            return Ok(Self::dead());
+        }
+
+        let class = ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_proxy_get_class, ptr);
+        if class.is_null() || CStr::from_ptr(class).to_bytes() != I::NAME.as_bytes() {
+            return Err(crate::ForeignProxyError::WrongInterface);
+        }
+
+        let listener = ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_proxy_get_listener, ptr);
+        if listener == &::wayland_sys::RUST_MANAGED as *const u8 as *const _ {
+            // the proxy is already ours
+            let mut inner = Self::from_c_ptr::<I>(ptr);
+            inner.wrapping = Some(ptr);
+            return Ok(inner);
+        } else if !listener.is_null() {
+            return Err(crate::ForeignProxyError::ExternallyManaged);
+        }
+
+        let new_user_data =
+            Box::into_raw(Box::new(ProxyUserData::<I>::new(UserData::new(), Vec::new())));
+        let ret = ffi_dispatch!(
+            WAYLAND_CLIENT_HANDLE,
+            wl_proxy_add_dispatcher,
+            ptr,
+            proxy_dispatcher::<I>,
+            &::wayland_sys::RUST_MANAGED as *const _ as *const _,
+            new_user_data as *mut _
+        );
+        if ret != 0 {
+            // a dispatcher was already set by another library
+            drop(Box::from_raw(new_user_data));
+            return Err(crate::ForeignProxyError::ExternallyManaged);
+        }
+        let internal = (*new_user_data).internal.clone();
+
+        // We are a Main<_>, so ptr == wrapping
+        Ok(ProxyInner { internal: Some(internal), ptr, wrapping: Some(ptr), display: None })
+    }
+
     fn dead() -> Self {
         ProxyInner {
             internal: Some(Arc::new(ProxyInternal {
                 alive: AtomicBool::new(false),
                 user_data: UserData::new(),
+                serial: crate::proxy::next_object_serial(),
+                parents: Vec::new(),
             })),
             ptr: std::ptr::null_mut(),
             wrapping: None,
@@ -307,6 +467,32 @@ impl ProxyInner {
     }
 }
 
+#[derive(Clone)]
+pub(crate) struct WeakProxyInner {
+    internal: Option<Weak<ProxyInternal>>,
+    ptr: *mut wl_proxy,
+    display: Option<Weak<super::display::DisplayGuard>>,
+}
+
+unsafe impl Send for WeakProxyInner {}
+unsafe impl Sync for WeakProxyInner {}
+
+impl WeakProxyInner {
+    pub(crate) fn upgrade(&self) -> Option<ProxyInner> {
+        let internal = match self.internal {
+            Some(ref internal) => Some(internal.upgrade()?),
+            None => None,
+        };
+        let inner =
+            ProxyInner { internal, ptr: self.ptr, wrapping: None, display: self.display.clone() };
+        if inner.is_alive() {
+            Some(inner)
+        } else {
+            None
+        }
+    }
+}
+
 impl Clone for ProxyInner {
     fn clone(&self) -> ProxyInner {
         let mut new = ProxyInner {
@@ -345,16 +531,21 @@ impl Drop for ProxyInner {
 
 type BoxedCallback<I> = Box<dyn Fn(<I as Interface>::Event, Main<I>, crate::DispatchData<'_>)>;
 
+// the internal data comes first, so that it can be read without knowing the interface
+#[repr(C)]
 struct ProxyUserData<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>> {
     internal: Arc<ProxyInternal>,
     implem: RefCell<Option<BoxedCallback<I>>>,
+    // set by a reassignment from within the callback, applied once it returns
+    pending_implem: RefCell<Option<Option<BoxedCallback<I>>>>,
 }
 
 impl<I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>> ProxyUserData<I> {
-    fn new(user_data: UserData) -> ProxyUserData<I> {
+    fn new(user_data: UserData, parents: Vec<u64>) -> ProxyUserData<I> {
         ProxyUserData {
-            internal: Arc::new(ProxyInternal::new(user_data)),
+            internal: Arc::new(ProxyInternal::new(user_data, parents)),
             implem: RefCell::new(None),
+            pending_implem: RefCell::new(None),
         }
     }
 }
@@ -375,6 +566,14 @@ where
     // we'll abort the process, so no access to corrupted data is possible.
     let ret = ::std::panic::catch_unwind(move || {
         let must_destroy = I::Event::MESSAGES[opcode as usize].destructor;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "dispatch",
+            interface = I::NAME,
+            opcode,
+            id = ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_proxy_get_id, proxy)
+        )
+        .entered();
         // retrieve the impl
         let user_data = ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_proxy_get_user_data, proxy);
         {
@@ -414,6 +613,12 @@ where
                 }
             }
         }
+        {
+            let user_data = &*(user_data as *mut ProxyUserData<I>);
+            if let Some(implem) = user_data.pending_implem.borrow_mut().take() {
+                *user_data.implem.borrow_mut() = implem;
+            }
+        }
         if must_destroy {
             // final cleanup
             let _ = Box::from_raw(user_data as *mut ProxyUserData<I>);
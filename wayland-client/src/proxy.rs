@@ -1,5 +1,7 @@
 use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use super::AnonymousObject;
 use wayland_commons::user_data::UserData;
@@ -9,8 +11,9 @@ use wayland_sys::client::*;
 
 use crate::event_queue::QueueToken;
 
-use crate::imp::ProxyInner;
+use crate::imp::{ProxyInner, WeakProxyInner};
 
+use wayland_commons::wire::MessageDesc;
 use wayland_commons::{filter::Filter, MessageGroup};
 
 /// An handle to a wayland proxy
@@ -60,13 +63,22 @@ impl<I: Interface> Debug for Proxy<I> {
 
 impl<I: Interface> Eq for Proxy<I> where I: AsRef<Proxy<I>> + From<Proxy<I>> {}
 
+impl<I: Interface> Hash for Proxy<I>
+where
+    I: AsRef<Proxy<I>> + From<Proxy<I>>,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.serial().hash(state)
+    }
+}
+
 impl<I: Interface> Proxy<I>
 where
     I: AsRef<Proxy<I>> + From<Proxy<I>>,
 {
     #[allow(dead_code)]
-    pub(crate) fn wrap(inner: ProxyInner) -> Proxy<I> {
-        Proxy { _i: ::std::marker::PhantomData, inner }
+    pub(crate) fn wrap<P: Into<ProxyInner>>(inner: P) -> Proxy<I> {
+        Proxy { _i: ::std::marker::PhantomData, inner: inner.into() }
     }
 
     /// Send a request creating an object through this object
@@ -112,11 +124,40 @@ where
         self.inner.version()
     }
 
+    /// Check if the version of this object supports the request named `name`
+    ///
+    /// Returns `false` on dead objects, and if the interface has no such request.
+    pub fn supports_request(&self, name: &str) -> bool {
+        supports_message(I::Request::MESSAGES, name, self.version())
+    }
+
+    /// Check if the version of this object supports the event named `name`
+    ///
+    /// Returns `false` on dead objects, and if the interface has no such event.
+    pub fn supports_event(&self, name: &str) -> bool {
+        supports_message(I::Event::MESSAGES, name, self.version())
+    }
+
     /// Retrieve the object id of this wayland object
     pub fn id(&self) -> u32 {
         self.inner.id()
     }
 
+    /// Retrieve the stable identifier of this wayland object
+    ///
+    /// Unlike the protocol id returned by `id()`, it is never reused by an other object and
+    /// stays the same once the object is destroyed, see [`ObjectId`](struct.ObjectId.html).
+    pub fn object_id(&self) -> ObjectId {
+        ObjectId { interface: I::NAME, protocol_id: self.inner.id(), serial: self.inner.serial() }
+    }
+
+    /// Create a weak handle to this wayland object
+    ///
+    /// See [`Weak`](struct.Weak.html) for details.
+    pub fn downgrade(&self) -> Weak<I> {
+        Weak { _i: ::std::marker::PhantomData, inner: self.inner.downgrade(), id: self.object_id() }
+    }
+
     /// Access the UserData associated to this object
     ///
     /// Each wayland object has an associated UserData, that can store
@@ -128,6 +169,32 @@ where
         self.inner.user_data()
     }
 
+    /// Set whether the events of this object are dispatched with a high priority
+    ///
+    /// The events received by high priority objects are dispatched by their event queue before
+    /// the events of the other objects already waiting in it. This is useful to keep input
+    /// latency low, for objects like `wl_pointer` or frame callbacks, when floods of events
+    /// such as output or dmabuf feedback events are received.
+    ///
+    /// The events of a given object are still dispatched in order, but as they can be
+    /// dispatched before earlier events of other objects, this should not be used for objects
+    /// whose events depend on the events of other objects. The events of this object already
+    /// waiting in its queue are dispatched with a high priority as well, and the objects created
+    /// by the events of this object are not high priority.
+    ///
+    /// NOTE: This method does nothing on a connection using `Backend::Native`.
+    pub fn set_high_priority(&self, high_priority: bool) {
+        self.inner.set_high_priority(high_priority)
+    }
+
+    /// Whether the events of this object are dispatched with a high priority
+    ///
+    /// See `set_high_priority()`, this is always `false` on a connection using
+    /// `Backend::Native`.
+    pub fn is_high_priority(&self) -> bool {
+        self.inner.is_high_priority()
+    }
+
     /// Check if the other proxy refers to the same underlying wayland object
     ///
     /// You can also use the `PartialEq` implementation.
@@ -166,6 +233,106 @@ impl Proxy<AnonymousObject> {
     }
 }
 
+static NEXT_OBJECT_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) fn next_object_serial() -> u64 {
+    NEXT_OBJECT_SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A stable identifier of a wayland object
+///
+/// The protocol ids of the objects are reused once they are destroyed, which makes them
+/// unsuitable to track objects. An `ObjectId` is never reused by an other object during the
+/// lifetime of the process, and the one of a destroyed object stays valid: it can be used as
+/// the key of a map, and compares equal to the id of any other handle to the same object.
+#[derive(Copy, Clone)]
+pub struct ObjectId {
+    interface: &'static str,
+    protocol_id: u32,
+    serial: u64,
+}
+
+impl ObjectId {
+    /// The name of the interface of the object
+    pub fn interface(&self) -> &'static str {
+        self.interface
+    }
+
+    /// The protocol id the object had when this identifier was retrieved
+    ///
+    /// This is 0 if the object was already dead.
+    pub fn protocol_id(&self) -> u32 {
+        self.protocol_id
+    }
+}
+
+impl PartialEq for ObjectId {
+    fn eq(&self, other: &ObjectId) -> bool {
+        self.serial == other.serial
+    }
+}
+
+impl Eq for ObjectId {}
+
+impl Hash for ObjectId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.serial.hash(state)
+    }
+}
+
+impl Debug for ObjectId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.interface, self.protocol_id)
+    }
+}
+
+/// A weak handle to a wayland proxy
+///
+/// It does not keep the user data of the object alive, and can only be upgraded back into
+/// a proxy while the object is alive: upgrading fails once it has been destroyed, even if its
+/// protocol id has been reused by a new object.
+///
+/// The proxies given by `upgrade()` are not attached to any event queue, like the ones
+/// obtained by cloning a proxy.
+pub struct Weak<I: Interface> {
+    _i: ::std::marker::PhantomData<&'static I>,
+    inner: WeakProxyInner,
+    id: ObjectId,
+}
+
+impl<I: Interface> Weak<I>
+where
+    I: AsRef<Proxy<I>> + From<Proxy<I>>,
+{
+    /// Try to retrieve a handle to the object
+    ///
+    /// Returns `None` if the object has been destroyed.
+    pub fn upgrade(&self) -> Option<I> {
+        self.inner.upgrade().map(|inner| Proxy::wrap(inner).into())
+    }
+
+    /// The stable identifier of the object
+    pub fn object_id(&self) -> ObjectId {
+        self.id
+    }
+}
+
+impl<I: Interface> Clone for Weak<I> {
+    fn clone(&self) -> Weak<I> {
+        Weak { _i: ::std::marker::PhantomData, inner: self.inner.clone(), id: self.id }
+    }
+}
+
+impl<I: Interface> Debug for Weak<I> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Weak({:?})", self.id)
+    }
+}
+
+fn supports_message(messages: &[MessageDesc], name: &str, version: u32) -> bool {
+    messages.iter().any(|desc| desc.name == name && desc.since <= version)
+}
+
 impl<I: Interface + Debug> Debug for Attached<I> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}[ATTACHED]", self.inner)
@@ -229,10 +396,10 @@ impl<I: Interface> Main<I>
 where
     I: AsRef<Proxy<I>> + From<Proxy<I>>,
 {
-    pub(crate) fn wrap(inner: ProxyInner) -> Main<I> {
+    pub(crate) fn wrap<P: Into<ProxyInner>>(inner: P) -> Main<I> {
         Main {
             inner: Attached {
-                inner: Proxy { _i: std::marker::PhantomData, inner }.into(),
+                inner: Proxy { _i: std::marker::PhantomData, inner: inner.into() }.into(),
                 _s: std::marker::PhantomData,
             },
         }
@@ -272,6 +439,54 @@ where
     {
         self.assign(Filter::new(move |(proxy, event), _, data| f(proxy, event, data)))
     }
+
+    /// Replace the filter assigned to this object
+    ///
+    /// This behaves like `assign(..)`, but can also be called from within the callback of
+    /// this object, for example to change the handling of the object as it changes of role
+    /// in a state machine. In this case the new filter is used starting with the next event.
+    pub fn reassign<E>(&self, filter: Filter<E>)
+    where
+        I: Sync,
+        E: From<(Main<I>, I::Event)> + 'static,
+        I::Event: MessageGroup<Map = crate::ProxyMap>,
+    {
+        self.inner.inner.as_ref().inner.reassign::<I, E>(filter);
+    }
+
+    /// Remove the filter assigned to this object
+    ///
+    /// The next events received by this object are delivered to the fallback callback of its
+    /// event queue, until it is assigned again. Like `reassign(..)`, this can be called from
+    /// within the callback of this object.
+    pub fn unassign(&self) {
+        #[cfg(feature = "use_system_lib")]
+        {
+            self.inner.inner.as_ref().inner.unassign::<I>();
+        }
+        #[cfg(not(feature = "use_system_lib"))]
+        {
+            self.inner.inner.as_ref().inner.unassign();
+        }
+    }
+
+    /// Assign a closure that can be invoked from any thread to this object
+    ///
+    /// The filters given to `assign(..)` and `quick_assign(..)` can only be invoked from the
+    /// thread they were assigned on, dispatching an event to them from an other thread is a
+    /// `ThreadGuard` violation. As the closure given here must be `Send`, the event queue of
+    /// this object can be dispatched from any thread, for example by a worker thread owning
+    /// it.
+    ///
+    /// Unlike a `Filter`, the closure cannot be shared between several objects.
+    pub fn assign_threadsafe<F>(&self, f: F)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        F: FnMut(Main<I>, I::Event, crate::DispatchData) + Send + 'static,
+        I::Event: MessageGroup<Map = crate::ProxyMap>,
+    {
+        self.inner.inner.as_ref().inner.assign_threadsafe::<I, F>(f);
+    }
 }
 
 impl Main<AnonymousObject> {
@@ -359,6 +574,60 @@ where
             panic!("[wayland-client] C interfacing methods can only be used with the `use_system_lib` cargo feature.")
         }
     }
+
+    /// Create a `Main` instance from a C pointer, if it is not managed by another library
+    ///
+    /// Like `from_c_ptr()`, this takes control of the provided proxy, but it checks first that
+    /// the proxy is of interface `I` and that no other library already set a listener on it.
+    /// If the proxy is already managed by this crate, this is equivalent to cloning it.
+    ///
+    /// Proxies managed by another library can still be used through `Proxy::from_c_ptr()`,
+    /// as external proxies that cannot be assigned to a filter.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// not activated.
+    ///
+    /// # Safety
+    ///
+    /// The provided pointer must point to a valid wayland object from `libwayland-client`, and
+    /// this must be called from the same thread as the one hosting the event queue handling
+    /// this proxy.
+    pub unsafe fn try_from_c_ptr(_ptr: *mut wl_proxy) -> Result<Main<I>, ForeignProxyError> {
+        #[cfg(feature = "use_system_lib")]
+        {
+            ProxyInner::try_init_from_c_ptr::<I>(_ptr).map(Main::wrap)
+        }
+        #[cfg(not(feature = "use_system_lib"))]
+        {
+            panic!("[wayland-client] C interfacing methods can only be used with the `use_system_lib` cargo feature.")
+        }
+    }
+}
+
+/// Error when taking control of a proxy created by another library
+///
+/// As returned by `Main::try_from_c_ptr()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ForeignProxyError {
+    /// The proxy is not of the requested interface
+    WrongInterface,
+    /// Another library already handles the events of this proxy
+    ExternallyManaged,
+}
+
+impl ::std::error::Error for ForeignProxyError {}
+
+impl ::std::fmt::Display for ForeignProxyError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match *self {
+            ForeignProxyError::WrongInterface => {
+                f.write_str("The proxy is not of the requested interface.")
+            }
+            ForeignProxyError::ExternallyManaged => {
+                f.write_str("The proxy is already managed by another library.")
+            }
+        }
+    }
 }
 
 impl<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>> Proxy<I> {
@@ -367,7 +636,7 @@ impl<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>> Proxy<I> {
     /// See `from_c_ptr` for details.
     ///
     /// NOTE: This method will panic if called while the `use_system_lib` feature is
-    /// not activated.
+    /// not activated. It always returns `false` on a connection using `Backend::Rust`.
     pub fn is_external(&self) -> bool {
         #[cfg(feature = "use_system_lib")]
         {
@@ -385,17 +654,17 @@ impl<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>> Proxy<I> {
     /// You will mostly need it to interface with C libraries needing access
     /// to wayland objects (to initialize an opengl context for example).
     ///
-    /// NOTE: This method will panic if called while the `use_system_lib` feature is
-    /// not activated.
+    /// Returns a null pointer if the object is dead: passing a dead object as the argument
+    /// of a request thus sends a null object, like the rust implementation does.
+    ///
+    /// On a connection using `Backend::Rust`, this is a pointer of the compatibility layer of
+    /// the `c_abi` cargo feature, which C can send requests and create objects with. The events
+    /// of the object are still dispatched to its rust handler.
+    ///
+    /// NOTE: This method will panic if called on a connection using `Backend::Rust` while the
+    /// `c_abi` feature is not activated.
     pub fn c_ptr(&self) -> *mut wl_proxy {
-        #[cfg(feature = "use_system_lib")]
-        {
-            self.inner.c_ptr()
-        }
-        #[cfg(not(feature = "use_system_lib"))]
-        {
-            panic!("[wayland-client] C interfacing methods can only be used with the `use_system_lib` cargo feature.")
-        }
+        self.inner.c_ptr(I::c_interface())
     }
 
     /// Create a `Proxy` instance from a C pointer
@@ -0,0 +1,64 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::{Interface, Main, MessageGroup, Proxy, ProxyMap};
+
+struct ResponseState<E> {
+    event: Option<E>,
+    waker: Option<Waker>,
+}
+
+/// A future resolving with the first event received by an object
+///
+/// This is meant for the objects answering a single request with a single event, like
+/// the `wl_callback` created by `wl_display.sync` or `wl_surface.frame`. The protocols
+/// generated with the `async_helpers` option of `wayland-scanner` provide a `<request>_async`
+/// method returning such a future for these requests.
+///
+/// The future does not read the socket by itself: it is woken when the event is dispatched
+/// by the event queue of the object, so this queue still needs to be dispatched, for example
+/// by the event loop driving the executor.
+pub struct ResponseFuture<I: Interface> {
+    state: Arc<Mutex<ResponseState<I::Event>>>,
+}
+
+impl<I> ResponseFuture<I>
+where
+    I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+    I::Event: MessageGroup<Map = ProxyMap>,
+{
+    /// Wait for the first event of an object
+    ///
+    /// This assigns a filter to the object, replacing any previously assigned one.
+    pub fn new(object: Main<I>) -> ResponseFuture<I> {
+        let state = Arc::new(Mutex::new(ResponseState { event: None, waker: None }));
+        let filter_state = state.clone();
+        object.quick_assign(move |_, event, _| {
+            let mut state = filter_state.lock().unwrap();
+            if state.event.is_none() {
+                state.event = Some(event);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        ResponseFuture { state }
+    }
+}
+
+impl<I: Interface> Future for ResponseFuture<I> {
+    type Output = I::Event;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<I::Event> {
+        let mut state = self.state.lock().unwrap();
+        match state.event.take() {
+            Some(event) => Poll::Ready(event),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * The variadic entry points of the C API of libwayland-client, which can't be defined in
+ * stable Rust. They collect their arguments in a `wl_argument` array, and forward it to the
+ * corresponding `_array` entry point of `c_abi.rs`.
+ */
+
+#include <errno.h>
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define WL_CLOSURE_MAX_ARGS 20
+
+struct wl_proxy;
+struct wl_object;
+struct wl_interface;
+struct wl_array;
+
+struct wl_message {
+	const char *name;
+	const char *signature;
+	const struct wl_interface **types;
+};
+
+struct wl_interface {
+	const char *name;
+	int version;
+	int method_count;
+	const struct wl_message *methods;
+	int event_count;
+	const struct wl_message *events;
+};
+
+union wl_argument {
+	int32_t i;
+	uint32_t u;
+	int32_t f;
+	const char *s;
+	struct wl_object *o;
+	uint32_t n;
+	struct wl_array *a;
+	int32_t h;
+};
+
+struct wl_proxy *wl_proxy_marshal_array_flags(struct wl_proxy *proxy, uint32_t opcode,
+					      const struct wl_interface *interface,
+					      uint32_t version, uint32_t flags,
+					      union wl_argument *args);
+void wl_proxy_marshal_array(struct wl_proxy *proxy, uint32_t opcode, union wl_argument *args);
+struct wl_proxy *wl_proxy_marshal_array_constructor(struct wl_proxy *proxy, uint32_t opcode,
+						    union wl_argument *args,
+						    const struct wl_interface *interface);
+struct wl_proxy *
+wl_proxy_marshal_array_constructor_versioned(struct wl_proxy *proxy, uint32_t opcode,
+					     union wl_argument *args,
+					     const struct wl_interface *interface,
+					     uint32_t version);
+
+/* errno is a macro, this is how the Rust side sets it */
+void wayland_rs_c_abi_set_errno(int value)
+{
+	errno = value;
+}
+
+/* The signature of a request, both the proxies of libwayland and of this library start with
+ * their interface. An unknown opcode gets an empty signature, it is reported by the `_array`
+ * entry point. */
+static const char *request_signature(struct wl_proxy *proxy, uint32_t opcode)
+{
+	const struct wl_interface *interface = *(const struct wl_interface **)proxy;
+
+	if (opcode >= (uint32_t)interface->method_count)
+		return "";
+	return interface->methods[opcode].signature;
+}
+
+static void args_from_va_list(const char *signature, union wl_argument *args, va_list ap)
+{
+	int i = 0;
+
+	for (; *signature != '\0' && i < WL_CLOSURE_MAX_ARGS; signature++) {
+		switch (*signature) {
+		case 'i':
+			args[i++].i = va_arg(ap, int32_t);
+			break;
+		case 'u':
+			args[i++].u = va_arg(ap, uint32_t);
+			break;
+		case 'f':
+			args[i++].f = va_arg(ap, int32_t);
+			break;
+		case 's':
+			args[i++].s = va_arg(ap, const char *);
+			break;
+		case 'o':
+			args[i++].o = va_arg(ap, struct wl_object *);
+			break;
+		case 'n':
+			/* the placeholder of the new object */
+			args[i++].o = va_arg(ap, struct wl_object *);
+			break;
+		case 'a':
+			args[i++].a = va_arg(ap, struct wl_array *);
+			break;
+		case 'h':
+			args[i++].h = va_arg(ap, int32_t);
+			break;
+		default:
+			/* the versions and the '?' of the nullable arguments */
+			break;
+		}
+	}
+}
+
+struct wl_proxy *wl_proxy_marshal_flags(struct wl_proxy *proxy, uint32_t opcode,
+					const struct wl_interface *interface, uint32_t version,
+					uint32_t flags, ...)
+{
+	union wl_argument args[WL_CLOSURE_MAX_ARGS];
+	va_list ap;
+
+	va_start(ap, flags);
+	args_from_va_list(request_signature(proxy, opcode), args, ap);
+	va_end(ap);
+
+	return wl_proxy_marshal_array_flags(proxy, opcode, interface, version, flags, args);
+}
+
+void wl_proxy_marshal(struct wl_proxy *proxy, uint32_t opcode, ...)
+{
+	union wl_argument args[WL_CLOSURE_MAX_ARGS];
+	va_list ap;
+
+	va_start(ap, opcode);
+	args_from_va_list(request_signature(proxy, opcode), args, ap);
+	va_end(ap);
+
+	wl_proxy_marshal_array(proxy, opcode, args);
+}
+
+struct wl_proxy *wl_proxy_marshal_constructor(struct wl_proxy *proxy, uint32_t opcode,
+					      const struct wl_interface *interface, ...)
+{
+	union wl_argument args[WL_CLOSURE_MAX_ARGS];
+	va_list ap;
+
+	va_start(ap, interface);
+	args_from_va_list(request_signature(proxy, opcode), args, ap);
+	va_end(ap);
+
+	return wl_proxy_marshal_array_constructor(proxy, opcode, args, interface);
+}
+
+struct wl_proxy *wl_proxy_marshal_constructor_versioned(struct wl_proxy *proxy, uint32_t opcode,
+							const struct wl_interface *interface,
+							uint32_t version, ...)
+{
+	union wl_argument args[WL_CLOSURE_MAX_ARGS];
+	va_list ap;
+
+	va_start(ap, version);
+	args_from_va_list(request_signature(proxy, opcode), args, ap);
+	va_end(ap);
+
+	return wl_proxy_marshal_array_constructor_versioned(proxy, opcode, args, interface,
+							    version);
+}
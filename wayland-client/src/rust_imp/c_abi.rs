@@ -0,0 +1,1155 @@
+//! Compatibility layer exposing the objects of the rust implementation through the C API of
+//! `libwayland-client`
+//!
+//! This exports the `wl_display_*`, `wl_event_queue_*` and `wl_proxy_*` entry points that the
+//! libraries written against `libwayland-client.so`, like the wayland platform of libEGL, use
+//! on the `wl_display` and `wl_proxy` pointers they are given. The variadic ones are defined
+//! in `c_abi.c`.
+//!
+//! The structs behind these pointers start like the `wl_proxy` of libwayland, with the
+//! interface, the listener and the id of the object, as the inline functions of the generated
+//! protocol headers expect. The objects created through the C API are described from their
+//! `wl_interface`, and their events are dispatched to their listener. The objects created in
+//! rust are only borrowed: they can be used to send requests and create objects, but their
+//! events are still dispatched to their rust handlers.
+//!
+//! With the `use_system_lib` feature, the pointers not created by this module belong to the
+//! system library, and the calls are forwarded to it.
+
+use std::collections::HashMap;
+#[cfg(feature = "use_system_lib")]
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, Once};
+
+use wayland_commons::debug;
+use wayland_commons::map::{Object, ObjectMetadata};
+use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+#[cfg(feature = "use_system_lib")]
+use wayland_sys::client::{
+    has_marshal_flags, WAYLAND_CLIENT_HANDLE, WAYLAND_CLIENT_MARSHAL_FLAGS_HANDLE,
+};
+use wayland_sys::client::{wl_display, wl_event_queue, wl_proxy, WL_MARSHAL_FLAG_DESTROY};
+use wayland_sys::common::{wl_argument, wl_array, wl_interface, wl_message};
+
+use crate::protocol::wl_display::{wl_display_events, wl_display_requests};
+use crate::{DispatchData, DispatchError, RawEvent};
+
+use super::connection::{Connection, Error as CxError};
+use super::proxy::{ObjectMeta, ProxyInner};
+use super::queues::QueueBuffer;
+use super::{
+    default_dispatcher, trace_destruction, Dispatched, Dispatcher, EventQueueInner, ProxyMap,
+    WAYLAND_DEBUG,
+};
+
+/// The maximum number of arguments of a message, like libwayland
+const MAX_ARGS: usize = 20;
+
+extern "C" {
+    fn wayland_rs_c_abi_set_errno(value: c_int);
+}
+
+fn set_errno(value: c_int) {
+    unsafe { wayland_rs_c_abi_set_errno(value) }
+}
+
+/// The `wl_interface` of the displays of this module
+///
+/// Its address identifies a `wl_display` for libEGL, so it is exported under the name of the
+/// one of libwayland.
+#[repr(transparent)]
+pub(crate) struct DisplayInterface(wl_interface);
+
+// it is never modified
+unsafe impl Sync for DisplayInterface {}
+
+// like the generated `wl_interface`s, this refers to the generated `wl_message`s
+#[allow(unknown_lints, static_mut_refs)]
+#[export_name = "wl_display_interface"]
+pub(crate) static DISPLAY_INTERFACE: DisplayInterface = DisplayInterface(wl_interface {
+    name: b"wl_display\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 2,
+    requests: unsafe { &wl_display_requests as *const _ },
+    event_count: 2,
+    events: unsafe { &wl_display_events as *const _ },
+});
+
+// The state shared by all the connections
+struct Globals {
+    // the descriptions made from the `wl_interface`s given by C, by address
+    descriptions: Mutex<HashMap<usize, &'static Description>>,
+    // the addresses of the displays, queues and proxies created by this module
+    #[cfg(feature = "use_system_lib")]
+    owned: Mutex<HashSet<usize>>,
+}
+
+fn globals() -> &'static Globals {
+    static INIT: Once = Once::new();
+    static mut GLOBALS: *const Globals = ptr::null();
+    unsafe {
+        INIT.call_once(|| {
+            GLOBALS = Box::into_raw(Box::new(Globals {
+                descriptions: Mutex::new(HashMap::new()),
+                #[cfg(feature = "use_system_lib")]
+                owned: Mutex::new(HashSet::new()),
+            }));
+        });
+        &*GLOBALS
+    }
+}
+
+fn own<T>(ptr: *const T) {
+    #[cfg(feature = "use_system_lib")]
+    {
+        globals().owned.lock().unwrap().insert(ptr as usize);
+    }
+    #[cfg(not(feature = "use_system_lib"))]
+    {
+        let _ = ptr;
+    }
+}
+
+fn disown<T>(ptr: *const T) {
+    #[cfg(feature = "use_system_lib")]
+    {
+        globals().owned.lock().unwrap().remove(&(ptr as usize));
+    }
+    #[cfg(not(feature = "use_system_lib"))]
+    {
+        let _ = ptr;
+    }
+}
+
+// Forward the call to the system library if the pointer was not created by this module
+macro_rules! forward_foreign {
+    ($ptr:expr, $handle:ident, $func:ident, $($arg:expr),*) => {
+        #[cfg(feature = "use_system_lib")]
+        {
+            if !globals().owned.lock().unwrap().contains(&($ptr as usize)) {
+                return ffi_dispatch!($handle, $func, $($arg),*);
+            }
+        }
+    };
+}
+
+/*
+ * Descriptions of the interfaces given by C
+ */
+
+// An interface described by a `wl_interface`
+struct Description {
+    interface: *const wl_interface,
+    name: &'static str,
+    requests: &'static [MessageDesc],
+    events: &'static [MessageDesc],
+}
+
+// the `wl_interface`s are static data
+unsafe impl Send for Description {}
+unsafe impl Sync for Description {}
+
+// The description of an object created through the C API, stored in its user data
+struct Described(&'static Description);
+
+impl Description {
+    unsafe fn of(interface: *const wl_interface) -> &'static Description {
+        let mut descriptions = globals().descriptions.lock().unwrap();
+        if let Some(&description) = descriptions.get(&(interface as usize)) {
+            return description;
+        }
+        let c_interface = &*interface;
+        let description: &'static Description = Box::leak(Box::new(Description {
+            interface,
+            name: leak_str(c_interface.name),
+            requests: messages(c_interface.requests, c_interface.request_count),
+            events: messages(c_interface.events, c_interface.event_count),
+        }));
+        descriptions.insert(interface as usize, description);
+        description
+    }
+
+    // The `wl_interface` of the description with that name, if any
+    fn named(name: &str) -> *const wl_interface {
+        if name == "wl_display" {
+            return &DISPLAY_INTERFACE.0;
+        }
+        let descriptions = globals().descriptions.lock().unwrap();
+        descriptions
+            .values()
+            .find(|description| description.name == name)
+            .map(|description| description.interface)
+            .unwrap_or_else(ptr::null)
+    }
+
+    fn object(&'static self, version: u32, meta: ObjectMeta) -> Object<ObjectMeta> {
+        meta.user_data.set_threadsafe(|| Described(self));
+        Object {
+            interface: self.name,
+            version,
+            requests: self.requests,
+            events: self.events,
+            request_args: &[],
+            event_args: &[],
+            meta,
+            childs_from_events: event_child,
+            childs_from_requests: no_child,
+        }
+    }
+}
+
+unsafe fn leak_str(s: *const c_char) -> &'static str {
+    Box::leak(CStr::from_ptr(s).to_string_lossy().into_owned().into_boxed_str())
+}
+
+unsafe fn messages(messages: *const wl_message, count: c_int) -> &'static [MessageDesc] {
+    let descs = (0..count.max(0) as usize)
+        .map(|i| {
+            let message = &*messages.add(i);
+            let signature = CStr::from_ptr(message.signature).to_bytes();
+            let types = parse_signature(signature).into_iter().map(|(kind, _)| kind);
+            MessageDesc {
+                name: leak_str(message.name),
+                signature: Box::leak(types.collect::<Vec<_>>().into_boxed_slice()),
+                since: since(signature),
+                // the objects of the C API are destroyed by `wl_proxy_destroy`
+                destructor: false,
+            }
+        })
+        .collect::<Vec<_>>();
+    Box::leak(descs.into_boxed_slice())
+}
+
+// The types of the arguments of a signature, and whether they are nullable
+fn parse_signature(signature: &[u8]) -> Vec<(ArgumentType, bool)> {
+    let mut args = Vec::new();
+    let mut nullable = false;
+    for &c in signature {
+        let kind = match c {
+            b'i' => ArgumentType::Int,
+            b'u' => ArgumentType::Uint,
+            b'f' => ArgumentType::Fixed,
+            b's' => ArgumentType::Str,
+            b'o' => ArgumentType::Object,
+            b'n' => ArgumentType::NewId,
+            b'a' => ArgumentType::Array,
+            b'h' => ArgumentType::Fd,
+            b'?' => {
+                nullable = true;
+                continue;
+            }
+            // the version of the message
+            _ => continue,
+        };
+        args.push((kind, nullable));
+        nullable = false;
+    }
+    args
+}
+
+fn since(signature: &[u8]) -> u32 {
+    let version = signature
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .fold(0, |version, &c| version * 10 + u32::from(c - b'0'));
+    version.max(1)
+}
+
+fn event_child(opcode: u16, version: u32, meta: &ObjectMeta) -> Option<Object<ObjectMeta>> {
+    let description = meta.user_data.get::<Described>()?.0;
+    if opcode as usize >= description.events.len() {
+        return None;
+    }
+    unsafe {
+        let message = &*(*description.interface).events.add(opcode as usize);
+        let signature = parse_signature(CStr::from_ptr(message.signature).to_bytes());
+        let idx = signature.iter().position(|&(kind, _)| kind == ArgumentType::NewId)?;
+        let interface = *message.types.add(idx);
+        if interface.is_null() {
+            return None;
+        }
+        Some(Description::of(interface).object(version, meta.child()))
+    }
+}
+
+fn no_child(_: u16, _: u32, _: &ObjectMeta) -> Option<Object<ObjectMeta>> {
+    None
+}
+
+/*
+ * The structs behind the C pointers
+ */
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    // the display of a connection
+    Display,
+    // created through the C API, which destroys it
+    Created,
+    // a handle to an object created in rust
+    Borrowed,
+    // made by `wl_proxy_create_wrapper`
+    Wrapper,
+}
+
+/// The `wl_proxy` given to C
+#[repr(C)]
+pub(crate) struct CProxy {
+    // the fields of the `wl_object` starting a `wl_proxy`
+    interface: *const wl_interface,
+    listener: *const c_void,
+    id: u32,
+    display: *mut CDisplay,
+    inner: ProxyInner,
+    user_data: *mut c_void,
+    kind: Kind,
+}
+
+impl CProxy {
+    fn new(
+        display: *mut CDisplay,
+        interface: *const wl_interface,
+        inner: ProxyInner,
+        kind: Kind,
+    ) -> *mut CProxy {
+        let proxy = Box::into_raw(Box::new(CProxy {
+            interface,
+            listener: ptr::null(),
+            id: inner.id,
+            display,
+            inner,
+            user_data: ptr::null_mut(),
+            kind,
+        }));
+        own(proxy);
+        proxy
+    }
+
+    unsafe fn free(proxy: *mut CProxy) {
+        disown(proxy);
+        drop(Box::from_raw(proxy));
+    }
+}
+
+/// The `wl_event_queue` given to C
+pub(crate) struct CQueue {
+    buffer: QueueBuffer,
+    inner: Mutex<EventQueueInner>,
+}
+
+// the hooks of an `EventQueueInner` are its only state that can't be sent to another thread,
+// and the C API never sets them
+unsafe impl Send for CQueue {}
+unsafe impl Sync for CQueue {}
+
+impl CQueue {
+    fn new(inner: EventQueueInner) -> CQueue {
+        CQueue { buffer: inner.buffer.clone(), inner: Mutex::new(inner) }
+    }
+}
+
+/// The `wl_display` given to C, owned by its connection
+#[repr(C)]
+pub(crate) struct CDisplay {
+    proxy: CProxy,
+    queue: CQueue,
+    // the proxies of the objects known by the C API, by id
+    proxies: Mutex<HashMap<u32, *mut CProxy>>,
+}
+
+// the proxies are behind a lock, and their fields only mutated by C, which synchronizes its
+// uses of them like with libwayland
+unsafe impl Send for CDisplay {}
+
+impl CDisplay {
+    fn new(connection: &Arc<Mutex<Connection>>) -> Box<CDisplay> {
+        let queue = CQueue::new(EventQueueInner::new(connection.clone(), None));
+        let mut inner =
+            ProxyInner::from_id(1, queue.inner.lock().unwrap().map.clone(), connection.clone())
+                .unwrap();
+        inner.queue = Some(queue.buffer.clone());
+        let mut display = Box::new(CDisplay {
+            proxy: CProxy {
+                interface: &DISPLAY_INTERFACE.0,
+                listener: ptr::null(),
+                id: 1,
+                display: ptr::null_mut(),
+                inner,
+                user_data: ptr::null_mut(),
+                kind: Kind::Display,
+            },
+            queue,
+            proxies: Mutex::new(HashMap::new()),
+        });
+        display.proxy.display = &mut *display;
+        own(&*display as *const CDisplay);
+        own(&display.queue as *const CQueue);
+        display
+    }
+
+    fn ptr(&self) -> *mut CDisplay {
+        self as *const CDisplay as *mut CDisplay
+    }
+
+    fn connection(&self) -> &Arc<Mutex<Connection>> {
+        &self.proxy.inner.connection
+    }
+
+    // Make the proxy of an object the C API will know by its id
+    fn register(&self, proxy: *mut CProxy) {
+        let id = unsafe { (*proxy).id };
+        if id == 0 {
+            return;
+        }
+        if let Some(old) = self.proxies.lock().unwrap().insert(id, proxy) {
+            // the id was reused, the previous object is dead
+            unsafe {
+                if (*old).kind == Kind::Borrowed {
+                    CProxy::free(old);
+                }
+            }
+        }
+    }
+
+    fn unregister(&self, proxy: *mut CProxy) {
+        let id = unsafe { (*proxy).id };
+        let mut proxies = self.proxies.lock().unwrap();
+        if proxies.get(&id) == Some(&proxy) {
+            proxies.remove(&id);
+        }
+    }
+
+    // The proxy of an object, borrowing it if the C API does not know it yet
+    fn proxy_for(&self, mut inner: ProxyInner, interface: *const wl_interface) -> *mut CProxy {
+        if inner.id == 1 {
+            return self.ptr() as *mut CProxy;
+        }
+        if let Some(&proxy) = self.proxies.lock().unwrap().get(&inner.id) {
+            if unsafe { (*proxy).inner.equals(&inner) } {
+                return proxy;
+            }
+        }
+        let interface = if interface.is_null() {
+            Description::named(inner.object.interface)
+        } else {
+            interface
+        };
+        if inner.queue.is_none() {
+            inner.queue = Some(self.queue.buffer.clone());
+        }
+        let proxy = CProxy::new(self.ptr(), interface, inner, Kind::Borrowed);
+        self.register(proxy);
+        proxy
+    }
+
+    // The proxy of the object with given id, as an argument of an event
+    fn object(&self, id: u32, interface: *const wl_interface) -> *mut CProxy {
+        let inner = &self.proxy.inner;
+        match ProxyInner::from_id(id, inner.map.clone(), inner.connection.clone()) {
+            Some(object) => self.proxy_for(object, interface),
+            None => ptr::null_mut(),
+        }
+    }
+
+    // The proxy of the object created by an event
+    fn new_object(&self, id: u32, interface: *const wl_interface) -> *mut CProxy {
+        let inner = &self.proxy.inner;
+        match ProxyInner::from_id(id, inner.map.clone(), inner.connection.clone()) {
+            Some(object) => {
+                let proxy = CProxy::new(self.ptr(), interface, object, Kind::Created);
+                self.register(proxy);
+                proxy
+            }
+            None => ptr::null_mut(),
+        }
+    }
+}
+
+impl Drop for CDisplay {
+    fn drop(&mut self) {
+        disown(self as *const CDisplay);
+        disown(&self.queue as *const CQueue);
+        for (_, proxy) in self.proxies.get_mut().unwrap().drain() {
+            // the proxies created by C are for it to destroy
+            unsafe {
+                if (*proxy).kind == Kind::Borrowed {
+                    CProxy::free(proxy);
+                }
+            }
+        }
+    }
+}
+
+/// The `wl_display` of a connection, created on first use
+pub(crate) fn display_ptr(connection: &Arc<Mutex<Connection>>) -> *mut wl_display {
+    if let Some(ref mut display) = connection.lock().unwrap().c_display {
+        return &mut **display as *mut CDisplay as *mut wl_display;
+    }
+    // creating the display takes the lock of the connection
+    let display = CDisplay::new(connection);
+    let mut cx = connection.lock().unwrap();
+    &mut **cx.c_display.get_or_insert(display) as *mut CDisplay as *mut wl_display
+}
+
+/// The `wl_proxy` of an object created in rust, null if it is dead
+pub(crate) fn proxy_ptr(proxy: &ProxyInner, interface: *const wl_interface) -> *mut wl_proxy {
+    if !proxy.is_alive() {
+        return ptr::null_mut();
+    }
+    let display = display_ptr(&proxy.connection) as *mut CDisplay;
+    unsafe { (*display).proxy_for(proxy.clone(), interface) as *mut wl_proxy }
+}
+
+/*
+ * Dispatching to the listeners
+ */
+
+// Dispatches the events of an object created through the C API to its listener
+struct ListenerDispatcher {
+    proxy: *mut CProxy,
+}
+
+// the proxy is only used by the queues, which C synchronizes with its destruction
+unsafe impl Send for ListenerDispatcher {}
+
+impl Dispatcher for ListenerDispatcher {
+    fn dispatch(
+        &mut self,
+        msg: Message,
+        proxy: ProxyInner,
+        _map: &mut ProxyMap,
+        _data: DispatchData,
+    ) -> Dispatched {
+        if WAYLAND_DEBUG.load(Ordering::Relaxed) {
+            debug::print_dispatched_message(
+                proxy.object.interface,
+                proxy.id,
+                proxy.object.events[msg.opcode as usize].name,
+                &msg.args,
+            );
+        }
+        // the listener may destroy the proxy, it must not be used afterwards
+        unsafe { dispatch_to_listener(self.proxy, msg) };
+        Dispatched::Yes
+    }
+}
+
+fn close_fds<'a, I: IntoIterator<Item = &'a Argument>>(args: I) {
+    for arg in args {
+        if let Argument::Fd(fd) = *arg {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
+unsafe fn dispatch_to_listener(proxy: *mut CProxy, msg: Message) {
+    let (interface, listener, data, display) = {
+        let proxy = &*proxy;
+        (proxy.interface, proxy.listener, proxy.user_data, &*proxy.display)
+    };
+    let func = if listener.is_null() || msg.args.len() > MAX_ARGS {
+        None
+    } else {
+        *(listener as *const Option<unsafe extern "C" fn()>).add(msg.opcode as usize)
+    };
+    let func = match func {
+        Some(func) => func,
+        None => return close_fds(&msg.args),
+    };
+
+    let message = &*(*interface).events.add(msg.opcode as usize);
+    let signature = parse_signature(CStr::from_ptr(message.signature).to_bytes());
+    // the `wl_array`s given to the listener, which must not move
+    let mut arrays = Vec::with_capacity(msg.args.len());
+    let mut values = Vec::with_capacity(msg.args.len());
+    for (i, (arg, &(_, nullable))) in msg.args.iter().zip(&signature).enumerate() {
+        let value = match *arg {
+            Argument::Int(v) | Argument::Fixed(v) | Argument::Fd(v) => v as usize,
+            Argument::Uint(v) => v as usize,
+            Argument::Str(ref s) if nullable && s.as_bytes().is_empty() => 0,
+            Argument::Str(ref s) => s.as_ptr() as usize,
+            Argument::Object(0) => 0,
+            Argument::Object(id) => display.object(id, *message.types.add(i)) as usize,
+            Argument::NewId(id) => display.new_object(id, *message.types.add(i)) as usize,
+            Argument::Array(ref a) => {
+                arrays.push(wl_array {
+                    size: a.len(),
+                    alloc: a.len(),
+                    data: a.as_ptr() as *mut c_void,
+                });
+                arrays.last().unwrap() as *const wl_array as usize
+            }
+        };
+        values.push(value);
+    }
+    call_listener(func, data, proxy as *mut wl_proxy, &values);
+}
+
+// Call a listener with its arguments in word-sized slots, like the calling conventions of the
+// supported platforms pass the integers and pointers of the C API
+unsafe fn call_listener(
+    func: unsafe extern "C" fn(),
+    data: *mut c_void,
+    proxy: *mut wl_proxy,
+    a: &[usize],
+) {
+    macro_rules! call {
+        (@word $i:tt) => { usize };
+        ($($i:tt)*) => {{
+            let func: unsafe extern "C" fn(*mut c_void, *mut wl_proxy $(, call!(@word $i))*) =
+                mem::transmute(func);
+            func(data, proxy $(, a[$i])*)
+        }};
+    }
+    match a.len() {
+        0 => call!(),
+        1 => call!(0),
+        2 => call!(0 1),
+        3 => call!(0 1 2),
+        4 => call!(0 1 2 3),
+        5 => call!(0 1 2 3 4),
+        6 => call!(0 1 2 3 4 5),
+        7 => call!(0 1 2 3 4 5 6),
+        8 => call!(0 1 2 3 4 5 6 7),
+        9 => call!(0 1 2 3 4 5 6 7 8),
+        10 => call!(0 1 2 3 4 5 6 7 8 9),
+        11 => call!(0 1 2 3 4 5 6 7 8 9 10),
+        12 => call!(0 1 2 3 4 5 6 7 8 9 10 11),
+        13 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12),
+        14 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13),
+        15 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14),
+        16 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15),
+        17 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16),
+        18 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17),
+        19 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18),
+        20 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19),
+        _ => unreachable!(),
+    }
+}
+
+// The events of the queues of the C API not dispatched to a listener
+fn discard(event: RawEvent, _: crate::Main<crate::AnonymousObject>, _: DispatchData) {
+    for arg in event.args {
+        if let crate::Argument::Fd(fd) = arg {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
+/*
+ * The entry points of the displays and queues
+ */
+
+unsafe fn c_display<'a>(display: *mut wl_display) -> &'a CDisplay {
+    &*(display as *const CDisplay)
+}
+
+unsafe fn c_queue(display: &CDisplay, queue: *mut wl_event_queue) -> &CQueue {
+    if queue.is_null() {
+        &display.queue
+    } else {
+        &*(queue as *const CQueue)
+    }
+}
+
+fn errno(error: &DispatchError) -> c_int {
+    match *error {
+        DispatchError::Backend(ref e) => e.raw_os_error().unwrap_or(libc::EIO),
+        DispatchError::Protocol(_) | DispatchError::InvalidState(_) => libc::EPROTO,
+    }
+}
+
+fn status(ret: Result<u32, DispatchError>) -> c_int {
+    match ret {
+        Ok(dispatched) => dispatched as c_int,
+        Err(e) => {
+            set_errno(errno(&e));
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_get_fd(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_get_fd, display);
+    c_display(display).connection().lock().unwrap().socket.get_socket().as_raw_fd()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_flush(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_flush, display);
+    match super::display::flush(c_display(display).connection()) {
+        Ok(progress) if progress.remaining == 0 => progress.written as c_int,
+        Ok(_) => {
+            set_errno(libc::EAGAIN);
+            -1
+        }
+        Err(e) => {
+            set_errno(errno(&e));
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_roundtrip(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_roundtrip, display);
+    wl_display_roundtrip_queue(display, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_roundtrip_queue(
+    display: *mut wl_display,
+    queue: *mut wl_event_queue,
+) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_roundtrip_queue, display, queue);
+    let queue = c_queue(c_display(display), queue).inner.lock().unwrap();
+    status(queue.sync_roundtrip(DispatchData::wrap(&mut ()), discard))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_dispatch(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_dispatch, display);
+    wl_display_dispatch_queue(display, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_dispatch_queue(
+    display: *mut wl_display,
+    queue: *mut wl_event_queue,
+) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_dispatch_queue, display, queue);
+    let queue = c_queue(c_display(display), queue).inner.lock().unwrap();
+    status(queue.dispatch(DispatchData::wrap(&mut ()), discard))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_dispatch_pending(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_dispatch_pending, display);
+    wl_display_dispatch_queue_pending(display, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_dispatch_queue_pending(
+    display: *mut wl_display,
+    queue: *mut wl_event_queue,
+) -> c_int {
+    forward_foreign!(
+        display,
+        WAYLAND_CLIENT_HANDLE,
+        wl_display_dispatch_queue_pending,
+        display,
+        queue
+    );
+    let queue = c_queue(c_display(display), queue).inner.lock().unwrap();
+    status(queue.dispatch_pending(DispatchData::wrap(&mut ()), discard))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_prepare_read(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_prepare_read, display);
+    wl_display_prepare_read_queue(display, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_prepare_read_queue(
+    display: *mut wl_display,
+    queue: *mut wl_event_queue,
+) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_prepare_read_queue, display, queue);
+    // like `EventQueueInner::prepare_read`, without waiting for a dispatch of the queue
+    if c_queue(c_display(display), queue).buffer.lock().unwrap().is_empty() {
+        0
+    } else {
+        set_errno(libc::EAGAIN);
+        -1
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_read_events(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_read_events, display);
+    match super::queues::read_events(c_display(display).connection()) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_errno(e.raw_os_error().unwrap_or(libc::EPROTO));
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_cancel_read(display: *mut wl_display) {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_cancel_read, display);
+    // reading does not need to be prepared
+    let _ = display;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_get_error(display: *mut wl_display) -> c_int {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_get_error, display);
+    c_display(display).connection().lock().unwrap().error().map(|e| errno(&e)).unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_get_protocol_error(
+    display: *mut wl_display,
+    interface: *mut *mut wl_interface,
+    id: *mut u32,
+) -> u32 {
+    forward_foreign!(
+        display,
+        WAYLAND_CLIENT_HANDLE,
+        wl_display_get_protocol_error,
+        display,
+        interface,
+        id
+    );
+    let cx = c_display(display).connection().lock().unwrap();
+    let last_error = cx.last_error.lock().unwrap();
+    let (code, object_interface, object_id) = match *last_error {
+        Some(CxError::Protocol(ref e)) => {
+            (e.code, Description::named(e.object_interface), e.object_id)
+        }
+        _ => (0, ptr::null(), 0),
+    };
+    if !interface.is_null() {
+        *interface = object_interface as *mut wl_interface;
+    }
+    if !id.is_null() {
+        *id = object_id;
+    }
+    code
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_display_create_queue(display: *mut wl_display) -> *mut wl_event_queue {
+    forward_foreign!(display, WAYLAND_CLIENT_HANDLE, wl_display_create_queue, display);
+    let connection = c_display(display).connection().clone();
+    let queue = Box::into_raw(Box::new(CQueue::new(EventQueueInner::new(connection, None))));
+    own(queue);
+    queue as *mut wl_event_queue
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_event_queue_destroy(queue: *mut wl_event_queue) {
+    forward_foreign!(queue, WAYLAND_CLIENT_HANDLE, wl_event_queue_destroy, queue);
+    // the proxies still attached to it keep its buffer
+    disown(queue);
+    drop(Box::from_raw(queue as *mut CQueue));
+}
+
+/*
+ * The entry points of the proxies
+ */
+
+// Send a request described by the interface of a proxy, and make the proxy of the object it
+// creates, if any
+unsafe fn marshal(
+    proxy: *mut CProxy,
+    opcode: u32,
+    interface: *const wl_interface,
+    version: u32,
+    flags: u32,
+    args: *const wl_argument,
+) -> *mut CProxy {
+    let p = &*proxy;
+    let c_interface = &*p.interface;
+    if opcode >= c_interface.request_count as u32 {
+        eprintln!(
+            "[wayland-client] Unknown request opcode {} for interface {}.",
+            opcode, p.inner.object.interface
+        );
+        return ptr::null_mut();
+    }
+    let message = &*c_interface.requests.add(opcode as usize);
+    let signature = parse_signature(CStr::from_ptr(message.signature).to_bytes());
+
+    let mut msg_args = Vec::with_capacity(signature.len());
+    for (i, &(kind, _)) in signature.iter().enumerate().take(MAX_ARGS) {
+        let arg = &*args.add(i);
+        msg_args.push(match kind {
+            ArgumentType::Int => Argument::Int(arg.i),
+            ArgumentType::Uint => Argument::Uint(arg.u),
+            ArgumentType::Fixed => Argument::Fixed(arg.f),
+            ArgumentType::Str if arg.s.is_null() => Argument::Str(Box::default()),
+            ArgumentType::Str => Argument::Str(Box::new(CStr::from_ptr(arg.s).to_owned())),
+            ArgumentType::Object if arg.o.is_null() => Argument::Object(0),
+            ArgumentType::Object => Argument::Object((*(arg.o as *const CProxy)).inner.id()),
+            ArgumentType::NewId => Argument::NewId(0),
+            ArgumentType::Array if arg.a.is_null() => Argument::Array(Box::default()),
+            ArgumentType::Array => {
+                let array = &*arg.a;
+                let data = std::slice::from_raw_parts(array.data as *const u8, array.size);
+                Argument::Array(Box::new(data.to_vec()))
+            }
+            ArgumentType::Fd => Argument::Fd(arg.h),
+        });
+    }
+    let creates = signature.iter().any(|&(kind, _)| kind == ArgumentType::NewId);
+    if creates && interface.is_null() {
+        eprintln!(
+            "[wayland-client] Request {}.{} creates an object, but no interface was given.",
+            p.inner.object.interface,
+            CStr::from_ptr(message.name).to_string_lossy()
+        );
+        return ptr::null_mut();
+    }
+
+    let msg = Message { sender_id: p.inner.id, opcode: opcode as u16, args: msg_args.into() };
+    // the requests described in rust know their destructors, the others are destroyed by
+    // `wl_proxy_destroy`
+    let destructor =
+        p.inner.object.requests.get(opcode as usize).map(|desc| desc.destructor).unwrap_or(false);
+    let version = if version == 0 { p.inner.version() } else { version };
+    let created = p
+        .inner
+        .send_raw(msg, destructor, |meta| Description::of(interface).object(version, meta))
+        .map(|inner| {
+            let created = CProxy::new(p.display, interface, inner, Kind::Created);
+            (*p.display).register(created);
+            created
+        });
+
+    if flags & WL_MARSHAL_FLAG_DESTROY != 0 {
+        wl_proxy_destroy(proxy as *mut wl_proxy);
+    }
+    created.unwrap_or_else(ptr::null_mut)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_marshal_array_flags(
+    proxy: *mut wl_proxy,
+    opcode: u32,
+    interface: *const wl_interface,
+    version: u32,
+    flags: u32,
+    args: *mut wl_argument,
+) -> *mut wl_proxy {
+    #[cfg(feature = "use_system_lib")]
+    {
+        if !globals().owned.lock().unwrap().contains(&(proxy as usize)) {
+            if has_marshal_flags() {
+                return ffi_dispatch!(
+                    WAYLAND_CLIENT_MARSHAL_FLAGS_HANDLE,
+                    wl_proxy_marshal_array_flags,
+                    proxy,
+                    opcode,
+                    interface,
+                    version,
+                    flags,
+                    args
+                );
+            }
+            let created = ffi_dispatch!(
+                WAYLAND_CLIENT_HANDLE,
+                wl_proxy_marshal_array_constructor_versioned,
+                proxy,
+                opcode,
+                args,
+                interface,
+                version
+            );
+            if flags & WL_MARSHAL_FLAG_DESTROY != 0 {
+                ffi_dispatch!(WAYLAND_CLIENT_HANDLE, wl_proxy_destroy, proxy);
+            }
+            return created;
+        }
+    }
+    marshal(proxy as *mut CProxy, opcode, interface, version, flags, args) as *mut wl_proxy
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_marshal_array(
+    proxy: *mut wl_proxy,
+    opcode: u32,
+    args: *mut wl_argument,
+) {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_marshal_array, proxy, opcode, args);
+    marshal(proxy as *mut CProxy, opcode, ptr::null(), 0, 0, args);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_marshal_array_constructor(
+    proxy: *mut wl_proxy,
+    opcode: u32,
+    args: *mut wl_argument,
+    interface: *const wl_interface,
+) -> *mut wl_proxy {
+    forward_foreign!(
+        proxy,
+        WAYLAND_CLIENT_HANDLE,
+        wl_proxy_marshal_array_constructor,
+        proxy,
+        opcode,
+        args,
+        interface
+    );
+    marshal(proxy as *mut CProxy, opcode, interface, 0, 0, args) as *mut wl_proxy
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_marshal_array_constructor_versioned(
+    proxy: *mut wl_proxy,
+    opcode: u32,
+    args: *mut wl_argument,
+    interface: *const wl_interface,
+    version: u32,
+) -> *mut wl_proxy {
+    forward_foreign!(
+        proxy,
+        WAYLAND_CLIENT_HANDLE,
+        wl_proxy_marshal_array_constructor_versioned,
+        proxy,
+        opcode,
+        args,
+        interface,
+        version
+    );
+    marshal(proxy as *mut CProxy, opcode, interface, version, 0, args) as *mut wl_proxy
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_destroy(proxy: *mut wl_proxy) {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_destroy, proxy);
+    let proxy = proxy as *mut CProxy;
+    match (*proxy).kind {
+        Kind::Created => {}
+        Kind::Wrapper => {
+            eprintln!("[wayland-client] Tried to destroy wrapper with wl_proxy_destroy()");
+            return;
+        }
+        // the display is destroyed with its connection, the objects created in rust by their
+        // rust handlers
+        Kind::Display | Kind::Borrowed => return,
+    }
+    (*(*proxy).display).unregister(proxy);
+    let inner = &(*proxy).inner;
+    if inner.is_alive() {
+        let interface = inner.object.interface;
+        trace_destruction(interface, inner.id, format_args!("destroyed by wl_proxy_destroy"));
+        inner.object.meta.alive.store(false, Ordering::Release);
+        let released = inner.map.remove_if(inner.id, |obj| {
+            obj.meta.dispatcher = default_dispatcher();
+            obj.meta.client_destroyed = true;
+            obj.meta.server_destroyed
+        });
+        if released == Ok(true) {
+            trace_destruction(interface, inner.id, format_args!("released"));
+        }
+    }
+    CProxy::free(proxy);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_add_listener(
+    proxy: *mut wl_proxy,
+    listener: *mut extern "C" fn(),
+    data: *mut c_void,
+) -> c_int {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_add_listener, proxy, listener, data);
+    let proxy = &mut *(proxy as *mut CProxy);
+    if proxy.kind != Kind::Created {
+        eprintln!(
+            "[wayland-client] Proxy {}@{} is not dispatched by the C API, it can't have a listener.",
+            proxy.inner.object.interface, proxy.id
+        );
+        return -1;
+    }
+    if !proxy.listener.is_null() {
+        eprintln!(
+            "[wayland-client] Proxy {}@{} already has a listener.",
+            proxy.inner.object.interface, proxy.id
+        );
+        return -1;
+    }
+    proxy.listener = listener as *const c_void;
+    proxy.user_data = data;
+    if proxy.inner.is_alive() {
+        let dispatcher = Arc::new(Mutex::new(ListenerDispatcher { proxy }));
+        let _ = proxy.inner.map.with(proxy.id, |obj| obj.meta.dispatcher = dispatcher);
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_get_listener(proxy: *mut wl_proxy) -> *const c_void {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_get_listener, proxy);
+    (*(proxy as *const CProxy)).listener
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_set_user_data(proxy: *mut wl_proxy, data: *mut c_void) {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_set_user_data, proxy, data);
+    (*(proxy as *mut CProxy)).user_data = data;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_get_user_data(proxy: *mut wl_proxy) -> *mut c_void {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_get_user_data, proxy);
+    (*(proxy as *const CProxy)).user_data
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_get_version(proxy: *mut wl_proxy) -> u32 {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_get_version, proxy);
+    (*(proxy as *const CProxy)).inner.version()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_get_id(proxy: *mut wl_proxy) -> u32 {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_get_id, proxy);
+    (*(proxy as *const CProxy)).id
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_get_class(proxy: *mut wl_proxy) -> *const c_char {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_get_class, proxy);
+    let interface = (*(proxy as *const CProxy)).interface;
+    if interface.is_null() {
+        ptr::null()
+    } else {
+        (*interface).name
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_set_queue(proxy: *mut wl_proxy, queue: *mut wl_event_queue) {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_set_queue, proxy, queue);
+    let proxy = &mut *(proxy as *mut CProxy);
+    let buffer = c_queue(&*proxy.display, queue).buffer.clone();
+    // the events of the other proxies stay on the queue of their owner, only the objects they
+    // create are affected
+    if proxy.kind == Kind::Created && proxy.inner.is_alive() {
+        let _ = proxy.inner.map.with(proxy.id, |obj| obj.meta.buffer = buffer.clone());
+    }
+    proxy.inner.queue = Some(buffer);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_create_wrapper(proxy: *mut wl_proxy) -> *mut wl_proxy {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_create_wrapper, proxy);
+    let proxy = &*(proxy as *const CProxy);
+    let wrapper = CProxy::new(proxy.display, proxy.interface, proxy.inner.clone(), Kind::Wrapper);
+    (*wrapper).user_data = proxy.user_data;
+    wrapper as *mut wl_proxy
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wl_proxy_wrapper_destroy(proxy: *mut wl_proxy) {
+    forward_foreign!(proxy, WAYLAND_CLIENT_HANDLE, wl_proxy_wrapper_destroy, proxy);
+    let proxy = proxy as *mut CProxy;
+    if (*proxy).kind != Kind::Wrapper {
+        eprintln!(
+            "[wayland-client] Tried to destroy non-wrapper proxy with wl_proxy_wrapper_destroy"
+        );
+        return;
+    }
+    CProxy::free(proxy);
+}
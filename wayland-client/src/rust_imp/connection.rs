@@ -1,17 +1,22 @@
-use std::cell::RefCell;
+use std::io;
+use std::mem::{discriminant, Discriminant};
 use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use nix::Result as NixResult;
 
-use wayland_commons::map::{Object, ObjectMap, SERVER_ID_LIMIT};
-use wayland_commons::socket::{BufferedSocket, Socket};
-use wayland_commons::wire::{Argument, ArgumentType, Message, MessageParseError};
+use wayland_commons::capture::{Direction, Recorder};
+use wayland_commons::map::{Object, SERVER_ID_LIMIT};
+use wayland_commons::socket::{BufferedSocket, Socket, DEFAULT_MAX_MESSAGE_SIZE};
+use wayland_commons::wire::{Argument, ArgumentType, Message, MessageParseError, Strictness};
 
+use super::discard_zombie_event;
+use super::map::ObjectStore;
 use super::proxy::ObjectMeta;
 use super::queues::QueueBuffer;
 
-use crate::ProtocolError;
+use crate::{ConnectionState, DispatchError, ProtocolError, RawEvent, ZombiePolicy};
 
 #[derive(Clone, Debug)]
 pub(crate) enum Error {
@@ -20,62 +25,358 @@ pub(crate) enum Error {
     Nix(::nix::Error),
 }
 
+/// Number of staging buffers, the threads sending requests are spread among them
+const STAGING_SHARDS: usize = 16;
+/// Number of requests staged in a buffer after which they are written to the socket
+const STAGING_LIMIT: usize = 256;
+
+static NEXT_STAGING_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static STAGING_SHARD: usize = NEXT_STAGING_SHARD.fetch_add(1, Ordering::Relaxed) % STAGING_SHARDS;
+}
+
+// A request staged without taking the connection lock
+struct StagedRequest {
+    sequence: usize,
+    interface: &'static str,
+    name: &'static str,
+    alive: Arc<AtomicBool>,
+    msg: Message,
+}
+
+/// Buffers of the requests sent without taking the connection lock
+///
+/// Each thread stages its requests in its own buffer, they are merged back in the order they
+/// were sent when the connection writes to its socket. The requests of the objects destroyed
+/// in the meantime are discarded, as if they were sent after the destruction.
+#[derive(Default)]
+pub(crate) struct RequestStaging {
+    enabled: AtomicBool,
+    sequence: AtomicUsize,
+    pending: AtomicUsize,
+    shards: [Mutex<Vec<StagedRequest>>; STAGING_SHARDS],
+}
+
+impl RequestStaging {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Stage a request, returns `true` if the buffer of this thread is full
+    pub(crate) fn stage(
+        &self,
+        interface: &'static str,
+        name: &'static str,
+        alive: Arc<AtomicBool>,
+        msg: Message,
+    ) -> bool {
+        let mut shard = self.shards[STAGING_SHARD.with(|&shard| shard)].lock().unwrap();
+        // the sequence number is taken with the lock held, so that a merge never misses a
+        // request older than the ones it writes from the same buffer
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        shard.push(StagedRequest { sequence, interface, name, alive, msg });
+        self.pending.fetch_add(1, Ordering::Release);
+        shard.len() >= STAGING_LIMIT
+    }
+
+    fn take(&self) -> Vec<StagedRequest> {
+        if self.pending.load(Ordering::Acquire) == 0 {
+            return Vec::new();
+        }
+        // all the buffers are locked before draining any: a request staged in a buffer already
+        // drained could otherwise be older than one staged in a buffer drained afterwards, and
+        // be written after it
+        let mut shards = self.shards.iter().map(|shard| shard.lock().unwrap()).collect::<Vec<_>>();
+        let mut requests = Vec::new();
+        for shard in &mut shards {
+            requests.append(shard);
+        }
+        self.pending.fetch_sub(requests.len(), Ordering::Release);
+        requests.sort_by_key(|request| request.sequence);
+        requests
+    }
+}
+
+/// Limit on the number of fds held in undispatched events
+pub(crate) struct FdBudget {
+    pub(crate) limit: usize,
+    pub(crate) on_pressure: Box<dyn FnMut(usize) + Send>,
+    under_pressure: bool,
+}
+
+impl FdBudget {
+    pub(crate) fn new(limit: usize, on_pressure: Box<dyn FnMut(usize) + Send>) -> FdBudget {
+        FdBudget { limit, on_pressure, under_pressure: false }
+    }
+}
+
+pub(crate) type StateListener = Box<dyn FnMut(&ConnectionState) + Send>;
+
+pub(crate) type ZombieCallback = Arc<Mutex<Box<dyn FnMut(u32, RawEvent) + Send>>>;
+
+/// The handling of the events of destroyed proxies, as set by a `ZombiePolicy`
+#[derive(Clone)]
+pub(crate) enum ZombieHandler {
+    Discard,
+    Log,
+    Deliver(ZombieCallback),
+}
+
+impl From<ZombiePolicy> for ZombieHandler {
+    fn from(policy: ZombiePolicy) -> ZombieHandler {
+        match policy {
+            ZombiePolicy::Discard => ZombieHandler::Discard,
+            ZombiePolicy::Log => ZombieHandler::Log,
+            ZombiePolicy::Deliver(callback) => {
+                ZombieHandler::Deliver(Arc::new(Mutex::new(callback)))
+            }
+        }
+    }
+}
+
+pub(crate) fn count_fds(msg: &Message) -> usize {
+    msg.args.iter().filter(|a| a.get_type() == ArgumentType::Fd).count()
+}
+
 pub(crate) struct Connection {
     pub(crate) socket: BufferedSocket,
-    pub(crate) map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+    pub(crate) map: Arc<ObjectStore<ObjectMeta>>,
     pub(crate) last_error: Arc<Mutex<Option<Error>>>,
     pub(crate) display_buffer: QueueBuffer,
+    pub(crate) recorder: Option<Recorder>,
+    // number of fds contained in the events waiting in the queue buffers
+    pub(crate) held_fds: Arc<AtomicUsize>,
+    pub(crate) fd_budget: Option<FdBudget>,
+    // whether any message was received from the server
+    received: bool,
+    state_listener: Option<StateListener>,
+    notified_state: Discriminant<ConnectionState>,
+    pub(crate) zombie_handler: ZombieHandler,
+    // number of events received for destroyed proxies
+    pub(crate) zombie_events: usize,
+    // events received for destroyed proxies, waiting to be delivered to the zombie callback
+    pub(crate) zombie_queue: Vec<(Message, Object<ObjectMeta>)>,
+    // number of times the connection was reset
+    pub(crate) generation: usize,
+    pub(crate) strictness: Strictness,
+    max_message_size: usize,
+    pub(crate) staging: Arc<RequestStaging>,
+    // the `wl_display` of this connection given to C, if any
+    #[cfg(feature = "c_abi")]
+    pub(crate) c_display: Option<Box<super::c_abi::CDisplay>>,
 }
 
 impl Connection {
     pub(crate) unsafe fn new(fd: RawFd, display_object: Object<ObjectMeta>) -> Connection {
         let socket = BufferedSocket::new(Socket::from_raw_fd(fd));
 
-        let mut map = ObjectMap::new();
+        let map = ObjectStore::new();
         // Insert first pre-existing object
         let display_buffer = display_object.meta.buffer.clone();
+        let staging = display_object.meta.staging.clone();
         map.insert_at(1, display_object).unwrap();
 
         Connection {
             socket,
-            map: Arc::new(Mutex::new(map)),
+            map: Arc::new(map),
             last_error: Arc::new(Mutex::new(None)),
             display_buffer,
+            recorder: None,
+            held_fds: Arc::new(AtomicUsize::new(0)),
+            fd_budget: None,
+            received: false,
+            state_listener: None,
+            notified_state: discriminant(&ConnectionState::Connecting),
+            zombie_handler: ZombieHandler::Discard,
+            zombie_events: 0,
+            zombie_queue: Vec::new(),
+            generation: 0,
+            strictness: Strictness::Lenient,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            staging,
+            #[cfg(feature = "c_abi")]
+            c_display: None,
         }
     }
 
-    pub(crate) fn write_message(&mut self, msg: &Message) -> NixResult<()> {
-        self.socket.write_message(msg)
+    pub(crate) fn set_max_message_size(&mut self, size: usize) {
+        self.socket.set_max_message_size(size);
+        // store the clamped value, so that it is preserved across reconnections
+        self.max_message_size = self.socket.max_message_size();
+    }
+
+    /// Replace the socket of this connection with a new one, to reconnect to a server
+    ///
+    /// All the objects but the display are marked as dead, and their undispatched events are
+    /// discarded. The `Arc`s shared with the event queues and the proxies are kept, so that
+    /// they keep working with the new connection.
+    pub(crate) unsafe fn reset(&mut self, fd: RawFd) {
+        self.socket = BufferedSocket::new(Socket::from_raw_fd(fd));
+        self.socket.set_max_message_size(self.max_message_size);
+        let display_object = self.map.find(1).unwrap();
+        for (id, obj) in self.map.clear() {
+            if id != 1 {
+                obj.meta.alive.store(false, Ordering::Release);
+            }
+            for msg in obj.meta.buffer.lock().unwrap().drain() {
+                discard_zombie_event(msg, None, false);
+            }
+        }
+        self.map.insert_at(1, display_object).unwrap();
+        for (msg, _) in self.zombie_queue.drain(..) {
+            discard_zombie_event(msg, None, false);
+        }
+        // the staged requests were sent to the previous server
+        self.staging.take();
+        self.held_fds.store(0, Ordering::Release);
+        if let Some(ref mut budget) = self.fd_budget {
+            budget.under_pressure = false;
+        }
+        *self.last_error.lock().unwrap() = None;
+        self.received = false;
+        self.generation += 1;
+        self.notify_state();
+    }
+
+    pub(crate) fn state(&self) -> ConnectionState {
+        match *self.last_error.lock().unwrap() {
+            None if self.received => ConnectionState::Ready,
+            None => ConnectionState::Connecting,
+            Some(Error::Protocol(ref e)) => ConnectionState::ErrorDeferred(e.clone()),
+            Some(Error::Parse(_)) => ConnectionState::Dead(io::ErrorKind::InvalidData),
+            Some(Error::Nix(::nix::Error::Sys(errno))) => {
+                ConnectionState::Dead(io::Error::from(errno).kind())
+            }
+            Some(Error::Nix(_)) => ConnectionState::Dead(io::ErrorKind::Other),
+        }
+    }
+
+    /// The error matching the state of the connection, if it is failed
+    pub(crate) fn error(&self) -> Option<DispatchError> {
+        self.state().error()
+    }
+
+    pub(crate) fn set_state_listener(&mut self, listener: Option<StateListener>) {
+        self.state_listener = listener;
+    }
+
+    fn notify_state(&mut self) {
+        let state = self.state();
+        if discriminant(&state) != self.notified_state {
+            self.notified_state = discriminant(&state);
+            if let Some(ref mut listener) = self.state_listener {
+                listener(&state);
+            }
+        }
+    }
+
+    // mark the connection as dead, unless it already failed
+    fn fail(&mut self, err: ::nix::Error) {
+        {
+            let mut last_error = self.last_error.lock().unwrap();
+            if last_error.is_none() {
+                *last_error = Some(Error::Nix(err));
+            }
+        }
+        self.notify_state();
+    }
+
+    /// Queue a message to be sent to the server
+    ///
+    /// Failing to do so kills the connection, as the server and the client would no longer
+    /// agree on the state of the objects. Once the connection failed, messages are discarded.
+    pub(crate) fn write_message(&mut self, msg: &Message) {
+        if self.last_error.lock().unwrap().is_some() {
+            return;
+        }
+        match self.socket.write_message(msg) {
+            // the socket is full but the message is queued, it is sent by a later flush
+            Ok(()) | Err(::nix::Error::Sys(::nix::errno::Errno::EAGAIN)) => {}
+            Err(e) => self.fail(e),
+        }
+    }
+
+    pub(crate) fn set_request_staging(&mut self, enabled: bool) {
+        self.staging.enabled.store(enabled, Ordering::Release);
+        if !enabled {
+            self.write_staged();
+        }
+    }
+
+    /// Write the staged requests to the socket, in the order they were sent
+    pub(crate) fn write_staged(&mut self) {
+        for request in self.staging.take() {
+            if !request.alive.load(Ordering::Acquire) {
+                continue;
+            }
+            if let Some(ref recorder) = self.recorder {
+                recorder.record(Direction::Sent, request.interface, request.name, &request.msg);
+            }
+            self.write_message(&request.msg);
+        }
     }
 
     pub(crate) fn flush(&mut self) -> NixResult<()> {
-        self.socket.flush()
+        self.write_staged();
+        let ret = self.socket.flush();
+        match ret {
+            // non-fatal errors, EPIPE may be followed by a protocol error waiting to be read
+            Ok(())
+            | Err(::nix::Error::Sys(::nix::errno::Errno::EAGAIN))
+            | Err(::nix::Error::Sys(::nix::errno::Errno::EPIPE)) => {}
+            Err(e) => self.fail(e),
+        }
+        ret
     }
 
     pub(crate) fn read_events(&mut self) -> Result<usize, Error> {
+        let ret = self.read_events_inner();
+        self.notify_state();
+        ret
+    }
+
+    fn read_events_inner(&mut self) -> Result<usize, Error> {
         if let Some(ref err) = *self.last_error.lock().unwrap() {
             return Err(err.clone());
         }
-        // acquire the map lock, this means no objects can be created nor destroyed while we
-        // are reading events
-        let mut map = self.map.lock().unwrap();
-        // wrap it in a RefCell for cheap sharing in the two closures below
-        let map = RefCell::new(&mut *map);
+        // stop reading while too many fds are held in undispatched events, they are
+        // left in the socket until the queues are dispatched
+        if let Some(ref mut budget) = self.fd_budget {
+            let held = self.held_fds.load(Ordering::Acquire);
+            if held >= budget.limit {
+                if !budget.under_pressure {
+                    budget.under_pressure = true;
+                    (budget.on_pressure)(held);
+                }
+                return Err(Error::Nix(::nix::Error::Sys(::nix::errno::Errno::EAGAIN)));
+            }
+            budget.under_pressure = false;
+        }
+        // the objects can't be created nor destroyed by requests while we are reading events, as
+        // the connection is locked
+        let map = &*self.map;
         let mut last_error = self.last_error.lock().unwrap();
+        let recorder = self.recorder.as_ref();
+        let held_fds = &self.held_fds;
+        // the events of destroyed proxies are queued if they are to be delivered
+        let (queue_zombies, log_zombies) = match self.zombie_handler {
+            ZombieHandler::Discard => (false, false),
+            ZombieHandler::Log => (false, true),
+            ZombieHandler::Deliver(_) => (true, false),
+        };
+        let mut zombie_events = 0;
+        let zombie_queue = &mut self.zombie_queue;
         // read messages
-        let ret = self.socket.read_messages(
-            |id, opcode| {
-                map.borrow()
-                    .find(id)
-                    .and_then(|o| o.events.get(opcode as usize))
-                    .map(|desc| desc.signature)
-            },
+        let ret = self.socket.read_messages_with(
+            self.strictness,
+            |id, opcode| map.find(id)?.event_desc(opcode),
             |msg| {
                 // Early exit on protocol error
                 if msg.sender_id == 1 && msg.opcode == 0 {
                     if let [Argument::Object(faulty_id), Argument::Uint(error_code), Argument::Str(ref error_msg)] = &msg.args[..] {
                         let error_msg = error_msg.to_string_lossy().into_owned();
-                        let faulty_interface = map.borrow().find(*faulty_id).map(|obj| obj.interface).unwrap_or("unknown");
+                        let faulty_interface = map.find(*faulty_id).map(|obj| obj.interface).unwrap_or("unknown");
                         // abort parsing, this is an unrecoverable error
                         *last_error = Some(Error::Protocol(ProtocolError {
                             code: *error_code,
@@ -90,9 +391,31 @@ impl Connection {
                 }
 
                 // dispatch the message to the proper object
-                let mut map = map.borrow_mut();
                 let object = map.find(msg.sender_id);
 
+                #[cfg(feature = "tracing")]
+                {
+                    let (interface, name) = object
+                        .as_ref()
+                        .map(|o| (o.interface, o.events[msg.opcode as usize].name))
+                        .unwrap_or(("unknown", "unknown"));
+                    tracing::trace!(
+                        interface,
+                        opcode = msg.opcode,
+                        id = msg.sender_id,
+                        "decoded event {}",
+                        name
+                    );
+                }
+
+                if let Some(recorder) = recorder {
+                    let (interface, name) = object
+                        .as_ref()
+                        .map(|o| (o.interface, o.events[msg.opcode as usize].name))
+                        .unwrap_or(("unknown", "unknown"));
+                    recorder.record(Direction::Received, interface, name, &msg);
+                }
+
                 // create a new object if applicable
                 if let Some((mut child, dead_parent)) = object
                     .as_ref()
@@ -106,10 +429,8 @@ impl Connection {
                         .unwrap();
                     let child_interface = child.interface;
                     // if this ID belonged to a now destroyed server object, we can replace it
-                    if new_id >= SERVER_ID_LIMIT
-                        && map.with(new_id, |obj| obj.meta.client_destroyed).unwrap_or(false)
-                    {
-                        map.remove(new_id)
+                    if new_id >= SERVER_ID_LIMIT {
+                        let _ = map.remove_if(new_id, |obj| obj.meta.client_destroyed);
                     }
                     // if the parent object is already destroyed, the user will never see this
                     // object, so we set it as client_destroyed to ignore all future messages to it
@@ -138,18 +459,25 @@ impl Connection {
 
                 // send the message to the appropriate pending queue
                 match object {
-                    Some(Object { meta: ObjectMeta { client_destroyed: true, .. }, .. }) | None => {
+                    Some(ref obj) if !obj.meta.client_destroyed => {
+                        held_fds.fetch_add(count_fds(&msg), Ordering::AcqRel);
+                        let mut buffer = obj.meta.buffer.lock().unwrap();
+                        // read with the buffer locked, like it is changed
+                        buffer.push(msg, obj.meta.high_priority.load(Ordering::Acquire));
+                    }
+                    Some(obj) if queue_zombies => {
+                        // the object may be released before the event could be dispatched
+                        // from its queue, so the zombie events are delivered apart
+                        zombie_events += 1;
+                        held_fds.fetch_add(count_fds(&msg), Ordering::AcqRel);
+                        zombie_queue.push((msg, obj));
+                    }
+                    _ => {
                         // this is a message sent to a destroyed object
                         // to avoid dying because of races, we just consume it into void
                         // closing any associated FDs
-                        for a in msg.args {
-                            if let Argument::Fd(fd) = a {
-                                let _ = ::nix::unistd::close(fd);
-                            }
-                        }
-                    }
-                    Some(obj) => {
-                        obj.meta.buffer.lock().unwrap().push_back(msg);
+                        zombie_events += 1;
+                        discard_zombie_event(msg, object.as_ref(), log_zombies);
                     }
                 };
 
@@ -158,13 +486,20 @@ impl Connection {
             },
         );
 
+        self.zombie_events += zombie_events;
+
         if let Some(ref e) = *last_error {
             // a protocol error was generated, don't lose it, it is the source of any subsequent error
             return Err(e.clone());
         }
 
         match ret {
-            Ok(Ok(n)) => Ok(n),
+            Ok(Ok(n)) => {
+                if n > 0 {
+                    self.received = true;
+                }
+                Ok(n)
+            }
             Ok(Err(e)) => {
                 *last_error = Some(Error::Parse(e.clone()));
                 Err(Error::Parse(e))
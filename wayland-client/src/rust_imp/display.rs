@@ -1,24 +1,33 @@
-use std::io;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
+use wayland_commons::capture::{Capture, Recorder};
 use wayland_commons::debug;
-use wayland_commons::map::{Object, ObjectMap};
-use wayland_commons::wire::Message;
+use wayland_commons::map::Object;
+use wayland_commons::wire::{Message, Strictness};
 use wayland_commons::MessageGroup;
 
 use crate::protocol::wl_display::{self, WlDisplay};
 
-use crate::{ConnectError, ProtocolError, Proxy};
+use crate::{
+    Backend, ConnectError, ConnectionState, DispatchError, FlushProgress, ObjectInfo,
+    ProtocolError, Proxy, ZombiePolicy,
+};
 
-use super::connection::{Connection, Error as CxError};
+use super::connection::{Connection, Error as CxError, FdBudget, StateListener};
+use super::map::ObjectStore;
 use super::proxy::{ObjectMeta, ProxyInner};
-use super::{Dispatched, EventQueueInner, ProxyMap, WAYLAND_DEBUG};
+use super::{
+    trace_destruction, Dispatched, EventQueueInner, ProxyMap, TRACE_DESTRUCTION, WAYLAND_DEBUG,
+};
 
 pub(crate) struct DisplayInner {
     connection: Arc<Mutex<Connection>>,
     proxy: Proxy<WlDisplay>,
+    // the socket to connect to again on reconnection, if known
+    socket_path: Mutex<Option<PathBuf>>,
 }
 
 impl DisplayInner {
@@ -30,10 +39,14 @@ impl DisplayInner {
                 WAYLAND_DEBUG.store(true, Ordering::Relaxed);
             }
         }
+        if std::env::var_os("WAYLAND_RS_TRACE_DESTRUCTION").map(|v| v == "1").unwrap_or(false) {
+            TRACE_DESTRUCTION.store(true, Ordering::Relaxed);
+        }
 
         // The special buffer for display events
         let buffer = super::queues::create_queue_buffer();
-        let display_object = Object::from_interface::<WlDisplay>(1, ObjectMeta::new(buffer));
+        let display_object =
+            Object::from_interface::<WlDisplay>(1, ObjectMeta::new(buffer, Default::default()));
         let (connection, map) = {
             let c = Connection::new(fd, display_object);
             let m = c.map.clone();
@@ -41,28 +54,41 @@ impl DisplayInner {
         };
 
         // Setup the display dispatcher
-        map.lock()
-            .unwrap()
-            .with(1, |obj| {
-                obj.meta.dispatcher = Arc::new(Mutex::new(DisplayDispatcher {
-                    map: map.clone(),
-                    last_error: connection.lock().unwrap().last_error.clone(),
-                }));
-            })
-            .unwrap();
+        map.with(1, |obj| {
+            obj.meta.dispatcher = Arc::new(Mutex::new(DisplayDispatcher {
+                map: map.clone(),
+                last_error: connection.lock().unwrap().last_error.clone(),
+            }));
+        })
+        .unwrap();
 
         let display_proxy = ProxyInner::from_id(1, map, connection.clone()).unwrap();
 
-        let display = DisplayInner { proxy: Proxy::wrap(display_proxy), connection };
+        let display = DisplayInner {
+            proxy: Proxy::wrap(display_proxy),
+            connection,
+            socket_path: Mutex::new(None),
+        };
 
         Ok(Arc::new(display))
     }
 
-    pub(crate) fn flush(&self) -> io::Result<()> {
-        match self.connection.lock().unwrap().flush() {
-            Ok(()) => Ok(()),
-            Err(::nix::Error::Sys(errno)) => Err(errno.into()),
-            Err(_) => unreachable!(),
+    pub(crate) fn backend(&self) -> Backend {
+        Backend::Rust
+    }
+
+    pub(crate) fn flush(&self) -> Result<FlushProgress, DispatchError> {
+        flush(&self.connection)
+    }
+
+    pub(crate) fn ptr(&self) -> *mut wayland_sys::client::wl_display {
+        #[cfg(feature = "c_abi")]
+        {
+            super::c_abi::display_ptr(&self.connection)
+        }
+        #[cfg(not(feature = "c_abi"))]
+        {
+            super::c_interfacing()
         }
     }
 
@@ -70,6 +96,13 @@ impl DisplayInner {
         EventQueueInner::new(me.connection.clone(), None)
     }
 
+    pub(crate) unsafe fn create_event_queue_from_external(
+        _me: &Arc<DisplayInner>,
+        _queue: *mut wayland_sys::client::wl_event_queue,
+    ) -> EventQueueInner {
+        panic!("[wayland-client] C interfacing methods can only be used with the native backend of the `use_system_lib` cargo feature.")
+    }
+
     pub(crate) fn get_proxy(&self) -> &Proxy<WlDisplay> {
         &self.proxy
     }
@@ -84,14 +117,120 @@ impl DisplayInner {
         }
     }
 
+    pub(crate) fn state(&self) -> ConnectionState {
+        self.connection.lock().unwrap().state()
+    }
+
+    pub(crate) fn set_state_listener(&self, listener: Option<StateListener>) {
+        self.connection.lock().unwrap().set_state_listener(listener);
+    }
+
+    pub(crate) fn socket_path(&self) -> Option<PathBuf> {
+        self.socket_path.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_socket_path(&self, path: PathBuf) {
+        *self.socket_path.lock().unwrap() = Some(path);
+    }
+
+    pub(crate) unsafe fn reconnect(&self, fd: RawFd) {
+        self.connection.lock().unwrap().reset(fd);
+    }
+
     pub(crate) fn get_connection_fd(&self) -> ::std::os::unix::io::RawFd {
         self.connection.lock().unwrap().socket.get_socket().as_raw_fd()
     }
+
+    pub(crate) fn objects(&self) -> Vec<ObjectInfo> {
+        let map = self.connection.lock().unwrap().map.clone();
+        map.snapshot()
+            .into_iter()
+            .map(|(id, obj)| ObjectInfo {
+                id,
+                interface: obj.interface,
+                version: obj.version,
+                alive: obj.meta.alive.load(Ordering::Acquire) && !obj.meta.client_destroyed,
+            })
+            .collect()
+    }
+
+    pub(crate) fn start_capture(&self) {
+        let mut cx = self.connection.lock().unwrap();
+        if cx.recorder.is_none() {
+            cx.recorder = Some(Recorder::new());
+        }
+    }
+
+    pub(crate) fn stop_capture(&self) -> Option<Capture> {
+        self.connection.lock().unwrap().recorder.take().map(|r| r.snapshot())
+    }
+
+    pub(crate) fn set_fd_budget(&self, budget: Option<FdBudget>) {
+        self.connection.lock().unwrap().fd_budget = budget;
+    }
+
+    pub(crate) fn pending_fds(&self) -> usize {
+        self.connection.lock().unwrap().held_fds.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_zombie_policy(&self, policy: ZombiePolicy) {
+        self.connection.lock().unwrap().zombie_handler = policy.into();
+    }
+
+    pub(crate) fn zombie_events(&self) -> usize {
+        self.connection.lock().unwrap().zombie_events
+    }
+
+    pub(crate) fn set_strictness(&self, strictness: Strictness) {
+        self.connection.lock().unwrap().strictness = strictness;
+    }
+
+    pub(crate) fn set_max_message_size(&self, size: usize) {
+        self.connection.lock().unwrap().set_max_message_size(size);
+    }
+
+    pub(crate) fn set_request_staging(&self, enabled: bool) {
+        self.connection.lock().unwrap().set_request_staging(enabled);
+    }
+}
+
+#[cfg(feature = "c_abi")]
+impl Drop for DisplayInner {
+    fn drop(&mut self) {
+        // the `wl_display` given to C holds handles to the connection, it is released even if
+        // a panic poisoned the connection
+        let c_display = self
+            .connection
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .c_display
+            .take();
+        drop(c_display);
+    }
+}
+
+/// Write the requests of a connection to its socket
+pub(crate) fn flush(connection: &Mutex<Connection>) -> Result<FlushProgress, DispatchError> {
+    let mut cx = connection.lock().unwrap();
+    if let Some(err) = cx.error() {
+        return Err(err);
+    }
+    cx.write_staged();
+    let pending = cx.socket.pending_bytes();
+    let ret = cx.flush();
+    let remaining = cx.socket.pending_bytes();
+    let progress = FlushProgress { written: pending - remaining, remaining };
+    match ret {
+        Ok(()) => Ok(progress),
+        Err(::nix::Error::Sys(::nix::errno::Errno::EAGAIN)) if progress.written > 0 => Ok(progress),
+        Err(::nix::Error::Sys(errno)) => Err(DispatchError::Backend(errno.into())),
+        Err(_) => unreachable!(),
+    }
 }
 
 // WlDisplay needs its own dispatcher, as it can be dispatched from multiple threads
 struct DisplayDispatcher {
-    map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+    map: Arc<ObjectStore<ObjectMeta>>,
     last_error: Arc<Mutex<Option<CxError>>>,
 }
 
@@ -122,28 +261,27 @@ impl super::Dispatcher for DisplayDispatcher {
                 eprintln!(
                     "[wayland-client] Protocol error {} on object {}@{}: {}",
                     code,
-                    object_id.as_ref().inner.object.interface,
+                    super::rust_proxy(object_id.as_ref()).object.interface,
                     object_id.as_ref().id(),
                     message
                 );
                 *self.last_error.lock().unwrap() = Some(CxError::Protocol(ProtocolError {
                     code,
                     object_id: object_id.as_ref().id(),
-                    object_interface: object_id.as_ref().inner.object.interface,
+                    object_interface: super::rust_proxy(object_id.as_ref()).object.interface,
                     message,
                 }));
             }
             wl_display::Event::DeleteId { id } => {
                 // cleanup the map as appropriate
-                let mut map = self.map.lock().unwrap();
-                let client_destroyed = map
-                    .with(id, |obj| {
-                        obj.meta.server_destroyed = true;
-                        obj.meta.client_destroyed
-                    })
-                    .unwrap_or(false);
-                if client_destroyed {
-                    map.remove(id);
+                let mut interface = "";
+                let released = self.map.remove_if(id, |obj| {
+                    obj.meta.server_destroyed = true;
+                    interface = obj.interface;
+                    obj.meta.client_destroyed
+                });
+                if released == Ok(true) {
+                    trace_destruction(interface, id, format_args!("released"));
                 }
             }
         }
@@ -0,0 +1,210 @@
+use std::collections::BTreeSet;
+use std::sync::{Mutex, RwLock};
+
+use wayland_commons::map::{Object, ObjectMetadata, SERVER_ID_LIMIT};
+
+/// Number of shards of each namespace of the store
+const SHARDS: usize = 16;
+
+/// The object store of a connection
+///
+/// This plays the role of the `ObjectMap` of `wayland-commons`, but its objects are spread
+/// among several locks, so that the threads looking up objects to dispatch their events or
+/// to parse their arguments don't contend with each other. Only creating and removing objects
+/// takes a lock common to all of them.
+pub(crate) struct ObjectStore<Meta: ObjectMetadata> {
+    client: Namespace<Meta>,
+    server: Namespace<Meta>,
+}
+
+impl<Meta: ObjectMetadata> ObjectStore<Meta> {
+    pub(crate) fn new() -> ObjectStore<Meta> {
+        ObjectStore { client: Namespace::new(), server: Namespace::new() }
+    }
+
+    // The namespace of an id and the index of the object in it
+    fn locate(&self, id: u32) -> Option<(&Namespace<Meta>, u32)> {
+        if id == 0 {
+            None
+        } else if id >= SERVER_ID_LIMIT {
+            Some((&self.server, id - SERVER_ID_LIMIT))
+        } else {
+            Some((&self.client, id - 1))
+        }
+    }
+
+    /// Find an object in the store
+    pub(crate) fn find(&self, id: u32) -> Option<Object<Meta>> {
+        let (namespace, idx) = self.locate(id)?;
+        namespace.find(idx)
+    }
+
+    /// Mutably access an object of the store, and remove it if `f` returns `true`
+    ///
+    /// Returns whether the object was removed, or an error if there is no such object.
+    pub(crate) fn remove_if<F: FnOnce(&mut Object<Meta>) -> bool>(
+        &self,
+        id: u32,
+        f: F,
+    ) -> Result<bool, ()> {
+        let (namespace, idx) = self.locate(id).ok_or(())?;
+        namespace.remove_if(idx, f)
+    }
+
+    /// Insert given object for given id
+    ///
+    /// Can fail if the requested id is not the next free id of this store.
+    /// (In which case this is a protocol error)
+    pub(crate) fn insert_at(&self, id: u32, object: Object<Meta>) -> Result<(), ()> {
+        let (namespace, idx) = self.locate(id).ok_or(())?;
+        namespace.insert_at(idx, object)
+    }
+
+    /// Allocate a new id for an object in the client namespace
+    pub(crate) fn client_insert_new(&self, object: Object<Meta>) -> u32 {
+        self.client.insert_new(object) + 1
+    }
+
+    /// Mutably access an object of the store
+    pub(crate) fn with<T, F: FnOnce(&mut Object<Meta>) -> T>(
+        &self,
+        id: u32,
+        f: F,
+    ) -> Result<T, ()> {
+        let (namespace, idx) = self.locate(id).ok_or(())?;
+        namespace.with(idx, f)
+    }
+
+    /// All the objects of the store with their ids, in the order of their ids
+    pub(crate) fn snapshot(&self) -> Vec<(u32, Object<Meta>)> {
+        let mut objects = self.client.snapshot(1);
+        objects.extend(self.server.snapshot(SERVER_ID_LIMIT));
+        objects
+    }
+
+    /// Remove all the objects of the store, and return them with their ids
+    pub(crate) fn clear(&self) -> Vec<(u32, Object<Meta>)> {
+        let mut objects = self.client.clear(1);
+        objects.extend(self.server.clear(SERVER_ID_LIMIT));
+        objects
+    }
+}
+
+// Object `idx` of a namespace is in slot `idx / SHARDS` of shard `idx % SHARDS`
+struct Namespace<Meta: ObjectMetadata> {
+    shards: Vec<RwLock<Vec<Option<Object<Meta>>>>>,
+    // taken before the shards to insert or remove objects
+    ids: Mutex<Ids>,
+}
+
+// The allocation of the ids of a namespace
+#[derive(Default)]
+struct Ids {
+    // the number of objects the namespace had room for so far
+    len: u32,
+    // the free places before `len`
+    free: BTreeSet<u32>,
+}
+
+impl<Meta: ObjectMetadata> Namespace<Meta> {
+    fn new() -> Namespace<Meta> {
+        Namespace {
+            shards: (0..SHARDS).map(|_| RwLock::new(Vec::new())).collect(),
+            ids: Mutex::new(Ids::default()),
+        }
+    }
+
+    fn shard(&self, idx: u32) -> (&RwLock<Vec<Option<Object<Meta>>>>, usize) {
+        (&self.shards[idx as usize % SHARDS], idx as usize / SHARDS)
+    }
+
+    fn find(&self, idx: u32) -> Option<Object<Meta>> {
+        let (shard, slot) = self.shard(idx);
+        shard.read().unwrap().get(slot).and_then(Clone::clone)
+    }
+
+    fn with<T, F: FnOnce(&mut Object<Meta>) -> T>(&self, idx: u32, f: F) -> Result<T, ()> {
+        let (shard, slot) = self.shard(idx);
+        match shard.write().unwrap().get_mut(slot) {
+            Some(&mut Some(ref mut obj)) => Ok(f(obj)),
+            _ => Err(()),
+        }
+    }
+
+    fn remove_if<F: FnOnce(&mut Object<Meta>) -> bool>(&self, idx: u32, f: F) -> Result<bool, ()> {
+        let mut ids = self.ids.lock().unwrap();
+        let (shard, slot) = self.shard(idx);
+        let mut shard = shard.write().unwrap();
+        let place = match shard.get_mut(slot) {
+            Some(place) if place.is_some() => place,
+            _ => return Err(()),
+        };
+        if !f(place.as_mut().unwrap()) {
+            return Ok(false);
+        }
+        *place = None;
+        ids.free.insert(idx);
+        Ok(true)
+    }
+
+    // Store an object in a place reserved for it
+    fn store(&self, idx: u32, object: Object<Meta>) {
+        let (shard, slot) = self.shard(idx);
+        let mut shard = shard.write().unwrap();
+        if shard.len() <= slot {
+            shard.resize_with(slot + 1, || None);
+        }
+        shard[slot] = Some(object);
+    }
+
+    fn insert_at(&self, idx: u32, object: Object<Meta>) -> Result<(), ()> {
+        let mut ids = self.ids.lock().unwrap();
+        if idx == ids.len {
+            ids.len += 1;
+        } else if !ids.free.remove(&idx) {
+            return Err(());
+        }
+        self.store(idx, object);
+        Ok(())
+    }
+
+    fn insert_new(&self, object: Object<Meta>) -> u32 {
+        let mut ids = self.ids.lock().unwrap();
+        // the first free place, like `ObjectMap` does
+        let idx = match ids.free.iter().next().copied() {
+            Some(idx) => {
+                ids.free.remove(&idx);
+                idx
+            }
+            None => {
+                ids.len += 1;
+                ids.len - 1
+            }
+        };
+        self.store(idx, object);
+        idx
+    }
+
+    fn snapshot(&self, first_id: u32) -> Vec<(u32, Object<Meta>)> {
+        let mut objects = Vec::new();
+        for (i, shard) in self.shards.iter().enumerate() {
+            for (slot, place) in shard.read().unwrap().iter().enumerate() {
+                if let Some(ref obj) = *place {
+                    objects.push(((slot * SHARDS + i) as u32 + first_id, obj.clone()));
+                }
+            }
+        }
+        objects.sort_by_key(|&(id, _)| id);
+        objects
+    }
+
+    fn clear(&self, first_id: u32) -> Vec<(u32, Object<Meta>)> {
+        let mut ids = self.ids.lock().unwrap();
+        let objects = self.snapshot(first_id);
+        for shard in &self.shards {
+            shard.write().unwrap().clear();
+        }
+        *ids = Ids::default();
+        objects
+    }
+}
@@ -3,38 +3,98 @@ use std::sync::{Arc, Mutex};
 
 use downcast::Downcast;
 
+use self::map::ObjectStore;
 use wayland_commons::debug;
 use wayland_commons::filter::Filter;
-use wayland_commons::map::ObjectMap;
-use wayland_commons::wire::Message;
+use wayland_commons::wire::{Argument, Message};
 use wayland_commons::{MessageGroup, ThreadGuard};
 
 use crate::{Interface, Main, Proxy};
 
+#[cfg(feature = "c_abi")]
+mod c_abi;
 mod connection;
 mod display;
+mod map;
 mod proxy;
 mod queues;
 
+pub(crate) use self::connection::{FdBudget, ZombieHandler};
 pub(crate) use self::display::DisplayInner;
-pub(crate) use self::proxy::ProxyInner;
+pub(crate) use self::proxy::{ProxyInner, WeakProxyInner};
 pub(crate) use self::queues::EventQueueInner;
 
+// The handle of a proxy created by this implementation
+fn rust_proxy<I: Interface>(proxy: &Proxy<I>) -> &ProxyInner {
+    // with the system library, the handles of both implementations are wrapped together
+    #[cfg(feature = "use_system_lib")]
+    {
+        proxy.inner.as_rust()
+    }
+    #[cfg(not(feature = "use_system_lib"))]
+    {
+        &proxy.inner
+    }
+}
+
+#[cfg(not(feature = "c_abi"))]
+fn c_interfacing() -> ! {
+    panic!("[wayland-client] C interfacing methods can only be used with the native backend of the `use_system_lib` cargo feature, or with the `c_abi` cargo feature.")
+}
+
 /// Flag to toggle debug output.
 static WAYLAND_DEBUG: AtomicBool = AtomicBool::new(false);
 
+/// Flag to toggle tracing of object destruction, in `WAYLAND_RS_TRACE_DESTRUCTION`.
+static TRACE_DESTRUCTION: AtomicBool = AtomicBool::new(false);
+
+fn trace_destruction(interface: &str, id: u32, what: std::fmt::Arguments) {
+    if TRACE_DESTRUCTION.load(Ordering::Relaxed) {
+        debug::print_object_lifecycle(interface, id, what);
+    }
+}
+
+// Discard an event received for a destroyed object, closing its fds
+fn discard_zombie_event(
+    msg: Message,
+    object: Option<&wayland_commons::map::Object<self::proxy::ObjectMeta>>,
+    log: bool,
+) {
+    if let Some(obj) = object {
+        let name = obj.events[msg.opcode as usize].name;
+        if log {
+            debug::print_object_lifecycle(
+                obj.interface,
+                msg.sender_id,
+                format_args!("discarded event {}", name),
+            );
+        } else {
+            trace_destruction(
+                obj.interface,
+                msg.sender_id,
+                format_args!("discarded event {}", name),
+            );
+        }
+    }
+    for a in msg.args {
+        if let Argument::Fd(fd) = a {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
 /// A handle to the object map internal to the library state.
 ///
 /// This type is only used by code generated by `wayland-scanner`, and can not
 /// be instantiated directly.
 pub struct ProxyMap {
-    map: Arc<Mutex<ObjectMap<self::proxy::ObjectMeta>>>,
+    map: Arc<ObjectStore<self::proxy::ObjectMeta>>,
     connection: Arc<Mutex<self::connection::Connection>>,
 }
 
 impl ProxyMap {
     pub(crate) fn make(
-        map: Arc<Mutex<ObjectMap<self::proxy::ObjectMeta>>>,
+        map: Arc<ObjectStore<self::proxy::ObjectMeta>>,
         connection: Arc<Mutex<self::connection::Connection>>,
     ) -> ProxyMap {
         ProxyMap { map, connection }
@@ -66,13 +126,7 @@ impl ProxyMap {
         &mut self,
         id: u32,
     ) -> Option<Main<I>> {
-        debug_assert!(self
-            .map
-            .lock()
-            .unwrap()
-            .find(id)
-            .map(|obj| obj.is_interface::<I>())
-            .unwrap_or(true));
+        debug_assert!(self.map.find(id).map(|obj| obj.is_interface::<I>()).unwrap_or(true));
         ProxyInner::from_id(id, self.map.clone(), self.connection.clone()).map(Main::wrap)
     }
 }
@@ -95,6 +149,11 @@ pub(crate) trait Dispatcher: Downcast + Send {
         map: &mut ProxyMap,
         data: crate::DispatchData,
     ) -> Dispatched;
+
+    /// Whether this dispatcher can only be invoked from the thread that created it
+    fn is_thread_bound(&self) -> bool {
+        false
+    }
 }
 
 mod dispatcher_impl {
@@ -110,6 +169,7 @@ pub(crate) struct ImplDispatcher<
 > {
     _i: ::std::marker::PhantomData<&'static I>,
     implementation: F,
+    thread_bound: bool,
 }
 
 impl<I, F> Dispatcher for ImplDispatcher<I, F>
@@ -127,6 +187,14 @@ where
     ) -> Dispatched {
         let opcode = msg.opcode as usize;
 
+        // The queue never dispatches events to objects it knows are destroyed, getting
+        // here means the map cleanup let an event through for a dead object.
+        debug_assert!(
+            !proxy.object.meta.client_destroyed,
+            "Event {}@{}.{} dispatched to an object destroyed by the client.",
+            proxy.object.interface, proxy.id, proxy.object.events[opcode].name
+        );
+
         if WAYLAND_DEBUG.load(Ordering::Relaxed) {
             debug::print_dispatched_message(
                 proxy.object.interface,
@@ -154,19 +222,19 @@ where
         }
 
         if message.is_destructor() {
+            trace_destruction(
+                proxy.object.interface,
+                proxy.id,
+                format_args!("destroyed by event {}", proxy.object.events[opcode].name),
+            );
             proxy.object.meta.alive.store(false, Ordering::Release);
-            {
-                // cleanup the map as appropriate
-                let mut map = proxy.map.lock().unwrap();
-                let server_destroyed = map
-                    .with(proxy.id, |obj| {
-                        obj.meta.client_destroyed = true;
-                        obj.meta.server_destroyed
-                    })
-                    .unwrap_or(false);
-                if server_destroyed {
-                    map.remove(proxy.id);
-                }
+            // cleanup the map as appropriate
+            let released = proxy.map.remove_if(proxy.id, |obj| {
+                obj.meta.client_destroyed = true;
+                obj.meta.server_destroyed
+            });
+            if released == Ok(true) {
+                trace_destruction(proxy.object.interface, proxy.id, format_args!("released"));
             }
         }
 
@@ -174,6 +242,10 @@ where
 
         Dispatched::Yes
     }
+
+    fn is_thread_bound(&self) -> bool {
+        self.thread_bound
+    }
 }
 
 pub(crate) fn make_dispatcher<I, E>(filter: Filter<E>) -> Arc<Mutex<dyn Dispatcher + Send>>
@@ -182,10 +254,29 @@ where
     E: From<(Main<I>, I::Event)> + 'static,
     I::Event: MessageGroup<Map = ProxyMap>,
 {
-    let guard = ThreadGuard::new(filter);
+    let guard = ThreadGuard::new_named(filter, I::NAME);
+    Arc::new(Mutex::new(ImplDispatcher {
+        _i: ::std::marker::PhantomData,
+        implementation: move |evt, proxy, data| {
+            if let Some(filter) = guard.get_or_report() {
+                filter.send((proxy, evt).into(), data)
+            }
+        },
+        thread_bound: true,
+    }))
+}
+
+// The closure is `Send`, so it can be dispatched from any thread without a `ThreadGuard`
+pub(crate) fn make_threadsafe_dispatcher<I, F>(mut f: F) -> Arc<Mutex<dyn Dispatcher + Send>>
+where
+    I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+    F: FnMut(Main<I>, I::Event, crate::DispatchData) + Send + 'static,
+    I::Event: MessageGroup<Map = ProxyMap>,
+{
     Arc::new(Mutex::new(ImplDispatcher {
         _i: ::std::marker::PhantomData,
-        implementation: move |evt, proxy, data| guard.get().send((proxy, evt).into(), data),
+        implementation: move |evt, proxy, data| f(proxy, evt, data),
+        thread_bound: false,
     }))
 }
 
@@ -1,26 +1,35 @@
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
+use wayland_commons::capture::Direction;
 use wayland_commons::debug;
 use wayland_commons::filter::Filter;
-use wayland_commons::map::{Object, ObjectMap, ObjectMetadata};
+use wayland_commons::map::{Object, ObjectMetadata};
 use wayland_commons::user_data::UserData;
-use wayland_commons::wire::{Argument, ArgumentType};
+use wayland_commons::wire::{Argument, ArgumentType, Message};
 use wayland_commons::MessageGroup;
+use wayland_sys::client::wl_proxy;
+use wayland_sys::common::wl_interface;
 
-use super::connection::Connection;
+use super::connection::{Connection, RequestStaging};
+use super::map::ObjectStore;
 use super::queues::QueueBuffer;
-use super::{Dispatcher, EventQueueInner, WAYLAND_DEBUG};
+use super::{trace_destruction, Dispatcher, EventQueueInner, WAYLAND_DEBUG};
 use crate::{Interface, Main, Proxy};
 
 #[derive(Clone)]
 pub(crate) struct ObjectMeta {
     pub(crate) buffer: QueueBuffer,
     pub(crate) alive: Arc<AtomicBool>,
-    user_data: Arc<UserData>,
+    pub(crate) user_data: Arc<UserData>,
     pub(crate) dispatcher: Arc<Mutex<dyn Dispatcher>>,
+    pub(crate) staging: Arc<RequestStaging>,
     pub(crate) server_destroyed: bool,
     pub(crate) client_destroyed: bool,
+    pub(crate) high_priority: Arc<AtomicBool>,
+    serial: u64,
+    // the serials of the objects involved in the creation of this one
+    parents: Arc<[u64]>,
 }
 
 impl ObjectMetadata for ObjectMeta {
@@ -30,21 +39,29 @@ impl ObjectMetadata for ObjectMeta {
             alive: Arc::new(AtomicBool::new(true)),
             user_data: Arc::new(UserData::new()),
             dispatcher: super::default_dispatcher(),
+            staging: self.staging.clone(),
             server_destroyed: false,
             client_destroyed: false,
+            high_priority: Arc::new(AtomicBool::new(false)),
+            serial: crate::proxy::next_object_serial(),
+            parents: Arc::from(vec![self.serial]),
         }
     }
 }
 
 impl ObjectMeta {
-    pub(crate) fn new(buffer: QueueBuffer) -> ObjectMeta {
+    pub(crate) fn new(buffer: QueueBuffer, staging: Arc<RequestStaging>) -> ObjectMeta {
         ObjectMeta {
             buffer,
             alive: Arc::new(AtomicBool::new(true)),
             user_data: Arc::new(UserData::new()),
             dispatcher: super::default_dispatcher(),
+            staging,
             server_destroyed: false,
             client_destroyed: false,
+            high_priority: Arc::new(AtomicBool::new(false)),
+            serial: crate::proxy::next_object_serial(),
+            parents: Arc::from(Vec::new()),
         }
     }
 
@@ -54,17 +71,46 @@ impl ObjectMeta {
             alive: Arc::new(AtomicBool::new(false)),
             user_data: Arc::new(UserData::new()),
             dispatcher: super::default_dispatcher(),
+            staging: Arc::new(RequestStaging::default()),
             server_destroyed: true,
             client_destroyed: true,
+            high_priority: Arc::new(AtomicBool::new(false)),
+            serial: crate::proxy::next_object_serial(),
+            parents: Arc::from(Vec::new()),
         }
     }
 }
 
+#[derive(Clone)]
+pub(crate) struct WeakProxyInner {
+    map: Arc<ObjectStore<ObjectMeta>>,
+    connection: Arc<Mutex<Connection>>,
+    id: u32,
+    alive: Weak<AtomicBool>,
+}
+
+impl WeakProxyInner {
+    pub(crate) fn upgrade(&self) -> Option<ProxyInner> {
+        let alive = self.alive.upgrade()?;
+        if !alive.load(Ordering::Acquire) {
+            return None;
+        }
+        // the id may have been reused by an other object
+        let mut inner = ProxyInner::from_id(self.id, self.map.clone(), self.connection.clone())?;
+        if !Arc::ptr_eq(&inner.object.meta.alive, &alive) {
+            return None;
+        }
+        inner.detach();
+        Some(inner)
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct ProxyInner {
-    pub(crate) map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+    pub(crate) map: Arc<ObjectStore<ObjectMeta>>,
     pub(crate) connection: Arc<Mutex<Connection>>,
-    pub(crate) object: Object<ObjectMeta>,
+    // shared between the clones, to keep the handles small
+    pub(crate) object: Arc<Object<ObjectMeta>>,
     pub(crate) id: u32,
     pub(crate) queue: Option<QueueBuffer>,
 }
@@ -72,22 +118,22 @@ pub(crate) struct ProxyInner {
 impl ProxyInner {
     pub(crate) fn from_id(
         id: u32,
-        map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+        map: Arc<ObjectStore<ObjectMeta>>,
         connection: Arc<Mutex<Connection>>,
     ) -> Option<ProxyInner> {
-        let me = map.lock().unwrap().find(id);
+        let me = map.find(id);
         me.map(|obj| ProxyInner {
             map,
             connection,
             id,
             queue: Some(obj.meta.buffer.clone()),
-            object: obj,
+            object: Arc::new(obj),
         })
     }
 
     pub(crate) fn dead<I: Interface>(
         id: u32,
-        map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+        map: Arc<ObjectStore<ObjectMeta>>,
         connection: Arc<Mutex<Connection>>,
     ) -> ProxyInner {
         ProxyInner {
@@ -95,7 +141,7 @@ impl ProxyInner {
             connection,
             id,
             queue: None,
-            object: Object::from_interface::<I>(1, ObjectMeta::dead()),
+            object: Arc::new(Object::from_interface::<I>(1, ObjectMeta::dead())),
         }
     }
 
@@ -123,6 +169,25 @@ impl ProxyInner {
         &*self.object.meta.user_data
     }
 
+    pub(crate) fn is_high_priority(&self) -> bool {
+        self.object.meta.high_priority.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_high_priority(&self, high_priority: bool) {
+        // ignore failure if target object is dead
+        if !self.is_alive() {
+            return;
+        }
+        // changed with the buffer locked, so that no event is pushed in between
+        let mut buffer = self.object.meta.buffer.lock().unwrap();
+        let was_high_priority =
+            self.object.meta.high_priority.swap(high_priority, Ordering::AcqRel);
+        // the events already waiting would otherwise be dispatched after the new ones
+        if high_priority && !was_high_priority {
+            buffer.prioritize(self.id);
+        }
+    }
+
     pub(crate) fn detach(&mut self) {
         self.queue = None;
     }
@@ -131,62 +196,116 @@ impl ProxyInner {
         self.queue = Some(queue.buffer.clone())
     }
 
+    pub(crate) fn c_ptr(&self, interface: *const wl_interface) -> *mut wl_proxy {
+        #[cfg(feature = "c_abi")]
+        {
+            super::c_abi::proxy_ptr(self, interface)
+        }
+        #[cfg(not(feature = "c_abi"))]
+        {
+            let _ = interface;
+            super::c_interfacing()
+        }
+    }
+
     pub(crate) fn send<I, J>(&self, msg: I::Request, version: Option<u32>) -> Option<ProxyInner>
     where
         I: Interface,
         J: Interface,
     {
+        let opcode = msg.opcode();
+        if let Some(o) = I::Request::child(opcode, 1, &()) {
+            if !o.is_interface::<J>() {
+                panic!(
+                    "Trying to use 'send_constructor' with the wrong return type. \
+                    Required interface {} but the message creates interface {}",
+                    J::NAME,
+                    o.interface
+                )
+            }
+        }
+        // otherwise there is no target interface in the protocol, this is a generic
+        // object-creating function (likely wl_registry.bind)
+        let destructor = msg.is_destructor();
+        let version = version.unwrap_or(self.object.version);
+        self.send_raw(msg.into_raw(self.id), destructor, |meta| {
+            Object::from_interface::<J>(version, meta)
+        })
+    }
+
+    /// Send a request in its wire form
+    ///
+    /// If the request creates an object, `child` makes it from its metadata.
+    pub(crate) fn send_raw<F>(
+        &self,
+        mut msg: Message,
+        destructor: bool,
+        child: F,
+    ) -> Option<ProxyInner>
+    where
+        F: FnOnce(ObjectMeta) -> Object<ObjectMeta>,
+    {
+        let interface = self.object.interface;
+        let desc = &self.object.requests[msg.opcode as usize];
+        let (name, signature) = (desc.name, desc.signature);
+
+        // requests that don't create or destroy objects and don't carry fds can be staged
+        // without taking the connection lock
+        if self.object.meta.staging.is_enabled()
+            && !destructor
+            && !signature.iter().any(|&t| t == ArgumentType::NewId || t == ArgumentType::Fd)
+            && self.is_alive()
+        {
+            if WAYLAND_DEBUG.load(Ordering::Relaxed) {
+                debug::print_send_message(interface, self.id, true, name, &msg.args);
+            }
+            let staging = &self.object.meta.staging;
+            if staging.stage(interface, name, self.object.meta.alive.clone(), msg) {
+                self.connection.lock().unwrap().write_staged();
+            }
+            return None;
+        }
+
         // grab the connection lock before anything else
         // this avoids the risk or races during object creation
         let mut conn_lock = self.connection.lock().unwrap();
-        let destructor = msg.is_destructor();
-        let mut msg = msg.into_raw(self.id);
-
-        let opcode = msg.opcode;
+        // the staged requests were sent before this one
+        conn_lock.write_staged();
 
         // figure out if the call creates an object
-        let nid_idx = I::Request::MESSAGES[opcode as usize]
-            .signature
+        // a new_id without an interface in the protocol, likely of wl_registry.bind, expands
+        // to (str, u32, new_id) in the message
+        let nid_idx = signature
             .iter()
-            .position(|&t| t == ArgumentType::NewId);
+            .position(|&t| t == ArgumentType::NewId)
+            .map(|idx| idx + msg.args.len() - signature.len());
 
         let alive = self.is_alive();
 
-        let ret = if let Some(mut nid_idx) = nid_idx {
+        let ret = if let Some(nid_idx) = nid_idx {
             let target_queue = self
                 .queue
                 .clone()
                 .expect("Attemping to create an object from a non-attached proxy.");
-            if let Some(o) = I::Request::child(opcode, 1, &()) {
-                if !o.is_interface::<J>() {
-                    panic!(
-                        "Trying to use 'send_constructor' with the wrong return type. \
-                        Required interface {} but the message creates interface {}",
-                        J::NAME,
-                        o.interface
-                    )
-                }
-            } else {
-                // There is no target interface in the protocol, this is a generic object-creating
-                // function (likely wl_registry.bind), the newid arg will thus expand to
-                // (str, u32, obj).
-                nid_idx += 2;
-            }
             // insert the newly created object in the message
-            let new_object = Object::from_interface::<J>(
-                version.unwrap_or(self.object.version),
-                if alive { ObjectMeta::new(target_queue.clone()) } else { ObjectMeta::dead() },
-            );
+            let new_object = child(if alive {
+                let mut meta =
+                    ObjectMeta::new(target_queue.clone(), self.object.meta.staging.clone());
+                meta.parents = self.request_parents(&msg);
+                meta
+            } else {
+                ObjectMeta::dead()
+            });
             let mut new_id = 0;
             if alive {
-                new_id = self.map.lock().unwrap().client_insert_new(new_object.clone());
+                new_id = self.map.client_insert_new(new_object.clone());
                 msg.args[nid_idx] = Argument::NewId(new_id);
             }
             Some(ProxyInner {
                 map: self.map.clone(),
                 connection: self.connection.clone(),
                 id: new_id,
-                object: new_object,
+                object: Arc::new(new_object),
                 queue: Some(target_queue),
             })
         } else {
@@ -194,13 +313,7 @@ impl ProxyInner {
         };
 
         if WAYLAND_DEBUG.load(Ordering::Relaxed) {
-            debug::print_send_message(
-                I::NAME,
-                self.id,
-                alive,
-                self.object.requests[msg.opcode as usize].name,
-                &msg.args,
-            );
+            debug::print_send_message(interface, self.id, alive, name, &msg.args);
         }
 
         // Only actually send the message (& process destructor) if the object is alive.
@@ -208,28 +321,60 @@ impl ProxyInner {
             return ret;
         }
 
-        conn_lock.write_message(&msg).expect("Sending a message failed.");
+        if let Some(ref recorder) = conn_lock.recorder {
+            recorder.record(Direction::Sent, interface, name, &msg);
+        }
+
+        conn_lock.write_message(&msg);
 
         if destructor {
+            trace_destruction(interface, self.id, format_args!("destroyed by request {}", name));
             self.object.meta.alive.store(false, Ordering::Release);
 
             // Cleanup the map as appropriate.
-            let mut map = conn_lock.map.lock().unwrap();
-            let server_destroyed = map
-                .with(self.id, |obj| {
-                    obj.meta.client_destroyed = true;
-                    obj.meta.server_destroyed
-                })
-                .unwrap_or(false);
-
-            if server_destroyed {
-                map.remove(self.id);
+            let released = conn_lock.map.remove_if(self.id, |obj| {
+                obj.meta.client_destroyed = true;
+                obj.meta.server_destroyed
+            });
+            if released == Ok(true) {
+                trace_destruction(interface, self.id, format_args!("released"));
             }
         }
 
         ret
     }
 
+    pub(crate) fn serial(&self) -> u64 {
+        self.object.meta.serial
+    }
+
+    pub(crate) fn parents(&self) -> Vec<u64> {
+        self.object.meta.parents.to_vec()
+    }
+
+    // The serials of this object and of the objects given as arguments of a request
+    fn request_parents(&self, msg: &Message) -> Arc<[u64]> {
+        let map = &self.map;
+        let mut parents = vec![self.object.meta.serial];
+        for arg in &msg.args {
+            if let Argument::Object(id) = *arg {
+                if let Some(obj) = map.find(id) {
+                    parents.push(obj.meta.serial);
+                }
+            }
+        }
+        Arc::from(parents)
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakProxyInner {
+        WeakProxyInner {
+            map: self.map.clone(),
+            connection: self.connection.clone(),
+            id: self.id,
+            alive: Arc::downgrade(&self.object.meta.alive),
+        }
+    }
+
     pub(crate) fn equals(&self, other: &ProxyInner) -> bool {
         self.is_alive() && Arc::ptr_eq(&self.object.meta.alive, &other.object.meta.alive)
     }
@@ -241,8 +386,38 @@ impl ProxyInner {
         I::Event: MessageGroup<Map = super::ProxyMap>,
     {
         // ignore failure if target object is dead
-        let _ = self.map.lock().unwrap().with(self.id, |obj| {
+        let _ = self.map.with(self.id, |obj| {
             obj.meta.dispatcher = super::make_dispatcher(filter);
         });
     }
+
+    pub fn reassign<I, E>(&self, filter: Filter<E>)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        E: From<(Main<I>, I::Event)> + 'static,
+        I::Event: MessageGroup<Map = super::ProxyMap>,
+    {
+        // the dispatcher of an object is not borrowed while it is running, so it can be
+        // replaced from its own callback
+        self.assign::<I, E>(filter);
+    }
+
+    pub fn unassign(&self) {
+        // ignore failure if target object is dead
+        let _ = self.map.with(self.id, |obj| {
+            obj.meta.dispatcher = super::default_dispatcher();
+        });
+    }
+
+    pub fn assign_threadsafe<I, F>(&self, f: F)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + Sync,
+        F: FnMut(Main<I>, I::Event, crate::DispatchData) + Send + 'static,
+        I::Event: MessageGroup<Map = super::ProxyMap>,
+    {
+        // ignore failure if target object is dead
+        let _ = self.map.with(self.id, |obj| {
+            obj.meta.dispatcher = super::make_threadsafe_dispatcher(f);
+        });
+    }
 }
@@ -1,32 +1,90 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::io;
 use std::os::unix::io::AsRawFd;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use nix::poll::{poll, PollFd, PollFlags};
 
-use wayland_commons::map::ObjectMap;
+use wayland_commons::map::Object;
 use wayland_commons::wire::{Argument, Message};
 
-use super::connection::{Connection, Error as CError};
+use super::connection::{count_fds, Connection, Error as CError};
+use super::map::ObjectStore;
 use super::proxy::{ObjectMeta, ProxyInner};
-use super::Dispatched;
+use super::{discard_zombie_event, Dispatched, ZombieHandler};
 
-use crate::{AnonymousObject, DispatchData, Filter, Main, RawEvent};
+use crate::{
+    AnonymousObject, DispatchData, DispatchError, DispatchStats, Filter, Main, RawEvent,
+    SlowDispatch,
+};
 
-pub(crate) type QueueBuffer = Arc<Mutex<VecDeque<Message>>>;
+pub(crate) type QueueBuffer = Arc<Mutex<EventBuffer>>;
+
+// The events waiting for their queue to dispatch them, the events of the high priority
+// objects are dispatched before the others
+#[derive(Default)]
+pub(crate) struct EventBuffer {
+    high: VecDeque<Message>,
+    normal: VecDeque<Message>,
+}
+
+impl EventBuffer {
+    pub(crate) fn push(&mut self, msg: Message, high_priority: bool) {
+        if high_priority {
+            self.high.push_back(msg);
+        } else {
+            self.normal.push_back(msg);
+        }
+    }
+
+    /// Move the waiting events of an object before the normal priority ones, keeping their order
+    pub(crate) fn prioritize(&mut self, id: u32) {
+        if !self.normal.iter().any(|msg| msg.sender_id == id) {
+            return;
+        }
+        let (high, normal) =
+            self.normal.drain(..).partition::<VecDeque<_>, _>(|msg| msg.sender_id == id);
+        self.high.extend(high);
+        self.normal = normal;
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<Message> {
+        self.high.pop_front().or_else(|| self.normal.pop_front())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Message> + '_ {
+        self.high.drain(..).chain(self.normal.drain(..))
+    }
+}
+
+pub(crate) type SlowDispatchHook = (Duration, Box<dyn FnMut(SlowDispatch)>);
 
 pub(crate) fn create_queue_buffer() -> QueueBuffer {
-    Arc::new(Mutex::new(VecDeque::new()))
+    Arc::new(Mutex::new(EventBuffer::default()))
 }
 
 pub(crate) struct EventQueueInner {
     pub(crate) connection: Arc<Mutex<Connection>>,
-    pub(crate) map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+    pub(crate) map: Arc<ObjectStore<ObjectMeta>>,
     pub(crate) buffer: QueueBuffer,
     display_buffer: QueueBuffer,
+    held_fds: Arc<AtomicUsize>,
+    stats: RefCell<Option<DispatchStats>>,
+    slow_hook: RefCell<Option<SlowDispatchHook>>,
+    // the generation of the connection when this queue last checked for a reconnection
+    generation: Cell<usize>,
 }
 
 impl EventQueueInner {
@@ -34,24 +92,97 @@ impl EventQueueInner {
         connection: Arc<Mutex<Connection>>,
         buffer: Option<QueueBuffer>,
     ) -> EventQueueInner {
-        let (map, display_buffer) = {
+        let (map, display_buffer, held_fds, generation) = {
             let cx = connection.lock().unwrap();
-            (cx.map.clone(), cx.display_buffer.clone())
+            (cx.map.clone(), cx.display_buffer.clone(), cx.held_fds.clone(), cx.generation)
         };
         EventQueueInner {
             connection,
             map,
             buffer: buffer.unwrap_or_else(create_queue_buffer),
             display_buffer,
+            held_fds,
+            stats: RefCell::new(None),
+            slow_hook: RefCell::new(None),
+            generation: Cell::new(generation),
         }
     }
 
-    pub(crate) fn dispatch<F>(&self, mut data: DispatchData, mut fallback: F) -> io::Result<u32>
+    pub(crate) fn supports_handoff(&self) -> bool {
+        true
+    }
+
+    pub(crate) fn has_slow_dispatch_hook(&self) -> bool {
+        self.slow_hook.borrow().is_some()
+    }
+
+    /// The first living object of this queue assigned to a filter bound to the current thread
+    pub(crate) fn thread_bound_object(&self) -> Option<(&'static str, u32)> {
+        let found = self.map.snapshot().into_iter().find(|(_, obj)| {
+            // only the dispatchers of this queue are locked, none of them can be running
+            Arc::ptr_eq(&obj.meta.buffer, &self.buffer)
+                && !obj.meta.client_destroyed
+                && obj.meta.dispatcher.lock().unwrap().is_thread_bound()
+        });
+        found.map(|(id, obj)| (obj.interface, id))
+    }
+
+    /// Whether the connection was reset since the last call
+    pub(crate) fn take_reconnection(&self) -> bool {
+        let generation = self.connection.lock().unwrap().generation;
+        self.generation.replace(generation) != generation
+    }
+
+    pub(crate) fn start_stats(&self) {
+        let mut stats = self.stats.borrow_mut();
+        if stats.is_none() {
+            *stats = Some(DispatchStats::default());
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Option<DispatchStats> {
+        self.stats.borrow().clone()
+    }
+
+    pub(crate) fn stop_stats(&self) -> Option<DispatchStats> {
+        self.stats.borrow_mut().take()
+    }
+
+    pub(crate) fn set_slow_dispatch_hook(&self, hook: Option<SlowDispatchHook>) {
+        *self.slow_hook.borrow_mut() = hook;
+    }
+
+    // Account for an event handled by a filter or the fallback closure
+    fn record_dispatch(
+        &self,
+        interface: &'static str,
+        id: u32,
+        event: &'static str,
+        start: Instant,
+    ) {
+        let duration = start.elapsed();
+        if let Some(ref mut stats) = *self.stats.borrow_mut() {
+            *stats.dispatched.entry(interface).or_insert(0) += 1;
+            stats.filter_time += duration;
+        }
+        if let Some((threshold, ref mut hook)) = *self.slow_hook.borrow_mut() {
+            if duration >= threshold {
+                hook(SlowDispatch { interface, id, event, duration });
+            }
+        }
+    }
+
+    pub(crate) fn dispatch<F>(
+        &self,
+        mut data: DispatchData,
+        mut fallback: F,
+    ) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
-        // don't read events if there are some pending
-        if let Err(()) = self.prepare_read() {
+        // don't read events if there are some pending, or if the connection failed: the
+        // events received before the failure are dispatched, then its error is reported
+        if self.prepare_read().is_err() || self.connection.lock().unwrap().error().is_some() {
             return self.dispatch_pending(data.reborrow(), &mut fallback);
         }
 
@@ -71,18 +202,21 @@ impl EventQueueInner {
                             Ok(_) => continue,
                             Err(::nix::Error::Sys(e)) => {
                                 self.cancel_read();
-                                return Err(e.into());
+                                return Err(DispatchError::Backend(e.into()));
                             }
                             Err(_) => unreachable!(),
                         }
                     }
+                    Err(::nix::Error::Sys(::nix::errno::Errno::EPIPE)) => {
+                        // don't abort on EPIPE, so we can continue reading
+                        // to get the protocol error
+                        break;
+                    }
                     Err(::nix::Error::Sys(e)) => {
-                        if e != ::nix::errno::Errno::EPIPE {
-                            // don't abort on EPIPE, so we can continue reading
-                            // to get the protocol error
-                            self.cancel_read();
-                            return Err(e.into());
-                        }
+                        self.cancel_read();
+                        return Err(conn_lock
+                            .error()
+                            .unwrap_or_else(|| DispatchError::Backend(e.into())));
                     }
                     Err(_) => unreachable!(),
                 }
@@ -94,7 +228,7 @@ impl EventQueueInner {
             Ok(_) => (),
             Err(::nix::Error::Sys(e)) => {
                 self.cancel_read();
-                return Err(e.into());
+                return Err(DispatchError::Backend(e.into()));
             }
             Err(_) => unreachable!(),
         }
@@ -112,102 +246,180 @@ impl EventQueueInner {
                 // under our nose
                 // this is alright, continue
             }
-            Err(e) => return Err(e),
+            Err(e) => return Err(e.into()),
         }
 
         dispatch_ret
     }
 
+    // Dispatch the events of a buffer, stopping once `max` events have been dispatched
     fn dispatch_buffer<F>(
         &self,
-        buffer: &Mutex<VecDeque<Message>>,
+        buffer: &Mutex<EventBuffer>,
         mut data: DispatchData,
+        max: u32,
         mut fallback: F,
-    ) -> io::Result<u32>
+    ) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
         let mut count = 0;
         let mut proxymap = super::ProxyMap::make(self.map.clone(), self.connection.clone());
-        loop {
-            let msg = { buffer.lock().unwrap().pop_front() };
+        let instrumented = self.stats.borrow().is_some() || self.slow_hook.borrow().is_some();
+        while count < max {
+            let (msg, depth) = {
+                let mut buffer = buffer.lock().unwrap();
+                let depth = buffer.len();
+                (buffer.pop_front(), depth)
+            };
             let msg = match msg {
                 Some(m) => m,
                 None => break,
             };
+            if let Some(ref mut stats) = *self.stats.borrow_mut() {
+                stats.max_queue_depth = stats.max_queue_depth.max(depth);
+            }
+            // the fds are now either dispatched or closed
+            self.held_fds.fetch_sub(count_fds(&msg), Ordering::AcqRel);
             let id = msg.sender_id;
             if let Some(proxy) = ProxyInner::from_id(id, self.map.clone(), self.connection.clone())
             {
                 let object = proxy.object.clone();
-                if object.meta.client_destroyed {
+                if object.meta.client_destroyed || !proxy.is_alive() {
                     // This is a potential race, if we reach here it means that the proxy was
-                    // destroyed by the user between this message was queued and now. To handle it
-                    // correctly, we must close any FDs it contains, mark any child object as
-                    // destroyed (but the server will never know about it, so the ids will be
-                    // leaked) and discard the event.
-                    for arg in msg.args {
-                        match arg {
-                            Argument::Fd(fd) => {
-                                let _ = ::nix::unistd::close(fd);
-                            }
-                            Argument::NewId(id) => {
-                                let mut map = self.map.lock().unwrap();
-                                map.with(id, |obj| {
+                    // destroyed by the user between this message was queued and now (or it was
+                    // queued to be delivered by the zombie policy). To handle it correctly, we
+                    // must mark any child object as destroyed (but the server will never know
+                    // about it, so the ids will be leaked) and close any FDs it contains or give
+                    // it to the zombie callback.
+                    for arg in &msg.args {
+                        if let Argument::NewId(id) = *arg {
+                            self.map
+                                .with(id, |obj| {
                                     obj.meta.client_destroyed = true;
                                 })
                                 .unwrap();
-                            }
-                            _ => {}
                         }
                     }
+                    let handler = {
+                        let mut cx = self.connection.lock().unwrap();
+                        cx.zombie_events += 1;
+                        cx.zombie_handler.clone()
+                    };
+                    handle_zombie_event(&handler, msg, &object, &mut proxymap);
                     continue;
                 }
+                let event = object.events[msg.opcode as usize].name;
+                let start = if instrumented { Some(Instant::now()) } else { None };
+                #[cfg(feature = "tracing")]
+                let _span = tracing::trace_span!(
+                    "dispatch",
+                    interface = object.interface,
+                    opcode = msg.opcode,
+                    id
+                )
+                .entered();
                 let mut dispatcher = object.meta.dispatcher.lock().unwrap();
                 match dispatcher.dispatch(msg, proxy, &mut proxymap, data.reborrow()) {
                     Dispatched::Yes => {
                         count += 1;
                     }
                     Dispatched::NoDispatch(msg, proxy) => {
-                        let raw_event = message_to_rawevent(msg, &proxy, &mut proxymap);
+                        let raw_event = message_to_rawevent(msg, &proxy.object, &mut proxymap);
                         fallback(raw_event, Main::wrap(proxy), data.reborrow());
                         count += 1;
                     }
                     Dispatched::BadMsg => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("Dispatch for object {}@{} errored.", object.interface, id),
-                        ))
+                        return Err(DispatchError::InvalidState(format!(
+                            "Dispatch for object {}@{} errored.",
+                            object.interface, id
+                        )))
                     }
                 }
+                if let Some(start) = start {
+                    self.record_dispatch(object.interface, id, event, start);
+                }
             } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Received an event for unknown object {}.", id),
-                ));
+                return Err(DispatchError::InvalidState(format!(
+                    "Received an event for unknown object {}.",
+                    id
+                )));
             }
         }
         Ok(count)
     }
 
-    pub(crate) fn dispatch_pending<F>(&self, mut data: DispatchData, fallback: F) -> io::Result<u32>
+    // Hand the events received for destroyed proxies to the zombie policy
+    fn dispatch_zombies(&self) {
+        let (zombies, handler) = {
+            let mut cx = self.connection.lock().unwrap();
+            if cx.zombie_queue.is_empty() {
+                return;
+            }
+            (::std::mem::take(&mut cx.zombie_queue), cx.zombie_handler.clone())
+        };
+        let mut proxymap = super::ProxyMap::make(self.map.clone(), self.connection.clone());
+        for (msg, object) in zombies {
+            self.held_fds.fetch_sub(count_fds(&msg), Ordering::AcqRel);
+            handle_zombie_event(&handler, msg, &object, &mut proxymap);
+        }
+    }
+
+    pub(crate) fn dispatch_pending<F>(
+        &self,
+        data: DispatchData,
+        fallback: F,
+    ) -> Result<u32, DispatchError>
+    where
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
+    {
+        self.dispatch_some(data, ::std::u32::MAX, fallback).map(|(dispatched, _)| dispatched)
+    }
+
+    pub(crate) fn dispatch_some<F>(
+        &self,
+        mut data: DispatchData,
+        max: u32,
+        fallback: F,
+    ) -> Result<(u32, bool), DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
+        self.dispatch_zombies();
+
         // First always dispatch the display buffer
-        let display_dispatched =
-            self.dispatch_buffer(&self.display_buffer, data.reborrow(), |_, _, _| unreachable!())?;
+        let display_dispatched = self.dispatch_buffer(
+            &self.display_buffer,
+            data.reborrow(),
+            max,
+            |_, _, _| unreachable!(),
+        )?;
 
         // Then our actual buffer
-        let self_dispatched = self.dispatch_buffer(&self.buffer, data.reborrow(), fallback)?;
+        let self_dispatched = self.dispatch_buffer(
+            &self.buffer,
+            data.reborrow(),
+            max - display_dispatched,
+            fallback,
+        )?;
 
-        Ok(display_dispatched + self_dispatched)
+        let dispatched = display_dispatched + self_dispatched;
+        let remaining = !self.display_buffer.lock().unwrap().is_empty()
+            || !self.buffer.lock().unwrap().is_empty();
+        if dispatched == 0 && !remaining {
+            // nothing left to dispatch, report the failure of the connection if any
+            if let Some(err) = self.connection.lock().unwrap().error() {
+                return Err(err);
+            }
+        }
+        Ok((dispatched, remaining))
     }
 
     pub(crate) fn sync_roundtrip<F>(
         &self,
         mut data: DispatchData,
         mut fallback: F,
-    ) -> io::Result<u32>
+    ) -> Result<u32, DispatchError>
     where
         F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData<'_>),
     {
@@ -245,20 +457,7 @@ impl EventQueueInner {
     }
 
     pub(crate) fn read_events(&self) -> io::Result<()> {
-        // TODO: integrate more properly with prepare read with a fence
-        match self.connection.lock().unwrap().read_events() {
-            Ok(_) => Ok(()),
-            Err(CError::Protocol(e)) => {
-                eprintln!("[wayland-client] Protocol error while reading events: {}", e);
-                Err(::nix::errno::Errno::EPROTO.into())
-            }
-            Err(CError::Parse(e)) => {
-                eprintln!("[wayland-client] Parse error while reading events: {}", e);
-                Err(::nix::errno::Errno::EPROTO.into())
-            }
-            Err(CError::Nix(::nix::Error::Sys(errno))) => Err(errno.into()),
-            Err(CError::Nix(_)) => unreachable!(),
-        }
+        read_events(&self.connection)
     }
 
     pub(crate) fn cancel_read(&self) {
@@ -266,7 +465,52 @@ impl EventQueueInner {
     }
 }
 
-fn message_to_rawevent(msg: Message, proxy: &ProxyInner, map: &mut super::ProxyMap) -> RawEvent {
+/// Read the events available on the socket of a connection, into the buffers of their queues
+pub(crate) fn read_events(connection: &Mutex<Connection>) -> io::Result<()> {
+    // TODO: integrate more properly with prepare read with a fence
+    let mut cx = connection.lock().unwrap();
+    match cx.read_events() {
+        Ok(_) => Ok(()),
+        Err(CError::Nix(::nix::Error::Sys(::nix::errno::Errno::EAGAIN))) => {
+            Err(::nix::errno::Errno::EAGAIN.into())
+        }
+        Err(e) => {
+            match e {
+                CError::Protocol(e) => {
+                    eprintln!("[wayland-client] Protocol error while reading events: {}", e)
+                }
+                CError::Parse(e) => {
+                    eprintln!("[wayland-client] Parse error while reading events: {}", e)
+                }
+                CError::Nix(_) => {}
+            }
+            Err(cx.error().map(Into::into).unwrap_or_else(|| io::ErrorKind::Other.into()))
+        }
+    }
+}
+
+fn handle_zombie_event(
+    handler: &ZombieHandler,
+    msg: Message,
+    object: &Object<ObjectMeta>,
+    map: &mut super::ProxyMap,
+) {
+    match *handler {
+        ZombieHandler::Deliver(ref callback) => {
+            let id = msg.sender_id;
+            let raw_event = message_to_rawevent(msg, object, map);
+            (*callback.lock().unwrap())(id, raw_event);
+        }
+        ZombieHandler::Log => discard_zombie_event(msg, Some(object), true),
+        ZombieHandler::Discard => discard_zombie_event(msg, Some(object), false),
+    }
+}
+
+fn message_to_rawevent(
+    msg: Message,
+    object: &Object<ObjectMeta>,
+    map: &mut super::ProxyMap,
+) -> RawEvent {
     let Message { opcode, args, .. } = msg;
 
     let args = args
@@ -296,9 +540,9 @@ fn message_to_rawevent(msg: Message, proxy: &ProxyInner, map: &mut super::ProxyM
         .collect();
 
     RawEvent {
-        interface: proxy.object.interface,
+        interface: object.interface,
         opcode,
-        name: proxy.object.events[opcode as usize].name,
+        name: object.events[opcode as usize].name,
         args,
     }
 }
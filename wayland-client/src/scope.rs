@@ -0,0 +1,140 @@
+use crate::{AnonymousObject, DispatchError, Display, FlushProgress, Interface, Proxy};
+
+struct Member {
+    proxy: Proxy<AnonymousObject>,
+    // `None` for the objects destroyed by the server
+    destructor: Option<Box<dyn FnOnce()>>,
+}
+
+/// A group of proxies destroyed together
+///
+/// Tearing down a window means destroying all the objects created for it, in an order that
+/// respects their dependencies: an `xdg_toplevel` must be destroyed before its `xdg_surface`,
+/// which must be destroyed before its `wl_surface`. A `DestructionScope` collects these
+/// objects, each with the request destroying it, and destroys them with a single call to
+/// `destroy()`.
+///
+/// Each object is destroyed before the objects it was created from: the object which sent its
+/// creating request and the objects given as arguments of this request, like the `wl_surface`
+/// of an `xdg_surface`. The other objects are destroyed in the reverse order of their addition.
+///
+/// ```no_run
+/// # use wayland_client::{Display, DestructionScope, Main};
+/// # use wayland_client::protocol::{wl_compositor, wl_subcompositor, wl_surface};
+/// # fn teardown(
+/// #     display: &Display,
+/// #     compositor: &Main<wl_compositor::WlCompositor>,
+/// #     subcompositor: &Main<wl_subcompositor::WlSubcompositor>,
+/// #     parent: &wl_surface::WlSurface,
+/// # ) {
+/// let mut scope = DestructionScope::new();
+/// let surface = compositor.create_surface();
+/// let subsurface = subcompositor.get_subsurface(&surface, parent);
+/// scope.add(&subsurface.detach(), |subsurface| subsurface.destroy());
+/// // the subsurface is still destroyed first, as it was created from the surface
+/// scope.add(&surface.detach(), |surface| surface.destroy());
+/// let callback = surface.frame();
+/// scope.add_server_destroyed(&callback.detach());
+///
+/// // destroys the subsurface, then the surface, and flushes the connection
+/// scope.destroy(display).unwrap();
+/// # }
+/// ```
+///
+/// The objects that are already dead when the scope is destroyed, for example because the
+/// server destroyed them, are skipped. Dropping a scope without calling `destroy()` does not
+/// destroy its objects.
+#[derive(Default)]
+pub struct DestructionScope {
+    members: Vec<Member>,
+}
+
+impl DestructionScope {
+    /// Create an empty scope
+    pub fn new() -> DestructionScope {
+        DestructionScope { members: Vec::new() }
+    }
+
+    /// Add an object to the scope
+    ///
+    /// The `destructor` sends the destructor request of the object, it is only invoked if the
+    /// object is still alive when the scope is destroyed.
+    pub fn add<I, F>(&mut self, object: &I, destructor: F)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>> + 'static,
+        F: FnOnce(&I) + 'static,
+    {
+        let proxy = object.as_ref().clone();
+        self.members.push(Member {
+            proxy: proxy.clone().anonymize(),
+            destructor: Some(Box::new(move || destructor(&proxy.into()))),
+        });
+    }
+
+    /// Add an object destroyed by the server to the scope
+    ///
+    /// This is for the objects without a destructor request, like the `wl_callback` of a
+    /// frame: the scope only tracks them, it does not destroy them.
+    pub fn add_server_destroyed<I>(&mut self, object: &I)
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+    {
+        self.members.push(Member { proxy: object.as_ref().clone().anonymize(), destructor: None });
+    }
+
+    /// The number of objects of the scope that are still alive
+    pub fn len(&self) -> usize {
+        self.members.iter().filter(|member| member.proxy.is_alive()).count()
+    }
+
+    /// Whether all the objects of the scope are dead
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget the objects of the scope that are already dead
+    ///
+    /// This is useful for long-lived scopes collecting short-lived objects, like frame
+    /// callbacks.
+    pub fn prune(&mut self) {
+        self.members.retain(|member| member.proxy.is_alive());
+    }
+
+    /// Destroy the objects of the scope, without flushing the connection
+    ///
+    /// Returns the number of objects actually destroyed.
+    pub fn destroy_unflushed(self) -> usize {
+        // only the objects left to destroy constrain the order
+        let mut pending = Vec::new();
+        for member in self.members {
+            match member.destructor {
+                Some(destructor) if member.proxy.is_alive() => pending.push((
+                    member.proxy.inner.serial(),
+                    member.proxy.inner.parents(),
+                    destructor,
+                )),
+                _ => {}
+            }
+        }
+        let destroyed = pending.len();
+        while !pending.is_empty() {
+            // the last added object that none of the others was created from, there is always
+            // one as the objects are created after their parents
+            let idx = (0..pending.len())
+                .rev()
+                .find(|&i| pending.iter().all(|(_, parents, _)| !parents.contains(&pending[i].0)))
+                .unwrap_or(pending.len() - 1);
+            let (_, _, destructor) = pending.remove(idx);
+            destructor();
+        }
+        destroyed
+    }
+
+    /// Destroy the objects of the scope and flush the connection
+    ///
+    /// See `Display::flush()` for the meaning of the returned value.
+    pub fn destroy(self, display: &Display) -> Result<FlushProgress, DispatchError> {
+        self.destroy_unflushed();
+        display.flush()
+    }
+}
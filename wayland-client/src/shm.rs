@@ -0,0 +1,410 @@
+//! Shared memory buffer management
+//!
+//! This module provides `ShmPool`, a growable memory pool shared with the server through the
+//! `wl_shm` global, from which you can allocate `Buffer`s to draw into.
+//!
+//! The pool tracks which parts of its memory are in use. A `Buffer` that has been attached
+//! to a surface is considered busy until the server sends `wl_buffer.release`; its contents
+//! can not be accessed in the meantime, and dropping it only returns its memory to the pool
+//! once it has been released.
+//!
+//! ```no_run
+//! # use wayland_client::{Attached, protocol::{wl_shm, wl_surface}};
+//! # fn draw(shm: &Attached<wl_shm::WlShm>, surface: &wl_surface::WlSurface) {
+//! use wayland_client::shm::ShmPool;
+//!
+//! let mut pool = ShmPool::new(shm).expect("Failed to create the pool");
+//! let mut buffer = pool.create_buffer(64, 64, 64 * 4, wl_shm::Format::Argb8888).unwrap();
+//! buffer.with_canvas(|canvas| {
+//!     for pixel in canvas.chunks_exact_mut(4) {
+//!         pixel.copy_from_slice(&[0xFF, 0x00, 0x00, 0xFF]);
+//!     }
+//! });
+//! buffer.attach_to(surface);
+//! surface.commit();
+//! # }
+//! ```
+//!
+//! On Linux the memory is allocated using `memfd_create` and sealed against shrinking, so
+//! that it can never be truncated under our feet (which would raise a `SIGBUS` when accessing
+//! the mapping). On other platforms `shm_open` is used as a fallback.
+
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::rc::{Rc, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{ptr, slice};
+
+use nix::errno::Errno;
+use nix::sys::{mman, stat};
+use nix::{fcntl, unistd};
+
+use crate::protocol::wl_buffer::WlBuffer;
+use crate::protocol::wl_shm::{Format, WlShm};
+use crate::protocol::wl_shm_pool::WlShmPool;
+use crate::protocol::wl_surface::WlSurface;
+use crate::{Attached, Main};
+
+// initial size of the pool, it grows when needed
+const INITIAL_POOL_SIZE: usize = 4096;
+// alignment of the buffers allocated in the pool
+const ALIGNMENT: usize = 64;
+
+/// A growable shared memory pool
+///
+/// Buffers allocated from this pool keep it alive, the pool is destroyed once it and all its
+/// buffers are dropped.
+pub struct ShmPool {
+    inner: Rc<RefCell<PoolInner>>,
+}
+
+struct PoolInner {
+    file: File,
+    map: *mut u8,
+    len: usize,
+    pool: Main<WlShmPool>,
+    allocator: Allocator,
+}
+
+impl ShmPool {
+    /// Create a new pool from the `wl_shm` global
+    pub fn new(shm: &Attached<WlShm>) -> io::Result<ShmPool> {
+        let fd = create_shm_fd()?;
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(INITIAL_POOL_SIZE as u64)?;
+        seal_shrink(fd);
+        let map = map_file(&file, INITIAL_POOL_SIZE)?;
+        let pool = shm.create_pool(fd, INITIAL_POOL_SIZE as i32);
+
+        Ok(ShmPool {
+            inner: Rc::new(RefCell::new(PoolInner {
+                file,
+                map,
+                len: INITIAL_POOL_SIZE,
+                pool,
+                allocator: Allocator::new(INITIAL_POOL_SIZE),
+            })),
+        })
+    }
+
+    /// Current size of the pool, in bytes
+    pub fn len(&self) -> usize {
+        self.inner.borrow().len
+    }
+
+    /// Whether the pool is empty
+    ///
+    /// This is never the case, it is only provided for consistency with `len()`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Allocate a new buffer from this pool
+    ///
+    /// The pool is grown if it does not have enough free space for it. The contents of the
+    /// buffer are unspecified.
+    pub fn create_buffer(
+        &mut self,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: Format,
+    ) -> io::Result<Buffer> {
+        if width <= 0 || height <= 0 || stride < width {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid buffer dimensions"));
+        }
+        let size = (stride as usize)
+            .checked_mul(height as usize)
+            .filter(|&size| size <= std::i32::MAX as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Buffer too large"))?;
+
+        let mut inner = self.inner.borrow_mut();
+        let offset = match inner.allocator.allocate(size) {
+            Some(offset) => offset,
+            None => {
+                let new_len = std::cmp::max(inner.len * 2, inner.len + size + ALIGNMENT);
+                inner.grow(new_len)?;
+                inner.allocator.allocate(size).expect("Pool was just grown")
+            }
+        };
+
+        let wl_buffer = inner.pool.create_buffer(offset as i32, width, height, stride, format);
+        let state = Rc::new(BufferState { busy: Cell::new(false), dropped: Cell::new(false) });
+        let pool = Rc::downgrade(&self.inner);
+        let handler_state = state.clone();
+        // wl_buffer.release is the only event of wl_buffer
+        wl_buffer.quick_assign(move |buffer, _, _| {
+            handler_state.busy.set(false);
+            if handler_state.dropped.get() {
+                buffer.destroy();
+                free_segment(&pool, offset, size);
+            }
+        });
+
+        Ok(Buffer {
+            pool: self.inner.clone(),
+            buffer: wl_buffer.detach(),
+            offset,
+            size,
+            width,
+            height,
+            stride,
+            format,
+            state,
+        })
+    }
+}
+
+impl PoolInner {
+    fn grow(&mut self, new_len: usize) -> io::Result<()> {
+        if new_len > std::i32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Pool too large"));
+        }
+        self.file.set_len(new_len as u64)?;
+        let map = map_file(&self.file, new_len)?;
+        unsafe {
+            let _ = mman::munmap(self.map as *mut _, self.len);
+        }
+        self.map = map;
+        self.allocator.grow(self.len, new_len);
+        self.len = new_len;
+        self.pool.resize(new_len as i32);
+        Ok(())
+    }
+}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = mman::munmap(self.map as *mut _, self.len);
+        }
+        self.pool.destroy();
+    }
+}
+
+struct BufferState {
+    busy: Cell<bool>,
+    dropped: Cell<bool>,
+}
+
+fn free_segment(pool: &Weak<RefCell<PoolInner>>, offset: usize, size: usize) {
+    if let Some(pool) = pool.upgrade() {
+        pool.borrow_mut().allocator.free(offset, size);
+    }
+}
+
+/// A buffer allocated from a `ShmPool`
+///
+/// Dropping it destroys the `wl_buffer`, right away if the buffer is not in use by the
+/// server, or once it is released otherwise.
+pub struct Buffer {
+    pool: Rc<RefCell<PoolInner>>,
+    buffer: WlBuffer,
+    offset: usize,
+    size: usize,
+    width: i32,
+    height: i32,
+    stride: i32,
+    format: Format,
+    state: Rc<BufferState>,
+}
+
+impl Buffer {
+    /// The `wl_buffer` of this buffer
+    ///
+    /// If you attach it to a surface yourself rather than using `attach_to()`, use
+    /// `mark_busy()` so that its contents are protected until its release.
+    pub fn wl_buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    /// Dimensions of the buffer, in pixels
+    pub fn dimensions(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    /// Stride of the buffer, in bytes
+    pub fn stride(&self) -> i32 {
+        self.stride
+    }
+
+    /// Pixel format of the buffer
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Whether the buffer is currently in use by the server
+    pub fn is_busy(&self) -> bool {
+        self.state.busy.get()
+    }
+
+    /// Mark the buffer as used by the server until it sends `wl_buffer.release`
+    pub fn mark_busy(&self) {
+        self.state.busy.set(true);
+    }
+
+    /// Attach this buffer to a surface
+    ///
+    /// The buffer is marked as busy until its release by the server. The surface is
+    /// not committed.
+    pub fn attach_to(&self, surface: &WlSurface) {
+        surface.attach(Some(&self.buffer), 0, 0);
+        self.mark_busy();
+    }
+
+    /// Access the contents of the buffer
+    ///
+    /// Returns `None` without calling the closure if the buffer is busy.
+    ///
+    /// The pool can not be grown while the closure runs, doing so (by allocating a new buffer
+    /// from it) will panic.
+    pub fn with_canvas<T, F: FnOnce(&mut [u8]) -> T>(&mut self, f: F) -> Option<T> {
+        if self.is_busy() {
+            return None;
+        }
+        let pool = self.pool.borrow_mut();
+        let canvas = unsafe { slice::from_raw_parts_mut(pool.map.add(self.offset), self.size) };
+        Some(f(canvas))
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if self.is_busy() {
+            // the release handler takes care of it
+            self.state.dropped.set(true);
+        } else {
+            self.buffer.destroy();
+            self.pool.borrow_mut().allocator.free(self.offset, self.size);
+        }
+    }
+}
+
+/// First-fit allocator over the pool memory
+///
+/// The free segments are kept sorted by offset and coalesced.
+#[derive(Debug)]
+struct Allocator {
+    free: Vec<(usize, usize)>,
+}
+
+impl Allocator {
+    fn new(len: usize) -> Allocator {
+        Allocator { free: vec![(0, len)] }
+    }
+
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        let size = align(size);
+        let idx = self.free.iter().position(|&(_, len)| len >= size)?;
+        let (offset, len) = self.free[idx];
+        if len == size {
+            self.free.remove(idx);
+        } else {
+            self.free[idx] = (offset + size, len - size);
+        }
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: usize, size: usize) {
+        let size = align(size);
+        let idx = self.free.iter().position(|&(o, _)| o > offset).unwrap_or(self.free.len());
+        self.free.insert(idx, (offset, size));
+        // merge with the next segment
+        if idx + 1 < self.free.len() && offset + size == self.free[idx + 1].0 {
+            self.free[idx].1 += self.free[idx + 1].1;
+            self.free.remove(idx + 1);
+        }
+        // merge with the previous segment
+        if idx > 0 && self.free[idx - 1].0 + self.free[idx - 1].1 == offset {
+            self.free[idx - 1].1 += self.free[idx].1;
+            self.free.remove(idx);
+        }
+    }
+
+    fn grow(&mut self, old_len: usize, new_len: usize) {
+        self.free(old_len, new_len - old_len);
+    }
+}
+
+fn align(size: usize) -> usize {
+    // ALIGNMENT is a power of two
+    (size + ALIGNMENT - 1) & !(ALIGNMENT - 1)
+}
+
+fn map_file(file: &File, len: usize) -> io::Result<*mut u8> {
+    let map = unsafe {
+        mman::mmap(
+            ptr::null_mut(),
+            len,
+            mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+            mman::MapFlags::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    map.map(|ptr| ptr as *mut u8).map_err(nix_to_io)
+}
+
+pub(crate) fn nix_to_io(err: nix::Error) -> io::Error {
+    match err {
+        nix::Error::Sys(errno) => errno.into(),
+        other => io::Error::new(io::ErrorKind::InvalidInput, other),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn seal_shrink(fd: RawFd) {
+    // Failure only means we are on the shm_open fallback, which can not be sealed
+    let _ = fcntl::fcntl(fd, fcntl::FcntlArg::F_ADD_SEALS(fcntl::SealFlag::F_SEAL_SHRINK));
+}
+
+#[cfg(not(target_os = "linux"))]
+fn seal_shrink(_fd: RawFd) {}
+
+fn create_shm_fd() -> io::Result<RawFd> {
+    #[cfg(target_os = "linux")]
+    {
+        use nix::sys::memfd;
+        use std::ffi::CStr;
+        loop {
+            match memfd::memfd_create(
+                CStr::from_bytes_with_nul(b"wayland-rs-shm\0").unwrap(),
+                memfd::MemFdCreateFlag::MFD_CLOEXEC | memfd::MemFdCreateFlag::MFD_ALLOW_SEALING,
+            ) {
+                Ok(fd) => return Ok(fd),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(nix::Error::Sys(Errno::ENOSYS)) => break,
+                Err(err) => return Err(nix_to_io(err)),
+            }
+        }
+    }
+
+    loop {
+        let name = format!(
+            "/wayland-rs-shm-{}",
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos()
+        );
+        match mman::shm_open(
+            name.as_str(),
+            fcntl::OFlag::O_CREAT
+                | fcntl::OFlag::O_EXCL
+                | fcntl::OFlag::O_RDWR
+                | fcntl::OFlag::O_CLOEXEC,
+            stat::Mode::S_IRUSR | stat::Mode::S_IWUSR,
+        ) {
+            Ok(fd) => {
+                return match mman::shm_unlink(name.as_str()) {
+                    Ok(()) => Ok(fd),
+                    Err(err) => {
+                        let _ = unistd::close(fd);
+                        Err(nix_to_io(err))
+                    }
+                }
+            }
+            Err(nix::Error::Sys(Errno::EEXIST)) | Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(err) => return Err(nix_to_io(err)),
+        }
+    }
+}
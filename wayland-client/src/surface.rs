@@ -0,0 +1,381 @@
+//! Double-buffered surface state
+//!
+//! Most of the state of a `wl_surface` is double-buffered: the requests setting it only
+//! change a pending state, which the compositor applies atomically on `wl_surface.commit`.
+//! This module provides `SurfaceState`, which accumulates this pending state on the client
+//! side and sends it in one go when committing, keeping track of what the compositor
+//! currently uses.
+//!
+//! The damage is coalesced before being sent: overlapping rectangles are merged, and too
+//! many of them are replaced by their bounding box. Damage can be given either in surface
+//! or in buffer coordinates, buffer damage is converted to surface coordinates (using the
+//! scale, transform and size of the buffer of the commit) if the surface is too old to
+//! support `wl_surface.damage_buffer`.
+//!
+//! ```no_run
+//! # use wayland_client::protocol::{wl_buffer, wl_surface};
+//! # fn draw(surface: &wl_surface::WlSurface, buffer: &wl_buffer::WlBuffer) {
+//! use wayland_client::surface::{Rect, SurfaceState};
+//!
+//! let mut state = SurfaceState::new(surface);
+//! state.set_buffer_scale(2);
+//! state.attach(buffer, 256, 256);
+//! state.damage_buffer(Rect::new(0, 0, 128, 128));
+//! state.damage_buffer(Rect::new(64, 64, 128, 128));
+//! // sends the scale, the buffer and a single damage rectangle, then commits
+//! state.commit();
+//! # }
+//! ```
+
+use std::cmp::{max, min};
+
+use crate::protocol::wl_buffer::WlBuffer;
+use crate::protocol::wl_output::Transform;
+use crate::protocol::wl_region::WlRegion;
+use crate::protocol::wl_surface::WlSurface;
+use crate::shm;
+
+// past this number of damage rectangles, they are replaced by their bounding box
+const MAX_DAMAGE_RECTS: usize = 32;
+
+/// A rectangle, used to describe damage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    /// Horizontal position of the top left corner
+    pub x: i32,
+    /// Vertical position of the top left corner
+    pub y: i32,
+    /// Width of the rectangle
+    pub width: i32,
+    /// Height of the rectangle
+    pub height: i32,
+}
+
+impl Rect {
+    /// Create a new rectangle
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    /// Whether this rectangle has no area
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    /// Whether this rectangle overlaps with another one
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && i64::from(self.x) < other.right()
+            && i64::from(other.x) < self.right()
+            && i64::from(self.y) < other.bottom()
+            && i64::from(other.y) < self.bottom()
+    }
+
+    /// The smallest rectangle containing both this one and another one
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        Rect::from_edges(
+            min(self.x, other.x).into(),
+            min(self.y, other.y).into(),
+            max(self.right(), other.right()),
+            max(self.bottom(), other.bottom()),
+        )
+    }
+
+    fn right(&self) -> i64 {
+        i64::from(self.x) + i64::from(self.width)
+    }
+
+    fn bottom(&self) -> i64 {
+        i64::from(self.y) + i64::from(self.height)
+    }
+
+    fn from_edges(left: i64, top: i64, right: i64, bottom: i64) -> Rect {
+        let clamp = |v: i64| max(min(v, i64::from(::std::i32::MAX)), 0) as i32;
+        Rect {
+            x: left as i32,
+            y: top as i32,
+            width: clamp(right - left),
+            height: clamp(bottom - top),
+        }
+    }
+}
+
+// A list of damage rectangles, overlapping rectangles are merged
+#[derive(Debug, Default)]
+struct Damage {
+    rects: Vec<Rect>,
+}
+
+impl Damage {
+    fn add(&mut self, mut rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        // merging two rectangles can make the result overlap with others, so loop
+        while let Some(idx) = self.rects.iter().position(|r| r.intersects(&rect)) {
+            rect = rect.union(&self.rects.swap_remove(idx));
+        }
+        self.rects.push(rect);
+        if self.rects.len() > MAX_DAMAGE_RECTS {
+            let bounds = self.rects.iter().fold(self.rects[0], |acc, r| acc.union(r));
+            self.rects = vec![bounds];
+        }
+    }
+
+    fn take(&mut self) -> Vec<Rect> {
+        ::std::mem::take(&mut self.rects)
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    // `Some(None)` means the buffer is detached, with `None` it is left unchanged
+    buffer: Option<Option<(WlBuffer, i32, i32)>>,
+    damage: Damage,
+    buffer_damage: Damage,
+    opaque_region: Option<Option<WlRegion>>,
+    input_region: Option<Option<WlRegion>>,
+    scale: Option<i32>,
+    transform: Option<Transform>,
+}
+
+/// A helper managing the double-buffered state of a `wl_surface`
+///
+/// The setters of this type only change the pending state, which is sent to the server by
+/// `commit()`. Only the values that differ from the current state are sent, and the requests
+/// that the version of the surface does not support are skipped.
+pub struct SurfaceState {
+    surface: WlSurface,
+    pending: Pending,
+    buffer_size: Option<(i32, i32)>,
+    scale: i32,
+    transform: Transform,
+}
+
+impl SurfaceState {
+    /// Manage the state of a surface
+    ///
+    /// The surface should be freshly created, as its current state is assumed to be the
+    /// initial one: no buffer, a scale of 1 and no transform.
+    pub fn new(surface: &WlSurface) -> SurfaceState {
+        SurfaceState {
+            surface: surface.clone(),
+            pending: Pending::default(),
+            buffer_size: None,
+            scale: 1,
+            transform: Transform::Normal,
+        }
+    }
+
+    /// The managed surface
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Attach a buffer of given dimensions, in pixels, on the next commit
+    ///
+    /// The dimensions are needed to convert buffer damage to surface coordinates.
+    pub fn attach(&mut self, buffer: &WlBuffer, width: i32, height: i32) {
+        self.pending.buffer = Some(Some((buffer.clone(), width, height)));
+    }
+
+    /// Attach a buffer of a `ShmPool` on the next commit
+    ///
+    /// The buffer is marked as busy right away, until its release by the server.
+    pub fn attach_shm(&mut self, buffer: &shm::Buffer) {
+        let (width, height) = buffer.dimensions();
+        self.attach(buffer.wl_buffer(), width, height);
+        buffer.mark_busy();
+    }
+
+    /// Remove the buffer of the surface on the next commit, unmapping it
+    pub fn detach(&mut self) {
+        self.pending.buffer = Some(None);
+    }
+
+    /// Damage a rectangle in surface coordinates
+    pub fn damage(&mut self, rect: Rect) {
+        self.pending.damage.add(rect);
+    }
+
+    /// Damage a rectangle in buffer coordinates
+    pub fn damage_buffer(&mut self, rect: Rect) {
+        self.pending.buffer_damage.add(rect);
+    }
+
+    /// The pending damage in surface coordinates
+    pub fn pending_damage(&self) -> &[Rect] {
+        &self.pending.damage.rects
+    }
+
+    /// The pending damage in buffer coordinates
+    pub fn pending_buffer_damage(&self) -> &[Rect] {
+        &self.pending.buffer_damage.rects
+    }
+
+    /// Set the opaque region on the next commit
+    ///
+    /// The region can be destroyed right after this call, as its contents are copied.
+    pub fn set_opaque_region(&mut self, region: Option<&WlRegion>) {
+        self.pending.opaque_region = Some(region.cloned());
+    }
+
+    /// Set the input region on the next commit
+    ///
+    /// The region can be destroyed right after this call, as its contents are copied.
+    pub fn set_input_region(&mut self, region: Option<&WlRegion>) {
+        self.pending.input_region = Some(region.cloned());
+    }
+
+    /// Set the scale of the buffers on the next commit
+    ///
+    /// This is ignored for surfaces older than version 3.
+    pub fn set_buffer_scale(&mut self, scale: i32) {
+        self.pending.scale = Some(scale);
+    }
+
+    /// Set the transform of the buffers on the next commit
+    ///
+    /// This is ignored for surfaces older than version 2.
+    pub fn set_buffer_transform(&mut self, transform: Transform) {
+        self.pending.transform = Some(transform);
+    }
+
+    /// The current scale of the buffers
+    pub fn buffer_scale(&self) -> i32 {
+        self.scale
+    }
+
+    /// The current transform of the buffers
+    pub fn buffer_transform(&self) -> Transform {
+        self.transform
+    }
+
+    /// The dimensions of the current buffer, in pixels
+    ///
+    /// Returns `None` if no buffer is attached.
+    pub fn buffer_size(&self) -> Option<(i32, i32)> {
+        self.buffer_size
+    }
+
+    /// The current size of the surface, in surface coordinates
+    ///
+    /// Returns `None` if no buffer is attached.
+    pub fn surface_size(&self) -> Option<(i32, i32)> {
+        self.buffer_size.map(|size| surface_size(size, self.scale, self.transform))
+    }
+
+    /// Send the pending state and commit the surface
+    pub fn commit(&mut self) {
+        let version = self.surface.as_ref().version();
+        let pending = ::std::mem::take(&mut self.pending);
+
+        if let Some(buffer) = pending.buffer {
+            match buffer {
+                Some((buffer, width, height)) => {
+                    self.surface.attach(Some(&buffer), 0, 0);
+                    self.buffer_size = Some((width, height));
+                }
+                None => {
+                    self.surface.attach(None, 0, 0);
+                    self.buffer_size = None;
+                }
+            }
+        }
+        if let Some(region) = pending.opaque_region {
+            self.surface.set_opaque_region(region.as_ref());
+        }
+        if let Some(region) = pending.input_region {
+            self.surface.set_input_region(region.as_ref());
+        }
+        match pending.transform {
+            Some(transform) if transform != self.transform && version >= 2 => {
+                self.surface.set_buffer_transform(transform);
+                self.transform = transform;
+            }
+            _ => {}
+        }
+        match pending.scale {
+            Some(scale) if scale != self.scale && version >= 3 => {
+                self.surface.set_buffer_scale(scale);
+                self.scale = scale;
+            }
+            _ => {}
+        }
+
+        let mut damage = pending.damage;
+        let mut buffer_damage = pending.buffer_damage;
+        if version >= 4 {
+            for rect in buffer_damage.take() {
+                self.surface.damage_buffer(rect.x, rect.y, rect.width, rect.height);
+            }
+        } else {
+            // the conversion uses the state of this commit, which applies to the damage
+            for rect in buffer_damage.take() {
+                match self.buffer_size {
+                    Some(size) => {
+                        damage.add(buffer_to_surface(rect, size, self.scale, self.transform))
+                    }
+                    None => damage.add(Rect::new(0, 0, ::std::i32::MAX, ::std::i32::MAX)),
+                }
+            }
+        }
+        for rect in damage.take() {
+            self.surface.damage(rect.x, rect.y, rect.width, rect.height);
+        }
+
+        self.surface.commit();
+    }
+}
+
+fn is_rotated(transform: Transform) -> bool {
+    match transform {
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => true,
+        _ => false,
+    }
+}
+
+fn surface_size((width, height): (i32, i32), scale: i32, transform: Transform) -> (i32, i32) {
+    let scale = max(scale, 1);
+    if is_rotated(transform) {
+        (height / scale, width / scale)
+    } else {
+        (width / scale, height / scale)
+    }
+}
+
+// Convert a rectangle from buffer to surface coordinates, this is the inverse of the
+// transformation compositors apply to surface damage
+fn buffer_to_surface(
+    rect: Rect,
+    buffer_size: (i32, i32),
+    scale: i32,
+    transform: Transform,
+) -> Rect {
+    let scale = i64::from(max(scale, 1));
+    let (width, height) = surface_size(buffer_size, scale as i32, transform);
+    let (w, h) = (i64::from(width), i64::from(height));
+    let convert = |u: i64, v: i64| match transform {
+        Transform::_90 => (w - v, u),
+        Transform::_180 => (w - u, h - v),
+        Transform::_270 => (v, h - u),
+        Transform::Flipped => (w - u, v),
+        Transform::Flipped90 => (v, u),
+        Transform::Flipped180 => (u, h - v),
+        Transform::Flipped270 => (w - v, h - u),
+        _ => (u, v),
+    };
+    // round outwards so that no damage is lost
+    let div_floor = |v: i64| (v as f64 / scale as f64).floor() as i64;
+    let div_ceil = |v: i64| (v as f64 / scale as f64).ceil() as i64;
+    let (x1, y1) = convert(div_floor(rect.x.into()), div_floor(rect.y.into()));
+    let (x2, y2) = convert(div_ceil(rect.right()), div_ceil(rect.bottom()));
+    Rect::from_edges(min(x1, x2), min(y1, y2), max(x1, x2), max(y1, y2))
+}
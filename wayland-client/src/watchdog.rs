@@ -0,0 +1,162 @@
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{DispatchError, Display};
+
+// how often the watchdog thread checks whether the compositor answered while waiting for it
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An event reported by a `Watchdog`
+#[derive(Debug)]
+pub enum WatchdogEvent {
+    /// The compositor did not answer a ping within the interval of the watchdog
+    CompositorUnresponsive {
+        /// How long ago the unanswered ping was sent
+        waiting: Duration,
+    },
+    /// The compositor answered a ping after having been reported unresponsive
+    CompositorResponsive {
+        /// How long the compositor took to answer the ping
+        latency: Duration,
+    },
+    /// The connection was lost, the watchdog stopped
+    ConnectionLost(DispatchError),
+}
+
+/// A watchdog checking that the compositor keeps answering
+///
+/// A compositor that hangs does not close the connection, and the clients waiting for its
+/// events hang with it. The watchdog detects this by sending a `wl_display.sync` ping once per
+/// interval, from a dedicated thread and event queue, and reports a `CompositorUnresponsive`
+/// event if the compositor does not answer it within the interval. This allows clients that
+/// must not hang silently, like kiosk clients, to trigger some recovery.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use wayland_client::{Display, Watchdog, WatchdogEvent};
+/// # let display = Display::connect_to_env().unwrap();
+/// let watchdog = Watchdog::spawn(&display, Duration::from_secs(5), |event| {
+///     if let WatchdogEvent::CompositorUnresponsive { waiting } = event {
+///         eprintln!("The compositor has not answered for {:?}", waiting);
+///     }
+/// })
+/// .unwrap();
+/// ```
+///
+/// The watchdog never reads the socket itself, as this would compete with the event loop of
+/// the application: the answers to its pings are read along with the events of the other
+/// queues, by the thread dispatching them or reading them with `prepare_read()`. The
+/// application must thus keep reading its events, an application that stops reading them is
+/// reported like an unresponsive compositor. The watchdog stops once dropped.
+pub struct Watchdog {
+    stop: Option<mpsc::Sender<()>>,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Start a watchdog on a connection
+    ///
+    /// The callback is invoked from the thread of the watchdog with the events it reports. An
+    /// unresponsive compositor is only reported once, until it answers again.
+    pub fn spawn<F>(display: &Display, interval: Duration, callback: F) -> io::Result<Watchdog>
+    where
+        F: FnMut(WatchdogEvent) + Send + 'static,
+    {
+        let display = display.clone();
+        let (stop, stop_receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let running2 = running.clone();
+        let thread = thread::Builder::new().name("wayland-watchdog".into()).spawn(move || {
+            watch(display, interval, stop_receiver, callback);
+            running2.store(false, Ordering::SeqCst);
+        })?;
+        Ok(Watchdog { stop: Some(stop), running, thread: Some(thread) })
+    }
+
+    /// Whether the watchdog is still running
+    ///
+    /// It stops by itself once the connection is lost.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Stop the watchdog, waiting for its thread to exit
+    pub fn stop(mut self) {
+        self.stop_thread();
+    }
+
+    fn stop_thread(&mut self) {
+        // disconnecting the channel stops the thread
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+fn watch<F>(display: Display, interval: Duration, stop: mpsc::Receiver<()>, mut callback: F)
+where
+    F: FnMut(WatchdogEvent),
+{
+    let mut queue = display.create_event_queue();
+    let proxy = display.attach(queue.token());
+    loop {
+        let sent = Instant::now();
+        let done = Rc::new(Cell::new(false));
+        let done2 = done.clone();
+        proxy.sync().quick_assign(move |_, _, _| done2.set(true));
+        // the application may be waiting for its events, without flushing the ping
+        match display.flush() {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => {
+                callback(WatchdogEvent::ConnectionLost(e));
+                return;
+            }
+        }
+
+        let mut unresponsive = false;
+        loop {
+            // Only the events already read are dispatched: reading the socket from this thread
+            // would take the events of the other queues from under the feet of the thread
+            // waiting for them, which would not be woken up.
+            if let Err(e) = queue.dispatch_pending(&mut (), |_, _, _| {}) {
+                callback(WatchdogEvent::ConnectionLost(e));
+                return;
+            }
+            if done.get() {
+                break;
+            }
+            if !unresponsive && sent.elapsed() >= interval {
+                unresponsive = true;
+                callback(WatchdogEvent::CompositorUnresponsive { waiting: sent.elapsed() });
+            }
+            match stop.recv_timeout(CHECK_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        }
+        if unresponsive {
+            callback(WatchdogEvent::CompositorResponsive { latency: sent.elapsed() });
+        }
+
+        // wait for the next ping
+        match stop.recv_timeout((sent + interval).saturating_duration_since(Instant::now())) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Implementations of the `raw-window-handle` traits
+//!
+//! The wayland handles of `raw-window-handle` are pointers to the objects of
+//! `libwayland-client.so`, this is why the `raw-window-handle` cargo feature
+//! also enables `use_system_lib`.
+
+use std::os::raw::c_void;
+
+use rwh::{
+    HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
+    WaylandDisplayHandle, WaylandWindowHandle,
+};
+
+use crate::protocol::wl_surface::WlSurface;
+use crate::Display;
+
+unsafe impl HasRawDisplayHandle for Display {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        let mut handle = WaylandDisplayHandle::empty();
+        handle.display = self.get_display_ptr() as *mut c_void;
+        RawDisplayHandle::Wayland(handle)
+    }
+}
+
+unsafe impl HasRawWindowHandle for WlSurface {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        let mut handle = WaylandWindowHandle::empty();
+        handle.surface = self.as_ref().c_ptr() as *mut c_void;
+        RawWindowHandle::Wayland(handle)
+    }
+}
+
+/// A surface along with the display it belongs to
+///
+/// Graphics libraries such as `wgpu` or `softbuffer` need both the window and the display
+/// handles from a single object, this type provides them for a `WlSurface`.
+///
+/// This is only available with the `raw-window-handle` cargo feature.
+#[derive(Clone)]
+pub struct WindowHandle {
+    display: Display,
+    surface: WlSurface,
+}
+
+impl WindowHandle {
+    /// Associate a surface with the display it was created from
+    pub fn new(display: &Display, surface: &WlSurface) -> WindowHandle {
+        WindowHandle { display: display.clone(), surface: surface.clone() }
+    }
+
+    /// The display of this handle
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    /// The surface of this handle
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+}
+
+unsafe impl HasRawDisplayHandle for WindowHandle {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        self.display.raw_display_handle()
+    }
+}
+
+unsafe impl HasRawWindowHandle for WindowHandle {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        self.surface.raw_window_handle()
+    }
+}
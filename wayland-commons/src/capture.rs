@@ -0,0 +1,370 @@
+//! Protocol traffic capture and replay
+//!
+//! A `Recorder` can be attached to a connection to log all the messages sent
+//! and received on it into a `Capture`. A capture can be serialized into a
+//! simple line-based text format, and later fed back to a client using a `Replayer`,
+//! which acts as a fake server replaying the events of the capture.
+//!
+//! File descriptors cannot be meaningfully recorded, they are stored as placeholders
+//! and replaced by a file descriptor to `/dev/null` during replay.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nix::Result as NixResult;
+
+use crate::socket::{BufferedSocket, Socket, MAX_BYTES_OUT, MAX_FDS_OUT};
+use crate::wire::{Argument, Message};
+
+/// Direction of a captured message
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The message was sent by the recording side
+    Sent,
+    /// The message was received by the recording side
+    Received,
+}
+
+/// A recorded argument of a message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CapturedArgument {
+    /// i32
+    Int(i32),
+    /// u32
+    Uint(u32),
+    /// fixed point, 1/256 precision
+    Fixed(i32),
+    /// CString, without its terminating nul byte
+    Str(Vec<u8>),
+    /// id of a wayland object
+    Object(u32),
+    /// id of a newly created wayland object
+    NewId(u32),
+    /// Vec<u8>
+    Array(Vec<u8>),
+    /// placeholder for a file descriptor
+    Fd,
+}
+
+impl<'a> From<&'a Argument> for CapturedArgument {
+    fn from(arg: &'a Argument) -> CapturedArgument {
+        match *arg {
+            Argument::Int(v) => CapturedArgument::Int(v),
+            Argument::Uint(v) => CapturedArgument::Uint(v),
+            Argument::Fixed(v) => CapturedArgument::Fixed(v),
+            Argument::Str(ref s) => CapturedArgument::Str(s.as_bytes().to_vec()),
+            Argument::Object(v) => CapturedArgument::Object(v),
+            Argument::NewId(v) => CapturedArgument::NewId(v),
+            Argument::Array(ref a) => CapturedArgument::Array((**a).clone()),
+            Argument::Fd(_) => CapturedArgument::Fd,
+        }
+    }
+}
+
+/// A recorded message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedMessage {
+    /// Time elapsed since the start of the capture
+    pub timestamp: Duration,
+    /// Direction of the message
+    pub direction: Direction,
+    /// Interface of the sender object
+    pub interface: String,
+    /// Name of the message
+    pub name: String,
+    /// Id of the sender object
+    pub sender_id: u32,
+    /// Opcode of the message
+    pub opcode: u16,
+    /// Arguments of the message
+    pub args: Vec<CapturedArgument>,
+}
+
+impl CapturedMessage {
+    /// Convert this message back into a wire message
+    ///
+    /// File descriptor placeholders are replaced by the provided `fd`.
+    pub fn to_message(&self, fd: std::os::unix::io::RawFd) -> Message {
+        Message {
+            sender_id: self.sender_id,
+            opcode: self.opcode,
+            args: self
+                .args
+                .iter()
+                .map(|arg| match *arg {
+                    CapturedArgument::Int(v) => Argument::Int(v),
+                    CapturedArgument::Uint(v) => Argument::Uint(v),
+                    CapturedArgument::Fixed(v) => Argument::Fixed(v),
+                    CapturedArgument::Str(ref s) => Argument::Str(Box::new(
+                        CString::new(s.clone()).unwrap_or_else(|_| CString::default()),
+                    )),
+                    CapturedArgument::Object(v) => Argument::Object(v),
+                    CapturedArgument::NewId(v) => Argument::NewId(v),
+                    CapturedArgument::Array(ref a) => Argument::Array(Box::new(a.clone())),
+                    CapturedArgument::Fd => Argument::Fd(fd),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A log of captured messages
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capture {
+    /// The captured messages, in chronological order
+    pub messages: Vec<CapturedMessage>,
+}
+
+impl Capture {
+    /// Serialize this capture into a writer
+    ///
+    /// Each message is written on its own line, as
+    /// `<micros> <'>' for sent, '<' for received> <interface>@<id>.<name> <opcode> <args>...`
+    /// with string and array contents hex-encoded.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for msg in &self.messages {
+            write!(
+                writer,
+                "{} {} {}@{}.{} {}",
+                msg.timestamp.as_micros(),
+                match msg.direction {
+                    Direction::Sent => '>',
+                    Direction::Received => '<',
+                },
+                msg.interface,
+                msg.sender_id,
+                msg.name,
+                msg.opcode
+            )?;
+            for arg in &msg.args {
+                match *arg {
+                    CapturedArgument::Int(v) => write!(writer, " i{}", v)?,
+                    CapturedArgument::Uint(v) => write!(writer, " u{}", v)?,
+                    CapturedArgument::Fixed(v) => write!(writer, " f{}", v)?,
+                    CapturedArgument::Str(ref s) => write!(writer, " s{}", to_hex(s))?,
+                    CapturedArgument::Object(v) => write!(writer, " o{}", v)?,
+                    CapturedArgument::NewId(v) => write!(writer, " n{}", v)?,
+                    CapturedArgument::Array(ref a) => write!(writer, " a{}", to_hex(a))?,
+                    CapturedArgument::Fd => write!(writer, " h")?,
+                }
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Deserialize a capture previously written with `write_to()`
+    pub fn read_from<R: BufRead>(reader: R) -> io::Result<Capture> {
+        let mut messages = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            messages.push(parse_line(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Malformed capture line: {}", line),
+                )
+            })?);
+        }
+        Ok(Capture { messages })
+    }
+}
+
+fn parse_line(line: &str) -> Option<CapturedMessage> {
+    let mut tokens = line.split(' ');
+    let timestamp = Duration::from_micros(tokens.next()?.parse().ok()?);
+    let direction = match tokens.next()? {
+        ">" => Direction::Sent,
+        "<" => Direction::Received,
+        _ => return None,
+    };
+    let mut object = tokens.next()?.splitn(2, '@');
+    let interface = object.next()?.to_owned();
+    let mut id_name = object.next()?.splitn(2, '.');
+    let sender_id = id_name.next()?.parse().ok()?;
+    let name = id_name.next()?.to_owned();
+    let opcode = tokens.next()?.parse().ok()?;
+    let args = tokens
+        .map(|token| {
+            let (kind, value) = (token.get(..1)?, token.get(1..)?);
+            Some(match kind {
+                "i" => CapturedArgument::Int(value.parse().ok()?),
+                "u" => CapturedArgument::Uint(value.parse().ok()?),
+                "f" => CapturedArgument::Fixed(value.parse().ok()?),
+                "s" => CapturedArgument::Str(from_hex(value)?),
+                "o" => CapturedArgument::Object(value.parse().ok()?),
+                "n" => CapturedArgument::NewId(value.parse().ok()?),
+                "a" => CapturedArgument::Array(from_hex(value)?),
+                "h" => CapturedArgument::Fd,
+                _ => return None,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(CapturedMessage { timestamp, direction, interface, name, sender_id, opcode, args })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// A handle to record messages into a shared capture
+///
+/// Cloning it gives a new handle to the same capture.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    start: Instant,
+    capture: Arc<Mutex<Capture>>,
+}
+
+impl Recorder {
+    /// Create a new recorder, with an empty capture
+    pub fn new() -> Recorder {
+        Recorder { start: Instant::now(), capture: Arc::new(Mutex::new(Capture::default())) }
+    }
+
+    /// Record a message
+    pub fn record(&self, direction: Direction, interface: &str, name: &str, msg: &Message) {
+        let captured = CapturedMessage {
+            timestamp: self.start.elapsed(),
+            direction,
+            interface: interface.into(),
+            name: name.into(),
+            sender_id: msg.sender_id,
+            opcode: msg.opcode,
+            args: msg.args.iter().map(CapturedArgument::from).collect(),
+        };
+        self.capture.lock().unwrap().messages.push(captured);
+    }
+
+    /// Get a copy of the messages captured so far
+    pub fn snapshot(&self) -> Capture {
+        self.capture.lock().unwrap().clone()
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Recorder {
+        Recorder::new()
+    }
+}
+
+/// A fake server replaying the events of a capture to a client
+///
+/// The capture is expected to have been recorded on the client side: the
+/// `Received` messages are sent to the client, while the `Sent` messages are
+/// used as synchronization points. Each call to `feed()` sends the events up to
+/// the next request the client was recorded sending, so the client can perform
+/// the same requests (and thus create the same objects) between two calls.
+pub struct Replayer {
+    socket: BufferedSocket,
+    messages: std::vec::IntoIter<CapturedMessage>,
+    placeholder: File,
+}
+
+impl Replayer {
+    /// Create a replayer writing to the provided socket
+    ///
+    /// The client to replay into must be connected to the other end of the socket.
+    pub fn new(capture: Capture, socket: Socket) -> io::Result<Replayer> {
+        Ok(Replayer {
+            socket: BufferedSocket::new(socket),
+            messages: capture.messages.into_iter(),
+            placeholder: File::open("/dev/null")?,
+        })
+    }
+
+    /// Send to the client the events up to its next recorded request
+    ///
+    /// Any request already sent by the client is discarded. Returns the number
+    /// of events sent, `0` means the end of the capture was reached.
+    pub fn feed(&mut self) -> NixResult<usize> {
+        self.drain_requests()?;
+        let mut count = 0;
+        let mut seen_event = false;
+        for msg in self.messages.by_ref() {
+            match msg.direction {
+                Direction::Received => {
+                    seen_event = true;
+                    self.socket.write_message(&msg.to_message(self.placeholder.as_raw_fd()))?;
+                    count += 1;
+                }
+                Direction::Sent if seen_event => break,
+                Direction::Sent => {}
+            }
+        }
+        self.socket.flush()?;
+        Ok(count)
+    }
+
+    /// Whether the whole capture has been replayed
+    pub fn is_done(&self) -> bool {
+        self.messages.as_slice().iter().all(|msg| msg.direction == Direction::Sent)
+    }
+
+    fn drain_requests(&mut self) -> NixResult<()> {
+        let mut bytes = [0u8; MAX_BYTES_OUT];
+        let mut fds = [0; MAX_FDS_OUT];
+        loop {
+            match self.socket.get_socket().rcv_msg(&mut bytes, &mut fds) {
+                Ok((0, _)) | Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => return Ok(()),
+                Ok((_, nfds)) => {
+                    for &fd in &fds[..nfds] {
+                        let _ = nix::unistd::close(fd);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+
+    #[test]
+    fn write_read_cycle() {
+        let rec = Recorder::new();
+        let msg = Message {
+            sender_id: 3,
+            opcode: 1,
+            args: smallvec![
+                Argument::Uint(12),
+                Argument::Str(Box::new(CString::new(&b"wl shm"[..]).unwrap())),
+                Argument::Array(Box::new(vec![1, 2, 3])),
+                Argument::Fixed(-256),
+                Argument::Fd(0),
+            ],
+        };
+        rec.record(Direction::Received, "wl_registry", "global", &msg);
+        rec.record(Direction::Sent, "wl_display", "sync", &msg);
+
+        let capture = rec.snapshot();
+        let mut buffer = Vec::new();
+        capture.write_to(&mut buffer).unwrap();
+        let parsed = Capture::read_from(&buffer[..]).unwrap();
+
+        // timestamps are serialized with a microsecond precision
+        for (a, b) in capture.messages.iter().zip(parsed.messages.iter()) {
+            assert_eq!(a.timestamp.as_micros(), b.timestamp.as_micros());
+            assert_eq!(a.args, b.args);
+            assert_eq!((a.direction, &a.interface, &a.name), (b.direction, &b.interface, &b.name));
+        }
+        assert_eq!(parsed.messages.len(), 2);
+        assert_eq!(parsed.messages[0].to_message(0), msg);
+    }
+}
@@ -42,6 +42,16 @@ pub fn print_send_message(
     eprintln!();
 }
 
+/// Print an object lifecycle event to stderr in a following format:
+///
+/// [timestamp] ** interface@id what
+pub fn print_object_lifecycle<D: std::fmt::Display>(interface: &str, id: u32, what: D) {
+    // Add timestamp to output.
+    print_timestamp();
+
+    eprintln!(" ** {}@{} {}", interface, id, what);
+}
+
 /// Print arguments with opening/closing bracket.
 fn print_args(args: &[Argument]) {
     let num_args = args.len();
@@ -12,19 +12,42 @@
 //! to define objects able to handle the messages your program receives. Note that
 //! this trait is auto-implemented for closures with appropriate signature, for
 //! convenience.
+//!
+//! ## `no_std` support
+//!
+//! Everything related to sockets, file descriptors, threads and the C library is gated
+//! behind the `std` cargo feature, enabled by default. Without it, this crate is `no_std`
+//! and only requires `alloc`: the wire message model of the `wire` module, the object map
+//! and the `MessageGroup` and `Interface` traits (without their C interop methods) remain
+//! available, for example to implement the protocol over other transports. Building
+//! without `std` requires Rust 1.64.
 
 #![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate nix;
 
+#[cfg(feature = "std")]
 use std::os::raw::c_void;
+#[cfg(feature = "std")]
 use wayland_sys::common as syscom;
 
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
 pub mod debug;
+#[cfg(feature = "std")]
 pub mod filter;
 pub mod map;
+#[cfg(feature = "std")]
+pub mod sniffer;
+#[cfg(feature = "std")]
 pub mod socket;
+#[cfg(feature = "std")]
 pub mod user_data;
 pub mod wire;
 
@@ -40,6 +63,11 @@ pub use smallvec::smallvec;
 pub trait MessageGroup: Sized {
     /// Wire representation of this MessageGroup
     const MESSAGES: &'static [wire::MessageDesc];
+    /// Validation metadata of the arguments of the messages, in the same order as `MESSAGES`
+    ///
+    /// This is only generated by `wayland-scanner` if requested, and is otherwise empty, in
+    /// which case the arguments of the messages are validated without it.
+    const ARGUMENTS: &'static [&'static [wire::ArgumentDesc]] = &[];
     /// The wrapper type for ObjectMap allowing the mapping of Object and
     /// NewId arguments to the object map during parsing.
     type Map;
@@ -59,14 +87,24 @@ pub trait MessageGroup: Sized {
     ) -> Option<crate::map::Object<Meta>>;
     /// Construct a message from its raw representation
     fn from_raw(msg: wire::Message, map: &mut Self::Map) -> Result<Self, ()>;
+    /// Construct a message from a raw representation borrowing the socket buffer
+    ///
+    /// This avoids building an intermediate `wire::Message`. The default implementation
+    /// converts the message to its owned form and forwards it to `from_raw()`.
+    fn from_raw_ref(msg: wire::MessageRef, map: &mut Self::Map) -> Result<Self, ()> {
+        Self::from_raw(msg.into_owned(), map)
+    }
     /// Turn this message into its raw representation
     fn into_raw(self, send_id: u32) -> wire::Message;
     /// Construct a message of this group from its C representation
     ///
+    /// This is only available with the `std` feature.
+    ///
     /// # Safety
     ///
     /// The pointers provided to this function must all be valid pointers from
     /// `libwayland-client`
+    #[cfg(feature = "std")]
     unsafe fn from_raw_c(
         obj: *mut c_void,
         opcode: u32,
@@ -76,6 +114,9 @@ pub trait MessageGroup: Sized {
     ///
     /// It can only be accessed from the provided closure, and this consumes
     /// the message.
+    ///
+    /// This is only available with the `std` feature.
+    #[cfg(feature = "std")]
     fn as_raw_c_in<F, T>(self, f: F) -> T
     where
         F: FnOnce(u32, &mut [syscom::wl_argument]) -> T;
@@ -106,7 +147,24 @@ pub trait Interface: 'static {
     /// advertise through the registry, and clients can choose any version among the
     /// ones the server supports.
     const VERSION: u32;
+    /// Description of this interface, for tooling
+    ///
+    /// The default only describes its name, version and messages, the code generated by
+    /// `wayland-scanner` with its `interface_description` option also describes the
+    /// arguments of the messages and the enums.
+    const DESCRIPTION: &'static wire::InterfaceDesc = &wire::InterfaceDesc {
+        name: Self::NAME,
+        version: Self::VERSION,
+        requests: Self::Request::MESSAGES,
+        events: Self::Event::MESSAGES,
+        request_args: &[],
+        event_args: &[],
+        enums: &[],
+    };
     /// Pointer to the C representation of this interface
+    ///
+    /// This is only available with the `std` feature.
+    #[cfg(feature = "std")]
     fn c_interface() -> *const syscom::wl_interface;
 }
 
@@ -135,6 +193,7 @@ impl MessageGroup for NoMessage {
     fn into_raw(self, _: u32) -> wire::Message {
         match self {}
     }
+    #[cfg(feature = "std")]
     unsafe fn from_raw_c(
         _obj: *mut c_void,
         _opcode: u32,
@@ -142,6 +201,7 @@ impl MessageGroup for NoMessage {
     ) -> Result<Self, ()> {
         Err(())
     }
+    #[cfg(feature = "std")]
     fn as_raw_c_in<F, T>(self, _f: F) -> T
     where
         F: FnOnce(u32, &mut [syscom::wl_argument]) -> T,
@@ -150,6 +210,60 @@ impl MessageGroup for NoMessage {
     }
 }
 
+#[cfg(feature = "std")]
+/// Action taken when a `ThreadGuard` is accessed from the wrong thread
+///
+/// This is a process-wide setting, see `set_thread_guard_policy()`. It only
+/// applies to accesses done through `ThreadGuard::get_or_report()`, which is
+/// what the dispatching machinery of `wayland-client` and `wayland-server` uses.
+#[derive(Debug)]
+pub enum ThreadGuardPolicy {
+    /// Panic with a message describing the violation (the default)
+    Panic,
+    /// Print the violation to stderr and drop the message being dispatched
+    LogAndDrop,
+    /// Send the violation to the provided channel and drop the message being dispatched
+    Forward(std::sync::mpsc::Sender<ThreadGuardViolation>),
+}
+
+#[cfg(feature = "std")]
+/// Description of an access to a `ThreadGuard` from the wrong thread
+#[derive(Clone, Debug)]
+pub struct ThreadGuardViolation {
+    /// The name given to the guard, usually the name of the interface the guarded
+    /// filter is assigned to
+    pub name: &'static str,
+    /// The thread owning the guarded value
+    pub owner: std::thread::ThreadId,
+    /// The thread that attempted the access
+    pub accessor: std::thread::ThreadId,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for ThreadGuardViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Attempted to access a ThreadGuard contents ({}) from the wrong thread: owned by {:?}, accessed from {:?}.",
+            self.name, self.owner, self.accessor
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+static THREAD_GUARD_POLICY: once_cell::sync::Lazy<std::sync::Mutex<ThreadGuardPolicy>> =
+    once_cell::sync::Lazy::new(|| std::sync::Mutex::new(ThreadGuardPolicy::Panic));
+
+#[cfg(feature = "std")]
+/// Set the action taken when a `ThreadGuard` is accessed from the wrong thread
+///
+/// Returns the previous policy.
+pub fn set_thread_guard_policy(policy: ThreadGuardPolicy) -> ThreadGuardPolicy {
+    let mut guard = THREAD_GUARD_POLICY.lock().unwrap_or_else(|e| e.into_inner());
+    std::mem::replace(&mut *guard, policy)
+}
+
+#[cfg(feature = "std")]
 /// Stores a value in a threadafe container that
 /// only lets you access it from its owning thread
 ///
@@ -157,30 +271,50 @@ impl MessageGroup for NoMessage {
 /// the underlying value will be leaked.
 pub struct ThreadGuard<T: ?Sized> {
     thread: std::thread::ThreadId,
+    name: &'static str,
     val: std::mem::ManuallyDrop<T>,
 }
 
+#[cfg(feature = "std")]
 impl<T> ThreadGuard<T> {
     /// Create a new ThreadGuard wrapper
     pub fn new(val: T) -> ThreadGuard<T> {
-        ThreadGuard { val: std::mem::ManuallyDrop::new(val), thread: std::thread::current().id() }
+        ThreadGuard::new_named(val, "<unnamed>")
+    }
+
+    /// Create a new ThreadGuard wrapper with a name
+    ///
+    /// The name is included in the diagnostics produced when the guard is
+    /// accessed from the wrong thread.
+    pub fn new_named(val: T, name: &'static str) -> ThreadGuard<T> {
+        ThreadGuard {
+            val: std::mem::ManuallyDrop::new(val),
+            thread: std::thread::current().id(),
+            name,
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: ?Sized> ThreadGuard<T> {
     /// Access the underlying value
     ///
     /// Panics if done on the wrong thread
     pub fn get(&self) -> &T {
-        self.try_get().expect("Attempted to access a ThreadGuard contents from the wrong thread.")
+        match self.try_get() {
+            Some(val) => val,
+            None => panic!("{}", self.violation()),
+        }
     }
 
     /// Mutably access the underlying value
     ///
     /// Panics if done on the wrong thread
     pub fn get_mut(&mut self) -> &mut T {
-        self.try_get_mut()
-            .expect("Attempted to access a ThreadGuard contents from the wrong thread.")
+        if self.thread != ::std::thread::current().id() {
+            panic!("{}", self.violation());
+        }
+        &mut self.val
     }
 
     /// Try to access the underlying value
@@ -204,8 +338,48 @@ impl<T: ?Sized> ThreadGuard<T> {
             None
         }
     }
+
+    /// Access the underlying value, applying the global `ThreadGuardPolicy` on failure
+    ///
+    /// If done on the wrong thread, this either panics, or reports the violation
+    /// and returns `None`, depending on the policy set with `set_thread_guard_policy()`.
+    pub fn get_or_report(&self) -> Option<&T> {
+        if let Some(val) = self.try_get() {
+            return Some(val);
+        }
+        let violation = self.violation();
+        let policy = THREAD_GUARD_POLICY.lock().unwrap_or_else(|e| e.into_inner());
+        match *policy {
+            ThreadGuardPolicy::Panic => {
+                // release the lock before unwinding to avoid poisoning it
+                ::std::mem::drop(policy);
+                panic!("{}", violation)
+            }
+            ThreadGuardPolicy::LogAndDrop => {
+                eprintln!("[wayland] {} Dropping the message.", violation);
+            }
+            ThreadGuardPolicy::Forward(ref sender) => {
+                let _ = sender.send(violation);
+            }
+        }
+        None
+    }
+
+    /// The name of this guard
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn violation(&self) -> ThreadGuardViolation {
+        ThreadGuardViolation {
+            name: self.name,
+            owner: self.thread,
+            accessor: ::std::thread::current().id(),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
 impl<T: ?Sized> Drop for ThreadGuard<T> {
     fn drop(&mut self) {
         // We can only actually perform the drop if we are on the right thread
@@ -216,5 +390,29 @@ impl<T: ?Sized> Drop for ThreadGuard<T> {
     }
 }
 
+#[cfg(feature = "std")]
 unsafe impl<T: ?Sized> Send for ThreadGuard<T> {}
+#[cfg(feature = "std")]
 unsafe impl<T: ?Sized> Sync for ThreadGuard<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_guard_forward_policy() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let guard = std::sync::Arc::new(ThreadGuard::new_named(42u32, "wl_test"));
+        let previous = set_thread_guard_policy(ThreadGuardPolicy::Forward(sender));
+
+        assert_eq!(guard.get_or_report(), Some(&42));
+        let other = guard.clone();
+        std::thread::spawn(move || assert!(other.get_or_report().is_none())).join().unwrap();
+
+        set_thread_guard_policy(previous);
+        let violation = receiver.try_recv().unwrap();
+        assert_eq!(violation.name, "wl_test");
+        assert_eq!(violation.owner, std::thread::current().id());
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -1,7 +1,9 @@
 //! Wayland objects map
+use crate::wire::{ArgumentDesc, ArgumentType};
 use crate::{Interface, MessageGroup, NoMessage};
 
-use std::cmp::Ordering;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
 
 /// Limit separating server-created from client-created objects IDs in the namespace
 pub const SERVER_ID_LIMIT: u32 = 0xFF00_0000;
@@ -30,6 +32,10 @@ pub struct Object<Meta: ObjectMetadata> {
     pub requests: &'static [crate::wire::MessageDesc],
     /// Description of the events of this object
     pub events: &'static [crate::wire::MessageDesc],
+    /// Validation metadata of the arguments of the requests of this object
+    pub request_args: &'static [&'static [ArgumentDesc]],
+    /// Validation metadata of the arguments of the events of this object
+    pub event_args: &'static [&'static [ArgumentDesc]],
     /// Metadata associated to this object (ex: its event queue client side)
     pub meta: Meta,
     /// A function which, from an opcode, a version, and the Meta, creates a child
@@ -48,6 +54,8 @@ impl<Meta: ObjectMetadata> Object<Meta> {
             version,
             requests: I::Request::MESSAGES,
             events: I::Event::MESSAGES,
+            request_args: I::Request::ARGUMENTS,
+            event_args: I::Event::ARGUMENTS,
             meta,
             childs_from_events: childs_from::<I::Event, Meta>,
             childs_from_requests: childs_from::<I::Request, Meta>,
@@ -66,6 +74,24 @@ impl<Meta: ObjectMetadata> Object<Meta> {
         (self.childs_from_requests)(opcode, self.version, &self.meta)
     }
 
+    /// The signature and validation metadata of given request opcode
+    pub fn request_desc(
+        &self,
+        opcode: u16,
+    ) -> Option<(&'static [ArgumentType], &'static [ArgumentDesc])> {
+        let desc = self.requests.get(opcode as usize)?;
+        Some((desc.signature, self.request_args.get(opcode as usize).copied().unwrap_or(&[])))
+    }
+
+    /// The signature and validation metadata of given event opcode
+    pub fn event_desc(
+        &self,
+        opcode: u16,
+    ) -> Option<(&'static [ArgumentType], &'static [ArgumentDesc])> {
+        let desc = self.events.get(opcode as usize)?;
+        Some((desc.signature, self.event_args.get(opcode as usize).copied().unwrap_or(&[])))
+    }
+
     /// Check whether this object is of given interface
     pub fn is_interface<I: Interface>(&self) -> bool {
         // TODO: we might want to be more robust than that
@@ -79,6 +105,8 @@ impl<Meta: ObjectMetadata> Object<Meta> {
             version: 0,
             requests: &[],
             events: &[],
+            request_args: &[],
+            event_args: &[],
             meta,
             childs_from_events: childs_from::<NoMessage, Meta>,
             childs_from_requests: childs_from::<NoMessage, Meta>,
@@ -180,6 +208,20 @@ impl<Meta: ObjectMetadata> ObjectMap<Meta> {
         }
     }
 
+    /// Iterate over all objects of the map, with their ids
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Object<Meta>)> + '_ {
+        let client = self
+            .client_objects
+            .iter()
+            .enumerate()
+            .filter_map(|(id, place)| place.as_ref().map(|obj| (id as u32 + 1, obj)));
+        let server =
+            self.server_objects.iter().enumerate().filter_map(|(id, place)| {
+                place.as_ref().map(|obj| (id as u32 + SERVER_ID_LIMIT, obj))
+            });
+        client.chain(server)
+    }
+
     /// Mutably access all objects of the map in sequence
     pub fn with_all<F: FnMut(u32, &mut Object<Meta>)>(&mut self, mut f: F) {
         for (id, place) in self.client_objects.iter_mut().enumerate() {
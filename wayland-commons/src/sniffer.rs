@@ -0,0 +1,427 @@
+//! Decoding of the traffic of a wayland connection
+//!
+//! A `Sniffer` follows the messages exchanged on a connection from the outside,
+//! as a proxy placed between a client and its compositor sees them, and decodes
+//! them using the descriptions provided by the `Interface` implementations of the
+//! protocols. It keeps track of the objects created and destroyed on the connection,
+//! so that each message can be annotated with the interface of its object and its name.
+//!
+//! The messages are decoded into `CapturedMessage`s, recorded from the point of view
+//! of the client: the requests are `Sent` and the events are `Received`. They can thus
+//! be stored in a `Capture` and later replayed to a client, or logged using `LogFormat`.
+//!
+//! The `forward()` function implements such a proxy on top of two sockets.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Instant;
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::Result as NixResult;
+use smallvec::SmallVec;
+
+use crate::capture::{CapturedArgument, CapturedMessage, Direction};
+use crate::map::{Object, ObjectMap, SERVER_ID_LIMIT};
+use crate::socket::{Socket, MAX_BYTES_OUT, MAX_FDS_OUT};
+use crate::wire::{parse_header, Argument, ArgumentType, Message, MessageParseError};
+use crate::Interface;
+
+// creates the object of a registered interface at given version
+type MakeObject = fn(u32, ()) -> Object<()>;
+
+// the bytes and fds received in one direction and not decoded yet
+#[derive(Default)]
+struct Pending {
+    bytes: Vec<u8>,
+    fds: Vec<RawFd>,
+}
+
+/// A decoder of the traffic of a wayland connection
+///
+/// The objects using a generic `new_id` argument, like the globals bound with
+/// `wl_registry.bind`, can only be decoded if their interface was registered
+/// with `register()`.
+///
+/// Messages that cannot be decoded, because their object or opcode is unknown, are
+/// reported with an empty `name` and no arguments. The file descriptors they carry
+/// cannot be accounted for, so the fds of the following messages of the same direction
+/// may be misattributed.
+pub struct Sniffer {
+    start: Instant,
+    map: ObjectMap<()>,
+    interfaces: HashMap<&'static str, MakeObject>,
+    requests: Pending,
+    events: Pending,
+}
+
+impl Sniffer {
+    /// Create a sniffer for a new connection
+    ///
+    /// `D` is the interface of the display object, with id 1.
+    pub fn new<D: Interface>() -> Sniffer {
+        let mut map = ObjectMap::new();
+        map.insert_at(1, Object::from_interface::<D>(1, ())).unwrap();
+        Sniffer {
+            start: Instant::now(),
+            map,
+            interfaces: HashMap::new(),
+            requests: Pending::default(),
+            events: Pending::default(),
+        }
+    }
+
+    /// Register an interface, to decode its objects created by a generic `new_id`
+    pub fn register<I: Interface>(&mut self) {
+        self.interfaces.insert(I::NAME, Object::from_interface::<I>);
+    }
+
+    /// Decode some data of the connection
+    ///
+    /// `bytes` and `fds` should be provided in the order they were received from the
+    /// socket, the data sent by the client being `Sent` and the data sent by the server
+    /// being `Received`. They do not need to be aligned on message boundaries: the
+    /// incomplete messages are kept until the rest of their data is provided.
+    ///
+    /// The callback is invoked with each decoded message. The fds are not used other
+    /// than for counting, and thus remain owned by the caller.
+    ///
+    /// An error is returned if the data does not follow the wire format, after which
+    /// this sniffer cannot decode this direction anymore.
+    pub fn feed<F>(
+        &mut self,
+        direction: Direction,
+        bytes: &[u8],
+        fds: &[RawFd],
+        mut callback: F,
+    ) -> Result<(), MessageParseError>
+    where
+        F: FnMut(CapturedMessage),
+    {
+        let Sniffer { start, map, interfaces, requests, events } = self;
+        let pending = match direction {
+            Direction::Sent => requests,
+            Direction::Received => events,
+        };
+        pending.bytes.extend_from_slice(bytes);
+        pending.fds.extend_from_slice(fds);
+
+        let mut read_bytes = 0;
+        let mut read_fds = 0;
+        let ret = loop {
+            let data = &pending.bytes[read_bytes..];
+            let header = match parse_header(data) {
+                Ok(header) if header.size <= data.len() => header,
+                Ok(_) | Err(MessageParseError::MissingData) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            let object = map.find(header.sender_id);
+            let desc = object.as_ref().and_then(|object| {
+                let descs = match direction {
+                    Direction::Sent => object.requests,
+                    Direction::Received => object.events,
+                };
+                descs.get(header.opcode as usize)
+            });
+            let (object, desc) = match (object, desc) {
+                (Some(object), Some(desc)) => (object, desc),
+                (object, _) => {
+                    let interface = object.map(|object| object.interface).unwrap_or("");
+                    callback(CapturedMessage {
+                        timestamp: start.elapsed(),
+                        direction,
+                        interface: interface.into(),
+                        name: String::new(),
+                        sender_id: header.sender_id,
+                        opcode: header.opcode,
+                        args: Vec::new(),
+                    });
+                    read_bytes += header.size;
+                    continue;
+                }
+            };
+            let child = match direction {
+                Direction::Sent => object.request_child(header.opcode),
+                Direction::Received => object.event_child(header.opcode),
+            };
+            // a new_id without an interface in the protocol is generic, and is sent on the
+            // wire preceded by the name and version of its interface
+            let mut signature = SmallVec::<[ArgumentType; 8]>::new();
+            for &arg in desc.signature {
+                if arg == ArgumentType::NewId && child.is_none() {
+                    signature.extend_from_slice(&[ArgumentType::Str, ArgumentType::Uint]);
+                }
+                signature.push(arg);
+            }
+            let words: SmallVec<[u32; 32]> = data[..header.size]
+                .chunks(4)
+                .map(|w| u32::from_ne_bytes([w[0], w[1], w[2], w[3]]))
+                .collect();
+            let msg = match Message::from_raw(&words, &signature, &pending.fds[read_fds..]) {
+                Ok((msg, _, rest_fds)) => {
+                    read_fds = pending.fds.len() - rest_fds.len();
+                    msg
+                }
+                // the fds of the message have not been provided yet
+                Err(MessageParseError::MissingFD) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            read_bytes += header.size;
+
+            // track the objects created by this message
+            if let Some(i) = msg.args.iter().position(|arg| arg.get_type() == ArgumentType::NewId) {
+                let created = child.or_else(|| match (&msg.args[i - 2], &msg.args[i - 1]) {
+                    (Argument::Str(name), &Argument::Uint(version)) => {
+                        let name = name.to_str().ok()?;
+                        interfaces.get(name).map(|make| make(version, ()))
+                    }
+                    _ => None,
+                });
+                if let Argument::NewId(id) = msg.args[i] {
+                    map.remove(id);
+                    if let Some(created) = created {
+                        let _ = map.insert_at(id, created);
+                    }
+                }
+            }
+            // track the destroyed objects: client-created ids are only released once the
+            // server acknowledges them with wl_display.delete_id
+            if direction == Direction::Received && header.sender_id == 1 && desc.name == "delete_id"
+            {
+                if let Some(&Argument::Uint(id)) = msg.args.first() {
+                    map.remove(id);
+                }
+            } else if desc.destructor && header.sender_id >= SERVER_ID_LIMIT {
+                map.remove(header.sender_id);
+            }
+
+            callback(CapturedMessage {
+                timestamp: start.elapsed(),
+                direction,
+                interface: object.interface.into(),
+                name: desc.name.into(),
+                sender_id: msg.sender_id,
+                opcode: msg.opcode,
+                args: msg.args.iter().map(CapturedArgument::from).collect(),
+            });
+        };
+
+        pending.bytes.drain(..read_bytes);
+        pending.fds.drain(..read_fds);
+        ret
+    }
+}
+
+/// The output format of the messages logged by a sniffer
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// A human-readable line per message
+    ///
+    /// The lines are in the format of `WAYLAND_DEBUG`,
+    /// `[seconds.micros] -> interface@id.name(args)` for requests and `<-` for events.
+    Text,
+    /// A JSON object per line
+    ///
+    /// The objects have the fields `timestamp` in seconds, `direction` (`"request"` or
+    /// `"event"`), `interface`, `id`, `opcode`, `name` and `args`, the latter being a list of
+    /// objects with a `type` and, except for fds, a `value`. The contents of arrays are
+    /// hex-encoded.
+    Json,
+}
+
+impl LogFormat {
+    /// Write a message in this format, followed by a newline
+    pub fn write<W: Write>(self, msg: &CapturedMessage, mut writer: W) -> io::Result<()> {
+        match self {
+            LogFormat::Text => write_text(msg, &mut writer),
+            LogFormat::Json => write_json(msg, &mut writer),
+        }
+    }
+}
+
+fn write_text<W: Write>(msg: &CapturedMessage, writer: &mut W) -> io::Result<()> {
+    write!(
+        writer,
+        "[{}.{:06}] {} {}@{}.",
+        msg.timestamp.as_secs(),
+        msg.timestamp.subsec_micros(),
+        match msg.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        },
+        if msg.interface.is_empty() { "[unknown]" } else { &msg.interface },
+        msg.sender_id,
+    )?;
+    if msg.name.is_empty() {
+        return writeln!(writer, "[opcode {}](?)", msg.opcode);
+    }
+    write!(writer, "{}(", msg.name)?;
+    for (i, arg) in msg.args.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ", ")?;
+        }
+        match *arg {
+            CapturedArgument::Int(v) => write!(writer, "{}", v)?,
+            CapturedArgument::Uint(v) => write!(writer, "{}", v)?,
+            CapturedArgument::Fixed(v) => write!(writer, "{}", f64::from(v) / 256.)?,
+            CapturedArgument::Str(ref s) => write!(writer, "{:?}", String::from_utf8_lossy(s))?,
+            CapturedArgument::Object(0) => write!(writer, "nil")?,
+            CapturedArgument::Object(v) => write!(writer, "@{}", v)?,
+            CapturedArgument::NewId(v) => write!(writer, "new id @{}", v)?,
+            CapturedArgument::Array(ref a) => write!(writer, "array[{}]", a.len())?,
+            CapturedArgument::Fd => write!(writer, "fd")?,
+        }
+    }
+    writeln!(writer, ")")
+}
+
+fn write_json<W: Write>(msg: &CapturedMessage, writer: &mut W) -> io::Result<()> {
+    write!(
+        writer,
+        "{{\"timestamp\":{}.{:06},\"direction\":\"{}\",\"interface\":{},\"id\":{},\"opcode\":{},\
+         \"name\":{},\"args\":[",
+        msg.timestamp.as_secs(),
+        msg.timestamp.subsec_micros(),
+        match msg.direction {
+            Direction::Sent => "request",
+            Direction::Received => "event",
+        },
+        json_string(msg.interface.as_bytes()),
+        msg.sender_id,
+        msg.opcode,
+        json_string(msg.name.as_bytes()),
+    )?;
+    for (i, arg) in msg.args.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        match *arg {
+            CapturedArgument::Int(v) => write!(writer, "{{\"type\":\"int\",\"value\":{}}}", v)?,
+            CapturedArgument::Uint(v) => write!(writer, "{{\"type\":\"uint\",\"value\":{}}}", v)?,
+            CapturedArgument::Fixed(v) => {
+                write!(writer, "{{\"type\":\"fixed\",\"value\":{}}}", f64::from(v) / 256.)?
+            }
+            CapturedArgument::Str(ref s) => {
+                write!(writer, "{{\"type\":\"string\",\"value\":{}}}", json_string(s))?
+            }
+            CapturedArgument::Object(v) => {
+                write!(writer, "{{\"type\":\"object\",\"value\":{}}}", v)?
+            }
+            CapturedArgument::NewId(v) => {
+                write!(writer, "{{\"type\":\"new_id\",\"value\":{}}}", v)?
+            }
+            CapturedArgument::Array(ref a) => {
+                let hex: String = a.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(writer, "{{\"type\":\"array\",\"value\":\"{}\"}}", hex)?
+            }
+            CapturedArgument::Fd => write!(writer, "{{\"type\":\"fd\"}}")?,
+        }
+    }
+    writeln!(writer, "]}}")
+}
+
+// quote and escape a possibly non-UTF-8 string for JSON
+fn json_string(s: &[u8]) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in String::from_utf8_lossy(s).chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Forward the traffic between a client and a server, decoding it with a sniffer
+///
+/// This transparently proxies the connection: all the data and fds received from one
+/// socket are sent to the other one unmodified. The callback is invoked with each message
+/// decoded by the sniffer. If the traffic cannot be decoded anymore, it is still forwarded
+/// but no longer reported.
+///
+/// This function blocks until one of the sides closes its connection, in which case it
+/// returns `Ok(())`, or an error occurs on one of the sockets.
+pub fn forward<F>(
+    client: &Socket,
+    server: &Socket,
+    sniffer: &mut Sniffer,
+    mut callback: F,
+) -> NixResult<()>
+where
+    F: FnMut(CapturedMessage),
+{
+    let mut bytes = [0u8; MAX_BYTES_OUT];
+    let mut fds = [0; MAX_FDS_OUT];
+    let mut decoding = [true, true];
+    loop {
+        let mut pollfds = [
+            PollFd::new(client.as_raw_fd(), PollFlags::POLLIN),
+            PollFd::new(server.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut pollfds, -1) {
+            Ok(_) => {}
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(e) => return Err(e),
+        }
+        for (i, pollfd) in pollfds.iter().enumerate() {
+            if pollfd.revents().map(|r| r.is_empty()).unwrap_or(true) {
+                continue;
+            }
+            let (from, to, direction) = if i == 0 {
+                (client, server, Direction::Sent)
+            } else {
+                (server, client, Direction::Received)
+            };
+            let (nbytes, nfds) = match from.rcv_msg(&mut bytes, &mut fds) {
+                Ok(ret) => ret,
+                Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => continue,
+                Err(nix::Error::Sys(nix::errno::Errno::ECONNRESET)) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if nbytes == 0 {
+                // the peer closed its connection
+                return Ok(());
+            }
+            let sent = send_all(to, &bytes[..nbytes], &fds[..nfds]);
+            if decoding[i] {
+                decoding[i] =
+                    sniffer.feed(direction, &bytes[..nbytes], &fds[..nfds], &mut callback).is_ok();
+            }
+            // the fds have been duplicated by the kernel when sent
+            for &fd in &fds[..nfds] {
+                let _ = nix::unistd::close(fd);
+            }
+            match sent {
+                Ok(()) => {}
+                Err(nix::Error::Sys(nix::errno::Errno::EPIPE)) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// send all the data to a socket, waiting for it to be writable if needed
+fn send_all(socket: &Socket, mut bytes: &[u8], mut fds: &[RawFd]) -> NixResult<()> {
+    while !bytes.is_empty() {
+        match socket.send_msg_vectored(&[bytes], fds) {
+            Ok(written) => {
+                bytes = &bytes[written..];
+                // the fds were sent along with the first byte
+                fds = &[];
+            }
+            Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => {
+                poll(&mut [PollFd::new(socket.as_raw_fd(), PollFlags::POLLOUT)], -1)?;
+            }
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
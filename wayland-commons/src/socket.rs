@@ -1,18 +1,34 @@
 //! Wayland socket manipulation
 
+use std::collections::VecDeque;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 
 use nix::{
     sys::{socket, uio},
     Result as NixResult,
 };
+use smallvec::SmallVec;
 
-use crate::wire::{ArgumentType, Message, MessageParseError, MessageWriteError};
+use crate::wire::{
+    ArgumentDesc, ArgumentType, Message, MessageParseError, MessageRef, MessageWriteError,
+    Strictness, MAX_MESSAGE_SIZE,
+};
 
 /// Maximum number of FD that can be sent in a single socket message
 pub const MAX_FDS_OUT: usize = 28;
 /// Maximum number of bytes that can be sent in a single socket message
 pub const MAX_BYTES_OUT: usize = 4096;
+/// Default maximum size of the messages of a `BufferedSocket`, in bytes
+///
+/// This is the limit of libwayland.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4096;
+
+// Maximum number of received FDs waiting for the messages they belong to
+const MAX_FDS_IN: usize = 1024;
+
+// Number of chunks of MAX_BYTES_OUT bytes a BufferedSocket queues before
+// write_message() tries to flush them
+const MAX_OUT_CHUNKS: usize = 16;
 
 /*
  * Socket
@@ -32,14 +48,29 @@ impl Socket {
     /// slice should not be longer than `MAX_BYTES_OUT` otherwise the receiving
     /// end may lose some data.
     pub fn send_msg(&self, bytes: &[u8], fds: &[RawFd]) -> NixResult<()> {
-        let iov = [uio::IoVec::from_slice(bytes)];
+        self.send_msg_vectored(&[bytes], fds)?;
+        Ok(())
+    }
+
+    /// Send a single message gathered from several buffers to the socket
+    ///
+    /// Return the number of bytes written, which is less than the total length of the
+    /// buffers if the socket could not accept all of them. If any byte was written, all
+    /// the Fds were sent along with it.
+    ///
+    /// The `fds` slice should not be longer than `MAX_FDS_OUT`, otherwise the receiving
+    /// end may lose some of them.
+    pub fn send_msg_vectored(&self, bytes: &[&[u8]], fds: &[RawFd]) -> NixResult<usize> {
+        let iov = bytes
+            .iter()
+            .map(|b| uio::IoVec::from_slice(b))
+            .collect::<SmallVec<[_; MAX_OUT_CHUNKS]>>();
         if !fds.is_empty() {
             let cmsgs = [socket::ControlMessage::ScmRights(fds)];
-            socket::sendmsg(self.fd, &iov, &cmsgs, socket::MsgFlags::MSG_DONTWAIT, None)?;
+            socket::sendmsg(self.fd, &iov, &cmsgs, socket::MsgFlags::MSG_DONTWAIT, None)
         } else {
-            socket::sendmsg(self.fd, &iov, &[], socket::MsgFlags::MSG_DONTWAIT, None)?;
-        };
-        Ok(())
+            socket::sendmsg(self.fd, &iov, &[], socket::MsgFlags::MSG_DONTWAIT, None)
+        }
     }
 
     /// Receive a single message from the socket
@@ -53,21 +84,32 @@ impl Socket {
     /// The `buffer` slice should be at least `MAX_BYTES_OUT` long and the `fds`
     /// slice `MAX_FDS_OUT` long, otherwise some data of the received message may
     /// be lost.
+    ///
+    /// The received Fds are close-on-exec. Where supported this is done atomically
+    /// by the kernel (`MSG_CMSG_CLOEXEC`), so they can never leak to a child process.
     pub fn rcv_msg(&self, buffer: &mut [u8], fds: &mut [RawFd]) -> NixResult<(usize, usize)> {
         let mut cmsg = cmsg_space!([RawFd; MAX_FDS_OUT]);
         let iov = [uio::IoVec::from_mut_slice(buffer)];
 
-        let msg =
-            socket::recvmsg(self.fd, &iov[..], Some(&mut cmsg), socket::MsgFlags::MSG_DONTWAIT)?;
+        let msg = socket::recvmsg(self.fd, &iov[..], Some(&mut cmsg), RCV_FLAGS)?;
 
         let mut fd_count = 0;
         let received_fds = msg.cmsgs().flat_map(|cmsg| match cmsg {
             socket::ControlMessageOwned::ScmRights(s) => s,
             _ => Vec::new(),
         });
-        for (fd, place) in received_fds.zip(fds.iter_mut()) {
-            fd_count += 1;
-            *place = fd;
+        for fd in received_fds {
+            set_cloexec(fd);
+            match fds.get_mut(fd_count) {
+                Some(place) => {
+                    *place = fd;
+                    fd_count += 1;
+                }
+                // no room to store it, don't leak it
+                None => {
+                    let _ = ::nix::unistd::close(fd);
+                }
+            }
         }
         Ok((msg.bytes, fd_count))
     }
@@ -97,6 +139,53 @@ impl Drop for Socket {
     }
 }
 
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+const RCV_FLAGS: socket::MsgFlags = socket::MsgFlags::from_bits_truncate(
+    socket::MsgFlags::MSG_DONTWAIT.bits() | socket::MsgFlags::MSG_CMSG_CLOEXEC.bits(),
+);
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+const RCV_FLAGS: socket::MsgFlags = socket::MsgFlags::MSG_DONTWAIT;
+
+// the fd was already received with MSG_CMSG_CLOEXEC
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn set_cloexec(_fd: RawFd) {}
+
+// not atomic, but the best we can do
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn set_cloexec(fd: RawFd) {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    let _ = fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC));
+}
+
 /*
  * BufferedSocket
  */
@@ -106,23 +195,124 @@ impl Drop for Socket {
 pub struct BufferedSocket {
     socket: Socket,
     in_data: Buffer<u32>,
-    in_fds: Buffer<RawFd>,
-    out_data: Buffer<u32>,
-    out_fds: Buffer<RawFd>,
+    in_fds: FdBuffer,
+    // the outgoing messages, in chunks that each fit in a single socket message
+    out_chunks: VecDeque<OutChunk>,
+    // number of bytes of the first chunk already written to the socket
+    out_written: usize,
+    // flushed chunks, kept to be reused
+    spare_chunks: Vec<OutChunk>,
+    max_message_size: usize,
+}
+
+struct OutChunk {
+    data: Buffer<u32>,
+    fds: Buffer<RawFd>,
+}
+
+impl OutChunk {
+    fn new(size: usize) -> OutChunk {
+        OutChunk { data: Buffer::new(size / 4), fds: Buffer::new(MAX_FDS_OUT) }
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.storage.len() * 4
+    }
+
+    fn bytes(&self) -> &[u8] {
+        let words = self.data.get_contents();
+        unsafe { ::std::slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 4) }
+    }
+
+    // Writes a message into this chunk, returns false if there is not enough space left
+    //
+    // Messages larger than `max_size` bytes are rejected with E2BIG.
+    fn write_message(&mut self, msg: &Message, max_size: usize) -> NixResult<bool> {
+        match msg
+            .write_to_buffers(self.data.get_writable_storage(), self.fds.get_writable_storage())
+        {
+            Ok((words_out, fds_out)) if words_out * 4 > max_size => {
+                // the dup()-ed fds will not be sent
+                for &fd in &self.fds.get_writable_storage()[..fds_out] {
+                    let _ = ::nix::unistd::close(fd);
+                }
+                Err(::nix::Error::Sys(::nix::errno::Errno::E2BIG))
+            }
+            Ok((words_out, fds_out)) => {
+                self.data.advance(words_out);
+                self.fds.advance(fds_out);
+                Ok(true)
+            }
+            Err(MessageWriteError::BufferTooSmall) => Ok(false),
+            Err(MessageWriteError::DupFdFailed(e)) => Err(e),
+            Err(MessageWriteError::TooLarge) => Err(::nix::Error::Sys(::nix::errno::Errno::E2BIG)),
+        }
+    }
+
+    // Closes the fds of this chunk, once they have been sent
+    fn close_fds(&mut self) {
+        for &fd in self.fds.get_contents() {
+            let _ = ::nix::unistd::close(fd);
+        }
+        self.fds.clear();
+    }
+}
+
+impl Drop for OutChunk {
+    fn drop(&mut self) {
+        // these fds were never sent
+        self.close_fds();
+    }
+}
+
+// The received fds, until they are taken by the message they belong to
+struct FdBuffer(Buffer<RawFd>);
+
+impl Drop for FdBuffer {
+    fn drop(&mut self) {
+        for &fd in self.0.get_contents() {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
 }
 
 impl BufferedSocket {
     /// Wrap a Socket into a Buffered Socket
     pub fn new(socket: Socket) -> BufferedSocket {
+        // Incoming buffers are twice as big in order to be able to store leftover data if needed
+        BufferedSocket::with_capacity(socket, 2 * MAX_BYTES_OUT)
+    }
+
+    /// Wrap a Socket into a Buffered Socket, with an incoming buffer of `capacity` bytes
+    ///
+    /// The incoming buffer still grows when needed, to hold a full socket message along
+    /// with the leftover data of the previous one, or messages larger than `MAX_BYTES_OUT`.
+    pub fn with_capacity(socket: Socket, capacity: usize) -> BufferedSocket {
         BufferedSocket {
             socket,
-            in_data: Buffer::new(2 * MAX_BYTES_OUT / 4), // Incoming buffers are twice as big in order to be
-            in_fds: Buffer::new(2 * MAX_FDS_OUT),        // able to store leftover data if needed
-            out_data: Buffer::new(MAX_BYTES_OUT / 4),
-            out_fds: Buffer::new(MAX_FDS_OUT),
+            in_data: Buffer::new(capacity / 4),
+            in_fds: FdBuffer(Buffer::new(2 * MAX_FDS_OUT)),
+            out_chunks: VecDeque::new(),
+            out_written: 0,
+            spare_chunks: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
         }
     }
 
+    /// Set the maximum size of the messages sent and received through this socket, in bytes
+    ///
+    /// Writing a larger message fails with `E2BIG`, and receiving one fails with
+    /// `MessageParseError::TooLarge`. The size is rounded down to a multiple of 4 and capped
+    /// at `MAX_MESSAGE_SIZE`, the default is `DEFAULT_MAX_MESSAGE_SIZE`.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = ::std::cmp::min(size, MAX_MESSAGE_SIZE) & !3;
+    }
+
+    /// The maximum size of the messages sent and received through this socket, in bytes
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
     /// Get direct access to the underlying socket
     pub fn get_socket(&mut self) -> &mut Socket {
         &mut self.socket
@@ -136,83 +326,144 @@ impl BufferedSocket {
     }
 
     /// Flush the contents of the outgoing buffer into the socket
+    ///
+    /// The buffered messages are written with as few socket messages as possible, each
+    /// carrying up to `MAX_FDS_OUT` Fds. If the socket cannot accept all of them, the
+    /// error `Error::Sys(EAGAIN)` is returned and the remaining data is kept for the
+    /// next flush, see `pending_bytes()`.
     pub fn flush(&mut self) -> NixResult<()> {
-        {
-            let words = self.out_data.get_contents();
-            let bytes = unsafe {
-                ::std::slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 4)
+        if self.out_chunks.is_empty() {
+            // nothing to write, this still reports a closed connection
+            return self.socket.send_msg(&[], &[]);
+        }
+        while !self.out_chunks.is_empty() {
+            let (sent, batch) = {
+                let mut bytes = SmallVec::<[&[u8]; MAX_OUT_CHUNKS]>::new();
+                let mut fds = SmallVec::<[RawFd; MAX_FDS_OUT]>::new();
+                for chunk in &self.out_chunks {
+                    let chunk_fds = chunk.fds.get_contents();
+                    if fds.len() + chunk_fds.len() > MAX_FDS_OUT {
+                        break;
+                    }
+                    fds.extend_from_slice(chunk_fds);
+                    bytes.push(chunk.bytes());
+                }
+                bytes[0] = &bytes[0][self.out_written..];
+                (self.socket.send_msg_vectored(&bytes, &fds)?, bytes.len())
             };
-            let fds = self.out_fds.get_contents();
-            self.socket.send_msg(bytes, fds)?;
-            for &fd in fds {
-                // once the fds are sent, we can close them
-                let _ = ::nix::unistd::close(fd);
+            // once the fds are sent, we can close them
+            for chunk in self.out_chunks.iter_mut().take(batch) {
+                chunk.close_fds();
+            }
+            self.out_written += sent;
+            while let Some(len) = self.out_chunks.front().map(|chunk| chunk.bytes().len()) {
+                if self.out_written < len {
+                    break;
+                }
+                self.out_written -= len;
+                let mut chunk = self.out_chunks.pop_front().unwrap();
+                chunk.data.clear();
+                self.spare_chunks.push(chunk);
             }
         }
-        self.out_data.clear();
-        self.out_fds.clear();
         Ok(())
     }
 
-    // internal method
-    //
-    // attempts to write a message in the internal out buffers,
-    // returns true if successful
-    //
-    // if false is returned, it means there is not enough space
-    // in the buffer
-    fn attempt_write_message(&mut self, msg: &Message) -> NixResult<bool> {
-        match msg.write_to_buffers(
-            self.out_data.get_writable_storage(),
-            self.out_fds.get_writable_storage(),
-        ) {
-            Ok((bytes_out, fds_out)) => {
-                self.out_data.advance(bytes_out);
-                self.out_fds.advance(fds_out);
-                Ok(true)
-            }
-            Err(MessageWriteError::BufferTooSmall) => Ok(false),
-            Err(MessageWriteError::DupFdFailed(e)) => Err(e),
-        }
+    /// Number of bytes in the outgoing buffer waiting to be written to the socket
+    pub fn pending_bytes(&self) -> usize {
+        self.out_chunks.iter().map(|chunk| chunk.bytes().len()).sum::<usize>() - self.out_written
     }
 
     /// Write a message to the outgoing buffer
     ///
-    /// This method may flush the internal buffer if necessary (if it is full).
+    /// This method may flush the internal buffer if necessary (if it is full). If the socket
+    /// cannot accept more data, the message is still queued and the buffer grows past its
+    /// usual size until a later `flush()` succeeds.
     ///
-    /// If the message is too big to fit in the buffer, the error `Error::Sys(E2BIG)`
+    /// If the message is larger than the maximum message size, the error `Error::Sys(E2BIG)`
     /// will be returned.
     pub fn write_message(&mut self, msg: &Message) -> NixResult<()> {
-        if !self.attempt_write_message(msg)? {
-            // the attempt failed, there is not enough space in the buffer
-            // we need to flush it
-            self.flush()?;
-            if !self.attempt_write_message(msg)? {
+        let max_size = self.max_message_size;
+        if let Some(chunk) = self.out_chunks.back_mut() {
+            if chunk.write_message(msg, max_size)? {
+                return Ok(());
+            }
+        }
+        // there is not enough space in the last chunk, we need a new one
+        if self.out_chunks.len() >= MAX_OUT_CHUNKS {
+            match self.flush() {
+                // the socket is full, the message is queued anyway
+                Ok(()) | Err(::nix::Error::Sys(::nix::errno::Errno::EAGAIN)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let mut chunk = self.spare_chunks.pop().unwrap_or_else(|| OutChunk::new(MAX_BYTES_OUT));
+        if !chunk.write_message(msg, max_size)? {
+            // the message is larger than a chunk, it needs a chunk of its own
+            if chunk.capacity() < max_size {
+                self.spare_chunks.push(chunk);
+                chunk = OutChunk::new(max_size);
+            }
+            if !chunk.write_message(msg, max_size)? {
                 // If this fails again, this means the message is too big
                 // to be transmitted at all
+                self.spare_chunks.push(chunk);
                 return Err(::nix::Error::Sys(::nix::errno::Errno::E2BIG));
             }
         }
+        self.out_chunks.push_back(chunk);
         Ok(())
     }
 
     /// Try to fill the incoming buffers of this socket, to prepare
     /// a new round of parsing.
+    ///
+    /// Fails with `EOVERFLOW` if the unparsed data would exceed the maximum message size
+    /// by more than two socket messages, which does not happen as long as the messages are
+    /// parsed between calls.
     pub fn fill_incoming_buffers(&mut self) -> NixResult<()> {
-        // clear the buffers if they have no content
+        // clear the buffers if they have no content, otherwise move the leftover
+        // content to the front to make room
         if !self.in_data.has_content() {
             self.in_data.clear();
+        } else {
+            self.in_data.move_to_front();
         }
-        if !self.in_fds.has_content() {
-            self.in_fds.clear();
+        if !self.in_fds.0.has_content() {
+            self.in_fds.0.clear();
+        } else {
+            self.in_fds.0.move_to_front();
         }
+        // the FDs can be received ahead of the messages using them, make room for those
+        // of a full socket message so that none gets lost
+        if self.in_fds.0.get_contents().len() + MAX_FDS_OUT > MAX_FDS_IN {
+            return Err(::nix::Error::Sys(::nix::errno::Errno::EOVERFLOW));
+        }
+        self.in_fds.0.reserve(MAX_FDS_OUT);
+        // make room for a full socket message, and for the rest of a pending message
+        // larger than that
+        let pending = self.in_data.get_contents();
+        let missing = match pending.get(1) {
+            Some(&word_2) => {
+                let size = ::std::cmp::min((word_2 >> 16) as usize, self.max_message_size);
+                (size / 4).saturating_sub(pending.len())
+            }
+            None => 0,
+        };
+        let wanted = ::std::cmp::max(missing, MAX_BYTES_OUT / 4);
+        // the leftover data is at most a pending message, anything more is never going to
+        // be parsed
+        if (pending.len() + wanted) * 4 > self.max_message_size + 2 * MAX_BYTES_OUT {
+            return Err(::nix::Error::Sys(::nix::errno::Errno::EOVERFLOW));
+        }
+        self.in_data.reserve(wanted);
         // receive a message
         let (in_bytes, in_fds) = {
             let words = self.in_data.get_writable_storage();
             let bytes = unsafe {
                 ::std::slice::from_raw_parts_mut(words.as_ptr() as *mut u8, words.len() * 4)
             };
-            let fds = self.in_fds.get_writable_storage();
+            let fds = self.in_fds.0.get_writable_storage();
             self.socket.rcv_msg(bytes, fds)?
         };
         if in_bytes == 0 {
@@ -221,7 +472,7 @@ impl BufferedSocket {
         }
         // advance the storage
         self.in_data.advance(in_bytes / 4 + if in_bytes % 4 > 0 { 1 } else { 0 });
-        self.in_fds.advance(in_fds);
+        self.in_fds.0.advance(in_fds);
         Ok(())
     }
 
@@ -243,23 +494,43 @@ impl BufferedSocket {
     pub fn read_one_message<F>(&mut self, mut signature: F) -> Result<Message, MessageParseError>
     where
         F: FnMut(u32, u16) -> Option<&'static [ArgumentType]>,
+    {
+        self.read_one_message_with(Strictness::Lenient, |id, opcode| {
+            signature(id, opcode).map(|sig| (sig, &[][..]))
+        })
+    }
+
+    /// Read, validate and deserialize a single message from the incoming buffers socket
+    ///
+    /// This is similar to `read_one_message()`, but the closure also provides the validation
+    /// metadata of the arguments of the message, and the message is validated according to
+    /// `strictness`. A message rejected by the validation is reported as a
+    /// `MessageParseError::Invalid` error.
+    pub fn read_one_message_with<F>(
+        &mut self,
+        strictness: Strictness,
+        mut desc: F,
+    ) -> Result<Message, MessageParseError>
+    where
+        F: FnMut(u32, u16) -> Option<(&'static [ArgumentType], &'static [ArgumentDesc])>,
     {
         let (msg, read_data, read_fd) = {
             let data = self.in_data.get_contents();
-            let fds = self.in_fds.get_contents();
+            let fds = self.in_fds.0.get_contents();
             if data.len() < 2 {
                 return Err(MessageParseError::MissingData);
             }
             let object_id = data[0];
             let opcode = (data[1] & 0x0000_FFFF) as u16;
-            if let Some(sig) = signature(object_id, opcode) {
-                match Message::from_raw(data, sig, fds) {
-                    Ok((msg, rest_data, rest_fds)) => {
-                        (msg, data.len() - rest_data.len(), fds.len() - rest_fds.len())
-                    }
-                    // TODO: gracefully handle wayland messages split across unix messages ?
-                    Err(e) => return Err(e),
-                }
+            let size = (data[1] >> 16) as usize;
+            if size > self.max_message_size {
+                return Err(MessageParseError::TooLarge(size));
+            }
+            if let Some((sig, args)) = desc(object_id, opcode) {
+                // a message split across unix messages is reported as MissingData
+                let (msg, rest_data, rest_fds) = MessageRef::from_raw(data, sig, fds)?;
+                msg.validate(args, strictness).map_err(MessageParseError::Invalid)?;
+                (msg.into_owned(), data.len() - rest_data.len(), fds.len() - rest_fds.len())
             } else {
                 // no signature found ?
                 return Err(MessageParseError::Malformed);
@@ -267,7 +538,7 @@ impl BufferedSocket {
         };
 
         self.in_data.offset(read_data);
-        self.in_fds.offset(read_fd);
+        self.in_fds.0.offset(read_fd);
 
         Ok(msg)
     }
@@ -294,17 +565,42 @@ impl BufferedSocket {
     /// - `Ok(Ok(n))`: no error occurred, `n` messages where processed
     /// - `Ok(Err(MessageParseError::Malformed))`: a malformed message was encountered
     ///   (this is a protocol error and is supposed to be fatal to the connection).
+    ///   A message whose data was received without the fds it expects is reported as
+    ///   `MessageParseError::MissingFD`, as the fds are always sent along with the data.
     /// - `Err(e)`: an I/O error occurred reading from the socked, details are in `e`
     ///   (this can be a "wouldblock" error, which just means that no message is available
     ///   to read)
     pub fn read_messages<F1, F2>(
         &mut self,
         mut signature: F1,
-        mut callback: F2,
+        callback: F2,
     ) -> NixResult<Result<usize, MessageParseError>>
     where
         F1: FnMut(u32, u16) -> Option<&'static [ArgumentType]>,
         F2: FnMut(Message) -> bool,
+    {
+        self.read_messages_with(
+            Strictness::Lenient,
+            |id, opcode| signature(id, opcode).map(|sig| (sig, &[][..])),
+            callback,
+        )
+    }
+
+    /// Read, validate and deserialize messages from the socket
+    ///
+    /// This is similar to `read_messages()`, but the first closure also provides the
+    /// validation metadata of the arguments of the message, and the messages are validated
+    /// according to `strictness`. Parsing stops at the first message rejected by the
+    /// validation, which is reported as a `MessageParseError::Invalid` error.
+    pub fn read_messages_with<F1, F2>(
+        &mut self,
+        strictness: Strictness,
+        mut desc: F1,
+        mut callback: F2,
+    ) -> NixResult<Result<usize, MessageParseError>>
+    where
+        F1: FnMut(u32, u16) -> Option<(&'static [ArgumentType], &'static [ArgumentDesc])>,
+        F2: FnMut(Message) -> bool,
     {
         // message parsing
         let mut dispatched = 0;
@@ -313,7 +609,7 @@ impl BufferedSocket {
             let mut err = None;
             // first parse any leftover messages
             loop {
-                match self.read_one_message(&mut signature) {
+                match self.read_one_message_with(strictness, &mut desc) {
                     Ok(msg) => {
                         let keep_going = callback(msg);
                         dispatched += 1;
@@ -330,11 +626,16 @@ impl BufferedSocket {
 
             // copy back any leftover content to the front of the buffer
             self.in_data.move_to_front();
-            self.in_fds.move_to_front();
-
-            if let Some(MessageParseError::Malformed) = err {
-                // early stop here
-                return Ok(Err(MessageParseError::Malformed));
+            self.in_fds.0.move_to_front();
+
+            // the fds of a message are sent along with its data, they are not coming if
+            // its data is complete, so `MissingFD` is an error like the others
+            match err {
+                Some(MessageParseError::MissingData) | None => {}
+                Some(e) => {
+                    // early stop here
+                    return Ok(Err(e));
+                }
             }
 
             if err.is_none() && self.in_data.has_content() {
@@ -415,16 +716,17 @@ impl<T: Copy + Default> Buffer<T> {
     /// Move the unread contents of the buffer to the front, to ensure
     /// maximal write space availability
     fn move_to_front(&mut self) {
-        unsafe {
-            ::std::ptr::copy(
-                &self.storage[self.offset] as *const T,
-                &mut self.storage[0] as *mut T,
-                self.occupied - self.offset,
-            );
-        }
+        self.storage.copy_within(self.offset..self.occupied, 0);
         self.occupied -= self.offset;
         self.offset = 0;
     }
+
+    /// Grow the buffer if needed so that it has room for at least `additional` more elements
+    fn reserve(&mut self, additional: usize) {
+        if self.storage.len() < self.occupied + additional {
+            self.storage.resize(self.occupied + additional, T::default());
+        }
+    }
 }
 
 #[cfg(test)]
@@ -512,6 +814,50 @@ mod tests {
         assert_eq!(ret, 1);
     }
 
+    #[test]
+    fn read_split_message() {
+        let msg = Message {
+            sender_id: 42,
+            opcode: 7,
+            args: smallvec![
+                Argument::Uint(3),
+                Argument::Str(Box::new(CString::new(&b"I like trains!"[..]).unwrap())),
+            ],
+        };
+        let mut raw = vec![0; 64];
+        let (len, _) = msg.write_to_buffers(&mut raw[..], &mut []).unwrap();
+        let bytes = unsafe { ::std::slice::from_raw_parts(raw.as_ptr() as *const u8, len * 4) };
+
+        let (client, server) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let client = unsafe { Socket::from_raw_fd(client.into_raw_fd()) };
+        let mut server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server.into_raw_fd()) });
+
+        static SIGNATURE: &'static [ArgumentType] = &[ArgumentType::Uint, ArgumentType::Str];
+        let mut received = Vec::new();
+
+        // the message is received in two parts
+        client.send_msg(&bytes[..12], &[]).unwrap();
+        let ret = server.read_messages(
+            |_, _| Some(SIGNATURE),
+            |message| {
+                received.push(message);
+                true
+            },
+        );
+        assert_eq!(ret.unwrap_err(), ::nix::Error::Sys(::nix::errno::Errno::EAGAIN));
+
+        client.send_msg(&bytes[12..], &[]).unwrap();
+        let ret = server.read_messages(
+            |_, _| Some(SIGNATURE),
+            |message| {
+                received.push(message);
+                true
+            },
+        );
+        assert_eq!(ret.unwrap().unwrap(), 1);
+        assert_eq!(received, vec![msg]);
+    }
+
     #[test]
     fn write_read_cycle_fd() {
         let msg = Message {
@@ -552,6 +898,214 @@ mod tests {
         assert_eq!(ret, 1);
     }
 
+    #[test]
+    fn write_read_many_fds() {
+        // more fds than fit in a single socket message
+        let messages = (0..40)
+            .map(|i| Message {
+                sender_id: 42,
+                opcode: 0,
+                args: smallvec![
+                    Argument::Uint(i),
+                    Argument::Fd(1),
+                    Argument::Array(Box::new(vec![0; 200]))
+                ],
+            })
+            .collect::<Vec<_>>();
+
+        let (client, server) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let mut client = BufferedSocket::new(unsafe { Socket::from_raw_fd(client.into_raw_fd()) });
+        let mut server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server.into_raw_fd()) });
+
+        for msg in &messages {
+            client.write_message(msg).unwrap();
+        }
+        client.flush().unwrap();
+        assert_eq!(client.pending_bytes(), 0);
+
+        static SIGNATURE: &'static [ArgumentType] =
+            &[ArgumentType::Uint, ArgumentType::Fd, ArgumentType::Array];
+
+        let mut recv_msgs = Vec::new();
+        while recv_msgs.len() < messages.len() {
+            server
+                .read_messages(
+                    |_, _| Some(SIGNATURE),
+                    |message| {
+                        recv_msgs.push(message);
+                        true
+                    },
+                )
+                .unwrap()
+                .unwrap();
+        }
+
+        assert_eq!(recv_msgs.len(), messages.len());
+        for (msg1, msg2) in messages.iter().zip(recv_msgs.iter()) {
+            assert_eq_msgs(msg1, msg2);
+        }
+    }
+
+    #[test]
+    fn fds_ahead_of_messages() {
+        // each pipe is a distinct file, to check the association of fds and messages
+        let pipes =
+            (0..3 * MAX_FDS_OUT).map(|_| ::nix::unistd::pipe().unwrap()).collect::<Vec<_>>();
+        let messages = pipes
+            .iter()
+            .map(|&(read, _)| Message {
+                sender_id: 42,
+                opcode: 0,
+                args: smallvec![Argument::Fd(read)],
+            })
+            .collect::<Vec<_>>();
+
+        let mut bytes = vec![0u32; 1024];
+        let mut fds = vec![0; 3 * MAX_FDS_OUT];
+        let (mut bytes_len, mut fds_len) = (0, 0);
+        for msg in &messages {
+            let (b, f) =
+                msg.write_to_buffers(&mut bytes[bytes_len..], &mut fds[fds_len..]).unwrap();
+            bytes_len += b;
+            fds_len += f;
+        }
+        let bytes =
+            unsafe { ::std::slice::from_raw_parts(bytes.as_ptr() as *const u8, bytes_len * 4) };
+
+        let (client, server) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let client = unsafe { Socket::from_raw_fd(client.into_raw_fd()) };
+        let mut server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server.into_raw_fd()) });
+
+        // all the fds are sent with the first messages
+        let msg_len = bytes.len() / messages.len();
+        for i in 0..3 {
+            let fds = &fds[i * MAX_FDS_OUT..(i + 1) * MAX_FDS_OUT];
+            client.send_msg(&bytes[i * msg_len..(i + 1) * msg_len], fds).unwrap();
+        }
+        client.send_msg(&bytes[3 * msg_len..], &[]).unwrap();
+
+        static SIGNATURE: &'static [ArgumentType] = &[ArgumentType::Fd];
+
+        let mut recv_msgs = Vec::new();
+        while recv_msgs.len() < messages.len() {
+            server
+                .read_messages(
+                    |_, _| Some(SIGNATURE),
+                    |message| {
+                        recv_msgs.push(message);
+                        // parse a single message per socket message
+                        false
+                    },
+                )
+                .unwrap()
+                .unwrap();
+        }
+
+        for (msg1, msg2) in messages.iter().zip(recv_msgs.iter()) {
+            assert_eq_msgs(msg1, msg2);
+        }
+    }
+
+    #[test]
+    fn withheld_fd() {
+        static SIGNATURE: &[ArgumentType] = &[ArgumentType::Fd];
+        let msg = Message { sender_id: 42, opcode: 0, args: smallvec![Argument::Uint(0)] };
+        let mut words = [0u32; 3];
+        msg.write_to_buffers(&mut words, &mut []).unwrap();
+        let bytes = unsafe { ::std::slice::from_raw_parts(words.as_ptr() as *const u8, 12) };
+
+        let (client, server) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let client = unsafe { Socket::from_raw_fd(client.into_raw_fd()) };
+        let mut server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server.into_raw_fd()) });
+
+        // the data of a message expecting an fd, without the fd, followed by more data
+        client.send_msg(&bytes[..8], &[]).unwrap();
+        for _ in 0..8 {
+            client.send_msg(&[0; MAX_BYTES_OUT], &[]).unwrap();
+        }
+
+        assert!(match server.read_messages(|_, _| Some(SIGNATURE), |_| true) {
+            Ok(Err(MessageParseError::MissingFD)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn unparsed_data_limit() {
+        let msg = Message { sender_id: 42, opcode: 0, args: smallvec![] };
+        let mut words = [0u32; 2];
+        msg.write_to_buffers(&mut words, &mut []).unwrap();
+        let bytes = unsafe { ::std::slice::from_raw_parts(words.as_ptr() as *const u8, 8) };
+
+        let (client, server) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let client = unsafe { Socket::from_raw_fd(client.into_raw_fd()) };
+        let mut server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server.into_raw_fd()) });
+
+        let chunk = bytes.iter().cycle().take(MAX_BYTES_OUT).cloned().collect::<Vec<u8>>();
+        for _ in 0..8 {
+            client.send_msg(&chunk, &[]).unwrap();
+        }
+
+        // the data keeps being received while it is not parsed, up to a limit
+        let mut ret = Ok(());
+        for _ in 0..16 {
+            ret = server.fill_incoming_buffers();
+            if ret.is_err() {
+                break;
+            }
+        }
+        assert_eq!(ret, Err(::nix::Error::Sys(::nix::errno::Errno::EOVERFLOW)));
+        assert!(
+            server.in_data.get_contents().len() * 4 <= DEFAULT_MAX_MESSAGE_SIZE + 2 * MAX_BYTES_OUT
+        );
+
+        // parsing the messages makes room again
+        let ret = server.read_messages(|_, _| Some(&[]), |_| true);
+        assert!(ret.unwrap().unwrap() > 0);
+    }
+
+    #[test]
+    fn partial_flush() {
+        let (client, server) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let mut client = BufferedSocket::new(unsafe { Socket::from_raw_fd(client.into_raw_fd()) });
+        let mut server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server.into_raw_fd()) });
+
+        let message = |i| Message {
+            sender_id: 42,
+            opcode: 0,
+            args: smallvec![Argument::Uint(i), Argument::Array(Box::new(vec![0; 1000]))],
+        };
+
+        // write more than the socket can hold, the messages are still queued
+        let sent = 1024;
+        for i in 0..sent {
+            client.write_message(&message(i)).unwrap();
+        }
+        assert_eq!(client.flush(), Err(::nix::Error::Sys(::nix::errno::Errno::EAGAIN)));
+        assert!(client.pending_bytes() > MAX_OUT_CHUNKS * MAX_BYTES_OUT);
+
+        static SIGNATURE: &'static [ArgumentType] = &[ArgumentType::Uint, ArgumentType::Array];
+
+        // no data was lost or duplicated
+        let mut received = 0;
+        while received < sent {
+            let _ = client.flush();
+            server
+                .read_messages(
+                    |_, _| Some(SIGNATURE),
+                    |msg| {
+                        assert_eq!(msg, message(received));
+                        received += 1;
+                        true
+                    },
+                )
+                .unwrap()
+                .unwrap();
+        }
+        assert_eq!(client.pending_bytes(), 0);
+        assert_eq!(received, sent);
+    }
+
     #[test]
     fn write_read_cycle_multiple() {
         let messages = [
@@ -662,4 +1216,44 @@ mod tests {
 
         assert_eq!(ret, 1);
     }
+
+    #[test]
+    fn large_messages() {
+        let msg = Message {
+            sender_id: 42,
+            opcode: 0,
+            args: smallvec![Argument::Array(vec![7; 20_000].into()), Argument::Uint(3)],
+        };
+        static SIGNATURE: &[ArgumentType] = &[ArgumentType::Array, ArgumentType::Uint];
+
+        let (client, server) = ::std::os::unix::net::UnixStream::pair().unwrap();
+        let mut client = BufferedSocket::new(unsafe { Socket::from_raw_fd(client.into_raw_fd()) });
+        let mut server = BufferedSocket::new(unsafe { Socket::from_raw_fd(server.into_raw_fd()) });
+
+        // the message exceeds the default limit
+        assert_eq!(client.write_message(&msg), Err(::nix::Error::Sys(::nix::errno::Errno::E2BIG)));
+
+        client.set_max_message_size(32_000);
+        client.write_message(&msg).unwrap();
+        client.write_message(&msg).unwrap();
+        client.flush().unwrap();
+
+        // the receiving end rejects it as well, until its limit is raised
+        assert!(match server.read_messages(|_, _| Some(SIGNATURE), |_| true) {
+            Ok(Err(MessageParseError::TooLarge(20_016))) => true,
+            _ => false,
+        });
+        server.set_max_message_size(32_000);
+        let mut received = 0;
+        let ret = server.read_messages(
+            |_, _| Some(SIGNATURE),
+            |message| {
+                assert_eq_msgs(&message, &msg);
+                received += 1;
+                true
+            },
+        );
+        assert_eq!(ret.unwrap().unwrap(), 2);
+        assert_eq!(received, 2);
+    }
 }
@@ -1,14 +1,37 @@
 //! Types and routines used to manipulate arguments from the wire format
+//!
+//! Besides the message types, this module provides a codec independent from any socket:
+//! `parse_header()`, `parse_message()` and `write_message()` convert between messages and
+//! byte buffers in the wire format, and can be used by fuzzers, protocol analyzers or
+//! alternative backends.
 
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr;
+
+#[cfg(not(feature = "std"))]
+use alloc::ffi::CString;
+#[cfg(not(feature = "std"))]
+use core::ffi::CStr;
+#[cfg(feature = "std")]
 use std::ffi::{CStr, CString};
+#[cfg(feature = "std")]
 use std::os::unix::io::RawFd;
-use std::ptr;
 
+#[cfg(feature = "std")]
 use nix::errno::Errno;
+#[cfg(feature = "std")]
 use nix::{Error as NixError, Result as NixResult};
 
 use smallvec::SmallVec;
 
+/// A raw file descriptor, as `std::os::unix::io::RawFd`
+///
+/// Without the `std` feature, file descriptors are only carried as numbers by the messages.
+#[cfg(not(feature = "std"))]
+pub type RawFd = i32;
+
 // The value of 4 is chosen for the following reasons:
 // - almost all messages have 4 arguments or less
 // - there are some potentially spammy events that have 3/4 arguments (wl_touch.move has 4 for example)
@@ -95,8 +118,8 @@ impl Argument {
     }
 }
 
-impl std::fmt::Display for Argument {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for Argument {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Argument::Int(value) => write!(f, "{}", value),
             Argument::Uint(value) => write!(f, "{}", value),
@@ -127,20 +150,28 @@ pub enum MessageWriteError {
     /// The buffer is too small to hold the message contents
     BufferTooSmall,
     /// The message contains a FD that could not be dup-ed
+    #[cfg(feature = "std")]
     DupFdFailed(::nix::Error),
+    /// The message is larger than `MAX_MESSAGE_SIZE`
+    TooLarge,
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for MessageWriteError {}
 
-impl std::fmt::Display for MessageWriteError {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+impl core::fmt::Display for MessageWriteError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
         match *self {
             MessageWriteError::BufferTooSmall => {
                 f.write_str("The provided buffer is too small to hold message content.")
             }
+            #[cfg(feature = "std")]
             MessageWriteError::DupFdFailed(_) => {
                 f.write_str("The message contains a file descriptor that could not be dup()-ed.")
             }
+            MessageWriteError::TooLarge => {
+                f.write_str("The message is too large to be represented on the wire.")
+            }
         }
     }
 }
@@ -154,12 +185,17 @@ pub enum MessageParseError {
     MissingData,
     /// The message is malformed and cannot be parsed
     Malformed,
+    /// The message is well-formed but was rejected by the validation of its contents
+    Invalid(ValidationError),
+    /// The announced size of the message, in bytes, exceeds the maximum size allowed
+    TooLarge(usize),
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for MessageParseError {}
 
-impl std::fmt::Display for MessageParseError {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+impl core::fmt::Display for MessageParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
         match *self {
             MessageParseError::MissingFD => {
                 f.write_str("The message references a FD but the buffer FD is empty.")
@@ -170,6 +206,188 @@ impl std::fmt::Display for MessageParseError {
             MessageParseError::Malformed => {
                 f.write_str("The message is malformed and cannot be parsed")
             }
+            MessageParseError::Invalid(ref e) => write!(f, "The message is invalid: {}", e),
+            MessageParseError::TooLarge(size) => {
+                write!(f, "The message is too large ({} bytes)", size)
+            }
+        }
+    }
+}
+
+/// Validation metadata of an argument of a message
+///
+/// They are only used when decoding messages with `Strictness::Strict`, and are generated
+/// by `wayland-scanner` from the `allow-null` and `enum` attributes of the protocol files.
+#[derive(Copy, Clone, Debug)]
+pub struct ArgumentDesc {
+    /// Whether this argument can be null, only meaningful for strings and objects
+    pub allow_null: bool,
+    /// If this argument is an enum, a function checking whether a value is part of it
+    pub enum_check: Option<fn(u32) -> bool>,
+}
+
+/// Description of an interface, as written in its protocol file
+///
+/// This is meant for the tools handling arbitrary messages, like debuggers, sniffers or code
+/// implementing protocols dynamically, and is available as `Interface::DESCRIPTION`. The
+/// argument and enum metadata is only generated by `wayland-scanner` if requested, and is
+/// otherwise empty.
+#[derive(Copy, Clone, Debug)]
+pub struct InterfaceDesc {
+    /// Name of the interface
+    pub name: &'static str,
+    /// Version of the interface
+    pub version: u32,
+    /// The requests of the interface, indexed by opcode
+    pub requests: &'static [MessageDesc],
+    /// The events of the interface, indexed by opcode
+    pub events: &'static [MessageDesc],
+    /// The arguments of the requests, in the same order as `requests`
+    pub request_args: &'static [&'static [ArgumentInfo]],
+    /// The arguments of the events, in the same order as `events`
+    pub event_args: &'static [&'static [ArgumentInfo]],
+    /// The enums of the interface
+    pub enums: &'static [EnumDesc],
+}
+
+impl InterfaceDesc {
+    /// The interface of the object created by a request, if any
+    ///
+    /// Returns `None` for the requests that do not create an object, or create an object of
+    /// an interface given at runtime, like `wl_registry.bind`.
+    pub fn request_child(&self, opcode: u16) -> Option<&'static str> {
+        child_interface(self.request_args, opcode)
+    }
+
+    /// The interface of the object created by an event, if any
+    pub fn event_child(&self, opcode: u16) -> Option<&'static str> {
+        child_interface(self.event_args, opcode)
+    }
+
+    /// The opcodes of the destructor requests of the interface
+    pub fn destructor_requests(&self) -> impl Iterator<Item = u16> + 'static {
+        destructors(self.requests)
+    }
+
+    /// The opcodes of the destructor events of the interface
+    pub fn destructor_events(&self) -> impl Iterator<Item = u16> + 'static {
+        destructors(self.events)
+    }
+
+    /// Find an enum of the interface by name
+    pub fn find_enum(&self, name: &str) -> Option<&'static EnumDesc> {
+        self.enums.iter().find(|enu| enu.name == name)
+    }
+}
+
+fn child_interface(args: &'static [&'static [ArgumentInfo]], opcode: u16) -> Option<&'static str> {
+    args.get(opcode as usize)?.iter().find(|arg| arg.typ == ArgumentType::NewId)?.interface
+}
+
+fn destructors(messages: &'static [MessageDesc]) -> impl Iterator<Item = u16> + 'static {
+    messages.iter().enumerate().filter(|(_, msg)| msg.destructor).map(|(opcode, _)| opcode as u16)
+}
+
+/// Description of an argument of a message
+#[derive(Copy, Clone, Debug)]
+pub struct ArgumentInfo {
+    /// Name of the argument
+    pub name: &'static str,
+    /// Type of the argument
+    pub typ: ArgumentType,
+    /// Interface of the object, for objects and new ids with a fixed interface
+    pub interface: Option<&'static str>,
+    /// Whether the argument can be null
+    pub allow_null: bool,
+    /// Name of the enum of the argument, as written in the protocol file
+    ///
+    /// It is prefixed with the name of its interface and a dot if it belongs to another one,
+    /// like `wl_output.transform`.
+    pub enum_name: Option<&'static str>,
+}
+
+/// Description of an enum of an interface
+#[derive(Copy, Clone, Debug)]
+pub struct EnumDesc {
+    /// Name of the enum
+    pub name: &'static str,
+    /// Whether the enum is a bitfield
+    pub bitfield: bool,
+    /// The entries of the enum
+    pub entries: &'static [EnumEntry],
+}
+
+impl EnumDesc {
+    /// The name of the entry with a given value, if any
+    pub fn entry_name(&self, value: u32) -> Option<&'static str> {
+        self.entries.iter().find(|entry| entry.value == value).map(|entry| entry.name)
+    }
+}
+
+/// An entry of an enum
+#[derive(Copy, Clone, Debug)]
+pub struct EnumEntry {
+    /// Name of the entry
+    pub name: &'static str,
+    /// Value of the entry
+    pub value: u32,
+    /// Minimum version of the interface for this entry
+    pub since: u32,
+}
+
+/// How strictly the contents of the received messages are validated
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Strictness {
+    /// Only check that the messages match their signature
+    ///
+    /// Strings that are not valid UTF-8 are converted lossily, null strings and objects are
+    /// accepted everywhere, and enum values are only checked by the generated code when
+    /// converting them to their Rust type.
+    Lenient,
+    /// Also validate the contents of the messages
+    ///
+    /// Messages are rejected if they contain strings that are not valid UTF-8, null strings
+    /// or objects where the protocol does not allow them, enum values that are not part of
+    /// their enum, or if the announced size of the message does not match the size of its
+    /// arguments.
+    Strict,
+}
+
+impl Default for Strictness {
+    fn default() -> Strictness {
+        Strictness::Lenient
+    }
+}
+
+/// The reason a message was rejected by `Strictness::Strict`
+///
+/// Arguments are designated by their index in the signature of the message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ValidationError {
+    /// This string argument is not valid UTF-8
+    InvalidUtf8(usize),
+    /// This argument is null, while the protocol does not allow it
+    UnexpectedNull(usize),
+    /// The value of this enum argument is not part of its enum
+    InvalidEnum(usize),
+    /// The message is larger than its arguments
+    TrailingData,
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ValidationError {}
+
+impl core::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
+        match *self {
+            ValidationError::InvalidUtf8(i) => write!(f, "argument {} is not valid UTF-8", i),
+            ValidationError::UnexpectedNull(i) => write!(f, "argument {} can not be null", i),
+            ValidationError::InvalidEnum(i) => {
+                write!(f, "argument {} is not a value of its enum", i)
+            }
+            ValidationError::TrailingData => {
+                f.write_str("the message is larger than its arguments")
+            }
         }
     }
 }
@@ -180,10 +398,23 @@ impl Message {
     /// Returns the number of elements written in each buffer
     ///
     /// Any serialized Fd will be `dup()`-ed in the process
+    ///
+    /// This is only available with the `std` feature, see `write_message()` otherwise.
+    #[cfg(feature = "std")]
     pub fn write_to_buffers<'a, 'b>(
         &self,
         payload: &'a mut [u32],
-        mut fds: &'b mut [RawFd],
+        fds: &'b mut [RawFd],
+    ) -> Result<(usize, usize), MessageWriteError> {
+        self.write_words(payload, fds, true)
+    }
+
+    // serialize the message, `dup()`-ing its fds only if `dup_fds` is set
+    fn write_words(
+        &self,
+        payload: &mut [u32],
+        mut fds: &mut [RawFd],
+        dup_fds: bool,
     ) -> Result<(usize, usize), MessageWriteError> {
         let orig_payload_len = payload.len();
         let orig_fds_len = fds.len();
@@ -226,7 +457,11 @@ impl Message {
 
         // we store all fds we dup-ed in this, which will auto-close
         // them on drop, if any of the `?` early-returns
+        #[cfg(feature = "std")]
         let mut pending_fds = FdStore::new();
+        // fds can only be duplicated with the std feature
+        #[cfg(not(feature = "std"))]
+        debug_assert!(!dup_fds);
 
         // write the contents in the buffer
         for arg in &self.args {
@@ -246,19 +481,31 @@ impl Message {
                 }
                 Argument::Fd(fd) => {
                     let old_fds = fds;
-                    let dup_fd = dup_fd_cloexec(fd).map_err(MessageWriteError::DupFdFailed)?;
-                    pending_fds.push(dup_fd);
-                    fds = write_buf(dup_fd, old_fds)?;
+                    #[cfg(feature = "std")]
+                    let fd = if dup_fds {
+                        let dup_fd = dup_fd_cloexec(fd).map_err(MessageWriteError::DupFdFailed)?;
+                        pending_fds.push(dup_fd);
+                        dup_fd
+                    } else {
+                        fd
+                    };
+                    fds = write_buf(fd, old_fds)?;
                     payload = old_payload;
                 }
             }
         }
 
+        let wrote_size = (free_size - payload.len()) * 4;
+        // the size of the message must fit in the 16 bits of its header
+        if wrote_size > MAX_MESSAGE_SIZE {
+            return Err(MessageWriteError::TooLarge);
+        }
+
         // we reached here, all writing was successful
         // no FD needs to be closed
+        #[cfg(feature = "std")]
         pending_fds.clear();
 
-        let wrote_size = (free_size - payload.len()) * 4;
         header[0] = self.sender_id;
         header[1] = ((wrote_size as u32) << 16) | u32::from(self.opcode);
         Ok((orig_payload_len - payload.len(), orig_fds_len - fds.len()))
@@ -270,28 +517,165 @@ impl Message {
     /// and the unused tail of the buffers is returned. If a single message was present,
     /// the returned slices should thus be empty.
     ///
-    /// Errors if the message is malformed.
+    /// Errors if the message is malformed, see `MessageRef::from_raw()`.
     pub fn from_raw<'a, 'b>(
         raw: &'a [u32],
         signature: &[ArgumentType],
         fds: &'b [RawFd],
     ) -> Result<(Message, &'a [u32], &'b [RawFd]), MessageParseError> {
-        // helper function to read arrays
-        fn read_array_from_payload(
-            array_len: usize,
-            payload: &[u32],
-        ) -> Result<(&[u8], &[u32]), MessageParseError> {
-            let word_len = array_len / 4 + if array_len % 4 != 0 { 1 } else { 0 };
-            if word_len > payload.len() {
-                return Err(MessageParseError::MissingData);
+        let (msg, rest, rest_fds) = MessageRef::from_raw(raw, signature, fds)?;
+        Ok((msg.into_owned(), rest, rest_fds))
+    }
+}
+
+/// An argument of a `MessageRef`, borrowing its contents from the parsed buffer
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ArgumentRef<'a> {
+    /// i32
+    Int(i32),
+    /// u32
+    Uint(u32),
+    /// fixed point, 1/256 precision
+    Fixed(i32),
+    /// CStr
+    ///
+    /// A null string is represented as an empty string.
+    Str(&'a CStr),
+    /// id of a wayland object
+    Object(u32),
+    /// id of a newly created wayland object
+    NewId(u32),
+    /// [u8]
+    Array(&'a [u8]),
+    /// RawFd
+    Fd(RawFd),
+}
+
+impl<'a> ArgumentRef<'a> {
+    /// Retrieve the type of a given argument instance
+    pub fn get_type(&self) -> ArgumentType {
+        match *self {
+            ArgumentRef::Int(_) => ArgumentType::Int,
+            ArgumentRef::Uint(_) => ArgumentType::Uint,
+            ArgumentRef::Fixed(_) => ArgumentType::Fixed,
+            ArgumentRef::Str(_) => ArgumentType::Str,
+            ArgumentRef::Object(_) => ArgumentType::Object,
+            ArgumentRef::NewId(_) => ArgumentType::NewId,
+            ArgumentRef::Array(_) => ArgumentType::Array,
+            ArgumentRef::Fd(_) => ArgumentType::Fd,
+        }
+    }
+
+    /// Copy the contents of this argument into an owned `Argument`
+    pub fn into_owned(self) -> Argument {
+        match self {
+            ArgumentRef::Int(i) => Argument::Int(i),
+            ArgumentRef::Uint(u) => Argument::Uint(u),
+            ArgumentRef::Fixed(f) => Argument::Fixed(f),
+            ArgumentRef::Str(s) => Argument::Str(Box::new(s.into())),
+            ArgumentRef::Object(o) => Argument::Object(o),
+            ArgumentRef::NewId(n) => Argument::NewId(n),
+            ArgumentRef::Array(a) => Argument::Array(Box::new(a.into())),
+            ArgumentRef::Fd(fd) => Argument::Fd(fd),
+        }
+    }
+}
+
+/// A wire message borrowing its arguments from the buffer it was parsed from
+///
+/// Parsing a `MessageRef` does not allocate: the message is validated once when
+/// parsed, and its arguments are decoded on the fly by `args()`.
+#[derive(Copy, Clone, Debug)]
+pub struct MessageRef<'a> {
+    /// ID of the object sending this message
+    pub sender_id: u32,
+    /// Opcode of the message
+    pub opcode: u16,
+    signature: &'a [ArgumentType],
+    payload: &'a [u32],
+    fds: &'a [RawFd],
+}
+
+// a null string is sent as an empty array
+const NULL_STR: &[u8] = b"\0";
+
+// decode an argument of given type from the front of the buffers
+fn decode_argument<'a>(
+    argtype: ArgumentType,
+    payload: &'a [u32],
+    fds: &'a [RawFd],
+) -> Result<(ArgumentRef<'a>, &'a [u32], &'a [RawFd]), MessageParseError> {
+    // helper function to read arrays
+    fn read_array_from_payload(
+        array_len: usize,
+        payload: &[u32],
+    ) -> Result<(&[u8], &[u32]), MessageParseError> {
+        let word_len = array_len / 4 + if array_len % 4 != 0 { 1 } else { 0 };
+        if word_len > payload.len() {
+            return Err(MessageParseError::Malformed);
+        }
+        let (array_contents, rest) = payload.split_at(word_len);
+        let array =
+            unsafe { core::slice::from_raw_parts(array_contents.as_ptr() as *const u8, array_len) };
+        Ok((array, rest))
+    }
+
+    if let ArgumentType::Fd = argtype {
+        // don't consume input but fd
+        return match fds.split_first() {
+            Some((&front, tail)) => Ok((ArgumentRef::Fd(front), payload, tail)),
+            None => Err(MessageParseError::MissingFD),
+        };
+    }
+
+    let (&front, tail) = payload.split_first().ok_or(MessageParseError::Malformed)?;
+    let (arg, tail) = match argtype {
+        ArgumentType::Int => (ArgumentRef::Int(front as i32), tail),
+        ArgumentType::Uint => (ArgumentRef::Uint(front), tail),
+        ArgumentType::Fixed => (ArgumentRef::Fixed(front as i32), tail),
+        ArgumentType::Str => {
+            let (v, rest) = read_array_from_payload(front as usize, tail)?;
+            let v = if v.is_empty() { NULL_STR } else { v };
+            match CStr::from_bytes_with_nul(v) {
+                Ok(s) => (ArgumentRef::Str(s), rest),
+                Err(_) => return Err(MessageParseError::Malformed),
             }
-            let (array_contents, rest) = payload.split_at(word_len);
-            let array = unsafe {
-                ::std::slice::from_raw_parts(array_contents.as_ptr() as *const u8, array_len)
-            };
-            Ok((array, rest))
         }
+        ArgumentType::Object => (ArgumentRef::Object(front), tail),
+        ArgumentType::NewId => (ArgumentRef::NewId(front), tail),
+        ArgumentType::Array => {
+            let (v, rest) = read_array_from_payload(front as usize, tail)?;
+            (ArgumentRef::Array(v), rest)
+        }
+        ArgumentType::Fd => unreachable!(),
+    };
+    Ok((arg, tail, fds))
+}
+
+// check a value against the enum of its argument, if any
+fn check_enum(desc: Option<&ArgumentDesc>, value: u32, i: usize) -> Result<(), ValidationError> {
+    match desc.and_then(|d| d.enum_check) {
+        Some(check) if !check(value) => Err(ValidationError::InvalidEnum(i)),
+        _ => Ok(()),
+    }
+}
 
+impl<'a> MessageRef<'a> {
+    /// Attempts to parse a single wayland message with the given signature.
+    ///
+    /// If the buffers contains several messages, only the first one will be parsed,
+    /// and the unused tail of the buffers is returned. If a single message was present,
+    /// the returned slices should thus be empty.
+    ///
+    /// Returns `MessageParseError::MissingData` if the buffer does not contain the whole
+    /// message, in which case parsing can be retried once more data is available, and
+    /// `MessageParseError::Malformed` if the contents of the message do not match the
+    /// signature.
+    pub fn from_raw<'r: 'a, 'f: 'a>(
+        raw: &'r [u32],
+        signature: &'a [ArgumentType],
+        fds: &'f [RawFd],
+    ) -> Result<(MessageRef<'a>, &'r [u32], &'f [RawFd]), MessageParseError> {
         if raw.len() < 2 {
             return Err(MessageParseError::MissingData);
         }
@@ -301,62 +685,235 @@ impl Message {
         let opcode = (word_2 & 0x0000_FFFF) as u16;
         let len = (word_2 >> 16) as usize / 4;
 
-        if len < 2 || len > raw.len() {
+        if len < 2 {
             return Err(MessageParseError::Malformed);
         }
+        if len > raw.len() {
+            // the rest of the message has not been received yet
+            return Err(MessageParseError::MissingData);
+        }
 
-        let (mut payload, rest) = raw.split_at(len);
-        payload = &payload[2..];
-        let mut fds = fds;
-
-        let arguments = signature
-            .iter()
-            .map(|argtype| {
-                if let ArgumentType::Fd = *argtype {
-                    // don't consume input but fd
-                    if let Some((&front, tail)) = fds.split_first() {
-                        fds = tail;
-                        Ok(Argument::Fd(front))
-                    } else {
-                        Err(MessageParseError::MissingFD)
-                    }
-                } else if let Some((&front, mut tail)) = payload.split_first() {
-                    let arg = match *argtype {
-                        ArgumentType::Int => Ok(Argument::Int(front as i32)),
-                        ArgumentType::Uint => Ok(Argument::Uint(front)),
-                        ArgumentType::Fixed => Ok(Argument::Fixed(front as i32)),
-                        ArgumentType::Str => read_array_from_payload(front as usize, tail)
-                            .and_then(|(v, rest)| {
-                                tail = rest;
-                                match CStr::from_bytes_with_nul(v) {
-                                    Ok(s) => Ok(Argument::Str(Box::new(s.into()))),
-                                    Err(_) => Err(MessageParseError::Malformed),
-                                }
-                            }),
-                        ArgumentType::Object => Ok(Argument::Object(front)),
-                        ArgumentType::NewId => Ok(Argument::NewId(front)),
-                        ArgumentType::Array => {
-                            read_array_from_payload(front as usize, tail).map(|(v, rest)| {
-                                tail = rest;
-                                Argument::Array(Box::new(v.into()))
-                            })
-                        }
-                        ArgumentType::Fd => unreachable!(),
-                    };
-                    payload = tail;
-                    arg
-                } else {
-                    Err(MessageParseError::MissingData)
+        let (payload, rest) = raw.split_at(len);
+        let fd_count = signature.iter().filter(|&&t| t == ArgumentType::Fd).count();
+        if fd_count > fds.len() {
+            return Err(MessageParseError::MissingFD);
+        }
+        let (msg_fds, rest_fds) = fds.split_at(fd_count);
+
+        let msg = MessageRef { sender_id, opcode, signature, payload: &payload[2..], fds: msg_fds };
+
+        // validate the contents once, so that `args()` can not fail
+        let (mut payload, mut fds) = (msg.payload, msg.fds);
+        for &argtype in signature {
+            let (_, p, f) = decode_argument(argtype, payload, fds)?;
+            payload = p;
+            fds = f;
+        }
+
+        Ok((msg, rest, rest_fds))
+    }
+
+    /// Validate the contents of this message
+    ///
+    /// `args` are the validation metadata of the arguments of the message, if they are not
+    /// available (`args` is shorter than the signature), the arguments are only checked for
+    /// UTF-8 validity. This always succeeds with `Strictness::Lenient`.
+    pub fn validate(
+        &self,
+        args: &[ArgumentDesc],
+        strictness: Strictness,
+    ) -> Result<(), ValidationError> {
+        if strictness == Strictness::Lenient {
+            return Ok(());
+        }
+        let (mut payload, mut fds) = (self.payload, self.fds);
+        for (i, &argtype) in self.signature.iter().enumerate() {
+            let desc = args.get(i);
+            let allow_null = desc.map(|d| d.allow_null).unwrap_or(true);
+            // a null string has a length of 0, unlike the empty string
+            if argtype == ArgumentType::Str && payload.first() == Some(&0) && !allow_null {
+                return Err(ValidationError::UnexpectedNull(i));
+            }
+            // the message was validated when parsed
+            let (arg, p, f) = decode_argument(argtype, payload, fds)
+                .map_err(|_| ValidationError::TrailingData)?;
+            match arg {
+                ArgumentRef::Str(s) if s.to_str().is_err() => {
+                    return Err(ValidationError::InvalidUtf8(i));
                 }
-            })
-            .collect::<Result<SmallVec<_>, MessageParseError>>()?;
+                ArgumentRef::Object(0) if !allow_null => {
+                    return Err(ValidationError::UnexpectedNull(i));
+                }
+                ArgumentRef::Int(v) => check_enum(desc, v as u32, i)?,
+                ArgumentRef::Uint(v) => check_enum(desc, v, i)?,
+                _ => {}
+            }
+            payload = p;
+            fds = f;
+        }
+        if !payload.is_empty() {
+            return Err(ValidationError::TrailingData);
+        }
+        Ok(())
+    }
+
+    /// The arguments of this message
+    pub fn args(&self) -> ArgumentsRef<'a> {
+        ArgumentsRef { signature: self.signature, payload: self.payload, fds: self.fds }
+    }
 
-        let msg = Message { sender_id, opcode, args: arguments };
-        Ok((msg, rest, fds))
+    /// Copy the contents of this message into an owned `Message`
+    pub fn into_owned(self) -> Message {
+        Message {
+            sender_id: self.sender_id,
+            opcode: self.opcode,
+            args: self.args().map(ArgumentRef::into_owned).collect(),
+        }
     }
 }
 
+/// Iterator over the arguments of a `MessageRef`
+///
+/// As returned by `MessageRef::args()`.
+#[derive(Clone, Debug)]
+pub struct ArgumentsRef<'a> {
+    signature: &'a [ArgumentType],
+    payload: &'a [u32],
+    fds: &'a [RawFd],
+}
+
+impl<'a> Iterator for ArgumentsRef<'a> {
+    type Item = ArgumentRef<'a>;
+
+    fn next(&mut self) -> Option<ArgumentRef<'a>> {
+        let (&argtype, signature) = self.signature.split_first()?;
+        // the message was validated when parsed
+        let (arg, payload, fds) = decode_argument(argtype, self.payload, self.fds).ok()?;
+        self.signature = signature;
+        self.payload = payload;
+        self.fds = fds;
+        Some(arg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.signature.len(), Some(self.signature.len()))
+    }
+}
+
+impl<'a> ExactSizeIterator for ArgumentsRef<'a> {}
+
+/// Size of the header of a wire message, in bytes
+pub const HEADER_SIZE: usize = 8;
+
+/// Maximum size of a wire message, in bytes
+///
+/// The size of a message is encoded on 16 bits in its header, and is a multiple of 4.
+pub const MAX_MESSAGE_SIZE: usize = 0xFFFC;
+
+/// The header of a wire message
+///
+/// As returned by `parse_header()`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MessageHeader {
+    /// ID of the object sending this message
+    pub sender_id: u32,
+    /// Opcode of the message
+    pub opcode: u16,
+    /// Size of the whole message in bytes, header included
+    pub size: usize,
+}
+
+// read a native-endian word from a 4-bytes slice
+fn read_word(bytes: &[u8]) -> u32 {
+    u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Parse the header of the message at the front of a byte buffer
+///
+/// This is enough to know which object the message is addressed to and which
+/// `MessageDesc` describes it, before calling `parse_message()`.
+///
+/// Returns `MessageParseError::MissingData` if the buffer is shorter than `HEADER_SIZE`,
+/// and `MessageParseError::Malformed` if the announced size is smaller than the header
+/// or not a multiple of 4. The buffer does not need to contain the whole message.
+pub fn parse_header(bytes: &[u8]) -> Result<MessageHeader, MessageParseError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(MessageParseError::MissingData);
+    }
+    let sender_id = read_word(&bytes[0..4]);
+    let word_2 = read_word(&bytes[4..8]);
+    let size = (word_2 >> 16) as usize;
+    if size < HEADER_SIZE || size % 4 != 0 {
+        return Err(MessageParseError::Malformed);
+    }
+    Ok(MessageHeader { sender_id, opcode: (word_2 & 0x0000_FFFF) as u16, size })
+}
+
+/// Parse a single message from a byte buffer and its accompanying file descriptors
+///
+/// `bytes` holds the message in the wire format, as read from a wayland socket in the
+/// native endianness, and `fds` the file descriptors received alongside it. No alignment
+/// is required from `bytes`, and no socket is involved: this is the codec used by
+/// `BufferedSocket`, exposed for fuzzers, protocol analyzers or alternative backends.
+///
+/// If the buffers contain several messages only the first one is parsed, and the number
+/// of bytes and of fds it used is returned along with it, so that the next message can be
+/// parsed from the remainder of the buffers. The fds are returned as-is in the message,
+/// and thus still owned by the caller.
+///
+/// Returns `MessageParseError::MissingData` if the buffer does not contain the whole
+/// message, `MessageParseError::MissingFD` if not enough fds are provided, and
+/// `MessageParseError::Malformed` if the contents of the message do not match the
+/// signature of `desc`.
+pub fn parse_message(
+    bytes: &[u8],
+    fds: &[RawFd],
+    desc: &MessageDesc,
+) -> Result<(Message, usize, usize), MessageParseError> {
+    let header = parse_header(bytes)?;
+    if header.size > bytes.len() {
+        return Err(MessageParseError::MissingData);
+    }
+    let words: SmallVec<[u32; 32]> = bytes[..header.size].chunks(4).map(read_word).collect();
+    let (msg, _, rest_fds) = Message::from_raw(&words, desc.signature, fds)?;
+    Ok((msg, header.size, fds.len() - rest_fds.len()))
+}
+
+/// Serialize a message at the end of a byte buffer
+///
+/// This is the counterpart of `parse_message()`: the message is appended to `bytes`
+/// in the wire format, and the file descriptors it contains to `fds`. Unlike
+/// `Message::write_to_buffers()`, the fds are not `dup()`-ed and remain owned by the caller.
+///
+/// On error, the buffers are left untouched.
+pub fn write_message(
+    msg: &Message,
+    bytes: &mut Vec<u8>,
+    fds: &mut Vec<RawFd>,
+) -> Result<(), MessageWriteError> {
+    let mut word_len = 2;
+    let mut fd_len = 0;
+    for arg in &msg.args {
+        match *arg {
+            Argument::Str(ref s) => word_len += 1 + (s.as_bytes_with_nul().len() + 3) / 4,
+            Argument::Array(ref a) => word_len += 1 + (a.len() + 3) / 4,
+            Argument::Fd(_) => fd_len += 1,
+            _ => word_len += 1,
+        }
+    }
+    let mut words = vec![0u32; word_len];
+    let mut msg_fds = vec![0; fd_len];
+    msg.write_words(&mut words, &mut msg_fds, false)?;
+    bytes.reserve(word_len * 4);
+    for word in words {
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    fds.extend_from_slice(&msg_fds);
+    Ok(())
+}
+
 /// Duplicate a `RawFd` and set the CLOEXEC flag on the copy
+#[cfg(feature = "std")]
 pub fn dup_fd_cloexec(fd: RawFd) -> NixResult<RawFd> {
     use nix::fcntl;
     match fcntl::fcntl(fd, fcntl::FcntlArg::F_DUPFD_CLOEXEC(0)) {
@@ -390,10 +947,12 @@ pub fn dup_fd_cloexec(fd: RawFd) -> NixResult<RawFd> {
  * utility struct that closes every FD it contains on drop
  */
 
+#[cfg(feature = "std")]
 struct FdStore {
     fds: Vec<RawFd>,
 }
 
+#[cfg(feature = "std")]
 impl FdStore {
     fn new() -> FdStore {
         FdStore { fds: Vec::new() }
@@ -406,6 +965,7 @@ impl FdStore {
     }
 }
 
+#[cfg(feature = "std")]
 impl Drop for FdStore {
     fn drop(&mut self) {
         use nix::unistd::close;
@@ -458,4 +1018,151 @@ mod tests {
         .unwrap();
         assert_eq!(rebuilt, msg);
     }
+
+    #[test]
+    fn message_ref_borrows_buffer() {
+        let mut bytes_buffer = vec![0; 1024];
+        let mut fd_buffer = vec![0; 10];
+
+        let msg = Message {
+            sender_id: 42,
+            opcode: 7,
+            args: smallvec![
+                Argument::Str(Box::new(CString::new(&b"I like trains!"[..]).unwrap())),
+                Argument::Array(vec![1, 2, 3, 4, 5].into()),
+                Argument::Uint(3),
+            ],
+        };
+        let (len, _) = msg.write_to_buffers(&mut bytes_buffer[..], &mut fd_buffer[..]).unwrap();
+        let signature = [ArgumentType::Str, ArgumentType::Array, ArgumentType::Uint];
+
+        let (msg_ref, rest, _) =
+            MessageRef::from_raw(&bytes_buffer[..len], &signature, &fd_buffer[..0]).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!((msg_ref.sender_id, msg_ref.opcode), (42, 7));
+        let args = msg_ref.args().collect::<Vec<_>>();
+        assert_eq!(args.len(), 3);
+        match args[1] {
+            ArgumentRef::Array(a) => {
+                assert_eq!(a, &[1, 2, 3, 4, 5]);
+                // the contents are not copied
+                let start = bytes_buffer.as_ptr() as usize;
+                let ptr = a.as_ptr() as usize;
+                assert!(ptr >= start && ptr < start + len * 4);
+            }
+            other => panic!("Unexpected argument {:?}", other),
+        }
+        assert_eq!(msg_ref.into_owned(), msg);
+
+        // an incomplete message can be parsed once completed
+        assert!(match MessageRef::from_raw(&bytes_buffer[..len - 1], &signature, &[]) {
+            Err(MessageParseError::MissingData) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn null_string() {
+        // a null string is sent as an empty array
+        let raw = [1, (16 << 16) | 2, 0, 12];
+        let signature = [ArgumentType::Str, ArgumentType::Uint];
+        let (msg, _, _) = MessageRef::from_raw(&raw[..], &signature, &[]).unwrap();
+        let args = msg.args().collect::<Vec<_>>();
+        assert_eq!(args, vec![ArgumentRef::Str(Default::default()), ArgumentRef::Uint(12)]);
+    }
+
+    #[test]
+    fn parse_write_message_cycle() {
+        static DESC: MessageDesc = MessageDesc {
+            name: "test",
+            signature: &[
+                ArgumentType::Str,
+                ArgumentType::Fd,
+                ArgumentType::Array,
+                ArgumentType::Int,
+            ],
+            since: 1,
+            destructor: false,
+        };
+        let msg = Message {
+            sender_id: 3,
+            opcode: 1,
+            args: smallvec![
+                Argument::Str(Box::new(CString::new(&b"hello"[..]).unwrap())),
+                Argument::Fd(12),
+                Argument::Array(vec![1, 2, 3].into()),
+                Argument::Int(-4),
+            ],
+        };
+        let mut bytes = vec![0xFF];
+        let mut fds = Vec::new();
+        write_message(&msg, &mut bytes, &mut fds).unwrap();
+        write_message(&msg, &mut bytes, &mut fds).unwrap();
+        // the fd is not dup()-ed
+        assert_eq!(fds, vec![12, 12]);
+
+        // parse from a misaligned buffer
+        let header = parse_header(&bytes[1..]).unwrap();
+        assert_eq!(header, MessageHeader { sender_id: 3, opcode: 1, size: 32 });
+        let (rebuilt, read_bytes, read_fds) = parse_message(&bytes[1..], &fds, &DESC).unwrap();
+        assert_eq!(rebuilt, msg);
+        assert_eq!((read_bytes, read_fds), (32, 1));
+        let (rebuilt, _, _) = parse_message(&bytes[33..], &fds[1..], &DESC).unwrap();
+        assert_eq!(rebuilt, msg);
+
+        // incomplete messages
+        assert!(match parse_message(&bytes[1..20], &fds, &DESC) {
+            Err(MessageParseError::MissingData) => true,
+            _ => false,
+        });
+        assert!(match parse_message(&bytes[1..5], &fds, &DESC) {
+            Err(MessageParseError::MissingData) => true,
+            _ => false,
+        });
+        match parse_message(&bytes[1..], &[], &DESC) {
+            Err(MessageParseError::MissingFD) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_malformed_header() {
+        let mut bytes = Vec::new();
+        // size of 6 bytes, smaller than the header
+        bytes.extend_from_slice(&1u32.to_ne_bytes());
+        bytes.extend_from_slice(&(6u32 << 16).to_ne_bytes());
+        match parse_header(&bytes) {
+            Err(MessageParseError::Malformed) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strict_validation() {
+        let signature = [ArgumentType::Str, ArgumentType::Object, ArgumentType::Uint];
+        let args = [
+            ArgumentDesc { allow_null: false, enum_check: None },
+            ArgumentDesc { allow_null: true, enum_check: None },
+            ArgumentDesc { allow_null: false, enum_check: Some(|v| v < 3) },
+        ];
+        let validate = |words: &[u32]| {
+            let (msg, _, _) = MessageRef::from_raw(words, &signature, &[]).unwrap();
+            assert_eq!(msg.validate(&args, Strictness::Lenient), Ok(()));
+            msg.validate(&args, Strictness::Strict)
+        };
+        let size = |words: u32| words << 18;
+
+        // "ab", null object, 2
+        let ab = u32::from_ne_bytes([b'a', b'b', 0, 0]);
+        assert_eq!(validate(&[1, size(6), 3, ab, 0, 2]), Ok(()));
+        // not valid UTF-8
+        let invalid = u32::from_ne_bytes([0xFF, b'b', 0, 0]);
+        assert_eq!(validate(&[1, size(6), 3, invalid, 0, 2]), Err(ValidationError::InvalidUtf8(0)));
+        // null string
+        assert_eq!(validate(&[1, size(5), 0, 0, 2]), Err(ValidationError::UnexpectedNull(0)));
+        // not a value of the enum
+        assert_eq!(validate(&[1, size(6), 3, ab, 0, 3]), Err(ValidationError::InvalidEnum(2)));
+        // trailing word
+        assert_eq!(validate(&[1, size(7), 3, ab, 0, 2, 0]), Err(ValidationError::TrailingData));
+    }
 }
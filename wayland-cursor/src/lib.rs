@@ -4,6 +4,11 @@
 //!
 //! It allows you to load cursors from the system and display them correctly.
 //!
+//! It does not depend on `libwayland-cursor`: XCursor files are parsed in Rust, themes
+//! are looked up following the XDG cursor theme specification (including the themes
+//! they `Inherits=` from), and the images are written to a `wl_shm` pool owned by
+//! the `CursorTheme`.
+//!
 //! First of all, you need to create a `CursorTheme`,
 //! which represents the full cursor theme.
 //!
@@ -13,6 +18,11 @@
 //! what time, as well as handles to the buffers containing these frames, to
 //! attach them to a wayland surface.
 //!
+//! On HiDPI outputs, tell the theme which scale factors you need with `set_scales`: each cursor
+//! is then loaded at `size * scale` for all of them, and `Cursor::image_for_scale` gives you the
+//! image to use on an output of a given scale. `CursorImageBuffer::attach_to` attaches it to
+//! your cursor surface with the matching `wl_surface.set_buffer_scale`.
+//!
 //! # Example
 //!
 //! ```ignore
@@ -56,6 +66,7 @@ use {nix::sys::memfd, std::ffi::CStr};
 use wayland_client::protocol::wl_buffer::WlBuffer;
 use wayland_client::protocol::wl_shm::{Format, WlShm};
 use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::protocol::wl_surface::WlSurface;
 use wayland_client::{Attached, Main};
 
 use xcursor::parser as xparser;
@@ -65,8 +76,10 @@ use xparser::Image as XCursorImage;
 /// Represents a cursor theme loaded from the system.
 pub struct CursorTheme {
     name: String,
+    theme: XCursorTheme,
     cursors: Vec<Cursor>,
     size: u32,
+    scales: Vec<u32>,
     pool: Main<WlShmPool>,
     pool_size: i32,
     file: File,
@@ -117,9 +130,65 @@ impl CursorTheme {
 
         let pool = shm.create_pool(file.as_raw_fd(), INITIAL_POOL_SIZE);
 
+        // Resolve the theme directories and its inheritance chain once and for all
+        let theme = XCursorTheme::load(name);
         let name = String::from(name);
 
-        CursorTheme { name, file, size, pool, pool_size: INITIAL_POOL_SIZE, cursors: Vec::new() }
+        CursorTheme {
+            name,
+            theme,
+            file,
+            size,
+            scales: vec![1],
+            pool,
+            pool_size: INITIAL_POOL_SIZE,
+            cursors: Vec::new(),
+        }
+    }
+
+    /// Name of this theme
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the output scale factors cursors should be loaded for
+    ///
+    /// Each cursor is loaded with images of size `size * scale` for each of these scales,
+    /// in addition to the images of scale `1`, which are always loaded. The cursors that were
+    /// already retrieved from this theme are completed with the images for the new scales.
+    ///
+    /// Scales of `0` are ignored.
+    pub fn set_scales(&mut self, scales: &[u32]) {
+        for &scale in scales {
+            if scale != 0 && !self.scales.contains(&scale) {
+                self.scales.push(scale);
+            }
+        }
+        self.scales.sort_unstable();
+
+        for i in 0..self.cursors.len() {
+            let missing = self
+                .scales
+                .iter()
+                .cloned()
+                .filter(|&scale| self.cursors[i].sets.iter().all(|set| set.scale != scale))
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                continue;
+            }
+            let name = self.cursors[i].name.clone();
+            if let Some(images) = self.load_images(&name) {
+                for scale in missing {
+                    let set = ImageSet::new(self, &images, self.size, scale);
+                    self.cursors[i].insert_set(set);
+                }
+            }
+        }
+    }
+
+    /// The output scale factors cursors are loaded for
+    pub fn scales(&self) -> &[u32] {
+        &self.scales
     }
 
     /// Retrieve a cursor from the theme.
@@ -142,16 +211,18 @@ impl CursorTheme {
     /// Keep in mind that if the cursor is already loaded,
     /// the function will make a duplicate.
     fn load_cursor(&mut self, name: &str, size: u32) -> Option<Cursor> {
-        let icon_path = XCursorTheme::load(&self.name).load_icon(name)?;
+        let images = self.load_images(name)?;
+        Some(Cursor::new(name, self, &images, size))
+    }
+
+    /// Find the file of a cursor in the theme, and parse its images
+    fn load_images(&self, name: &str) -> Option<Vec<XCursorImage>> {
+        let icon_path = self.theme.load_icon(name)?;
         let mut icon_file = File::open(icon_path).ok()?;
 
         let mut buf = Vec::new();
-        let images = {
-            icon_file.read_to_end(&mut buf).ok()?;
-            xparser::parse_xcursor(&buf)?
-        };
-
-        Some(Cursor::new(name, self, &images, size))
+        icon_file.read_to_end(&mut buf).ok()?;
+        xparser::parse_xcursor(&buf)
     }
 
     /// Grow the wl_shm_pool this theme is stored on.
@@ -167,30 +238,68 @@ impl CursorTheme {
 }
 
 /// A cursor from a theme. Can contain several images if animated.
+///
+/// The cursor holds a set of images for each scale factor requested with
+/// `CursorTheme::set_scales`. Indexing it and `frame_and_duration` use the images of scale `1`.
 #[derive(Clone)]
 pub struct Cursor {
     name: String,
+    // sorted by scale, the first one is always of scale 1
+    sets: Vec<ImageSet>,
+}
+
+/// The images of a cursor for a given scale factor
+#[derive(Clone)]
+struct ImageSet {
+    scale: u32,
     images: Vec<CursorImageBuffer>,
     total_duration: u32,
 }
 
-impl Cursor {
-    /// Construct a new Cursor.
+impl ImageSet {
+    /// Write the images of size `size * scale` into `theme`.
     ///
-    /// Each of the provided images will be written into `theme`.
     /// This will also grow `theme.pool` if necessary.
-    fn new(name: &str, theme: &mut CursorTheme, images: &[XCursorImage], size: u32) -> Self {
+    fn new(theme: &mut CursorTheme, images: &[XCursorImage], size: u32, scale: u32) -> Self {
         let mut total_duration = 0;
-        let images: Vec<CursorImageBuffer> = Cursor::nearest_images(size, images)
+        let images: Vec<CursorImageBuffer> = Cursor::nearest_images(size * scale, images)
             .map(|image| {
-                let buffer = CursorImageBuffer::new(theme, image);
+                let buffer = CursorImageBuffer::new(theme, image, scale);
                 total_duration += buffer.delay;
 
                 buffer
             })
             .collect();
 
-        Cursor { total_duration, name: String::from(name), images }
+        ImageSet { scale, images, total_duration }
+    }
+
+    fn frame_and_duration(&self, millis: u32) -> FrameAndDuration {
+        frame_and_duration(self.images.iter().map(|img| img.delay), self.total_duration, millis)
+    }
+}
+
+impl Cursor {
+    /// Construct a new Cursor.
+    ///
+    /// The images for each of the scales of `theme` will be written into it.
+    fn new(name: &str, theme: &mut CursorTheme, images: &[XCursorImage], size: u32) -> Self {
+        let mut sets = Vec::with_capacity(theme.scales.len());
+        for scale in theme.scales.clone() {
+            sets.push(ImageSet::new(theme, images, size, scale));
+        }
+
+        Cursor { name: String::from(name), sets }
+    }
+
+    fn insert_set(&mut self, set: ImageSet) {
+        let pos = self.sets.iter().position(|s| s.scale > set.scale).unwrap_or(self.sets.len());
+        self.sets.insert(pos, set);
+    }
+
+    fn set_for_scale(&self, scale: u32) -> &ImageSet {
+        let scales = self.sets.iter().map(|set| set.scale).collect::<Vec<_>>();
+        &self.sets[best_scale(&scales, scale)]
     }
 
     fn nearest_images(size: u32, images: &[XCursorImage]) -> impl Iterator<Item = &XCursorImage> {
@@ -215,24 +324,48 @@ impl Cursor {
     ///
     /// Time will wrap, so if for instance the cursor has an animation during 100ms,
     /// then calling this function with 5ms and 105ms as input gives the same output.
-    pub fn frame_and_duration(&self, mut millis: u32) -> FrameAndDuration {
-        millis %= self.total_duration;
-
-        let mut res = 0;
-        for (i, img) in self.images.iter().enumerate() {
-            if millis < img.delay {
-                res = i;
-                break;
-            }
-            millis -= img.delay;
-        }
+    ///
+    /// For a cursor that is not animated, the duration is `0`.
+    pub fn frame_and_duration(&self, millis: u32) -> FrameAndDuration {
+        self.sets[0].frame_and_duration(millis)
+    }
 
-        FrameAndDuration { frame_index: res, frame_duration: millis }
+    /// Whether this cursor is animated
+    pub fn is_animated(&self) -> bool {
+        self.sets[0].images.len() > 1 && self.sets[0].total_duration > 0
     }
 
     /// Total number of images forming this cursor animation
     pub fn image_count(&self) -> usize {
-        self.images.len()
+        self.sets[0].images.len()
+    }
+
+    /// The scale factors this cursor has images for
+    pub fn scales(&self) -> impl Iterator<Item = u32> + '_ {
+        self.sets.iter().map(|set| set.scale)
+    }
+
+    /// The first image of this cursor to display on an output of given scale
+    ///
+    /// If the cursor was not loaded for this scale, the images of the largest loaded scale
+    /// below it are used, or of the smallest one if there is none. The scale of the returned
+    /// image is given by `CursorImageBuffer::buffer_scale()`.
+    pub fn image_for_scale(&self, scale: u32) -> &CursorImageBuffer {
+        &self.set_for_scale(scale).images[0]
+    }
+
+    /// Same as `frame_and_duration`, but for an output of given scale
+    ///
+    /// The fallback rules of `image_for_scale` apply. Images of different scales can have
+    /// different timings, hence the image to display is returned along the frame information.
+    pub fn frame_for_scale(
+        &self,
+        scale: u32,
+        millis: u32,
+    ) -> (&CursorImageBuffer, FrameAndDuration) {
+        let set = self.set_for_scale(scale);
+        let frame = set.frame_and_duration(millis);
+        (&set.images[frame.frame_index], frame)
     }
 }
 
@@ -240,10 +373,15 @@ impl Index<usize> for Cursor {
     type Output = CursorImageBuffer;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.images[index]
+        &self.sets[0].images[index]
     }
 }
 
+/// Index of the scale to use for `target` among `scales`, which is sorted and not empty
+fn best_scale(scales: &[u32], target: u32) -> usize {
+    scales.iter().rposition(|&scale| scale <= target).unwrap_or(0)
+}
+
 /// A buffer containing a cursor image.
 ///
 /// You can access the `WlBuffer` via `Deref`.
@@ -258,6 +396,7 @@ pub struct CursorImageBuffer {
     yhot: u32,
     width: u32,
     height: u32,
+    scale: u32,
 }
 
 impl CursorImageBuffer {
@@ -265,7 +404,7 @@ impl CursorImageBuffer {
     ///
     /// This function appends the pixels of the image to the provided file,
     /// and constructs a wl_buffer on that data.
-    fn new(theme: &mut CursorTheme, image: &XCursorImage) -> Self {
+    fn new(theme: &mut CursorTheme, image: &XCursorImage, scale: u32) -> Self {
         let buf = &image.pixels_rgba;
         let offset = theme.file.seek(SeekFrom::End(0)).unwrap();
 
@@ -291,6 +430,7 @@ impl CursorImageBuffer {
             yhot: image.yhot,
             width: image.width,
             height: image.height,
+            scale,
         }
     }
 
@@ -300,10 +440,39 @@ impl CursorImageBuffer {
     }
 
     /// Location of the pointer hotspot in this image
+    ///
+    /// This is in buffer pixels, see `surface_hotspot` for the value to give to
+    /// `wl_pointer.set_cursor`.
     pub fn hotspot(&self) -> (u32, u32) {
         (self.xhot, self.yhot)
     }
 
+    /// Location of the pointer hotspot in surface coordinates
+    ///
+    /// This is the hotspot divided by the buffer scale.
+    pub fn surface_hotspot(&self) -> (u32, u32) {
+        (self.xhot / self.scale, self.yhot / self.scale)
+    }
+
+    /// The scale factor this image was loaded for
+    ///
+    /// This is the value to set with `wl_surface.set_buffer_scale` on the surface
+    /// displaying it.
+    pub fn buffer_scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Attach this image to a surface, setting its buffer scale accordingly
+    ///
+    /// The buffer scale is only set if the surface is of version 3 or more, and the surface
+    /// is not committed.
+    pub fn attach_to(&self, surface: &WlSurface) {
+        if surface.as_ref().version() >= 3 {
+            surface.set_buffer_scale(self.scale as i32);
+        }
+        surface.attach(Some(&self.buffer), 0, 0);
+    }
+
     /// Time (in milliseconds) for which this image should be displayed
     pub fn delay(&self) -> u32 {
         self.delay
@@ -318,6 +487,106 @@ impl Deref for CursorImageBuffer {
     }
 }
 
+fn frame_and_duration<I: Iterator<Item = u32>>(
+    delays: I,
+    total_duration: u32,
+    millis: u32,
+) -> FrameAndDuration {
+    if total_duration == 0 {
+        return FrameAndDuration { frame_index: 0, frame_duration: 0 };
+    }
+
+    let mut millis = millis % total_duration;
+    for (i, delay) in delays.enumerate() {
+        if millis < delay {
+            return FrameAndDuration { frame_index: i, frame_duration: delay - millis };
+        }
+        millis -= delay;
+    }
+
+    // unreachable as long as total_duration is the sum of the delays
+    FrameAndDuration { frame_index: 0, frame_duration: 0 }
+}
+
+/// A helper to drive the animation of a cursor
+///
+/// Request a `wl_surface.frame` callback on the cursor surface each time you commit it,
+/// and feed the timestamp it provides to `update()`:
+///
+/// ```ignore
+/// let mut animator = CursorAnimator::new(cursor.clone());
+/// cursor_surface.attach(Some(&animator.current()), 0, 0);
+/// cursor_surface.frame().quick_assign(/* ... */);
+/// cursor_surface.commit();
+///
+/// // in the frame callback:
+/// if let Some(buffer) = animator.update(time) {
+///     cursor_surface.attach(Some(&buffer), 0, 0);
+///     cursor_surface.damage_buffer(0, 0, i32::MAX, i32::MAX);
+/// }
+/// // request the next frame callback and commit
+/// ```
+#[derive(Clone)]
+pub struct CursorAnimator {
+    cursor: Cursor,
+    start: Option<u32>,
+    current: usize,
+    scale: u32,
+}
+
+impl CursorAnimator {
+    /// Create a new animator, starting on the first frame of the cursor
+    pub fn new(cursor: Cursor) -> CursorAnimator {
+        CursorAnimator { cursor, start: None, current: 0, scale: 1 }
+    }
+
+    /// Set the scale of the output the cursor is displayed on
+    ///
+    /// Returns the image to attach with this scale, the animation continues where it was.
+    pub fn set_scale(&mut self, scale: u32) -> &CursorImageBuffer {
+        self.scale = scale;
+        let set = self.cursor.set_for_scale(scale);
+        if self.current >= set.images.len() {
+            self.current = 0;
+        }
+        &set.images[self.current]
+    }
+
+    /// The cursor animated by this animator
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    /// The image currently displayed
+    pub fn current(&self) -> &CursorImageBuffer {
+        &self.cursor.set_for_scale(self.scale).images[self.current]
+    }
+
+    /// Advance the animation to the given time, in milliseconds
+    ///
+    /// This is intended to receive the timestamp of `wl_callback.done` events of the
+    /// cursor surface's frame callbacks. The first call sets the start of the animation.
+    ///
+    /// Returns the new image to attach if the frame changed.
+    pub fn update(&mut self, time: u32) -> Option<&CursorImageBuffer> {
+        let start = *self.start.get_or_insert(time);
+        let set = self.cursor.set_for_scale(self.scale);
+        let frame = set.frame_and_duration(time.wrapping_sub(start)).frame_index;
+        if frame != self.current {
+            self.current = frame;
+            Some(&set.images[frame])
+        } else {
+            None
+        }
+    }
+
+    /// Restart the animation from its first frame at the next `update()`
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.current = 0;
+    }
+}
+
 /// Which frame to show, and for how long.
 ///
 /// This struct is output by `Cursor::frame_and_duration`
@@ -384,3 +653,38 @@ fn create_shm_fd() -> IoResult<RawFd> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{best_scale, frame_and_duration, FrameAndDuration};
+
+    #[test]
+    fn frame_timing() {
+        let delays = [10, 20, 30];
+        let frame = |millis| frame_and_duration(delays.iter().cloned(), 60, millis);
+
+        assert_eq!(frame(0), FrameAndDuration { frame_index: 0, frame_duration: 10 });
+        assert_eq!(frame(15), FrameAndDuration { frame_index: 1, frame_duration: 15 });
+        assert_eq!(frame(59), FrameAndDuration { frame_index: 2, frame_duration: 1 });
+        // time wraps around
+        assert_eq!(frame(75), frame(15));
+    }
+
+    #[test]
+    fn static_cursor() {
+        let frame = frame_and_duration([0].iter().cloned(), 0, 42);
+        assert_eq!(frame, FrameAndDuration { frame_index: 0, frame_duration: 0 });
+    }
+
+    #[test]
+    fn scale_selection() {
+        let scales = [1, 2, 3];
+        assert_eq!(best_scale(&scales, 1), 0);
+        assert_eq!(best_scale(&scales, 2), 1);
+        // fall back to the largest scale below the target
+        assert_eq!(best_scale(&scales, 5), 2);
+        // or to the smallest one
+        assert_eq!(best_scale(&[2, 4], 1), 0);
+        assert_eq!(best_scale(&[1, 4], 3), 0);
+    }
+}
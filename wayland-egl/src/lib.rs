@@ -1,25 +1,75 @@
 //! EGL utilities
 //!
-//! This module contains bindings to the `libwayland-egl.so` library.
+//! This module provides the `wl_egl_window` object, as defined by `libwayland-egl.so`.
 //!
-//! This library is used to interface with the OpenGL stack, and creating
+//! This object is used to interface with the OpenGL stack, and creating
 //! EGL surfaces from a wayland surface.
 //!
+//! The `wl_egl_window` struct is a stable ABI shared between the application and the EGL
+//! drivers, this crate implements it directly and does not need `libwayland-egl.so`. The EGL
+//! drivers interact with the wayland objects through `libwayland-client` however, and as such
+//! this crate requires the `use_system_lib` feature of `wayland-client` and only works with its
+//! C backend: a surface of the rust implementation has no `wl_proxy` to give them.
+//!
 //! See WlEglSurface documentation for details.
 
-use std::os::raw::c_void;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::sync::Mutex;
 
 use wayland_client::protocol::wl_surface::WlSurface;
-use wayland_sys::{client::wl_proxy, egl::*, ffi_dispatch};
+use wayland_sys::client::wl_proxy;
 
 /// Checks if the wayland-egl lib is available and can be used
 ///
+/// As `libwayland-egl.so` is no longer needed, this only checks that `libwayland-client.so`
+/// is available.
+///
 /// Trying to create an `WlEglSurface` while this function returns
 /// `false` will result in a panic.
 pub fn is_available() -> bool {
-    is_lib_available()
+    wayland_sys::client::is_lib_available()
+}
+
+// The version of the ABI of `struct wl_egl_window`, as defined in `wayland-egl-backend.h`
+const WL_EGL_WINDOW_VERSION: isize = 3;
+
+// The layout of this struct is set by `wayland-egl-backend.h`, the fields after `version`
+// are read and written directly by the EGL drivers.
+#[repr(C)]
+struct WlEglWindow {
+    version: isize,
+    width: c_int,
+    height: c_int,
+    dx: c_int,
+    dy: c_int,
+    attached_width: c_int,
+    attached_height: c_int,
+    driver_private: *mut c_void,
+    resize_callback: Option<unsafe extern "C" fn(*mut WlEglWindow, *mut c_void)>,
+    destroy_window_callback: Option<unsafe extern "C" fn(*mut c_void)>,
+    surface: *mut wl_proxy,
+}
+
+/// Enum representing the possible reasons why creating an EGL surface failed
+#[derive(Debug)]
+pub enum Error {
+    /// The size given to the surface is not strictly positive
+    InvalidSize,
 }
 
+impl ::std::error::Error for Error {}
+
+impl ::std::fmt::Display for Error {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match *self {
+            Error::InvalidSize => f.write_str("The size of the EGL surface must be positive."),
+        }
+    }
+}
+
+type ResizeCallback = Box<dyn FnMut(i32, i32, i32, i32) + Send>;
+
 unsafe impl Send for WlEglSurface {}
 unsafe impl Sync for WlEglSurface {}
 
@@ -31,39 +81,67 @@ unsafe impl Sync for WlEglSurface {}
 /// EGL context (you'll most likely need the display ptr as well, that you can
 /// get via the `ptr` method of the `Proxy` trait on the `WlDisplay` object).
 pub struct WlEglSurface {
-    ptr: *mut wl_egl_window,
+    // the drivers keep a pointer to it and write to it, so it is only accessed through
+    // this raw pointer
+    window: *mut WlEglWindow,
+    on_resize: Mutex<Option<ResizeCallback>>,
 }
 
 impl WlEglSurface {
     /// Create an EGL surface from a wayland surface
-    pub fn new(surface: &WlSurface, width: i32, height: i32) -> WlEglSurface {
+    ///
+    /// Returns `Error::InvalidSize` if `width` or `height` is not strictly positive.
+    pub fn new(surface: &WlSurface, width: i32, height: i32) -> Result<WlEglSurface, Error> {
         unsafe { WlEglSurface::new_from_raw(surface.as_ref().c_ptr(), width, height) }
     }
 
     /// Create an EGL surface from a raw pointer to a wayland surface
     ///
+    /// Returns `Error::InvalidSize` if `width` or `height` is not strictly positive.
+    ///
     /// # Safety
     ///
     /// The provided pointer must be a valid `wl_surface` pointer from `libwayland-client`.
-    pub unsafe fn new_from_raw(surface: *mut wl_proxy, width: i32, height: i32) -> WlEglSurface {
-        let ptr = ffi_dispatch!(WAYLAND_EGL_HANDLE, wl_egl_window_create, surface, width, height);
-        WlEglSurface { ptr }
+    pub unsafe fn new_from_raw(
+        surface: *mut wl_proxy,
+        width: i32,
+        height: i32,
+    ) -> Result<WlEglSurface, Error> {
+        if width <= 0 || height <= 0 {
+            return Err(Error::InvalidSize);
+        }
+        Ok(WlEglSurface {
+            window: Box::into_raw(Box::new(WlEglWindow {
+                version: WL_EGL_WINDOW_VERSION,
+                width,
+                height,
+                dx: 0,
+                dy: 0,
+                attached_width: 0,
+                attached_height: 0,
+                driver_private: ptr::null_mut(),
+                resize_callback: None,
+                destroy_window_callback: None,
+                surface,
+            })),
+            on_resize: Mutex::new(None),
+        })
     }
 
     /// Fetch current size of the EGL surface
+    ///
+    /// This is the size of the last buffer attached by the driver, as given by
+    /// `get_attached_size()`.
     pub fn get_size(&self) -> (i32, i32) {
-        let mut w = 0i32;
-        let mut h = 0i32;
-        unsafe {
-            ffi_dispatch!(
-                WAYLAND_EGL_HANDLE,
-                wl_egl_window_get_attached_size,
-                self.ptr,
-                &mut w as *mut i32,
-                &mut h as *mut i32
-            );
-        }
-        (w, h)
+        self.get_attached_size()
+    }
+
+    /// Fetch the size of the last buffer the driver attached to the surface
+    ///
+    /// This is `(0, 0)` until the driver attached a buffer. It lags behind the size given to
+    /// `resize()` until the driver draws its next frame.
+    pub fn get_attached_size(&self) -> (i32, i32) {
+        unsafe { ((*self.window).attached_width, (*self.window).attached_height) }
     }
 
     /// Resize the EGL surface
@@ -72,25 +150,54 @@ impl WlEglSurface {
     /// the surface, the two others `(dx, dy)` represent the displacement
     /// of the top-left corner of the surface. It allows you to control the
     /// direction of the resizing if necessary.
+    ///
+    /// If `width` or `height` is not strictly positive, the surface keeps its previous size and
+    /// neither the driver nor the resize callback are notified.
     pub fn resize(&self, width: i32, height: i32, dx: i32, dy: i32) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
         unsafe {
-            ffi_dispatch!(WAYLAND_EGL_HANDLE, wl_egl_window_resize, self.ptr, width, height, dx, dy)
+            (*self.window).width = width;
+            (*self.window).height = height;
+            (*self.window).dx = dx;
+            (*self.window).dy = dy;
+            if let Some(callback) = (*self.window).resize_callback {
+                callback(self.window, (*self.window).driver_private);
+            }
+        }
+        if let Some(ref mut on_resize) = *self.on_resize.lock().unwrap() {
+            on_resize(width, height, dx, dy);
         }
     }
 
+    /// Set a callback invoked each time the surface is resized
+    ///
+    /// It is given the arguments passed to `resize()`, and is invoked after the driver was
+    /// notified of the new size. This replaces any previously set callback.
+    pub fn set_resize_callback<F>(&self, callback: F)
+    where
+        F: FnMut(i32, i32, i32, i32) + Send + 'static,
+    {
+        *self.on_resize.lock().unwrap() = Some(Box::new(callback));
+    }
+
     /// Raw pointer to the EGL surface
     ///
     /// You'll need this pointer to initialize the EGL context in your
     /// favourite OpenGL lib.
     pub fn ptr(&self) -> *const c_void {
-        self.ptr as *const c_void
+        self.window as *const c_void
     }
 }
 
 impl Drop for WlEglSurface {
     fn drop(&mut self) {
         unsafe {
-            ffi_dispatch!(WAYLAND_EGL_HANDLE, wl_egl_window_destroy, self.ptr);
+            if let Some(callback) = (*self.window).destroy_window_callback {
+                callback((*self.window).driver_private);
+            }
+            drop(Box::from_raw(self.window));
         }
     }
 }
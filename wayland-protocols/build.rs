@@ -40,6 +40,18 @@ static UNSTABLE_PROTOCOLS: &[UnstableProtocol] = &[
     ("xwayland-keyboard-grab", &[("v1", &[])]),
 ];
 
+static STAGING_PROTOCOLS: &[UnstableProtocol] = &[
+    ("content-type", &[("v1", &[])]),
+    ("drm-lease", &[("v1", &[])]),
+    ("ext-idle-notify", &[("v1", &[])]),
+    ("ext-session-lock", &[("v1", &[])]),
+    ("fractional-scale", &[("v1", &[])]),
+    ("single-pixel-buffer", &[("v1", &[])]),
+    ("tearing-control", &[("v1", &[])]),
+    ("xdg-activation", &[("v1", &[])]),
+    ("xwayland-shell", &[("v1", &[])]),
+];
+
 static WLR_UNSTABLE_PROTOCOLS: &[UnstableProtocol] = &[
     ("wlr-data-control", &[("v1", &[])]),
     ("wlr-export-dmabuf", &[("v1", &[])]),
@@ -66,19 +78,29 @@ fn generate_protocol(
     println!("cargo:rerun-if-changed={}", protocol_file.display());
 
     if client {
-        generate_code_with_destructor_events(
+        generate_code_with_options(
             &protocol_file,
             out_dir.join(&format!("{}_client_api.rs", name)),
             Side::Client,
-            dest_events,
+            &Options::new()
+                .destructor_events(dest_events)
+                .argument_metadata(true)
+                .borrowed_events(true)
+                .borrowed_parsing(true)
+                .interface_description(true),
         );
     }
     if server {
-        generate_code_with_destructor_events(
+        generate_code_with_options(
             &protocol_file,
             out_dir.join(&format!("{}_server_api.rs", name)),
             Side::Server,
-            dest_events,
+            &Options::new()
+                .destructor_events(dest_events)
+                .checked_events(true)
+                .argument_metadata(true)
+                .borrowed_parsing(true)
+                .interface_description(true),
         );
     }
 }
@@ -87,6 +109,8 @@ fn main() {
     println!("cargo:rerun-if-changed-env=CARGO_FEATURE_CLIENT");
     println!("cargo:rerun-if-changed-env=CARGO_FEATURE_SERVER");
     println!("cargo:rerun-if-changed-env=CARGO_FEATURE_UNSTABLE_PROTOCOLS");
+    println!("cargo:rerun-if-changed-env=CARGO_FEATURE_STAGING_PROTOCOLS");
+    println!("cargo:rerun-if-changed-env=CARGO_FEATURE_WLR_PROTOCOLS");
 
     let out_dir_str = var("OUT_DIR").unwrap();
     let out_dir = Path::new(&out_dir_str);
@@ -118,6 +142,22 @@ fn main() {
         );
     }
 
+    if var("CARGO_FEATURE_STAGING_PROTOCOLS").ok().is_some() {
+        for &(name, versions) in STAGING_PROTOCOLS {
+            for &(version, dest_events) in versions {
+                let file = format!("{name}/{name}-{version}.xml", name = name, version = version);
+                generate_protocol(
+                    &format!("{name}-{version}", name = name, version = version),
+                    &Path::new("./protocols/staging").join(file),
+                    out_dir,
+                    client,
+                    server,
+                    dest_events,
+                );
+            }
+        }
+    }
+
     if var("CARGO_FEATURE_UNSTABLE_PROTOCOLS").ok().is_some() {
         for &(name, versions) in UNSTABLE_PROTOCOLS {
             for &(version, dest_events) in versions {
@@ -133,6 +173,9 @@ fn main() {
                 );
             }
         }
+    }
+
+    if var("CARGO_FEATURE_WLR_PROTOCOLS").ok().is_some() {
         for &(name, versions) in WLR_UNSTABLE_PROTOCOLS {
             for &(version, dest_events) in versions {
                 let file = format!("{name}-unstable-{version}.xml", name = name, version = version);
@@ -11,20 +11,42 @@
 //! to protocols that are not yet considered stable. As such, no stability guarantee is
 //! given for these protocols.
 //!
+//! The cargo feature `staging_protocols` adds a `staging` module, containing bindings
+//! to the protocols of the staging phase of wayland-protocols. They are meant to be
+//! widely used, but may still receive backward incompatible changes.
+//!
+//! The cargo feature `wlr_protocols` fills the `wlr` module with bindings to the protocols
+//! of wlroots, such as the layer shell or screencopy. It is enabled by `unstable_protocols`,
+//! but can be used on its own.
+//!
+//! Other crates can generate bindings to their own protocols with the same layout using
+//! the `include_protocol!` macro.
+//!
 //! Some protocols require unstable rust features, the inclusion of them is controlled
 //! by the cargo feature `nightly`.
 
 #![warn(missing_docs)]
 
-#[macro_use]
-extern crate bitflags;
-
 #[macro_use]
 mod protocol_macro;
 
+// Used by the expansion of `include_protocol!`
+#[doc(hidden)]
+pub mod __private {
+    pub use bitflags;
+    #[cfg(feature = "client")]
+    pub use wayland_client;
+    pub use wayland_commons;
+    #[cfg(feature = "server")]
+    pub use wayland_server;
+}
+
 #[cfg(feature = "unstable_protocols")]
 pub mod unstable;
 
+#[cfg(feature = "staging_protocols")]
+pub mod staging;
+
 pub mod misc;
 pub mod wlr;
 
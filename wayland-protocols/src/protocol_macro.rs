@@ -1,3 +1,88 @@
+/// Include the bindings of a protocol generated by `wayland-scanner`
+///
+/// This macro lets other crates define their own protocol extensions with the same module
+/// layout as the protocols of this crate. Their build script generates the code with
+/// `wayland-scanner`, in a file of `OUT_DIR` named `<name>_client_api.rs` for the client side
+/// and `<name>_server_api.rs` for the server side:
+///
+/// ```ignore
+/// // build.rs
+/// let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+/// wayland_scanner::generate_code(
+///     "./protocols/my-protocol.xml",
+///     out_dir.join("my-protocol_client_api.rs"),
+///     wayland_scanner::Side::Client,
+/// );
+/// ```
+///
+/// The macro then expands to a `client` or `server` module containing the generated code.
+/// The first list gives the core interfaces used by the protocol, the second one the paths
+/// to the interfaces it uses from other protocols:
+///
+/// ```ignore
+/// pub mod my_protocol {
+///     wayland_protocols::include_protocol!(
+///         client,
+///         "my-protocol",
+///         [wl_surface, wl_seat],
+///         [wayland_protocols::xdg_shell::client::xdg_toplevel]
+///     );
+/// }
+/// ```
+///
+/// The client side requires the `client` cargo feature of this crate, and the server
+/// side its `server` cargo feature.
+#[macro_export]
+macro_rules! include_protocol(
+    // the generated code uses `bitflags!` from nested modules, which only see it in textual scope
+    (@bitflags $d: tt) => {
+        #[allow(unused_macros)]
+        macro_rules! bitflags {
+            ($d($d tokens: tt)*) => { $crate::__private::bitflags::bitflags! { $d($d tokens)* } };
+        }
+    };
+    (client, $name: expr, [$($import: ident),*], [$($($prot_import: ident)::+),*]) => {
+        #[allow(dead_code,non_camel_case_types,unused_unsafe,unused_variables)]
+        #[allow(non_upper_case_globals,non_snake_case,unused_imports)]
+        #[allow(missing_docs, clippy::all)]
+        pub mod client {
+            //! Client-side API of this protocol
+            pub(crate) use $crate::__private::wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject, ResponseFuture};
+            pub(crate) use $crate::__private::wayland_commons::map::{Object, ObjectMetadata};
+            pub(crate) use $crate::__private::wayland_commons::{Interface, MessageGroup};
+            pub(crate) use $crate::__private::wayland_commons::wire::{Argument, ArgumentDesc, MessageDesc, ArgumentType, Message, ArgumentRef, MessageRef, InterfaceDesc, ArgumentInfo, EnumDesc, EnumEntry};
+            pub(crate) use $crate::__private::wayland_commons::smallvec;
+            pub(crate) use $crate::__private::wayland_client::protocol::{$($import),*};
+            pub(crate) use $crate::__private::wayland_client::sys;
+            $crate::include_protocol!(@bitflags $);
+            $(
+                pub(crate) use $($prot_import)::+;
+            )*
+            include!(concat!(env!("OUT_DIR"), "/", $name, "_client_api.rs"));
+        }
+    };
+    (server, $name: expr, [$($import: ident),*], [$($($prot_import: ident)::+),*]) => {
+        #[allow(dead_code,non_camel_case_types,unused_unsafe,unused_variables)]
+        #[allow(non_upper_case_globals,non_snake_case,unused_imports)]
+        #[allow(missing_docs, clippy::all)]
+        pub mod server {
+            //! Server-side API of this protocol
+            pub(crate) use $crate::__private::wayland_server::{Main, AnonymousObject, Resource, ResourceMap, VersionCheck, VersionTooLow};
+            pub(crate) use $crate::__private::wayland_commons::map::{Object, ObjectMetadata};
+            pub(crate) use $crate::__private::wayland_commons::{Interface, MessageGroup};
+            pub(crate) use $crate::__private::wayland_commons::wire::{Argument, ArgumentDesc, MessageDesc, ArgumentType, Message, ArgumentRef, MessageRef, InterfaceDesc, ArgumentInfo, EnumDesc, EnumEntry};
+            pub(crate) use $crate::__private::wayland_commons::smallvec;
+            pub(crate) use $crate::__private::wayland_server::protocol::{$($import),*};
+            pub(crate) use $crate::__private::wayland_server::sys;
+            $crate::include_protocol!(@bitflags $);
+            $(
+                pub(crate) use $($prot_import)::+;
+            )*
+            include!(concat!(env!("OUT_DIR"), "/", $name, "_server_api.rs"));
+        }
+    };
+);
+
 #[macro_escape]
 macro_rules! wayland_protocol(
     ($name: expr, [$(($import: ident, $interface: ident)),*], [$(($prot_name:ident, $prot_import: ident, $prot_iface: ident)),*]) => {
@@ -8,46 +93,30 @@ macro_rules! wayland_protocol(
         pub use self::generated::server;
 
         mod generated {
-            #![allow(dead_code,non_camel_case_types,unused_unsafe,unused_variables)]
-            #![allow(non_upper_case_globals,non_snake_case,unused_imports)]
-            #![allow(missing_docs, clippy::all)]
-
             #[cfg(feature = "client")]
-            pub mod client {
-                //! Client-side API of this protocol
-                pub(crate) use wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject};
-                pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
-                pub(crate) use wayland_commons::{Interface, MessageGroup};
-                pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
-                pub(crate) use wayland_commons::smallvec;
-                pub(crate) use wayland_client::protocol::{$($import),*};
-                pub(crate) use wayland_client::sys;
-                $(
-                    pub(crate) use crate::$prot_name::client::$prot_import;
-                )*
-                include!(concat!(env!("OUT_DIR"), "/", $name, "_client_api.rs"));
-            }
+            include_protocol!(
+                client,
+                $name,
+                [$($import),*],
+                [$(crate::$prot_name::client::$prot_import),*]
+            );
 
             #[cfg(feature = "server")]
-            pub mod server {
-                //! Server-side API of this protocol
-                pub(crate) use wayland_server::{Main, AnonymousObject, Resource, ResourceMap};
-                pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
-                pub(crate) use wayland_commons::{Interface, MessageGroup};
-                pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
-                pub(crate) use wayland_commons::smallvec;
-                pub(crate) use wayland_server::protocol::{$($import),*};
-                pub(crate) use wayland_server::sys;
-                $(
-                    pub(crate) use crate::$prot_name::server::$prot_import;
-                )*
-                include!(concat!(env!("OUT_DIR"), "/", $name, "_server_api.rs"));
-            }
+            include_protocol!(
+                server,
+                $name,
+                [$($import),*],
+                [$(crate::$prot_name::server::$prot_import),*]
+            );
         }
     }
 );
 
-#[cfg(feature = "unstable_protocols")]
+#[cfg(any(
+    feature = "unstable_protocols",
+    feature = "staging_protocols",
+    feature = "wlr_protocols"
+))]
 #[macro_escape]
 macro_rules! wayland_protocol_versioned(
     ($name: expr, [$($version: ident),*], $std_imports:tt, $prot_imports:tt) => {
@@ -0,0 +1,104 @@
+//! Staging protocols from wayland-protocols
+//!
+//! The protocols described in this module are in the staging phase of
+//! wayland-protocols: they are meant to be used and implemented, but may
+//! still be modified in backward incompatible ways before becoming stable.
+//!
+//! Backward compatible changes may be added together with the
+//! corresponding interface version bump.
+//!
+//! Backward incompatible changes are done by bumping the version
+//! number in the protocol and interface names and resetting the
+//! interface version.
+
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod content_type {
+    //! Content type hint protocol
+    //!
+    //! Allows clients to describe the kind of content displayed by a surface, so that the
+    //! compositor can optimize its presentation, for example for games or videos.
+
+    wayland_protocol_versioned!("content-type", [v1], [(wl_surface, wl_surface_interface)], []);
+}
+
+pub mod drm_lease {
+    //! DRM lease protocol
+    //!
+    //! Allows clients such as VR compositors to take control of a DRM connector that is
+    //! not used by the compositor.
+
+    wayland_protocol_versioned!("drm-lease", [v1], [], []);
+}
+
+pub mod ext_idle_notify {
+    //! Idle notification protocol
+    //!
+    //! Allows clients to be notified when the user has been idle for a given amount of time.
+
+    wayland_protocol_versioned!("ext-idle-notify", [v1], [(wl_seat, wl_seat_interface)], []);
+}
+
+pub mod ext_session_lock {
+    //! Session lock protocol
+    //!
+    //! Allows a privileged client to lock the session and display arbitrary content on
+    //! the outputs while it is locked.
+
+    wayland_protocol_versioned!(
+        "ext-session-lock",
+        [v1],
+        [(wl_surface, wl_surface_interface), (wl_output, wl_output_interface)],
+        []
+    );
+}
+
+pub mod fractional_scale {
+    //! Fractional scale protocol
+    //!
+    //! Allows the compositor to suggest a non-integer scale to use for the buffers of
+    //! a surface.
+
+    wayland_protocol_versioned!("fractional-scale", [v1], [(wl_surface, wl_surface_interface)], []);
+}
+
+pub mod single_pixel_buffer {
+    //! Single pixel buffer protocol
+    //!
+    //! Allows the creation of buffers of a single pixel of a given color, that can be scaled
+    //! to any size with the viewporter protocol.
+
+    wayland_protocol_versioned!("single-pixel-buffer", [v1], [(wl_buffer, wl_buffer_interface)], []);
+}
+
+pub mod tearing_control {
+    //! Tearing control protocol
+    //!
+    //! Allows clients to hint that the compositor may present their content with tearing,
+    //! to reduce latency.
+
+    wayland_protocol_versioned!("tearing-control", [v1], [(wl_surface, wl_surface_interface)], []);
+}
+
+pub mod xdg_activation {
+    //! XDG activation protocol
+    //!
+    //! Allows clients to pass focus to another toplevel surface, possibly of an other client,
+    //! using activation tokens.
+
+    wayland_protocol_versioned!(
+        "xdg-activation",
+        [v1],
+        [(wl_surface, wl_surface_interface), (wl_seat, wl_seat_interface)],
+        []
+    );
+}
+
+pub mod xwayland_shell {
+    //! Xwayland shell protocol
+    //!
+    //! Allows Xwayland to associate X11 windows with their wayland surfaces. Only Xwayland
+    //! is meant to use this protocol.
+
+    wayland_protocol_versioned!("xwayland-shell", [v1], [(wl_surface, wl_surface_interface)], []);
+}
@@ -2,10 +2,13 @@
 //!
 //! This module regroup bindings to the protocol extensions from
 //! [wlr-protocols](https://github.com/swaywm/wlr-protocols).
+//!
+//! Its contents are controlled by the `wlr_protocols` cargo feature, which is also enabled
+//! by `unstable_protocols`.
 
 #![cfg_attr(rustfmt, rustfmt_skip)]
 
-#[cfg(feature = "unstable_protocols")]
+#[cfg(feature = "wlr_protocols")]
 pub mod unstable {
     //! Unstable protocols from wlr-protocols
     //!
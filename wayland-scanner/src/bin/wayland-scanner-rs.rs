@@ -0,0 +1,184 @@
+//! Command line interface to the scanner
+//!
+//! Generates the code of a protocol to a file, for projects that check the generated code
+//! in their repository rather than generating it from a build script.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::process::{self, Command, Stdio};
+
+use wayland_scanner::{generate_code_streams_with_options, Options, Side};
+
+const USAGE: &str = "\
+Usage: wayland-scanner-rs [OPTIONS] <client|server> <PROTOCOL> [OUTPUT]
+
+Generates the rust code of the wayland protocol described by the XML file PROTOCOL,
+for use with wayland-client or wayland-server. The code is written to OUTPUT, or to the
+standard output if it is omitted. PROTOCOL can be `-` to read the standard input.
+
+Options:
+  -d, --destructor-event <INTERFACE>.<EVENT>
+                   Mark an event as a destructor, this option can be repeated
+      --serde      Implement the serde traits for the messages of the protocol
+      --async      Generate futures for the requests answered by a single event
+      --checked-events
+                   Generate version-checked methods for the events of the protocol
+      --argument-metadata
+                   Generate the validation metadata of the arguments of the messages
+      --no-std     Only generate the model of the protocol, for no_std crates
+      --no-rustfmt Do not format the code with rustfmt
+  -h, --help       Print this help";
+
+struct Args {
+    side: Side,
+    protocol: OsString,
+    output: Option<OsString>,
+    destructor_events: Vec<(String, String)>,
+    serde: bool,
+    async_helpers: bool,
+    checked_events: bool,
+    argument_metadata: bool,
+    no_std: bool,
+    rustfmt: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = env::args_os().skip(1);
+    let mut positional = Vec::new();
+    let mut destructor_events = Vec::new();
+    let mut serde = false;
+    let mut async_helpers = false;
+    let mut checked_events = false;
+    let mut argument_metadata = false;
+    let mut no_std = false;
+    let mut rustfmt = true;
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-h") | Some("--help") => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            Some("-d") | Some("--destructor-event") => {
+                let event = args
+                    .next()
+                    .and_then(|e| e.into_string().ok())
+                    .ok_or_else(|| format!("{} requires an argument", arg.to_string_lossy()))?;
+                let mut parts = event.splitn(2, '.');
+                match (parts.next(), parts.next()) {
+                    (Some(interface), Some(event))
+                        if !interface.is_empty() && !event.is_empty() =>
+                    {
+                        destructor_events.push((interface.to_owned(), event.to_owned()))
+                    }
+                    _ => return Err(format!("invalid destructor event `{}`", event)),
+                }
+            }
+            Some("--serde") => serde = true,
+            Some("--async") => async_helpers = true,
+            Some("--checked-events") => checked_events = true,
+            Some("--argument-metadata") => argument_metadata = true,
+            Some("--no-std") => no_std = true,
+            Some("--no-rustfmt") => rustfmt = false,
+            Some(a) if a.starts_with('-') && a != "-" => {
+                return Err(format!("unknown option `{}`", a))
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() < 2 || positional.len() > 3 {
+        return Err("wrong number of arguments".into());
+    }
+    let mut positional = positional.into_iter();
+    let side = match positional.next().unwrap().to_str() {
+        Some("client") => Side::Client,
+        Some("server") => Side::Server,
+        other => {
+            return Err(format!("invalid side `{}`", other.unwrap_or("<non-utf8>")));
+        }
+    };
+
+    Ok(Args {
+        side,
+        protocol: positional.next().unwrap(),
+        output: positional.next(),
+        destructor_events,
+        serde,
+        async_helpers,
+        checked_events,
+        argument_metadata,
+        no_std,
+        rustfmt,
+    })
+}
+
+// Format the code through the standard streams of rustfmt, if it is available
+fn rustfmt(code: &[u8]) -> Option<Vec<u8>> {
+    let mut child = Command::new("rustfmt")
+        .arg("--edition")
+        .arg("2018")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take().unwrap().write_all(code).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if output.status.success() {
+        Some(output.stdout)
+    } else {
+        None
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let input: Box<dyn Read> = if args.protocol == "-" {
+        Box::new(io::stdin())
+    } else {
+        let file = File::open(&args.protocol)
+            .map_err(|e| format!("{}: {}", args.protocol.to_string_lossy(), e))?;
+        Box::new(file)
+    };
+
+    let events = args.destructor_events.iter().map(|(i, e)| (&i[..], &e[..])).collect::<Vec<_>>();
+    let options = Options::new()
+        .destructor_events(&events)
+        .serde(args.serde)
+        .async_helpers(args.async_helpers)
+        .checked_events(args.checked_events)
+        .argument_metadata(args.argument_metadata)
+        .no_std(args.no_std);
+    let mut code = Vec::new();
+    generate_code_streams_with_options(input, &mut code, args.side, &options);
+    // with the `pretty_print` feature the code is already formatted
+    if args.rustfmt && !cfg!(feature = "pretty_print") {
+        // like the build script API, the code is left unformatted if rustfmt is not available
+        if let Some(formatted) = rustfmt(&code) {
+            code = formatted;
+        }
+    }
+
+    match args.output {
+        Some(path) => {
+            fs::write(&path, &code).map_err(|e| format!("{}: {}", path.to_string_lossy(), e))
+        }
+        None => io::stdout().write_all(&code).map_err(|e| e.to_string()),
+    }
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -6,18 +6,30 @@ use quote::quote;
 use crate::common_gen::*;
 use crate::protocol::*;
 use crate::util::*;
-use crate::Side;
+use crate::{Options, Side};
 
-pub(crate) fn generate_protocol_client(protocol: Protocol) -> TokenStream {
+pub(crate) fn generate_protocol_client(protocol: Protocol, options: &Options) -> TokenStream {
     // Force the fallback to work around https://github.com/alexcrichton/proc-macro2/issues/218
     proc_macro2::fallback::force();
 
+    let borrowed_imports = if options.borrowed_parsing || options.borrowed_events {
+        Some(quote!(
+            use super::{ArgumentRef, MessageRef};
+        ))
+    } else {
+        None
+    };
     let modules = protocol.interfaces.iter().map(|iface| {
         let doc_attr = iface.description.as_ref().map(description_to_doc_attr);
         let mod_name = Ident::new(&iface.name, Span::call_site());
         let iface_name = Ident::new(&snake_to_camel(&iface.name), Span::call_site());
 
         let enums = &iface.enums;
+        let enums_serde = if options.serde {
+            iface.enums.iter().map(gen_enum_serde).collect()
+        } else {
+            Vec::new()
+        };
 
         let ident = Ident::new("Request", Span::call_site());
         let requests = gen_messagegroup(
@@ -26,6 +38,7 @@ pub(crate) fn generate_protocol_client(protocol: Protocol) -> TokenStream {
             false,
             &iface.requests,
             Some(messagegroup_c_addon(&ident, &iface_name, Side::Client, false, &iface.requests)),
+            options,
         );
 
         let ident = Ident::new("Event", Span::call_site());
@@ -35,17 +48,29 @@ pub(crate) fn generate_protocol_client(protocol: Protocol) -> TokenStream {
             true,
             &iface.events,
             Some(messagegroup_c_addon(&ident, &iface_name, Side::Client, true, &iface.events)),
+            options,
         );
 
         let interface = gen_interface(
             &iface_name,
             &iface.name,
             iface.version,
-            Some(interface_c_addon(&iface.name)),
+            Some(interface_addon(iface, options)),
             Side::Client,
         );
 
+        let borrowed_events = if options.borrowed_events {
+            gen_borrowed_events(&iface.events)
+        } else {
+            TokenStream::new()
+        };
+
         let object_methods = gen_object_methods(&iface_name, &iface.requests, Side::Client);
+        let async_helpers = if options.async_helpers {
+            gen_async_helpers(&iface_name, &iface.requests, &protocol)
+        } else {
+            TokenStream::new()
+        };
         let sinces = gen_since_constants(&iface.requests, &iface.events);
         let c_interface = super::c_interface_gen::generate_interface(&iface);
 
@@ -57,14 +82,18 @@ pub(crate) fn generate_protocol_client(protocol: Protocol) -> TokenStream {
                     Proxy, AnonymousObject, Interface, MessageGroup, MessageDesc, ArgumentType,
                     Object, Message, Argument, ObjectMetadata, types_null, NULLPTR, Main, smallvec,
                 };
+                #borrowed_imports
                 use super::sys::common::{wl_interface, wl_array, wl_argument, wl_message};
                 use super::sys::client::*;
 
                 #(#enums)*
+                #(#enums_serde)*
                 #requests
                 #events
+                #borrowed_events
                 #interface
                 #object_methods
+                #async_helpers
                 #sinces
                 #c_interface
             }
@@ -72,18 +101,27 @@ pub(crate) fn generate_protocol_client(protocol: Protocol) -> TokenStream {
     });
 
     let c_prefix = super::c_interface_gen::generate_interfaces_prefix(&protocol);
+    let serde_helpers = if options.serde { Some(gen_serde_helpers(Side::Client)) } else { None };
 
     quote! {
         #c_prefix
+        #serde_helpers
 
         #(#modules)*
     }
 }
 
-pub(crate) fn generate_protocol_server(protocol: Protocol) -> TokenStream {
+pub(crate) fn generate_protocol_server(protocol: Protocol, options: &Options) -> TokenStream {
     // Force the fallback to work around https://github.com/alexcrichton/proc-macro2/issues/218
     proc_macro2::fallback::force();
 
+    let borrowed_imports = if options.borrowed_parsing {
+        Some(quote!(
+            use super::{ArgumentRef, MessageRef};
+        ))
+    } else {
+        None
+    };
     let modules = protocol
         .interfaces
         .iter()
@@ -95,6 +133,8 @@ pub(crate) fn generate_protocol_server(protocol: Protocol) -> TokenStream {
             let iface_name = Ident::new(&snake_to_camel(&iface.name), Span::call_site());
 
             let enums = &iface.enums;
+            let enums_serde =
+                if options.serde { iface.enums.iter().map(gen_enum_serde).collect() } else { Vec::new() };
 
             let ident = Ident::new("Request", Span::call_site());
             let requests = gen_messagegroup(
@@ -109,6 +149,7 @@ pub(crate) fn generate_protocol_server(protocol: Protocol) -> TokenStream {
                     true,
                     &iface.requests,
                 )),
+                options,
             );
 
             let ident = Ident::new("Event", Span::call_site());
@@ -124,16 +165,23 @@ pub(crate) fn generate_protocol_server(protocol: Protocol) -> TokenStream {
                     false,
                     &iface.events,
                 )),
+                options,
             );
 
             let interface = gen_interface(
                 &Ident::new(&snake_to_camel(&iface.name), Span::call_site()),
                 &iface.name,
                 iface.version,
-                Some(interface_c_addon(&iface.name)),
+                Some(interface_addon(iface, options)),
                 Side::Server,
             );
             let object_methods = gen_object_methods(&iface_name, &iface.events, Side::Server);
+            let post_error = gen_post_error(&iface_name, &iface.enums);
+            let checked_events = if options.checked_events {
+                gen_checked_events(&iface_name, &iface.events)
+            } else {
+                TokenStream::new()
+            };
             let sinces = gen_since_constants(&iface.requests, &iface.events);
             let c_interface = super::c_interface_gen::generate_interface(&iface);
 
@@ -143,16 +191,20 @@ pub(crate) fn generate_protocol_server(protocol: Protocol) -> TokenStream {
                     use std::os::raw::c_char;
                     use super::{
                         Resource, AnonymousObject, Interface, MessageGroup, MessageDesc, Main, smallvec,
-                        ArgumentType, Object, Message, Argument, ObjectMetadata, types_null, NULLPTR
+                        ArgumentType, Object, Message, Argument, ObjectMetadata, types_null, NULLPTR,
                     };
+                    #borrowed_imports
                     use super::sys::common::{wl_argument, wl_interface, wl_array, wl_message};
                     use super::sys::server::*;
 
                     #(#enums)*
+                    #(#enums_serde)*
                     #requests
                     #events
                     #interface
                     #object_methods
+                    #post_error
+                    #checked_events
                     #sinces
                     #c_interface
                 }
@@ -160,9 +212,69 @@ pub(crate) fn generate_protocol_server(protocol: Protocol) -> TokenStream {
         });
 
     let c_prefix = super::c_interface_gen::generate_interfaces_prefix(&protocol);
+    let serde_helpers = if options.serde { Some(gen_serde_helpers(Side::Server)) } else { None };
 
     quote! {
         #c_prefix
+        #serde_helpers
+        #(#modules)*
+    }
+}
+
+pub(crate) fn generate_protocol_model(protocol: Protocol, options: &Options) -> TokenStream {
+    // Force the fallback to work around https://github.com/alexcrichton/proc-macro2/issues/218
+    proc_macro2::fallback::force();
+
+    let modules = protocol.interfaces.iter().map(|iface| {
+        let doc_attr = iface.description.as_ref().map(description_to_doc_attr);
+        let mod_name = Ident::new(&iface.name, Span::call_site());
+        let iface_name = &iface.name;
+        let version = iface.version;
+
+        let enums = &iface.enums;
+        let enums_serde = if options.serde {
+            iface.enums.iter().map(gen_enum_serde).collect()
+        } else {
+            Vec::new()
+        };
+
+        let requests = gen_message_descs(&iface.requests);
+        let events = gen_message_descs(&iface.events);
+        let arguments = if options.argument_metadata {
+            let request_args = gen_argument_descs(&iface.requests);
+            let event_args = gen_argument_descs(&iface.events);
+            Some(quote! {
+                /// Validation metadata of the arguments of the requests, in the order of `REQUESTS`
+                pub const REQUEST_ARGUMENTS: &[&[super::ArgumentDesc]] = &[#(#request_args,)*];
+                /// Validation metadata of the arguments of the events, in the order of `EVENTS`
+                pub const EVENT_ARGUMENTS: &[&[super::ArgumentDesc]] = &[#(#event_args,)*];
+            })
+        } else {
+            None
+        };
+        let sinces = gen_since_constants(&iface.requests, &iface.events);
+
+        quote! {
+            #doc_attr
+            pub mod #mod_name {
+                #(#enums)*
+                #(#enums_serde)*
+
+                /// Name of this interface
+                pub const NAME: &str = #iface_name;
+                /// Maximum supported version of this interface
+                pub const VERSION: u32 = #version;
+                /// Description of the requests of this interface, indexed by opcode
+                pub const REQUESTS: &[super::MessageDesc] = &[#(#requests,)*];
+                /// Description of the events of this interface, indexed by opcode
+                pub const EVENTS: &[super::MessageDesc] = &[#(#events,)*];
+                #arguments
+                #sinces
+            }
+        }
+    });
+
+    quote! {
         #(#modules)*
     }
 }
@@ -540,6 +652,16 @@ fn messagegroup_c_addon(
     }
 }
 
+fn interface_addon(iface: &Interface, options: &Options) -> TokenStream {
+    let c_addon = interface_c_addon(&iface.name);
+    if options.interface_description {
+        let description = gen_interface_description(iface);
+        quote!(#c_addon #description)
+    } else {
+        c_addon
+    }
+}
+
 fn interface_c_addon(low_name: &str) -> TokenStream {
     let iface_name = Ident::new(&format!("{}_interface", low_name), Span::call_site());
     quote! {
@@ -9,7 +9,7 @@ use quote::{quote, ToTokens};
 
 use crate::protocol::*;
 use crate::util::*;
-use crate::Side;
+use crate::{Options, Side};
 
 pub(crate) fn to_doc_attr(text: &str) -> TokenStream {
     let text = text.lines().map(str::trim).collect::<Vec<_>>().join("\n");
@@ -138,6 +138,186 @@ impl ToTokens for Enum {
     }
 }
 
+// The helper of the `serde_helpers` module handling an argument, if it is not (de)serializable
+fn serde_helper(arg: &Arg, side: Side, receiver: bool) -> Option<&'static str> {
+    if arg.enum_.is_some() {
+        return None;
+    }
+    match arg.typ {
+        Type::Fd => Some("fd"),
+        Type::Object => Some("object"),
+        Type::NewId if !receiver && side == Side::Client => None,
+        Type::NewId if arg.interface.is_none() => Some("anonymous_new_object"),
+        Type::NewId if side == Side::Server && !receiver => Some("resource"),
+        Type::NewId => Some("new_object"),
+        _ => None,
+    }
+}
+
+/// Serialize enums as their raw value, like on the wire
+pub(crate) fn gen_enum_serde(enu: &Enum) -> TokenStream {
+    let ident = Ident::new(&snake_to_camel(&enu.name), Span::call_site());
+    let name = &enu.name;
+
+    quote! {
+        impl serde::Serialize for #ident {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_u32(self.to_raw())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for #ident {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = <u32 as serde::Deserialize>::deserialize(deserializer)?;
+                #ident::from_raw(value).ok_or_else(|| {
+                    serde::de::Error::custom(format!("invalid value {} for enum {}", value, #name))
+                })
+            }
+        }
+    }
+}
+
+/// The functions used to (de)serialize the arguments that are not plain data
+///
+/// Objects are serialized as their protocol id and can not be deserialized without a
+/// connection, file descriptors are serialized as a placeholder and deserialized as -1.
+pub(crate) fn gen_serde_helpers(side: Side) -> TokenStream {
+    let object = side.object_name();
+    let side_specific = if side == Side::Server {
+        quote! {
+            pub fn serialize_resource<I, S>(object: &Resource<I>, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+                S: serde::Serializer,
+            {
+                serializer.serialize_u32(object.id())
+            }
+
+            pub fn serialize_optional_resource<I, S>(
+                object: &Option<Resource<I>>,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error>
+            where
+                I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+                S: serde::Serializer,
+            {
+                match *object {
+                    Some(ref object) => serializer.serialize_some(&object.id()),
+                    None => serializer.serialize_none(),
+                }
+            }
+
+            pub use self::deserialize_object as deserialize_resource;
+            pub use self::deserialize_optional_object as deserialize_optional_resource;
+        }
+    } else {
+        quote!()
+    };
+
+    quote! {
+        mod serde_helpers {
+            use super::{AnonymousObject, Interface, Main, #object};
+
+            fn object_error<E: serde::de::Error>(id: u32) -> E {
+                E::custom(format!("object {} can not be deserialized without a connection", id))
+            }
+
+            pub fn serialize_fd<S: serde::Serializer>(
+                _fd: &::std::os::unix::io::RawFd,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                serializer.serialize_unit()
+            }
+
+            pub fn deserialize_fd<'de, D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<::std::os::unix::io::RawFd, D::Error> {
+                <() as serde::Deserialize>::deserialize(deserializer)?;
+                Ok(-1)
+            }
+
+            pub fn serialize_object<I, S>(object: &I, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                I: Interface + AsRef<#object<I>> + From<#object<I>>,
+                S: serde::Serializer,
+            {
+                serializer.serialize_u32(object.as_ref().id())
+            }
+
+            pub fn serialize_optional_object<I, S>(
+                object: &Option<I>,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error>
+            where
+                I: Interface + AsRef<#object<I>> + From<#object<I>>,
+                S: serde::Serializer,
+            {
+                match *object {
+                    Some(ref object) => serializer.serialize_some(&object.as_ref().id()),
+                    None => serializer.serialize_none(),
+                }
+            }
+
+            pub fn deserialize_object<'de, T, D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<T, D::Error> {
+                let id = <u32 as serde::Deserialize>::deserialize(deserializer)?;
+                Err(object_error(id))
+            }
+
+            pub fn deserialize_optional_object<'de, T, D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Option<T>, D::Error> {
+                match <Option<u32> as serde::Deserialize>::deserialize(deserializer)? {
+                    Some(id) => Err(object_error(id)),
+                    None => Ok(None),
+                }
+            }
+
+            pub fn serialize_new_object<I, S>(object: &Main<I>, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                I: Interface + AsRef<#object<I>> + From<#object<I>>,
+                S: serde::Serializer,
+            {
+                serializer.serialize_u32((**object).as_ref().id())
+            }
+
+            pub fn serialize_optional_new_object<I, S>(
+                object: &Option<Main<I>>,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error>
+            where
+                I: Interface + AsRef<#object<I>> + From<#object<I>>,
+                S: serde::Serializer,
+            {
+                match *object {
+                    Some(ref object) => serializer.serialize_some(&(**object).as_ref().id()),
+                    None => serializer.serialize_none(),
+                }
+            }
+
+            pub use self::deserialize_object as deserialize_new_object;
+            pub use self::deserialize_optional_object as deserialize_optional_new_object;
+
+            pub fn serialize_anonymous_new_object<S: serde::Serializer>(
+                &(ref interface, version, ref object): &(String, u32, AnonymousObject),
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                serde::Serialize::serialize(&(interface, version, object.as_ref().id()), serializer)
+            }
+
+            pub fn deserialize_anonymous_new_object<'de, D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<(String, u32, AnonymousObject), D::Error> {
+                let (_, _, id) = <(String, u32, u32) as serde::Deserialize>::deserialize(deserializer)?;
+                Err(object_error(id))
+            }
+
+            #side_specific
+        }
+    }
+}
+
 pub(crate) fn gen_since_constants(requests: &[Message], events: &[Message]) -> TokenStream {
     let req_constants = requests.iter().map(|msg| {
         let cstname =
@@ -164,12 +344,125 @@ pub(crate) fn gen_since_constants(requests: &[Message], events: &[Message]) -> T
     }
 }
 
+/// The `MessageDesc` describing each of the messages, for the `MessageGroup::MESSAGES` array
+pub(crate) fn gen_message_descs(messages: &[Message]) -> Vec<TokenStream> {
+    messages
+        .iter()
+        .map(|msg| {
+            let name_value = &msg.name;
+            let since_value = Literal::u32_unsuffixed(msg.since);
+            let signature_values = msg.args.iter().map(|arg| {
+                let common_type = arg.typ.common_type();
+                quote!(super::ArgumentType::#common_type)
+            });
+            let is_destructor = msg.typ == Some(Type::Destructor);
+
+            quote! {
+                super::MessageDesc {
+                    name: #name_value,
+                    since: #since_value,
+                    signature: &[
+                        #(#signature_values,)*
+                    ],
+                    destructor: #is_destructor,
+                }
+            }
+        })
+        .collect()
+}
+
+/// The `Interface::DESCRIPTION` of an interface
+pub(crate) fn gen_interface_description(iface: &Interface) -> TokenStream {
+    fn args_info(messages: &[Message]) -> Vec<TokenStream> {
+        messages
+            .iter()
+            .map(|msg| {
+                let args = msg.args.iter().map(|arg| {
+                    let name = &arg.name;
+                    let common_type = arg.typ.common_type();
+                    let interface = match arg.interface {
+                        Some(ref iface) => quote!(Some(#iface)),
+                        None => quote!(None),
+                    };
+                    let allow_null = arg.allow_null;
+                    let enum_name = match arg.enum_ {
+                        Some(ref enu) => quote!(Some(#enu)),
+                        None => quote!(None),
+                    };
+                    quote! {
+                        super::ArgumentInfo {
+                            name: #name,
+                            typ: super::ArgumentType::#common_type,
+                            interface: #interface,
+                            allow_null: #allow_null,
+                            enum_name: #enum_name,
+                        }
+                    }
+                });
+                quote!(&[#(#args,)*])
+            })
+            .collect()
+    }
+
+    let name = &iface.name;
+    let version = Literal::u32_unsuffixed(iface.version);
+    let request_args = args_info(&iface.requests);
+    let event_args = args_info(&iface.events);
+    let enums = iface.enums.iter().map(|enu| {
+        let name = &enu.name;
+        let bitfield = enu.bitfield;
+        let entries = enu.entries.iter().map(|entry| {
+            let name = &entry.name;
+            let value = Literal::u32_unsuffixed(entry.value);
+            let since = Literal::u32_unsuffixed(u32::from(entry.since));
+            quote!(super::EnumEntry { name: #name, value: #value, since: #since })
+        });
+        quote! {
+            super::EnumDesc { name: #name, bitfield: #bitfield, entries: &[#(#entries,)*] }
+        }
+    });
+
+    quote! {
+        const DESCRIPTION: &'static super::InterfaceDesc = &super::InterfaceDesc {
+            name: #name,
+            version: #version,
+            requests: Request::MESSAGES,
+            events: Event::MESSAGES,
+            request_args: &[#(#request_args,)*],
+            event_args: &[#(#event_args,)*],
+            enums: &[#(#enums,)*],
+        };
+    }
+}
+
+/// The `ArgumentDesc`s of the arguments of each of the messages, for `MessageGroup::ARGUMENTS`
+pub(crate) fn gen_argument_descs(messages: &[Message]) -> Vec<TokenStream> {
+    messages
+        .iter()
+        .map(|msg| {
+            let descs = msg.args.iter().map(|arg| {
+                let allow_null = arg.allow_null;
+                // bitfields are parsed by truncating unknown bits, which must be rejected here
+                let enum_check = if let Some(ref enu) = arg.enum_ {
+                    let enum_ident = dotted_to_relname(enu);
+                    quote!(Some(|v| #enum_ident::from_raw(v).map(|e| e.to_raw() == v).unwrap_or(false)))
+                } else {
+                    quote!(None)
+                };
+                quote!(super::ArgumentDesc { allow_null: #allow_null, enum_check: #enum_check })
+            });
+            quote!(&[#(#descs,)*])
+        })
+        .collect()
+}
+
 pub(crate) fn gen_messagegroup(
     name: &Ident,
     side: Side,
     receiver: bool,
     messages: &[Message],
     addon: Option<TokenStream>,
+    options: &Options,
 ) -> TokenStream {
     let variants = messages.iter().map(|msg| {
         let mut docs = String::new();
@@ -250,7 +543,19 @@ pub(crate) fn gen_messagegroup(
                 } else {
                     field_type_inner.into_token_stream()
                 };
+                let serde_attr = if options.serde {
+                    serde_helper(arg, side, receiver).map(|helper| {
+                        let optional = if arg.allow_null { "optional_" } else { "" };
+                        let ser = format!("super::serde_helpers::serialize_{}{}", optional, helper);
+                        let de =
+                            format!("super::serde_helpers::deserialize_{}{}", optional, helper);
+                        quote!(#[serde(serialize_with = #ser, deserialize_with = #de)])
+                    })
+                } else {
+                    None
+                };
                 Some(quote! {
+                    #serde_attr
                     #field_name: #field_type
                 })
             });
@@ -268,26 +573,18 @@ pub(crate) fn gen_messagegroup(
         }
     });
 
-    let message_array_values = messages.iter().map(|msg| {
-        let name_value = &msg.name;
-        let since_value = Literal::u32_unsuffixed(msg.since);
-        let signature_values = msg.args.iter().map(|arg| {
-            let common_type = arg.typ.common_type();
-            quote!(super::ArgumentType::#common_type)
-        });
-        let is_destructor = msg.typ == Some(Type::Destructor);
+    let message_array_values = gen_message_descs(messages);
 
-        quote! {
-            super::MessageDesc {
-                name: #name_value,
-                since: #since_value,
-                signature: &[
-                    #(#signature_values,)*
-                ],
-                destructor: #is_destructor,
-            }
-        }
-    });
+    let arguments = if options.argument_metadata {
+        let arg_values = gen_argument_descs(messages);
+        Some(quote! {
+            const ARGUMENTS: &'static [&'static [super::ArgumentDesc]] = &[
+                #(#arg_values,)*
+            ];
+        })
+    } else {
+        None
+    };
 
     let map_type = if side == Side::Client { quote!(ProxyMap) } else { quote!(ResourceMap) };
 
@@ -360,126 +657,17 @@ pub(crate) fn gen_messagegroup(
         })
         .chain(iter::once(quote!(_ => None)));
 
-    let from_raw_body = if receiver {
-        let match_arms = messages
-            .iter()
-            .enumerate()
-            .map(|(opcode, msg)| {
-                let pattern = Literal::u16_unsuffixed(opcode as u16);
-                let msg_type = Ident::new(&snake_to_camel(&msg.name), Span::call_site());
-                let msg_type_qualified = quote!(#name::#msg_type);
-
-                let block = if msg.args.is_empty() {
-                    quote!(Ok(#msg_type_qualified))
-                } else {
-                    let fields = msg.args.iter().map(|arg| {
-                        let field_name = Ident::new(
-                            &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
-                            Span::call_site(),
-                        );
-                        let some_code_path = match arg.typ {
-                            Type::Int => {
-                                if let Some(ref enu) = arg.enum_ {
-                                    let enum_ident = dotted_to_relname(enu);
-                                    quote!(#enum_ident::from_raw(val as u32).ok_or(())?)
-                                } else {
-                                    quote!(val)
-                                }
-                            }
-                            Type::Uint => {
-                                if let Some(ref enu) = arg.enum_ {
-                                    let enum_ident = dotted_to_relname(enu);
-                                    quote!(#enum_ident::from_raw(val).ok_or(())?)
-                                } else {
-                                    quote!(val)
-                                }
-                            }
-                            Type::Fixed => quote!((val as f64) / 256.),
-                            Type::Array => {
-                                if arg.allow_null {
-                                    quote!(if val.len() == 0 { None } else { Some(*val) })
-                                } else {
-                                    quote!(*val)
-                                }
-                            }
-                            Type::String => {
-                                let string_conversion = quote! {
-                                    let s = String::from_utf8(val.into_bytes())
-                                        .unwrap_or_else(|e| String::from_utf8_lossy(&e.into_bytes()).into());
-                                };
-
-                                if arg.allow_null {
-                                    quote! {
-                                        #string_conversion
-                                        if s.len() == 0 { None } else { Some(s) }
-                                    }
-                                } else {
-                                    quote! {
-                                        #string_conversion
-                                        s
-                                    }
-                                }
-                            }
-                            Type::Fd => quote!(val),
-                            Type::Object => {
-                                let map_lookup = if side == Side::Client {
-                                    quote!(map.get_or_dead(val).into())
-                                } else {
-                                    quote!(map.get(val).ok_or(())?.into())
-                                };
-                                if arg.allow_null {
-                                    quote!(if val == 0 { None } else { Some(#map_lookup) })
-                                } else {
-                                    map_lookup
-                                }
-                            }
-                            Type::NewId => {
-                                let map_lookup = quote!(map.get_new(val).ok_or(())?);
-                                if arg.allow_null {
-                                    quote!(if val == 0 { None } else { Some(#map_lookup) })
-                                } else {
-                                    map_lookup
-                                }
-                            }
-                            Type::Destructor => panic!("An argument cannot have type destructor!"),
-                        };
-
-                        let common_type = arg.typ.common_type();
-
-                        quote! {
-                            #field_name: {
-                                if let Some(Argument::#common_type(val)) = args.next() {
-                                    #some_code_path
-                                } else {
-                                    return Err(());
-                                }
-                            }
-                        }
-                    });
-
-                    quote! {
-                        {
-                            let mut args = msg.args.into_iter();
-
-                            Ok(#msg_type_qualified {
-                                #(#fields,)*
-                            })
-                        }
-                    }
-                };
-
-                quote!(#pattern => #block)
-            })
-            .chain(iter::once(quote!(_ => Err(()))));
-
-        quote! {
-            match msg.opcode {
-                #(#match_arms,)*
+    let from_raw_body = gen_from_raw_body(name, side, receiver, messages, false);
+    // without it, the default implementation of the trait converts the message to `Message`
+    let from_raw_ref = if options.borrowed_parsing {
+        let from_raw_ref_body = gen_from_raw_body(name, side, receiver, messages, true);
+        Some(quote! {
+            fn from_raw_ref(msg: MessageRef, map: &mut Self::Map) -> Result<Self, ()> {
+                #from_raw_ref_body
             }
-        }
+        })
     } else {
-        let panic_message = format!("{}::from_raw can not be used {:?}-side.", name, side);
-        quote!(panic!(#panic_message))
+        None
     };
 
     let into_raw_body = if receiver {
@@ -604,8 +792,15 @@ pub(crate) fn gen_messagegroup(
         }
     };
 
+    let serde_derive = if options.serde {
+        Some(quote!(#[derive(serde::Serialize, serde::Deserialize)]))
+    } else {
+        None
+    };
+
     quote! {
         #[derive(Debug)]
+        #serde_derive
         #[non_exhaustive]
         pub enum #name {
             #(#variants,)*
@@ -616,6 +811,8 @@ pub(crate) fn gen_messagegroup(
                 #(#message_array_values,)*
             ];
 
+            #arguments
+
             type Map = super::#map_type;
 
             fn is_destructor(&self) -> bool {
@@ -646,6 +843,8 @@ pub(crate) fn gen_messagegroup(
                 #from_raw_body
             }
 
+            #from_raw_ref
+
             fn into_raw(self, sender_id: u32) -> Message {
                 #into_raw_body
             }
@@ -655,6 +854,147 @@ pub(crate) fn gen_messagegroup(
     }
 }
 
+fn gen_from_raw_body(
+    name: &Ident,
+    side: Side,
+    receiver: bool,
+    messages: &[Message],
+    borrowed: bool,
+) -> TokenStream {
+    if receiver {
+        let match_arms = messages
+        .iter()
+        .enumerate()
+        .map(|(opcode, msg)| {
+            let pattern = Literal::u16_unsuffixed(opcode as u16);
+            let msg_type = Ident::new(&snake_to_camel(&msg.name), Span::call_site());
+            let msg_type_qualified = quote!(#name::#msg_type);
+
+            let block = if msg.args.is_empty() {
+                quote!(Ok(#msg_type_qualified))
+            } else {
+                let fields = msg.args.iter().map(|arg| {
+                    let field_name = Ident::new(
+                        &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
+                        Span::call_site(),
+                    );
+                    let some_code_path = match arg.typ {
+                        Type::Int => {
+                            if let Some(ref enu) = arg.enum_ {
+                                let enum_ident = dotted_to_relname(enu);
+                                quote!(#enum_ident::from_raw(val as u32).ok_or(())?)
+                            } else {
+                                quote!(val)
+                            }
+                        }
+                        Type::Uint => {
+                            if let Some(ref enu) = arg.enum_ {
+                                let enum_ident = dotted_to_relname(enu);
+                                quote!(#enum_ident::from_raw(val).ok_or(())?)
+                            } else {
+                                quote!(val)
+                            }
+                        }
+                        Type::Fixed => quote!((val as f64) / 256.),
+                        Type::Array => {
+                            let array = if borrowed { quote!(val.to_vec()) } else { quote!(*val) };
+                            if arg.allow_null {
+                                quote!(if val.len() == 0 { None } else { Some(#array) })
+                            } else {
+                                array
+                            }
+                        }
+                        Type::String => {
+                            let string_conversion = if borrowed {
+                                quote! {
+                                    let s = String::from_utf8_lossy(val.to_bytes()).into_owned();
+                                }
+                            } else {
+                                quote! {
+                                    let s = String::from_utf8(val.into_bytes())
+                                        .unwrap_or_else(|e| String::from_utf8_lossy(&e.into_bytes()).into());
+                                }
+                            };
+
+                            if arg.allow_null {
+                                quote! {
+                                    #string_conversion
+                                    if s.len() == 0 { None } else { Some(s) }
+                                }
+                            } else {
+                                quote! {
+                                    #string_conversion
+                                    s
+                                }
+                            }
+                        }
+                        Type::Fd => quote!(val),
+                        Type::Object => {
+                            let map_lookup = if side == Side::Client {
+                                quote!(map.get_or_dead(val).into())
+                            } else {
+                                quote!(map.get(val).ok_or(())?.into())
+                            };
+                            if arg.allow_null {
+                                quote!(if val == 0 { None } else { Some(#map_lookup) })
+                            } else {
+                                map_lookup
+                            }
+                        }
+                        Type::NewId => {
+                            let map_lookup = quote!(map.get_new(val).ok_or(())?);
+                            if arg.allow_null {
+                                quote!(if val == 0 { None } else { Some(#map_lookup) })
+                            } else {
+                                map_lookup
+                            }
+                        }
+                        Type::Destructor => panic!("An argument cannot have type destructor!"),
+                    };
+
+                    let common_type = arg.typ.common_type();
+                    let argument = if borrowed { quote!(ArgumentRef) } else { quote!(Argument) };
+
+                    quote! {
+                        #field_name: {
+                            if let Some(#argument::#common_type(val)) = args.next() {
+                                #some_code_path
+                            } else {
+                                return Err(());
+                            }
+                        }
+                    }
+                });
+
+                let args = if borrowed { quote!(msg.args()) } else { quote!(msg.args.into_iter()) };
+
+                quote! {
+                    {
+                        let mut args = #args;
+
+                        Ok(#msg_type_qualified {
+                            #(#fields,)*
+                        })
+                    }
+                }
+            };
+
+            quote!(#pattern => #block)
+        })
+        .chain(iter::once(quote!(_ => Err(()))));
+
+        quote! {
+            match msg.opcode {
+                #(#match_arms,)*
+            }
+        }
+    } else {
+        let method = if borrowed { "from_raw_ref" } else { "from_raw" };
+        let panic_message = format!("{}::{} can not be used {:?}-side.", name, method, side);
+        quote!(panic!(#panic_message))
+    }
+}
+
 pub(crate) fn gen_interface(
     name: &Ident,
     low_name: &str,
@@ -666,7 +1006,7 @@ pub(crate) fn gen_interface(
     let version_lit = Literal::u32_unsuffixed(version);
 
     quote! {
-        #[derive(Clone, Eq, PartialEq)]
+        #[derive(Clone, Eq, PartialEq, Hash)]
         pub struct #name(#object_type<#name>);
 
         impl AsRef<#object_type<#name>> for #name {
@@ -707,6 +1047,43 @@ pub(crate) fn gen_interface(
     }
 }
 
+// The parameter of a method sending a message, if the argument is not its return value
+fn method_arg(arg: &Arg, side: Side) -> Option<TokenStream> {
+    let arg_type_inner = if let Some(ref name) = arg.enum_ {
+        dotted_to_relname(name)
+    } else {
+        let mut typ = arg.typ;
+        if typ == Type::NewId && side == Side::Server {
+            typ = Type::Object;
+        }
+        match typ {
+            Type::Object => arg
+                .interface
+                .as_ref()
+                .map(|iface| {
+                    let iface_mod = Ident::new(iface, Span::call_site());
+                    let iface_type = Ident::new(&snake_to_camel(iface), Span::call_site());
+                    quote!(&super::#iface_mod::#iface_type)
+                })
+                .unwrap_or(quote!(&super::AnonymousObject)),
+            Type::NewId => {
+                // client-side, the return-type handles that
+                return None;
+            }
+            _ => arg.typ.rust_type(),
+        }
+    };
+
+    let arg_name = Ident::new(
+        &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
+        Span::call_site(),
+    );
+
+    let arg_type = if arg.allow_null { quote!(Option<#arg_type_inner>) } else { arg_type_inner };
+
+    Some(quote!(#arg_name: #arg_type))
+}
+
 pub fn method_prototype<'a>(
     iname: &Ident,
     msg: &'a Message,
@@ -745,42 +1122,7 @@ pub fn method_prototype<'a>(
     }
     .into_iter();
 
-    args.extend(msg.args.iter().filter_map(|arg| {
-        let arg_type_inner = if let Some(ref name) = arg.enum_ {
-            dotted_to_relname(name)
-        } else {
-            let mut typ = arg.typ;
-            if typ == Type::NewId && side == Side::Server {
-                typ = Type::Object;
-            }
-            match typ {
-                Type::Object => arg
-                    .interface
-                    .as_ref()
-                    .map(|iface| {
-                        let iface_mod = Ident::new(iface, Span::call_site());
-                        let iface_type = Ident::new(&snake_to_camel(iface), Span::call_site());
-                        quote!(&super::#iface_mod::#iface_type)
-                    })
-                    .unwrap_or(quote!(&super::AnonymousObject)),
-                Type::NewId => {
-                    // client-side, the return-type handles that
-                    return None;
-                }
-                _ => arg.typ.rust_type(),
-            }
-        };
-
-        let arg_name = Ident::new(
-            &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
-            Span::call_site(),
-        );
-
-        let arg_type =
-            if arg.allow_null { quote!(Option<#arg_type_inner>) } else { arg_type_inner };
-
-        Some(quote!(#arg_name: #arg_type))
-    }));
+    args.extend(msg.args.iter().filter_map(|arg| method_arg(arg, side)));
 
     let return_type = if let Some(arg) = newid {
         match arg.interface {
@@ -803,6 +1145,48 @@ pub fn method_prototype<'a>(
     (prototype, newid)
 }
 
+// The fields of a message sent by a method, given its parameters
+fn message_init(msg: &Message, side: Side) -> TokenStream {
+    if msg.args.is_empty() {
+        TokenStream::new()
+    } else {
+        let args = msg.args.iter().flat_map(|arg| {
+            let arg_name = Ident::new(
+                &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
+                Span::call_site(),
+            );
+            let arg_value = match (arg.typ, side) {
+                (Type::NewId, Side::Client) => {
+                    if arg.interface.is_some() {
+                        return None;
+                    } else {
+                        quote!((T::NAME.into(), version))
+                    }
+                }
+                (Type::NewId, Side::Server) => {
+                    if arg.allow_null {
+                        quote!(#arg_name.map(|o| o.as_ref().clone()))
+                    } else {
+                        quote!(#arg_name.as_ref().clone())
+                    }
+                }
+                (Type::Object, _) => {
+                    if arg.allow_null {
+                        quote!(#arg_name.map(|o| o.clone()))
+                    } else {
+                        quote!(#arg_name.clone())
+                    }
+                }
+                _ => quote!(#arg_name),
+            };
+
+            Some(quote!(#arg_name: #arg_value))
+        });
+
+        quote!({ #(#args),* })
+    }
+}
+
 pub(crate) fn gen_object_methods(name: &Ident, messages: &[Message], side: Side) -> TokenStream {
     let outgoing_message_type = Ident::new(
         match side {
@@ -829,44 +1213,7 @@ pub(crate) fn gen_object_methods(name: &Ident, messages: &[Message], side: Side)
         let msg_name = Ident::new(&snake_to_camel(&msg.name), Span::call_site());
         let (proto, return_type) = method_prototype(name, &msg, side);
 
-        let msg_init = if msg.args.is_empty() {
-            TokenStream::new()
-        } else {
-            let args = msg.args.iter().flat_map(|arg| {
-                let arg_name = Ident::new(
-                    &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
-                    Span::call_site(),
-                );
-                let arg_value = match (arg.typ, side) {
-                    (Type::NewId, Side::Client) => {
-                        if arg.interface.is_some() {
-                            return None;
-                        } else {
-                            quote!((T::NAME.into(), version))
-                        }
-                    },
-                    (Type::NewId, Side::Server) => {
-                        if arg.allow_null {
-                            quote!(#arg_name.map(|o| o.as_ref().clone()))
-                        } else {
-                            quote!(#arg_name.as_ref().clone())
-                        }
-                    }
-                    (Type::Object, _) => {
-                        if arg.allow_null {
-                            quote!(#arg_name.map(|o| o.clone()))
-                        } else {
-                            quote!(#arg_name.clone())
-                        }
-                    }
-                    _ => quote!(#arg_name),
-                };
-
-                Some(quote!(#arg_name: #arg_value))
-            });
-
-            quote!({ #(#args),* })
-        };
+        let msg_init = message_init(msg, side);
 
         let send_stmt = match return_type {
             Some(ret_type) if ret_type.interface.is_none() => {
@@ -895,3 +1242,293 @@ pub(crate) fn gen_object_methods(name: &Ident, messages: &[Message], side: Side)
         }
     }
 }
+
+pub(crate) fn gen_checked_events(name: &Ident, events: &[Message]) -> TokenStream {
+    let methods = events
+        .iter()
+        .filter(|msg| msg.since > 1)
+        .map(|msg| {
+            let doc_attr = to_doc_attr(&format!(
+                "Send a `{}` event, checking the version of the resource\n\n\
+             This event is only available since version {} of the interface, the `check` decides \
+             what to do if the version of the resource is lower.",
+                msg.name, msg.since
+            ));
+            let fn_name = Ident::new(&format!("{}_checked", msg.name), Span::call_site());
+            let msg_name = Ident::new(&snake_to_camel(&msg.name), Span::call_site());
+            let args = msg.args.iter().filter_map(|arg| method_arg(arg, Side::Server));
+            let msg_init = message_init(msg, Side::Server);
+
+            quote! {
+                #doc_attr
+                pub fn #fn_name(
+                    &self,
+                    check: super::VersionCheck,
+                    #(#args),*
+                ) -> Result<(), super::VersionTooLow> {
+                    let msg = Event::#msg_name #msg_init;
+                    self.0.send_checked(msg, check)
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if methods.is_empty() {
+        return TokenStream::new();
+    }
+
+    quote! {
+        impl #name {
+            #(#methods)*
+        }
+    }
+}
+
+pub(crate) fn gen_borrowed_events(events: &[Message]) -> TokenStream {
+    // there is nothing to borrow from the other messages
+    let borrows = events
+        .iter()
+        .flat_map(|msg| msg.args.iter())
+        .any(|arg| arg.typ == Type::String || arg.typ == Type::Array);
+    if !borrows {
+        return TokenStream::new();
+    }
+
+    let field_name = |arg: &Arg| {
+        Ident::new(
+            &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
+            Span::call_site(),
+        )
+    };
+
+    let variants = events.iter().map(|msg| {
+        let doc_attr = msg.description.as_ref().map(description_to_doc_attr);
+        let msg_name = Ident::new(&snake_to_camel(&msg.name), Span::call_site());
+        if msg.args.is_empty() {
+            return quote!(#doc_attr #msg_name);
+        }
+        let fields = msg.args.iter().map(|arg| {
+            let field_name = field_name(arg);
+            let field_type = if let Some(ref enu) = arg.enum_ {
+                dotted_to_relname(enu)
+            } else {
+                match arg.typ {
+                    Type::Uint | Type::Object | Type::NewId => quote!(u32),
+                    Type::Int => quote!(i32),
+                    Type::Fixed => quote!(f64),
+                    Type::String => quote!(&'a str),
+                    Type::Array => quote!(&'a [u8]),
+                    Type::Fd => quote!(::std::os::unix::io::RawFd),
+                    Type::Destructor => panic!("An argument cannot have type \"destructor\"."),
+                }
+            };
+            if arg.allow_null {
+                quote!(#field_name: Option<#field_type>)
+            } else {
+                quote!(#field_name: #field_type)
+            }
+        });
+        quote!(#doc_attr #msg_name { #(#fields),* })
+    });
+
+    let parse_arms = events.iter().enumerate().map(|(opcode, msg)| {
+        let pattern = Literal::u16_unsuffixed(opcode as u16);
+        let msg_name = Ident::new(&snake_to_camel(&msg.name), Span::call_site());
+        if msg.args.is_empty() {
+            return quote!(#pattern => Ok(EventRef::#msg_name));
+        }
+        let fields = msg.args.iter().map(|arg| {
+            let field_name = field_name(arg);
+            let value = match arg.typ {
+                Type::Int | Type::Uint if arg.enum_.is_some() => {
+                    let enum_ident = dotted_to_relname(arg.enum_.as_ref().unwrap());
+                    if arg.typ == Type::Int {
+                        quote!(#enum_ident::from_raw(val as u32).ok_or(())?)
+                    } else {
+                        quote!(#enum_ident::from_raw(val).ok_or(())?)
+                    }
+                }
+                Type::Fixed => quote!((val as f64) / 256.),
+                // the strings are only checked to be valid UTF-8, not copied
+                Type::String => quote!(val.to_str().map_err(|_| ())?),
+                _ => quote!(val),
+            };
+            let value = if !arg.allow_null {
+                value
+            } else if arg.typ == Type::String {
+                quote!(if val.to_bytes().is_empty() { None } else { Some(#value) })
+            } else if arg.typ == Type::Array {
+                quote!(if val.is_empty() { None } else { Some(#value) })
+            } else {
+                quote!(if val == 0 { None } else { Some(#value) })
+            };
+            let common_type = arg.typ.common_type();
+            quote! {
+                #field_name: {
+                    if let Some(ArgumentRef::#common_type(val)) = args.next() {
+                        #value
+                    } else {
+                        return Err(());
+                    }
+                }
+            }
+        });
+        quote! {
+            #pattern => {
+                let mut args = msg.args();
+                Ok(EventRef::#msg_name { #(#fields,)* })
+            }
+        }
+    });
+
+    let owned_arms = events.iter().map(|msg| {
+        let msg_name = Ident::new(&snake_to_camel(&msg.name), Span::call_site());
+        if msg.args.is_empty() {
+            return quote!(EventRef::#msg_name => Event::#msg_name);
+        }
+        let names = msg.args.iter().map(field_name).collect::<Vec<_>>();
+        let fields = msg.args.iter().map(|arg| {
+            let field_name = field_name(arg);
+            let value = |val: &Ident| match arg.typ {
+                Type::String => Some(quote!(#val.to_owned())),
+                Type::Array => Some(quote!(#val.to_vec())),
+                Type::Object => Some(quote!(map.get_or_dead(#val).into())),
+                Type::NewId => Some(quote!(map.get_new(#val).ok_or(())?)),
+                _ => None,
+            };
+            let val = Ident::new("val", Span::call_site());
+            match value(&val) {
+                Some(value) if arg.allow_null => {
+                    quote!(#field_name: match #field_name { Some(val) => Some(#value), None => None })
+                }
+                Some(_) => {
+                    let value = value(&field_name);
+                    quote!(#field_name: #value)
+                }
+                None => quote!(#field_name),
+            }
+        });
+        quote! {
+            EventRef::#msg_name { #(#names),* } => Event::#msg_name { #(#fields,)* }
+        }
+    });
+
+    quote! {
+        /// The events of this interface, borrowing their contents from the received message
+        ///
+        /// Its variants mirror the ones of `Event`, except that strings and arrays borrow from
+        /// the message, and objects are given as their protocol id.
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        #[non_exhaustive]
+        pub enum EventRef<'a> {
+            #(#variants,)*
+        }
+
+        impl<'a> EventRef<'a> {
+            /// Decode an event without allocating, borrowing from the message
+            pub fn from_raw_ref(msg: MessageRef<'a>) -> Result<EventRef<'a>, ()> {
+                match msg.opcode {
+                    #(#parse_arms,)*
+                    _ => Err(()),
+                }
+            }
+
+            /// Convert this event to its owned version
+            ///
+            /// The objects are looked up in the map, like when decoding an `Event`.
+            pub fn into_owned(self, map: &mut super::ProxyMap) -> Result<Event, ()> {
+                Ok(match self {
+                    #(#owned_arms,)*
+                })
+            }
+        }
+    }
+}
+
+pub(crate) fn gen_post_error(name: &Ident, enums: &[Enum]) -> TokenStream {
+    if !enums.iter().any(|enu| enu.name == "error" && !enu.bitfield) {
+        return TokenStream::new();
+    }
+
+    quote! {
+        impl #name {
+            /// Posts a protocol error of this interface to this resource
+            ///
+            /// An error is fatal to the client that caused it.
+            pub fn post_error(&self, error: Error, msg: String) {
+                self.0.post_error(error.to_raw(), msg)
+            }
+        }
+    }
+}
+
+// The event answering a request, if the request creates an object of this protocol whose
+// only event is a destructor, like `wl_display.sync` and its `wl_callback`
+fn response_event<'a>(protocol: &'a Protocol, msg: &Message) -> Option<&'a Message> {
+    if msg.typ == Some(Type::Destructor) {
+        return None;
+    }
+    let iface_name = msg.args.iter().find(|arg| arg.typ == Type::NewId)?.interface.as_ref()?;
+    let iface = protocol.interfaces.iter().find(|iface| &iface.name == iface_name)?;
+    match iface.events[..] {
+        [ref event] if event.typ == Some(Type::Destructor) => Some(event),
+        _ => None,
+    }
+}
+
+pub(crate) fn gen_async_helpers(
+    name: &Ident,
+    requests: &[Message],
+    protocol: &Protocol,
+) -> TokenStream {
+    let helpers = requests
+        .iter()
+        .filter_map(|msg| response_event(protocol, msg).map(|event| (msg, event)))
+        .map(|(msg, event)| {
+            let mut docs = format!(
+                "Send a `{}` request, resolving once the `{}` event of the new object is received\n\n\
+                 The request is sent immediately, and the future resolves with the event when it \
+                 is dispatched by the event queue of the new object.",
+                msg.name, event.name
+            );
+            if msg.since > 1 {
+                docs += &format!("\n\nOnly available since version {} of the interface.", msg.since);
+            }
+            let doc_attr = to_doc_attr(&docs);
+
+            let fn_name = Ident::new(&format!("{}_async", msg.name), Span::call_site());
+            let method_name = Ident::new(
+                &format!("{}{}", if is_keyword(&msg.name) { "_" } else { "" }, msg.name),
+                Span::call_site(),
+            );
+            let args = msg.args.iter().filter_map(|arg| method_arg(arg, Side::Client));
+            let arg_names = msg.args.iter().filter(|arg| arg.typ != Type::NewId).map(|arg| {
+                Ident::new(
+                    &format!("{}{}", if is_keyword(&arg.name) { "_" } else { "" }, arg.name),
+                    Span::call_site(),
+                )
+            });
+            let iface = msg.args.iter().find(|arg| arg.typ == Type::NewId).unwrap();
+            let iface = iface.interface.as_ref().unwrap();
+            let iface_mod = Ident::new(iface, Span::call_site());
+            let iface_type = Ident::new(&snake_to_camel(iface), Span::call_site());
+
+            quote! {
+                #doc_attr
+                pub fn #fn_name(&self, #(#args),*) -> super::ResponseFuture<super::#iface_mod::#iface_type> {
+                    super::ResponseFuture::new(self.#method_name(#(#arg_names),*))
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if helpers.is_empty() {
+        return TokenStream::new();
+    }
+
+    quote! {
+        impl #name {
+            #(#helpers)*
+        }
+    }
+}
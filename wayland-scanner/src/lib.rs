@@ -68,7 +68,7 @@
 //!
 //!     pub mod client {
 //!         // These imports are used by the generated code
-//!         pub(crate) use wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject};
+//!         pub(crate) use wayland_client::{Main, Attached, Proxy, ProxyMap, AnonymousObject, ResponseFuture};
 //!         pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
 //!         pub(crate) use wayland_commons::{Interface, MessageGroup};
 //!         pub(crate) use wayland_commons::wire::{Argument, MessageDesc, ArgumentType, Message};
@@ -82,6 +82,23 @@
 //!     }
 //! }
 //! ```
+//!
+//! ## Command line interface
+//!
+//! If you prefer to check the generated code in your repository, the `wayland-scanner-rs`
+//! binary of this crate generates it from the command line:
+//!
+//! ```text
+//! wayland-scanner-rs client my_protocol.xml src/my_protocol_api.rs
+//! ```
+//!
+//! ## Reproducible output
+//!
+//! The generated code only depends on the protocol file: interfaces, messages and arguments
+//! are generated in the order of the file. By default the code written to files is formatted
+//! with `rustfmt` if it is available, so its layout depends on the installed version of
+//! `rustfmt`. The `pretty_print` cargo feature formats the code with `prettyplease` instead,
+//! for all the functions of this crate, so that the output is the same on every machine.
 
 #![warn(missing_docs)]
 // disable clippy lints that are not compatible with rust 1.41
@@ -102,6 +119,179 @@ mod util;
 
 pub use side::Side;
 
+// Render the generated code, formatted with prettyplease if the `pretty_print` feature is enabled
+fn render(code: proc_macro2::TokenStream) -> String {
+    #[cfg(feature = "pretty_print")]
+    {
+        let file: syn::File = syn::parse2(code).expect("The generated code could not be parsed.");
+        prettyplease::unparse(&file)
+    }
+    #[cfg(not(feature = "pretty_print"))]
+    {
+        code.to_string()
+    }
+}
+
+/// Options of the code generation
+///
+/// They are given to `generate_code_with_options` or `generate_code_streams_with_options`.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    destructor_events: Vec<(String, String)>,
+    serde: bool,
+    async_helpers: bool,
+    checked_events: bool,
+    argument_metadata: bool,
+    borrowed_events: bool,
+    borrowed_parsing: bool,
+    interface_description: bool,
+    no_std: bool,
+}
+
+impl Options {
+    /// The default options
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// Mark some events as being destructors
+    ///
+    /// The events are given in the format `("interface_name", "event_name")`, as this
+    /// information is not encoded in the protocol files but instead written in the protocol
+    /// documentation.
+    pub fn destructor_events(mut self, events: &[(&str, &str)]) -> Options {
+        self.destructor_events
+            .extend(events.iter().map(|&(iface, event)| (iface.to_owned(), event.to_owned())));
+        self
+    }
+
+    /// Implement `serde::Serialize` and `serde::Deserialize` for the generated messages
+    ///
+    /// The `Request` and `Event` enums and the enums of the protocol implement these traits,
+    /// and the crate including the generated code needs to depend on `serde`. Enums are
+    /// serialized as their raw value.
+    ///
+    /// Objects are serialized as their protocol id, and cannot be deserialized as there is no
+    /// connection to create them on: deserializing a message containing an object fails, unless
+    /// the object is null. File descriptors are serialized as a placeholder, and deserialized as
+    /// `-1`.
+    pub fn serde(mut self, serde: bool) -> Options {
+        self.serde = serde;
+        self
+    }
+
+    /// Generate futures for the requests answered by a single event
+    ///
+    /// For each request creating an object whose only event is a destructor, like
+    /// `wl_display.sync` and `wl_surface.frame`, an additional `<request>_async` method sends
+    /// the request and returns a `ResponseFuture` resolving with the event of the new object.
+    /// This only concerns client-side code and objects of the same protocol, and the module
+    /// including the generated code needs to import `wayland_client::ResponseFuture`.
+    pub fn async_helpers(mut self, async_helpers: bool) -> Options {
+        self.async_helpers = async_helpers;
+        self
+    }
+
+    /// Generate version-checked methods for the events of the protocol
+    ///
+    /// For each event that is not available since the first version of its interface, an
+    /// additional `<event>_checked` method takes a `VersionCheck` deciding whether the event is
+    /// skipped, an error is returned, or the method panics if the version of the resource is
+    /// too low. This only concerns server-side code, and the module including the generated
+    /// code needs to import `wayland_server::{VersionCheck, VersionTooLow}`.
+    pub fn checked_events(mut self, checked_events: bool) -> Options {
+        self.checked_events = checked_events;
+        self
+    }
+
+    /// Generate the validation metadata of the arguments of the messages
+    ///
+    /// The `MessageGroup::ARGUMENTS` constant of the generated messages describes which of
+    /// their arguments can be null and which values their enum arguments can take, so that
+    /// the rust implementations can reject invalid messages when decoding them with
+    /// `Strictness::Strict`. The module including the generated code needs to import
+    /// `wayland_commons::wire::ArgumentDesc`.
+    pub fn argument_metadata(mut self, argument_metadata: bool) -> Options {
+        self.argument_metadata = argument_metadata;
+        self
+    }
+
+    /// Generate borrowed versions of the events of the protocol
+    ///
+    /// Each interface module gets an `EventRef<'a>` enum, mirroring `Event` except that its
+    /// strings and arrays borrow from a `MessageRef` and its objects are given as their id.
+    /// `EventRef::from_raw_ref()` decodes it without allocating, which avoids copying the
+    /// payload of high-rate events, and `EventRef::into_owned()` converts it to an `Event`.
+    /// Only the interfaces with events carrying strings or arrays get one, and this only
+    /// concerns client-side code. The module including the generated code needs to import
+    /// `wayland_commons::wire::{ArgumentRef, MessageRef}`.
+    pub fn borrowed_events(mut self, borrowed_events: bool) -> Options {
+        self.borrowed_events = borrowed_events;
+        self
+    }
+
+    /// Decode the received messages without building an intermediate `wire::Message`
+    ///
+    /// The generated messages implement `MessageGroup::from_raw_ref()`, reading their
+    /// arguments from a `MessageRef` borrowing the receive buffer, so that only their own
+    /// strings and arrays are allocated. Otherwise the default implementation of this method
+    /// converts the message to a `wire::Message` first. The module including the generated
+    /// code needs to import `wayland_commons::wire::{ArgumentRef, MessageRef}`.
+    pub fn borrowed_parsing(mut self, borrowed_parsing: bool) -> Options {
+        self.borrowed_parsing = borrowed_parsing;
+        self
+    }
+
+    /// Generate the full description of the interfaces of the protocol
+    ///
+    /// The `Interface::DESCRIPTION` constant of the generated interfaces also describes the
+    /// names, interfaces and enums of the arguments of their messages, as well as their enums,
+    /// for tooling handling arbitrary messages. The module including the generated code needs
+    /// to import `wayland_commons::wire::{InterfaceDesc, ArgumentInfo, EnumDesc, EnumEntry}`.
+    pub fn interface_description(mut self, interface_description: bool) -> Options {
+        self.interface_description = interface_description;
+        self
+    }
+
+    /// Only generate the model of the protocol, for `no_std` crates
+    ///
+    /// Instead of the objects of `wayland-client` or `wayland-server`, each interface module
+    /// only contains the enums of the interface, its `NAME` and `VERSION`, the `REQUESTS`
+    /// and `EVENTS` arrays of `MessageDesc` describing its messages and the version constants
+    /// of its messages. This code does not depend on `std`, and can be used with
+    /// `wayland-commons` built without its `std` feature to decode and encode the messages
+    /// over other transports. The side does not matter in this mode.
+    ///
+    /// The module including the generated code needs to import
+    /// `wayland_commons::wire::{MessageDesc, ArgumentType}`, as well as `ArgumentDesc` with
+    /// `argument_metadata`, and the `bitflags!` macro if the protocol has bitfields.
+    pub fn no_std(mut self, no_std: bool) -> Options {
+        self.no_std = no_std;
+        self
+    }
+}
+
+fn generate(
+    mut protocol: protocol::Protocol,
+    side: Side,
+    options: &Options,
+) -> proc_macro2::TokenStream {
+    for interface in &mut protocol.interfaces {
+        let iface_name = &interface.name;
+        for event in &mut interface.events {
+            if options.destructor_events.iter().any(|(i, e)| i == iface_name && *e == event.name) {
+                event.typ = Some(crate::protocol::Type::Destructor);
+            }
+        }
+    }
+
+    match side {
+        _ if options.no_std => c_code_gen::generate_protocol_model(protocol, options),
+        Side::Client => c_code_gen::generate_protocol_client(protocol, options),
+        Side::Server => c_code_gen::generate_protocol_server(protocol, options),
+    }
+}
+
 fn load_xml<P: AsRef<Path>>(prot: P) -> protocol::Protocol {
     let pfile = File::open(prot.as_ref())
         .unwrap_or_else(|_| panic!("Unable to open protocol file `{}`.", prot.as_ref().display()));
@@ -134,29 +324,29 @@ pub fn generate_code_with_destructor_events<P1: AsRef<Path>, P2: AsRef<Path>>(
     side: Side,
     events: &[(&str, &str)],
 ) {
-    let mut protocol = load_xml(prot);
+    generate_code_with_options(prot, target, side, &Options::new().destructor_events(events));
+}
 
-    for interface in &mut protocol.interfaces {
-        for event in &mut interface.events {
-            if events.contains(&(&interface.name, &event.name)) {
-                event.typ = Some(crate::protocol::Type::Destructor);
-            }
-        }
-    }
+/// Generate the code for a protocol with the given options
+///
+/// Same as `generate_code`, with the options of the code generation.
+pub fn generate_code_with_options<P1: AsRef<Path>, P2: AsRef<Path>>(
+    prot: P1,
+    target: P2,
+    side: Side,
+    options: &Options,
+) {
+    let protocol = load_xml(prot);
 
     {
         let mut out =
             OpenOptions::new().write(true).truncate(true).create(true).open(&target).unwrap();
-
-        let output = match side {
-            Side::Client => c_code_gen::generate_protocol_client(protocol),
-            Side::Server => c_code_gen::generate_protocol_server(protocol),
-        };
-
-        write!(&mut out, "{}", output).unwrap();
+        write!(&mut out, "{}", render(generate(protocol, side, options))).unwrap();
     }
 
-    let _ = Command::new("rustfmt").arg(target.as_ref()).status();
+    if !cfg!(feature = "pretty_print") {
+        let _ = Command::new("rustfmt").arg(target.as_ref()).status();
+    }
 }
 
 /// Generate the code for a protocol from/to IO streams
@@ -184,20 +374,23 @@ pub fn generate_code_streams_with_destructor_events<P1: Read, P2: Write>(
     side: Side,
     events: &[(&str, &str)],
 ) {
-    let mut protocol = parse::parse_stream(protocol);
-
-    for interface in &mut protocol.interfaces {
-        for event in &mut interface.events {
-            if events.contains(&(&interface.name, &event.name)) {
-                event.typ = Some(crate::protocol::Type::Destructor);
-            }
-        }
-    }
-
-    let output = match side {
-        Side::Client => c_code_gen::generate_protocol_client(protocol),
-        Side::Server => c_code_gen::generate_protocol_server(protocol),
-    };
+    generate_code_streams_with_options(
+        protocol,
+        target,
+        side,
+        &Options::new().destructor_events(events),
+    );
+}
 
-    write!(target, "{}", output).unwrap();
+/// Generate the code for a protocol from/to IO streams with the given options
+///
+/// Same as `generate_code_streams`, with the options of the code generation.
+pub fn generate_code_streams_with_options<P1: Read, P2: Write>(
+    protocol: P1,
+    target: &mut P2,
+    side: Side,
+    options: &Options,
+) {
+    let protocol = parse::parse_stream(protocol);
+    write!(target, "{}", render(generate(protocol, side, options))).unwrap();
 }
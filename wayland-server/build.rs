@@ -11,10 +11,15 @@ fn main() {
     let out_dir = Path::new(&out_dir_str);
 
     println!("cargo:rerun-if-changed={}", protocol_file);
-    generate_code_with_destructor_events(
+    generate_code_with_options(
         protocol_file,
         out_dir.join("wayland_api.rs"),
         Side::Server,
-        &[("wl_callback", "done")],
+        &Options::new()
+            .destructor_events(&[("wl_callback", "done")])
+            .checked_events(true)
+            .argument_metadata(true)
+            .borrowed_parsing(true)
+            .interface_description(true),
     );
 }
@@ -7,6 +7,21 @@ use crate::imp::ClientInner;
 
 use crate::{Interface, Main, Resource, UserDataMap};
 
+/// Information about a resource of a client
+///
+/// As returned by `Client::resources()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceInfo {
+    /// The protocol id of the resource
+    pub id: u32,
+    /// The interface of the resource
+    pub interface: &'static str,
+    /// The version of the resource
+    pub version: u32,
+    /// Whether the resource is still alive
+    pub alive: bool,
+}
+
 /// A handle to a client connected to your server
 ///
 /// There can be several handles referring to the same client.
@@ -51,6 +66,17 @@ impl Client {
         self.inner.flush()
     }
 
+    /// Number of bytes of events waiting to be written to the socket of this client
+    ///
+    /// A client that does not read its socket accumulates events, this can be used to
+    /// detect it. Returns 0 if the client is dead.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn pending_bytes(&self) -> usize {
+        self.inner.pending_bytes()
+    }
+
     /// Kills this client
     ///
     /// Does nothing if the client is already dead.
@@ -58,8 +84,19 @@ impl Client {
         self.inner.kill()
     }
 
+    /// Posts an `implementation` error of `wl_display` to this client
+    ///
+    /// This reports a failure of the compositor that is not a protocol error of the client,
+    /// and is fatal to the client.
+    pub fn post_implementation_error(&self, msg: String) {
+        self.inner.post_implementation_error(msg)
+    }
+
     /// Returns a reference to the `UserDataMap` associated with this client
     ///
+    /// It can store the state of the server for this client, and is given to the destructors
+    /// of the client when it disconnects, see `add_destructor()`.
+    ///
     /// See `UserDataMap` documentation for details about its use.
     pub fn data_map(&self) -> &UserDataMap {
         self.inner.user_data_map()
@@ -98,6 +135,38 @@ impl Client {
         self.inner.create_resource::<I>(version).map(Main::wrap)
     }
 
+    /// List the resources of this client
+    ///
+    /// This is a snapshot of the object map of the client, with the interface, id, version
+    /// and liveness of each resource, which can be used to find resources that are never
+    /// destroyed. The list is empty if the client is dead.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn enumerate_resources(&self) -> Vec<ResourceInfo> {
+        self.inner.resources()
+    }
+
+    /// Alias of `enumerate_resources()`
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn resources(&self) -> Vec<ResourceInfo> {
+        self.enumerate_resources()
+    }
+
+    /// List the live resources of this client with a given interface
+    ///
+    /// This can be used to send an event to all the objects of an interface a client
+    /// created, like a `wl_keyboard.leave` to all its keyboards. The resources are in
+    /// increasing id order, and the list is empty if the client is dead.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn resources_of<I: Interface + From<Resource<I>> + AsRef<Resource<I>>>(&self) -> Vec<I> {
+        self.inner.resources_of::<I>().into_iter().map(|obj| Resource::wrap(obj).into()).collect()
+    }
+
     /// Retrieve a resource of this client for a given id
     ///
     /// You need to know in advance which is the interface of this object. If the given id does
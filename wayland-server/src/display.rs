@@ -3,19 +3,24 @@ use std::ffi::{OsStr, OsString};
 use std::io::{Error as IoError, ErrorKind, Result as IoResult};
 use std::os::unix::io::{IntoRawFd, RawFd};
 use std::path::PathBuf;
+use std::rc::Rc;
 
 #[cfg(feature = "use_system_lib")]
 use wayland_sys::server::wl_display;
 
 use crate::imp::DisplayInner;
 
-use crate::{Client, Filter, Global, Interface, Main, Resource};
+use crate::{Client, DispatchPool, Filter, Global, Interface, Main, Resource};
 
 /// The wayland display
 ///
 /// This is the core of your wayland server, this object must
 /// be kept alive as long as your server is running. It allows
 /// you to manage listening sockets and clients.
+///
+/// A display is bound to the thread it was created on, and the clients connecting to its
+/// sockets are all dispatched on this thread. To dispatch independent clients on several
+/// threads, see `create_dispatch_pool()`.
 pub struct Display {
     inner: DisplayInner,
 }
@@ -63,6 +68,37 @@ impl Display {
         ))
     }
 
+    /// Create a new global object with some associated data
+    ///
+    /// This works like `create_global()`, except that your filter receives the data, shared
+    /// between all the binds of the global, along with the new resource and its version. This
+    /// allows creating several globals of the same interface, like outputs or seats, with a
+    /// single filter.
+    ///
+    /// `max_version` is clamped to the version of the protocol files this crate was built
+    /// with, so it can be set to the highest version your code supports.
+    pub fn create_global_with_data<I, D, E>(
+        &mut self,
+        max_version: u32,
+        filter: Filter<E>,
+        user_data: D,
+    ) -> Global<I>
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        D: 'static,
+        E: From<(Main<I>, u32, Rc<D>)> + 'static,
+    {
+        let version = std::cmp::max(std::cmp::min(max_version, I::VERSION), 1);
+        let user_data = Rc::new(user_data);
+        Global::create(self.inner.create_global(
+            version,
+            move |main, version, ddata| {
+                filter.send((main, version, user_data.clone()).into(), ddata)
+            },
+            None::<fn(_) -> bool>,
+        ))
+    }
+
     /// Create a new global object with a client filter
     ///
     /// This object will only be advertized to clients for which your
@@ -102,6 +138,30 @@ impl Display {
         ))
     }
 
+    /// Create a new global object that can be bound from any thread
+    ///
+    /// This works like `create_global()`, except that the global is also advertised to the
+    /// clients of the dispatch pools of this display. Your implementation receives the new
+    /// resource and its version, and is invoked by the thread dispatching the client that
+    /// binds the global.
+    ///
+    /// The globals created with the other methods are only advertised to the clients
+    /// dispatched on the thread of the display, but all the globals share the same names.
+    pub fn create_shared_global<I, F>(&mut self, version: u32, implementation: F) -> Global<I>
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        F: Fn(Main<I>, u32) + Send + Sync + 'static,
+    {
+        assert!(
+            version <= I::VERSION,
+            "Cannot create global {} with version {}, maximum protocol version is {}.",
+            I::NAME,
+            version,
+            I::VERSION
+        );
+        Global::create(self.inner.create_shared_global(version, implementation))
+    }
+
     /// Flush events to the clients
     ///
     /// Will send as many pending events as possible to the respective sockets of the clients.
@@ -115,6 +175,24 @@ impl Display {
         self.inner.flush_clients(data)
     }
 
+    /// Shuts the server down
+    ///
+    /// This stops accepting new clients on the listening sockets, sends the pending events to
+    /// the clients, then disconnects them, invoking the destructors of their resources and the
+    /// ones added with `Client::add_destructor()`. Clients created afterwards with
+    /// `create_client()` are served normally.
+    ///
+    /// With the rust implementation, the listening sockets are closed and their socket and lock
+    /// files are removed. With the system library, this only happens when the `Display` is
+    /// dropped.
+    ///
+    /// The provided `data` will be mutably accessible from all the callbacks that may be called
+    /// during this via the [`DispatchData`](struct.DispatchData.html) mechanism.
+    pub fn shutdown<T: std::any::Any>(&mut self, data: &mut T) {
+        let data = crate::DispatchData::wrap(data);
+        self.inner.shutdown(data)
+    }
+
     /// Dispatches all pending messages to their respective filters
     ///
     /// This method will block waiting for messages until one of these occur:
@@ -144,6 +222,32 @@ impl Display {
         self.inner.dispatch(clamped_timeout, data)
     }
 
+    /// Dispatch the requests available once the poll file descriptor was reported readable
+    ///
+    /// This is meant for event loops notifying readiness, like `mio` with the `mio` cargo feature:
+    /// it dispatches with a timeout of `0` until the file descriptor of `get_poll_fd()` is no
+    /// longer readable, which the edge-triggered notifications require. Spurious wakeups are not
+    /// an error. Never blocks.
+    ///
+    /// The provided `data` will be mutably accessible from all the callbacks, via the
+    /// [`DispatchData`](struct.DispatchData.html) mechanism. If you don't need global data, you
+    /// can just provide a `&mut ()` there.
+    pub fn dispatch_ready<T: std::any::Any>(&mut self, data: &mut T) -> IoResult<()> {
+        use nix::poll::{poll, PollFd, PollFlags};
+        loop {
+            // dispatch at least once, to resume the requests deferred by the request gates
+            self.dispatch(std::time::Duration::from_millis(0), data)?;
+            let mut fds = [PollFd::new(self.get_poll_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, 0) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(::nix::Error::Sys(::nix::errno::Errno::EINTR)) => continue,
+                Err(::nix::Error::Sys(e)) => return Err(e.into()),
+                Err(_) => unreachable!(),
+            }
+        }
+    }
+
     /// Retrieve the underlying file descriptor
     ///
     /// This file descriptor can be monitored for activity with a poll/epoll like mechanism.
@@ -154,6 +258,126 @@ impl Display {
     pub fn get_poll_fd(&self) -> RawFd {
         self.inner.get_poll_fd()
     }
+
+    /// Install a hook intercepting the events sent through all resources of an interface
+    ///
+    /// This behaves like `Resource::set_send_hook()`, for all the resources of interface `I`
+    /// of all clients. It replaces any hook previously set for this interface, and runs after
+    /// the hook of the resource if any.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn set_send_hook<I, F>(&mut self, hook: F)
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        F: FnMut(&Resource<I>, I::Event) -> Option<I::Event> + Send + 'static,
+    {
+        self.inner.set_send_hook::<I>(Some(Box::new(hook)))
+    }
+
+    /// Remove the send hook of an interface
+    ///
+    /// NOTE: This method does nothing when the `use_system_lib` feature is activated.
+    pub fn clear_send_hook<I>(&mut self)
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+    {
+        self.inner.set_send_hook::<I>(None)
+    }
+
+    /// Install a gate evaluated before dispatching each request of the resources of an interface
+    ///
+    /// This behaves like `Resource::set_request_gate()`, for all the resources of interface `I`
+    /// of all clients. It replaces any gate previously set for this interface, and runs after the
+    /// gate of the resource if any.
+    ///
+    /// The requests deferred by a gate are evaluated again at the beginning of each call to
+    /// `dispatch()`, the client being ignored until then: don't dispatch with an infinite
+    /// timeout while requests are deferred.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn set_request_gate<I, F>(&mut self, gate: F)
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        F: FnMut(&Client, &Resource<I>, u16) -> crate::GateDecision + Send + 'static,
+    {
+        self.inner.set_request_gate(I::NAME, Some(crate::resource::erase_request_gate(gate)))
+    }
+
+    /// Remove the request gate of an interface
+    ///
+    /// NOTE: This method does nothing when the `use_system_lib` feature is activated.
+    pub fn clear_request_gate<I>(&mut self)
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+    {
+        self.inner.set_request_gate(I::NAME, None)
+    }
+
+    /// Set how strictly the requests of the clients are validated
+    ///
+    /// With `Strictness::Strict`, requests containing invalid UTF-8 strings, null arguments
+    /// where the protocol does not allow them or invalid enum values are rejected, and the
+    /// client sending them is disconnected with a protocol error. This applies to the clients
+    /// connecting after this call, the default being `Strictness::Lenient`.
+    ///
+    /// NOTE: This method does nothing when the `use_system_lib` feature is activated.
+    pub fn set_strictness(&mut self, strictness: crate::Strictness) {
+        self.inner.set_strictness(strictness)
+    }
+
+    /// Set the maximum size of the messages exchanged with the clients
+    ///
+    /// By default, messages are limited to 4096 bytes like in libwayland. Raising this limit
+    /// allows sending and receiving larger messages, for example big arrays. The size is in
+    /// bytes, header included, and is capped at `wayland_commons::wire::MAX_MESSAGE_SIZE`.
+    /// Clients sending requests larger than the limit are disconnected with a protocol error.
+    /// This applies to the clients connecting after this call.
+    ///
+    /// NOTE: This method does nothing when the `use_system_lib` feature is activated.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.inner.set_max_message_size(size)
+    }
+
+    /// Check whether some events are waiting to be flushed to the clients
+    ///
+    /// This becomes `true` when an event is sent to a client, and is reset by
+    /// `flush_clients()`. It stays `true` if some events could not be written because
+    /// the socket of a client is full.
+    ///
+    /// NOTE: This method always returns `true` when the `use_system_lib` feature is
+    /// activated.
+    pub fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    /// Set a callback notified when the clients need to be flushed
+    ///
+    /// The callback is invoked when the first event is sent to a client after a call to
+    /// `flush_clients()`, so that you can schedule a single flush of all the clients, for
+    /// example once per frame or when your event loop becomes idle. As events can be sent
+    /// from any thread, it may be invoked from any of them, and must not try to flush
+    /// the clients by itself. It can however use the clients, for example to check their
+    /// `pending_bytes()`.
+    ///
+    /// This replaces any previously set callback.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn set_flush_notifier<F>(&mut self, notifier: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.inner.set_flush_notifier(Some(std::sync::Arc::new(notifier)))
+    }
+
+    /// Remove the callback set with `set_flush_notifier()`
+    ///
+    /// NOTE: This method does nothing when the `use_system_lib` feature is activated.
+    pub fn clear_flush_notifier(&mut self) {
+        self.inner.set_flush_notifier(None)
+    }
 }
 
 impl Display {
@@ -231,6 +455,24 @@ impl Display {
         let data = crate::DispatchData::wrap(data);
         Client::make(self.inner.create_client(fd, data))
     }
+
+    /// Create a pool of threads dispatching clients
+    ///
+    /// Each client created from the pool is dispatched by one of its `threads`, instead of the
+    /// thread of this display. Its requests are processed in order, but in parallel with the
+    /// requests of the clients of the other threads.
+    ///
+    /// The clients of the pool are advertised the globals created with
+    /// `create_shared_global()`. They use the strictness and maximum message size of this
+    /// display at the time the pool is created, but not its send hooks, request gates and flush
+    /// notifier. The threads flush their clients by themselves, and the filters of their
+    /// resources are given a `()` as `DispatchData`.
+    ///
+    /// NOTE: Dispatch pools are only available with the rust implementation, this method
+    /// returns an error when the `use_system_lib` feature is activated.
+    pub fn create_dispatch_pool(&mut self, threads: usize) -> IoResult<DispatchPool> {
+        self.inner.create_dispatch_pool(threads).map(DispatchPool::create)
+    }
 }
 
 #[cfg(feature = "use_system_lib")]
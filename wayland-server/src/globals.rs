@@ -6,7 +6,7 @@ use crate::imp::GlobalInner;
 ///
 /// This is given to you when you register a global to the event loop.
 ///
-/// This handle allows you do destroy the global when needed.
+/// This handle allows you to disable or destroy the global when needed.
 ///
 /// If you know you will never destroy this global, you can let this
 /// handle go out of scope.
@@ -19,6 +19,19 @@ impl<I: Interface + AsRef<Resource<I>> + From<Resource<I>>> Global<I> {
         Global { inner }
     }
 
+    /// Stops advertising the associated global object
+    ///
+    /// The clients are told that the global was removed, but the resources created from it
+    /// stay alive, and the clients that did not process the removal yet can still bind it.
+    /// You can then destroy the global once the clients had the time to do so, for example
+    /// after a few seconds, to avoid killing them with a protocol error.
+    ///
+    /// With the system library, this requires libwayland-server 1.17 or later, and does
+    /// nothing on older versions.
+    pub fn disable(&self) {
+        self.inner.disable()
+    }
+
     /// Destroys the associated global object.
     pub fn destroy(self) {
         self.inner.destroy()
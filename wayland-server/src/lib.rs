@@ -65,6 +65,12 @@
 //! yourself using the `Display::flush_clients` and `Display::dispatch` methods. The `Display::get_poll_fd`
 //! methods provides you with a file descriptor that can be used in a polling structure to integrate
 //! the wayland socket in an event loop.
+//!
+//! ## `mio` support
+//!
+//! The `mio` cargo feature implements the `Source` trait of `mio` for `Display`, to register its
+//! poll file descriptor in a `mio::Poll`. Once it is readable, dispatch the requests of the clients
+//! with `Display::dispatch_ready()`, which handles the edge-triggered notifications of `mio`.
 
 #![warn(missing_docs)]
 
@@ -77,18 +83,25 @@ extern crate wayland_sys;
 mod client;
 mod display;
 mod globals;
+#[cfg(feature = "mio")]
+mod mio_source;
+mod pool;
 mod resource;
+pub mod shm;
 
-pub use client::Client;
+pub use client::{Client, ResourceInfo};
 pub use display::Display;
 pub use globals::Global;
-pub use resource::{Main, Resource};
+pub use pool::DispatchPool;
+pub use resource::{GateDecision, Main, ObjectId, Resource, VersionCheck, VersionTooLow, Weak};
 
 pub use anonymous_object::AnonymousObject;
 pub use wayland_commons::user_data::UserDataMap;
 pub use wayland_commons::{
     filter::{DispatchData, Filter},
-    Interface, MessageGroup, NoMessage,
+    set_thread_guard_policy,
+    wire::Strictness,
+    Interface, MessageGroup, NoMessage, ThreadGuardPolicy, ThreadGuardViolation,
 };
 
 /// C-associated types
@@ -116,10 +129,15 @@ pub mod protocol {
     #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
     #![allow(missing_docs, clippy::all)]
 
-    pub(crate) use crate::{AnonymousObject, Main, Resource, ResourceMap};
+    pub(crate) use crate::{
+        AnonymousObject, Main, Resource, ResourceMap, VersionCheck, VersionTooLow,
+    };
     pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
     pub(crate) use wayland_commons::smallvec;
-    pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+    pub(crate) use wayland_commons::wire::{
+        Argument, ArgumentDesc, ArgumentInfo, ArgumentRef, ArgumentType, EnumDesc, EnumEntry,
+        InterfaceDesc, Message, MessageDesc, MessageRef,
+    };
     pub(crate) use wayland_commons::{Interface, MessageGroup};
     pub(crate) use wayland_sys as sys;
     include!(concat!(env!("OUT_DIR"), "/wayland_api.rs"));
@@ -133,7 +151,7 @@ mod anonymous_object {
     ///
     /// A special Interface implementation representing an
     /// handle to an object for which the interface is not known.
-    #[derive(Clone, Eq, PartialEq)]
+    #[derive(Clone, Eq, PartialEq, Hash)]
     pub struct AnonymousObject(Resource<AnonymousObject>);
 
     impl Interface for AnonymousObject {
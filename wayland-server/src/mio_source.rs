@@ -0,0 +1,37 @@
+//! Implementation of the `mio` `Source` trait
+//!
+//! The `Display` registers its poll file descriptor, which covers both the listening sockets
+//! and the connections of the clients. Once it is reported readable, the requests are
+//! dispatched with `Display::dispatch_ready()`.
+
+use std::io;
+
+use mio::event::Source;
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+
+use crate::Display;
+
+impl Source for Display {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.get_poll_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.get_poll_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.get_poll_fd()).deregister(registry)
+    }
+}
@@ -9,7 +9,10 @@ use wayland_commons::ThreadGuard;
 use wayland_sys::server::*;
 
 use super::resource::ResourceInner;
-use crate::{DispatchData, Interface, Resource, UserDataMap};
+use crate::{DispatchData, Interface, Resource, ResourceInfo, UserDataMap};
+
+// the `implementation` error code of `wl_display`
+const WL_DISPLAY_ERROR_IMPLEMENTATION: u32 = 3;
 
 type BoxedDest = Box<dyn FnMut(Arc<UserDataMap>, DispatchData<'_>) + 'static>;
 
@@ -100,6 +103,29 @@ impl ClientInner {
         }
     }
 
+    pub(crate) fn post_implementation_error(&self, msg: String) {
+        if !self.alive() {
+            return;
+        }
+        // the message is used as a format string by libwayland
+        let msg = msg.replace('%', "%%");
+        let _c_safety_guard = super::C_SAFETY.lock();
+        unsafe {
+            let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_client_get_object, self.ptr, 1);
+            if display.is_null() {
+                return;
+            }
+            let cstring = ::std::ffi::CString::from_vec_unchecked(msg.into());
+            ffi_dispatch!(
+                WAYLAND_SERVER_HANDLE,
+                wl_resource_post_error,
+                display,
+                WL_DISPLAY_ERROR_IMPLEMENTATION,
+                cstring.as_ptr()
+            )
+        }
+    }
+
     pub(crate) fn user_data_map(&self) -> &UserDataMap {
         &self.internal.user_data_map
     }
@@ -146,6 +172,18 @@ impl ClientInner {
         }
     }
 
+    pub(crate) fn pending_bytes(&self) -> usize {
+        panic!("[wayland-server] Counting the pending bytes is only available with the rust implementation.")
+    }
+
+    pub(crate) fn resources(&self) -> Vec<ResourceInfo> {
+        panic!("[wayland-server] Listing the resources is only available with the rust implementation.")
+    }
+
+    pub(crate) fn resources_of<I: Interface>(&self) -> Vec<ResourceInner> {
+        panic!("[wayland-server] Listing the resources is only available with the rust implementation.")
+    }
+
     pub(crate) fn get_resource<I: Interface + From<Resource<I>> + AsRef<Resource<I>>>(
         &self,
         id: u32,
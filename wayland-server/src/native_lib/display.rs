@@ -6,14 +6,15 @@ use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::os::unix::io::RawFd;
 use std::ptr;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use wayland_sys::server::*;
 
 use super::globals::GlobalData;
-use super::{ClientInner, GlobalInner};
+use super::{ClientInner, DispatchPoolInner, GlobalInner, RequestGate, SendHook};
 
 use crate::display::get_runtime_dir;
-use crate::{Interface, Main, Resource};
+use crate::{Interface, Main, Resource, Strictness};
 
 pub(crate) struct DisplayInner {
     pub(crate) ptr: *mut wl_display,
@@ -93,6 +94,30 @@ impl DisplayInner {
         }
     }
 
+    pub(crate) fn create_shared_global<I, F>(
+        &mut self,
+        version: u32,
+        implementation: F,
+    ) -> GlobalInner<I>
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        F: Fn(Main<I>, u32) + Send + Sync + 'static,
+    {
+        // all the clients are dispatched on the thread of the display
+        self.create_global(
+            version,
+            move |main, version, _| implementation(main, version),
+            None::<fn(_) -> bool>,
+        )
+    }
+
+    pub(crate) fn create_dispatch_pool(&mut self, _threads: usize) -> IoResult<DispatchPoolInner> {
+        Err(IoError::new(
+            ErrorKind::Other,
+            "[wayland-server] Dispatch pools are only available with the rust implementation.",
+        ))
+    }
+
     pub(crate) fn flush_clients(&mut self, data: crate::DispatchData) {
         super::with_dispatch_data(data, || {
             let _c_safety_guard = super::C_SAFETY.lock();
@@ -100,6 +125,16 @@ impl DisplayInner {
         })
     }
 
+    pub(crate) fn shutdown(&mut self, data: crate::DispatchData) {
+        super::with_dispatch_data(data, || {
+            let _c_safety_guard = super::C_SAFETY.lock();
+            unsafe {
+                ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_flush_clients, self.ptr);
+                ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy_clients, self.ptr);
+            }
+        })
+    }
+
     pub(crate) fn dispatch(
         &mut self,
         timeout: i32,
@@ -205,6 +240,38 @@ impl DisplayInner {
         });
         ClientInner::from_ptr(ret)
     }
+
+    pub(crate) fn set_send_hook<I: Interface>(&mut self, hook: Option<SendHook<I>>) {
+        if hook.is_some() {
+            panic!("[wayland-server] Send hooks are only available with the rust implementation.");
+        }
+    }
+
+    pub(crate) fn set_request_gate(&mut self, _interface: &'static str, gate: Option<RequestGate>) {
+        if gate.is_some() {
+            panic!(
+                "[wayland-server] Request gates are only available with the rust implementation."
+            );
+        }
+    }
+
+    // libwayland does its own validation, with a fixed maximum message size
+    pub(crate) fn set_strictness(&mut self, _strictness: Strictness) {}
+
+    pub(crate) fn set_max_message_size(&mut self, _size: usize) {}
+
+    // libwayland does not tell whether some events are waiting to be flushed
+    pub(crate) fn needs_flush(&self) -> bool {
+        true
+    }
+
+    pub(crate) fn set_flush_notifier(&mut self, notifier: Option<Arc<dyn Fn() + Send + Sync>>) {
+        if notifier.is_some() {
+            panic!(
+                "[wayland-server] Flush notifiers are only available with the rust implementation."
+            );
+        }
+    }
 }
 
 unsafe extern "C" fn client_created(_listener: *mut wl_listener, data: *mut c_void) {
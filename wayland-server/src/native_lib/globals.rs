@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::os::raw::c_void;
 use std::rc::Rc;
 
@@ -29,6 +29,7 @@ pub(crate) struct GlobalInner<I: Interface + AsRef<Resource<I>> + From<Resource<
     ptr: *mut wl_global,
     data: *mut GlobalData<I>,
     rust_globals: Rc<RefCell<Vec<*mut wl_global>>>,
+    removed: Cell<bool>,
 }
 
 impl<I> GlobalInner<I>
@@ -40,7 +41,17 @@ where
         data: Box<GlobalData<I>>,
         rust_globals: Rc<RefCell<Vec<*mut wl_global>>>,
     ) -> GlobalInner<I> {
-        GlobalInner { ptr, data: Box::into_raw(data), rust_globals }
+        GlobalInner { ptr, data: Box::into_raw(data), rust_globals, removed: Cell::new(false) }
+    }
+
+    pub fn disable(&self) {
+        let _c_safety_guard = super::C_SAFETY.lock();
+        // libwayland complains if a global is removed twice
+        if has_global_remove() && !self.removed.replace(true) {
+            unsafe {
+                ffi_dispatch!(WAYLAND_SERVER_GLOBAL_REMOVE_HANDLE, wl_global_remove, self.ptr);
+            }
+        }
     }
 
     pub fn destroy(self) {
@@ -7,12 +7,14 @@ use crate::{Interface, Main, Resource};
 mod client;
 mod display;
 mod globals;
+mod pool;
 mod resource;
 
 pub(crate) use self::client::ClientInner;
 pub(crate) use self::display::DisplayInner;
 pub(crate) use self::globals::GlobalInner;
-pub(crate) use self::resource::ResourceInner;
+pub(crate) use self::pool::DispatchPoolInner;
+pub(crate) use self::resource::{RequestGate, ResourceInner, SendHook, WeakResourceInner};
 
 lazy_static::lazy_static! {
     // This lock *must* be held whenever an ffi call is made to
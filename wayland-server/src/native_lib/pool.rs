@@ -0,0 +1,16 @@
+use std::os::unix::io::RawFd;
+
+use super::ClientInner;
+
+// dispatch pools are not available with the system library, and can not be created
+pub(crate) enum DispatchPoolInner {}
+
+impl DispatchPoolInner {
+    pub(crate) fn threads(&self) -> usize {
+        match *self {}
+    }
+
+    pub(crate) unsafe fn create_client(&mut self, _thread: usize, _fd: RawFd) -> ClientInner {
+        match *self {}
+    }
+}
@@ -1,28 +1,41 @@
 use std::cell::RefCell;
 use std::os::raw::{c_int, c_void};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 use wayland_sys::common::*;
 use wayland_sys::server::*;
 
 use wayland_commons::user_data::UserData;
 
-use crate::{DispatchData, Interface, Main, MessageGroup, Resource};
+use crate::{DispatchData, GateDecision, Interface, Main, MessageGroup, Resource};
 
 use super::ClientInner;
 
+pub(crate) type SendHook<I> =
+    Box<dyn FnMut(&Resource<I>, <I as Interface>::Event) -> Option<<I as Interface>::Event> + Send>;
+
+pub(crate) type RequestGate = Box<dyn FnMut(&ResourceInner, u16) -> GateDecision + Send>;
+
 pub(crate) struct ResourceInternal {
     alive: AtomicBool,
     user_data: UserData,
+    serial: u64,
 }
 
 impl ResourceInternal {
     fn new(user_data: UserData) -> ResourceInternal {
-        ResourceInternal { alive: AtomicBool::new(true), user_data }
+        ResourceInternal {
+            alive: AtomicBool::new(true),
+            user_data,
+            serial: crate::resource::next_object_serial(),
+        }
     }
 }
 
+// the serials of the external resources are derived from their address, the others are counted
+const EXTERNAL_SERIAL: u64 = 1 << 63;
+
 pub(crate) struct ResourceInner {
     internal: Option<Arc<ResourceInternal>>,
     ptr: *mut wl_resource,
@@ -31,6 +44,30 @@ pub(crate) struct ResourceInner {
 unsafe impl Send for ResourceInner {}
 unsafe impl Sync for ResourceInner {}
 
+#[derive(Clone)]
+pub(crate) struct WeakResourceInner {
+    internal: Option<Weak<ResourceInternal>>,
+    ptr: *mut wl_resource,
+}
+
+unsafe impl Send for WeakResourceInner {}
+unsafe impl Sync for WeakResourceInner {}
+
+impl WeakResourceInner {
+    pub(crate) fn upgrade(&self) -> Option<ResourceInner> {
+        let internal = match self.internal {
+            Some(ref internal) => Some(internal.upgrade()?),
+            None => None,
+        };
+        let inner = ResourceInner { internal, ptr: self.ptr };
+        if inner.is_alive() {
+            Some(inner)
+        } else {
+            None
+        }
+    }
+}
+
 impl ResourceInner {
     pub(crate) fn send<I: Interface>(&self, msg: I::Event) {
         if let Some(ref internal) = self.internal {
@@ -82,6 +119,17 @@ impl ResourceInner {
         self.internal.is_none()
     }
 
+    pub(crate) fn serial(&self) -> u64 {
+        match self.internal {
+            Some(ref internal) => internal.serial,
+            None => self.ptr as usize as u64 | EXTERNAL_SERIAL,
+        }
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakResourceInner {
+        WeakResourceInner { internal: self.internal.as_ref().map(Arc::downgrade), ptr: self.ptr }
+    }
+
     pub(crate) fn equals(&self, other: &ResourceInner) -> bool {
         match (&self.internal, &other.internal) {
             (&Some(ref my_inner), &Some(ref other_inner)) => Arc::ptr_eq(my_inner, other_inner),
@@ -155,6 +203,7 @@ impl ResourceInner {
                 internal: Some(Arc::new(ResourceInternal {
                     alive: AtomicBool::new(false),
                     user_data: UserData::new(),
+                    serial: crate::resource::next_object_serial(),
                 })),
                 ptr,
             };
@@ -185,6 +234,7 @@ impl ResourceInner {
                 internal: Some(Arc::new(ResourceInternal {
                     alive: AtomicBool::new(false),
                     user_data: UserData::new(),
+                    serial: crate::resource::next_object_serial(),
                 })),
                 ptr,
             };
@@ -245,6 +295,20 @@ impl ResourceInner {
         }
     }
 
+    pub(crate) fn set_send_hook<I: Interface>(&self, hook: Option<SendHook<I>>) {
+        if hook.is_some() {
+            panic!("[wayland-server] Send hooks are only available with the rust implementation.");
+        }
+    }
+
+    pub(crate) fn set_request_gate(&self, gate: Option<RequestGate>) {
+        if gate.is_some() {
+            panic!(
+                "[wayland-server] Request gates are only available with the rust implementation."
+            );
+        }
+    }
+
     pub(crate) fn clone(&self) -> ResourceInner {
         ResourceInner { internal: self.internal.clone(), ptr: self.ptr }
     }
@@ -0,0 +1,62 @@
+use std::os::unix::io::RawFd;
+
+use crate::imp::DispatchPoolInner;
+use crate::Client;
+
+/// A pool of threads dispatching clients
+///
+/// This is created by `Display::create_dispatch_pool()`. Each client given to the pool is
+/// dispatched by one of its threads: the requests of a client are processed in order, but
+/// independent clients are processed in parallel, so that an expensive client does not
+/// delay the others.
+///
+/// The threads are stopped and their clients disconnected when the pool is dropped.
+pub struct DispatchPool {
+    inner: DispatchPoolInner,
+    next: usize,
+}
+
+impl DispatchPool {
+    pub(crate) fn create(inner: DispatchPoolInner) -> DispatchPool {
+        DispatchPool { inner, next: 0 }
+    }
+
+    /// The number of threads of this pool
+    pub fn threads(&self) -> usize {
+        self.inner.threads()
+    }
+
+    /// Create a new client from an already-existing connected Fd
+    ///
+    /// The clients are spread over the threads of the pool, in turn.
+    ///
+    /// # Safety
+    ///
+    /// The provided file descriptor must be associated to a valid client connection.
+    pub unsafe fn create_client(&mut self, fd: RawFd) -> Client {
+        let thread = self.next;
+        self.next = (self.next + 1) % self.threads();
+        self.create_client_on(thread, fd)
+    }
+
+    /// Create a new client dispatched by a given thread of the pool
+    ///
+    /// This allows grouping clients that share some state on the same thread. The client is
+    /// created by this thread, so this method waits for it to finish dispatching the events
+    /// it is processing.
+    ///
+    /// Panics if `thread` is not lower than `threads()`.
+    ///
+    /// # Safety
+    ///
+    /// The provided file descriptor must be associated to a valid client connection.
+    pub unsafe fn create_client_on(&mut self, thread: usize, fd: RawFd) -> Client {
+        assert!(
+            thread < self.threads(),
+            "Cannot create a client on thread {}, the pool has {} threads.",
+            thread,
+            self.threads()
+        );
+        Client::make(self.inner.create_client(thread, fd))
+    }
+}
@@ -1,13 +1,83 @@
 use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use wayland_commons::user_data::UserData;
 use wayland_commons::{Interface, MessageGroup};
 
 use wayland_sys::server::*;
 
-use crate::imp::ResourceInner;
+use crate::imp::{ResourceInner, WeakResourceInner};
 use crate::{Client, Filter};
 
+/// What to do when sending an event a resource is too old for
+///
+/// Used by `Resource::send_checked()` and the `<event>_checked()` methods of the protocol
+/// interfaces.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VersionCheck {
+    /// Silently drop the event
+    Skip,
+    /// Drop the event and return a `VersionTooLow` error
+    Error,
+    /// Panic, like `Resource::send()`
+    Panic,
+}
+
+/// An event could not be sent because the version of its resource is too low
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionTooLow {
+    /// Name of the interface of the resource
+    pub interface: &'static str,
+    /// Name of the event
+    pub event: &'static str,
+    /// Version of the interface the event was introduced in
+    pub since: u32,
+    /// Version of the resource
+    pub version: u32,
+}
+
+impl std::error::Error for VersionTooLow {}
+
+impl fmt::Display for VersionTooLow {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "event {}.{} requires version >= {} but the resource is version {}",
+            self.interface, self.event, self.since, self.version
+        )
+    }
+}
+
+/// What to do with a request, as decided by a request gate
+///
+/// See `Resource::set_request_gate()` and `Display::set_request_gate()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GateDecision {
+    /// Dispatch the request
+    Allow,
+    /// Stop dispatching the requests of the client until the next dispatch of the display
+    ///
+    /// The request is kept, and the gates are evaluated again before dispatching it.
+    Defer,
+    /// Disconnect the client, dropping the request
+    Kill,
+}
+
+pub(crate) fn erase_request_gate<I, F>(mut gate: F) -> crate::imp::RequestGate
+where
+    I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+    F: FnMut(&Client, &Resource<I>, u16) -> GateDecision + Send + 'static,
+{
+    Box::new(move |inner: &ResourceInner, opcode| {
+        let resource = Resource::<I>::wrap(inner.clone());
+        match resource.client() {
+            Some(client) => gate(&client, &resource, opcode),
+            None => GateDecision::Allow,
+        }
+    })
+}
+
 /// An handle to a wayland resource
 ///
 /// This represents a wayland object instantiated in a client
@@ -34,6 +104,12 @@ impl<I: Interface + From<Resource<I>> + AsRef<Resource<I>>> PartialEq for Resour
 
 impl<I: Interface + From<Resource<I>> + AsRef<Resource<I>>> Eq for Resource<I> {}
 
+impl<I: Interface + From<Resource<I>> + AsRef<Resource<I>>> Hash for Resource<I> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.serial().hash(state)
+    }
+}
+
 impl<I> Resource<I>
 where
     I: Interface + From<Resource<I>> + AsRef<Resource<I>>,
@@ -70,9 +146,92 @@ where
                 self.version()
             );
         }
+        #[cfg(not(feature = "use_system_lib"))]
+        let msg = match self.inner.apply_send_hooks(self, msg) {
+            Some(msg) => msg,
+            None => return,
+        };
         self.inner.send::<I>(msg)
     }
 
+    /// Send an event through this object, checking its version first
+    ///
+    /// Unlike `send()`, which panics if the event requires a higher version than the one
+    /// of this resource, the `check` decides what happens in this case: the event can be
+    /// skipped, reported as an error, or trigger the same panic as `send()`.
+    ///
+    /// The interfaces of the protocols also have `<event>_checked()` methods building the
+    /// event and calling this method, for the events not available in their first version.
+    pub fn send_checked(&self, msg: I::Event, check: VersionCheck) -> Result<(), VersionTooLow> {
+        if check != VersionCheck::Panic && self.is_alive() && msg.since() > self.version() {
+            if check == VersionCheck::Skip {
+                return Ok(());
+            }
+            let opcode = msg.opcode() as usize;
+            return Err(VersionTooLow {
+                interface: I::NAME,
+                event: I::Event::MESSAGES[opcode].name,
+                since: msg.since(),
+                version: self.version(),
+            });
+        }
+        self.send(msg);
+        Ok(())
+    }
+
+    /// Install a hook intercepting the events sent through this resource
+    ///
+    /// The hook is invoked with each event just before it is sent, and returns the event
+    /// to actually send, or `None` to drop it. It can be used to observe, rewrite or veto
+    /// events, for example to delay frame callbacks when testing clients. It replaces any
+    /// hook previously set on this resource, and runs before the hook of its interface
+    /// set with `Display::set_send_hook()`.
+    ///
+    /// Events sent from within a hook are not intercepted. Vetoing a destructor event
+    /// does not destroy the object.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn set_send_hook<F>(&self, hook: F)
+    where
+        F: FnMut(&Resource<I>, I::Event) -> Option<I::Event> + Send + 'static,
+    {
+        self.inner.set_send_hook::<I>(Some(Box::new(hook)))
+    }
+
+    /// Remove the send hook of this resource
+    ///
+    /// NOTE: This method does nothing when the `use_system_lib` feature is activated.
+    pub fn clear_send_hook(&self) {
+        self.inner.set_send_hook::<I>(None)
+    }
+
+    /// Install a gate evaluated before dispatching each request of this resource
+    ///
+    /// The gate is given the client, the resource and the opcode of the request, and decides
+    /// whether it is dispatched, deferred or whether the client is disconnected. This allows
+    /// throttling the clients flooding the compositor with requests. It replaces any gate
+    /// previously set on this resource, and runs before the gate of its interface set with
+    /// `Display::set_request_gate()`, which is only evaluated if this one allows the request.
+    ///
+    /// The objects created by a request already exist when its gate is evaluated.
+    ///
+    /// NOTE: This method will panic if called while the `use_system_lib` feature is
+    /// activated.
+    pub fn set_request_gate<F>(&self, gate: F)
+    where
+        F: FnMut(&Client, &Resource<I>, u16) -> GateDecision + Send + 'static,
+    {
+        self.inner.set_request_gate(Some(erase_request_gate(gate)))
+    }
+
+    /// Remove the request gate of this resource
+    ///
+    /// NOTE: This method does nothing when the `use_system_lib` feature is activated.
+    pub fn clear_request_gate(&self) {
+        self.inner.set_request_gate(None)
+    }
+
     /// Check if the object associated with this resource is still alive
     ///
     /// Will return `false` if the object has been destroyed.
@@ -108,7 +267,9 @@ where
 
     /// Posts a protocol error to this resource
     ///
-    /// The error code can be obtained from the various `Error` enums of the protocols.
+    /// The error code can be obtained from the various `Error` enums of the protocols. The
+    /// interfaces that define an `Error` enum also have a `post_error()` method taking it
+    /// directly.
     ///
     /// An error is fatal to the client that caused it.
     pub fn post_error(&self, error_code: u32, msg: String) {
@@ -137,6 +298,21 @@ where
     pub fn id(&self) -> u32 {
         self.inner.id()
     }
+
+    /// Retrieve the stable identifier of this wayland object
+    ///
+    /// Unlike the protocol id returned by `id()`, it is never reused by an other object and
+    /// stays the same once the object is destroyed, see [`ObjectId`](struct.ObjectId.html).
+    pub fn object_id(&self) -> ObjectId {
+        ObjectId { interface: I::NAME, protocol_id: self.inner.id(), serial: self.inner.serial() }
+    }
+
+    /// Create a weak handle to this wayland object
+    ///
+    /// See [`Weak`](struct.Weak.html) for details.
+    pub fn downgrade(&self) -> Weak<I> {
+        Weak { _i: ::std::marker::PhantomData, inner: self.inner.downgrade(), id: self.object_id() }
+    }
 }
 
 impl<I> Resource<I>
@@ -257,6 +433,100 @@ impl<I: Interface> Debug for Resource<I> {
     }
 }
 
+static NEXT_OBJECT_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) fn next_object_serial() -> u64 {
+    NEXT_OBJECT_SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A stable identifier of a wayland object
+///
+/// The protocol ids of the objects of a client are reused once they are destroyed, and the
+/// ids of different clients overlap, which makes them unsuitable to track resources. An
+/// `ObjectId` is never reused by an other object during the lifetime of the process, and the
+/// one of a destroyed object stays valid: it can be used as the key of a map, and compares
+/// equal to the id of any other handle to the same object.
+#[derive(Copy, Clone)]
+pub struct ObjectId {
+    interface: &'static str,
+    protocol_id: u32,
+    serial: u64,
+}
+
+impl ObjectId {
+    /// The name of the interface of the object
+    pub fn interface(&self) -> &'static str {
+        self.interface
+    }
+
+    /// The protocol id the object had when this identifier was retrieved
+    ///
+    /// This is 0 if the object was already dead.
+    pub fn protocol_id(&self) -> u32 {
+        self.protocol_id
+    }
+}
+
+impl PartialEq for ObjectId {
+    fn eq(&self, other: &ObjectId) -> bool {
+        self.serial == other.serial
+    }
+}
+
+impl Eq for ObjectId {}
+
+impl Hash for ObjectId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.serial.hash(state)
+    }
+}
+
+impl Debug for ObjectId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.interface, self.protocol_id)
+    }
+}
+
+/// A weak handle to a wayland resource
+///
+/// It does not keep the user data of the object alive, and can only be upgraded back into
+/// a resource while the object is alive: upgrading fails once it has been destroyed, even if
+/// its protocol id has been reused by a new object.
+pub struct Weak<I: Interface> {
+    _i: ::std::marker::PhantomData<&'static I>,
+    inner: WeakResourceInner,
+    id: ObjectId,
+}
+
+impl<I> Weak<I>
+where
+    I: Interface + From<Resource<I>> + AsRef<Resource<I>>,
+{
+    /// Try to retrieve a handle to the object
+    ///
+    /// Returns `None` if the object has been destroyed.
+    pub fn upgrade(&self) -> Option<I> {
+        self.inner.upgrade().map(|inner| Resource::wrap(inner).into())
+    }
+
+    /// The stable identifier of the object
+    pub fn object_id(&self) -> ObjectId {
+        self.id
+    }
+}
+
+impl<I: Interface> Clone for Weak<I> {
+    fn clone(&self) -> Weak<I> {
+        Weak { _i: ::std::marker::PhantomData, inner: self.inner.clone(), id: self.id }
+    }
+}
+
+impl<I: Interface> Debug for Weak<I> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Weak({:?})", self.id)
+    }
+}
+
 /// A main handle to a proxy
 #[derive(Clone, PartialEq)]
 pub struct Main<I: Interface + AsRef<Resource<I>> + From<Resource<I>>> {
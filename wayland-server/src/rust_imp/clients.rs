@@ -1,8 +1,8 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::ffi::CString;
 use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
 use std::rc::Rc;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, ThreadId};
 
@@ -10,15 +10,17 @@ use nix::Result as NixResult;
 
 use wayland_commons::debug;
 use wayland_commons::map::{Object, ObjectMap, ObjectMetadata, SERVER_ID_LIMIT};
-use wayland_commons::socket::{BufferedSocket, Socket};
-use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc, MessageParseError};
+use wayland_commons::socket::{BufferedSocket, Socket, DEFAULT_MAX_MESSAGE_SIZE};
+use wayland_commons::wire::{
+    Argument, ArgumentType, Message, MessageDesc, MessageParseError, Strictness,
+};
 use wayland_commons::{smallvec, ThreadGuard};
 
-use crate::{DispatchData, Interface, UserDataMap};
+use crate::{DispatchData, GateDecision, Interface, ResourceInfo, UserDataMap};
 
 use super::event_loop_glue::{FdManager, Token};
 use super::globals::GlobalManager;
-use super::resources::{ObjectMeta, ResourceDestructor, ResourceInner};
+use super::resources::{ObjectMeta, RequestGates, ResourceDestructor, ResourceInner, SendHooks};
 use super::{Dispatched, WAYLAND_DEBUG};
 
 #[derive(Clone, Debug)]
@@ -30,6 +32,38 @@ pub(crate) enum Error {
 
 type BoxedClientDestructor = Box<dyn FnMut(Arc<UserDataMap>, DispatchData<'_>)>;
 
+pub(crate) type FlushNotifier = Arc<dyn Fn() + Send + Sync>;
+
+/// Tracks whether some events are waiting for `Display::flush_clients()`
+#[derive(Default)]
+pub(crate) struct FlushScheduler {
+    needs_flush: AtomicBool,
+    notifier: Mutex<Option<FlushNotifier>>,
+}
+
+impl FlushScheduler {
+    pub(crate) fn needs_flush(&self) -> bool {
+        self.needs_flush.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_notifier(&self, notifier: Option<FlushNotifier>) {
+        *self.notifier.lock().unwrap() = notifier;
+    }
+
+    // an event was queued, returns whether it is the first one since the last flush
+    fn mark(&self) -> bool {
+        !self.needs_flush.swap(true, Ordering::AcqRel)
+    }
+
+    fn notify(&self) {
+        // clone the notifier out of the lock, so that it can replace itself
+        let notifier = self.notifier.lock().unwrap().clone();
+        if let Some(notifier) = notifier {
+            notifier();
+        }
+    }
+}
+
 pub(crate) struct ClientConnection {
     socket: BufferedSocket,
     pub(crate) map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
@@ -38,6 +72,12 @@ pub(crate) struct ClientConnection {
     last_error: Option<Error>,
     pending_destructors: Vec<ResourceInner>,
     zombie_clients: Arc<Mutex<Vec<ClientConnection>>>,
+    strictness: Strictness,
+    flush_scheduler: Arc<FlushScheduler>,
+    // the flush scheduler must be notified once the connection is unlocked
+    flush_notification: bool,
+    // a request deferred by a request gate, to dispatch before reading the next ones
+    deferred_request: Option<Message>,
 }
 
 impl ClientConnection {
@@ -45,8 +85,12 @@ impl ClientConnection {
         fd: RawFd,
         display_object: Object<ObjectMeta>,
         zombies: Arc<Mutex<Vec<ClientConnection>>>,
+        strictness: Strictness,
+        max_message_size: usize,
+        flush_scheduler: Arc<FlushScheduler>,
     ) -> ClientConnection {
-        let socket = BufferedSocket::new(Socket::from_raw_fd(fd));
+        let mut socket = BufferedSocket::new(Socket::from_raw_fd(fd));
+        socket.set_max_message_size(max_message_size);
 
         let mut map = ObjectMap::new();
         // Insert first pre-existing object
@@ -60,6 +104,10 @@ impl ClientConnection {
             last_error: None,
             pending_destructors: Vec::new(),
             zombie_clients: zombies,
+            strictness,
+            flush_scheduler,
+            flush_notification: false,
+            deferred_request: None,
         }
     }
 
@@ -70,13 +118,27 @@ impl ClientConnection {
     pub(crate) fn call_destructors(&mut self, mut data: crate::DispatchData) {
         for resource in self.pending_destructors.drain(..) {
             if let Some(ref dest) = resource.object.meta.destructor {
-                (&mut *dest.get().borrow_mut())(resource.clone(), data.reborrow());
+                if let Some(dest) = dest.get_or_report() {
+                    (&mut *dest.borrow_mut())(resource.clone(), data.reborrow());
+                }
             }
         }
     }
 
     pub(crate) fn write_message(&mut self, msg: &Message) -> NixResult<()> {
-        self.socket.write_message(msg)
+        self.socket.write_message(msg)?;
+        if self.flush_scheduler.mark() {
+            self.flush_notification = true;
+        }
+        Ok(())
+    }
+
+    fn take_flush_notification(&mut self) -> Option<Arc<FlushScheduler>> {
+        if ::std::mem::replace(&mut self.flush_notification, false) {
+            Some(self.flush_scheduler.clone())
+        } else {
+            None
+        }
     }
 
     pub(crate) fn flush(&mut self) -> NixResult<()> {
@@ -101,41 +163,43 @@ impl ClientConnection {
         if let Some(ref err) = self.last_error {
             return Err(err.clone());
         }
+        if let Some(msg) = self.deferred_request.take() {
+            return Ok(Some(msg));
+        }
         // acquire the map lock, this means no objects can be created nor destroyed while we
         // are reading requests
         let mut map = self.map.lock().unwrap();
         // read messages
-        let ret = self.socket.read_one_message(|id, opcode| {
-            map.find(id).and_then(|o| o.requests.get(opcode as usize)).map(|desc| desc.signature)
-        });
+        let strictness = self.strictness;
+        let ret = self
+            .socket
+            .read_one_message_with(strictness, |id, opcode| map.find(id)?.request_desc(opcode));
         let msg = match ret {
             Ok(msg) => msg,
-            Err(MessageParseError::Malformed) => {
-                self.last_error = Some(Error::Parse(MessageParseError::Malformed));
-                return Err(Error::Parse(MessageParseError::Malformed));
-            }
-            Err(MessageParseError::MissingData) | Err(MessageParseError::MissingFD) => {
+            Err(MessageParseError::MissingData) => {
                 // missing data, read sockets and try again
                 self.socket.fill_incoming_buffers().map_err(Error::Nix)?;
 
-                let msg = self.socket.read_one_message(|id, opcode| {
-                    map.find(id)
-                        .and_then(|o| o.requests.get(opcode as usize))
-                        .map(|desc| desc.signature)
+                let msg = self.socket.read_one_message_with(strictness, |id, opcode| {
+                    map.find(id)?.request_desc(opcode)
                 });
 
                 match msg {
                     Ok(msg) => msg,
-                    Err(MessageParseError::Malformed) => {
-                        self.last_error = Some(Error::Parse(MessageParseError::Malformed));
-                        return Err(Error::Parse(MessageParseError::Malformed));
-                    }
-                    Err(MessageParseError::MissingData) | Err(MessageParseError::MissingFD) => {
+                    Err(MessageParseError::MissingData) => {
                         // still nothing, there is nothing to read
                         return Ok(None);
                     }
+                    Err(e) => {
+                        self.last_error = Some(Error::Parse(e.clone()));
+                        return Err(Error::Parse(e));
+                    }
                 }
             }
+            Err(e) => {
+                self.last_error = Some(Error::Parse(e.clone()));
+                return Err(Error::Parse(e));
+            }
         };
 
         // we reach here, there is now a message to process in msg
@@ -147,11 +211,7 @@ impl ClientConnection {
                 // this is a message sent to a destroyed object
                 // to avoid dying because of races, we just consume it into void
                 // closing any associated FDs
-                for a in msg.args {
-                    if let Argument::Fd(fd) = a {
-                        let _ = ::nix::unistd::close(fd);
-                    }
-                }
+                close_fds(msg);
 
                 return Ok(None);
             }
@@ -195,12 +255,17 @@ impl ClientConnection {
             data: Arc::new(Mutex::new(None)),
             user_data_map: self.user_data_map.clone(),
             loop_thread: thread::current().id(),
+            send_hooks: Arc::new(SendHooks::default()),
+            request_gates: Arc::new(RequestGates::default()),
         };
+        if let Some(msg) = self.deferred_request.take() {
+            close_fds(msg);
+        }
         self.map.lock().unwrap().with_all(|id, obj| {
             let resource = ResourceInner { id, object: obj.clone(), client: dummy_client.clone() };
             obj.meta.alive.store(false, Ordering::Release);
-            if let Some(ref dest) = obj.meta.destructor {
-                (&mut *dest.get().borrow_mut())(resource, data.reborrow());
+            if let Some(dest) = obj.meta.destructor.as_ref().and_then(|d| d.get_or_report()) {
+                (&mut *dest.borrow_mut())(resource, data.reborrow());
             }
         });
         let _ = ::nix::unistd::close(self.socket.into_socket().into_raw_fd());
@@ -215,6 +280,8 @@ pub(crate) struct ClientInner {
     pub(crate) data: Arc<Mutex<Option<ClientConnection>>>,
     user_data_map: Arc<UserDataMap>,
     pub(crate) loop_thread: ThreadId,
+    pub(crate) send_hooks: Arc<SendHooks>,
+    pub(crate) request_gates: Arc<RequestGates>,
 }
 
 impl ClientInner {
@@ -232,6 +299,10 @@ impl ClientInner {
         }
     }
 
+    pub(crate) fn pending_bytes(&self) -> usize {
+        self.data.lock().unwrap().as_ref().map(|cx| cx.socket.pending_bytes()).unwrap_or(0)
+    }
+
     pub(crate) fn kill(&self) {
         if let Some(mut clientconn) = self.data.lock().unwrap().take() {
             let _ = clientconn.socket.flush();
@@ -264,8 +335,27 @@ impl ClientInner {
         }
     }
 
+    /// Access the connection of the client, if it is alive, to send it some messages
+    ///
+    /// The flush notifier is invoked once the connection is unlocked, so that it can use it.
+    pub(crate) fn with_connection<T, F>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut ClientConnection) -> T,
+    {
+        let (ret, notification) = {
+            let mut guard = self.data.lock().unwrap();
+            let cx = guard.as_mut()?;
+            let ret = f(cx);
+            (ret, cx.take_flush_notification())
+        };
+        if let Some(scheduler) = notification {
+            scheduler.notify();
+        }
+        Some(ret)
+    }
+
     pub(crate) fn post_error(&self, object: u32, error_code: u32, msg: String) {
-        if let Some(ref mut data) = *self.data.lock().unwrap() {
+        self.with_connection(|data| {
             let _ = data.write_message(&Message {
                 sender_id: 1,
                 opcode: 0,
@@ -275,10 +365,15 @@ impl ClientInner {
                     Argument::Str(Box::new(CString::new(msg).unwrap())),
                 ],
             });
-        }
+        });
         self.kill();
     }
 
+    pub(crate) fn post_implementation_error(&self, msg: String) {
+        // the error is sent on the display object of the client
+        self.post_error(1, WL_DISPLAY_ERROR_IMPLEMENTATION, msg)
+    }
+
     pub(crate) fn create_resource<I: Interface>(&self, version: u32) -> Option<ResourceInner> {
         if self.loop_thread != thread::current().id() {
             panic!("Can only create ressources from the thread hosting the Display.");
@@ -300,6 +395,34 @@ impl ClientInner {
         Some(ResourceInner::from_id(id, map, self.clone()).unwrap())
     }
 
+    pub(crate) fn resources(&self) -> Vec<ResourceInfo> {
+        let map = match self.data.lock().unwrap().as_ref() {
+            Some(cx) => cx.map.clone(),
+            None => return Vec::new(),
+        };
+        let map = map.lock().unwrap();
+        map.iter()
+            .map(|(id, obj)| ResourceInfo {
+                id,
+                interface: obj.interface,
+                version: obj.version,
+                alive: obj.meta.alive.load(Ordering::Acquire),
+            })
+            .collect()
+    }
+
+    pub(crate) fn resources_of<I: Interface>(&self) -> Vec<ResourceInner> {
+        let map = match self.data.lock().unwrap().as_ref() {
+            Some(cx) => cx.map.clone(),
+            None => return Vec::new(),
+        };
+        let map = map.lock().unwrap();
+        map.iter()
+            .filter(|&(_, obj)| obj.is_interface::<I>() && obj.meta.alive.load(Ordering::Acquire))
+            .map(|(id, obj)| ResourceInner { id, object: obj.clone(), client: self.clone() })
+            .collect()
+    }
+
     pub(crate) fn get_resource<I: Interface>(&self, id: u32) -> Option<ResourceInner> {
         let object = self
             .data
@@ -345,19 +468,28 @@ pub(crate) struct ClientManager {
     epoll_mgr: Rc<FdManager>,
     clients: Vec<(RefCell<Option<Token>>, ClientInner)>,
     zombie_clients: Arc<Mutex<Vec<ClientConnection>>>,
-    global_mgr: Rc<RefCell<GlobalManager>>,
+    global_mgr: GlobalManager,
+    pub(crate) send_hooks: Arc<SendHooks>,
+    pub(crate) request_gates: Arc<RequestGates>,
+    pub(crate) deferred: DeferredClients,
+    pub(crate) strictness: Strictness,
+    pub(crate) max_message_size: usize,
+    pub(crate) flush_scheduler: Arc<FlushScheduler>,
 }
 
 impl ClientManager {
-    pub(crate) fn new(
-        epoll_mgr: Rc<FdManager>,
-        global_mgr: Rc<RefCell<GlobalManager>>,
-    ) -> ClientManager {
+    pub(crate) fn new(epoll_mgr: Rc<FdManager>, global_mgr: GlobalManager) -> ClientManager {
         ClientManager {
             epoll_mgr,
             clients: Vec::new(),
             zombie_clients: Arc::new(Mutex::new(Vec::new())),
             global_mgr,
+            send_hooks: Arc::new(SendHooks::default()),
+            request_gates: Arc::new(RequestGates::default()),
+            deferred: DeferredClients::default(),
+            strictness: Strictness::Lenient,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            flush_scheduler: Arc::new(FlushScheduler::default()),
         }
     }
 
@@ -371,6 +503,8 @@ impl ClientManager {
             version: 1,
             requests: DISPLAY_REQUESTS,
             events: DISPLAY_EVENTS,
+            request_args: &[],
+            event_args: &[],
             meta: ObjectMeta::with_dispatcher(DisplayDispatcher {
                 global_mgr: self.global_mgr.clone(),
             }),
@@ -378,7 +512,14 @@ impl ClientManager {
             childs_from_requests: display_req_child,
         };
 
-        let cx = ClientConnection::new(fd, display_object, self.zombie_clients.clone());
+        let cx = ClientConnection::new(
+            fd,
+            display_object,
+            self.zombie_clients.clone(),
+            self.strictness,
+            self.max_message_size,
+            self.flush_scheduler.clone(),
+        );
         let map = cx.map.clone();
         let user_data_map = cx.user_data_map.clone();
 
@@ -386,9 +527,18 @@ impl ClientManager {
             data: Arc::new(Mutex::new(Some(cx))),
             user_data_map,
             loop_thread: thread::current().id(), // init_client is only called by the display, which does not change threads
+            send_hooks: self.send_hooks.clone(),
+            request_gates: self.request_gates.clone(),
         };
 
-        let implementation = ClientImplementation { inner: client.clone(), map };
+        let implementation = ClientImplementation {
+            inner: client.clone(),
+            map,
+            epoll_mgr: self.epoll_mgr.clone(),
+            token: Rc::new(Cell::new(None)),
+            deferred: self.deferred.clone(),
+        };
+        let token = implementation.token.clone();
 
         // process any pending messages before inserting it into the event loop
         implementation.process_messages(data);
@@ -401,7 +551,10 @@ impl ClientManager {
 
         let source =
             match self.epoll_mgr.register(fd, move |data| implementation.process_messages(data)) {
-                Ok(source) => Some(source),
+                Ok(source) => {
+                    token.set(Some(source));
+                    Some(source)
+                }
                 Err(e) => {
                     eprintln!("[wayland-server] Failed to insert client into event loop: {:?}", e);
                     client.kill();
@@ -419,10 +572,20 @@ impl ClientManager {
     pub(crate) fn flush_all(&mut self, mut disp_data: crate::DispatchData) {
         // flush all clients and cleanup dead ones
         let epoll_mgr = self.epoll_mgr.clone();
+        self.flush_scheduler.needs_flush.store(false, Ordering::Release);
+        let mut incomplete = false;
         self.clients.retain(|&(ref s, ref c)| {
             if let Some(ref mut data) = *c.data.lock().unwrap() {
                 data.call_destructors(disp_data.reborrow());
-                data.flush().is_ok()
+                // if the socket is full, what could not be written is kept for the next flush
+                match data.flush() {
+                    Ok(()) => true,
+                    Err(::nix::Error::Sys(::nix::errno::Errno::EAGAIN)) => {
+                        incomplete = true;
+                        true
+                    }
+                    Err(_) => false,
+                }
             } else {
                 // This is a dead client, clean it up
                 if let Some(token) = s.borrow_mut().take() {
@@ -432,6 +595,12 @@ impl ClientManager {
             }
         });
 
+        // the clients whose socket is full still need a flush, without notifying again as
+        // retrying right away would not help
+        if incomplete {
+            self.flush_scheduler.needs_flush.store(true, Ordering::Release);
+        }
+
         let mut guard = self.zombie_clients.lock().unwrap();
         for zombie in guard.drain(..) {
             zombie.cleanup(disp_data.reborrow());
@@ -439,14 +608,17 @@ impl ClientManager {
     }
 
     // kill & cleanup all clients
-    pub(crate) fn kill_all(&mut self) {
+    pub(crate) fn kill_all(&mut self, data: crate::DispatchData) {
         for &(_, ref client) in &self.clients {
             client.kill();
         }
-        self.flush_all(crate::DispatchData::wrap(&mut ()));
+        self.flush_all(data);
     }
 }
 
+// the `implementation` error code of `wl_display`
+const WL_DISPLAY_ERROR_IMPLEMENTATION: u32 = 3;
+
 const DISPLAY_REQUESTS: &[MessageDesc] = &[
     MessageDesc { name: "sync", since: 1, signature: &[ArgumentType::NewId], destructor: false },
     MessageDesc {
@@ -507,6 +679,8 @@ fn display_req_child(opcode: u16, _: u32, meta: &ObjectMeta) -> Option<Object<Ob
             version: 1,
             requests: REGISTRY_REQUESTS,
             events: REGISTRY_EVENTS,
+            request_args: &[],
+            event_args: &[],
             meta: meta.child(),
             childs_from_events: no_child,
             childs_from_requests: no_child,
@@ -519,12 +693,62 @@ fn no_child(_: u16, _: u32, _: &ObjectMeta) -> Option<Object<ObjectMeta>> {
     None
 }
 
+fn close_fds(msg: Message) {
+    for a in msg.args {
+        if let Argument::Fd(fd) = a {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
+/// The clients whose requests were deferred by a request gate
+#[derive(Clone, Default)]
+pub(crate) struct DeferredClients {
+    clients: Rc<RefCell<Vec<ClientImplementation>>>,
+}
+
+impl DeferredClients {
+    /// Dispatch the requests of the deferred clients, evaluating their gates again
+    pub(crate) fn resume(&self, mut data: crate::DispatchData) {
+        let clients = ::std::mem::take(&mut *self.clients.borrow_mut());
+        for client in clients {
+            if let Some(token) = client.token.get() {
+                let _ = client.epoll_mgr.set_enabled(token, true);
+            }
+            client.process_messages(data.reborrow());
+        }
+    }
+}
+
+#[derive(Clone)]
 struct ClientImplementation {
     inner: ClientInner,
     map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+    epoll_mgr: Rc<FdManager>,
+    token: Rc<Cell<Option<Token>>>,
+    deferred: DeferredClients,
 }
 
 impl ClientImplementation {
+    // stop reading the requests of the client until the next dispatch
+    fn defer(&self, msg: Message) {
+        match *self.inner.data.lock().unwrap() {
+            Some(ref mut data) => data.deferred_request = Some(msg),
+            None => {
+                close_fds(msg);
+                return;
+            }
+        }
+        // the socket of the client may still be readable, don't wake up the event loop for it
+        if let Some(token) = self.token.get() {
+            let _ = self.epoll_mgr.set_enabled(token, false);
+        }
+        let mut clients = self.deferred.clients.borrow_mut();
+        if !clients.iter().any(|client| client.inner.equals(&self.inner)) {
+            clients.push(self.clone());
+        }
+    }
+
     fn process_messages(&self, mut data: crate::DispatchData) {
         loop {
             // we must process the messages one by one, because message parsing depends
@@ -542,6 +766,24 @@ impl ClientImplementation {
                     return;
                 }
                 Ok(Some(msg)) => msg,
+                Err(Error::Parse(MessageParseError::Invalid(e))) => {
+                    // The message was rejected by the validation, report it to the client.
+                    self.inner.post_error(
+                        1,
+                        super::display::DISPLAY_ERROR_INVALID_METHOD,
+                        format!("invalid arguments: {}", e),
+                    );
+                    return;
+                }
+                Err(Error::Parse(MessageParseError::TooLarge(size))) => {
+                    // The client exceeded the maximum message size, report it as well.
+                    self.inner.post_error(
+                        1,
+                        super::display::DISPLAY_ERROR_INVALID_METHOD,
+                        format!("message too large ({} bytes)", size),
+                    );
+                    return;
+                }
                 Err(_) => {
                     // On error, kill the client.
                     self.inner.kill();
@@ -566,8 +808,24 @@ impl ClientImplementation {
                 }
             };
 
+            match res.check_request_gates(opcode) {
+                GateDecision::Allow => {}
+                GateDecision::Defer => {
+                    self.defer(msg);
+                    return;
+                }
+                GateDecision::Kill => {
+                    close_fds(msg);
+                    self.inner.kill();
+                    return;
+                }
+            }
+
             let object = res.object.clone();
-            let mut dispatcher = object.meta.dispatcher.get().borrow_mut();
+            let mut dispatcher = match object.meta.dispatcher.get_or_report() {
+                Some(dispatcher) => dispatcher.borrow_mut(),
+                None => continue,
+            };
 
             match dispatcher.dispatch(msg, res, &mut resourcemap, data.reborrow()) {
                 Dispatched::Yes => (),
@@ -596,7 +854,7 @@ impl ClientImplementation {
 }
 
 struct DisplayDispatcher {
-    global_mgr: Rc<RefCell<GlobalManager>>,
+    global_mgr: GlobalManager,
 }
 
 impl super::Dispatcher for DisplayDispatcher {
@@ -652,7 +910,7 @@ impl super::Dispatcher for DisplayDispatcher {
                     return Dispatched::BadMsg;
                 }
 
-                self.global_mgr.borrow_mut().new_registry(new_id, map.client.clone());
+                self.global_mgr.new_registry(new_id, map.client.clone());
             }
             _ => return Dispatched::BadMsg,
         }
@@ -662,7 +920,7 @@ impl super::Dispatcher for DisplayDispatcher {
 }
 
 struct RegistryDispatcher {
-    global_mgr: Rc<RefCell<GlobalManager>>,
+    global_mgr: GlobalManager,
 }
 
 impl super::Dispatcher for RegistryDispatcher {
@@ -699,7 +957,7 @@ impl super::Dispatcher for RegistryDispatcher {
             Some(Argument::NewId(id)) => id,
             _ => return Dispatched::BadMsg,
         };
-        match self.global_mgr.borrow().bind(
+        match self.global_mgr.bind(
             resource.id,
             new_id,
             global_id,
@@ -1,20 +1,24 @@
 use std::cell::RefCell;
 use std::env;
 use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
 use std::io;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::net::UnixListener;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::atomic::Ordering;
 
+use wayland_commons::wire::Strictness;
+
 use crate::display::get_runtime_dir;
 use crate::{Interface, Main, Resource};
 
 use super::clients::ClientManager;
 use super::event_loop_glue::{FdManager, Token};
 use super::globals::GlobalManager;
-use super::{ClientInner, GlobalInner, WAYLAND_DEBUG};
+use super::{ClientInner, DispatchPoolInner, GlobalInner, WAYLAND_DEBUG};
 
 pub(crate) const DISPLAY_ERROR_INVALID_OBJECT: u32 = 0;
 pub(crate) const DISPLAY_ERROR_INVALID_METHOD: u32 = 1;
@@ -24,7 +28,7 @@ pub(crate) const DISPLAY_ERROR_NO_MEMORY: u32 = 2;
 pub(crate) struct DisplayInner {
     epoll_mgr: Rc<FdManager>,
     pub(crate) clients_mgr: Rc<RefCell<ClientManager>>,
-    global_mgr: Rc<RefCell<GlobalManager>>,
+    global_mgr: GlobalManager,
     listeners: Vec<Token>,
 }
 
@@ -38,7 +42,7 @@ impl DisplayInner {
             }
         }
 
-        let global_mgr = Rc::new(RefCell::new(GlobalManager::new()));
+        let global_mgr = GlobalManager::new();
         let epoll_mgr = Rc::new(FdManager::new().unwrap());
 
         let clients_mgr =
@@ -58,18 +62,54 @@ impl DisplayInner {
         F1: FnMut(Main<I>, u32, crate::DispatchData<'_>) + 'static,
         F2: FnMut(ClientInner) -> bool + 'static,
     {
-        self.global_mgr.borrow_mut().add_global(version, implementation, filter)
+        self.global_mgr.add_global(version, implementation, filter)
+    }
+
+    pub(crate) fn create_shared_global<I, F>(
+        &mut self,
+        version: u32,
+        implementation: F,
+    ) -> GlobalInner<I>
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        F: Fn(Main<I>, u32) + Send + Sync + 'static,
+    {
+        self.global_mgr.add_shared_global(version, implementation)
+    }
+
+    pub(crate) fn create_dispatch_pool(&mut self, threads: usize) -> io::Result<DispatchPoolInner> {
+        let clients_mgr = self.clients_mgr.borrow();
+        DispatchPoolInner::new(
+            threads,
+            self.global_mgr.clone(),
+            clients_mgr.strictness,
+            clients_mgr.max_message_size,
+        )
     }
 
     pub(crate) fn flush_clients(&mut self, data: crate::DispatchData) {
         self.clients_mgr.borrow_mut().flush_all(data)
     }
 
-    fn add_unix_listener(&mut self, listener: UnixListener) -> io::Result<()> {
+    pub(crate) fn shutdown(&mut self, mut data: crate::DispatchData) {
+        // dropping the listeners closes them and removes their socket and lock files
+        for l in self.listeners.drain(..) {
+            self.epoll_mgr.deregister(l);
+        }
+        let mut clients_mgr = self.clients_mgr.borrow_mut();
+        clients_mgr.flush_all(data.reborrow());
+        clients_mgr.kill_all(data);
+    }
+
+    fn add_unix_listener(
+        &mut self,
+        listener: UnixListener,
+        lock: Option<(File, PathBuf)>,
+    ) -> io::Result<()> {
         listener.set_nonblocking(true)?;
         // The WaylandListener will automatically remove the filesystem socket
-        // on drop, if any.
-        let listener = WaylandListener(listener);
+        // and its lock file on drop, if any.
+        let listener = WaylandListener(listener, lock);
 
         let client_mgr = self.clients_mgr.clone();
 
@@ -120,9 +160,36 @@ impl DisplayInner {
             path.push("wayland-0");
         }
 
-        let listener = UnixListener::bind(path)?;
+        // like libwayland, take a lock next to the socket, so that a socket left behind by a
+        // server that did not exit cleanly can be told apart from one in use
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let lock = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .mode(0o660)
+            .open(&lock_path)?;
+        if nix::fcntl::flock(lock.as_raw_fd(), nix::fcntl::FlockArg::LockExclusiveNonblock).is_err()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Socket {} is already in use by another server.", path.display()),
+            ));
+        }
+        // we hold the lock, so an existing socket is a stale one
+        let _ = fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                let _ = fs::remove_file(&lock_path);
+                return Err(e);
+            }
+        };
 
-        self.add_unix_listener(listener)
+        self.add_unix_listener(listener, Some((lock, lock_path)))
     }
 
     pub(crate) fn add_socket_auto(&mut self) -> io::Result<OsString> {
@@ -140,7 +207,7 @@ impl DisplayInner {
     }
 
     pub(crate) unsafe fn add_socket_fd(&mut self, fd: RawFd) -> io::Result<()> {
-        self.add_unix_listener(FromRawFd::from_raw_fd(fd))
+        self.add_unix_listener(FromRawFd::from_raw_fd(fd), None)
     }
 
     pub(crate) unsafe fn create_client(
@@ -154,8 +221,10 @@ impl DisplayInner {
     pub(crate) fn dispatch(
         &mut self,
         timeout: i32,
-        data: crate::DispatchData,
+        mut data: crate::DispatchData,
     ) -> std::io::Result<()> {
+        let deferred = self.clients_mgr.borrow().deferred.clone();
+        deferred.resume(data.reborrow());
         self.epoll_mgr
             .poll(timeout, data)
             .map_err(|e| From::from(e.as_errno().unwrap_or(nix::errno::Errno::EINVAL)))
@@ -164,6 +233,34 @@ impl DisplayInner {
     pub(crate) fn get_poll_fd(&self) -> RawFd {
         self.epoll_mgr.get_poll_fd()
     }
+
+    pub(crate) fn set_send_hook<I: Interface>(&mut self, hook: Option<super::SendHook<I>>) {
+        self.clients_mgr.borrow().send_hooks.set(hook)
+    }
+
+    pub(crate) fn set_request_gate(
+        &mut self,
+        interface: &'static str,
+        gate: Option<super::RequestGate>,
+    ) {
+        self.clients_mgr.borrow().request_gates.set(interface, gate)
+    }
+
+    pub(crate) fn set_strictness(&mut self, strictness: Strictness) {
+        self.clients_mgr.borrow_mut().strictness = strictness;
+    }
+
+    pub(crate) fn set_max_message_size(&mut self, size: usize) {
+        self.clients_mgr.borrow_mut().max_message_size = size;
+    }
+
+    pub(crate) fn needs_flush(&self) -> bool {
+        self.clients_mgr.borrow().flush_scheduler.needs_flush()
+    }
+
+    pub(crate) fn set_flush_notifier(&mut self, notifier: Option<super::clients::FlushNotifier>) {
+        self.clients_mgr.borrow().flush_scheduler.set_notifier(notifier)
+    }
 }
 
 impl Drop for DisplayInner {
@@ -171,11 +268,11 @@ impl Drop for DisplayInner {
         for l in self.listeners.drain(..) {
             self.epoll_mgr.deregister(l);
         }
-        self.clients_mgr.borrow_mut().kill_all();
+        self.clients_mgr.borrow_mut().kill_all(crate::DispatchData::wrap(&mut ()));
     }
 }
 
-struct WaylandListener(UnixListener);
+struct WaylandListener(UnixListener, Option<(File, PathBuf)>);
 
 impl WaylandListener {
     fn eprint_error(&self, error: io::Error) {
@@ -203,5 +300,8 @@ impl Drop for WaylandListener {
                 let _ = ::std::fs::remove_file(path);
             }
         }
+        if let Some((_, ref lock_path)) = self.1 {
+            let _ = ::std::fs::remove_file(lock_path);
+        }
     }
 }
@@ -58,6 +58,17 @@ impl FdManager {
         }
     }
 
+    /// Stop or resume monitoring a registered fd
+    pub(crate) fn set_enabled(&self, token: Token, enabled: bool) -> nix::Result<()> {
+        let fd = match self.callbacks.borrow()[token.0] {
+            Some((fd, _)) => fd,
+            None => return Ok(()),
+        };
+        let flags = if enabled { EpollFlags::EPOLLIN } else { EpollFlags::empty() };
+        let mut evt = EpollEvent::new(flags, token.0 as u64);
+        epoll_ctl(self.epoll_fd, EpollOp::EpollCtlMod, fd, &mut evt)
+    }
+
     pub(crate) fn poll(&self, timeout: i32, mut data: crate::DispatchData) -> nix::Result<()> {
         let mut events = [EpollEvent::empty(); 32];
         let n = epoll_wait(self.epoll_fd, &mut events, timeout as isize)?;
@@ -91,3 +102,9 @@ impl FdManager {
         self.epoll_fd
     }
 }
+
+impl Drop for FdManager {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.epoll_fd);
+    }
+}
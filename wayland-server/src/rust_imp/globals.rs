@@ -1,55 +1,86 @@
-use std::cell::{Cell, RefCell};
+use std::cell::RefCell;
 use std::ffi::CString;
-use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
 
 use wayland_commons::map::Object;
 use wayland_commons::smallvec;
 use wayland_commons::wire::{Argument, Message};
+use wayland_commons::ThreadGuard;
 
 use crate::{DispatchData, Interface, Main, Resource};
 
 use super::resources::ObjectMeta;
 use super::{ClientInner, ResourceInner};
 
-type GlobalFilter = Rc<RefCell<dyn FnMut(ClientInner) -> bool>>;
+type GlobalFilter = Arc<ThreadGuard<RefCell<dyn FnMut(ClientInner) -> bool>>>;
 
 pub(crate) struct GlobalInner<I: Interface> {
     _i: ::std::marker::PhantomData<*const I>,
-    destroyed_marker: Rc<Cell<bool>>,
+    destroyed_marker: Arc<AtomicBool>,
     id: u32,
-    registries: Rc<RefCell<Vec<(u32, ClientInner)>>>,
-    filter: Option<GlobalFilter>,
+    manager: GlobalManager,
 }
 
 impl<I: Interface> GlobalInner<I> {
+    pub fn disable(&self) {
+        if !self.destroyed_marker.swap(true, Ordering::AcqRel) {
+            self.manager.send_destroyed_global(self.id);
+        }
+    }
+
     pub fn destroy(self) {
-        self.destroyed_marker.set(true);
-        send_destroyed_global(&self.registries.borrow(), self.id, self.filter.as_deref());
+        self.disable();
     }
 }
 
-type GlobalImplementation = dyn Fn(u32, u32, ClientInner, DispatchData) -> Result<(), ()>;
+type GlobalImplementation =
+    dyn Fn(u32, u32, ClientInner, DispatchData) -> Result<(), ()> + Send + Sync;
 
 struct GlobalData {
     version: u32,
     interface: &'static str,
-    destroyed: Rc<Cell<bool>>,
-    implem: Box<GlobalImplementation>,
+    destroyed: Arc<AtomicBool>,
+    implem: Arc<GlobalImplementation>,
     filter: Option<GlobalFilter>,
+    // the thread whose clients can bind the global, any thread can for shared globals
+    thread: Option<ThreadId>,
 }
 
-pub(crate) struct GlobalManager {
-    registries: Rc<RefCell<Vec<(u32, ClientInner)>>>,
+impl GlobalData {
+    fn visible_to(&self, client: &ClientInner) -> bool {
+        self.thread.map(|thread| thread == client.loop_thread).unwrap_or(true)
+            && !self.filter.as_ref().map(|f| !call_filter(f, client)).unwrap_or(false)
+    }
+}
+
+struct GlobalState {
+    registries: Vec<(u32, ClientInner)>,
     globals: Vec<GlobalData>,
 }
 
+/// The globals of a display, shared with the threads of its dispatch pools
+///
+/// They are all in the same namespace, and advertised to the registries of all threads. The
+/// globals of the display can only be bound by the clients dispatched on its thread.
+#[derive(Clone)]
+pub(crate) struct GlobalManager {
+    state: Arc<Mutex<GlobalState>>,
+}
+
 impl GlobalManager {
     pub(crate) fn new() -> GlobalManager {
-        GlobalManager { registries: Rc::new(RefCell::new(Vec::new())), globals: Vec::new() }
+        GlobalManager {
+            state: Arc::new(Mutex::new(GlobalState {
+                registries: Vec::new(),
+                globals: Vec::new(),
+            })),
+        }
     }
 
     pub(crate) fn add_global<I, F1, F2>(
-        &mut self,
+        &self,
         version: u32,
         implementation: F1,
         filter: Option<F2>,
@@ -59,12 +90,52 @@ impl GlobalManager {
         F1: FnMut(Main<I>, u32, DispatchData) + 'static,
         F2: FnMut(ClientInner) -> bool + 'static,
     {
-        let implem = RefCell::new(implementation);
+        // the global is only visible to the clients of this thread
+        let implem = ThreadGuard::new_named(RefCell::new(implementation), I::NAME);
+        let filter = filter.map(|f| {
+            Arc::new(ThreadGuard::new_named(RefCell::new(f), I::NAME)) as Arc<ThreadGuard<_>>
+        });
+        self.insert::<I, _>(
+            version,
+            move |main, version, data| {
+                if let Some(implem) = implem.get_or_report() {
+                    (&mut *implem.borrow_mut())(main, version, data);
+                }
+            },
+            filter,
+            Some(thread::current().id()),
+        )
+    }
+
+    pub(crate) fn add_shared_global<I, F>(&self, version: u32, implementation: F) -> GlobalInner<I>
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        F: Fn(Main<I>, u32) + Send + Sync + 'static,
+    {
+        self.insert::<I, _>(
+            version,
+            move |main, version, _| implementation(main, version),
+            None,
+            None,
+        )
+    }
+
+    fn insert<I, F>(
+        &self,
+        version: u32,
+        implementation: F,
+        filter: Option<GlobalFilter>,
+        thread: Option<ThreadId>,
+    ) -> GlobalInner<I>
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+        F: Fn(Main<I>, u32, DispatchData) + Send + Sync + 'static,
+    {
         let data = GlobalData {
             version,
             interface: I::NAME,
-            destroyed: Rc::new(Cell::new(false)),
-            implem: Box::new(move |newid, version, client, data| {
+            destroyed: Arc::new(AtomicBool::new(false)),
+            implem: Arc::new(move |newid, version, client: ClientInner, data| {
                 // insert the object in the map, and call the global bind callback
                 // This is done in two times to ensure the client lock is not locked during
                 // the callback
@@ -78,7 +149,7 @@ impl GlobalManager {
                     None
                 };
                 if let Some(map) = map {
-                    (&mut *implem.borrow_mut())(
+                    implementation(
                         Main::wrap(ResourceInner::from_id(newid, map, client).unwrap()),
                         version,
                         data,
@@ -86,47 +157,39 @@ impl GlobalManager {
                 }
                 Ok(())
             }),
-            filter: filter.map(|f| Rc::new(RefCell::new(f)) as Rc<_>),
+            filter,
+            thread,
         };
 
         let destroyed_marker = data.destroyed.clone();
 
-        let id = self.globals.len() as u32 + 1;
-
-        let filter = data.filter.clone();
-        {
-            send_new_global(&self.registries.borrow(), id, I::NAME, version, filter.as_deref());
+        let mut state = self.state.lock().unwrap();
+        let id = state.globals.len() as u32 + 1;
+        let interface = CString::new(I::NAME.as_bytes().to_owned()).unwrap();
+        for reg in &state.registries {
+            if data.visible_to(&reg.1) {
+                send_global_msg(reg, id, interface.clone(), version);
+            }
         }
+        state.globals.push(data);
 
-        self.globals.push(data);
-
-        GlobalInner {
-            _i: ::std::marker::PhantomData,
-            destroyed_marker,
-            id,
-            registries: self.registries.clone(),
-            filter,
-        }
+        GlobalInner { _i: ::std::marker::PhantomData, destroyed_marker, id, manager: self.clone() }
     }
 
-    pub(crate) fn new_registry(&mut self, id: u32, client: ClientInner) {
+    pub(crate) fn new_registry(&self, id: u32, client: ClientInner) {
         let reg = (id, client);
-        for (id, global) in self.globals.iter_mut().enumerate() {
-            if global.destroyed.get() {
+        let mut state = self.state.lock().unwrap();
+        for (id, global) in state.globals.iter().enumerate() {
+            if global.destroyed.load(Ordering::Acquire) || !global.visible_to(&reg.1) {
                 continue;
             }
-            if let Some(ref filter) = global.filter {
-                if !(&mut *filter.borrow_mut())(reg.1.clone()) {
-                    continue;
-                }
-            }
             let interface = CString::new(global.interface.as_bytes().to_owned()).unwrap();
             send_global_msg(&reg, id as u32 + 1, interface, global.version);
         }
-        self.registries.borrow_mut().push(reg);
+        state.registries.push(reg);
 
         // cleanup destroyed clients, to avoid accumulating stale connections
-        self.self_cleanup();
+        state.registries.retain(|&(_, ref client)| client.alive());
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -140,67 +203,71 @@ impl GlobalManager {
         client: ClientInner,
         data: DispatchData,
     ) -> Result<(), ()> {
-        if let Some(ref global_data) = self.globals.get((global_id - 1) as usize) {
-            if !global_data
-                .filter
-                .as_ref()
-                .map(|f| (&mut *f.borrow_mut())(client.clone()))
-                .unwrap_or(true)
-            {
+        // the bind callback is invoked once the globals are unlocked, so that it can create new ones
+        let implem = {
+            let state = self.state.lock().unwrap();
+            match state.globals.get((global_id - 1) as usize) {
+                Some(global_data) if global_data.visible_to(&client) => {
+                    if global_data.interface != interface {
+                        Err(format!(
+                            "Invalid global {} ({}), interface should be {}",
+                            interface, global_id, global_data.interface
+                        ))
+                    } else if version == 0 {
+                        Err(format!(
+                            "Invalid version for global {} ({}): 0 is not a valid version",
+                            interface, global_id
+                        ))
+                    } else if global_data.version < version {
+                        Err(format!(
+                            "Invalid version for global {} ({}): have {}, wanted {}",
+                            interface, global_id, global_data.version, version
+                        ))
+                    } else {
+                        Ok(global_data.implem.clone())
+                    }
+                }
                 // client is not allowed to see this global
-                client.post_error(
-                    registry_id,
-                    super::display::DISPLAY_ERROR_INVALID_OBJECT,
-                    format!("Invalid global {} ({})", interface, global_id),
-                );
-            } else if global_data.interface != interface {
-                client.post_error(
-                    registry_id,
-                    super::display::DISPLAY_ERROR_INVALID_OBJECT,
-                    format!(
-                        "Invalid global {} ({}), interface should be {}",
-                        interface, global_id, global_data.interface
-                    ),
-                );
-            } else if version == 0 {
-                client.post_error(
-                    registry_id,
-                    super::display::DISPLAY_ERROR_INVALID_OBJECT,
-                    format!(
-                        "Invalid version for global {} ({}): 0 is not a valid version",
-                        interface, global_id
-                    ),
-                );
-            } else if global_data.version < version {
-                client.post_error(
-                    registry_id,
-                    super::display::DISPLAY_ERROR_INVALID_OBJECT,
-                    format!(
-                        "Invalid version for global {} ({}): have {}, wanted {}",
-                        interface, global_id, global_data.version, version
-                    ),
-                );
-            } else {
-                // all is good, we insert the object in the map and send it the events
-                return (global_data.implem)(resource_newid, version, client, data);
+                _ => Err(format!("Invalid global {} ({})", interface, global_id)),
+            }
+        };
+        match implem {
+            // all is good, we insert the object in the map and send it the events
+            Ok(implem) => implem(resource_newid, version, client, data),
+            Err(msg) => {
+                client.post_error(registry_id, super::display::DISPLAY_ERROR_INVALID_OBJECT, msg);
+                Ok(())
             }
-        } else {
-            client.post_error(
-                registry_id,
-                super::display::DISPLAY_ERROR_INVALID_OBJECT,
-                format!("Invalid global {} ({})", interface, global_id),
-            );
         }
-        Ok(())
     }
 
-    fn self_cleanup(&self) {
-        self.registries.borrow_mut().retain(|&(_, ref client)| client.alive());
+    fn send_destroyed_global(&self, global_id: u32) {
+        let state = self.state.lock().unwrap();
+        let global = &state.globals[(global_id - 1) as usize];
+        for &(id, ref client) in &state.registries {
+            if !global.visible_to(client) {
+                continue;
+            }
+            client.with_connection(|clientconn| {
+                let _ = clientconn.write_message(&Message {
+                    sender_id: id,
+                    opcode: 1,
+                    args: smallvec![Argument::Uint(global_id)],
+                });
+            });
+        }
+    }
+}
+
+fn call_filter(filter: &GlobalFilter, client: &ClientInner) -> bool {
+    match filter.get_or_report() {
+        Some(filter) => (&mut *filter.borrow_mut())(client.clone()),
+        None => false,
     }
 }
 
 fn send_global_msg(reg: &(u32, ClientInner), global_id: u32, interface: CString, version: u32) {
-    if let Some(ref mut clientconn) = *reg.1.data.lock().unwrap() {
+    reg.1.with_connection(|clientconn| {
         let _ = clientconn.write_message(&Message {
             sender_id: reg.0,
             opcode: 0,
@@ -210,60 +277,5 @@ fn send_global_msg(reg: &(u32, ClientInner), global_id: u32, interface: CString,
                 Argument::Uint(version),
             ],
         });
-    }
-}
-
-fn send_new_global(
-    registries: &[(u32, ClientInner)],
-    global_id: u32,
-    interface: &str,
-    version: u32,
-    filter: Option<&RefCell<dyn FnMut(ClientInner) -> bool>>,
-) {
-    let iface = CString::new(interface.as_bytes().to_owned()).unwrap();
-    if let Some(filter) = filter {
-        let mut filter = filter.borrow_mut();
-        for reg in registries {
-            if !(&mut *filter)(reg.1.clone()) {
-                continue;
-            }
-            send_global_msg(reg, global_id, iface.clone(), version)
-        }
-    } else {
-        for reg in registries {
-            send_global_msg(reg, global_id, iface.clone(), version)
-        }
-    }
-}
-
-fn send_destroyed_global(
-    registries: &[(u32, ClientInner)],
-    global_id: u32,
-    filter: Option<&RefCell<dyn FnMut(ClientInner) -> bool>>,
-) {
-    if let Some(filter) = filter {
-        let mut filter = filter.borrow_mut();
-        for &(id, ref client) in registries {
-            if !(&mut *filter)(client.clone()) {
-                continue;
-            }
-            if let Some(ref mut clientconn) = *client.data.lock().unwrap() {
-                let _ = clientconn.write_message(&Message {
-                    sender_id: id,
-                    opcode: 1,
-                    args: smallvec![Argument::Uint(global_id)],
-                });
-            }
-        }
-    } else {
-        for &(id, ref client) in registries {
-            if let Some(ref mut clientconn) = *client.data.lock().unwrap() {
-                let _ = clientconn.write_message(&Message {
-                    sender_id: id,
-                    opcode: 1,
-                    args: smallvec![Argument::Uint(global_id)],
-                });
-            }
-        }
-    }
+    });
 }
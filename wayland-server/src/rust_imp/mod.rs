@@ -15,12 +15,14 @@ mod clients;
 mod display;
 mod event_loop_glue;
 mod globals;
+mod pool;
 mod resources;
 
 pub(crate) use self::clients::ClientInner;
 pub(crate) use self::display::DisplayInner;
 pub(crate) use self::globals::GlobalInner;
-pub(crate) use self::resources::ResourceInner;
+pub(crate) use self::pool::DispatchPoolInner;
+pub(crate) use self::resources::{RequestGate, ResourceInner, SendHook, WeakResourceInner};
 
 use self::resources::ResourceDestructor;
 
@@ -149,11 +151,13 @@ where
 
         if message.is_destructor() {
             resource.object.meta.alive.store(false, Ordering::Release);
-            let mut kill = false;
-            if let Some(ref mut data) = *resource.client.data.lock().unwrap() {
-                data.schedule_destructor(resource.clone());
-                kill = data.delete_id(resource.id).is_err();
-            }
+            let kill = resource
+                .client
+                .with_connection(|data| {
+                    data.schedule_destructor(resource.clone());
+                    data.delete_id(resource.id).is_err()
+                })
+                .unwrap_or(false);
             if kill {
                 resource.client.kill();
             }
@@ -171,10 +175,13 @@ where
     E: From<(Main<I>, I::Request)> + 'static,
     I::Request: MessageGroup<Map = ResourceMap>,
 {
-    Arc::new(ThreadGuard::new(RefCell::new(ImplDispatcher {
-        _i: ::std::marker::PhantomData,
-        implementation: move |evt, res, data| filter.send((res, evt).into(), data),
-    })))
+    Arc::new(ThreadGuard::new_named(
+        RefCell::new(ImplDispatcher {
+            _i: ::std::marker::PhantomData,
+            implementation: move |evt, res, data| filter.send((res, evt).into(), data),
+        }),
+        I::NAME,
+    ))
 }
 
 pub(crate) fn default_dispatcher() -> Arc<ThreadGuard<RefCell<dyn Dispatcher>>> {
@@ -199,7 +206,10 @@ where
     I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
     E: From<Resource<I>> + 'static,
 {
-    Arc::new(ThreadGuard::new(RefCell::new(move |res, data: DispatchData<'_>| {
-        filter.send(Resource::<I>::wrap(res).into(), data)
-    })))
+    Arc::new(ThreadGuard::new_named(
+        RefCell::new(move |res, data: DispatchData<'_>| {
+            filter.send(Resource::<I>::wrap(res).into(), data)
+        }),
+        I::NAME,
+    ))
 }
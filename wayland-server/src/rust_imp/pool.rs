@@ -0,0 +1,241 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use wayland_commons::wire::Strictness;
+
+use super::clients::ClientManager;
+use super::event_loop_glue::{FdManager, Token};
+use super::globals::GlobalManager;
+use super::ClientInner;
+
+type NewClients = Arc<Mutex<Vec<(RawFd, Sender<ClientInner>)>>>;
+
+pub(crate) struct DispatchPoolInner {
+    workers: Vec<Worker>,
+}
+
+impl DispatchPoolInner {
+    pub(crate) fn new(
+        threads: usize,
+        global_mgr: GlobalManager,
+        strictness: Strictness,
+        max_message_size: usize,
+    ) -> io::Result<DispatchPoolInner> {
+        if threads == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A dispatch pool needs at least one thread.",
+            ));
+        }
+        let workers = (0..threads)
+            .map(|i| Worker::spawn(i, global_mgr.clone(), strictness, max_message_size))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(DispatchPoolInner { workers })
+    }
+
+    pub(crate) fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub(crate) unsafe fn create_client(&mut self, thread: usize, fd: RawFd) -> ClientInner {
+        let worker = &self.workers[thread];
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut new_clients = worker.new_clients.lock().unwrap();
+            if worker.stop.load(Ordering::Acquire) {
+                panic!("[wayland-server] Thread {} of the dispatch pool has stopped.", thread);
+            }
+            new_clients.push((fd, sender));
+        }
+        wake(&worker.waker);
+        // the client is created by the worker, as its objects are bound to the thread
+        receiver.recv().unwrap_or_else(|_| {
+            panic!("[wayland-server] Thread {} of the dispatch pool has stopped.", thread)
+        })
+    }
+}
+
+impl Drop for DispatchPoolInner {
+    fn drop(&mut self) {
+        for worker in &self.workers {
+            worker.stop.store(true, Ordering::Release);
+            wake(&worker.waker);
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                // a panic of the thread was already reported by the thread itself
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+struct Worker {
+    // written to wake up the thread, to create a client, flush the clients or stop
+    waker: Arc<UnixStream>,
+    new_clients: NewClients,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn(
+        index: usize,
+        global_mgr: GlobalManager,
+        strictness: Strictness,
+        max_message_size: usize,
+    ) -> io::Result<Worker> {
+        let (waker, wakee) = UnixStream::pair()?;
+        waker.set_nonblocking(true)?;
+        wakee.set_nonblocking(true)?;
+        let waker = Arc::new(waker);
+        let new_clients = NewClients::default();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (ready_sender, ready) = mpsc::channel();
+        let thread = {
+            let waker = waker.clone();
+            let new_clients = new_clients.clone();
+            let stop = stop.clone();
+            thread::Builder::new().name(format!("wayland-server-pool-{}", index)).spawn(
+                move || {
+                    let _exit = WorkerExit { new_clients: new_clients.clone(), stop: stop.clone() };
+                    match WorkerLoop::new(
+                        global_mgr,
+                        strictness,
+                        max_message_size,
+                        waker,
+                        wakee,
+                        new_clients,
+                    ) {
+                        Ok(worker_loop) => {
+                            let _ = ready_sender.send(Ok(()));
+                            worker_loop.run(&stop);
+                        }
+                        Err(e) => {
+                            let _ = ready_sender.send(Err(e));
+                        }
+                    }
+                },
+            )?
+        };
+
+        let worker = Worker { waker, new_clients, stop, thread: Some(thread) };
+        match ready.recv() {
+            Ok(Ok(())) => Ok(worker),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "A thread of the dispatch pool panicked during its initialization.",
+            )),
+        }
+    }
+}
+
+// stops accepting clients once the thread exits, even if it panicked
+struct WorkerExit {
+    new_clients: NewClients,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for WorkerExit {
+    fn drop(&mut self) {
+        let mut new_clients = self.new_clients.lock().unwrap_or_else(|e| e.into_inner());
+        self.stop.store(true, Ordering::Release);
+        // dropping the senders tells the waiting threads that the clients were not created
+        for (fd, _) in new_clients.drain(..) {
+            let _ = ::nix::unistd::close(fd);
+        }
+    }
+}
+
+struct WorkerLoop {
+    epoll_mgr: Rc<FdManager>,
+    clients_mgr: Rc<RefCell<ClientManager>>,
+    wakee: Token,
+}
+
+impl WorkerLoop {
+    fn new(
+        global_mgr: GlobalManager,
+        strictness: Strictness,
+        max_message_size: usize,
+        waker: Arc<UnixStream>,
+        wakee: UnixStream,
+        new_clients: NewClients,
+    ) -> io::Result<WorkerLoop> {
+        let epoll_mgr = Rc::new(FdManager::new().map_err(nix_to_io)?);
+        let clients_mgr = Rc::new(RefCell::new(ClientManager::new(epoll_mgr.clone(), global_mgr)));
+        {
+            let mut clients_mgr = clients_mgr.borrow_mut();
+            clients_mgr.strictness = strictness;
+            clients_mgr.max_message_size = max_message_size;
+            // the events can be sent from other threads, like the removal of a global
+            clients_mgr.flush_scheduler.set_notifier(Some(Arc::new(move || wake(&waker))));
+        }
+
+        let clients = clients_mgr.clone();
+        let wakee = epoll_mgr
+            .register(wakee.as_raw_fd(), move |mut data| {
+                let mut buffer = [0; 64];
+                while let Ok(n) = (&wakee).read(&mut buffer) {
+                    if n == 0 {
+                        break;
+                    }
+                }
+                let new_clients = ::std::mem::take(&mut *new_clients.lock().unwrap());
+                for (fd, sender) in new_clients {
+                    let client = unsafe { clients.borrow_mut().init_client(fd, data.reborrow()) };
+                    let _ = sender.send(client);
+                }
+            })
+            .map_err(nix_to_io)?;
+
+        Ok(WorkerLoop { epoll_mgr, clients_mgr, wakee })
+    }
+
+    fn run(self, stop: &AtomicBool) {
+        // the filters of the pool clients are given no data
+        let mut data = ();
+        while !stop.load(Ordering::Acquire) {
+            let deferred = self.clients_mgr.borrow().deferred.clone();
+            deferred.resume(crate::DispatchData::wrap(&mut data));
+            match self.epoll_mgr.poll(-1, crate::DispatchData::wrap(&mut data)) {
+                Ok(()) | Err(::nix::Error::Sys(::nix::errno::Errno::EINTR)) => {}
+                Err(e) => {
+                    eprintln!("[wayland-server] Dispatch pool failed to poll its clients: {}", e);
+                    break;
+                }
+            }
+            self.clients_mgr.borrow_mut().flush_all(crate::DispatchData::wrap(&mut data));
+        }
+    }
+}
+
+impl Drop for WorkerLoop {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // the state of the clients is unknown, leak them rather than risking an abort
+            return;
+        }
+        // the callbacks of the event loop keep the client manager alive, remove them
+        self.epoll_mgr.deregister(self.wakee);
+        self.clients_mgr.borrow_mut().kill_all(crate::DispatchData::wrap(&mut ()));
+    }
+}
+
+fn wake(waker: &UnixStream) {
+    // if the socket is full, the thread has a wake up pending anyway
+    let _ = (&*waker).write(&[0]);
+}
+
+fn nix_to_io(e: ::nix::Error) -> io::Error {
+    io::Error::from(e.as_errno().unwrap_or(::nix::errno::Errno::EINVAL))
+}
@@ -1,8 +1,9 @@
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
-use crate::{Interface, Main, Resource};
+use crate::{GateDecision, Interface, Main, Resource};
 
 use wayland_commons::debug;
 use wayland_commons::map::{Object, ObjectMap, ObjectMetadata};
@@ -13,12 +14,87 @@ use super::{ClientInner, Dispatcher, WAYLAND_DEBUG};
 
 pub(crate) type ResourceDestructor = RefCell<dyn FnMut(ResourceInner, crate::DispatchData<'_>)>;
 
+pub(crate) type SendHook<I> =
+    Box<dyn FnMut(&Resource<I>, <I as Interface>::Event) -> Option<<I as Interface>::Event> + Send>;
+
+// a type-erased SendHook<I>
+type AnySendHook = Arc<Mutex<Box<dyn Any + Send>>>;
+
+pub(crate) type RequestGate = Box<dyn FnMut(&ResourceInner, u16) -> GateDecision + Send>;
+
+type SharedRequestGate = Arc<Mutex<RequestGate>>;
+
+thread_local! {
+    // events sent from within a send hook are not intercepted
+    static IN_SEND_HOOK: Cell<bool> = Cell::new(false);
+}
+
+fn erase_hook<I: Interface>(hook: SendHook<I>) -> AnySendHook {
+    Arc::new(Mutex::new(Box::new(hook)))
+}
+
+fn run_hook<I>(hook: &AnySendHook, resource: &Resource<I>, msg: I::Event) -> Option<I::Event>
+where
+    I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+{
+    let mut guard = hook.lock().unwrap();
+    match guard.downcast_mut::<SendHook<I>>() {
+        Some(hook) => hook(resource, msg),
+        None => Some(msg),
+    }
+}
+
+/// The per-interface send hooks of a display
+#[derive(Default)]
+pub(crate) struct SendHooks {
+    hooks: Mutex<Vec<(&'static str, AnySendHook)>>,
+}
+
+impl SendHooks {
+    pub(crate) fn set<I: Interface>(&self, hook: Option<SendHook<I>>) {
+        let mut hooks = self.hooks.lock().unwrap();
+        hooks.retain(|&(interface, _)| interface != I::NAME);
+        if let Some(hook) = hook {
+            hooks.push((I::NAME, erase_hook(hook)));
+        }
+    }
+
+    fn get(&self, interface: &str) -> Option<AnySendHook> {
+        let hooks = self.hooks.lock().unwrap();
+        hooks.iter().find(|&&(name, _)| name == interface).map(|(_, hook)| hook.clone())
+    }
+}
+
+/// The per-interface request gates of a display
+#[derive(Default)]
+pub(crate) struct RequestGates {
+    gates: Mutex<Vec<(&'static str, SharedRequestGate)>>,
+}
+
+impl RequestGates {
+    pub(crate) fn set(&self, interface: &'static str, gate: Option<RequestGate>) {
+        let mut gates = self.gates.lock().unwrap();
+        gates.retain(|&(name, _)| name != interface);
+        if let Some(gate) = gate {
+            gates.push((interface, Arc::new(Mutex::new(gate))));
+        }
+    }
+
+    fn get(&self, interface: &str) -> Option<SharedRequestGate> {
+        let gates = self.gates.lock().unwrap();
+        gates.iter().find(|&&(name, _)| name == interface).map(|(_, gate)| gate.clone())
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct ObjectMeta {
     pub(crate) dispatcher: Arc<ThreadGuard<RefCell<dyn Dispatcher>>>,
     pub(crate) destructor: Option<Arc<ThreadGuard<ResourceDestructor>>>,
     pub(crate) alive: Arc<AtomicBool>,
     user_data: Arc<UserData>,
+    send_hook: Arc<Mutex<Option<AnySendHook>>>,
+    request_gate: Arc<Mutex<Option<SharedRequestGate>>>,
+    serial: u64,
 }
 
 impl ObjectMetadata for ObjectMeta {
@@ -34,6 +110,9 @@ impl ObjectMeta {
             user_data: Arc::new(UserData::new()),
             dispatcher: super::default_dispatcher(),
             destructor: None,
+            send_hook: Arc::new(Mutex::new(None)),
+            request_gate: Arc::new(Mutex::new(None)),
+            serial: crate::resource::next_object_serial(),
         }
     }
 
@@ -43,6 +122,9 @@ impl ObjectMeta {
             user_data: Arc::new(UserData::new()),
             dispatcher: Arc::new(ThreadGuard::new(RefCell::new(disp))),
             destructor: None,
+            send_hook: Arc::new(Mutex::new(None)),
+            request_gate: Arc::new(Mutex::new(None)),
+            serial: crate::resource::next_object_serial(),
         }
     }
 }
@@ -54,6 +136,30 @@ pub(crate) struct ResourceInner {
     pub(crate) client: ClientInner,
 }
 
+#[derive(Clone)]
+pub(crate) struct WeakResourceInner {
+    id: u32,
+    client: ClientInner,
+    alive: Weak<AtomicBool>,
+}
+
+impl WeakResourceInner {
+    pub(crate) fn upgrade(&self) -> Option<ResourceInner> {
+        let alive = self.alive.upgrade()?;
+        if !alive.load(Ordering::Acquire) {
+            return None;
+        }
+        let map = self.client.data.lock().unwrap().as_ref()?.map.clone();
+        // the id may have been reused by an other object
+        let inner = ResourceInner::from_id(self.id, map, self.client.clone())?;
+        if Arc::ptr_eq(&inner.object.meta.alive, &alive) {
+            Some(inner)
+        } else {
+            None
+        }
+    }
+}
+
 impl ResourceInner {
     pub(crate) fn from_id(
         id: u32,
@@ -69,7 +175,7 @@ impl ResourceInner {
     }
 
     pub(crate) fn send<I: Interface>(&self, msg: I::Event) {
-        if let Some(ref mut conn_lock) = *self.client.data.lock().unwrap() {
+        self.client.with_connection(|conn_lock| {
             let is_alive = self.is_alive();
 
             let destructor = msg.is_destructor();
@@ -98,6 +204,71 @@ impl ResourceInner {
                 // send delete_id
                 let _ = conn_lock.delete_id(self.id);
             }
+        });
+    }
+
+    pub(crate) fn set_send_hook<I: Interface>(&self, hook: Option<SendHook<I>>) {
+        *self.object.meta.send_hook.lock().unwrap() = hook.map(erase_hook);
+    }
+
+    /// Run the send hooks of this resource and of its interface on an event
+    ///
+    /// Returns `None` if the event was vetoed.
+    pub(crate) fn apply_send_hooks<I>(
+        &self,
+        resource: &Resource<I>,
+        msg: I::Event,
+    ) -> Option<I::Event>
+    where
+        I: Interface + AsRef<Resource<I>> + From<Resource<I>>,
+    {
+        if IN_SEND_HOOK.with(|flag| flag.get()) {
+            return Some(msg);
+        }
+        // clone the hooks out of their locks, so that they can be changed from a hook
+        let resource_hook = self.object.meta.send_hook.lock().unwrap().clone();
+        let interface_hook = self.client.send_hooks.get(I::NAME);
+        if resource_hook.is_none() && interface_hook.is_none() {
+            return Some(msg);
+        }
+
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                IN_SEND_HOOK.with(|flag| flag.set(false));
+            }
+        }
+        IN_SEND_HOOK.with(|flag| flag.set(true));
+        let _reset = Reset;
+
+        let msg = match resource_hook {
+            Some(hook) => run_hook(&hook, resource, msg)?,
+            None => msg,
+        };
+        match interface_hook {
+            Some(hook) => run_hook(&hook, resource, msg),
+            None => Some(msg),
+        }
+    }
+
+    pub(crate) fn set_request_gate(&self, gate: Option<RequestGate>) {
+        *self.object.meta.request_gate.lock().unwrap() =
+            gate.map(|gate| Arc::new(Mutex::new(gate)));
+    }
+
+    /// Run the request gates of this resource and of its interface on a request
+    pub(crate) fn check_request_gates(&self, opcode: u16) -> GateDecision {
+        // clone the gates out of their locks, so that they can be changed from a gate
+        let resource_gate = self.object.meta.request_gate.lock().unwrap().clone();
+        if let Some(gate) = resource_gate {
+            match (*gate.lock().unwrap())(self, opcode) {
+                GateDecision::Allow => {}
+                decision => return decision,
+            }
+        }
+        match self.client.request_gates.get(self.object.interface) {
+            Some(gate) => (*gate.lock().unwrap())(self, opcode),
+            None => GateDecision::Allow,
         }
     }
 
@@ -113,6 +284,18 @@ impl ResourceInner {
         self.is_alive() && Arc::ptr_eq(&self.object.meta.alive, &other.object.meta.alive)
     }
 
+    pub(crate) fn serial(&self) -> u64 {
+        self.object.meta.serial
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakResourceInner {
+        WeakResourceInner {
+            id: self.id,
+            client: self.client.clone(),
+            alive: Arc::downgrade(&self.object.meta.alive),
+        }
+    }
+
     pub(crate) fn same_client_as(&self, other: &ResourceInner) -> bool {
         self.client.equals(&other.client)
     }
@@ -0,0 +1,412 @@
+//! Shared memory buffer helpers
+//!
+//! This module provides an implementation of the `wl_shm` global, created with
+//! `init_shm_global()`, and `ShmBuffer` to safely access the contents of the `wl_buffer`s
+//! created from it.
+//!
+//! ```no_run
+//! # use wayland_server::{Display, protocol::{wl_buffer::WlBuffer, wl_shm::Format}};
+//! # fn render(display: &mut Display, buffer: &WlBuffer) {
+//! use wayland_server::shm::{init_shm_global, ShmBuffer};
+//!
+//! // Argb8888 and Xrgb8888 are always supported
+//! init_shm_global(display, vec![Format::Rgb565]);
+//!
+//! // once a client attached a buffer to a surface:
+//! if let Some(shm_buffer) = ShmBuffer::get(buffer) {
+//!     let result = shm_buffer.with_contents(|contents, data| {
+//!         // upload the contents...
+//!     });
+//!     if result.is_err() {
+//!         // the client truncated its pool file, it should be killed
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! A client can truncate the file backing its pool at any time, which would make any access
+//! to the mapped memory raise a `SIGBUS`, crashing the server. Like libwayland does, accessing
+//! the contents through `ShmBuffer::with_contents()` protects against this: a `SIGBUS` handler
+//! replaces the faulty mapping with zeroed memory, and the access is reported as an error.
+//! This handler is installed the first time `with_contents()` is called, signals not raised
+//! by this access are forwarded to the handler that was previously installed.
+
+use std::cell::Cell;
+use std::fmt;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+use std::sync::{Arc, Once, RwLock};
+
+use nix::sys::{mman, signal};
+use nix::unistd;
+
+use crate::protocol::wl_buffer::WlBuffer;
+use crate::protocol::wl_shm::{self, Format, WlShm};
+use crate::protocol::wl_shm_pool::{self, WlShmPool};
+use crate::{Display, Filter, Global, Main};
+
+/// Create the `wl_shm` global
+///
+/// The formats `Argb8888` and `Xrgb8888` are always advertised, as required by the protocol,
+/// in addition to the provided `formats`.
+///
+/// The pools and buffers created by the clients are validated and handled by this global,
+/// their contents can be accessed with `ShmBuffer`.
+pub fn init_shm_global(display: &mut Display, mut formats: Vec<Format>) -> Global<WlShm> {
+    for &format in &[Format::Argb8888, Format::Xrgb8888] {
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    let formats = Arc::new(formats);
+
+    display.create_global::<WlShm, _>(
+        1,
+        Filter::new(move |(shm, _version): (Main<WlShm>, u32), _, _| {
+            let shm_formats = formats.clone();
+            shm.quick_assign(move |shm, request, _| {
+                let wl_shm::Request::CreatePool { id, fd, size } = request;
+                create_pool(&shm, id, fd, size, shm_formats.clone());
+            });
+            for &format in formats.iter() {
+                shm.format(format);
+            }
+        }),
+    )
+}
+
+fn create_pool(
+    shm: &WlShm,
+    pool: Main<WlShmPool>,
+    fd: RawFd,
+    size: i32,
+    formats: Arc<Vec<Format>>,
+) {
+    if size <= 0 {
+        shm.as_ref().post_error(
+            wl_shm::Error::InvalidStride as u32,
+            format!("Invalid size for a new wl_shm_pool: {}.", size),
+        );
+        let _ = unistd::close(fd);
+        return;
+    }
+    let map = match MemMap::new(fd, size as usize) {
+        Ok(map) => map,
+        Err(()) => {
+            shm.as_ref()
+                .post_error(wl_shm::Error::InvalidFd as u32, format!("Failed to mmap fd {}.", fd));
+            let _ = unistd::close(fd);
+            return;
+        }
+    };
+    let pool_data = Arc::new(Pool { map: RwLock::new(map), fd });
+
+    pool.quick_assign(move |pool, request, _| match request {
+        wl_shm_pool::Request::CreateBuffer { id, offset, width, height, stride, format } => {
+            if !formats.contains(&format) {
+                pool.as_ref().post_error(
+                    wl_shm::Error::InvalidFormat as u32,
+                    format!("Invalid format {:?}.", format),
+                );
+                return;
+            }
+            let pool_size = pool_data.size();
+            if offset < 0
+                || width <= 0
+                || height <= 0
+                || stride < width
+                || std::i32::MAX / stride <= height
+                || offset as usize + (stride * height) as usize > pool_size
+            {
+                pool.as_ref().post_error(
+                    wl_shm::Error::InvalidStride as u32,
+                    format!(
+                        "Invalid buffer (offset: {}, width: {}, height: {}, stride: {}) for a pool of size {}.",
+                        offset, width, height, stride, pool_size
+                    ),
+                );
+                return;
+            }
+            let data = BufferData { offset, width, height, stride, format };
+            let buffer_pool = pool_data.clone();
+            id.as_ref().user_data().set_threadsafe(move || ShmBufferData { pool: buffer_pool, data });
+            id.quick_assign(|_, _, _| {});
+        }
+        wl_shm_pool::Request::Resize { size } => {
+            if let Err(old_size) = pool_data.resize(size) {
+                pool.as_ref().post_error(
+                    wl_shm::Error::InvalidStride as u32,
+                    format!("Invalid new size {} for a pool of size {}, pools can only grow.", size, old_size),
+                );
+            }
+        }
+        _ => {}
+    });
+}
+
+/// The specification of a shm buffer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BufferData {
+    /// Offset of the start of the buffer relative to the beginning of the contents
+    /// provided to `with_contents()`
+    pub offset: i32,
+    /// Width of the buffer, in pixels
+    pub width: i32,
+    /// Height of the buffer, in pixels
+    pub height: i32,
+    /// Stride of the buffer, in bytes
+    pub stride: i32,
+    /// The format of the buffer
+    pub format: Format,
+}
+
+/// Error that can occur when accessing the contents of a shm buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferAccessError {
+    /// The memory of the pool is no longer accessible
+    ///
+    /// This happens when the client truncated the file backing the pool. It is a protocol
+    /// error from the client, which should be killed.
+    BadMap,
+}
+
+impl fmt::Display for BufferAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BufferAccessError::BadMap => {
+                f.write_str("The client truncated the file backing its shm pool")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BufferAccessError {}
+
+struct ShmBufferData {
+    pool: Arc<Pool>,
+    data: BufferData,
+}
+
+/// A buffer created from the `wl_shm` global of `init_shm_global()`
+pub struct ShmBuffer {
+    pool: Arc<Pool>,
+    data: BufferData,
+}
+
+impl ShmBuffer {
+    /// Retrieve the shm buffer associated to a `wl_buffer`
+    ///
+    /// Returns `None` if this buffer was not created by the `wl_shm` global of
+    /// `init_shm_global()`.
+    pub fn get(buffer: &WlBuffer) -> Option<ShmBuffer> {
+        buffer
+            .as_ref()
+            .user_data()
+            .get::<ShmBufferData>()
+            .map(|d| ShmBuffer { pool: d.pool.clone(), data: d.data })
+    }
+
+    /// The specification of this buffer
+    pub fn data(&self) -> BufferData {
+        self.data
+    }
+
+    /// Access the contents of the buffer
+    ///
+    /// The closure is given the whole memory of the pool the buffer was created from, the
+    /// buffer itself starts at `data.offset`.
+    ///
+    /// If the client truncated the file backing the pool, the closure sees zeroes instead of
+    /// the missing memory, and `BufferAccessError::BadMap` is returned.
+    pub fn with_contents<T, F>(&self, f: F) -> Result<T, BufferAccessError>
+    where
+        F: FnOnce(&[u8], BufferData) -> T,
+    {
+        install_sigbus_handler();
+        let map = self.pool.map.read().unwrap();
+        if map.faulted.load(Ordering::SeqCst) {
+            return Err(BufferAccessError::BadMap);
+        }
+        let end = self.data.offset as usize + (self.data.stride * self.data.height) as usize;
+        if end > map.size {
+            return Err(BufferAccessError::BadMap);
+        }
+
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                SIGBUS_GUARD.with(|guard| guard.set(ptr::null()));
+            }
+        }
+        SIGBUS_GUARD.with(|guard| guard.set(&*map as *const MemMap));
+        let reset = Reset;
+
+        let contents = unsafe { std::slice::from_raw_parts(map.ptr, map.size) };
+        let ret = f(contents, self.data);
+
+        // the flag is set from the signal handler, behind the back of the compiler
+        compiler_fence(Ordering::SeqCst);
+        drop(reset);
+        if map.faulted.load(Ordering::SeqCst) {
+            // the mapping was replaced by anonymous memory, all further accesses are invalid
+            return Err(BufferAccessError::BadMap);
+        }
+        Ok(ret)
+    }
+}
+
+struct Pool {
+    map: RwLock<MemMap>,
+    fd: RawFd,
+}
+
+impl Pool {
+    fn size(&self) -> usize {
+        self.map.read().unwrap().size
+    }
+
+    // returns the current size on failure
+    fn resize(&self, size: i32) -> Result<(), usize> {
+        let mut map = self.map.write().unwrap();
+        if size <= 0 || (size as usize) < map.size {
+            return Err(map.size);
+        }
+        map.remap(self.fd, size as usize).map_err(|()| map.size)
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
+
+struct MemMap {
+    ptr: *mut u8,
+    size: usize,
+    faulted: AtomicBool,
+}
+
+// the mapping is only read through shared references
+unsafe impl Send for MemMap {}
+unsafe impl Sync for MemMap {}
+
+impl MemMap {
+    fn new(fd: RawFd, size: usize) -> Result<MemMap, ()> {
+        Ok(MemMap { ptr: map(fd, size)?, size, faulted: AtomicBool::new(false) })
+    }
+
+    fn remap(&mut self, fd: RawFd, size: usize) -> Result<(), ()> {
+        let ptr = map(fd, size)?;
+        unmap(self.ptr, self.size);
+        self.ptr = ptr;
+        self.size = size;
+        Ok(())
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.ptr as usize && addr < self.ptr as usize + self.size
+    }
+
+    // replace the mapping by anonymous zeroed memory, this is called from the signal handler
+    fn nullify(&self) -> Result<(), ()> {
+        let ret = unsafe {
+            mman::mmap(
+                self.ptr as *mut _,
+                self.size,
+                mman::ProtFlags::PROT_READ,
+                mman::MapFlags::MAP_ANONYMOUS
+                    | mman::MapFlags::MAP_PRIVATE
+                    | mman::MapFlags::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        ret.map(|_| ()).map_err(|_| ())
+    }
+}
+
+impl Drop for MemMap {
+    fn drop(&mut self) {
+        unmap(self.ptr, self.size);
+    }
+}
+
+fn map(fd: RawFd, size: usize) -> Result<*mut u8, ()> {
+    let ret = unsafe {
+        mman::mmap(
+            ptr::null_mut(),
+            size,
+            mman::ProtFlags::PROT_READ,
+            mman::MapFlags::MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+    ret.map(|ptr| ptr as *mut u8).map_err(|_| ())
+}
+
+fn unmap(ptr: *mut u8, size: usize) {
+    let _ = unsafe { mman::munmap(ptr as *mut _, size) };
+}
+
+/*
+ * SIGBUS handling
+ */
+
+thread_local! {
+    // the mapping being accessed by this thread
+    static SIGBUS_GUARD: Cell<*const MemMap> = Cell::new(ptr::null());
+}
+
+static SIGBUS_INIT: Once = Once::new();
+static mut OLD_SIGBUS_HANDLER: Option<signal::SigAction> = None;
+
+fn install_sigbus_handler() {
+    SIGBUS_INIT.call_once(|| unsafe {
+        let action = signal::SigAction::new(
+            signal::SigHandler::SigAction(sigbus_handler),
+            signal::SaFlags::SA_NODEFER,
+            signal::SigSet::empty(),
+        );
+        if let Ok(old) = signal::sigaction(signal::Signal::SIGBUS, &action) {
+            OLD_SIGBUS_HANDLER = Some(old);
+        }
+    });
+}
+
+unsafe fn reraise_sigbus() {
+    // restore the previous handler and let it handle the signal
+    if let Some(old) = OLD_SIGBUS_HANDLER {
+        let _ = signal::sigaction(signal::Signal::SIGBUS, &old);
+    }
+    let _ = signal::raise(signal::Signal::SIGBUS);
+}
+
+extern "C" fn sigbus_handler(
+    _signum: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _context: *mut libc::c_void,
+) {
+    let faulty_addr = unsafe { siginfo_si_addr(info) } as usize;
+    SIGBUS_GUARD.with(|guard| match unsafe { guard.get().as_ref() } {
+        Some(map) if map.contains(faulty_addr) => {
+            map.faulted.store(true, Ordering::SeqCst);
+            if map.nullify().is_err() {
+                unsafe { reraise_sigbus() };
+            }
+        }
+        _ => unsafe { reraise_sigbus() },
+    });
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn siginfo_si_addr(info: *mut libc::siginfo_t) -> *mut libc::c_void {
+    (*info).si_addr()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn siginfo_si_addr(info: *mut libc::siginfo_t) -> *mut libc::c_void {
+    (*info).si_addr
+}
@@ -83,6 +83,30 @@ external_library!(WaylandClient, "wayland-client",
         fn wl_proxy_marshal(*mut wl_proxy, u32) -> (),
 );
 
+// Symbols of newer versions of libwayland-client are in separate tables, so that a missing
+// one only disables the functions needing it. Without the `dlopen` feature, using them
+// requires linking against a version of the library providing them.
+
+// proxy tags, since libwayland-client 1.18
+#[cfg(feature = "client")]
+external_library!(WaylandClientTags, "wayland-client",
+    functions:
+        fn wl_proxy_set_tag(*mut wl_proxy, *const *const c_char) -> (),
+        fn wl_proxy_get_tag(*mut wl_proxy) -> *const *const c_char,
+);
+
+// marshalling with flags, since libwayland-client 1.20
+#[cfg(feature = "client")]
+external_library!(WaylandClientMarshalFlags, "wayland-client",
+    functions:
+        fn wl_proxy_marshal_array_flags(*mut wl_proxy, u32, *const wl_interface, u32, u32, *mut wl_argument) -> *mut wl_proxy,
+    varargs:
+        fn wl_proxy_marshal_flags(*mut wl_proxy, u32, *const wl_interface, u32, u32) -> *mut wl_proxy,
+);
+
+/// Flag of `wl_proxy_marshal_flags` destroying the proxy after sending the request
+pub const WL_MARSHAL_FLAG_DESTROY: u32 = 1 << 0;
+
 #[cfg(all(feature = "client", feature = "dlopen"))]
 lazy_static::lazy_static!(
     pub static ref WAYLAND_CLIENT_OPTION: Option<WaylandClient> = {
@@ -114,6 +138,28 @@ lazy_static::lazy_static!(
     };
 );
 
+#[cfg(all(feature = "client", feature = "dlopen"))]
+lazy_static::lazy_static!(
+    pub static ref WAYLAND_CLIENT_TAGS_OPTION: Option<WaylandClientTags> = {
+        ["libwayland-client.so", "libwayland-client.so.0"]
+            .iter()
+            .filter_map(|ver| WaylandClientTags::open(ver).ok())
+            .next()
+    };
+    pub static ref WAYLAND_CLIENT_TAGS_HANDLE: &'static WaylandClientTags = {
+        WAYLAND_CLIENT_TAGS_OPTION.as_ref().expect("Library libwayland-client.so does not support proxy tags.")
+    };
+    pub static ref WAYLAND_CLIENT_MARSHAL_FLAGS_OPTION: Option<WaylandClientMarshalFlags> = {
+        ["libwayland-client.so", "libwayland-client.so.0"]
+            .iter()
+            .filter_map(|ver| WaylandClientMarshalFlags::open(ver).ok())
+            .next()
+    };
+    pub static ref WAYLAND_CLIENT_MARSHAL_FLAGS_HANDLE: &'static WaylandClientMarshalFlags = {
+        WAYLAND_CLIENT_MARSHAL_FLAGS_OPTION.as_ref().expect("Library libwayland-client.so does not support marshalling flags.")
+    };
+);
+
 #[cfg(all(feature = "client", not(feature = "dlopen")))]
 pub fn is_lib_available() -> bool {
     true
@@ -122,3 +168,27 @@ pub fn is_lib_available() -> bool {
 pub fn is_lib_available() -> bool {
     WAYLAND_CLIENT_OPTION.is_some()
 }
+
+/// Checks whether the proxy tags functions can be used, with `WAYLAND_CLIENT_TAGS_HANDLE`
+#[cfg(all(feature = "client", not(feature = "dlopen")))]
+pub fn has_proxy_tags() -> bool {
+    true
+}
+/// Checks whether the proxy tags functions can be used, with `WAYLAND_CLIENT_TAGS_HANDLE`
+#[cfg(all(feature = "client", feature = "dlopen"))]
+pub fn has_proxy_tags() -> bool {
+    WAYLAND_CLIENT_TAGS_OPTION.is_some()
+}
+
+/// Checks whether the `wl_proxy_marshal_*flags` functions can be used, with
+/// `WAYLAND_CLIENT_MARSHAL_FLAGS_HANDLE`
+#[cfg(all(feature = "client", not(feature = "dlopen")))]
+pub fn has_marshal_flags() -> bool {
+    true
+}
+/// Checks whether the `wl_proxy_marshal_*flags` functions can be used, with
+/// `WAYLAND_CLIENT_MARSHAL_FLAGS_HANDLE`
+#[cfg(all(feature = "client", feature = "dlopen"))]
+pub fn has_marshal_flags() -> bool {
+    WAYLAND_CLIENT_MARSHAL_FLAGS_OPTION.is_some()
+}
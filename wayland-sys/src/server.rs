@@ -16,6 +16,7 @@ pub enum wl_display {}
 pub enum wl_event_loop {}
 pub enum wl_event_source {}
 pub enum wl_global {}
+pub enum wl_protocol_logger {}
 pub enum wl_resource {}
 pub enum wl_shm_buffer {}
 
@@ -28,6 +29,27 @@ pub type wl_notify_func_t = unsafe extern "C" fn(*mut wl_listener, *mut c_void)
 pub type wl_resource_destroy_func_t = unsafe extern "C" fn(*mut wl_resource) -> ();
 pub type wl_display_global_filter_func_t =
     unsafe extern "C" fn(*const wl_client, *const wl_global, *mut c_void) -> bool;
+pub type wl_client_for_each_resource_iterator_func_t =
+    unsafe extern "C" fn(*mut wl_resource, *mut c_void) -> wl_iterator_result;
+pub type wl_protocol_logger_func_t =
+    unsafe extern "C" fn(*mut c_void, wl_protocol_logger_type, *const wl_protocol_logger_message) -> ();
+
+pub type wl_iterator_result = c_int;
+pub const WL_ITERATOR_STOP: wl_iterator_result = 0;
+pub const WL_ITERATOR_CONTINUE: wl_iterator_result = 1;
+
+pub type wl_protocol_logger_type = c_int;
+pub const WL_PROTOCOL_LOGGER_REQUEST: wl_protocol_logger_type = 0;
+pub const WL_PROTOCOL_LOGGER_EVENT: wl_protocol_logger_type = 1;
+
+#[repr(C)]
+pub struct wl_protocol_logger_message {
+    pub resource: *mut wl_resource,
+    pub message_opcode: c_int,
+    pub message: *const wl_message,
+    pub arguments_count: c_int,
+    pub arguments: *const wl_argument,
+}
 
 #[repr(C)]
 pub struct wl_listener {
@@ -49,6 +71,11 @@ external_library!(WaylandServer, "wayland-server",
         fn wl_client_get_display(*mut wl_client) -> *mut wl_display,
         fn wl_client_get_credentials(*mut wl_client, *mut pid_t, *mut uid_t, *mut gid_t) -> (),
         fn wl_client_get_object(*mut wl_client, u32) -> *mut wl_resource,
+        fn wl_client_get_fd(*mut wl_client) -> c_int,
+        fn wl_client_get_link(*mut wl_client) -> *mut wl_list,
+        fn wl_client_from_link(*mut wl_list) -> *mut wl_client,
+        fn wl_client_for_each_resource(*mut wl_client, wl_client_for_each_resource_iterator_func_t, *mut c_void) -> (),
+        fn wl_client_add_resource_created_listener(*mut wl_client, *mut wl_listener) -> (),
         fn wl_client_add_destroy_listener(*mut wl_client, *mut wl_listener) -> (),
         fn wl_client_get_destroy_listener(*mut wl_client, wl_notify_func_t) -> *mut wl_listener,
         fn wl_client_post_no_memory(*mut wl_client) -> (),
@@ -74,6 +101,7 @@ external_library!(WaylandServer, "wayland-server",
         fn wl_display_init_shm(*mut wl_display) -> c_int,
         fn wl_display_add_client_created_listener(*mut wl_display, *mut wl_listener) -> (),
         fn wl_display_set_global_filter(*mut wl_display, wl_display_global_filter_func_t, *mut c_void) -> (),
+        fn wl_display_get_client_list(*mut wl_display) -> *mut wl_list,
     // wl_event_loop
         fn wl_event_loop_create() -> *mut wl_event_loop,
         fn wl_event_loop_destroy(*mut wl_event_loop) -> (),
@@ -144,6 +172,24 @@ external_library!(WaylandServer, "wayland-server",
         fn wl_resource_post_error(*mut wl_resource, u32, *const c_char) -> (),
 );
 
+// Like on the client side, the symbols of newer versions of libwayland-server are in separate
+// tables, so that a missing one only disables the functions needing it.
+
+// protocol logger
+#[cfg(feature = "server")]
+external_library!(WaylandServerLogger, "wayland-server",
+    functions:
+        fn wl_display_add_protocol_logger(*mut wl_display, wl_protocol_logger_func_t, *mut c_void) -> *mut wl_protocol_logger,
+        fn wl_protocol_logger_destroy(*mut wl_protocol_logger) -> (),
+);
+
+// removal of globals before their destruction, since libwayland-server 1.17
+#[cfg(feature = "server")]
+external_library!(WaylandServerGlobalRemove, "wayland-server",
+    functions:
+        fn wl_global_remove(*mut wl_global) -> (),
+);
+
 #[cfg(all(feature = "server", feature = "dlopen"))]
 lazy_static::lazy_static!(
     pub static ref WAYLAND_SERVER_OPTION: Option<WaylandServer> = {
@@ -175,6 +221,50 @@ lazy_static::lazy_static!(
     };
 );
 
+#[cfg(all(feature = "server", feature = "dlopen"))]
+lazy_static::lazy_static!(
+    pub static ref WAYLAND_SERVER_LOGGER_OPTION: Option<WaylandServerLogger> = {
+        ["libwayland-server.so", "libwayland-server.so.0"]
+            .iter()
+            .filter_map(|ver| WaylandServerLogger::open(ver).ok())
+            .next()
+    };
+    pub static ref WAYLAND_SERVER_LOGGER_HANDLE: &'static WaylandServerLogger = {
+        WAYLAND_SERVER_LOGGER_OPTION.as_ref().expect("Library libwayland-server.so does not support protocol loggers.")
+    };
+    pub static ref WAYLAND_SERVER_GLOBAL_REMOVE_OPTION: Option<WaylandServerGlobalRemove> = {
+        ["libwayland-server.so", "libwayland-server.so.0"]
+            .iter()
+            .filter_map(|ver| WaylandServerGlobalRemove::open(ver).ok())
+            .next()
+    };
+    pub static ref WAYLAND_SERVER_GLOBAL_REMOVE_HANDLE: &'static WaylandServerGlobalRemove = {
+        WAYLAND_SERVER_GLOBAL_REMOVE_OPTION.as_ref().expect("Library libwayland-server.so does not support removing globals.")
+    };
+);
+
+/// Checks whether the protocol logger functions can be used, with `WAYLAND_SERVER_LOGGER_HANDLE`
+#[cfg(all(feature = "server", not(feature = "dlopen")))]
+pub fn has_protocol_logger() -> bool {
+    true
+}
+/// Checks whether the protocol logger functions can be used, with `WAYLAND_SERVER_LOGGER_HANDLE`
+#[cfg(all(feature = "server", feature = "dlopen"))]
+pub fn has_protocol_logger() -> bool {
+    WAYLAND_SERVER_LOGGER_OPTION.is_some()
+}
+
+/// Checks whether `wl_global_remove` can be used, with `WAYLAND_SERVER_GLOBAL_REMOVE_HANDLE`
+#[cfg(all(feature = "server", not(feature = "dlopen")))]
+pub fn has_global_remove() -> bool {
+    true
+}
+/// Checks whether `wl_global_remove` can be used, with `WAYLAND_SERVER_GLOBAL_REMOVE_HANDLE`
+#[cfg(all(feature = "server", feature = "dlopen"))]
+pub fn has_global_remove() -> bool {
+    WAYLAND_SERVER_GLOBAL_REMOVE_OPTION.is_some()
+}
+
 #[cfg(all(feature = "server", not(feature = "dlopen")))]
 pub fn is_lib_available() -> bool {
     true